@@ -0,0 +1,107 @@
+//! Grammar agnostic benchmarks of the runtime.
+//!
+//! Run with `cargo bench --features bench`
+use antlr_rust::bench_support::*;
+use antlr_rust::common_token_stream::CommonTokenStream;
+use antlr_rust::int_stream::{IntStream, EOF};
+use antlr_rust::rule_context::EmptyContextType;
+use antlr_rust::token_factory::OwningTokenFactory;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::rc::Rc;
+
+const SEED: u64 = 0x5EED;
+
+fn token_stream(c: &mut Criterion) {
+    let mut group = c.benchmark_group("token_stream");
+    for &hidden in &[0u32, 30, 70] {
+        let config = TokenListConfig {
+            hidden_percent: hidden,
+            ..TokenListConfig::default()
+        };
+        let tokens = generate_tokens(SEED, &config);
+        group.throughput(Throughput::Elements(tokens.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("consume_all", hidden),
+            &tokens,
+            |b, tokens| {
+                b.iter(|| {
+                    let mut stream = CommonTokenStream::new(ReplayTokenSource::new(tokens.clone()));
+                    while stream.la(1) != EOF {
+                        black_box(stream.la(2));
+                        stream.consume();
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn count_nodes(node: &Rc<BenchTree>) -> usize {
+    1 + node.get_children().map(|c| count_nodes(&c)).sum::<usize>()
+}
+
+fn tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree");
+    let shapes = [
+        (
+            "deep",
+            TreeShape {
+                depth: 200,
+                width: 2,
+                leaf_percent: 90,
+            },
+        ),
+        (
+            "wide",
+            TreeShape {
+                depth: 4,
+                width: 20,
+                leaf_percent: 50,
+            },
+        ),
+    ];
+    for (name, shape) in shapes.iter() {
+        group.bench_function(BenchmarkId::new("build", name), |b| {
+            b.iter(|| build_tree(SEED, *shape))
+        });
+        let tree = build_tree(SEED, *shape);
+        group.bench_function(BenchmarkId::new("walk", name), |b| {
+            b.iter(|| count_nodes(black_box(&tree)))
+        });
+    }
+    group.finish();
+}
+
+fn interval_set(c: &mut Criterion) {
+    let ops = interval_set_ops(SEED, 10_000, 1 << 16);
+    let mut group = c.benchmark_group("interval_set");
+    group.throughput(Throughput::Elements(ops.len() as u64));
+    group.bench_function("op_mix", |b| {
+        b.iter(|| apply_interval_set_ops(black_box(&ops)))
+    });
+    group.finish();
+}
+
+fn atn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("atn");
+    group.bench_function("deserialize", |b| b.iter(atn_fixture));
+    let atn = atn_fixture();
+    group.bench_function("dfa_init", |b| b.iter(|| dfa_fixture(black_box(&atn))));
+    group.bench_function("decision_lookahead", |b| {
+        b.iter(|| {
+            for &state in &atn.decision_to_state {
+                black_box(
+                    atn.next_tokens_in_ctx::<EmptyContextType<'_, OwningTokenFactory>>(
+                        atn.states[state].as_ref(),
+                        None,
+                    ),
+                );
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, token_stream, tree, interval_set, atn);
+criterion_main!(benches);
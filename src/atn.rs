@@ -41,6 +41,10 @@ pub struct ATN {
     pub rule_to_token_type: Vec<isize>,
 
     pub states: Vec<Box<dyn ATNState>>,
+
+    rule_source_positions: Vec<Option<(u32, u32)>>,
+
+    decision_source_positions: Vec<Option<(u32, u32)>>,
 }
 
 impl Debug for ATN {
@@ -67,6 +71,8 @@ impl ATN {
             rule_to_stop_state: Vec::new(),
             rule_to_token_type: Vec::new(),
             states: Vec::new(),
+            rule_source_positions: Vec::new(),
+            decision_source_positions: Vec::new(),
         }
     }
 
@@ -106,6 +112,88 @@ impl ATN {
         self.decision_to_state[decision]
     }
 
+    /// Index of the rule that contains `decision`
+    pub fn decision_to_rule(&self, decision: usize) -> usize {
+        self.states[self.decision_to_state[decision]].get_rule_index()
+    }
+
+    /// Sets `(line, column)` of each rule definition in the grammar source, indexed by rule.
+    ///
+    /// Serialized ATN does not carry grammar source positions, so they are available
+    /// only if generated code sets them before ATN is shared with recognizers.
+    pub fn set_rule_source_positions(&mut self, positions: Vec<Option<(u32, u32)>>) {
+        self.rule_source_positions = positions
+    }
+
+    /// Sets `(line, column)` of each decision in the grammar source, indexed by decision
+    pub fn set_decision_source_positions(&mut self, positions: Vec<Option<(u32, u32)>>) {
+        self.decision_source_positions = positions
+    }
+
+    /// `(line, column)` of the rule definition in the grammar source, if known
+    pub fn rule_source_position(&self, rule_index: usize) -> Option<(u32, u32)> {
+        self.rule_source_positions
+            .get(rule_index)
+            .copied()
+            .flatten()
+    }
+
+    /// `(line, column)` of the decision in the grammar source, if known
+    pub fn decision_source_position(&self, decision: usize) -> Option<(u32, u32)> {
+        self.decision_source_positions
+            .get(decision)
+            .copied()
+            .flatten()
+    }
+
+    /// Location of `decision` in the grammar source as `Expr.g4:42:10`.
+    ///
+    /// Falls back to the position of the rule containing `decision`
+    /// if there is no position for the decision itself.
+    pub fn decision_location(&self, decision: isize, grammar_file_name: &str) -> Option<String> {
+        if decision < 0 || decision as usize >= self.decision_to_state.len() {
+            return None;
+        }
+        let decision = decision as usize;
+        let (line, column) = self
+            .decision_source_position(decision)
+            .or_else(|| self.rule_source_position(self.decision_to_rule(decision)))?;
+        Some(format!("{}:{}:{}", grammar_file_name, line, column))
+    }
+
+    /// Describes `decision` for diagnostics as `decision 7 (rule expr) at Expr.g4:42:10`.
+    ///
+    /// Rule index is used if `rule_names` has no name for the rule, location is omitted
+    /// if grammar source positions are unknown, and it is just `decision 7`
+    /// if there is no such decision in this ATN.
+    pub fn describe_decision(
+        &self, decision: isize, rule_names: &[&str], grammar_file_name: &str,
+    ) -> String {
+        if decision < 0 || decision as usize >= self.decision_to_state.len() {
+            return format!("decision {}", decision);
+        }
+        let rule_index = self.decision_to_rule(decision as usize);
+        let mut description = match rule_names.get(rule_index) {
+            Some(rule_name) => format!("decision {} (rule {})", decision, rule_name),
+            None => format!("decision {} (rule {})", decision, rule_index),
+        };
+        if let Some(location) = self.decision_location(decision, grammar_file_name) {
+            description += " at ";
+            description += &location;
+        }
+        description
+    }
+
+    /// Number of lexer modes, always 0 for parser ATN
+    pub fn modes_count(&self) -> usize { self.mode_to_start_state.len() }
+
+    /// Tokens start state of the lexer `mode`, or `None` if there is no such mode
+    pub fn mode_start_state(&self, mode: usize) -> Option<&dyn ATNState> {
+        self.mode_to_start_state
+            .get(mode)
+            .map(|&state| self.states[state].as_ref())
+    }
+
     /// Computes the set of input symbols which could follow ATN state number
     /// {@code stateNumber} in the specified full {@code context}. This method
     /// considers the complete parser context, but does not evaluate semantic
@@ -172,4 +260,30 @@ impl ATN {
         }
         expected
     }
+
+    /// Returns true if `symbol` can follow state `state_number` with call stack `states_stack`,
+    /// same as `get_expected_tokens(..).contains(symbol)` but stops walking the call stack
+    /// as soon as `symbol` is found.
+    pub fn is_expected_token(
+        &self, symbol: isize, state_number: isize, states_stack: impl Iterator<Item = isize>,
+    ) -> bool {
+        let s = self.states[state_number as usize].as_ref();
+        let mut following = self.next_tokens(s);
+        if following.contains(symbol) {
+            return true;
+        }
+        for state in states_stack {
+            if !following.contains(TOKEN_EPSILON) {
+                return false;
+            }
+            let invoking_state = self.states[state as usize].as_ref();
+            let tr = invoking_state.get_transitions().first().unwrap().as_ref();
+            let tr = tr.cast::<RuleTransition>();
+            following = self.next_tokens(self.states[tr.follow_state].as_ref());
+            if following.contains(symbol) {
+                return true;
+            }
+        }
+        following.contains(TOKEN_EPSILON) && symbol == TOKEN_EOF
+    }
 }
@@ -134,7 +134,11 @@ impl ATNConfigSet {
         config: Box<ATNConfig>,
         mut merge_cache: Option<&mut MergeCache>,
     ) -> bool {
-        assert!(!self.read_only);
+        runtime_assert!(
+            !self.read_only,
+            { state: config.get_state(), alt: config.get_alt() },
+            "can't add config to readonly ATNConfigSet"
+        );
 
         if *config.semantic_context != SemanticContext::NONE {
             self.has_semantic_context = true
@@ -227,3 +231,55 @@ impl ATNConfigSet {
 
     pub fn set_dips_into_outer_context(&mut self, _v: bool) { self.dips_into_outer_context = _v }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::atn_config::ATNConfig;
+    use crate::prediction_context::{PredictionContext, EMPTY_PREDICTION_CONTEXT};
+    use crate::semantic_context::SemanticContext;
+
+    use super::ATNConfigSet;
+
+    fn context(return_state: isize) -> Option<Arc<PredictionContext>> {
+        let parent = Some(EMPTY_PREDICTION_CONTEXT.clone());
+        Some(PredictionContext::new_singleton(parent, return_state).alloc())
+    }
+
+    #[test]
+    fn test_configs_deduplication() {
+        let mut set = ATNConfigSet::new_base_atnconfig_set(true);
+        set.add(Box::new(ATNConfig::new(1, 1, context(10))));
+        set.add(Box::new(ATNConfig::new(1, 1, context(20))));
+        // same state and alternative, contexts are merged
+        assert_eq!(set.length(), 1);
+        let config = set.get_items().next().unwrap();
+        assert_eq!(config.get_context().unwrap().length(), 2);
+
+        let predicate = SemanticContext::Predicate {
+            rule_index: 0,
+            pred_index: 0,
+            is_ctx_dependent: false,
+        };
+        set.add(Box::new(ATNConfig::new(1, 2, context(10))));
+        set.add(Box::new(ATNConfig::new_with_semantic(
+            1,
+            1,
+            context(10),
+            Box::new(predicate),
+        )));
+        assert_eq!(set.length(), 3);
+        assert!(set.has_semantic_context());
+        assert_eq!(set.get_alts().iter().collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't add config to readonly ATNConfigSet")]
+    fn test_read_only() {
+        let mut set = ATNConfigSet::new_base_atnconfig_set(false);
+        set.add(Box::new(ATNConfig::new(1, 1, context(10))));
+        set.set_read_only(true);
+        set.add(Box::new(ATNConfig::new(2, 1, context(10))));
+    }
+}
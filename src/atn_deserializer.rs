@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::str::Chars;
 use std::str::FromStr;
 
@@ -14,6 +15,7 @@ use crate::atn_state::ATNStateType;
 use crate::atn_state::BaseATNState;
 use crate::atn_state::*;
 use crate::atn_type::ATNType;
+use crate::errors::ANTLRError;
 use crate::int_stream::EOF;
 use crate::interval_set::IntervalSet;
 use crate::lexer_action::LexerAction::*;
@@ -38,7 +40,213 @@ lazy_static! {
     ];
 }
 
-const SERIALIZED_VERSION: isize = 3;
+pub(crate) const SERIALIZED_VERSION: isize = 3;
+
+/// Format of ANTLR 4.10 and later, it has no UUID and values are not shifted
+pub(crate) const SERIALIZED_VERSION_4: isize = 4;
+
+/// Returns true if ATN serialized with `actual_uuid` contains data added with `feature`
+fn is_feature_supported(feature: &Uuid, actual_uuid: &Uuid) -> bool {
+    let position = |uuid| SUPPORTED_UUIDS.iter().position(|it| it == uuid);
+    match (position(feature), position(actual_uuid)) {
+        (Some(feature), Some(actual)) => actual >= feature,
+        _ => false,
+    }
+}
+
+/// Serialized representation of ATN as it is stored in generated recognizers.
+///
+/// Usually it is a single `&'static str` constant. For targets that have to split it into several
+/// segments (e.g. because of string literal size limits) segments are joined once on construction,
+/// so `as_str` always returns the same slice without allocations.
+///
+/// ATN can also be stored as a numeric `&'static [i32]` or `&'static [u16]` slice
+/// that contains the same values as ANTLR tool serializes into the string, one per element,
+/// which avoids escaping of non printable characters in generated code.
+/// `ATNDeserializer::deserialize_atn` selects decoding according to the form of the data
+/// and its version, so both the format of ANTLR before 4.10 (version 3)
+/// and the one of ANTLR 4.10+ (version 4) are supported.
+/// In the latter `&[i32]` holds values as is, while string and `&[u16]` hold them
+/// as 16-bit words, same as in ANTLR 4.10+ Java target.
+#[derive(Debug, Clone)]
+pub struct SerializedATN(SerializedData);
+
+#[derive(Debug, Clone)]
+enum SerializedData {
+    Text(Cow<'static, str>),
+    Int(&'static [i32]),
+    Utf16(&'static [u16]),
+}
+
+impl SerializedATN {
+    /// Wraps serialized ATN constant
+    pub const fn from_static(data: &'static str) -> SerializedATN {
+        SerializedATN(SerializedData::Text(Cow::Borrowed(data)))
+    }
+
+    /// Joins serialized ATN segments into a single cached string
+    pub fn from_segments(segments: &[&str]) -> SerializedATN {
+        SerializedATN(SerializedData::Text(Cow::Owned(segments.concat())))
+    }
+
+    /// Wraps serialized ATN constant stored as `i32` values
+    pub const fn from_ints(data: &'static [i32]) -> SerializedATN {
+        SerializedATN(SerializedData::Int(data))
+    }
+
+    /// Wraps serialized ATN constant stored as UTF-16 code units
+    pub const fn from_utf16(data: &'static [u16]) -> SerializedATN {
+        SerializedATN(SerializedData::Utf16(data))
+    }
+
+    /// Returns serialized ATN data if it is stored as a string
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.0 {
+            SerializedData::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    // values as they were before being encoded by ANTLR tool
+    fn values(&self) -> Box<dyn Iterator<Item = isize> + '_> {
+        match &self.0 {
+            SerializedData::Text(text) => decode_chars(text.chars()),
+            SerializedData::Int(data) => decode(data.iter().map(|&it| it as isize), false),
+            SerializedData::Utf16(data) => decode(data.iter().map(|&it| it as isize), true),
+        }
+    }
+}
+
+impl From<&'static str> for SerializedATN {
+    fn from(data: &'static str) -> Self { SerializedATN::from_static(data) }
+}
+
+impl From<&'static [i32]> for SerializedATN {
+    fn from(data: &'static [i32]) -> Self { SerializedATN::from_ints(data) }
+}
+
+impl From<&'static [u16]> for SerializedATN {
+    fn from(data: &'static [u16]) -> Self { SerializedATN::from_utf16(data) }
+}
+
+fn decode_chars(data: Chars<'_>) -> Box<dyn Iterator<Item = isize> + '_> {
+    let data = data.map(|ch| {
+        let ch = ch as isize;
+        // decode surrogates
+        if ch > 0xFFFF {
+            ch - 0x3000
+        } else {
+            ch
+        }
+    });
+    decode(data, true)
+}
+
+// Decodes values according to the version which is always stored as is in the first one,
+// `words` is true if values are stored as 16-bit words
+fn decode<'a>(
+    data: impl Iterator<Item = isize> + 'a, words: bool,
+) -> Box<dyn Iterator<Item = isize> + 'a> {
+    let mut data = data.peekable();
+    match data.peek() {
+        Some(&SERIALIZED_VERSION) => Box::new(unshift(data)),
+        _ if words => Box::new(decode_words(data)),
+        _ => Box::new(data),
+    }
+}
+
+// ANTLR tool adds 2 to all values except the version to avoid zero and -1 in the output
+fn unshift(data: impl Iterator<Item = isize>) -> impl Iterator<Item = isize> {
+    data.enumerate()
+        .map(|(i, value)| if i == 0 { value } else { value - 2 })
+}
+
+// Since ANTLR 4.10 values that do not fit into 15 bits are stored as two 16-bit words,
+// upper one first with the highest bit set, -1 is stored as two 0xFFFF words
+fn decode_words(mut data: impl Iterator<Item = isize>) -> impl Iterator<Item = isize> {
+    std::iter::from_fn(move || {
+        let word = data.next()?;
+        if word & 0x8000 == 0 {
+            return Some(word);
+        }
+        let lower = data.next()?;
+        Some(if word == 0xFFFF && lower == 0xFFFF {
+            -1
+        } else {
+            (word & 0x7FFF) << 16 | lower
+        })
+    })
+}
+
+/// Serialized values that are being deserialized.
+///
+/// Keeps track of position of the next value,
+/// so errors about truncated or corrupt data can point at the offending value.
+struct ATNData<'a> {
+    values: Box<dyn Iterator<Item = isize> + 'a>,
+    position: usize,
+}
+
+impl<'a> ATNData<'a> {
+    fn new(values: impl Iterator<Item = isize> + 'a) -> Self {
+        ATNData {
+            values: Box::new(values),
+            position: 0,
+        }
+    }
+
+    /// Reads next value, `what` describes it in the error message if data has ended
+    fn next(&mut self, what: &str) -> Result<isize, ANTLRError> {
+        match self.values.next() {
+            Some(value) => {
+                self.position += 1;
+                Ok(value)
+            }
+            None => runtime_bail!(
+                { position: self.position },
+                "serialized ATN ended while reading {}",
+                what
+            ),
+        }
+    }
+
+    /// Reads number of the elements that follow
+    fn next_count(&mut self, what: &str) -> Result<usize, ANTLRError> {
+        let count = self.next(what)?;
+        if count < 0 {
+            runtime_bail!({ position: self.position - 1 }, "negative {} {}", what, count);
+        }
+        Ok(count as usize)
+    }
+
+    /// Reads index of one of the `len` elements
+    fn next_index(&mut self, what: &str, len: usize) -> Result<usize, ANTLRError> {
+        let index = self.next(what)?;
+        if index < 0 || index as usize >= len {
+            runtime_bail!(
+                { position: self.position - 1 },
+                "{} {} is out of range 0..{}",
+                what,
+                index,
+                len
+            );
+        }
+        Ok(index as usize)
+    }
+}
+
+// Returns rule index of `state` if ATN has such rule
+fn check_rule_index(atn: &ATN, state: &dyn ATNState) -> Result<usize, ANTLRError> {
+    let rule_index = state.get_rule_index();
+    if rule_index >= atn.rule_to_start_state.len() {
+        runtime_bail!(
+            { state_number: state.get_state_number() },
+            "state belongs to rule {} that does not exist",
+            rule_index as isize
+        );
+    }
+    Ok(rule_index)
+}
 
 #[derive(Debug)]
 pub struct ATNDeserializer {
@@ -52,39 +260,67 @@ impl ATNDeserializer {
         }
     }
 
+    /// Deserializes ATN directly from the representation stored in recognizer
+    ///
+    /// Panics if `data` is not a valid serialized ATN
+    pub fn deserialize_atn(&self, data: &SerializedATN) -> ATN {
+        self.deserialize_values(ATNData::new(data.values()))
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Panics if `data` is not a valid serialized ATN, see `try_deserialize`
     pub fn deserialize(&self, data: Chars<'_>) -> ATN {
-        let mut data = data.clone().map(|ch| {
-            let mut ch = ch as isize;
-            // decode surrogates
-            ch = if ch > 0xFFFF { ch - 0x3000 } else { ch };
-            ch -= 2;
-            ch
-        });
+        self.try_deserialize(data)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `deserialize` but returns `ANTLRError::IllegalStateError`
+    /// if `data` is truncated or corrupt, e.g. because it was not produced by ANTLR tool.
+    pub fn try_deserialize(&self, data: Chars<'_>) -> Result<ATN, ANTLRError> {
+        self.deserialize_values(ATNData::new(decode_chars(data)))
+    }
 
-        self.check_version(data.next().unwrap() + 2);
+    fn deserialize_values(&self, mut data: ATNData<'_>) -> Result<ATN, ANTLRError> {
+        let version = data.next("version")?;
+        self.check_version(version)?;
+        let legacy = version == SERIALIZED_VERSION;
 
-        let _uuid = self.check_uuid(&mut data);
+        // only the old format identifies features by UUID, the new one has all of them
+        let uuid = if legacy {
+            self.check_uuid(&mut data)?
+        } else {
+            *ADDED_UNICODE_SMP
+        };
 
-        let mut atn = self.read_atn(&mut data);
+        let mut atn = self.read_atn(&mut data)?;
 
-        self.read_states(&mut atn, &mut data);
-        self.read_rules(&mut atn, &mut data);
-        self.read_modes(&mut atn, &mut data);
+        self.read_states(&mut atn, &mut data, &uuid)?;
+        self.read_rules(&mut atn, &mut data)?;
+        self.read_modes(&mut atn, &mut data)?;
 
-        let mut sets = self.read_sets(&mut atn, &mut data, |data| {
-            data.next().unwrap() as u16 as isize
-        });
+        let mut sets = if legacy {
+            self.read_sets(&mut atn, &mut data, |data| {
+                Ok(data.next("set bound")? as u16 as isize)
+            })?
+        } else {
+            self.read_sets(&mut atn, &mut data, |data| data.next("set bound"))?
+        };
 
-        sets.extend(self.read_sets(&mut atn, &mut data, |data| {
-            (data.next().unwrap() & 0xFFFF) | data.next().unwrap() << 16
-        }));
+        // sets with code points that do not fit into u16,
+        // each bound is serialized as two u16 values, lower half first
+        if legacy && is_feature_supported(&ADDED_UNICODE_SMP, &uuid) {
+            sets.extend(self.read_sets(&mut atn, &mut data, |data| {
+                let lower = data.next("set bound")? & 0xFFFF;
+                Ok(lower | (data.next("set bound")? & 0xFFFF) << 16)
+            })?);
+        }
 
-        self.read_edges(&mut atn, &mut data, &sets);
-        self.read_decisions(&mut atn, &mut data);
+        self.read_edges(&mut atn, &mut data, &sets)?;
+        self.read_decisions(&mut atn, &mut data)?;
         if atn.grammar_type == ATNType::LEXER {
-            self.read_lexer_actions(&mut atn, &mut data);
+            self.read_lexer_actions(&mut atn, &mut data)?;
         }
-        self.mark_precedence_decisions(&mut atn, &mut data);
+        self.mark_precedence_decisions(&mut atn)?;
         if self.deserialization_options.is_verify() {
             self.verify_atn(&mut atn, &mut data);
         }
@@ -94,83 +330,91 @@ impl ATNDeserializer {
         //            a.verifyATN(atn)
         //        }
 
-        atn
+        Ok(atn)
     }
 
     // fn reset(&self, _data: Vec<u8>) { unimplemented!() }
 
-    fn check_version(&self, version: isize) {
-        if version != self::SERIALIZED_VERSION {
-            panic!(
-                "Could not deserialize ATN with version {} (expected {})",
-                version, SERIALIZED_VERSION
+    fn check_version(&self, version: isize) -> Result<(), ANTLRError> {
+        if version != SERIALIZED_VERSION && version != SERIALIZED_VERSION_4 {
+            runtime_bail!(
+                "Could not deserialize ATN with version {} (expected {} or {})",
+                version,
+                SERIALIZED_VERSION,
+                SERIALIZED_VERSION_4
             );
         }
+        Ok(())
     }
 
-    fn check_uuid(&self, data: &mut dyn Iterator<Item = isize>) -> Uuid {
+    fn check_uuid(&self, data: &mut ATNData<'_>) -> Result<Uuid, ANTLRError> {
         //rust uses UTF-8 encoding so we need explicitly convert unicode
         //codepoint numbers to bytes
         let mut bytes = Vec::new();
-        for i in data.take(8) {
-            bytes.write_u16::<LittleEndian>(i as u16).unwrap();
+        for _ in 0..8 {
+            bytes
+                .write_u16::<LittleEndian>(data.next("UUID")? as u16)
+                .unwrap();
         }
 
         bytes.reverse();
         let uuid = Uuid::from_slice(&bytes).unwrap();
         if !SUPPORTED_UUIDS.contains(&uuid) {
-            panic!("Could not deserialize ATN with UUID {}", uuid)
+            runtime_bail!("Could not deserialize ATN with UUID {}", uuid)
         }
-        uuid
+        Ok(uuid)
     }
 
-    fn read_atn(&self, data: &mut dyn Iterator<Item = isize>) -> ATN {
+    fn read_atn(&self, data: &mut ATNData<'_>) -> Result<ATN, ANTLRError> {
         let atn = ATN::new_atn(
-            match data.next() {
-                Some(0) => ATNType::LEXER,
-                Some(1) => ATNType::PARSER,
-                _ => panic!("invalid ATN type"),
+            match data.next("ATN type")? {
+                0 => ATNType::LEXER,
+                1 => ATNType::PARSER,
+                other => {
+                    runtime_bail!({ position: data.position - 1 }, "invalid ATN type {}", other)
+                }
             },
-            data.next().unwrap(),
+            data.next("max token type")?,
         );
 
-        atn
+        Ok(atn)
     }
 
-    fn read_states(&self, atn: &mut ATN, data: &mut dyn Iterator<Item = isize>) {
+    fn read_states(
+        &self, atn: &mut ATN, data: &mut ATNData<'_>, uuid: &Uuid,
+    ) -> Result<(), ANTLRError> {
         //        let loop_back_states = Vec::<(BaseATNState,isize)>::new();
         //        let end_states = Vec::<(BaseATNState,isize)>::new();
-        let states_count = data.next().unwrap() as usize;
+        let states_count = data.next_count("state count")?;
         for i in 0..states_count {
-            let state_type = data.next().unwrap();
+            let state_type = data.next("state type")?;
             if state_type == ATNSTATE_INVALID_STATE_NUMBER {
-                atn.add_state(self.state_factory(ATNSTATE_INVALID_TYPE, -1, i));
-                panic!("why invalid state serialized?");
+                runtime_bail!({ state_number: i }, "invalid state serialized");
             }
 
-            let mut rule_index = data.next().unwrap();
+            let mut rule_index = data.next("state rule index")?;
             if rule_index == 0xFFFF {
                 rule_index = -1;
             }
-            let mut state = self.state_factory(state_type, rule_index, i);
+            let mut state = self.state_factory(state_type, rule_index, i)?;
 
             match state.get_state_type_mut() {
                 ATNStateType::DecisionState {
                     state: ATNDecisionState::BlockStartState { end_state, .. },
                     ..
-                } => *end_state = data.next().unwrap() as ATNStateRef,
+                } => *end_state = data.next_index("block end state", states_count)?,
                 ATNStateType::LoopEndState(loop_back) => {
-                    *loop_back = data.next().unwrap() as ATNStateRef
+                    *loop_back = data.next_index("loop back state", states_count)?
                 }
                 _ => (),
             }
             atn.add_state(state);
         }
 
-        let num_non_greedy = data.next().unwrap();
+        let num_non_greedy = data.next_count("non-greedy state count")?;
         //println!("num_non_greedy {}", num_non_greedy);
         for _ in 0..num_non_greedy {
-            let st = data.next().unwrap() as usize;
+            let st = data.next_index("non-greedy state", states_count)?;
             if let ATNStateType::DecisionState { nongreedy: ng, .. } =
                 atn.states[st].get_state_type_mut()
             {
@@ -178,11 +422,10 @@ impl ATNDeserializer {
             }
         }
 
-        //if (supportsPrecedencePredicates)
-        if true {
-            let num_precedence_states = data.next().unwrap();
+        if is_feature_supported(&ADDED_PRECEDENCE_TRANSITIONS, uuid) {
+            let num_precedence_states = data.next_count("precedence state count")?;
             for _ in 0..num_precedence_states {
-                let st = data.next().unwrap() as usize;
+                let st = data.next_index("precedence state", states_count)?;
                 if let ATNStateType::RuleStartState {
                     is_left_recursive: left_rec,
                     ..
@@ -192,20 +435,21 @@ impl ATNDeserializer {
                 }
             }
         }
+        Ok(())
     }
 
-    fn read_rules(&self, atn: &mut ATN, data: &mut dyn Iterator<Item = isize>) {
-        let nrules = data.next().unwrap() as usize;
+    fn read_rules(&self, atn: &mut ATN, data: &mut ATNData<'_>) -> Result<(), ANTLRError> {
+        let nrules = data.next_count("rule count")?;
         //        if atn.grammar_type == ATNType::LEXER {
         //            atn.rule_to_token_type.resize(nrules, 0)
         //        }
 
-        atn.rule_to_start_state.resize(nrules, 0);
-        for i in 0..nrules {
-            let s = data.next().unwrap() as usize;
-            atn.rule_to_start_state[i] = s;
+        // count is not trusted to preallocate, vectors grow only as rules are actually read
+        for _ in 0..nrules {
+            let s = data.next_index("rule start state", atn.states.len())?;
+            atn.rule_to_start_state.push(s);
             if atn.grammar_type == ATNType::LEXER {
-                let token_type = data.next().unwrap();
+                let token_type = data.next("rule token type")?;
 
                 atn.rule_to_token_type.push(token_type);
             }
@@ -215,9 +459,9 @@ impl ATNDeserializer {
 
         atn.rule_to_stop_state.resize(nrules, 0);
         for i in 0..atn.states.len() {
-            let state = atn.states.get(i).unwrap();
+            let state = atn.states[i].as_ref();
             if let ATNStateType::RuleStopState = state.get_state_type() {
-                let rule_index = state.get_rule_index();
+                let rule_index = check_rule_index(atn, state)?;
                 atn.rule_to_stop_state[rule_index] = i;
                 let start_state = atn
                     .states
@@ -231,61 +475,59 @@ impl ATNDeserializer {
                 }
             }
         }
+        Ok(())
     }
 
-    fn read_modes(&self, atn: &mut ATN, data: &mut dyn Iterator<Item = isize>) {
-        let nmodes = data.next().unwrap();
+    fn read_modes(&self, atn: &mut ATN, data: &mut ATNData<'_>) -> Result<(), ANTLRError> {
+        let nmodes = data.next_count("mode count")?;
         for _i in 0..nmodes {
-            atn.mode_to_start_state.push(data.next().unwrap() as usize);
+            let start_state = data.next_index("mode start state", atn.states.len())?;
+            atn.mode_to_start_state.push(start_state);
         }
+        Ok(())
     }
 
-    fn read_sets<T: Iterator<Item = isize>>(
-        &self,
-        _atn: &mut ATN,
-        data: &mut T,
-        read_unicode: fn(&mut T) -> isize,
-    ) -> Vec<IntervalSet> {
-        let nsets = data.next().unwrap();
+    fn read_sets<'a>(
+        &self, _atn: &mut ATN, data: &mut ATNData<'a>,
+        read_unicode: fn(&mut ATNData<'a>) -> Result<isize, ANTLRError>,
+    ) -> Result<Vec<IntervalSet>, ANTLRError> {
+        let nsets = data.next_count("set count")?;
         let mut sets = Vec::new();
         for _i in 0..nsets {
-            let intervals = data.next().unwrap();
+            let intervals = data.next_count("interval count")?;
 
             let mut set = IntervalSet::new();
 
             // check if contains eof
-            if data.next().unwrap() != 0 {
+            if data.next("set EOF flag")? != 0 {
                 set.add_one(-1)
             }
 
             for _ in 0..intervals {
-                set.add_range(read_unicode(data), read_unicode(data));
+                set.add_range(read_unicode(data)?, read_unicode(data)?);
             }
             sets.push(set);
         }
 
-        sets
+        Ok(sets)
     }
 
     fn read_edges(
-        &self,
-        atn: &mut ATN,
-        data: &mut dyn Iterator<Item = isize>,
-        sets: &Vec<IntervalSet>,
-    ) {
-        let nedges = data.next().unwrap();
+        &self, atn: &mut ATN, data: &mut ATNData<'_>, sets: &Vec<IntervalSet>,
+    ) -> Result<(), ANTLRError> {
+        let nedges = data.next_count("edge count")?;
 
         for _i in 0..nedges {
-            let src = data.next().unwrap() as usize;
-            let trg = data.next().unwrap() as usize;
-            let ttype = data.next().unwrap();
-            let arg1 = data.next().unwrap();
-            let arg2 = data.next().unwrap();
-            let arg3 = data.next().unwrap();
+            let src = data.next_index("edge source state", atn.states.len())?;
+            let trg = data.next_index("edge target state", atn.states.len())?;
+            let ttype = data.next("edge type")?;
+            let arg1 = data.next("edge argument")?;
+            let arg2 = data.next("edge argument")?;
+            let arg3 = data.next("edge argument")?;
 
-            let transition = self.edge_factory(atn, ttype, src, trg, arg1, arg2, arg3, sets);
+            let transition = self.edge_factory(atn, ttype, src, trg, arg1, arg2, arg3, sets)?;
 
-            atn.states.get_mut(src).unwrap().add_transition(transition);
+            atn.states[src].add_transition(transition);
         }
 
         let mut new_tr = Vec::new();
@@ -295,19 +537,17 @@ impl ATNDeserializer {
                     TransitionType::TRANSITION_RULE => {
                         //                        println!("TRANSITION_RULE");
                         let tr = tr.as_ref().cast::<RuleTransition>();
-                        let target = atn.states.get(tr.get_target()).unwrap();
+                        let target = atn.states[tr.get_target()].as_ref();
+                        let rule_index = check_rule_index(atn, target)?;
 
                         let outermost_prec_return = if let ATNStateType::RuleStartState {
                             is_left_recursive: true,
                             ..
-                        } = atn
-                            .states
-                            .get(atn.rule_to_start_state[target.get_rule_index()])
-                            .unwrap()
-                            .get_state_type()
+                        } =
+                            atn.states[atn.rule_to_start_state[rule_index]].get_state_type()
                         {
                             if tr.precedence == 0 {
-                                target.get_rule_index() as isize
+                                rule_index as isize
                             } else {
                                 -1
                             }
@@ -319,10 +559,7 @@ impl ATNDeserializer {
                             target: tr.follow_state,
                             outermost_precedence_return: outermost_prec_return,
                         };
-                        new_tr.push((
-                            atn.rule_to_stop_state[target.get_rule_index()],
-                            Box::new(return_tr),
-                        ));
+                        new_tr.push((atn.rule_to_stop_state[rule_index], Box::new(return_tr)));
                     }
                     _ => continue,
                 }
@@ -362,43 +599,46 @@ impl ATNDeserializer {
                 _x => { /*println!("{:?}",x);*/ }
             }
         }
+        Ok(())
     }
 
-    fn read_decisions(&self, atn: &mut ATN, _data: &mut dyn Iterator<Item = isize>) {
-        let ndecisions = _data.next().unwrap();
+    fn read_decisions(&self, atn: &mut ATN, _data: &mut ATNData<'_>) -> Result<(), ANTLRError> {
+        let ndecisions = _data.next_count("decision count")?;
         for i in 0..ndecisions {
-            let s = _data.next().unwrap() as usize;
-            let dec_state: &mut Box<dyn ATNState> = atn.states.get_mut(s).unwrap();
+            let s = _data.next_index("decision state", atn.states.len())?;
+            let dec_state: &mut Box<dyn ATNState> = &mut atn.states[s];
             atn.decision_to_state.push(s);
             if let ATNStateType::DecisionState { decision, .. } = dec_state.get_state_type_mut() {
-                *decision = i
+                *decision = i as isize
             }
         }
+        Ok(())
     }
 
-    fn read_lexer_actions(&self, atn: &mut ATN, _data: &mut dyn Iterator<Item = isize>) {
+    fn read_lexer_actions(&self, atn: &mut ATN, _data: &mut ATNData<'_>) -> Result<(), ANTLRError> {
         //lexer actions are always supported here
-        let nactions = _data.next().unwrap() as usize;
+        let nactions = _data.next_count("lexer action count")?;
 
         for _i in 0..nactions {
-            let action_type = _data.next().unwrap();
+            let action_type = _data.next("lexer action type")?;
 
-            let mut data1 = _data.next().unwrap();
+            let mut data1 = _data.next("lexer action argument")?;
             if data1 == 0xFFFF {
                 data1 = -1;
             }
-            let mut data2 = _data.next().unwrap();
+            let mut data2 = _data.next("lexer action argument")?;
             if data2 == 0xFFFF {
                 data2 = -1;
             }
 
-            let lexer_action = self.lexer_action_factory(action_type, data1, data2);
+            let lexer_action = self.lexer_action_factory(action_type, data1, data2)?;
 
             atn.lexer_actions.push(lexer_action);
         }
+        Ok(())
     }
 
-    fn mark_precedence_decisions(&self, _atn: &mut ATN, _data: &mut dyn Iterator<Item = isize>) {
+    fn mark_precedence_decisions(&self, _atn: &mut ATN) -> Result<(), ANTLRError> {
         let mut precedence_states = Vec::new();
         for state in _atn.states.iter() {
             if let ATNStateType::DecisionState {
@@ -406,14 +646,19 @@ impl ATNDeserializer {
                 ..
             } = state.get_state_type()
             {
+                let rule_index = check_rule_index(_atn, state.as_ref())?;
                 if let ATNStateType::RuleStartState {
                     is_left_recursive: true,
                     ..
-                } =
-                    _atn.states[_atn.rule_to_start_state[state.get_rule_index()]].get_state_type()
+                } = _atn.states[_atn.rule_to_start_state[rule_index]].get_state_type()
                 {
-                    let maybe_loop_end =
-                        state.get_transitions().iter().last().unwrap().get_target();
+                    let maybe_loop_end = match state.get_transitions().last() {
+                        Some(transition) => transition.get_target(),
+                        None => runtime_bail!(
+                            { state_number: state.get_state_number() },
+                            "star loop entry state without transitions"
+                        ),
+                    };
                     let maybe_loop_end = _atn.states[maybe_loop_end].as_ref();
                     if let ATNStateType::LoopEndState(_) = maybe_loop_end.get_state_type() {
                         if maybe_loop_end.has_epsilon_only_transitions() {
@@ -441,9 +686,10 @@ impl ATNDeserializer {
                 *is_precedence = true
             }
         }
+        Ok(())
     }
 
-    fn verify_atn(&self, _atn: &mut ATN, _data: &mut dyn Iterator<Item = isize>) {
+    fn verify_atn(&self, _atn: &mut ATN, _data: &mut ATNData<'_>) {
         //TODO
     }
 
@@ -459,7 +705,7 @@ impl ATNDeserializer {
         arg2: isize,
         arg3: isize,
         sets: &Vec<IntervalSet>,
-    ) -> Box<dyn Transition> {
+    ) -> Result<Box<dyn Transition>, ANTLRError> {
         //        //        let target = atn.states.get
         //        let mut base = BaseTransition {
         //            target: trg,
@@ -468,7 +714,15 @@ impl ATNDeserializer {
         //            interval_set: IntervalSet::new_interval_set(),
         //        };
 
-        match type_index {
+        let set = |index: isize| match sets.get(index as usize) {
+            Some(set) => Ok(set.clone()),
+            None => Err(runtime_error!(
+                { src: _src, target: target },
+                "transition refers to set {} that does not exist",
+                index
+            )),
+        };
+        Ok(match type_index {
             TRANSITION_EPSILON => Box::new(EpsilonTransition {
                 target,
                 outermost_precedence_return: 0,
@@ -483,6 +737,13 @@ impl ATNDeserializer {
                 stop: arg2,
             }),
             TRANSITION_RULE => {
+                if _atn.states.get(arg1 as usize).is_none() {
+                    runtime_bail!(
+                        { src: _src, target: target },
+                        "rule transition to state {} that does not exist",
+                        arg1
+                    );
+                }
                 //                base.set_target(arg1 as usize);
                 Box::new(RuleTransition {
                     target: arg1 as usize,
@@ -510,19 +771,23 @@ impl ATNDeserializer {
             }),
             TRANSITION_SET => Box::new(SetTransition {
                 target,
-                set: sets[arg1 as usize].clone(),
+                set: set(arg1)?,
             }),
             TRANSITION_NOTSET => Box::new(NotSetTransition {
                 target,
-                set: sets[arg1 as usize].clone(),
+                set: set(arg1)?,
             }),
             TRANSITION_WILDCARD => Box::new(WildcardTransition { target }),
             TRANSITION_PRECEDENCE => Box::new(PrecedencePredicateTransition {
                 target,
                 precedence: arg1,
             }),
-            _ => panic!("invalid transition type"),
-        }
+            _ => runtime_bail!(
+                { src: _src, target: target, args: (arg1, arg2, arg3) },
+                "invalid transition type {}",
+                type_index
+            ),
+        })
     }
 
     fn state_factory(
@@ -530,7 +795,7 @@ impl ATNDeserializer {
         type_index: isize,
         rule_index: isize,
         state_number: usize,
-    ) -> Box<dyn ATNState> {
+    ) -> Result<Box<dyn ATNState>, ANTLRError> {
         let mut state = BaseATNState::new_base_atnstate();
         state.state_number = state_number;
         state.rule_index = rule_index as usize;
@@ -589,14 +854,20 @@ impl ATNDeserializer {
             },
             ATNSTATE_LOOP_END => ATNStateType::LoopEndState(0),
 
-            _ => panic!("invalid ATN state type"),
+            _ => runtime_bail!(
+                { state_number: state_number, rule_index: rule_index },
+                "invalid ATN state type {}",
+                type_index
+            ),
         };
         //        println!("created state {} {:?}", state_number, state.state_type);
-        Box::new(state)
+        Ok(Box::new(state))
     }
 
-    fn lexer_action_factory(&self, action_type: isize, data1: isize, data2: isize) -> LexerAction {
-        match action_type {
+    fn lexer_action_factory(
+        &self, action_type: isize, data1: isize, data2: isize,
+    ) -> Result<LexerAction, ANTLRError> {
+        Ok(match action_type {
             LEXER_ACTION_TYPE_CHANNEL => LexerChannelAction(data1),
             LEXER_ACTION_TYPE_CUSTOM => LexerCustomAction {
                 rule_index: data1,
@@ -608,7 +879,282 @@ impl ATNDeserializer {
             LEXER_ACTION_TYPE_PUSH_MODE => LexerPushModeAction(data1),
             LEXER_ACTION_TYPE_SKIP => LexerSkipAction,
             LEXER_ACTION_TYPE_TYPE => LexerTypeAction(data1),
-            _ => panic!("invalid action type {}", action_type),
+            _ => runtime_bail!(
+                { data: (data1, data2) },
+                "invalid lexer action type {}",
+                action_type
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serialized_atn_as_str() {
+        let data = SerializedATN::from_segments(&["\x03\u{608b}", "\u{a72a}"]);
+        assert_eq!(data.as_str(), Some("\x03\u{608b}\u{a72a}"));
+        assert!(std::ptr::eq(data.as_str().unwrap(), data.as_str().unwrap()));
+
+        const STATIC: &str = "\x03";
+        let data = SerializedATN::from_static(STATIC);
+        assert!(std::ptr::eq(data.as_str().unwrap(), STATIC));
+        assert!(SerializedATN::from_ints(&[3]).as_str().is_none());
+    }
+
+    /// Encodes values the same way as ANTLR tool does for generated recognizers
+    fn serialize(uuid: &Uuid, values: &[isize]) -> String {
+        let uuid = uuid.as_u128();
+        let uuid = (0..8).map(|i| (uuid >> (16 * i)) as isize & 0xFFFF);
+        let encoded = values[1..].iter().copied().map(|value| {
+            let ch = (value + 2) & 0xFFFF;
+            // surrogates can't be stored in `str`
+            let ch = if (0xD800..0xE000).contains(&ch) {
+                ch + 0x3000
+            } else {
+                ch
+            };
+            std::char::from_u32(ch as u32).unwrap()
+        });
+        let mut result = std::char::from_u32(values[0] as u32).unwrap().to_string();
+        result.extend(uuid.map(|it| std::char::from_u32(((it + 2) & 0xFFFF) as u32).unwrap()));
+        result.extend(encoded);
+        result
+    }
+
+    /// Encodes values the same way as ANTLR 4.10+ does for string and UTF-16 targets
+    fn encode_words(values: &[isize]) -> Vec<u16> {
+        let mut result = Vec::new();
+        for &value in values {
+            match value {
+                -1 => result.extend_from_slice(&[0xFFFF, 0xFFFF]),
+                0..=0x7FFF => result.push(value as u16),
+                _ => result.extend_from_slice(&[(value >> 16) as u16 | 0x8000, value as u16]),
+            }
         }
+        result
+    }
+
+    /// Lexer with two rules `EMOJI : [\u{1F600}-\u{1F64F}] ;` and `ABC : [a-c] | EOF ;`,
+    /// the second one is in the separate mode
+    fn two_modes_lexer(first_edge_type: isize) -> String {
+        let values = two_modes_lexer_values(SERIALIZED_VERSION, first_edge_type);
+        serialize(&ADDED_UNICODE_SMP, &values)
+    }
+
+    /// Values of `two_modes_lexer` in the format of `version`
+    fn two_modes_lexer_values(version: isize, first_edge_type: isize) -> Vec<isize> {
+        let sets: &[isize] = if version == SERIALIZED_VERSION {
+            #[rustfmt::skip]
+            let sets = &[
+                // 16-bit sets: interval count, contains EOF, intervals
+                1, 1, 1, 'a' as isize, 'c' as isize,
+                // 32-bit sets: interval count, contains EOF, intervals as lower and upper halves
+                1, 1, 0, 0xF600, 0x1, 0xF64F, 0x1,
+            ];
+            sets
+        } else {
+            #[rustfmt::skip]
+            let sets = &[
+                // sets: interval count, contains EOF, intervals
+                2,
+                1, 1, 'a' as isize, 'c' as isize,
+                1, 0, 0x1F600, 0x1F64F,
+            ];
+            sets
+        };
+        #[rustfmt::skip]
+        let head = [
+            version,
+            // lexer, max token type
+            0, 2,
+            // states: two tokens start states and two rules `EMOJI : [\u{1F600}-\u{1F64F}] ;`
+            // and `ABC : [a-c] | EOF ;` which is in the second mode
+            8,
+            ATNSTATE_TOKEN_START, -1,
+            ATNSTATE_TOKEN_START, -1,
+            ATNSTATE_RULE_START, 0,
+            ATNSTATE_RULE_STOP, 0,
+            ATNSTATE_RULE_START, 1,
+            ATNSTATE_RULE_STOP, 1,
+            ATNSTATE_BASIC, 0,
+            ATNSTATE_BASIC, 1,
+            // non greedy and precedence states
+            0, 0,
+            // rules with start state and token type
+            2, 2, 1, 4, 2,
+            // modes
+            2, 0, 1,
+        ];
+        #[rustfmt::skip]
+        let tail = [
+            // edges
+            6,
+            0, 2, first_edge_type, 0, 0, 0,
+            1, 4, TRANSITION_EPSILON, 0, 0, 0,
+            2, 6, TRANSITION_SET, 1, 0, 0,
+            6, 3, TRANSITION_EPSILON, 0, 0, 0,
+            4, 7, TRANSITION_SET, 0, 0, 0,
+            7, 5, TRANSITION_EPSILON, 0, 0, 0,
+            // decisions
+            2, 0, 1,
+            // lexer actions
+            0,
+        ];
+        [&head[..], sets, &tail[..]].concat()
+    }
+
+    fn assert_same_lexer(atn: &ATN, expected: &ATN) {
+        assert_eq!(atn.modes_count(), 2);
+        assert_eq!(atn.states.len(), expected.states.len());
+        for mode in 0..2 {
+            let start = atn.mode_start_state(mode).unwrap();
+            let expected_start = expected.mode_start_state(mode).unwrap();
+            assert_eq!(atn.next_tokens(start), expected.next_tokens(expected_start));
+        }
+    }
+
+    #[test]
+    fn test_lexer_modes_and_unicode_sets() {
+        let data = two_modes_lexer(TRANSITION_EPSILON);
+        let atn = ATNDeserializer::new(None).deserialize(data.chars());
+
+        assert_eq!(atn.modes_count(), 2);
+        assert!(atn.mode_start_state(2).is_none());
+
+        let default_mode = atn.next_tokens(atn.mode_start_state(0).unwrap());
+        assert!(default_mode.contains(0x1F600));
+        assert!(default_mode.contains(0x1F64F));
+        assert!(!default_mode.contains(0x1F650));
+        assert!(!default_mode.contains('a' as isize));
+
+        let second_mode = atn.next_tokens(atn.mode_start_state(1).unwrap());
+        assert!(second_mode.contains(EOF));
+        assert!(second_mode.contains('b' as isize));
+        assert!(!second_mode.contains(0x1F600));
+    }
+
+    #[test]
+    fn test_numeric_serialized_atn() {
+        let text = two_modes_lexer(TRANSITION_EPSILON);
+        let ints: &'static [i32] = Box::leak(text.chars().map(|ch| ch as i32).collect());
+        let utf16: &'static [u16] = Box::leak(text.encode_utf16().collect());
+        let expected = ATNDeserializer::new(None).deserialize(text.chars());
+        for data in &[SerializedATN::from(ints), SerializedATN::from(utf16)] {
+            let atn = ATNDeserializer::new(None).deserialize_atn(data);
+            assert_same_lexer(&atn, &expected);
+        }
+    }
+
+    #[test]
+    fn test_version_4_serialized_atn() {
+        let values = two_modes_lexer_values(SERIALIZED_VERSION_4, TRANSITION_EPSILON);
+        let ints: &'static [i32] = Box::leak(values.iter().map(|&it| it as i32).collect());
+        let words = encode_words(&values);
+        // token start states have no rule and code points of the emoji set don't fit into u16
+        assert!(words.windows(2).any(|it| it == [0xFFFF, 0xFFFF]));
+        assert!(words.windows(2).any(|it| it == [0x8001, 0xF600]));
+        let text = words
+            .iter()
+            .map(|&it| std::char::from_u32(it as u32).unwrap())
+            .collect::<String>();
+        let utf16: &'static [u16] = Box::leak(words.into_boxed_slice());
+
+        let expected =
+            ATNDeserializer::new(None).deserialize(two_modes_lexer(TRANSITION_EPSILON).chars());
+        for data in &[SerializedATN::from(ints), SerializedATN::from(utf16)] {
+            let atn = ATNDeserializer::new(None).deserialize_atn(data);
+            assert_same_lexer(&atn, &expected);
+        }
+        let atn = ATNDeserializer::new(None).deserialize(text.chars());
+        assert_same_lexer(&atn, &expected);
+    }
+
+    #[test]
+    fn test_invalid_transition_message() {
+        let data = two_modes_lexer(99);
+        let payload = std::panic::catch_unwind(|| {
+            ATNDeserializer::new(None).deserialize(data.chars());
+        })
+        .unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert!(
+            message.starts_with("antlr-rust internal error: invalid transition type 99"),
+            "{}",
+            message
+        );
+        #[cfg(feature = "debug-diagnostics")]
+        assert!(
+            message.ends_with("[src=0, target=2, args=(0, 0, 0)]"),
+            "{}",
+            message
+        );
+    }
+
+    fn error_message(data: &str) -> String {
+        match ATNDeserializer::new(None).try_deserialize(data.chars()) {
+            Err(ANTLRError::IllegalStateError(message)) => message,
+            r => panic!("expected error, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_corrupt_atn_messages() {
+        let message = error_message("\x05");
+        assert_eq!(
+            message,
+            "antlr-rust internal error: Could not deserialize ATN with version 5 (expected 3 or 4)"
+        );
+
+        // last edge, decisions and lexer actions are missing
+        let data = two_modes_lexer(TRANSITION_EPSILON);
+        let truncated = data
+            .chars()
+            .take(data.chars().count() - 10)
+            .collect::<String>();
+        let message = error_message(&truncated);
+        assert!(
+            message.starts_with(
+                "antlr-rust internal error: serialized ATN ended while reading edge source state"
+            ),
+            "{}",
+            message
+        );
+        #[cfg(feature = "debug-diagnostics")]
+        assert!(message.ends_with(&format!("[position={}]", data.chars().count() - 10)));
+
+        let mut values = two_modes_lexer_values(SERIALIZED_VERSION_4, TRANSITION_EPSILON);
+        // start state of the second mode
+        assert_eq!(values[29], 1);
+        values[29] = 8;
+        let data = encode_words(&values)
+            .iter()
+            .map(|&it| std::char::from_u32(it as u32).unwrap())
+            .collect::<String>();
+        let message = error_message(&data);
+        assert!(
+            message
+                .starts_with("antlr-rust internal error: mode start state 8 is out of range 0..8"),
+            "{}",
+            message
+        );
+        #[cfg(feature = "debug-diagnostics")]
+        assert!(message.ends_with("[position=29]"), "{}", message);
+    }
+
+    #[test]
+    fn test_feature_support() {
+        assert!(is_feature_supported(&ADDED_UNICODE_SMP, &ADDED_UNICODE_SMP));
+        assert!(is_feature_supported(
+            &ADDED_PRECEDENCE_TRANSITIONS,
+            &ADDED_LEXER_ACTIONS
+        ));
+        assert!(!is_feature_supported(
+            &ADDED_UNICODE_SMP,
+            &ADDED_LEXER_ACTIONS
+        ));
+        assert!(!is_feature_supported(&ADDED_UNICODE_SMP, &Uuid::nil()));
     }
 }
@@ -0,0 +1,433 @@
+//! Synthetic workloads for benchmarking the runtime itself.
+//!
+//! Everything here is grammar agnostic, so it can be used to measure stream throughput,
+//! tree construction, `IntervalSet` operations and DFA lookups without a generated parser.
+//! All generators are driven by [`BenchRng`] and produce exactly the same output for the same seed,
+//! so numbers obtained with them are comparable between runs and between crates.
+//!
+//! Only available with `bench` feature enabled.
+use std::rc::Rc;
+use std::sync::atomic::{AtomicIsize, AtomicUsize};
+use std::sync::Arc;
+
+use crate::atn::ATN;
+use crate::atn_deserializer::ATNDeserializer;
+use crate::dfa::DFA;
+use crate::int_stream::{IntStream, EOF};
+use crate::interval_set::IntervalSet;
+use crate::parser_rule_context::BaseParserRuleContext;
+use crate::rule_context::{EmptyContext, EmptyCustomRuleContext};
+use crate::token::{OwningToken, TOKEN_DEFAULT_CHANNEL, TOKEN_EOF, TOKEN_HIDDEN_CHANNEL};
+use crate::token_factory::OwningTokenFactory;
+use crate::token_source::TokenSource;
+use crate::tree::TerminalNode;
+use better_any::{Tid, TidAble};
+use std::marker::PhantomData;
+
+/// Small deterministic pseudo random generator (SplitMix64).
+///
+/// Used instead of external crates to guarantee that sequence for a given seed
+/// never changes between versions of dependencies.
+#[derive(Clone, Debug)]
+pub struct BenchRng(u64);
+
+impl BenchRng {
+    /// Creates generator with `seed`
+    pub fn new(seed: u64) -> BenchRng { BenchRng(seed) }
+
+    /// Returns next value in sequence
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns value in `low..high` range
+    pub fn gen_range(&mut self, low: isize, high: isize) -> isize {
+        assert!(low < high, "empty range");
+        low + (self.next_u64() % (high - low) as u64) as isize
+    }
+
+    /// Returns true with `percent`% probability
+    pub fn gen_percent(&mut self, percent: u32) -> bool { (self.next_u64() % 100) < percent as u64 }
+}
+
+/// Configuration for [`generate_tokens`]
+#[derive(Clone, Debug)]
+pub struct TokenListConfig {
+    /// Number of tokens to generate, not counting EOF
+    pub count: usize,
+    /// Token types are generated in `1..=max_token_type` range
+    pub max_token_type: isize,
+    /// Percentage of tokens that go to `TOKEN_HIDDEN_CHANNEL`
+    pub hidden_percent: u32,
+    /// Percentage of tokens that go to `extra_channel`
+    pub extra_channel_percent: u32,
+    /// Additional custom channel
+    pub extra_channel: isize,
+    /// Text of generated tokens is `1..=max_text_len` chars long
+    pub max_text_len: usize,
+}
+
+impl Default for TokenListConfig {
+    fn default() -> Self {
+        TokenListConfig {
+            count: 10_000,
+            max_token_type: 20,
+            hidden_percent: 30,
+            extra_channel_percent: 5,
+            extra_channel: 2,
+            max_text_len: 8,
+        }
+    }
+}
+
+/// Generates list of tokens terminated by EOF token.
+///
+/// Offsets, lines and columns are consistent as if tokens were produced by a lexer,
+/// hidden tokens contain whitespace, others contain ascii letters.
+pub fn generate_tokens(seed: u64, config: &TokenListConfig) -> Vec<OwningToken> {
+    let mut rng = BenchRng::new(seed);
+    let mut result = Vec::with_capacity(config.count + 1);
+    let mut offset = 0;
+    let mut line = 1;
+    let mut column = 0;
+    for index in 0..config.count {
+        let roll = (rng.next_u64() % 100) as u32;
+        let channel = if roll < config.hidden_percent {
+            TOKEN_HIDDEN_CHANNEL
+        } else if roll < config.hidden_percent + config.extra_channel_percent {
+            config.extra_channel
+        } else {
+            TOKEN_DEFAULT_CHANNEL
+        };
+        let len = rng.gen_range(1, config.max_text_len as isize + 1);
+        let text: String = if channel == TOKEN_HIDDEN_CHANNEL {
+            (0..len)
+                .map(|_| if rng.gen_percent(20) { '\n' } else { ' ' })
+                .collect()
+        } else {
+            (0..len)
+                .map(|_| (b'a' + rng.gen_range(0, 26) as u8) as char)
+                .collect()
+        };
+        let token_type = rng.gen_range(1, config.max_token_type + 1);
+        let (start_line, start_column) = (line, column);
+        for ch in text.chars() {
+            if ch == '\n' {
+                line += 1;
+                column = 0;
+            } else {
+                column += 1;
+            }
+        }
+        result.push(OwningToken {
+            token_type,
+            channel,
+            start: offset,
+            stop: offset + len - 1,
+            token_index: AtomicIsize::new(index as isize),
+            mode: AtomicUsize::new(0),
+            line: start_line,
+            column: start_column,
+            end_line: line,
+            end_column: column,
+            text: text.clone(),
+            read_only: true,
+        });
+        offset += len;
+    }
+    result.push(OwningToken {
+        token_type: TOKEN_EOF,
+        channel: TOKEN_DEFAULT_CHANNEL,
+        start: offset,
+        stop: offset - 1,
+        token_index: AtomicIsize::new(config.count as isize),
+        mode: AtomicUsize::new(0),
+        line,
+        column,
+        end_line: line,
+        end_column: column,
+        text: "<EOF>".to_owned(),
+        read_only: true,
+    });
+    result
+}
+
+/// `TokenSource` that replays pre-generated tokens, for measuring token streams in isolation from lexer.
+#[derive(Tid, Debug)]
+pub struct ReplayTokenSource<'input> {
+    tokens: Vec<OwningToken>,
+    pos: usize,
+    _pd: PhantomData<&'input str>,
+}
+
+impl<'input> ReplayTokenSource<'input> {
+    /// Creates token source that returns `tokens` in order and then EOF forever
+    pub fn new(tokens: Vec<OwningToken>) -> ReplayTokenSource<'input> {
+        ReplayTokenSource {
+            tokens,
+            pos: 0,
+            _pd: PhantomData,
+        }
+    }
+}
+
+impl<'input> TokenSource<'input> for ReplayTokenSource<'input> {
+    type TF = OwningTokenFactory;
+
+    fn next_token(&mut self) -> Box<OwningToken> {
+        let token = match self.tokens.get(self.pos) {
+            Some(token) => token.clone(),
+            None => OwningToken {
+                token_type: EOF,
+                channel: TOKEN_DEFAULT_CHANNEL,
+                start: -1,
+                stop: -1,
+                token_index: AtomicIsize::new(-1),
+                mode: AtomicUsize::new(0),
+                line: 0,
+                column: -1,
+                end_line: 0,
+                end_column: -1,
+                text: "<EOF>".to_owned(),
+                read_only: true,
+            },
+        };
+        self.pos += 1;
+        Box::new(token)
+    }
+
+    fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { None }
+
+    fn get_source_name(&self) -> String { "<bench>".to_owned() }
+
+    fn get_token_factory(&self) -> &'input OwningTokenFactory { &OwningTokenFactory }
+}
+
+/// Node type of trees created by [`build_tree`]
+pub type BenchTree = EmptyContext<'static, OwningTokenFactory>;
+
+/// Shape of the tree created by [`build_tree`]
+#[derive(Clone, Copy, Debug)]
+pub struct TreeShape {
+    /// Max depth of rule nodes
+    pub depth: usize,
+    /// Max number of children of each rule node
+    pub width: usize,
+    /// Percentage of children that are terminal nodes instead of rule nodes
+    pub leaf_percent: u32,
+}
+
+/// Builds parse tree with random shape limited by `shape`.
+///
+/// Every rule node has at least one child, so the tree always reaches `shape.depth` along the first branch.
+pub fn build_tree(seed: u64, shape: TreeShape) -> Rc<BenchTree> {
+    let mut rng = BenchRng::new(seed);
+    let mut token_index = 0;
+    build_subtree(&mut rng, shape, None, shape.depth, &mut token_index)
+}
+
+fn build_subtree(
+    rng: &mut BenchRng, shape: TreeShape, parent: Option<Rc<BenchTree>>, depth: usize,
+    token_index: &mut isize,
+) -> Rc<BenchTree> {
+    let node: Rc<BenchTree> = Rc::new(BaseParserRuleContext::new_parser_ctx(
+        parent,
+        depth as isize,
+        EmptyCustomRuleContext(PhantomData),
+    ));
+    let width = rng.gen_range(1, shape.width.max(1) as isize + 1);
+    for i in 0..width {
+        let is_rule = depth > 0 && (i == 0 || !rng.gen_percent(shape.leaf_percent));
+        let child: Rc<BenchTree> = if is_rule {
+            build_subtree(rng, shape, Some(node.clone()), depth - 1, token_index)
+        } else {
+            let token = OwningToken {
+                token_type: rng.gen_range(1, 20),
+                channel: TOKEN_DEFAULT_CHANNEL,
+                start: *token_index,
+                stop: *token_index,
+                token_index: AtomicIsize::new(*token_index),
+                mode: AtomicUsize::new(0),
+                line: 1,
+                column: *token_index,
+                end_line: 1,
+                end_column: *token_index + 1,
+                text: "x".to_owned(),
+                read_only: true,
+            };
+            *token_index += 1;
+            Rc::new(TerminalNode::new(Box::new(token)))
+        };
+        node.add_child(child);
+    }
+    node
+}
+
+/// Single operation of the `IntervalSet` workload
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[allow(missing_docs)]
+pub enum IntervalSetOp {
+    AddOne(isize),
+    AddRange(isize, isize),
+    RemoveOne(isize),
+    Contains(isize),
+    Complement(isize, isize),
+}
+
+/// Generates `count` operations over values in `0..max_value` range.
+///
+/// Mix is roughly 40% additions, 10% removals, 45% lookups and 5% complements,
+/// which resembles what ATN simulation does with lookahead sets.
+pub fn interval_set_ops(seed: u64, count: usize, max_value: isize) -> Vec<IntervalSetOp> {
+    let mut rng = BenchRng::new(seed);
+    (0..count)
+        .map(|_| {
+            let a = rng.gen_range(0, max_value);
+            match rng.next_u64() % 20 {
+                0..=3 => IntervalSetOp::AddOne(a),
+                4..=7 => IntervalSetOp::AddRange(a, (a + rng.gen_range(0, 16)).min(max_value)),
+                8..=9 => IntervalSetOp::RemoveOne(a),
+                10..=18 => IntervalSetOp::Contains(a),
+                _ => IntervalSetOp::Complement(0, max_value),
+            }
+        })
+        .collect()
+}
+
+/// Applies `ops` to an empty set, returns resulting set and number of successful lookups
+pub fn apply_interval_set_ops(ops: &[IntervalSetOp]) -> (IntervalSet, usize) {
+    let mut set = IntervalSet::new();
+    let mut hits = 0;
+    for op in ops {
+        match *op {
+            IntervalSetOp::AddOne(v) => set.add_one(v),
+            IntervalSetOp::AddRange(l, h) => set.add_range(l, h),
+            IntervalSetOp::RemoveOne(v) => set.remove_one(v),
+            IntervalSetOp::Contains(v) => hits += set.contains(v) as usize,
+            IntervalSetOp::Complement(l, h) => hits += set.complement(l, h).length() as usize,
+        }
+    }
+    (set, hits)
+}
+
+// ATN of the grammar below, serialized by ANTLR 4.8:
+// csvFile: hdr row+ ; hdr : row ; row : field (',' field)* '\r'? '\n' ;
+// field : TEXT | STRING | ;
+const FIXTURE_ATN: &str =
+    "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x03\
+	\x08\x25\x04\x02\x09\x02\x04\x03\x09\x03\x04\x04\x09\x04\x04\x05\x09\x05\
+	\x03\x02\x03\x02\x06\x02\x0d\x0a\x02\x0d\x02\x0e\x02\x0e\x03\x03\x03\x03\
+	\x03\x04\x03\x04\x03\x04\x07\x04\x16\x0a\x04\x0c\x04\x0e\x04\x19\x0b\x04\
+	\x03\x04\x05\x04\x1c\x0a\x04\x03\x04\x03\x04\x03\x05\x03\x05\x03\x05\x05\
+	\x05\x23\x0a\x05\x03\x05\x02\x02\x06\x02\x04\x06\x08\x02\x02\x02\x25\x02\
+	\x0a\x03\x02\x02\x02\x04\x10\x03\x02\x02\x02\x06\x12\x03\x02\x02\x02\x08\
+	\x22\x03\x02\x02\x02\x0a\x0c\x05\x04\x03\x02\x0b\x0d\x05\x06\x04\x02\x0c\
+	\x0b\x03\x02\x02\x02\x0d\x0e\x03\x02\x02\x02\x0e\x0c\x03\x02\x02\x02\x0e\
+	\x0f\x03\x02\x02\x02\x0f\x03\x03\x02\x02\x02\x10\x11\x05\x06\x04\x02\x11\
+	\x05\x03\x02\x02\x02\x12\x17\x05\x08\x05\x02\x13\x14\x07\x03\x02\x02\x14\
+	\x16\x05\x08\x05\x02\x15\x13\x03\x02\x02\x02\x16\x19\x03\x02\x02\x02\x17\
+	\x15\x03\x02\x02\x02\x17\x18\x03\x02\x02\x02\x18\x1b\x03\x02\x02\x02\x19\
+	\x17\x03\x02\x02\x02\x1a\x1c\x07\x04\x02\x02\x1b\x1a\x03\x02\x02\x02\x1b\
+	\x1c\x03\x02\x02\x02\x1c\x1d\x03\x02\x02\x02\x1d\x1e\x07\x05\x02\x02\x1e\
+	\x07\x03\x02\x02\x02\x1f\x23\x07\x07\x02\x02\x20\x23\x07\x08\x02\x02\x21\
+	\x23\x03\x02\x02\x02\x22\x1f\x03\x02\x02\x02\x22\x20\x03\x02\x02\x02\x22\
+	\x21\x03\x02\x02\x02\x23\x09\x03\x02\x02\x02\x06\x0e\x17\x1b\x22";
+
+/// Parser ATN with four decisions (`row+` loop, `(',' field)*` loop, optional `'\r'` and `field` alternatives)
+pub fn atn_fixture() -> Arc<ATN> {
+    Arc::new(ATNDeserializer::new(None).deserialize(FIXTURE_ATN.chars()))
+}
+
+/// Empty DFA for each decision of `atn`, same as generated parsers create them
+pub fn dfa_fixture(atn: &Arc<ATN>) -> Vec<DFA> {
+    (0..atn.decision_to_state.len())
+        .map(|i| DFA::new(atn.clone(), atn.get_decision_state(i), i as isize))
+        .collect()
+}
+
+/// Token types of the fixture grammar
+pub mod fixture_tokens {
+    #![allow(missing_docs)]
+    pub const COMMA: isize = 1;
+    pub const CR: isize = 2;
+    pub const LF: isize = 3;
+    pub const WS: isize = 4;
+    pub const TEXT: isize = 5;
+    pub const STRING: isize = 6;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token::Token;
+
+    fn signature(node: &Rc<BenchTree>, out: &mut Vec<usize>) {
+        out.push(node.get_child_count());
+        node.get_children().for_each(|c| signature(&c, out));
+    }
+
+    #[test]
+    fn test_rng_is_deterministic() {
+        let a: Vec<u64> = (0..10)
+            .scan(BenchRng::new(42), |r, _| Some(r.next_u64()))
+            .collect();
+        let b: Vec<u64> = (0..10)
+            .scan(BenchRng::new(42), |r, _| Some(r.next_u64()))
+            .collect();
+        let c: Vec<u64> = (0..10)
+            .scan(BenchRng::new(43), |r, _| Some(r.next_u64()))
+            .collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_tokens_are_deterministic() {
+        let config = TokenListConfig {
+            count: 500,
+            ..TokenListConfig::default()
+        };
+        let a = generate_tokens(7, &config);
+        let b = generate_tokens(7, &config);
+        assert_eq!(a.len(), 501);
+        assert_eq!(
+            a.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+            b.iter().map(|t| t.to_string()).collect::<Vec<_>>()
+        );
+        assert_eq!(a.last().unwrap().get_token_type(), TOKEN_EOF);
+        assert!(a.iter().any(|t| t.get_channel() == TOKEN_HIDDEN_CHANNEL));
+        assert!(a.iter().any(|t| t.get_channel() == config.extra_channel));
+    }
+
+    #[test]
+    fn test_tree_is_deterministic() {
+        let shape = TreeShape {
+            depth: 6,
+            width: 4,
+            leaf_percent: 50,
+        };
+        let (mut a, mut b) = (vec![], vec![]);
+        signature(&build_tree(3, shape), &mut a);
+        signature(&build_tree(3, shape), &mut b);
+        assert_eq!(a, b);
+        assert!(a.len() > shape.depth);
+    }
+
+    #[test]
+    fn test_interval_ops_are_deterministic() {
+        let ops = interval_set_ops(11, 1000, 500);
+        assert_eq!(ops, interval_set_ops(11, 1000, 500));
+        assert_eq!(apply_interval_set_ops(&ops), apply_interval_set_ops(&ops));
+    }
+
+    #[test]
+    fn test_atn_fixture() {
+        let atn = atn_fixture();
+        assert_eq!(atn.decision_to_state.len(), 4);
+        assert_eq!(dfa_fixture(&atn).len(), 4);
+    }
+}
@@ -0,0 +1,174 @@
+//! Lexer input for binary data
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::ops::Deref;
+
+use better_any::{impl_tid, TidAble};
+
+use crate::char_stream::CharStream;
+use crate::input_stream::InputStream;
+use crate::int_stream::IntStream;
+use crate::interval_set::CharInterval;
+
+/// How `ByteCharStream` converts bytes into token text
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ByteTextFormat {
+    /// Bytes are decoded as UTF-8, invalid sequences are replaced with U+FFFD
+    Lossy,
+    /// Each byte is written as two lowercase hex digits, separated by spaces, e.g. `"0a ff"`
+    Hex,
+}
+
+/// `CharStream` over bytes for grammars that describe binary data.
+///
+/// It is a `ByteStream` (or owned `InputStream<Box<[u8]>>`) with token text produced
+/// according to `ByteTextFormat`, so input is not required to be UTF-8.
+/// `la` returns byte values 0..=255 and indexes are byte offsets,
+/// so lexer rules can match bytes with char ranges like `'\u0080'..'\u00FF'`.
+/// Raw bytes can be obtained by using token factory with `&[u8]` or `Cow<[u8]>`
+/// as its `From` type.
+#[derive(Debug)]
+pub struct ByteCharStream<Data: Deref> {
+    base: InputStream<Data>,
+    text_format: ByteTextFormat,
+}
+
+#[impl_tid]
+impl<'a> TidAble<'a> for ByteCharStream<&'a [u8]> {}
+
+#[impl_tid]
+impl<'a> TidAble<'a> for ByteCharStream<Box<[u8]>> {}
+
+impl<Data: Deref<Target = [u8]>> ByteCharStream<Data> {
+    /// Creates stream over bytes of `input` with `ByteTextFormat::Lossy` token text.
+    /// Source name and position are the ones of `input`.
+    pub fn new(input: InputStream<Data>) -> Self {
+        Self {
+            base: input,
+            text_format: ByteTextFormat::Lossy,
+        }
+    }
+
+    /// Sets how bytes are converted into token text
+    pub fn with_text_format(mut self, text_format: ByteTextFormat) -> Self {
+        self.text_format = text_format;
+        self
+    }
+
+    /// Bytes of `interval`, clamped to the input
+    pub fn get_bytes(&self, interval: CharInterval) -> &[u8] {
+        let data = self.base.data();
+        let len = data.len() as isize;
+        let start = interval.a.max(0).min(len);
+        let stop = (interval.b + 1).max(start).min(len);
+        &data[start as usize..stop as usize]
+    }
+
+    fn format(&self, interval: CharInterval) -> String {
+        let bytes = self.get_bytes(interval);
+        match self.text_format {
+            ByteTextFormat::Lossy => String::from_utf8_lossy(bytes).into_owned(),
+            ByteTextFormat::Hex => {
+                let mut text = String::with_capacity(bytes.len() * 3);
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i > 0 {
+                        text.push(' ');
+                    }
+                    write!(text, "{:02x}", byte).unwrap();
+                }
+                text
+            }
+        }
+    }
+}
+
+impl<Data: Deref<Target = [u8]>> IntStream for ByteCharStream<Data> {
+    #[inline]
+    fn consume(&mut self) { self.base.consume() }
+
+    #[inline]
+    fn la(&mut self, i: isize) -> isize { self.base.la(i) }
+
+    #[inline]
+    fn mark(&mut self) -> isize { self.base.mark() }
+
+    #[inline]
+    fn release(&mut self, marker: isize) { self.base.release(marker) }
+
+    #[inline]
+    fn index(&self) -> isize { self.base.index() }
+
+    #[inline]
+    fn seek(&mut self, index: isize) { self.base.seek(index) }
+
+    #[inline]
+    fn size(&self) -> isize { self.base.size() }
+
+    fn get_source_name(&self) -> String { self.base.get_source_name() }
+}
+
+impl<Data: Deref<Target = [u8]>> CharStream<String> for ByteCharStream<Data> {
+    fn get_text(&self, interval: CharInterval) -> String { self.format(interval) }
+}
+
+impl<'b, Data: Deref<Target = [u8]>> CharStream<Cow<'b, str>> for ByteCharStream<Data> {
+    fn get_text(&self, interval: CharInterval) -> Cow<'b, str> { self.format(interval).into() }
+}
+
+impl<'a> CharStream<&'a [u8]> for ByteCharStream<&'a [u8]> {
+    fn get_text(&self, interval: CharInterval) -> &'a [u8] { self.base.get_text(interval) }
+}
+
+impl<'a> CharStream<Cow<'a, [u8]>> for ByteCharStream<&'a [u8]> {
+    fn get_text(&self, interval: CharInterval) -> Cow<'a, [u8]> { self.base.get_text(interval) }
+}
+
+impl<'a> CharStream<Cow<'a, [u8]>> for ByteCharStream<Box<[u8]>> {
+    fn get_text(&self, interval: CharInterval) -> Cow<'a, [u8]> {
+        Cow::Owned(self.base.get_text(interval))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::char_stream::CharStream;
+    use crate::input_stream::{ByteStream, InputStream};
+    use crate::int_stream::{check_stream_contract, IntStream};
+    use crate::interval_set::CharInterval;
+
+    use super::{ByteCharStream, ByteTextFormat};
+
+    #[test]
+    fn test_contract() {
+        let data = b"\x00a\xff\x80\n";
+        let symbols = data.iter().map(|&it| it as isize).collect::<Vec<_>>();
+        check_stream_contract(&mut ByteCharStream::new(ByteStream::new(data)), &symbols);
+        let owned = InputStream::new_owned(Box::from(&data[..]));
+        check_stream_contract(&mut ByteCharStream::new(owned), &symbols);
+        check_stream_contract(&mut ByteCharStream::new(ByteStream::new(b"")), &[]);
+    }
+
+    #[test]
+    fn test_text() {
+        let data = b"ok\xff\x0a";
+        let mut stream = ByteCharStream::new(ByteStream::new(data));
+        stream.seek(2);
+        assert_eq!(stream.la(1), 0xFF);
+        assert_eq!(stream.la(-1), 'k' as isize);
+        let text: String = stream.get_text(CharInterval::new(0, 3));
+        assert_eq!(text, "ok\u{FFFD}\n");
+        let bytes: &[u8] = stream.get_text(CharInterval::new(2, 10));
+        assert_eq!(bytes, b"\xff\x0a");
+        let bytes: Cow<'_, [u8]> = stream.get_text(CharInterval::new(3, 2));
+        assert!(bytes.is_empty());
+
+        let input = InputStream::new_owned(Box::from(&data[..]));
+        let stream = ByteCharStream::new(input).with_text_format(ByteTextFormat::Hex);
+        let text: Cow<'_, str> = stream.get_text(CharInterval::new(1, 3));
+        assert_eq!(text, "6b ff 0a");
+        let bytes: Cow<'_, [u8]> = stream.get_text(CharInterval::new(0, 0));
+        assert_eq!(&*bytes, b"o");
+    }
+}
@@ -0,0 +1,56 @@
+//! Cooperative cancellation of parsing
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Flag that stops parsing once it is set, possibly from another thread.
+///
+/// Clones share the same flag, so a clone can be given to the parser
+/// with `BaseParser::set_cancellation_token` while the original one is kept to cancel parsing.
+/// ```text
+/// let token = CancellationToken::new();
+/// parser.set_cancellation_token(Some(token.clone()));
+/// // in another thread
+/// token.cancel();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates token that is not cancelled
+    pub fn new() -> Self { Self::default() }
+
+    /// Requests cancellation of all parsers that use this token
+    pub fn cancel(&self) { self.0.store(true, Ordering::Relaxed) }
+
+    /// Whether `cancel` has been called
+    pub fn is_cancelled(&self) -> bool { self.0.load(Ordering::Relaxed) }
+}
+
+/// Number of cancellation checks after which parser reads the clock to test its deadline.
+///
+/// Cancellation token is tested on every check, because it is just an atomic load,
+/// while `Instant::now()` is too costly to be called on every matched token and prediction step.
+pub const DEADLINE_CHECK_INTERVAL: u32 = 64;
+
+/// Reason of `ANTLRError::Cancelled`, returned when parsing has been stopped
+/// by `CancellationToken` or because the deadline set with `BaseParser::set_deadline` has passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseCancelled {
+    /// `CancellationToken::cancel` has been called
+    Cancelled,
+    /// Parsing has not finished before the deadline
+    DeadlineExceeded,
+}
+
+impl Display for ParseCancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseCancelled::Cancelled => f.write_str("parsing has been cancelled"),
+            ParseCancelled::DeadlineExceeded => f.write_str("parsing deadline has been exceeded"),
+        }
+    }
+}
+
+impl Error for ParseCancelled {}
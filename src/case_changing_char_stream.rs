@@ -0,0 +1,142 @@
+//! Case insensitive lexing
+use better_any::{impl_tid, TidAble};
+
+use crate::char_stream::CharStream;
+use crate::int_stream::IntStream;
+use crate::interval_set::CharInterval;
+
+/// `CharStream` decorator that returns upper or lower case chars from `la`
+/// while `get_text` returns original text.
+///
+/// Allows case insensitive keywords, e.g. in SQL, by writing lexer rules in single case only,
+/// like `SELECT: 'SELECT';` for upper case stream, without changing the text of the tokens.
+/// Chars which case mapping is not a single char, like `ß`, are returned as is.
+/// ```
+/// use antlr_rust::case_changing_char_stream::CaseChangingCharStream;
+/// use antlr_rust::int_stream::IntStream;
+/// use antlr_rust::InputStream;
+/// let mut input = CaseChangingCharStream::upper(InputStream::new("select"));
+/// assert_eq!(input.la(1), 'S' as isize);
+/// ```
+#[derive(Debug)]
+pub struct CaseChangingCharStream<S> {
+    stream: S,
+    upper: bool,
+}
+
+#[impl_tid]
+impl<'a, S: TidAble<'a>> TidAble<'a> for CaseChangingCharStream<S> {}
+
+impl<S: IntStream> CaseChangingCharStream<S> {
+    /// Creates stream that returns upper case chars from `la`
+    pub fn upper(stream: S) -> Self {
+        Self {
+            stream,
+            upper: true,
+        }
+    }
+
+    /// Creates stream that returns lower case chars from `la`
+    pub fn lower(stream: S) -> Self {
+        Self {
+            stream,
+            upper: false,
+        }
+    }
+
+    /// Reference to wrapped stream
+    pub fn get_ref(&self) -> &S { &self.stream }
+
+    /// Returns wrapped stream
+    pub fn into_inner(self) -> S { self.stream }
+
+    fn change_case(&self, symbol: isize) -> isize {
+        let ch = match std::char::from_u32(symbol as u32) {
+            Some(ch) if symbol >= 0 => ch,
+            _ => return symbol,
+        };
+        let changed = if self.upper {
+            single_char(ch.to_uppercase())
+        } else {
+            single_char(ch.to_lowercase())
+        };
+        changed.map_or(symbol, |it| it as isize)
+    }
+}
+
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    let first = chars.next()?;
+    match chars.next() {
+        None => Some(first),
+        Some(_) => None,
+    }
+}
+
+impl<S: IntStream> IntStream for CaseChangingCharStream<S> {
+    #[inline]
+    fn consume(&mut self) { self.stream.consume() }
+
+    #[inline]
+    fn la(&mut self, i: isize) -> isize {
+        let symbol = self.stream.la(i);
+        self.change_case(symbol)
+    }
+
+    #[inline]
+    fn mark(&mut self) -> isize { self.stream.mark() }
+
+    #[inline]
+    fn release(&mut self, marker: isize) { self.stream.release(marker) }
+
+    #[inline]
+    fn index(&self) -> isize { self.stream.index() }
+
+    #[inline]
+    fn seek(&mut self, index: isize) { self.stream.seek(index) }
+
+    #[inline]
+    fn size(&self) -> isize { self.stream.size() }
+
+    fn get_source_name(&self) -> String { self.stream.get_source_name() }
+}
+
+impl<T, S: CharStream<T>> CharStream<T> for CaseChangingCharStream<S> {
+    #[inline]
+    fn get_text(&self, interval: CharInterval) -> T { self.stream.get_text(interval) }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::char_stream::CharStream;
+    use crate::int_stream::{check_stream_contract, IntStream, EOF, LA_UNDEFINED};
+    use crate::interval_set::CharInterval;
+    use crate::InputStream;
+
+    use super::CaseChangingCharStream;
+
+    #[test]
+    fn test_case_changing() {
+        let text = "SeLect ß Ω1";
+        let mut symbols = text
+            .chars()
+            .map(|it| it.to_uppercase().next().unwrap() as isize)
+            .collect::<Vec<_>>();
+        // 'ß' is upper cased to "SS" so it is kept as is
+        symbols[7] = 'ß' as isize;
+        let mut stream = CaseChangingCharStream::upper(InputStream::new(text));
+        check_stream_contract(&mut stream, &symbols);
+
+        let mut stream = CaseChangingCharStream::lower(InputStream::new(text));
+        assert_eq!(stream.la(1), 's' as isize);
+        assert_eq!(stream.la(2), 'e' as isize);
+        assert_eq!(stream.la(0), LA_UNDEFINED);
+        assert_eq!(stream.la(-1), EOF);
+        stream.seek(10);
+        assert_eq!(stream.la(1), 'ω' as isize);
+        assert_eq!(stream.la(2), '1' as isize);
+
+        let text: String = stream.get_text(CharInterval::new(0, 5));
+        assert_eq!(text, "SeLect");
+        assert_eq!(stream.into_inner().index(), 10);
+    }
+}
@@ -4,28 +4,25 @@ use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::ops::{Index, Range, RangeFrom};
 
+#[cfg(feature = "mmap")]
+pub use self::mmap::{MmapCharStream, MmapFile};
+#[cfg(feature = "rope")]
+pub use self::rope::RopeCharStream;
 use crate::int_stream::IntStream;
+use crate::interval_set::CharInterval;
 
 /// Provides underlying data for Tokens.
 pub trait CharStream<Data>: IntStream {
-    /// Returns underlying data piece, either slice or owned copy.
+    /// Returns underlying data piece for chars in `interval`, either slice or owned copy.
     /// Panics if provided indexes are invalid
     /// Called by parser only on token intervals.
     /// This fact can be used by custom implementations  
-    fn get_text(&self, a: isize, b: isize) -> Data;
+    fn get_text(&self, interval: CharInterval) -> Data;
 }
 
-/// Trait for input that can be accepted by `InputStream` to be able to provide lexer with data.
+/// Symbols of the input that can be accepted by `InputStream` to implement `IntStream`.
 /// Public for implementation reasons.
-pub trait InputData:
-    Index<Range<usize>, Output = Self>
-    + Index<RangeFrom<usize>, Output = Self>
-    + ToOwned
-    + Debug
-    + 'static
-{
-    // fn to_indexed_vec(&self) -> Vec<(u32, u32)>;
-
+pub trait InputSymbols {
     #[doc(hidden)]
     fn offset(&self, index: isize, item_offset: isize) -> Option<isize>;
 
@@ -34,6 +31,19 @@ pub trait InputData:
 
     #[doc(hidden)]
     fn len(&self) -> usize;
+}
+
+/// Trait for input that can be accepted by `InputStream` to be able to provide lexer with data.
+/// Public for implementation reasons.
+pub trait InputData:
+    InputSymbols
+    + Index<Range<usize>, Output = Self>
+    + Index<RangeFrom<usize>, Output = Self>
+    + ToOwned
+    + Debug
+    + 'static
+{
+    // fn to_indexed_vec(&self) -> Vec<(u32, u32)>;
 
     #[doc(hidden)]
     fn from_text(text: &str) -> Self::Owned;
@@ -42,17 +52,7 @@ pub trait InputData:
     fn to_display(&self) -> String;
 }
 
-impl<T: Into<u32> + From<u8> + TryFrom<u32> + Copy + Debug + 'static> InputData for [T]
-where
-    <T as TryFrom<u32>>::Error: Debug,
-{
-    // fn to_indexed_vec(&self) -> Vec<(u32, u32)> {
-    //     self.into_iter()
-    //         .enumerate()
-    //         .map(|(x, &y)| (x as u32, y.into()))
-    //         .collect()
-    // }
-
+impl<T: Into<u32> + Copy> InputSymbols for [T] {
     #[inline]
     fn offset(&self, index: isize, item_offset: isize) -> Option<isize> {
         let new_index = index + item_offset;
@@ -73,6 +73,18 @@ where
 
     #[inline]
     fn len(&self) -> usize { self.len() }
+}
+
+impl<T: Into<u32> + From<u8> + TryFrom<u32> + Copy + Debug + 'static> InputData for [T]
+where
+    <T as TryFrom<u32>>::Error: Debug,
+{
+    // fn to_indexed_vec(&self) -> Vec<(u32, u32)> {
+    //     self.into_iter()
+    //         .enumerate()
+    //         .map(|(x, &y)| (x as u32, y.into()))
+    //         .collect()
+    // }
 
     #[inline]
     fn from_text(text: &str) -> Self::Owned {
@@ -107,13 +119,7 @@ where
 //     }
 // }
 
-impl InputData for str {
-    // fn to_indexed_vec(&self) -> Vec<(u32, u32)> {
-    //     self.char_indices()
-    //         .map(|(i, ch)| (i as u32, ch as u32))
-    //         .collect()
-    // }
-
+impl InputSymbols for str {
     #[inline]
     fn offset(&self, mut index: isize, mut item_offset: isize) -> Option<isize> {
         if item_offset == 0 {
@@ -144,6 +150,14 @@ impl InputData for str {
 
     #[inline]
     fn len(&self) -> usize { self.len() }
+}
+
+impl InputData for str {
+    // fn to_indexed_vec(&self) -> Vec<(u32, u32)> {
+    //     self.char_indices()
+    //         .map(|(i, ch)| (i as u32, ch as u32))
+    //         .collect()
+    // }
 
     fn from_text(text: &str) -> Self::Owned { text.to_owned() }
 
@@ -153,3 +167,129 @@ impl InputData for str {
     #[inline]
     fn to_display(&self) -> String { self.to_string() }
 }
+
+#[cfg(feature = "mmap")]
+mod mmap {
+    use std::fs::File;
+    use std::io;
+    use std::path::Path;
+
+    use crate::input_stream::InputStream;
+
+    /// `InputStream` over the content of `MmapFile`
+    pub type MmapCharStream<'a> = InputStream<&'a str>;
+
+    /// UTF-8 file mapped into memory, so it can be lexed without reading it into a `String`.
+    ///
+    /// ```no_run
+    /// # use antlr_rust::char_stream::MmapFile;
+    /// let file = unsafe { MmapFile::open("dump.sql")? };
+    /// let input = file.char_stream();
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[derive(Debug)]
+    pub struct MmapFile {
+        map: memmap2::Mmap,
+        name: String,
+    }
+
+    impl MmapFile {
+        /// Maps file at `path` into memory.
+        /// Fails with `io::ErrorKind::InvalidData` if file content is not valid UTF-8.
+        ///
+        /// # Safety
+        /// File must not be modified while it is mapped, see `memmap2::Mmap::map`.
+        pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+            let path = path.as_ref();
+            let map = memmap2::Mmap::map(&File::open(path)?)?;
+            std::str::from_utf8(&map).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(Self {
+                map,
+                name: path.display().to_string(),
+            })
+        }
+
+        /// Content of the file
+        pub fn as_str(&self) -> &str {
+            // validated in `open`
+            unsafe { std::str::from_utf8_unchecked(&self.map) }
+        }
+
+        /// Creates lexer input over the mapped content, named after the file
+        pub fn char_stream(&self) -> MmapCharStream<'_> {
+            InputStream::new(self.as_str()).with_source_name(self.name.clone())
+        }
+    }
+}
+
+#[cfg(feature = "rope")]
+mod rope {
+    use ropey::{Rope, RopeSlice};
+
+    use crate::char_stream::{CharStream, InputSymbols};
+    use crate::input_stream::InputStream;
+    use crate::int_stream::IntStream;
+    use crate::interval_set::CharInterval;
+
+    /// `InputStream` over `ropey::Rope`, e.g. document of a language server.
+    ///
+    /// Indexes are char indexes, same as in `Rope` itself, so token start/stop
+    /// can be used with `Rope` methods directly.
+    /// Only text of the tokens is copied out of the rope, see `get_slice` to avoid even that.
+    /// `Rope` is cheap to clone, so lexing a clone does not block editing of the document.
+    /// ```
+    /// # use antlr_rust::char_stream::RopeCharStream;
+    /// # use antlr_rust::int_stream::IntStream;
+    /// let rope = ropey::Rope::from_str("fn main() {}");
+    /// let snapshot = rope.clone();
+    /// let mut input = RopeCharStream::new(&snapshot).with_source_name("main.rs");
+    /// assert_eq!(input.la(1), 'f' as isize);
+    /// ```
+    pub type RopeCharStream<'a> = InputStream<&'a Rope>;
+
+    impl InputSymbols for Rope {
+        #[inline]
+        fn offset(&self, index: isize, item_offset: isize) -> Option<isize> {
+            let new_index = index + item_offset;
+            if new_index < 0 || new_index > self.len_chars() as isize {
+                return None;
+            }
+            Some(new_index)
+        }
+
+        #[inline]
+        fn item(&self, index: isize) -> Option<isize> {
+            if index < 0 {
+                return None;
+            }
+            self.get_char(index as usize).map(|it| it as isize)
+        }
+
+        #[inline]
+        fn len(&self) -> usize { self.len_chars() }
+    }
+
+    impl<'a> InputStream<&'a Rope> {
+        /// Underlying rope
+        pub fn rope(&self) -> &Rope { self.data() }
+
+        /// Chars of `interval`, clamped to the input, without copying them
+        pub fn get_slice(&self, interval: CharInterval) -> RopeSlice<'_> {
+            let len = self.size();
+            let start = interval.a.max(0).min(len);
+            let stop = (interval.b + 1).max(start).min(len);
+            self.rope().slice(start as usize..stop as usize)
+        }
+    }
+
+    impl<'a, T: From<String>> CharStream<T> for InputStream<&'a Rope> {
+        fn get_text(&self, interval: CharInterval) -> T {
+            let slice = self.get_slice(interval);
+            let mut text = String::with_capacity(slice.len_bytes());
+            for chunk in slice.chunks() {
+                text.push_str(chunk);
+            }
+            text.into()
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Creation of lexer input from files and readers
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::input_stream::{DecodingErrorPolicy, Encoding, InputStream};
+
+/// Constructors of lexer input, same as `CharStreams` in Java runtime.
+///
+/// Input is decoded into `InputStream<Box<str>>` according to its byte order mark:
+/// UTF-8 and UTF-16 byte order marks are detected and stripped, so they do not shift
+/// token positions, and UTF-16 input is transcoded into UTF-8.
+/// Input without byte order mark is considered to be UTF-8.
+/// Detected encoding is available via `InputStream::get_source_encoding`.
+/// ```no_run
+/// use antlr_rust::int_stream::IntStream;
+/// use antlr_rust::CharStreams;
+/// let input = CharStreams::from_path("input.txt").unwrap();
+/// assert_eq!(input.get_source_name(), "input.txt");
+/// ```
+#[derive(Debug)]
+pub struct CharStreams;
+
+impl CharStreams {
+    /// Reads and decodes file at `path`, its path becomes the source name of the stream.
+    ///
+    /// Malformed input results in `io::ErrorKind::InvalidData` error
+    /// with `input_stream::DecodingError` inside.
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<InputStream<Box<str>>> {
+        Self::from_path_with_policy(path, DecodingErrorPolicy::Error)
+    }
+
+    /// Same as `from_path` but handles malformed input according to `policy`
+    pub fn from_path_with_policy(
+        path: impl AsRef<Path>, policy: DecodingErrorPolicy,
+    ) -> io::Result<InputStream<Box<str>>> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        let input = Self::from_bytes(&bytes, policy)?;
+        Ok(input.with_source_name(path.display().to_string()))
+    }
+
+    /// Reads and decodes all input from `reader`
+    pub fn from_reader(
+        mut reader: impl Read, policy: DecodingErrorPolicy,
+    ) -> io::Result<InputStream<Box<str>>> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes, policy)
+    }
+
+    /// Decodes `bytes` in encoding detected by byte order mark
+    pub fn from_bytes(
+        bytes: &[u8], policy: DecodingErrorPolicy,
+    ) -> io::Result<InputStream<Box<str>>> {
+        InputStream::from_bytes_auto(bytes, policy)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Decodes `bytes` in particular `encoding`, skipping its byte order mark if present
+    pub fn from_bytes_with_encoding(
+        bytes: &[u8], encoding: Encoding, policy: DecodingErrorPolicy,
+    ) -> io::Result<InputStream<Box<str>>> {
+        InputStream::from_bytes_with_encoding(bytes, encoding, policy)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io;
+
+    use crate::char_stream::CharStream;
+    use crate::input_stream::{DecodingError, DecodingErrorPolicy, Encoding};
+    use crate::int_stream::IntStream;
+    use crate::interval_set::CharInterval;
+
+    use super::CharStreams;
+
+    #[test]
+    fn test_from_path() {
+        let dir = std::env::temp_dir();
+        let cases: &[(&str, &[u8], Encoding)] = &[
+            ("utf8", b"\xEF\xBB\xBFa\xC3\xA4", Encoding::Utf8),
+            ("utf8_no_bom", b"a\xC3\xA4", Encoding::Utf8),
+            ("utf16le", b"\xFF\xFEa\x00\xE4\x00", Encoding::Utf16Le),
+            ("utf16be", b"\xFE\xFF\x00a\x00\xE4", Encoding::Utf16Be),
+        ];
+        for &(name, bytes, encoding) in cases {
+            let path = dir.join(format!(
+                "antlr_char_streams_{}_{}",
+                std::process::id(),
+                name
+            ));
+            fs::write(&path, bytes).unwrap();
+            let mut input = CharStreams::from_path(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(input.get_source_encoding(), Some(encoding), "{}", name);
+            assert_eq!(input.get_source_name(), path.display().to_string());
+            assert_eq!(input.la(1), 'a' as isize, "{}", name);
+            let text: String = input.get_text(CharInterval::new(0, 1));
+            assert_eq!(text, "aä");
+        }
+
+        let error = CharStreams::from_bytes(b"a\xFF", DecodingErrorPolicy::Error).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = error
+            .into_inner()
+            .unwrap()
+            .downcast::<DecodingError>()
+            .unwrap();
+        assert_eq!(error.byte_offset, 1);
+        let input = CharStreams::from_reader(&b"a\xFF"[..], DecodingErrorPolicy::Replace).unwrap();
+        assert_eq!(input.size(), 4);
+        assert_eq!(input.get_source_encoding(), Some(Encoding::Utf8));
+    }
+}
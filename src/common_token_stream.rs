@@ -1,11 +1,16 @@
 //! Channel based `TokenStream`
 use std::borrow::Borrow;
+use std::ops::DerefMut;
 
+use crate::char_stream::CharStream;
+use crate::errors::ANTLRError;
 use crate::int_stream::{IntStream, IterWrapper, EOF};
+use crate::interval_set::TokenInterval;
+use crate::lexer::{BaseLexer, LexerRecog};
 use crate::token::{Token, TOKEN_DEFAULT_CHANNEL, TOKEN_INVALID_TYPE};
 use crate::token_factory::TokenFactory;
 use crate::token_source::TokenSource;
-use crate::token_stream::{TokenStream, UnbufferedTokenStream};
+use crate::token_stream::{infallible, FetchedTokens, TokenStream, UnbufferedTokenStream};
 use better_any::{Tid, TidAble};
 
 /// Default token stream that skips token that not correspond to current channel.
@@ -18,19 +23,23 @@ pub struct CommonTokenStream<'input, T: TokenSource<'input>> {
 impl<'input, T: TokenSource<'input>> IntStream for CommonTokenStream<'input, T> {
     #[inline]
     fn consume(&mut self) {
+        infallible(self.sync_current(false));
         self.base.consume();
         //        self.base.p = self.next_token_on_channel(self.base.p,self.channel);
         //        self.base.current_token_index = self.base.p;
-        let next = self.next_token_on_channel(self.base.p, self.channel, 1);
+        let next = infallible(self.next_token_on_channel(self.base.p, self.channel, 1, false));
         self.base.seek(next);
         // Ok(())
     }
 
     #[inline]
     fn la(&mut self, i: isize) -> isize {
+        if i == 0 {
+            return TOKEN_INVALID_TYPE;
+        }
         self.lt(i)
             .map(|t| t.borrow().get_token_type())
-            .unwrap_or(TOKEN_INVALID_TYPE)
+            .unwrap_or(EOF)
     }
 
     #[inline(always)]
@@ -42,8 +51,12 @@ impl<'input, T: TokenSource<'input>> IntStream for CommonTokenStream<'input, T>
     #[inline(always)]
     fn index(&self) -> isize { self.base.index() }
 
-    #[inline(always)]
-    fn seek(&mut self, index: isize) { self.base.seek(index); }
+    /// Off-channel token at `index` is skipped, same as by `consume`
+    #[inline]
+    fn seek(&mut self, index: isize) {
+        let index = infallible(self.next_token_on_channel(index.max(0), self.channel, 1, false));
+        self.base.seek(index);
+    }
 
     #[inline(always)]
     fn size(&self) -> isize { self.base.size() }
@@ -57,17 +70,44 @@ impl<'input, T: TokenSource<'input>> TokenStream<'input> for CommonTokenStream<'
     #[inline(always)]
     fn lt(&mut self, k: isize) -> Option<&<Self::TF as TokenFactory<'input>>::Tok> {
         if k == 1 {
+            infallible(self.sync_current(false));
             return self.base.tokens.get(self.base.p as usize);
         }
         if k == 0 {
-            panic!();
+            return None;
         }
         if k < 0 {
             return self.lb(-k);
         }
-        self.lt_inner(k)
+        infallible(self.lt_inner(k, false))
+    }
+
+    fn try_lt(
+        &mut self, k: isize,
+    ) -> Result<Option<&<Self::TF as TokenFactory<'input>>::Tok>, ANTLRError> {
+        if k == 0 {
+            return Ok(None);
+        }
+        if k < 0 {
+            return Ok(self.lb(-k));
+        }
+        self.sync_current(true)?;
+        if k == 1 {
+            return Ok(self.base.tokens.get(self.base.p as usize));
+        }
+        self.lt_inner(k, true)
     }
 
+    fn try_consume(&mut self) -> Result<(), ANTLRError> {
+        self.sync_current(true)?;
+        self.consume();
+        Ok(())
+    }
+
+    fn set_defer_errors(&mut self, defer: bool) { self.base.set_defer_errors(defer); }
+
+    fn defers_errors(&self) -> bool { self.base.defers_errors() }
+
     #[inline]
     fn get(&self, index: isize) -> &<Self::TF as TokenFactory<'input>>::Tok { self.base.get(index) }
 
@@ -75,8 +115,8 @@ impl<'input, T: TokenSource<'input>> TokenStream<'input> for CommonTokenStream<'
         self.base.get_token_source()
     }
 
-    fn get_text_from_interval(&self, start: isize, stop: isize) -> String {
-        self.base.get_text_from_interval(start, stop)
+    fn get_text_from_interval(&self, interval: TokenInterval) -> String {
+        self.base.get_text_from_interval(interval)
     }
 }
 
@@ -92,43 +132,120 @@ impl<'input, T: TokenSource<'input>> CommonTokenStream<'input, T> {
             base: UnbufferedTokenStream::new_buffered(lexer),
             channel,
         };
-        r.sync(0);
+        // failure to fetch the first token is reported by the next call that reads it
+        r.base.defer_errors = true;
+        // leading off-channel tokens are skipped
+        r.seek(0);
+        r.base.defer_errors = false;
         r
     }
 
-    fn lt_inner(&mut self, k: isize) -> Option<&<T::TF as TokenFactory<'input>>::Tok> {
-        let mut i = self.base.p;
-        let mut n = 1; // we know tokens[p] is a good one
-                       // find k good tokens
-        while n < k {
-            // skip off-channel tokens, but make sure to not look past EOF
-            if self.sync(i + 1) {
-                i = self.next_token_on_channel(i + 1, self.channel, 1);
-            }
-            n += 1;
-        }
+    fn lt_inner(
+        &mut self, k: isize, fallible: bool,
+    ) -> Result<Option<&<T::TF as TokenFactory<'input>>::Tok>, ANTLRError> {
+        let channel = self.channel;
+        // we know tokens[p] is a good one, find k good tokens
+        let i = self
+            .base
+            .lookahead(self.base.p, k, fallible, |t| t.get_channel() == channel)?;
         //		if ( i>range ) range = i;
-        return self.base.tokens.get(i as usize);
+        return Ok(match i {
+            Some(i) => self.base.tokens.get(i as usize),
+            None => None,
+        });
     }
 
-    /// Restarts this token stream
-    pub fn reset(&mut self) {
-        self.base.p = 0;
-        self.base.current_token_index = 0;
+    // Current token can be missing if token source failed when stream tried to fetch it,
+    // so it is fetched again, skipping off-channel tokens.
+    fn sync_current(&mut self, fallible: bool) -> Result<(), ANTLRError> {
+        if self.base.p < self.size() {
+            return Ok(());
+        }
+        let next = self.next_token_on_channel(self.base.p, self.channel, 1, fallible)?;
+        self.base.seek(next);
+        Ok(())
+    }
+
+    /// Fetches all remaining tokens from token source, up to and including EOF.
+    ///
+    /// Tokens fetched before token source failure stay in the buffer.
+    pub fn fill(&mut self) -> Result<(), ANTLRError> {
+        while !self.base.is_finished() {
+            self.base.try_fill(1)?;
+        }
+        Ok(())
+    }
+
+    /// Restarts this token stream from the first token on its channel
+    pub fn reset(&mut self) { self.seek(0) }
+
+    /// Returns tokens that are not on the channel of this stream, like whitespace or comments,
+    /// between token at `token_index` and the next token on the channel of this stream.
+    /// If `channel` is `Some`, only tokens on that channel are returned.
+    ///
+    /// Panics if there is no token at `token_index`.
+    pub fn get_hidden_tokens_to_right(
+        &mut self, token_index: isize, channel: Option<isize>,
+    ) -> Vec<&<T::TF as TokenFactory<'input>>::Tok> {
+        self.check_token_index(token_index);
+        let next = infallible(self.next_token_on_channel(token_index + 1, self.channel, 1, false));
+        self.filter_for_channel(token_index + 1, next - 1, channel)
+    }
+
+    /// Returns tokens that are not on the channel of this stream, like whitespace or comments,
+    /// between the previous token on the channel of this stream and token at `token_index`.
+    /// If `channel` is `Some`, only tokens on that channel are returned.
+    ///
+    /// Panics if there is no token at `token_index`.
+    pub fn get_hidden_tokens_to_left(
+        &mut self, token_index: isize, channel: Option<isize>,
+    ) -> Vec<&<T::TF as TokenFactory<'input>>::Tok> {
+        self.check_token_index(token_index);
+        if token_index == 0 {
+            return Vec::new();
+        }
+        let previous =
+            infallible(self.next_token_on_channel(token_index - 1, self.channel, -1, false));
+        self.filter_for_channel(previous + 1, token_index - 1, channel)
+    }
+
+    fn check_token_index(&mut self, token_index: isize) {
+        let exists = token_index >= 0 && infallible(self.base.fetch_to(token_index, false));
+        assert!(exists, "there is no token with index {}", token_index);
     }
 
     /// Creates iterator over this token stream
     pub fn iter(&mut self) -> IterWrapper<'_, Self> { IterWrapper(self) }
 
-    fn sync(&mut self, i: isize) -> bool {
-        let need = i - self.size() + 1;
-        if need > 0 {
-            let fetched = self.base.fill(need);
-            return fetched >= need;
-        }
+    /// All tokens fetched so far on all channels, i.e. all tokens of the input after `fill`,
+    /// token at index `i` has token index `i`
+    pub fn get_tokens(&self) -> &[<T::TF as TokenFactory<'input>>::Tok] { &self.base.tokens }
+
+    /// Tokens with indexes in `from..=to` on all channels, without moving the stream,
+    /// see `UnbufferedTokenStream::peek_range`
+    pub fn peek_range(
+        &mut self, from: isize, to: isize,
+    ) -> &[<T::TF as TokenFactory<'input>>::Tok] {
+        self.base.peek_range(from, to)
+    }
 
-        true
+    // Drops tokens fetched after the last consumed token on this stream channel,
+    // returns that token if there is one.
+    fn discard_lookahead(&mut self) -> Option<<T::TF as TokenFactory<'input>>::Tok> {
+        let channel = self.channel;
+        let base = &mut self.base;
+        let last = (0..base.p.min(base.tokens.len() as isize))
+            .rev()
+            .find(|&i| base.tokens[i as usize].borrow().get_channel() == channel);
+        let keep = last.map_or(0, |i| i + 1);
+        let start = base.get_buffer_start_index();
+        base.tokens.truncate(keep as usize);
+        base.p = keep;
+        base.current_token_index = start + keep;
+        base.error = None;
+        last.map(|i| base.tokens[i as usize].clone())
     }
+
     //
     //    fn fetch(&self, n: isize) -> int { unimplemented!() }
     //
@@ -143,44 +260,34 @@ impl<'input, T: TokenSource<'input>> CommonTokenStream<'input, T> {
     //    fn set_token_source(&self, tokenSource: TokenSource) { unimplemented!() }
 
     //todo make this const generic over direction
-    fn next_token_on_channel(&mut self, mut i: isize, channel: isize, direction: isize) -> isize {
-        self.sync(i);
-        if i >= self.size() {
-            return self.size() - 1;
-        }
-
-        let mut token = self.base.tokens[i as usize].borrow();
-        while token.get_channel() != channel {
-            if token.get_token_type() == EOF || i < 0 {
-                return i;
-            }
-
-            i += direction;
-            self.sync(i);
-            token = self.base.tokens[i as usize].borrow();
-        }
+    fn next_token_on_channel(
+        &mut self, i: isize, channel: isize, direction: isize, fallible: bool,
+    ) -> Result<isize, ANTLRError> {
+        self.base
+            .next_visible(i, direction, fallible, |t| t.get_channel() == channel)
+    }
 
-        return i;
+    // Off-channel tokens in `from..=to`, or only ones on `channel` if it is `Some`
+    fn filter_for_channel(
+        &self, from: isize, to: isize, channel: Option<isize>,
+    ) -> Vec<&<T::TF as TokenFactory<'input>>::Tok> {
+        let to = to.min(self.size() - 1);
+        (from.max(0)..=to)
+            .filter(|&i| {
+                let token_channel = self.base.tokens[i as usize].borrow().get_channel();
+                match channel {
+                    Some(channel) => token_channel == channel,
+                    None => token_channel != self.channel,
+                }
+            })
+            .map(|i| &self.base.tokens[i as usize])
+            .collect()
     }
     //
-    //    fn previous_token_on_channel(&self, i: isize, channel: isize) -> int { unimplemented!() }
-    //
-    //    fn get_hidden_tokens_to_right(&self, tokenIndex: isize, channel: isize) -> Vec<Token> { unimplemented!() }
-    //
-    //    fn get_hidden_tokens_to_left(&self, tokenIndex: isize, channel: isize) -> Vec<Token> { unimplemented!() }
-    //
-    //    fn filter_for_channel(&self, left: isize, right: isize, channel: isize) -> Vec<Token> { unimplemented!() }
-    //
     //    fn get_source_name(&self) -> String { unimplemented!() }
     //
     //    fn get_all_text(&self) -> String { unimplemented!() }
     //
-    //    fn get_text_from_tokens(&self, start: Token, end: Token) -> String { unimplemented!() }
-    //
-    //    fn get_text_from_rule_context(&self, interval: RuleContext) -> String { unimplemented!() }
-    //
-    //    fn get_text_from_interval(&self, interval: &Interval) -> String { unimplemented!() }
-    //
     //    fn fill(&self) { unimplemented!() }
     //
     //    fn adjust_seek_index(&self, i: isize) -> int { unimplemented!() }
@@ -198,7 +305,7 @@ impl<'input, T: TokenSource<'input>> CommonTokenStream<'input, T> {
         // find k good tokens looking backwards
         while n <= k && i > 0 {
             // skip off-channel tokens
-            i = self.next_token_on_channel(i - 1, self.channel, -1);
+            i = infallible(self.next_token_on_channel(i - 1, self.channel, -1, false));
             n += 1;
         }
         if i < 0 {
@@ -210,3 +317,31 @@ impl<'input, T: TokenSource<'input>> CommonTokenStream<'input, T> {
 
     //    fn get_number_of_on_channel_tokens(&self) -> int { unimplemented!() }
 }
+
+impl<'input, T, R, Input, TF> CommonTokenStream<'input, T>
+where
+    T: TokenSource<'input, TF = TF> + DerefMut<Target = BaseLexer<'input, R, Input, TF>>,
+    R: LexerRecog<'input, BaseLexer<'input, R, Input, TF>> + 'static,
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    /// Makes the next token of this stream the next token of one of `start_token_types`
+    /// after the last consumed token, or EOF, with `BaseLexer::scan_for`.
+    ///
+    /// Already fetched lookahead tokens are dropped and lexed again, so input skipped
+    /// while lexing them is not lost. Skipped input ends up in a garbage token that
+    /// is kept in this stream on `TOKEN_GARBAGE_CHANNEL`.
+    /// ```text
+    /// loop {
+    ///     parser.input.scan_for(&[SELECT]);
+    ///     if parser.input.la(1) == TOKEN_EOF { break; }
+    ///     let statement = parser.select_statement()?;
+    /// }
+    /// ```
+    pub fn scan_for(&mut self, start_token_types: &[isize]) {
+        let last = self.discard_lookahead();
+        let lexer = &mut *self.base.token_source;
+        lexer.resume_after(last.as_ref().map(Borrow::borrow));
+        lexer.scan_for(start_token_types);
+    }
+}
@@ -0,0 +1,569 @@
+//! High density parse tree representation for huge inputs.
+//!
+//! Regular parse tree allocates every node separately and every leaf owns its token,
+//! which makes tree the main memory consumer when parsing hundreds of megabytes of input.
+//! `CompactTree` stores the same tree as a struct of arrays indexed by `u32` node ids:
+//! rule nodes keep only rule index and alt number, and leaves keep only index of the token
+//! in the token stream that the tree was parsed from, so token data is shared with that stream.
+//!
+//! ```
+//! use antlr_rust::compact_tree::{CompactNodeKind, CompactTree};
+//! use antlr_rust::tree_builder::TreeBuilder;
+//!
+//! let tree = TreeBuilder::new()
+//!     .rule(0)
+//!     .token(1, "x")
+//!     .rule(1)
+//!     .end()
+//!     .end()
+//!     .finish()
+//!     .unwrap();
+//! let compact = CompactTree::from_parse_tree(&*tree);
+//! let root = compact.root();
+//! assert_eq!(root.rule_index(), Some(0));
+//! let kinds = root.children().map(|it| it.kind()).collect::<Vec<_>>();
+//! assert_eq!(kinds, vec![CompactNodeKind::Token, CompactNodeKind::Rule]);
+//! assert_eq!(root.get_child(0).unwrap().token_index(), Some(0));
+//! ```
+use std::borrow::Borrow;
+use std::mem::size_of;
+
+use better_any::TidExt;
+
+use crate::atn::INVALID_ALT;
+use crate::parser::ParserNodeType;
+use crate::parser_rule_context::ParserRuleContext;
+use crate::token::{OwningToken, Token};
+use crate::token_factory::TokenFactory;
+use crate::token_stream::TokenStream;
+use crate::tree::{leaf_node_text, leaf_text, ErrorNode, LeafNode, TerminalNode};
+use crate::utils;
+
+/// Index of the node in `CompactTree`
+pub type NodeId = u32;
+
+const NO_NODE: NodeId = NodeId::max_value();
+// leaf data with this bit set is an index in `CompactTree::detached`
+const DETACHED: u32 = 1 << 31;
+
+/// Kind of the `CompactTree` node
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum CompactNodeKind {
+    /// Rule node
+    Rule,
+    /// Token matched by parser
+    Token,
+    /// Token created or skipped by error recovery
+    Error,
+}
+
+/// Parse tree stored as a struct of arrays, see module level documentation
+#[derive(Debug, Clone, Default)]
+pub struct CompactTree {
+    kinds: Vec<CompactNodeKind>,
+    /// rule index for rules, token index for leaves
+    data: Vec<u32>,
+    /// alt number for rules, unused for leaves
+    alts: Vec<u32>,
+    parents: Vec<NodeId>,
+    first_children: Vec<NodeId>,
+    next_siblings: Vec<NodeId>,
+    /// tokens that are not a part of the token stream, e.g. conjured by error recovery
+    detached: Vec<OwningToken>,
+}
+
+impl CompactTree {
+    /// Copies parse tree starting at `root`.
+    ///
+    /// Panics if tree has more than `2^31` nodes.
+    pub fn from_parse_tree<'input, T>(root: &T) -> CompactTree
+    where
+        T: ParserRuleContext<'input> + ?Sized,
+    {
+        let mut tree = CompactTree::default();
+        tree.add(root, NO_NODE);
+        tree.kinds.shrink_to_fit();
+        tree.data.shrink_to_fit();
+        tree.alts.shrink_to_fit();
+        tree.parents.shrink_to_fit();
+        tree.first_children.shrink_to_fit();
+        tree.next_siblings.shrink_to_fit();
+        tree
+    }
+
+    fn push(&mut self, kind: CompactNodeKind, data: u32, alt: u32, parent: NodeId) -> NodeId {
+        let id = self.kinds.len();
+        assert!(id < DETACHED as usize, "too many nodes for CompactTree");
+        self.kinds.push(kind);
+        self.data.push(data);
+        self.alts.push(alt);
+        self.parents.push(parent);
+        self.first_children.push(NO_NODE);
+        self.next_siblings.push(NO_NODE);
+        id as NodeId
+    }
+
+    fn add<'input, T>(&mut self, node: &T, parent: NodeId) -> NodeId
+    where
+        T: ParserRuleContext<'input> + ?Sized,
+    {
+        if let Some(leaf) = node.downcast_ref::<TerminalNode<'input, T::Ctx>>() {
+            return self.add_leaf(CompactNodeKind::Token, leaf, parent);
+        }
+        if let Some(leaf) = node.downcast_ref::<ErrorNode<'input, T::Ctx>>() {
+            return self.add_leaf(CompactNodeKind::Error, leaf, parent);
+        }
+        let id = self.push(
+            CompactNodeKind::Rule,
+            node.get_rule_index() as u32,
+            node.get_alt_number() as u32,
+            parent,
+        );
+        let mut prev = NO_NODE;
+        for child in node.get_children() {
+            let child = self.add(&*child, id);
+            if prev == NO_NODE {
+                self.first_children[id as usize] = child;
+            } else {
+                self.next_siblings[prev as usize] = child;
+            }
+            prev = child;
+        }
+        id
+    }
+
+    fn add_leaf<'input, Node: ParserNodeType<'input>, E: 'static>(
+        &mut self, kind: CompactNodeKind, leaf: &LeafNode<'input, Node, E>, parent: NodeId,
+    ) -> NodeId {
+        let token: &<Node::TF as TokenFactory<'input>>::Inner = leaf.symbol.borrow();
+        let data = if token.get_token_index() >= 0 {
+            token.get_token_index() as u32
+        } else {
+            self.detached.push(token.to_owned());
+            (self.detached.len() - 1) as u32 | DETACHED
+        };
+        self.push(kind, data, 0, parent)
+    }
+
+    /// Returns root node
+    pub fn root(&self) -> CompactNode<'_> { self.node(0) }
+
+    /// Returns node with `id`, panics if there is no such node
+    pub fn node(&self, id: NodeId) -> CompactNode<'_> {
+        assert!((id as usize) < self.len(), "node {} is out of range", id);
+        CompactNode { tree: self, id }
+    }
+
+    /// Number of nodes in this tree
+    pub fn len(&self) -> usize { self.kinds.len() }
+
+    /// Returns true if tree has no nodes
+    pub fn is_empty(&self) -> bool { self.kinds.is_empty() }
+
+    /// Approximate number of bytes this tree occupies on the heap
+    pub fn heap_size(&self) -> usize {
+        self.kinds.capacity() * size_of::<CompactNodeKind>()
+            + (self.data.capacity()
+                + self.alts.capacity()
+                + self.parents.capacity()
+                + self.first_children.capacity()
+                + self.next_siblings.capacity())
+                * size_of::<u32>()
+            + self
+                .detached
+                .iter()
+                .map(|it| size_of::<OwningToken>() + it.text.capacity())
+                .sum::<usize>()
+    }
+
+    /// Calls `listener` for every node in this tree in the same order as `ParseTreeWalker`
+    pub fn walk(&self, listener: &mut (impl CompactTreeListener + ?Sized)) {
+        if self.is_empty() {
+            return;
+        }
+        // iterative to not overflow stack on deep trees
+        let mut id = 0;
+        loop {
+            let node = self.node(id);
+            let first_child = self.first_children[id as usize];
+            match node.kind() {
+                CompactNodeKind::Token => listener.visit_terminal(node),
+                CompactNodeKind::Error => listener.visit_error_node(node),
+                CompactNodeKind::Rule => listener.enter_every_rule(node),
+            }
+            if first_child != NO_NODE {
+                id = first_child;
+                continue;
+            }
+            // go up until there is a next sibling, exiting completed rules
+            let mut current = id;
+            loop {
+                if self.kinds[current as usize] == CompactNodeKind::Rule {
+                    listener.exit_every_rule(self.node(current));
+                }
+                if current == 0 {
+                    return;
+                }
+                let next = self.next_siblings[current as usize];
+                if next != NO_NODE {
+                    id = next;
+                    break;
+                }
+                current = self.parents[current as usize];
+            }
+        }
+    }
+
+    /// Print out a whole tree in LISP format like `trees::string_tree`,
+    /// `tokens` must be the stream this tree was parsed from
+    pub fn string_tree<'input, S>(&self, rule_names: &[&str], tokens: &S) -> String
+    where
+        S: TokenStream<'input> + ?Sized,
+    {
+        let mut result = String::new();
+        let mut listener = StringTreeListener {
+            rule_names,
+            tokens,
+            result: &mut result,
+        };
+        self.walk(&mut listener);
+        result
+    }
+}
+
+struct StringTreeListener<'a, S: ?Sized> {
+    rule_names: &'a [&'a str],
+    tokens: &'a S,
+    result: &'a mut String,
+}
+
+impl<'a, S: ?Sized> StringTreeListener<'a, S> {
+    fn push(&mut self, text: &str) {
+        if !self.result.is_empty() && !self.result.ends_with('(') {
+            self.result.push(' ');
+        }
+        self.result
+            .push_str(&utils::escape_whitespaces(text, false));
+    }
+}
+
+impl<'a, 'input, S: TokenStream<'input> + ?Sized> CompactTreeListener
+    for StringTreeListener<'a, S>
+{
+    fn visit_terminal(&mut self, node: CompactNode<'_>) {
+        self.push(&node.get_node_text(self.rule_names, self.tokens))
+    }
+
+    fn visit_error_node(&mut self, node: CompactNode<'_>) { self.visit_terminal(node) }
+
+    fn enter_every_rule(&mut self, node: CompactNode<'_>) {
+        let text = node.get_node_text(self.rule_names, self.tokens);
+        if node.child_count() == 0 {
+            self.push(&text);
+        } else {
+            self.push("(");
+            self.result
+                .push_str(&utils::escape_whitespaces(text, false));
+        }
+    }
+
+    fn exit_every_rule(&mut self, node: CompactNode<'_>) {
+        if node.child_count() != 0 {
+            self.result.push(')');
+        }
+    }
+}
+
+/// Reference to the `CompactTree` node, provides read only API similar to `Tree`
+#[derive(Debug, Clone, Copy)]
+pub struct CompactNode<'a> {
+    tree: &'a CompactTree,
+    id: NodeId,
+}
+
+impl<'a> CompactNode<'a> {
+    /// Id of this node in the tree
+    pub fn id(&self) -> NodeId { self.id }
+
+    /// Kind of this node
+    pub fn kind(&self) -> CompactNodeKind { self.tree.kinds[self.id as usize] }
+
+    fn link(&self, id: NodeId) -> Option<CompactNode<'a>> {
+        if id == NO_NODE {
+            None
+        } else {
+            Some(self.tree.node(id))
+        }
+    }
+
+    /// Parent node, `None` for root
+    pub fn get_parent(&self) -> Option<CompactNode<'a>> {
+        self.link(self.tree.parents[self.id as usize])
+    }
+
+    /// Iterates over children of this node
+    pub fn children(&self) -> CompactChildren<'a> {
+        CompactChildren {
+            tree: self.tree,
+            next: self.tree.first_children[self.id as usize],
+        }
+    }
+
+    /// Returns `i`-th child, takes `O(i)` time
+    pub fn get_child(&self, i: usize) -> Option<CompactNode<'a>> { self.children().nth(i) }
+
+    /// Number of children, takes `O(n)` time
+    pub fn child_count(&self) -> usize { self.children().count() }
+
+    /// Rule index of the rule node, `None` for leaves
+    pub fn rule_index(&self) -> Option<usize> {
+        match self.kind() {
+            CompactNodeKind::Rule => Some(self.tree.data[self.id as usize] as usize),
+            _ => None,
+        }
+    }
+
+    /// Alt number of the rule node, `INVALID_ALT` for leaves
+    pub fn alt_number(&self) -> isize {
+        match self.kind() {
+            CompactNodeKind::Rule => self.tree.alts[self.id as usize] as i32 as isize,
+            _ => INVALID_ALT,
+        }
+    }
+
+    /// Index of the leaf token in the token stream.
+    ///
+    /// `None` for rules and for tokens that are not a part of the token stream,
+    /// those are available with `detached_token`
+    pub fn token_index(&self) -> Option<isize> {
+        match self.kind() {
+            CompactNodeKind::Rule => None,
+            _ => {
+                let data = self.tree.data[self.id as usize];
+                if data & DETACHED == 0 {
+                    Some(data as isize)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Token of the leaf that is not a part of the token stream, e.g. conjured by error recovery
+    pub fn detached_token(&self) -> Option<&'a OwningToken> {
+        match (self.kind(), self.token_index()) {
+            (CompactNodeKind::Rule, _) | (_, Some(_)) => None,
+            (_, None) => {
+                let index = self.tree.data[self.id as usize] & !DETACHED;
+                Some(&self.tree.detached[index as usize])
+            }
+        }
+    }
+
+    /// Same as `Tree::get_node_text`, `tokens` must be the stream tree was parsed from
+    pub fn get_node_text<'input, S>(&self, rule_names: &[&str], tokens: &S) -> String
+    where
+        S: TokenStream<'input> + ?Sized,
+    {
+        match self.rule_index() {
+            Some(rule_index) => {
+                let rule_name = rule_names
+                    .get(rule_index)
+                    .map(|&it| it.to_owned())
+                    .unwrap_or_else(|| rule_index.to_string());
+                let alt_number = self.alt_number();
+                if alt_number != INVALID_ALT {
+                    return format!("{}:{}", rule_name, alt_number);
+                }
+                rule_name
+            }
+            None => match self.detached_token() {
+                Some(token) => leaf_node_text(token),
+                None => leaf_node_text(tokens.get(self.token_index().unwrap()).borrow()),
+            },
+        }
+    }
+
+    /// Same as `ParseTree::get_text`, `tokens` must be the stream tree was parsed from
+    pub fn get_text<'input, S>(&self, tokens: &S) -> String
+    where
+        S: TokenStream<'input> + ?Sized,
+    {
+        if let Some(token) = self.detached_token() {
+            return leaf_text(token);
+        }
+        match self.token_index() {
+            Some(index) => leaf_text(tokens.get(index).borrow()),
+            None => self.children().map(|it| it.get_text(tokens)).collect(),
+        }
+    }
+}
+
+/// Iterator over children of `CompactNode`
+#[derive(Debug, Clone)]
+pub struct CompactChildren<'a> {
+    tree: &'a CompactTree,
+    next: NodeId,
+}
+
+impl<'a> Iterator for CompactChildren<'a> {
+    type Item = CompactNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == NO_NODE {
+            return None;
+        }
+        let node = self.tree.node(self.next);
+        self.next = self.tree.next_siblings[self.next as usize];
+        Some(node)
+    }
+}
+
+/// Listener for `CompactTree::walk`, counterpart of `ParseTreeListener`
+pub trait CompactTreeListener {
+    /// Called for every terminal node
+    fn visit_terminal(&mut self, _node: CompactNode<'_>) {}
+    /// Called for every error node
+    fn visit_error_node(&mut self, _node: CompactNode<'_>) {}
+    /// Called when walker enters rule node
+    fn enter_every_rule(&mut self, _node: CompactNode<'_>) {}
+    /// Called when walker exits rule node
+    fn exit_every_rule(&mut self, _node: CompactNode<'_>) {}
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::int_stream::EOF;
+    use crate::tree_builder::{BuiltTree, TreeBuilder};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl CompactTreeListener for Events {
+        fn visit_terminal(&mut self, node: CompactNode<'_>) {
+            self.0
+                .push(format!("token {}", node.token_index().unwrap()))
+        }
+
+        fn visit_error_node(&mut self, node: CompactNode<'_>) {
+            self.0.push(format!("error {:?}", node.token_index()))
+        }
+
+        fn enter_every_rule(&mut self, node: CompactNode<'_>) {
+            self.0.push(format!("enter {}", node.rule_index().unwrap()))
+        }
+
+        fn exit_every_rule(&mut self, node: CompactNode<'_>) {
+            self.0.push(format!("exit {}", node.rule_index().unwrap()))
+        }
+    }
+
+    fn sample() -> Rc<BuiltTree<'static>> {
+        TreeBuilder::new()
+            .rule(0)
+            .rule(1)
+            .token(1, "a")
+            .rule(2)
+            .end()
+            .end()
+            .error_token(2, ";")
+            .token(EOF, "<EOF>")
+            .end()
+            .finish()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_structure() {
+        let tree = CompactTree::from_parse_tree(&*sample());
+        assert_eq!(tree.len(), 6);
+        let root = tree.root();
+        assert_eq!(root.rule_index(), Some(0));
+        assert_eq!(root.alt_number(), INVALID_ALT);
+        assert!(root.get_parent().is_none());
+        assert_eq!(root.child_count(), 3);
+
+        let inner = root.get_child(0).unwrap();
+        assert_eq!(inner.rule_index(), Some(1));
+        assert_eq!(inner.get_parent().unwrap().id(), root.id());
+        let empty = inner.get_child(1).unwrap();
+        assert_eq!(empty.rule_index(), Some(2));
+        assert_eq!(empty.child_count(), 0);
+
+        let error = root.get_child(1).unwrap();
+        assert_eq!(error.kind(), CompactNodeKind::Error);
+        assert_eq!(error.token_index(), Some(1));
+        assert!(error.detached_token().is_none());
+        assert_eq!(root.get_child(2).unwrap().token_index(), Some(2));
+
+        let mut events = Events::default();
+        tree.walk(&mut events);
+        assert_eq!(
+            events.0,
+            vec![
+                "enter 0",
+                "enter 1",
+                "token 0",
+                "enter 2",
+                "exit 2",
+                "exit 1",
+                "error Some(1)",
+                "token 2",
+                "exit 0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_density() {
+        let tree = CompactTree::from_parse_tree(&*sample());
+        let per_node = tree.heap_size() / tree.len();
+        assert!(per_node <= 24, "{} bytes per node", per_node);
+    }
+
+    // builds 1M nodes, run with `--features heavy_tests`
+    #[cfg(feature = "heavy_tests")]
+    #[test]
+    fn test_million_nodes_memory() {
+        use crate::parser_rule_context::BaseParserRuleContext;
+        use crate::rule_context::EmptyContextType;
+        use crate::token::CommonToken;
+        use crate::token_factory::CommonTokenFactory;
+        use crate::tree_builder::BuiltRuleContext;
+
+        const ROWS: usize = 250_000;
+        let mut builder = TreeBuilder::new().rule(0);
+        for _ in 0..ROWS {
+            builder = builder
+                .rule(1)
+                .token(1, "x")
+                .token(2, ",")
+                .token(1, "y")
+                .end();
+        }
+        let root = builder.end().finish().unwrap();
+        let tree = CompactTree::from_parse_tree(&*root);
+        assert_eq!(tree.len(), ROWS * 4 + 1);
+
+        // lower bound of the regular tree: node allocations only,
+        // without allocator overhead, children vectors and token texts
+        let rc_overhead = 2 * size_of::<usize>();
+        let rule_size = size_of::<BaseParserRuleContext<'static, BuiltRuleContext<'static>>>();
+        type Leaf = TerminalNode<'static, EmptyContextType<'static, CommonTokenFactory>>;
+        let leaf_size = size_of::<Leaf>() + size_of::<CommonToken<'static>>();
+        let boxed = ROWS * (rule_size + rc_overhead + 3 * (leaf_size + rc_overhead));
+        let boxed_per_node = boxed / tree.len();
+        let compact_per_node = tree.heap_size() / tree.len();
+        assert!(
+            compact_per_node * 4 <= boxed_per_node,
+            "compact: {} bytes per node, boxed: {} bytes per node",
+            compact_per_node,
+            boxed_per_node
+        );
+    }
+}
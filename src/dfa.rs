@@ -93,9 +93,11 @@ impl DFA {
     }
 
     pub fn get_precedence_start_state(&self, _precedence: isize) -> Option<DFAStateRef> {
-        if !self.is_precedence_dfa {
-            panic!("dfa is supposed to be precedence here");
-        }
+        runtime_assert!(
+            self.is_precedence_dfa,
+            { decision: self.decision, precedence: _precedence },
+            "dfa is supposed to be precedence here"
+        );
 
         self.s0.and_then(|s0| {
             self.states[s0]
@@ -109,9 +111,11 @@ impl DFA {
     }
 
     pub fn set_precedence_start_state(&mut self, precedence: isize, _start_state: DFAStateRef) {
-        if !self.is_precedence_dfa {
-            panic!("set_precedence_start_state called for not precedence dfa")
-        }
+        runtime_assert!(
+            self.is_precedence_dfa,
+            { decision: self.decision, precedence: precedence },
+            "set_precedence_start_state called for not precedence dfa"
+        );
 
         if precedence < 0 {
             return;
@@ -128,6 +132,12 @@ impl DFA {
         }
     }
 
+    /// Number of the decision this DFA predicts alternatives for
+    pub fn decision(&self) -> isize { self.decision }
+
+    /// ATN decision state of the decision this DFA predicts alternatives for
+    pub fn atn_start_state(&self) -> ATNStateRef { self.atn_start_state }
+
     pub fn is_precedence_dfa(&self) -> bool { self.is_precedence_dfa }
 
     pub fn set_precedence_dfa(&mut self, precedence_dfa: bool) {
@@ -0,0 +1,581 @@
+//! Saving DFA to disk, so that the next run does not have to build it again
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use bit_set::BitSet;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use murmur3::murmur3_32::MurmurHasher;
+
+use crate::atn::ATN;
+use crate::atn_config::{ATNConfig, ATNConfigType};
+use crate::atn_config_set::ATNConfigSet;
+use crate::atn_simulator::IATNSimulator;
+use crate::atn_type::ATNType;
+use crate::dfa::DFA;
+use crate::dfa_state::{DFAState, DFAStateRef, PredPrediction};
+use crate::lexer_action::*;
+use crate::lexer_action_executor::LexerActionExecutor;
+use crate::prediction_context::{PredictionContext, EMPTY_PREDICTION_CONTEXT};
+use crate::semantic_context::SemanticContext;
+
+const MAGIC: &[u8; 8] = b"ANTLRDFA";
+const FORMAT_VERSION: i64 = 1;
+const LEXER_ACTION_TYPE_INDEXED_CUSTOM: isize = 8;
+
+/// DFA of a grammar in a form that can be saved to disk and restored by the next run.
+///
+/// Programs like command line tools parse with the same grammar on every invocation,
+/// and without the DFA built by previous runs each of them pays for ATN simulation anew.
+/// Cache is bound to the ATN it was captured from, restoring it for other ATN,
+/// e.g. after the grammar was changed, fails with `io::ErrorKind::InvalidData`
+/// and leaves DFA intact, so the caller can just continue with a cold DFA.
+///
+/// Lexer and parser have separate DFAs, so they need separate caches.
+/// ```text
+/// if let Ok(cache) = DfaCache::load(&path) {
+///     let _ = cache.restore(parser.get_interpreter());
+/// }
+/// let tree = parser.compilation_unit()?;
+/// DfaCache::capture(parser.get_interpreter()).save(&path)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct DfaCache {
+    data: Vec<u8>,
+}
+
+impl DfaCache {
+    /// Captures current DFA of `simulator`
+    pub fn capture(simulator: &dyn IATNSimulator) -> DfaCache {
+        // DFA is locked for the whole capture, so that contexts can be identified by address
+        let dfa = simulator.decision_to_dfa();
+        let dfa = dfa.iter().map(|dfa| dfa.read()).collect::<Vec<_>>();
+        let mut writer = Writer {
+            data: MAGIC.to_vec(),
+            contexts: HashMap::new(),
+        };
+        writer.value(FORMAT_VERSION);
+        writer.value(atn_hash(simulator.atn()) as i64);
+        writer.value(dfa.len() as i64);
+        for dfa in dfa.iter() {
+            writer.dfa(dfa);
+        }
+        DfaCache { data: writer.data }
+    }
+
+    /// Replaces DFA of `simulator` with the cached one.
+    ///
+    /// DFA is shared by all recognizers of the same grammar,
+    /// so it should be restored before any of them starts.
+    pub fn restore(&self, simulator: &dyn IATNSimulator) -> io::Result<()> {
+        let atn = simulator.atn();
+        let mut reader = Reader {
+            data: &self.data[MAGIC.len()..],
+            atn,
+            contexts: Vec::new(),
+        };
+        reader.value()?;
+        if reader.value()? != atn_hash(atn) as i64 {
+            return Err(invalid("cache was captured for different ATN"));
+        }
+        let dfa = simulator.decision_to_dfa();
+        if reader.len()? != dfa.len() {
+            return Err(invalid("cache was captured for different ATN"));
+        }
+        let restored = dfa
+            .iter()
+            .map(|dfa| reader.dfa(&dfa.read()))
+            .collect::<io::Result<Vec<_>>>()?;
+        if !reader.data.is_empty() {
+            return Err(invalid("unexpected data at the end"));
+        }
+
+        for (dfa, restored) in dfa.iter().zip(restored) {
+            let mut dfa = dfa.write();
+            dfa.states = restored.states;
+            dfa.states_map = restored.states_map;
+            dfa.s0 = restored.s0;
+            dfa.set_precedence_dfa(restored.is_precedence_dfa);
+        }
+        Ok(())
+    }
+
+    /// Loads cache saved by `save`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<DfaCache> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    /// Saves cache to the file at `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> { fs::write(path, &self.data) }
+
+    /// Creates cache from the data returned by `as_bytes`
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<DfaCache> {
+        if !data.starts_with(MAGIC) {
+            return Err(invalid("not a DFA cache"));
+        }
+        let mut version = &data[MAGIC.len()..];
+        if version.read_i64::<LittleEndian>().ok() != Some(FORMAT_VERSION) {
+            return Err(invalid("unsupported format version"));
+        }
+        Ok(DfaCache { data })
+    }
+
+    /// Serialized cache data
+    pub fn as_bytes(&self) -> &[u8] { &self.data }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid DFA cache: {}", message),
+    )
+}
+
+// structural hash of ATN to detect caches captured for other grammars or their versions
+fn atn_hash(atn: &ATN) -> u64 {
+    let mut hasher = MurmurHasher::default();
+    (atn.grammar_type == ATNType::LEXER).hash(&mut hasher);
+    atn.max_token_type.hash(&mut hasher);
+    atn.states.len().hash(&mut hasher);
+    for state in &atn.states {
+        state.get_state_type_id().hash(&mut hasher);
+        state.get_rule_index().hash(&mut hasher);
+        for transition in state.get_transitions() {
+            transition.get_target().hash(&mut hasher);
+            (transition.get_serialization_type() as isize).hash(&mut hasher);
+            if let Some(label) = transition.get_label() {
+                label.to_index_string().hash(&mut hasher);
+            }
+            transition.get_predicate().hash(&mut hasher);
+        }
+    }
+    atn.decision_to_state.hash(&mut hasher);
+    atn.rule_to_start_state.hash(&mut hasher);
+    atn.mode_to_start_state.hash(&mut hasher);
+    hasher.finish()
+}
+
+struct Writer {
+    data: Vec<u8>,
+    // ids of prediction contexts that were already written
+    contexts: HashMap<*const PredictionContext, i64>,
+}
+
+impl Writer {
+    fn value(&mut self, value: i64) { self.data.write_i64::<LittleEndian>(value).unwrap() }
+
+    fn flag(&mut self, flag: bool) { self.value(flag as i64) }
+
+    // `usize::MAX` becomes -1
+    fn refs(&mut self, refs: impl ExactSizeIterator<Item = usize>) {
+        self.value(refs.len() as i64);
+        refs.for_each(|it| self.value(it as i64));
+    }
+
+    fn dfa(&mut self, dfa: &DFA) {
+        self.value(dfa.decision as i64);
+        self.value(dfa.atn_start_state as i64);
+        self.flag(dfa.is_precedence_dfa());
+        self.value(dfa.s0.map_or(-1, |it| it as i64));
+        self.value(dfa.states.len() as i64);
+        for state in &dfa.states {
+            self.state(state);
+        }
+        self.value(dfa.states_map.len() as i64);
+        for (&hash, states) in &dfa.states_map {
+            self.value(hash as i64);
+            self.refs(states.iter().copied());
+        }
+    }
+
+    fn state(&mut self, state: &DFAState) {
+        self.value(state.state_number as i64);
+        self.refs(state.edges.iter().copied());
+        self.flag(state.is_accept_state);
+        self.value(state.prediction as i64);
+        self.executor(state.lexer_action_executor.as_deref());
+        self.flag(state.requires_full_context);
+        self.value(state.predicates.len() as i64);
+        for predicate in &state.predicates {
+            self.value(predicate.alt as i64);
+            self.semantic(&predicate.pred);
+        }
+        self.configs(&state.configs);
+    }
+
+    fn configs(&mut self, configs: &ATNConfigSet) {
+        self.flag(configs.full_context());
+        self.value(configs.get_unique_alt() as i64);
+        self.flag(configs.has_semantic_context());
+        self.flag(configs.get_dips_into_outer_context());
+        self.flag(configs.read_only());
+        let conflicting_alts = configs.conflicting_alts.iter().collect::<Vec<_>>();
+        self.refs(conflicting_alts.into_iter());
+        self.value(configs.length() as i64);
+        for config in configs.get_items() {
+            self.config(config);
+        }
+    }
+
+    fn config(&mut self, config: &ATNConfig) {
+        self.value(config.get_state() as i64);
+        self.value(config.get_alt() as i64);
+        self.context(config.get_context());
+        self.semantic(&config.semantic_context);
+        self.value(config.get_reaches_into_outer_context() as i64);
+        self.flag(config.is_precedence_filter_suppressed());
+        match &config.config_type {
+            ATNConfigType::BaseATNConfig => self.value(0),
+            ATNConfigType::LexerATNConfig {
+                lexer_action_executor,
+                passed_through_non_greedy_decision,
+            } => {
+                self.value(1);
+                self.flag(*passed_through_non_greedy_decision);
+                self.executor(lexer_action_executor.as_deref());
+            }
+        }
+    }
+
+    // -2 for no context, id of already written context,
+    // or -1 followed by the context itself, its parents are written before it gets an id
+    fn context(&mut self, context: Option<&Arc<PredictionContext>>) {
+        let context = match context {
+            None => return self.value(-2),
+            Some(context) => context.as_ref(),
+        };
+        if let Some(&id) = self.contexts.get(&(context as *const _)) {
+            return self.value(id);
+        }
+        self.value(-1);
+        self.flag(matches!(context, PredictionContext::Array(_)));
+        self.value(context.length() as i64);
+        for i in 0..context.length() {
+            self.context(context.get_parent(i));
+            self.value(context.get_return_state(i) as i64);
+        }
+        let id = self.contexts.len() as i64;
+        self.contexts.insert(context as *const _, id);
+    }
+
+    fn semantic(&mut self, semantic: &SemanticContext) {
+        match semantic {
+            SemanticContext::Predicate {
+                rule_index,
+                pred_index,
+                is_ctx_dependent,
+            } => {
+                self.value(0);
+                self.value(*rule_index as i64);
+                self.value(*pred_index as i64);
+                self.flag(*is_ctx_dependent);
+            }
+            SemanticContext::Precedence(precedence) => {
+                self.value(1);
+                self.value(*precedence as i64);
+            }
+            SemanticContext::AND(operands) => {
+                self.value(2);
+                self.value(operands.len() as i64);
+                operands.iter().for_each(|it| self.semantic(it));
+            }
+            SemanticContext::OR(operands) => {
+                self.value(3);
+                self.value(operands.len() as i64);
+                operands.iter().for_each(|it| self.semantic(it));
+            }
+        }
+    }
+
+    fn executor(&mut self, executor: Option<&LexerActionExecutor>) {
+        match executor {
+            None => self.value(-1),
+            Some(executor) => {
+                let actions = executor.get_lexer_actions();
+                self.value(actions.len() as i64);
+                actions.iter().for_each(|it| self.action(it));
+            }
+        }
+    }
+
+    fn action(&mut self, action: &LexerAction) {
+        let (action_type, args) = match *action {
+            LexerAction::LexerChannelAction(channel) => (LEXER_ACTION_TYPE_CHANNEL, [channel, 0]),
+            LexerAction::LexerCustomAction {
+                rule_index,
+                action_index,
+            } => (LEXER_ACTION_TYPE_CUSTOM, [rule_index, action_index]),
+            LexerAction::LexerModeAction(mode) => (LEXER_ACTION_TYPE_MODE, [mode, 0]),
+            LexerAction::LexerMoreAction => (LEXER_ACTION_TYPE_MORE, [0, 0]),
+            LexerAction::LexerPopModeAction => (LEXER_ACTION_TYPE_POP_MODE, [0, 0]),
+            LexerAction::LexerPushModeAction(mode) => (LEXER_ACTION_TYPE_PUSH_MODE, [mode, 0]),
+            LexerAction::LexerSkipAction => (LEXER_ACTION_TYPE_SKIP, [0, 0]),
+            LexerAction::LexerTypeAction(token_type) => (LEXER_ACTION_TYPE_TYPE, [token_type, 0]),
+            LexerAction::LexerIndexedCustomAction { offset, ref action } => {
+                self.value(LEXER_ACTION_TYPE_INDEXED_CUSTOM as i64);
+                self.value(offset as i64);
+                return self.action(action);
+            }
+        };
+        self.value(action_type as i64);
+        args.iter().for_each(|&it| self.value(it as i64));
+    }
+}
+
+struct RestoredDFA {
+    states: Vec<DFAState>,
+    states_map: HashMap<u64, Vec<DFAStateRef>>,
+    s0: Option<DFAStateRef>,
+    is_precedence_dfa: bool,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    atn: &'a ATN,
+    contexts: Vec<Arc<PredictionContext>>,
+}
+
+impl Reader<'_> {
+    fn value(&mut self) -> io::Result<i64> {
+        self.data
+            .read_i64::<LittleEndian>()
+            .map_err(|_| invalid("unexpected end of data"))
+    }
+
+    fn flag(&mut self) -> io::Result<bool> { Ok(self.value()? != 0) }
+
+    // length of a sequence, each element of which takes at least one value
+    fn len(&mut self) -> io::Result<usize> {
+        let len = self.value()?;
+        if len < 0 || len as u64 > self.data.len() as u64 / 8 {
+            return Err(invalid("invalid length"));
+        }
+        Ok(len as usize)
+    }
+
+    fn index(&mut self, bound: usize) -> io::Result<usize> {
+        match self.value()? {
+            index if index >= 0 && (index as u64) < bound as u64 => Ok(index as usize),
+            _ => Err(invalid("index out of bounds")),
+        }
+    }
+
+    // same as `index` but also allows -1 that stands for `usize::MAX`
+    fn refs(&mut self, bound: usize) -> io::Result<Vec<usize>> {
+        (0..self.len()?)
+            .map(|_| match self.value()? {
+                -1 => Ok(usize::MAX),
+                index if index >= 0 && (index as u64) < bound as u64 => Ok(index as usize),
+                _ => Err(invalid("index out of bounds")),
+            })
+            .collect()
+    }
+
+    fn dfa(&mut self, expected: &DFA) -> io::Result<RestoredDFA> {
+        let decision = self.value()?;
+        let atn_start_state = self.value()?;
+        if decision != expected.decision as i64
+            || atn_start_state != expected.atn_start_state as i64
+        {
+            return Err(invalid("cache was captured for different ATN"));
+        }
+        let is_precedence_dfa = self.flag()?;
+        let s0 = self.value()?;
+        let count = self.len()?;
+        let s0 = match s0 {
+            -1 => None,
+            s0 if s0 >= 0 && (s0 as u64) < count as u64 => Some(s0 as usize),
+            _ => return Err(invalid("index out of bounds")),
+        };
+        let states = (0..count)
+            .map(|_| self.state(count))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut states_map = HashMap::new();
+        for _ in 0..self.len()? {
+            let hash = self.value()? as u64;
+            states_map.insert(hash, self.refs(count)?);
+        }
+        Ok(RestoredDFA {
+            states,
+            states_map,
+            s0,
+            is_precedence_dfa,
+        })
+    }
+
+    fn state(&mut self, count: usize) -> io::Result<DFAState> {
+        let state_number = self.value()? as usize;
+        let edges = self.refs(count)?;
+        let is_accept_state = self.flag()?;
+        let prediction = self.value()? as isize;
+        let lexer_action_executor = self.executor()?.map(Box::new);
+        let requires_full_context = self.flag()?;
+        let predicates = (0..self.len()?)
+            .map(|_| {
+                Ok(PredPrediction {
+                    alt: self.value()? as isize,
+                    pred: self.semantic()?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let configs = Box::new(self.configs()?);
+
+        let mut state = DFAState::new_dfastate(state_number, configs);
+        state.edges = edges;
+        state.is_accept_state = is_accept_state;
+        state.prediction = prediction;
+        state.lexer_action_executor = lexer_action_executor;
+        state.requires_full_context = requires_full_context;
+        state.predicates = predicates;
+        Ok(state)
+    }
+
+    fn configs(&mut self) -> io::Result<ATNConfigSet> {
+        let full_ctx = self.flag()?;
+        let unique_alt = self.value()? as isize;
+        let has_semantic_context = self.flag()?;
+        let dips_into_outer_context = self.flag()?;
+        let read_only = self.flag()?;
+        // alternatives are numbered by transitions of decision states
+        let alts_bound = self.atn.states.len() + 1;
+        let conflicting_alts = self.refs(alts_bound)?;
+
+        let mut configs = if self.atn.grammar_type == ATNType::LEXER {
+            ATNConfigSet::new_ordered()
+        } else {
+            ATNConfigSet::new_base_atnconfig_set(full_ctx)
+        };
+        for _ in 0..self.len()? {
+            configs.add(Box::new(self.config()?));
+        }
+        configs.set_unique_alt(unique_alt);
+        configs.conflicting_alts = conflicting_alts
+            .into_iter()
+            .filter(|&it| it != usize::MAX)
+            .collect::<BitSet>();
+        configs.set_has_semantic_context(has_semantic_context);
+        configs.set_dips_into_outer_context(dips_into_outer_context);
+        configs.set_read_only(read_only);
+        Ok(configs)
+    }
+
+    fn config(&mut self) -> io::Result<ATNConfig> {
+        let state = self.index(self.atn.states.len())?;
+        let alt = self.value()? as isize;
+        let context = self.context()?;
+        let semantic_context = Box::new(self.semantic()?);
+        let mut config = ATNConfig::new_with_semantic(state, alt, context, semantic_context);
+        config.set_reaches_into_outer_context(self.value()? as isize);
+        config.set_precedence_filter_suppressed(self.flag()?);
+        match self.value()? {
+            0 => {}
+            1 => {
+                let passed_through_non_greedy_decision = self.flag()?;
+                config.config_type = ATNConfigType::LexerATNConfig {
+                    lexer_action_executor: self.executor()?.map(Box::new),
+                    passed_through_non_greedy_decision,
+                };
+            }
+            _ => return Err(invalid("unknown config type")),
+        }
+        Ok(config)
+    }
+
+    fn context(&mut self) -> io::Result<Option<Arc<PredictionContext>>> {
+        match self.value()? {
+            -2 => Ok(None),
+            -1 => {
+                let is_array = self.flag()?;
+                let length = self.len()?;
+                let mut parents = Vec::with_capacity(length);
+                let mut return_states = Vec::with_capacity(length);
+                for _ in 0..length {
+                    parents.push(self.context()?);
+                    return_states.push(self.value()? as isize);
+                }
+                let context = match (is_array, parents.pop()) {
+                    (false, Some(parent)) if parents.is_empty() => {
+                        let context = PredictionContext::new_singleton(parent, return_states[0]);
+                        if context.is_empty() {
+                            EMPTY_PREDICTION_CONTEXT.clone()
+                        } else {
+                            Arc::new(context)
+                        }
+                    }
+                    (true, Some(last)) => {
+                        parents.push(last);
+                        PredictionContext::new_array(parents, return_states).alloc()
+                    }
+                    _ => return Err(invalid("invalid prediction context")),
+                };
+                self.contexts.push(context.clone());
+                Ok(Some(context))
+            }
+            id => match self.contexts.get(id as usize).filter(|_| id >= 0) {
+                Some(context) => Ok(Some(context.clone())),
+                None => Err(invalid("invalid prediction context")),
+            },
+        }
+    }
+
+    fn semantic(&mut self) -> io::Result<SemanticContext> {
+        Ok(match self.value()? {
+            0 => SemanticContext::Predicate {
+                rule_index: self.value()? as isize,
+                pred_index: self.value()? as isize,
+                is_ctx_dependent: self.flag()?,
+            },
+            1 => SemanticContext::Precedence(self.value()? as isize),
+            2 => SemanticContext::AND(self.semantic_operands()?),
+            3 => SemanticContext::OR(self.semantic_operands()?),
+            _ => return Err(invalid("unknown semantic context")),
+        })
+    }
+
+    fn semantic_operands(&mut self) -> io::Result<Vec<SemanticContext>> {
+        (0..self.len()?).map(|_| self.semantic()).collect()
+    }
+
+    fn executor(&mut self) -> io::Result<Option<LexerActionExecutor>> {
+        match self.value()? {
+            -1 => Ok(None),
+            length if length >= 0 && (length as u64) <= self.data.len() as u64 / 8 => {
+                let actions = (0..length)
+                    .map(|_| self.action())
+                    .collect::<io::Result<Vec<_>>>()?;
+                Ok(Some(LexerActionExecutor::new(actions)))
+            }
+            _ => Err(invalid("invalid length")),
+        }
+    }
+
+    fn action(&mut self) -> io::Result<LexerAction> {
+        let action_type = self.value()? as isize;
+        if action_type == LEXER_ACTION_TYPE_INDEXED_CUSTOM {
+            let offset = self.value()? as isize;
+            return Ok(LexerAction::LexerIndexedCustomAction {
+                offset,
+                action: Box::new(self.action()?),
+            });
+        }
+        let first = self.value()? as isize;
+        let second = self.value()? as isize;
+        Ok(match action_type {
+            LEXER_ACTION_TYPE_CHANNEL => LexerAction::LexerChannelAction(first),
+            LEXER_ACTION_TYPE_CUSTOM => LexerAction::LexerCustomAction {
+                rule_index: first,
+                action_index: second,
+            },
+            LEXER_ACTION_TYPE_MODE => LexerAction::LexerModeAction(first),
+            LEXER_ACTION_TYPE_MORE => LexerAction::LexerMoreAction,
+            LEXER_ACTION_TYPE_POP_MODE => LexerAction::LexerPopModeAction,
+            LEXER_ACTION_TYPE_PUSH_MODE => LexerAction::LexerPushModeAction(first),
+            LEXER_ACTION_TYPE_SKIP => LexerAction::LexerSkipAction,
+            LEXER_ACTION_TYPE_TYPE => LexerAction::LexerTypeAction(first),
+            _ => return Err(invalid("unknown lexer action")),
+        })
+    }
+}
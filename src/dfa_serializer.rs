@@ -49,8 +49,12 @@ impl DFASerializer<'_, '_> {
         );
         if state.is_accept_state {
             base_str = if !state.predicates.is_empty() {
-                unimplemented!()
-            //                format!("{}=>{:?}", base_str, state.predicates)
+                let predicates = state.predicates.iter().map(|it| it.to_string());
+                format!(
+                    "{}=>[{}]",
+                    base_str,
+                    predicates.collect::<Vec<_>>().join(", ")
+                )
             } else {
                 format!("{}=>{}", base_str, state.prediction)
             };
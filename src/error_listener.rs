@@ -1,6 +1,8 @@
 //! Error reporting
-use std::cell::Ref;
+use std::cell::{Ref, RefCell};
+use std::collections::HashSet;
 use std::ops::Deref;
+use std::rc::Rc;
 
 use bit_set::BitSet;
 
@@ -8,16 +10,18 @@ use crate::atn_config_set::ATNConfigSet;
 use crate::dfa::DFA;
 use crate::errors::ANTLRError;
 
-use crate::parser::Parser;
-use crate::recognizer::Recognizer;
+use crate::recognizer::RecognizerRef;
 
-use crate::token_factory::TokenFactory;
+use crate::token::{OwningToken, Token};
 use std::borrow::Cow;
 use std::fmt::Debug;
 
 /// Describes interface for listening on parser/lexer errors.
 /// Should only listen for errors, for processing/recovering from errors use `ErrorStrategy`
-pub trait ErrorListener<'a, T: Recognizer<'a>> {
+///
+/// Recognizer is passed as type erased `RecognizerRef`, so single listener
+/// can be shared between lexer and parser by adding the same `Rc` to both of them.
+pub trait ErrorListener {
     /// Called when parser/lexer encounter hard error.
     ///
     /// The `_error` is not None for all syntax errors except
@@ -25,53 +29,44 @@ pub trait ErrorListener<'a, T: Recognizer<'a>> {
     /// in-line, without returning from the surrounding rule (via the single
     /// token insertion and deletion mechanism)
     fn syntax_error(
-        &self,
-        _recognizer: &T,
-        _offending_symbol: Option<&<T::TF as TokenFactory<'a>>::Inner>,
-        _line: isize,
-        _column: isize,
-        _msg: &str,
-        _error: Option<&ANTLRError>,
+        &self, _recognizer: &dyn RecognizerRef, _offending_symbol: Option<&OwningToken>,
+        _line: isize, _column: isize, _msg: &str, _error: Option<&ANTLRError>,
     ) {
     }
 
     /// This method is called by the parser when a full-context prediction
     /// results in an ambiguity.
     fn report_ambiguity(
-        &self,
-        _recognizer: &T,
-        _dfa: &DFA,
-        _start_index: isize,
-        _stop_index: isize,
-        _exact: bool,
-        _ambig_alts: &BitSet,
-        _configs: &ATNConfigSet,
+        &self, _recognizer: &dyn RecognizerRef, _dfa: &DFA, _start_index: isize,
+        _stop_index: isize, _exact: bool, _ambig_alts: &BitSet, _configs: &ATNConfigSet,
     ) {
     }
 
     /// This method is called when an SLL conflict occurs and the parser is about
     /// to use the full context information to make an LL decision.
     fn report_attempting_full_context(
-        &self,
-        _recognizer: &T,
-        _dfa: &DFA,
-        _start_index: isize,
-        _stop_index: isize,
-        _conflicting_alts: &BitSet,
-        _configs: &ATNConfigSet,
+        &self, _recognizer: &dyn RecognizerRef, _dfa: &DFA, _start_index: isize,
+        _stop_index: isize, _conflicting_alts: &BitSet, _configs: &ATNConfigSet,
     ) {
     }
 
     /// This method is called by the parser when a full-context prediction has a
     /// unique result.
     fn report_context_sensitivity(
-        &self,
-        _recognizer: &T,
-        _dfa: &DFA,
-        _start_index: isize,
-        _stop_index: isize,
-        _prediction: isize,
-        _configs: &ATNConfigSet,
+        &self, _recognizer: &dyn RecognizerRef, _dfa: &DFA, _start_index: isize,
+        _stop_index: isize, _prediction: isize, _configs: &ATNConfigSet,
+    ) {
+    }
+
+    /// This method is called by the parser when DFA of the `decision` in rule `rule_name`
+    /// grows over the size set by `ParserATNSimulator::set_dfa_growth_warning`.
+    /// It is called at most once per decision per parse.
+    ///
+    /// `state_count` is the current number of DFA states, and `start_index..=input_index`
+    /// are token indexes of the input that caused the latest DFA state to be added.
+    fn report_dfa_growth(
+        &self, _recognizer: &dyn RecognizerRef, _decision: isize, _rule_name: &str,
+        _state_count: usize, _start_index: isize, _input_index: isize,
     ) {
     }
 }
@@ -80,34 +75,130 @@ pub trait ErrorListener<'a, T: Recognizer<'a>> {
 #[derive(Debug)]
 pub struct ConsoleErrorListener {}
 
-impl<'a, T: Recognizer<'a>> ErrorListener<'a, T> for ConsoleErrorListener {
+impl ErrorListener for ConsoleErrorListener {
     fn syntax_error(
-        &self,
-        _recognizer: &T,
-        _offending_symbol: Option<&<T::TF as TokenFactory<'a>>::Inner>,
-        line: isize,
-        column: isize,
-        msg: &str,
-        _e: Option<&ANTLRError>,
+        &self, _recognizer: &dyn RecognizerRef, _offending_symbol: Option<&OwningToken>,
+        line: isize, column: isize, msg: &str, _e: Option<&ANTLRError>,
     ) {
         eprintln!("line {}:{} {}", line, column, msg);
     }
 }
 
+/// Syntax error collected by `CollectingErrorListener`
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// Line of the offending token
+    pub line: isize,
+    /// Column of the offending token
+    pub column: isize,
+    /// Error message
+    pub message: String,
+    /// Token that caused the error
+    pub offending_token: Option<OwningToken>,
+    /// Error that was reported, `None` if parser recovered inline
+    pub error: Option<ANTLRError>,
+}
+
+/// Error listener that stores all syntax errors it receives
+#[derive(Debug, Default)]
+pub struct CollectingErrorListener {
+    diagnostics: RefCell<Vec<Diagnostic>>,
+}
+
+impl CollectingErrorListener {
+    /// Creates new empty listener
+    pub fn new() -> Self { Self::default() }
+
+    /// Returns collected errors sorted by position and clears this listener.
+    ///
+    /// If error recovery reported several errors for the same offending token
+    /// only the first one is returned.
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.diagnostics.take();
+        // stable sort keeps reporting order for the same position
+        diagnostics.sort_by_key(|it| (it.line, it.column));
+        let mut seen = HashSet::new();
+        diagnostics.retain(|it| match &it.offending_token {
+            // conjured tokens do not have index
+            Some(token) if token.get_token_index() >= 0 => seen.insert(token.get_token_index()),
+            _ => true,
+        });
+        diagnostics
+    }
+}
+
+impl ErrorListener for CollectingErrorListener {
+    fn syntax_error(
+        &self, _recognizer: &dyn RecognizerRef, offending_symbol: Option<&OwningToken>,
+        line: isize, column: isize, msg: &str, error: Option<&ANTLRError>,
+    ) {
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            line,
+            column,
+            message: msg.to_owned(),
+            offending_token: offending_symbol.cloned(),
+            error: error.cloned(),
+        })
+    }
+}
+
+/// Ambiguity collected by `AmbiguityCollector`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AmbiguityInfo {
+    /// Decision number
+    pub decision: isize,
+    /// Token index where prediction started
+    pub start_index: isize,
+    /// Token index where ambiguity was detected
+    pub stop_index: isize,
+    /// Whether ambiguity is exact
+    pub exact: bool,
+    /// Ambiguous alternatives
+    pub alts: BitSet,
+}
+
+/// Error listener that stores all ambiguities reported by parser,
+/// e.g. to see the competing trees with `ParserInterpreter::get_all_possible_parse_trees`.
+///
+/// Parser reports ambiguities only for decisions that needed full context prediction,
+/// use `PredictionMode::LL_EXACT_AMBIG_DETECTION` to get only exact ones.
+#[derive(Debug, Default)]
+pub struct AmbiguityCollector {
+    ambiguities: RefCell<Vec<AmbiguityInfo>>,
+}
+
+impl AmbiguityCollector {
+    /// Creates new empty listener
+    pub fn new() -> Self { Self::default() }
+
+    /// Returns collected ambiguities in reporting order and clears this listener
+    pub fn take_ambiguities(&self) -> Vec<AmbiguityInfo> { self.ambiguities.take() }
+}
+
+impl ErrorListener for AmbiguityCollector {
+    fn report_ambiguity(
+        &self, _recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        exact: bool, ambig_alts: &BitSet, _configs: &ATNConfigSet,
+    ) {
+        self.ambiguities.borrow_mut().push(AmbiguityInfo {
+            decision: dfa.decision,
+            start_index,
+            stop_index,
+            exact,
+            alts: ambig_alts.clone(),
+        })
+    }
+}
+
 // #[derive(Debug)]
-pub(crate) struct ProxyErrorListener<'b, 'a, T> {
-    pub delegates: Ref<'b, Vec<Box<dyn ErrorListener<'a, T>>>>,
+pub(crate) struct ProxyErrorListener<'b, 'a> {
+    pub delegates: Ref<'b, Vec<Rc<dyn ErrorListener + 'a>>>,
 }
 
-impl<'b, 'a, T: Recognizer<'a>> ErrorListener<'a, T> for ProxyErrorListener<'b, 'a, T> {
+impl<'b, 'a> ErrorListener for ProxyErrorListener<'b, 'a> {
     fn syntax_error(
-        &self,
-        _recognizer: &T,
-        offending_symbol: Option<&<T::TF as TokenFactory<'a>>::Inner>,
-        line: isize,
-        column: isize,
-        msg: &str,
-        e: Option<&ANTLRError>,
+        &self, _recognizer: &dyn RecognizerRef, offending_symbol: Option<&OwningToken>,
+        line: isize, column: isize, msg: &str, e: Option<&ANTLRError>,
     ) {
         for listener in self.delegates.deref() {
             listener.syntax_error(_recognizer, offending_symbol, line, column, msg, e)
@@ -115,14 +206,8 @@ impl<'b, 'a, T: Recognizer<'a>> ErrorListener<'a, T> for ProxyErrorListener<'b,
     }
 
     fn report_ambiguity(
-        &self,
-        recognizer: &T,
-        dfa: &DFA,
-        start_index: isize,
-        stop_index: isize,
-        exact: bool,
-        ambig_alts: &BitSet<u32>,
-        configs: &ATNConfigSet,
+        &self, recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        exact: bool, ambig_alts: &BitSet<u32>, configs: &ATNConfigSet,
     ) {
         for listener in self.delegates.deref() {
             listener.report_ambiguity(
@@ -138,13 +223,8 @@ impl<'b, 'a, T: Recognizer<'a>> ErrorListener<'a, T> for ProxyErrorListener<'b,
     }
 
     fn report_attempting_full_context(
-        &self,
-        recognizer: &T,
-        dfa: &DFA,
-        start_index: isize,
-        stop_index: isize,
-        conflicting_alts: &BitSet<u32>,
-        configs: &ATNConfigSet,
+        &self, recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        conflicting_alts: &BitSet<u32>, configs: &ATNConfigSet,
     ) {
         for listener in self.delegates.deref() {
             listener.report_attempting_full_context(
@@ -159,13 +239,8 @@ impl<'b, 'a, T: Recognizer<'a>> ErrorListener<'a, T> for ProxyErrorListener<'b,
     }
 
     fn report_context_sensitivity(
-        &self,
-        recognizer: &T,
-        dfa: &DFA,
-        start_index: isize,
-        stop_index: isize,
-        prediction: isize,
-        configs: &ATNConfigSet,
+        &self, recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        prediction: isize, configs: &ATNConfigSet,
     ) {
         for listener in self.delegates.deref() {
             listener.report_context_sensitivity(
@@ -178,11 +253,27 @@ impl<'b, 'a, T: Recognizer<'a>> ErrorListener<'a, T> for ProxyErrorListener<'b,
             )
         }
     }
+
+    fn report_dfa_growth(
+        &self, recognizer: &dyn RecognizerRef, decision: isize, rule_name: &str,
+        state_count: usize, start_index: isize, input_index: isize,
+    ) {
+        for listener in self.delegates.deref() {
+            listener.report_dfa_growth(
+                recognizer,
+                decision,
+                rule_name,
+                state_count,
+                start_index,
+                input_index,
+            )
+        }
+    }
 }
 
 /// This implementation of `ErrorListener` can be used to identify
 /// certain potential correctness and performance problems in grammars. "Reports"
-/// are made by calling `Parser::notify_error_listeners` with the appropriate
+/// are made by calling `RecognizerRef::report_diagnostic` with the appropriate
 /// message.
 ///
 ///  - Ambiguities: These are cases where more than one path through the
@@ -204,17 +295,17 @@ impl DiagnosticErrorListener {
     /// When `exact_only` is true, only exactly known ambiguities are reported.
     pub fn new(exact_only: bool) -> Self { Self { exact_only } }
 
-    fn get_decision_description<'a, T: Parser<'a>>(&self, recog: &T, dfa: &DFA) -> String {
-        let decision = dfa.decision;
-        let rule_index = recog.get_atn().states[dfa.atn_start_state].get_rule_index();
-
-        let rule_names = recog.get_rule_names();
-        if let Some(&rule_name) = rule_names.get(rule_index) {
-            format!("{} ({})", decision, rule_name)
-        } else {
-            decision.to_string()
+    fn get_decision_description(&self, recog: &dyn RecognizerRef, dfa: &DFA) -> String {
+        match recog.atn() {
+            Some(atn) => atn.describe_decision(
+                dfa.decision(),
+                recog.rule_names(),
+                recog.grammar_file_name(),
+            ),
+            None => format!("decision {}", dfa.decision()),
         }
     }
+
     /// Computes the set of conflicting or ambiguous alternatives from a
     /// configuration set, if that information was not already provided by the
     /// parser in `alts`.
@@ -236,67 +327,45 @@ impl DiagnosticErrorListener {
     }
 }
 
-impl<'a, T: Parser<'a>> ErrorListener<'a, T> for DiagnosticErrorListener {
+impl ErrorListener for DiagnosticErrorListener {
     fn report_ambiguity(
-        &self,
-        recognizer: &T,
-        dfa: &DFA,
-        start_index: isize,
-        stop_index: isize,
-        exact: bool,
-        ambig_alts: &BitSet<u32>,
-        _configs: &ATNConfigSet,
+        &self, recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        exact: bool, ambig_alts: &BitSet<u32>, _configs: &ATNConfigSet,
     ) {
         if self.exact_only && !exact {
             return;
         }
         let msg = format!(
-            "reportAmbiguity d={}: ambigAlts={:?}, input='{}'",
+            "reportAmbiguity {}: ambigAlts={:?}, input='{}'",
             self.get_decision_description(recognizer, dfa),
             ambig_alts,
-            recognizer
-                .get_input_stream()
-                .get_text_from_interval(start_index, stop_index)
+            recognizer.input_text(start_index, stop_index)
         );
-        recognizer.notify_error_listeners(msg, None, None);
+        recognizer.report_diagnostic(msg);
     }
 
     fn report_attempting_full_context(
-        &self,
-        recognizer: &T,
-        dfa: &DFA,
-        start_index: isize,
-        stop_index: isize,
-        _conflicting_alts: &BitSet<u32>,
-        _configs: &ATNConfigSet,
+        &self, recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        _conflicting_alts: &BitSet<u32>, _configs: &ATNConfigSet,
     ) {
         let msg = format!(
-            "reportAttemptingFullContext d={}, input='{}'",
+            "reportAttemptingFullContext {}, input='{}'",
             self.get_decision_description(recognizer, dfa),
-            recognizer
-                .get_input_stream()
-                .get_text_from_interval(start_index, stop_index)
+            recognizer.input_text(start_index, stop_index)
         );
-        recognizer.notify_error_listeners(msg, None, None);
+        recognizer.report_diagnostic(msg);
     }
 
     fn report_context_sensitivity(
-        &self,
-        recognizer: &T,
-        dfa: &DFA,
-        start_index: isize,
-        stop_index: isize,
-        _prediction: isize,
-        _configs: &ATNConfigSet,
+        &self, recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        _prediction: isize, _configs: &ATNConfigSet,
     ) {
         let msg = format!(
-            "reportContextSensitivity d={}, input='{}'",
+            "reportContextSensitivity {}, input='{}'",
             self.get_decision_description(recognizer, dfa),
-            recognizer
-                .get_input_stream()
-                .get_text_from_interval(start_index, stop_index)
+            recognizer.input_text(start_index, stop_index)
         );
-        recognizer.notify_error_listeners(msg, None, None);
+        recognizer.report_diagnostic(msg);
     }
 }
 /*
@@ -11,7 +11,7 @@ use crate::atn_state::*;
 use crate::char_stream::{CharStream, InputData};
 use crate::dfa::ScopeExt;
 use crate::errors::{ANTLRError, FailedPredicateError, InputMisMatchError, NoViableAltError};
-use crate::interval_set::IntervalSet;
+use crate::interval_set::{IntervalSet, TokenInterval};
 use crate::parser::{Parser, ParserNodeType};
 use crate::parser_rule_context::ParserRuleContext;
 use crate::rule_context::{CustomRuleContext, RuleContext};
@@ -89,10 +89,14 @@ pub trait ErrorStrategy<'a, T: Parser<'a>>: Tid<'a> {
 // impl<'a, T: Parser<'a>> Default for Box<dyn ErrorStrategy<'a, T> + 'a> {
 //     fn default() -> Self { Box::new(DefaultErrorStrategy::new()) }
 // }
-//
-// /// Error strategy trait object if there is a need to change error strategy at runtime
-// /// Supports downcasting.
-// pub type DynHandler<'a, T> = Box<dyn ErrorStrategy<'a, T> + 'a>;
+
+/// Error strategy trait object if there is a need to change error strategy at runtime,
+/// e.g. to switch to a custom recovery policy for some inputs.
+/// Supports downcasting.
+///
+/// Generated parsers and `ParserInterpreter` use it as their error strategy
+/// when created with `with_dyn_strategy`, then strategy can be replaced with `set_error_strategy`.
+pub type DynHandler<'a, T> = Box<dyn ErrorStrategy<'a, T> + 'a>;
 
 #[impl_tid]
 impl<'a, T: Parser<'a> + TidAble<'a>> TidAble<'a> for Box<dyn ErrorStrategy<'a, T> + 'a> {}
@@ -184,10 +188,12 @@ impl<'input, Ctx: ParserNodeType<'input>> DefaultErrorStrategy<'input, Ctx> {
         let input = if e.start_token.token_type == TOKEN_EOF {
             "<EOF>".to_owned()
         } else {
-            recognizer.get_input_stream_mut().get_text_from_interval(
-                e.start_token.get_token_index(),
-                e.base.offending_token.get_token_index(),
-            )
+            recognizer
+                .get_input_stream_mut()
+                .get_text_from_interval(TokenInterval::new(
+                    e.start_token.get_token_index(),
+                    e.base.offending_token.get_token_index(),
+                ))
         };
 
         format!("no viable alternative at input '{}'", input)
@@ -201,9 +207,7 @@ impl<'input, Ctx: ParserNodeType<'input>> DefaultErrorStrategy<'input, Ctx> {
         format!(
             "mismatched input {} expecting {}",
             self.get_token_error_display(&e.base.offending_token),
-            e.base
-                .get_expected_tokens(recognizer)
-                .to_token_string(recognizer.get_vocabulary())
+            self.get_expected_display(recognizer, &e.expected)
         )
     }
 
@@ -229,7 +233,7 @@ impl<'input, Ctx: ParserNodeType<'input>> DefaultErrorStrategy<'input, Ctx> {
 
         self.begin_error_condition(recognizer);
         let expecting = self.get_expected_tokens(recognizer);
-        let expecting = expecting.to_token_string(recognizer.get_vocabulary());
+        let expecting = self.get_expected_display(recognizer, &expecting);
         let t = recognizer.get_current_token().borrow();
         let token_name = self.get_token_error_display(t);
         let msg = format!("extraneous input {} expecting {}", token_name, expecting);
@@ -247,7 +251,7 @@ impl<'input, Ctx: ParserNodeType<'input>> DefaultErrorStrategy<'input, Ctx> {
 
         self.begin_error_condition(recognizer);
         let expecting = self.get_expected_tokens(recognizer);
-        let expecting = expecting.to_token_string(recognizer.get_vocabulary());
+        let expecting = self.get_expected_display(recognizer, &expecting);
         let t = recognizer.get_current_token().borrow();
         let _token_name = self.get_token_error_display(t);
         let msg = format!(
@@ -283,21 +287,27 @@ impl<'input, Ctx: ParserNodeType<'input>> DefaultErrorStrategy<'input, Ctx> {
         false
     }
 
+    /// Deletes current token if the token after it is the one parser expects now.
+    ///
+    /// If `LA(2)` is in the expected set, reports current token as extraneous,
+    /// consumes it and returns the token that now matches, which is not consumed yet.
+    /// Otherwise returns `None` without touching the input,
+    /// so that single token insertion or panic mode recovery can be attempted instead.
     fn single_token_deletion<'a, T: Parser<'input, Node = Ctx, TF = Ctx::TF>>(
         &mut self,
         recognizer: &'a mut T,
     ) -> Option<&'a <T::TF as TokenFactory<'input>>::Tok> {
         let next_token_type = recognizer.get_input_stream_mut().la(2);
         let expecting = self.get_expected_tokens(recognizer);
-        //        println!("expecting {}", expecting.to_token_string(recognizer.get_vocabulary()));
-        if expecting.contains(next_token_type) {
-            self.report_unwanted_token(recognizer);
-            recognizer.consume(self);
-            self.report_match(recognizer);
-            let matched_symbol = recognizer.get_current_token();
-            return Some(matched_symbol);
+        if !expecting.contains(next_token_type) {
+            return None;
         }
-        None
+        self.report_unwanted_token(recognizer);
+        // simply delete extra token
+        recognizer.consume(self);
+        // we know current token is correct
+        self.report_match(recognizer);
+        Some(recognizer.get_current_token())
     }
 
     fn get_missing_symbol<T: Parser<'input, Node = Ctx, TF = Ctx::TF>>(
@@ -345,6 +355,17 @@ impl<'input, Ctx: ParserNodeType<'input>> DefaultErrorStrategy<'input, Ctx> {
         recognizer.get_expected_tokens()
     }
 
+    /// Expected token set as it is shown in error messages,
+    /// whitespaces in literal token names are escaped so message stays on one line
+    fn get_expected_display<T: Parser<'input, Node = Ctx, TF = Ctx::TF>>(
+        &self, recognizer: &T, expecting: &IntervalSet,
+    ) -> String {
+        escape_whitespaces(
+            expecting.to_token_string(recognizer.get_vocabulary()),
+            false,
+        )
+    }
+
     fn get_token_error_display<T: Token + ?Sized>(&self, t: &T) -> String {
         let text = t.get_text().to_display();
         self.escape_ws_and_quote(&text)
@@ -425,7 +446,8 @@ impl<'a, T: Parser<'a>> ErrorStrategy<'a, T> for DefaultErrorStrategy<'a, T::Nod
         //        Err(ANTLRError::IllegalStateError("aaa".to_string()))
     }
 
-    fn recover(&mut self, recognizer: &mut T, _e: &ANTLRError) -> Result<(), ANTLRError> {
+    fn recover(&mut self, recognizer: &mut T, e: &ANTLRError) -> Result<(), ANTLRError> {
+        check_input_error(recognizer, e)?;
         if self.last_error_index == recognizer.get_input_stream_mut().index()
             && self.last_error_states.is_some()
             && self
@@ -447,6 +469,9 @@ impl<'a, T: Parser<'a>> ErrorStrategy<'a, T> for DefaultErrorStrategy<'a, T::Nod
     }
 
     fn sync(&mut self, recognizer: &mut T) -> Result<(), ANTLRError> {
+        recognizer.get_input_stream_mut().try_lt(1)?;
+        recognizer.check_cancelled()?;
+        recognizer.check_rule_depth()?;
         if self.in_error_recovery_mode(recognizer) {
             return Ok(());
         }
@@ -488,7 +513,15 @@ impl<'a, T: Parser<'a>> ErrorStrategy<'a, T> for DefaultErrorStrategy<'a, T::Nod
                 expecting.add_set(&self.get_error_recovery_set(recognizer));
                 self.consume_until(recognizer, &expecting);
             }
-            _ => panic!("invalid ANTState type id"),
+            type_id => runtime_panic!(
+                {
+                    state: state.get_state_number(),
+                    input_index: recognizer.get_input_stream().index(),
+                    rule_stack: recognizer.get_rule_invocation_stack(),
+                },
+                "sync in ATN state of unexpected type {}",
+                type_id
+            ),
         }
 
         Ok(())
@@ -497,7 +530,12 @@ impl<'a, T: Parser<'a>> ErrorStrategy<'a, T> for DefaultErrorStrategy<'a, T::Nod
     fn in_error_recovery_mode(&mut self, _recognizer: &mut T) -> bool { self.error_recovery_mode }
 
     fn report_error(&mut self, recognizer: &mut T, e: &ANTLRError) {
-        if self.in_error_recovery_mode(recognizer) {
+        recognizer
+            .get_parser_rule_context()
+            .set_exception(e.clone());
+        if self.in_error_recovery_mode(recognizer)
+            || matches!(e, ANTLRError::Io(_) | ANTLRError::Cancelled(_))
+        {
             return;
         }
 
@@ -506,6 +544,8 @@ impl<'a, T: Parser<'a>> ErrorStrategy<'a, T> for DefaultErrorStrategy<'a, T::Nod
             ANTLRError::NoAltError(e) => self.report_no_viable_alternative(recognizer, e),
             ANTLRError::InputMismatchError(e) => self.report_input_mismatch(recognizer, e),
             ANTLRError::PredicateError(e) => self.report_failed_predicate(recognizer, e),
+            ANTLRError::StrictnessError(e) => e.base.message.clone(),
+            ANTLRError::DepthExceededError(e) => e.base.message.clone(),
             _ => e.to_string(),
         };
         let offending_token_index = e.get_offending_token().map(|it| it.get_token_index());
@@ -518,6 +558,19 @@ impl<'a, T: Parser<'a>> ErrorStrategy<'a, T> for DefaultErrorStrategy<'a, T::Nod
     }
 }
 
+/// Token source errors and cancellation are not recoverable, so they are returned as is.
+/// Syntax error can also be caused by token source error that was not returned yet,
+/// e.g. if lookahead token was not fetched, in that case token source error is returned instead.
+fn check_input_error<'a, T: Parser<'a>>(
+    recognizer: &mut T, e: &ANTLRError,
+) -> Result<(), ANTLRError> {
+    if let ANTLRError::Io(_) | ANTLRError::Cancelled(_) = e {
+        return Err(e.clone());
+    }
+    recognizer.get_input_stream_mut().try_lt(1)?;
+    Ok(())
+}
+
 /// This implementation of `ANTLRErrorStrategy` responds to syntax errors
 /// by immediately canceling the parse operation with a
 /// `ParseCancellationException`. The implementation ensures that the
@@ -529,7 +582,7 @@ impl<'a, T: Parser<'a>> ErrorStrategy<'a, T> for DefaultErrorStrategy<'a, T::Nod
 ///  - Two-stage parsing: This error strategy allows the first
 /// stage of two-stage parsing to immediately terminate if an error is
 /// encountered, and immediately fall back to the second stage. In addition to
-/// avoiding wasted work by attempting to recover from errors here, the empty
+/// avoiding wasted work by attempting to recover from errors here, the trivial
 /// implementation of `sync` improves the performance of
 /// the first stage.
 ///  - Silent validation: When syntax errors are not being
@@ -605,13 +658,15 @@ impl<'a, T: Parser<'a>> ErrorStrategy<'a, T> for BailErrorStrategy<'a, T::Node>
 
     #[cold]
     fn recover(&mut self, recognizer: &mut T, e: &ANTLRError) -> Result<(), ANTLRError> {
+        check_input_error(recognizer, e)?;
         Err(self.process_error(recognizer, &e))
     }
 
     #[inline(always)]
-    fn sync(&mut self, _recognizer: &mut T) -> Result<(), ANTLRError> {
-        /* empty */
-        Ok(())
+    fn sync(&mut self, recognizer: &mut T) -> Result<(), ANTLRError> {
+        // only makes sure that token source has not failed and parsing was not cancelled
+        recognizer.get_input_stream_mut().try_lt(1)?;
+        recognizer.check_cancelled()
     }
 
     #[inline(always)]
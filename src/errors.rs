@@ -4,16 +4,22 @@ use std::error::Error;
 use std::fmt;
 use std::fmt::Formatter;
 use std::fmt::{Debug, Display};
+use std::io;
 use std::ops::Deref;
 use std::rc::Rc;
 
+use crate::atn_config::ATNConfig;
+use crate::atn_config_set::ATNConfigSet;
 use crate::atn_simulator::IATNSimulator;
+use crate::cancellation::ParseCancelled;
+use crate::char_stream::InputData;
 use crate::interval_set::IntervalSet;
 use crate::parser::{Parser, ParserNodeType};
-use crate::rule_context::states_stack;
-use crate::token::{OwningToken, Token};
+use crate::rule_context::{states_stack, CustomRuleContext};
+use crate::token::{OwningToken, Token, TOKEN_EOF};
 use crate::transition::PredicateTransition;
 use crate::transition::TransitionType::TRANSITION_PREDICATE;
+use crate::utils::escape_whitespaces;
 
 /// Main ANTLR4 Rust runtime error
 #[derive(Debug, Clone)]
@@ -46,10 +52,33 @@ pub enum ANTLRError {
     /// prediction.
     PredicateError(FailedPredicateError),
 
+    /// Prediction succeeded but reported a condition forbidden by parser's `StrictnessPolicy`,
+    /// e.g. an ambiguity. Such errors are reported only if policy was explicitly enabled,
+    /// and then parsing is aborted with `FallThrough` wrapping `StrictnessError`.
+    StrictnessError(StrictnessError),
+
+    /// Parser went deeper into nested rules than allowed by `BaseParser::set_max_rule_depth`.
+    /// Reported and recovered from like other syntax errors,
+    /// so deeply nested input results in an error instead of a stack overflow.
+    DepthExceededError(DepthExceededError),
+
     /// Internal error. Or user provided type returned data that is
     /// incompatible with current parser state
     IllegalStateError(String),
 
+    /// Token source failed to produce next token, e.g. because underlying input could not be read
+    /// or decoded. Error strategies do not try to recover from it, so it is returned as is
+    /// from the rule that was called by user.
+    ///
+    /// Token stream stays at the position where error has happened,
+    /// and failed token is requested from token source again on the next fetch.
+    Io(IoError),
+
+    /// Parsing has been stopped by `CancellationToken` or because its deadline has passed.
+    /// Like `Io`, it is returned as is from the rule that was called by user,
+    /// without being reported or recovered from.
+    Cancelled(ParseCancelled),
+
     /// Unrecoverable error. Indicates that error should not be processed by parser/error strategy
     /// and it should abort parsing and immediately return to caller.
     FallThrough(Rc<dyn Error>),
@@ -77,26 +106,64 @@ pub enum ANTLRError {
 // }
 
 impl Display for ANTLRError {
-    fn fmt(&self, _f: &mut Formatter<'_>) -> fmt::Result { <Self as Debug>::fmt(self, _f) }
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ANTLRError::LexerNoAltError { start_index } => {
+                write!(f, "token recognition error at index {}", start_index)
+            }
+            ANTLRError::NoAltError(e) => Display::fmt(e, f),
+            ANTLRError::InputMismatchError(e) => Display::fmt(e, f),
+            ANTLRError::PredicateError(e) => Display::fmt(e, f),
+            ANTLRError::StrictnessError(e) => Display::fmt(e, f),
+            ANTLRError::DepthExceededError(e) => Display::fmt(e, f),
+            ANTLRError::IllegalStateError(msg) => f.write_str(msg),
+            ANTLRError::Io(e) => Display::fmt(e, f),
+            ANTLRError::Cancelled(e) => Display::fmt(e, f),
+            ANTLRError::FallThrough(e) => Display::fmt(e, f),
+            ANTLRError::OtherError(e) => Display::fmt(e, f),
+        }
+    }
 }
 
+// `Display` already shows the payload, so source is the source of the payload itself,
+// otherwise error chain reporters would print the same message twice
 impl Error for ANTLRError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
-            ANTLRError::FallThrough(x) => Some(x.as_ref()),
-            ANTLRError::OtherError(x) => Some(x.as_ref()),
+            ANTLRError::NoAltError(x) => x.source(),
+            ANTLRError::InputMismatchError(x) => x.source(),
+            ANTLRError::PredicateError(x) => x.source(),
+            ANTLRError::StrictnessError(x) => x.source(),
+            ANTLRError::DepthExceededError(x) => x.source(),
+            ANTLRError::FallThrough(x) => x.source(),
+            ANTLRError::OtherError(x) => x.source(),
+            ANTLRError::Io(x) => x.source(),
             _ => None,
         }
     }
 }
 
 impl ANTLRError {
+    /// Returns common part of parser errors if this is one of them
+    pub fn get_recognition_error(&self) -> Option<&BaseRecognitionError> {
+        Some(match self {
+            ANTLRError::NoAltError(e) => &e.base,
+            ANTLRError::InputMismatchError(e) => &e.base,
+            ANTLRError::PredicateError(e) => &e.base,
+            ANTLRError::StrictnessError(e) => &e.base,
+            ANTLRError::DepthExceededError(e) => &e.base,
+            _ => return None,
+        })
+    }
+
     /// Returns first token that caused parser to fail.
     pub fn get_offending_token(&self) -> Option<&OwningToken> {
         Some(match self {
             ANTLRError::NoAltError(e) => &e.base.offending_token,
             ANTLRError::InputMismatchError(e) => &e.base.offending_token,
             ANTLRError::PredicateError(e) => &e.base.offending_token,
+            ANTLRError::StrictnessError(e) => &e.base.offending_token,
+            ANTLRError::DepthExceededError(e) => &e.base.offending_token,
             _ => return None,
         })
     }
@@ -118,7 +185,11 @@ pub struct BaseRecognitionError {
     pub offending_token: OwningToken,
     pub offending_state: isize,
     states_stack: Vec<isize>, // ctx: Rc<dyn ParserRuleContext>
-                              //    input: Box<IntStream>
+    //    input: Box<IntStream>
+    grammar_file_name: String,
+    rule_index: Option<usize>,
+    rule_name: String,
+    recognizer_state: isize,
 }
 
 impl BaseRecognitionError {
@@ -130,17 +201,85 @@ impl BaseRecognitionError {
             .get_expected_tokens(self.offending_state, self.states_stack.iter().copied())
     }
 
+    /// Name of the grammar file of the parser that produced this error
+    pub fn get_grammar_file_name(&self) -> &str { &self.grammar_file_name }
+
+    /// Index of the rule parser was in when error happened
+    pub fn get_rule_index(&self) -> Option<usize> { self.rule_index }
+
+    /// Name of the rule parser was in when error happened, empty if it is unknown
+    pub fn get_rule_name(&self) -> &str { &self.rule_name }
+
+    /// ATN state parser was in when error happened.
+    ///
+    /// Unlike `offending_state` it is never adjusted to the state where error was detected
+    pub fn get_recognizer_state(&self) -> isize { self.recognizer_state }
+
+    /// Returns error position in the `line L:C` form.
+    ///
+    /// If the parser that produced this error has a grammar file name, header is prefixed
+    /// with it, e.g. `CSV.g4: line 1:0`, so errors from different grammars can be told apart.
+    pub fn get_error_header(&self) -> String {
+        let position = format!(
+            "line {}:{}",
+            self.offending_token.get_line(),
+            self.offending_token.get_column()
+        );
+        if !self.grammar_file_name.is_empty() {
+            format!("{}: {}", self.grammar_file_name, position)
+        } else {
+            position
+        }
+    }
+
+    // error header followed by `description`
+    fn fmt_with(&self, f: &mut Formatter<'_>, description: fmt::Arguments<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.get_error_header(), description)
+    }
+
     fn new<'a, T: Parser<'a>>(recog: &mut T) -> BaseRecognitionError {
+        let offending_token = recog.get_current_token().borrow().to_owned();
+        Self::from_parser(recog, "".to_string(), offending_token)
+    }
+
+    fn from_parser<'a, T: Parser<'a>>(
+        recog: &T, message: String, offending_token: OwningToken,
+    ) -> BaseRecognitionError {
+        let ctx = recog.get_parser_rule_context();
+        let mut result = Self::detached(recog, message, offending_token, ctx.get_rule_index());
+        result.states_stack = states_stack(ctx.clone()).collect();
+        result
+    }
+
+    /// Error that does not depend on parser's current context
+    fn detached<'a, T: Parser<'a>>(
+        recog: &T, message: String, offending_token: OwningToken, rule_index: usize,
+    ) -> BaseRecognitionError {
+        let rule_name = recog.get_rule_names().get(rule_index);
         BaseRecognitionError {
-            message: "".to_string(),
-            offending_token: recog.get_current_token().borrow().to_owned(),
+            message,
+            offending_token,
             offending_state: recog.get_state(),
-            // ctx: recog.get_parser_rule_context().clone(),
-            states_stack: states_stack(recog.get_parser_rule_context().clone()).collect(),
+            states_stack: Vec::new(),
+            grammar_file_name: recog.get_grammar_file_name().to_owned(),
+            rule_index: rule_name.map(|_| rule_index),
+            rule_name: rule_name.map(|&it| it.to_owned()).unwrap_or_default(),
+            recognizer_state: recog.get_state(),
         }
     }
 }
 
+// displays token like `DefaultErrorStrategy` does in error messages
+fn token_display(token: &OwningToken) -> String {
+    if token.get_token_type() == TOKEN_EOF {
+        return "<EOF>".to_owned();
+    }
+    format!(
+        "'{}'",
+        escape_whitespaces(token.get_text().to_display(), false)
+    )
+}
+
 /// See `ANTLRError::NoAltError`
 #[derive(Debug, Clone)]
 #[allow(missing_docs)]
@@ -148,22 +287,20 @@ pub struct NoViableAltError {
     pub base: BaseRecognitionError,
     pub start_token: OwningToken,
     //    ctx: Rc<dyn ParserRuleContext>,
-    //    dead_end_configs: BaseATNConfigSet,
+    /// Configurations prediction was in right before it failed to match offending token,
+    /// i.e. the paths through the ATN that were still possible.
+    /// Empty if error was not produced by prediction.
+    pub dead_end_configs: Vec<ATNConfig>,
 }
 
 #[allow(missing_docs)]
 impl NoViableAltError {
     pub fn new<'a, T: Parser<'a>>(recog: &mut T) -> NoViableAltError {
         Self {
-            base: BaseRecognitionError {
-                message: "".to_string(),
-                offending_token: recog.get_current_token().borrow().to_owned(),
-                offending_state: recog.get_state(),
-                // ctx: recog.get_parser_rule_context().clone(),
-                states_stack: states_stack(recog.get_parser_rule_context().clone()).collect(),
-            },
+            base: BaseRecognitionError::new(recog),
             start_token: recog.get_current_token().borrow().to_owned(),
             //            ctx: recog.get_parser_rule_context().clone()
+            dead_end_configs: Vec::new(),
         }
     }
     pub fn new_full<'a, T: Parser<'a>>(
@@ -172,31 +309,67 @@ impl NoViableAltError {
         offending_token: OwningToken,
     ) -> NoViableAltError {
         Self {
-            base: BaseRecognitionError {
-                message: "".to_string(),
-                offending_token,
-                offending_state: recog.get_state(),
-                states_stack: states_stack(recog.get_parser_rule_context().clone()).collect(), // ctx: recog.get_parser_rule_context().clone(),
-            },
+            base: BaseRecognitionError::from_parser(recog, "".to_string(), offending_token),
             start_token,
             //            ctx
+            dead_end_configs: Vec::new(),
         }
     }
+
+    /// Sets configurations prediction ended up in
+    pub fn with_dead_end_configs(mut self, configs: &ATNConfigSet) -> Self {
+        self.dead_end_configs = configs.get_items().cloned().collect();
+        self
+    }
+
+    /// Alternatives that were still viable right before prediction failed, in ascending order
+    pub fn get_dead_end_alts(&self) -> Vec<isize> {
+        let mut alts = self
+            .dead_end_configs
+            .iter()
+            .map(|it| it.get_alt())
+            .collect::<Vec<_>>();
+        alts.sort_unstable();
+        alts.dedup();
+        alts
+    }
 }
 
+impl Display for NoViableAltError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let offending = token_display(&self.base.offending_token);
+        if self.start_token.get_token_index() == self.base.offending_token.get_token_index() {
+            self.base.fmt_with(
+                f,
+                format_args!("no viable alternative at input {}", offending),
+            )
+        } else {
+            let start = token_display(&self.start_token);
+            self.base.fmt_with(
+                f,
+                format_args!("no viable alternative at input {}..{}", start, offending),
+            )
+        }
+    }
+}
+
+impl Error for NoViableAltError {}
+
 /// See `ANTLRError::InputMismatchError`
 #[derive(Debug, Clone)]
 #[allow(missing_docs)]
 pub struct InputMisMatchError {
     pub base: BaseRecognitionError,
+    /// Token types parser expected instead of the offending token,
+    /// same as `BaseRecognitionError::get_expected_tokens` at the time error was created
+    pub expected: IntervalSet,
 }
 
 #[allow(missing_docs)]
 impl InputMisMatchError {
     pub fn new<'a, T: Parser<'a>>(recognizer: &mut T) -> InputMisMatchError {
-        InputMisMatchError {
-            base: BaseRecognitionError::new(recognizer),
-        }
+        let base = BaseRecognitionError::new(recognizer);
+        Self::from_base(recognizer, base)
     }
 
     pub fn with_state<'a, T: Parser<'a>>(
@@ -204,14 +377,44 @@ impl InputMisMatchError {
         offending_state: isize,
         ctx: Rc<<T::Node as ParserNodeType<'a>>::Type>,
     ) -> InputMisMatchError {
-        let mut a = Self::new(recognizer);
-        // a.base.ctx = ctx;
-        a.base.offending_state = offending_state;
-        a.base.states_stack = states_stack(ctx).collect();
-        a
+        let mut base = BaseRecognitionError::new(recognizer);
+        // base.ctx = ctx;
+        base.offending_state = offending_state;
+        base.states_stack = states_stack(ctx).collect();
+        Self::from_base(recognizer, base)
+    }
+
+    fn from_base<'a, T: Parser<'a>>(recognizer: &T, base: BaseRecognitionError) -> Self {
+        let expected = base.get_expected_tokens(recognizer);
+        InputMisMatchError { base, expected }
+    }
+
+    /// Error for input that is left after root rule with `rule_index` has been completed.
+    /// `offending_state` is expected to be a rule stop state so that only `EOF` is expected.
+    pub(crate) fn trailing_input<'a, T: Parser<'a>>(
+        recognizer: &T, offending_token: OwningToken, offending_state: isize, rule_index: usize,
+    ) -> Self {
+        let mut base =
+            BaseRecognitionError::detached(recognizer, "".to_string(), offending_token, rule_index);
+        base.offending_state = offending_state;
+        Self::from_base(recognizer, base)
+    }
+}
+
+impl Display for InputMisMatchError {
+    /// Expected tokens are displayed as token types,
+    /// use `IntervalSet::to_token_string` to display them with names from grammar vocabulary
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let offending = token_display(&self.base.offending_token);
+        self.base.fmt_with(
+            f,
+            format_args!("mismatched input {} expecting {}", offending, self.expected),
+        )
     }
 }
 
+impl Error for InputMisMatchError {}
+
 //fn new_input_mis_match_exception(recognizer: Parser) -> InputMisMatchError { unimplemented!() }
 
 /// See `ANTLRError::PredicateError`
@@ -243,20 +446,165 @@ impl FailedPredicateError {
         };
 
         ANTLRError::PredicateError(FailedPredicateError {
-            base: BaseRecognitionError {
-                message: msg.unwrap_or_else(|| {
+            base: BaseRecognitionError::from_parser(
+                recog,
+                msg.unwrap_or_else(|| {
                     format!(
                         "failed predicate: {}",
                         predicate.as_deref().unwrap_or("None")
                     )
                 }),
-                offending_token: recog.get_current_token().borrow().to_owned(),
-                offending_state: recog.get_state(),
-                states_stack: states_stack(recog.get_parser_rule_context().clone()).collect(), // ctx: recog.get_parser_rule_context().clone()
-            },
+                recog.get_current_token().borrow().to_owned(),
+            ),
             rule_index,
             predicate_index,
             predicate: predicate.unwrap_or_default(),
         })
     }
+
+    /// Index of the failed predicate among the predicates of its rule
+    pub fn get_predicate_index(&self) -> isize { self.predicate_index }
+}
+
+impl Display for FailedPredicateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.base.rule_name.is_empty() {
+            self.base.fmt_with(f, format_args!("{}", self.base.message))
+        } else {
+            let rule_name = &self.base.rule_name;
+            self.base
+                .fmt_with(f, format_args!("rule {} {}", rule_name, self.base.message))
+        }
+    }
+}
+
+impl Error for FailedPredicateError {}
+
+/// Kind of prediction report that was turned into `StrictnessError`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StrictnessViolation {
+    /// Input matches several alternatives
+    Ambiguity,
+    /// SLL prediction had a conflict and parser had to retry with full context
+    FullContextFallback,
+    /// Full context prediction resolved SLL conflict, so decision depends on outer context
+    ContextSensitivity,
+}
+
+/// See `ANTLRError::Io`
+#[derive(Debug, Clone)]
+pub struct IoError {
+    /// Index that failed token would have in the token stream
+    pub token_index: isize,
+    /// Line of the token source position where error has happened
+    pub line: isize,
+    /// Char position in line of the token source position where error has happened
+    pub column: isize,
+    /// Error returned by token source
+    pub error: Rc<io::Error>,
+}
+
+// underlying error is not displayed, it is the source of this one
+impl Display for IoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}:{} failed to read token {}",
+            self.line, self.column, self.token_index
+        )
+    }
+}
+
+impl Error for IoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> { Some(self.error.as_ref()) }
+}
+
+/// See `ANTLRError::StrictnessError`
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub struct StrictnessError {
+    pub base: BaseRecognitionError,
+    pub violation: StrictnessViolation,
+    /// Decision number where violation happened
+    pub decision: isize,
+    /// Conflicting alternatives, or predicted one for `ContextSensitivity`
+    pub alts: Vec<isize>,
+    /// Token index of the first token of the input that caused violation
+    pub start_index: isize,
+    /// Token index of the last token of the input that caused violation
+    pub stop_index: isize,
+}
+
+#[allow(missing_docs)]
+impl StrictnessError {
+    pub fn new<'a, T: Parser<'a>>(
+        recog: &mut T, violation: StrictnessViolation, decision: isize, alts: Vec<isize>,
+        start_index: isize, stop_index: isize,
+    ) -> StrictnessError {
+        let offending_token = recog
+            .get_input_stream()
+            .get(start_index)
+            .borrow()
+            .to_owned();
+        let description = recog.get_atn().describe_decision(
+            decision,
+            recog.get_rule_names(),
+            recog.get_grammar_file_name(),
+        );
+        StrictnessError {
+            base: BaseRecognitionError::from_parser(
+                recog,
+                format!(
+                    "{:?} in {} for alternatives {:?} at input {}..{}",
+                    violation, description, alts, start_index, stop_index
+                ),
+                offending_token,
+            ),
+            violation,
+            decision,
+            alts,
+            start_index,
+            stop_index,
+        }
+    }
 }
+
+impl Display for StrictnessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.base.fmt_with(f, format_args!("{}", self.base.message))
+    }
+}
+
+impl Error for StrictnessError {}
+
+/// See `ANTLRError::DepthExceededError`
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub struct DepthExceededError {
+    pub base: BaseRecognitionError,
+    /// Maximum number of nested rule invocations that was exceeded
+    pub max_depth: usize,
+}
+
+#[allow(missing_docs)]
+impl DepthExceededError {
+    pub fn new<'a, T: Parser<'a>>(recog: &T, max_depth: usize) -> DepthExceededError {
+        let offending_token = recog.get_current_token().borrow().to_owned();
+        DepthExceededError {
+            base: BaseRecognitionError::from_parser(
+                recog,
+                format!("rule invocation depth exceeds limit of {}", max_depth),
+                offending_token,
+            ),
+            max_depth,
+        }
+    }
+}
+
+impl Display for DepthExceededError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.base.fmt_with(f, format_args!("{}", self.base.message))
+    }
+}
+
+impl Error for DepthExceededError {}
@@ -0,0 +1,352 @@
+//! INDENT/DEDENT tokens for indentation sensitive grammars
+use std::borrow::Borrow;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::io;
+
+use better_any::{Tid, TidAble};
+
+use crate::char_stream::{CharStream, InputData};
+use crate::int_stream::{IntStream, EOF};
+use crate::token::{Token, TOKEN_DEFAULT_CHANNEL, TOKEN_HIDDEN_CHANNEL};
+use crate::token_factory::TokenFactory;
+use crate::token_source::TokenSource;
+use crate::utils::CrateOnly;
+
+type Tok<'input, T> = <<T as TokenSource<'input>>::TF as TokenFactory<'input>>::Tok;
+
+/// Token source decorator that turns leading whitespace of lines into INDENT/DEDENT tokens,
+/// for Python or YAML like grammars.
+///
+/// Lexer is expected to produce tokens of `newline_type` for line breaks.
+/// Indentation of a line is the whitespace at the end of the newline token text,
+/// e.g. for `NEWLINE: '\r'? '\n' [ \t]*;`, together with the leading whitespace
+/// of off-channel tokens before the first token of the line,
+/// e.g. from `WS: [ \t]+ -> channel(HIDDEN);`.
+/// Whitespace that is skipped by the lexer is not taken into account.
+///
+/// For each line with on-channel tokens:
+///  - INDENT is emitted before the first token of the line if it is indented deeper
+///    than the previous one, DEDENT is emitted for each closed indentation level otherwise.
+///    If indentation does not match any of the outer levels it starts a new level;
+///  - newline at the end of the line is left as is, so it is a statement terminator.
+///    Newlines of blank lines are moved to `TOKEN_HIDDEN_CHANNEL`.
+///
+/// At the end of input NEWLINE is emitted if the last line has no newline,
+/// followed by DEDENT for each open indentation level.
+/// Synthetic tokens have empty text and are located right before the token that caused them.
+/// Tab advances indentation to the next multiple of tab width, which is 8 by default,
+/// form feed resets it to 0.
+/// ```text
+/// let source = IndentationAwareTokenSource::new(lexer, NEWLINE, INDENT, DEDENT);
+/// let parser = MyParser::new(CommonTokenStream::new(source));
+/// ```
+#[derive(Tid, Debug)]
+pub struct IndentationAwareTokenSource<'input, T: TokenSource<'input>> {
+    source: T,
+    newline_type: isize,
+    indent_type: isize,
+    dedent_type: isize,
+    tab_width: isize,
+    queue: VecDeque<Tok<'input, T>>,
+    // open indentation levels, outermost level 0 is not included
+    indents: Vec<isize>,
+    // whether the current line already has on-channel tokens
+    line_has_tokens: bool,
+    // indentation of the current line so far
+    indentation: isize,
+    // false once there was something other than whitespace before the first token of the line
+    counting: bool,
+}
+
+impl<'input, T: TokenSource<'input>> IndentationAwareTokenSource<'input, T> {
+    /// Creates decorator that emits `indent_type` and `dedent_type` tokens
+    /// according to indentation of lines separated with `newline_type` tokens of `source`
+    pub fn new(source: T, newline_type: isize, indent_type: isize, dedent_type: isize) -> Self {
+        Self {
+            source,
+            newline_type,
+            indent_type,
+            dedent_type,
+            tab_width: 8,
+            queue: VecDeque::new(),
+            indents: Vec::new(),
+            line_has_tokens: false,
+            indentation: 0,
+            counting: true,
+        }
+    }
+
+    /// Sets number of columns tab advances indentation to
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        runtime_assert!(tab_width > 0, "tab width must be positive");
+        self.tab_width = tab_width as isize;
+        self
+    }
+
+    /// Current indentation levels, from outermost to innermost, not including level 0
+    pub fn get_indentation_levels(&self) -> &[isize] { &self.indents }
+
+    /// Returns underlying token source
+    pub fn get_ref(&self) -> &T { &self.source }
+
+    /// Returns underlying token source
+    pub fn into_inner(self) -> T { self.source }
+
+    fn produce<E>(
+        &mut self, mut next: impl FnMut(&mut T) -> Result<Tok<'input, T>, E>,
+    ) -> Result<Tok<'input, T>, E> {
+        loop {
+            if let Some(token) = self.queue.pop_front() {
+                return Ok(token);
+            }
+            let token = next(&mut self.source)?;
+            self.process(token);
+        }
+    }
+
+    fn process(&mut self, token: Tok<'input, T>) {
+        let original: &<T::TF as TokenFactory<'input>>::Inner = token.borrow();
+        let token_type = original.get_token_type();
+        if token_type == EOF {
+            if self.line_has_tokens {
+                let newline = self.create_synthetic(self.newline_type, original);
+                self.queue.push_back(newline);
+            }
+            while self.indents.pop().is_some() {
+                let dedent = self.create_synthetic(self.dedent_type, original);
+                self.queue.push_back(dedent);
+            }
+            self.line_has_tokens = false;
+            self.indentation = 0;
+            self.counting = true;
+            self.queue.push_back(token);
+        } else if token_type == self.newline_type {
+            let text = original.get_text().to_display();
+            let tail = text
+                .rsplit(|c: char| c == '\n' || c == '\r')
+                .next()
+                .unwrap_or("");
+            self.indentation = 0;
+            self.counting = true;
+            self.advance_indentation(tail);
+            if self.line_has_tokens {
+                self.line_has_tokens = false;
+                self.queue.push_back(token);
+            } else {
+                let hidden = self.to_hidden(original);
+                self.queue.push_back(hidden);
+            }
+        } else if original.get_channel() != TOKEN_DEFAULT_CHANNEL || self.line_has_tokens {
+            if !self.line_has_tokens {
+                self.advance_indentation(&original.get_text().to_display());
+            }
+            self.queue.push_back(token);
+        } else {
+            self.line_has_tokens = true;
+            let indentation = self.indentation;
+            while self.indents.last().map_or(false, |&it| it > indentation) {
+                self.indents.pop();
+                let dedent = self.create_synthetic(self.dedent_type, original);
+                self.queue.push_back(dedent);
+            }
+            if indentation > self.indents.last().copied().unwrap_or(0) {
+                self.indents.push(indentation);
+                let indent = self.create_synthetic(self.indent_type, original);
+                self.queue.push_back(indent);
+            }
+            self.queue.push_back(token);
+        }
+    }
+
+    // Adds leading whitespace of `text` to the indentation of the current line
+    fn advance_indentation(&mut self, text: &str) {
+        for c in text.chars() {
+            if !self.counting {
+                return;
+            }
+            match c {
+                ' ' => self.indentation += 1,
+                '\t' => self.indentation += self.tab_width - self.indentation % self.tab_width,
+                '\x0c' => self.indentation = 0,
+                _ => self.counting = false,
+            }
+        }
+    }
+
+    // zero length token right before `next`
+    fn create_synthetic(
+        &self, token_type: isize, next: &<T::TF as TokenFactory<'input>>::Inner,
+    ) -> Tok<'input, T> {
+        self.source.get_token_factory().create(
+            None::<&mut dyn CharStream<<T::TF as TokenFactory<'input>>::From>>,
+            token_type,
+            Some(<<T::TF as TokenFactory<'input>>::Data as InputData>::from_text("")),
+            TOKEN_DEFAULT_CHANNEL,
+            next.get_start(),
+            next.get_start() - 1,
+            next.get_line(),
+            next.get_column(),
+        )
+    }
+
+    fn to_hidden(&self, original: &<T::TF as TokenFactory<'input>>::Inner) -> Tok<'input, T> {
+        let hidden = self.source.get_token_factory().create_with_end(
+            None::<&mut dyn CharStream<<T::TF as TokenFactory<'input>>::From>>,
+            original.get_token_type(),
+            Some(original.get_text().to_owned()),
+            TOKEN_HIDDEN_CHANNEL,
+            original.get_start(),
+            original.get_stop(),
+            original.get_line(),
+            original.get_column(),
+            original.get_end_line(),
+            original.get_end_column(),
+        );
+        let inner: &<T::TF as TokenFactory<'input>>::Inner = hidden.borrow();
+        inner.set_mode(original.get_mode(), CrateOnly);
+        hidden
+    }
+}
+
+impl<'input, T> TokenSource<'input> for IndentationAwareTokenSource<'input, T>
+where
+    T: TokenSource<'input>,
+{
+    type TF = T::TF;
+
+    fn next_token(&mut self) -> <Self::TF as TokenFactory<'input>>::Tok {
+        match self.produce(|source| Ok::<_, Infallible>(source.next_token())) {
+            Ok(token) => token,
+            Err(never) => match never {},
+        }
+    }
+
+    fn try_next_token(&mut self) -> io::Result<<Self::TF as TokenFactory<'input>>::Tok> {
+        self.produce(|source| source.try_next_token())
+    }
+
+    fn get_line(&self) -> isize { self.source.get_line() }
+
+    fn get_char_position_in_line(&self) -> isize { self.source.get_char_position_in_line() }
+
+    fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { self.source.get_input_stream() }
+
+    fn get_source_name(&self) -> String { self.source.get_source_name() }
+
+    fn get_token_factory(&self) -> &'input Self::TF { self.source.get_token_factory() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::char_stream::CharStream;
+    use crate::int_stream::{IntStream, EOF};
+    use crate::token::{CommonToken, Token, TOKEN_HIDDEN_CHANNEL};
+    use crate::token_factory::{CommonTokenFactory, TokenFactory};
+    use crate::token_source::TokenSource;
+
+    use super::IndentationAwareTokenSource;
+
+    const NEWLINE: isize = 1;
+    const ID: isize = 2;
+    const COMMENT: isize = 3;
+    const INDENT: isize = 4;
+    const DEDENT: isize = 5;
+
+    struct Tokens(Vec<(isize, &'static str)>);
+
+    impl<'input> TokenSource<'input> for Tokens {
+        type TF = CommonTokenFactory;
+
+        fn next_token(&mut self) -> Box<CommonToken<'input>> {
+            let (token_type, text) = if self.0.is_empty() {
+                (EOF, "")
+            } else {
+                self.0.remove(0)
+            };
+            let channel = if token_type == COMMENT {
+                TOKEN_HIDDEN_CHANNEL
+            } else {
+                0
+            };
+            self.get_token_factory().create_with_end(
+                None::<&mut dyn CharStream<Cow<'_, str>>>,
+                token_type,
+                Some(text.to_owned()),
+                channel,
+                0,
+                text.len() as isize - 1,
+                1,
+                0,
+                1,
+                text.len() as isize,
+            )
+        }
+
+        fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { None }
+
+        fn get_source_name(&self) -> String { "tokens".to_owned() }
+
+        fn get_token_factory(&self) -> &'input Self::TF { Default::default() }
+    }
+
+    fn types(tokens: Vec<(isize, &'static str)>, tab_width: usize) -> Vec<isize> {
+        let mut source = IndentationAwareTokenSource::new(Tokens(tokens), NEWLINE, INDENT, DEDENT)
+            .with_tab_width(tab_width);
+        let mut types = Vec::new();
+        loop {
+            let token = source.next_token();
+            if token.get_channel() == 0 {
+                types.push(token.get_token_type());
+            }
+            if token.get_token_type() == EOF {
+                return types;
+            }
+        }
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let tokens = vec![
+            (ID, "a"),
+            (NEWLINE, "\n\t"),
+            (ID, "b"),
+            (NEWLINE, "\r\n        "),
+            (ID, "c"),
+            (NEWLINE, "\n  \t"),
+            (ID, "d"),
+        ];
+        let indented = [
+            ID, NEWLINE, INDENT, ID, NEWLINE, ID, NEWLINE, ID, NEWLINE, DEDENT, EOF,
+        ];
+        assert_eq!(types(tokens.clone(), 8), indented);
+        assert_eq!(
+            types(tokens, 4),
+            [
+                ID, NEWLINE, INDENT, ID, NEWLINE, INDENT, ID, NEWLINE, DEDENT, ID, NEWLINE, DEDENT,
+                EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hidden_tokens() {
+        let tokens = vec![
+            (COMMENT, "// header"),
+            (NEWLINE, "\n"),
+            (ID, "a"),
+            (NEWLINE, "\n   "),
+            (COMMENT, "/* b */ "),
+            (ID, "b"),
+            (NEWLINE, "\n\x0c"),
+            (COMMENT, " "),
+            (ID, "c"),
+        ];
+        assert_eq!(
+            types(tokens, 8),
+            [ID, NEWLINE, INDENT, ID, NEWLINE, DEDENT, INDENT, ID, NEWLINE, DEDENT, EOF]
+        );
+    }
+}
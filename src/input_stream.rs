@@ -1,11 +1,20 @@
 //! Input to lexer
 use std::borrow::Cow;
 
-use crate::char_stream::{CharStream, InputData};
-use crate::int_stream::IntStream;
+use crate::char_stream::{CharStream, InputData, InputSymbols};
+use crate::int_stream::{panic_consume_eof, IntStream, LA_UNDEFINED};
+use crate::interval_set::CharInterval;
+use crate::line_index::{LineIndex, NewlineMode};
+use std::any::type_name;
+use std::char::REPLACEMENT_CHARACTER;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read};
 use std::ops::Deref;
 
 use better_any::{impl_tid, TidAble};
+use once_cell::unsync::OnceCell;
 
 /// Default rust target input stream.
 ///
@@ -13,12 +22,16 @@ use better_any::{impl_tid, TidAble};
 /// `InputStream<&str>` has slightly different index behavior in compare to java runtime when there are
 /// non-ASCII unicode characters.
 /// If you need it to generate exactly the same indexes as Java runtime, you have to use `CodePoint8/16/32BitCharStream`,
+/// or owned `InputStream::from_code_points`,
 /// which does not use rusts native `str` type, so it would do additional conversions and allocations along the way.
 #[derive(Debug)]
 pub struct InputStream<Data: Deref> {
     name: String,
     data_raw: Data,
     index: isize,
+    line_index: OnceCell<LineIndex>,
+    encoding: Option<Encoding>,
+    newline_mode: NewlineMode,
 }
 
 #[impl_tid]
@@ -29,14 +42,52 @@ impl<'a, T: ?Sized + 'static> TidAble<'a> for InputStream<Box<T>> {}
 
 impl<'a, T: From<&'a str>> CharStream<T> for InputStream<&'a str> {
     #[inline]
-    fn get_text(&self, start: isize, stop: isize) -> T { self.get_text_inner(start, stop).into() }
+    fn get_text(&self, interval: CharInterval) -> T {
+        self.get_text_inner(interval.a, interval.b).into()
+    }
 }
 
-impl<T: From<D::Owned>, D: ?Sized + InputData> CharStream<T> for InputStream<Box<D>> {
+impl<T: From<String>> CharStream<T> for InputStream<Box<str>> {
     #[inline]
-    fn get_text(&self, start: isize, stop: isize) -> T { self.get_text_owned(start, stop).into() }
+    fn get_text(&self, interval: CharInterval) -> T {
+        self.get_text_boxed(interval.a, interval.b)
+            .to_owned()
+            .into()
+    }
+}
+
+impl<T> CharStream<String> for InputStream<Box<[T]>>
+where
+    [T]: InputData,
+{
+    fn get_text(&self, interval: CharInterval) -> String {
+        self.get_text_boxed(interval.a, interval.b).to_display()
+    }
+}
+
+impl<'b, T> CharStream<Cow<'b, str>> for InputStream<Box<[T]>>
+where
+    [T]: InputData,
+{
+    #[inline]
+    fn get_text(&self, interval: CharInterval) -> Cow<'b, str> {
+        self.get_text_boxed(interval.a, interval.b)
+            .to_display()
+            .into()
+    }
 }
-/// `InputStream` over byte slice
+
+impl<T: Clone> CharStream<Vec<T>> for InputStream<Box<[T]>>
+where
+    [T]: InputData,
+{
+    #[inline]
+    fn get_text(&self, interval: CharInterval) -> Vec<T> {
+        self.get_text_boxed(interval.a, interval.b).to_vec()
+    }
+}
+/// `InputStream` over byte slice, each byte is a code point of token text.
+/// For binary data see `byte_char_stream::ByteCharStream`
 pub type ByteStream<'a> = InputStream<&'a [u8]>;
 /// InputStream which treats the input as a series of Unicode code points that fit into `u8`
 pub type CodePoint8BitCharStream<'a> = InputStream<&'a [u8]>;
@@ -50,8 +101,8 @@ where
     [T]: InputData,
 {
     #[inline]
-    fn get_text(&self, a: isize, b: isize) -> Cow<'a, [T]> {
-        Cow::Borrowed(self.get_text_inner(a, b))
+    fn get_text(&self, interval: CharInterval) -> Cow<'a, [T]> {
+        Cow::Borrowed(self.get_text_inner(interval.a, interval.b))
     }
 }
 
@@ -59,7 +110,9 @@ impl<'a, T> CharStream<String> for InputStream<&'a [T]>
 where
     [T]: InputData,
 {
-    fn get_text(&self, a: isize, b: isize) -> String { self.get_text_inner(a, b).to_display() }
+    fn get_text(&self, interval: CharInterval) -> String {
+        self.get_text_inner(interval.a, interval.b).to_display()
+    }
 }
 
 impl<'a, 'b, T> CharStream<Cow<'b, str>> for InputStream<&'a [T]>
@@ -67,8 +120,10 @@ where
     [T]: InputData,
 {
     #[inline]
-    fn get_text(&self, a: isize, b: isize) -> Cow<'b, str> {
-        self.get_text_inner(a, b).to_display().into()
+    fn get_text(&self, interval: CharInterval) -> Cow<'b, str> {
+        self.get_text_inner(interval.a, interval.b)
+            .to_display()
+            .into()
     }
 }
 
@@ -77,11 +132,13 @@ where
     [T]: InputData,
 {
     #[inline]
-    fn get_text(&self, a: isize, b: isize) -> &'a [T] { self.get_text_inner(a, b) }
+    fn get_text(&self, interval: CharInterval) -> &'a [T] {
+        self.get_text_inner(interval.a, interval.b)
+    }
 }
 
 impl<Data: ?Sized + InputData> InputStream<Box<Data>> {
-    fn get_text_owned(&self, start: isize, stop: isize) -> Data::Owned {
+    fn get_text_boxed(&self, start: isize, stop: isize) -> &Data {
         let start = start as usize;
         let stop = self.data_raw.offset(stop, 1).unwrap_or(stop) as usize;
 
@@ -90,7 +147,6 @@ impl<Data: ?Sized + InputData> InputStream<Box<Data>> {
         } else {
             &self.data_raw[start..]
         }
-        .to_owned()
     }
 
     /// Creates new `InputStream` over owned data   
@@ -99,10 +155,222 @@ impl<Data: ?Sized + InputData> InputStream<Box<Data>> {
             name: "<empty>".to_string(),
             data_raw: data.into(),
             index: 0,
+            line_index: OnceCell::new(),
+            encoding: None,
+            newline_mode: NewlineMode::Lf,
+        }
+    }
+}
+
+impl<T: TryFrom<u32>> InputStream<Box<[T]>>
+where
+    [T]: InputData,
+{
+    /// Creates new `InputStream` over Unicode code points of `text`,
+    /// so indexes are the same as in Java and other ANTLR runtimes.
+    /// `T` is the width of the stored code points,
+    /// e.g. `InputStream::<Box<[u32]>>::from_code_points`.
+    ///
+    /// Panics if some code point does not fit into `T`.
+    pub fn from_code_points(text: &str) -> Self { Self::collect(text.chars()) }
+
+    /// Same as `from_code_points` but for UTF-16 `units`.
+    /// Surrogate pairs are combined into single code point,
+    /// unpaired surrogates are replaced with U+FFFD.
+    pub fn from_utf16(units: &[u16]) -> Self {
+        Self::collect(
+            std::char::decode_utf16(units.iter().copied())
+                .map(|it| it.unwrap_or(REPLACEMENT_CHARACTER)),
+        )
+    }
+
+    fn collect(chars: impl Iterator<Item = char>) -> Self {
+        let data = chars
+            .map(|it| {
+                T::try_from(it as u32).unwrap_or_else(|_| {
+                    panic!(
+                        "code point {:#X} does not fit into {}",
+                        it as u32,
+                        type_name::<T>()
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        Self::new_owned(data.into_boxed_slice())
+    }
+}
+
+/// Encoding of the raw bytes passed to `InputStream::from_bytes_with_encoding`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Encoding {
+    /// UTF-8
+    Utf8,
+    /// UTF-16 little endian, surrogate pairs are combined into single code point
+    Utf16Le,
+    /// UTF-16 big endian, surrogate pairs are combined into single code point
+    Utf16Be,
+    /// ISO-8859-1, each byte is a code point
+    Latin1,
+}
+
+/// What to do with malformed input while decoding
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DecodingErrorPolicy {
+    /// Stop and return `DecodingError`
+    Error,
+    /// Replace malformed sequence with U+FFFD
+    Replace,
+}
+
+/// Malformed input found while decoding bytes into `InputStream`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DecodingError {
+    /// Encoding that was used for decoding
+    pub encoding: Encoding,
+    /// Offset of the first byte of malformed sequence in the original input
+    pub byte_offset: usize,
+}
+
+impl Display for DecodingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid {:?} data at byte offset {}",
+            self.encoding, self.byte_offset
+        )
+    }
+}
+
+impl Error for DecodingError {}
+
+impl InputStream<Box<str>> {
+    /// Creates new `InputStream` by decoding `bytes` in particular `encoding`.
+    ///
+    /// Byte order mark that corresponds to `encoding` is skipped if present.
+    /// Resulting stream behaves exactly like one created from UTF-8 string,
+    /// so indexes are the indexes in the decoded UTF-8 data.
+    pub fn from_bytes_with_encoding(
+        bytes: &[u8], encoding: Encoding, policy: DecodingErrorPolicy,
+    ) -> Result<Self, DecodingError> {
+        let bom: &[u8] = match encoding {
+            Encoding::Utf8 => b"\xEF\xBB\xBF",
+            Encoding::Utf16Le => b"\xFF\xFE",
+            Encoding::Utf16Be => b"\xFE\xFF",
+            Encoding::Latin1 => b"",
+        };
+        let skip = if !bom.is_empty() && bytes.starts_with(bom) {
+            bom.len()
+        } else {
+            0
+        };
+        let text = match encoding {
+            Encoding::Utf8 => decode_utf8(bytes, skip, policy)?,
+            Encoding::Utf16Le => decode_utf16(bytes, skip, policy, u16::from_le_bytes)?,
+            Encoding::Utf16Be => decode_utf16(bytes, skip, policy, u16::from_be_bytes)?,
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+        };
+        let mut input = Self::new_owned(text.into_boxed_str());
+        input.encoding = Some(encoding);
+        Ok(input)
+    }
+
+    /// Creates new `InputStream` by reading all UTF-8 input from `reader`.
+    ///
+    /// To lex input as it arrives, without reading all of it first,
+    /// use `unbuffered_char_stream::UnbufferedCharStream`.
+    pub fn from_reader(mut reader: impl Read) -> io::Result<Self> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let mut input = Self::new_owned(text.into_boxed_str());
+        input.encoding = Some(Encoding::Utf8);
+        Ok(input)
+    }
+
+    /// Same as `from_bytes_with_encoding` but detects encoding by byte order mark.
+    /// Input without byte order mark is considered to be UTF-8.
+    pub fn from_bytes_auto(
+        bytes: &[u8], policy: DecodingErrorPolicy,
+    ) -> Result<Self, DecodingError> {
+        let encoding = if bytes.starts_with(b"\xFF\xFE") {
+            Encoding::Utf16Le
+        } else if bytes.starts_with(b"\xFE\xFF") {
+            Encoding::Utf16Be
+        } else {
+            Encoding::Utf8
+        };
+        Self::from_bytes_with_encoding(bytes, encoding, policy)
+    }
+}
+
+fn decode_utf8(
+    bytes: &[u8], mut pos: usize, policy: DecodingErrorPolicy,
+) -> Result<String, DecodingError> {
+    let mut result = String::with_capacity(bytes.len() - pos);
+    loop {
+        match std::str::from_utf8(&bytes[pos..]) {
+            Ok(valid) => {
+                result.push_str(valid);
+                return Ok(result);
+            }
+            Err(e) => {
+                let valid_up_to = pos + e.valid_up_to();
+                result.push_str(std::str::from_utf8(&bytes[pos..valid_up_to]).unwrap());
+                if policy == DecodingErrorPolicy::Error {
+                    return Err(DecodingError {
+                        encoding: Encoding::Utf8,
+                        byte_offset: valid_up_to,
+                    });
+                }
+                result.push(REPLACEMENT_CHARACTER);
+                pos = valid_up_to + e.error_len().unwrap_or(bytes.len() - valid_up_to);
+            }
         }
     }
 }
 
+fn decode_utf16(
+    bytes: &[u8], skip: usize, policy: DecodingErrorPolicy, to_unit: fn([u8; 2]) -> u16,
+) -> Result<String, DecodingError> {
+    let encoding = if to_unit([0, 1]) == 1 {
+        Encoding::Utf16Be
+    } else {
+        Encoding::Utf16Le
+    };
+    let data = &bytes[skip..];
+    let mut result = String::with_capacity(data.len() / 2);
+    let units = data.chunks_exact(2).map(|it| to_unit([it[0], it[1]]));
+    // offset of each unit is tracked to report malformed surrogates
+    let mut offset = skip;
+    for decoded in std::char::decode_utf16(units) {
+        match decoded {
+            Ok(ch) => {
+                offset += ch.len_utf16() * 2;
+                result.push(ch)
+            }
+            Err(_) if policy == DecodingErrorPolicy::Error => {
+                return Err(DecodingError {
+                    encoding,
+                    byte_offset: offset,
+                })
+            }
+            Err(_) => {
+                offset += 2;
+                result.push(REPLACEMENT_CHARACTER)
+            }
+        }
+    }
+    if data.len() % 2 != 0 {
+        if policy == DecodingErrorPolicy::Error {
+            return Err(DecodingError {
+                encoding,
+                byte_offset: bytes.len() - 1,
+            });
+        }
+        result.push(REPLACEMENT_CHARACTER)
+    }
+    Ok(result)
+}
+
 impl<'a, Data> InputStream<&'a Data>
 where
     Data: ?Sized + InputData,
@@ -121,7 +389,12 @@ where
             &self.data_raw[start..]
         }
     }
+}
 
+impl<'a, Data> InputStream<&'a Data>
+where
+    Data: ?Sized + InputSymbols,
+{
     /// Creates new `InputStream` over borrowed data
     pub fn new(data_raw: &'a Data) -> Self {
         // let data_raw = data_raw.as_ref();
@@ -130,22 +403,84 @@ where
             name: "<empty>".to_string(),
             data_raw,
             index: 0,
+            line_index: OnceCell::new(),
+            encoding: None,
+            newline_mode: NewlineMode::Lf,
             // phantom: Default::default(),
         }
     }
 }
 impl<'a, Data: Deref> InputStream<Data>
 where
-    Data::Target: InputData,
+    Data::Target: InputSymbols,
 {
     /// Resets input stream to start from the beginning of this slice
     #[inline]
     pub fn reset(&mut self) { self.index = 0 }
+
+    /// Sets name returned by `get_source_name`, e.g. file name
+    pub fn with_source_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Encoding input has been decoded from, `None` if stream was created from decoded data
+    pub fn get_source_encoding(&self) -> Option<Encoding> { self.encoding }
+
+    pub(crate) fn data(&self) -> &Data::Target { &self.data_raw }
+}
+
+/// Line/column mapping.
+///
+/// Lines are 1-based and columns are 0-based and counted in chars, same as in tokens produced by lexer.
+/// Line terminators are defined by `with_newline_mode`, by default only `\n` starts a new line,
+/// so both `\n` and `\r\n` terminated lines are supported.
+/// `LineIndex` is built on the first call and then reused.
+/// Positions past the end of input are clamped to the last position.
+impl<Data: Deref<Target = str>> InputStream<Data> {
+    /// Index of line starts of the input,
+    /// also converts positions into char offsets which are used by other runtimes
+    pub fn line_index(&self) -> &LineIndex {
+        self.line_index
+            .get_or_init(|| LineIndex::with_newline_mode(&self.data_raw, self.newline_mode))
+    }
+
+    /// Sets which line terminators start a new line for line/column mapping,
+    /// e.g. `NewlineMode::Any` for input with standalone `\r` line terminators.
+    /// Input itself and its indexes are not changed.
+    ///
+    /// Lexer counts token lines independently, see `BaseLexer::set_newline_mode`.
+    pub fn with_newline_mode(mut self, mode: NewlineMode) -> Self {
+        self.newline_mode = mode;
+        self.line_index = OnceCell::new();
+        self
+    }
+
+    /// Number of lines in the input.
+    /// Input ending with line terminator has additional empty last line.
+    pub fn line_count(&self) -> usize { self.line_index().line_count() }
+
+    /// Converts stream index into `(line, column)`
+    pub fn position_of(&self, index: isize) -> (isize, isize) {
+        self.line_index().position_of_byte(index.max(0) as usize)
+    }
+
+    /// Converts `(line, column)` into stream index.
+    /// Column past the end of line is clamped to the position of its terminator
+    pub fn index_of(&self, line: isize, column: isize) -> isize {
+        self.line_index().byte_offset_of(line, column) as isize
+    }
+
+    /// Text of the `line` without line terminator
+    pub fn line_text(&self, line: isize) -> &str {
+        let text: &str = &self.data_raw;
+        &text[self.line_index().line_range(line)]
+    }
 }
 
 impl<'a, Data: Deref> IntStream for InputStream<Data>
 where
-    Data::Target: InputData,
+    Data::Target: InputSymbols,
 {
     #[inline]
     fn consume(&mut self) {
@@ -154,7 +489,7 @@ where
             // self.current = self.data_raw.deref().item(index).unwrap_or(TOKEN_EOF);
             // Ok(())
         } else {
-            panic!("cannot consume EOF");
+            panic_consume_eof();
         }
     }
 
@@ -167,7 +502,7 @@ where
                 .unwrap_or(crate::int_stream::EOF);
         }
         if offset == 0 {
-            panic!("should not be called with offset 0");
+            return LA_UNDEFINED;
         }
         if offset < 0 {
             offset += 1; // e.g., translate LA(-1) to use offset i=0; then data[p+0-1]
@@ -189,7 +524,7 @@ where
     fn index(&self) -> isize { self.index }
 
     #[inline]
-    fn seek(&mut self, index: isize) { self.index = index }
+    fn seek(&mut self, index: isize) { self.index = index.max(0).min(self.size()) }
 
     #[inline]
     fn size(&self) -> isize { self.data_raw.len() as isize }
@@ -202,9 +537,11 @@ mod test {
     use std::ops::Deref;
 
     use crate::char_stream::CharStream;
-    use crate::int_stream::{IntStream, EOF};
+    use crate::int_stream::{check_stream_contract, IntStream, EOF};
+    use crate::interval_set::CharInterval;
+    use crate::line_index::NewlineMode;
 
-    use super::InputStream;
+    use super::{DecodingError, DecodingErrorPolicy, Encoding, InputStream};
 
     #[test]
     fn test_str_input_stream() {
@@ -223,11 +560,11 @@ mod test {
         assert_eq!(input.index(), 5);
         assert_eq!(input.la(-2), '1' as isize);
         assert_eq!(input.la(2), EOF);
-        assert_eq!(input.get_text(1, 1).deref(), "1");
-        assert_eq!(input.get_text(1, 2).deref(), "1は");
-        assert_eq!(input.get_text(2, 2).deref(), "は");
-        assert_eq!(input.get_text(2, 5).deref(), "は3");
-        assert_eq!(input.get_text(5, 5).deref(), "3");
+        assert_eq!(input.get_text(CharInterval::new(1, 1)).deref(), "1");
+        assert_eq!(input.get_text(CharInterval::new(1, 2)).deref(), "1は");
+        assert_eq!(input.get_text(CharInterval::new(2, 2)).deref(), "は");
+        assert_eq!(input.get_text(CharInterval::new(2, 5)).deref(), "は3");
+        assert_eq!(input.get_text(CharInterval::new(5, 5)).deref(), "3");
     }
 
     #[test]
@@ -240,4 +577,188 @@ mod test {
         let mut input = InputStream::new("は".as_bytes());
         assert_eq!(input.la(1), 227);
     }
+
+    #[test]
+    fn test_utf16_input_stream() {
+        // BOM, "a😀b" with astral char encoded as surrogate pair
+        let bytes = b"\xFF\xFEa\x00\x3D\xD8\x00\xDEb\x00";
+        let mut input = InputStream::from_bytes_auto(bytes, DecodingErrorPolicy::Error).unwrap();
+        let mut expected = InputStream::new("a😀b");
+        assert_eq!(input.size(), expected.size());
+        assert_eq!(input.la(1), 'a' as isize);
+        input.consume();
+        expected.consume();
+        assert_eq!(input.la(1), 0x1F600);
+        assert_eq!(input.index(), expected.index());
+        input.consume();
+        assert_eq!(input.la(1), 'b' as isize);
+        assert_eq!(input.la(-1), 0x1F600);
+        input.seek(1);
+        assert_eq!(input.la(1), 0x1F600);
+        assert_eq!(
+            CharStream::<String>::get_text(&input, CharInterval::new(0, 1)),
+            "a😀"
+        );
+
+        let be = InputStream::from_bytes_with_encoding(
+            b"\xFE\xFF\xD8\x3D\xDE\x00",
+            Encoding::Utf16Be,
+            DecodingErrorPolicy::Error,
+        )
+        .unwrap();
+        assert_eq!(
+            CharStream::<String>::get_text(&be, CharInterval::new(0, 0)),
+            "😀"
+        );
+    }
+
+    #[test]
+    fn test_code_point_input_stream() {
+        let mut input = InputStream::<Box<[u32]>>::from_code_points("x😀é\n");
+        input.seek(1);
+        assert_eq!(input.la(1), 0x1F600);
+        input.consume();
+        assert_eq!(input.index(), 2);
+        assert_eq!(input.la(1), 'é' as isize);
+        assert_eq!(input.size(), 4);
+        assert_eq!(
+            CharStream::<String>::get_text(&input, CharInterval::new(1, 2)),
+            "😀é"
+        );
+        assert_eq!(
+            CharStream::<String>::get_text(&input, CharInterval::new(2, 10)),
+            "é\n"
+        );
+        let code_points: Vec<u32> = input.get_text(CharInterval::new(0, 0));
+        assert_eq!(code_points, vec!['x' as u32]);
+
+        // lone surrogate
+        let mut input = InputStream::<Box<[u16]>>::from_utf16(&[0x61, 0xD83D, 0x62]);
+        input.seek(1);
+        assert_eq!(input.la(1), 0xFFFD);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn test_narrow_code_points() { InputStream::<Box<[u8]>>::from_code_points("V1は3"); }
+
+    #[test]
+    fn test_line_index() {
+        let input = InputStream::new("ab\r\nпри\nx😀y");
+        assert_eq!(input.line_count(), 3);
+        assert_eq!(input.line_text(1), "ab");
+        assert_eq!(input.line_text(2), "при");
+        assert_eq!(input.line_text(3), "x😀y");
+        assert_eq!(input.line_text(10), "x😀y");
+
+        assert_eq!(input.position_of(0), (1, 0));
+        assert_eq!(input.position_of(2), (1, 2));
+        assert_eq!(input.position_of(4), (2, 0));
+        // "и" starts after two 2-byte chars
+        assert_eq!(input.position_of(8), (2, 2));
+        assert_eq!(input.position_of(11), (3, 0));
+        assert_eq!(input.position_of(16), (3, 2));
+        assert_eq!(input.position_of(17), (3, 3));
+        assert_eq!(input.position_of(100), (3, 3));
+
+        assert_eq!(input.index_of(1, 0), 0);
+        assert_eq!(input.index_of(1, 5), 2);
+        assert_eq!(input.index_of(2, 2), 8);
+        assert_eq!(input.index_of(3, 2), 16);
+        assert_eq!(input.index_of(3, 3), 17);
+        assert_eq!(input.index_of(7, 0), 17);
+        for index in &[0, 1, 2, 4, 6, 8, 11, 12, 16, 17] {
+            let (line, column) = input.position_of(*index);
+            assert_eq!(input.index_of(line, column), *index);
+        }
+
+        let input = InputStream::new_owned("a\n".to_owned().into_boxed_str());
+        assert_eq!(input.line_count(), 2);
+        assert_eq!(input.line_text(2), "");
+        assert_eq!(input.position_of(2), (2, 0));
+        assert_eq!(InputStream::new("").line_count(), 1);
+
+        let input = InputStream::new("a\rb").with_newline_mode(NewlineMode::Any);
+        assert_eq!(input.line_text(2), "b");
+        assert_eq!(input.position_of(2), (2, 0));
+    }
+
+    #[test]
+    fn test_decoding_errors() {
+        // lone high surrogate
+        let bytes = b"a\x00\x3D\xD8b\x00";
+        assert_eq!(
+            InputStream::from_bytes_with_encoding(
+                bytes,
+                Encoding::Utf16Le,
+                DecodingErrorPolicy::Error
+            )
+            .unwrap_err(),
+            DecodingError {
+                encoding: Encoding::Utf16Le,
+                byte_offset: 2
+            }
+        );
+        let mut input = InputStream::from_bytes_with_encoding(
+            bytes,
+            Encoding::Utf16Le,
+            DecodingErrorPolicy::Replace,
+        )
+        .unwrap();
+        input.seek(1);
+        assert_eq!(input.la(1), 0xFFFD);
+
+        // 0xE9 is 'é' in Latin-1 but invalid UTF-8
+        let bytes = b"caf\xE9!";
+        assert_eq!(
+            InputStream::from_bytes_auto(bytes, DecodingErrorPolicy::Error).unwrap_err(),
+            DecodingError {
+                encoding: Encoding::Utf8,
+                byte_offset: 3
+            }
+        );
+        let input = InputStream::from_bytes_auto(bytes, DecodingErrorPolicy::Replace).unwrap();
+        assert_eq!(
+            CharStream::<String>::get_text(&input, CharInterval::new(0, 6)),
+            "caf\u{FFFD}!"
+        );
+        let mut input = InputStream::from_bytes_with_encoding(
+            bytes,
+            Encoding::Latin1,
+            DecodingErrorPolicy::Error,
+        )
+        .unwrap();
+        input.seek(3);
+        assert_eq!(input.la(1), 0xE9);
+        assert_eq!(
+            CharStream::<String>::get_text(&input, CharInterval::new(0, 5)),
+            "café!"
+        );
+    }
+
+    #[test]
+    fn test_stream_contract() {
+        let text = "V1は3";
+        let symbols = text.chars().map(|it| it as isize).collect::<Vec<_>>();
+        check_stream_contract(&mut InputStream::new(text), &symbols);
+
+        let code_points = text.chars().map(|it| it as u32).collect::<Vec<_>>();
+        check_stream_contract(&mut InputStream::new(&code_points[..]), &symbols);
+
+        check_stream_contract(
+            &mut InputStream::new_owned(Box::<str>::from(text)),
+            &symbols,
+        );
+        let mut wide = InputStream::<Box<[u16]>>::from_code_points(text);
+        check_stream_contract(&mut wide, &symbols);
+        let units = "a😀b".encode_utf16().collect::<Vec<_>>();
+        let mut astral = InputStream::<Box<[u32]>>::from_utf16(&units);
+        check_stream_contract(&mut astral, &['a' as isize, 0x1F600, 'b' as isize]);
+        check_stream_contract(&mut InputStream::new(""), &[]);
+
+        let mut from_reader = InputStream::from_reader(text.as_bytes()).unwrap();
+        check_stream_contract(&mut from_reader, &symbols);
+        let error = InputStream::from_reader(&b"\xff"[..]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
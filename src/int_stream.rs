@@ -1,4 +1,7 @@
 //! <ost generic stream of symbols
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::errors::ANTLRError;
 
 /// `IntStream::la` must return EOF in the end of stream
 pub const EOF: isize = -1;
@@ -6,6 +9,31 @@ pub const EOF: isize = -1;
 /// A simple stream of symbols whose values are represented as integers. This
 /// interface provides *marked ranges* with support for a minimum level
 /// of buffering necessary to implement arbitrary lookahead during prediction.
+///
+/// ### Boundary contract
+/// All implementations must behave the same way at the boundaries of the stream,
+/// `check_stream_contract` can be used to verify it:
+///  - `consume()` at EOF panics with `ANTLRError::IllegalStateError` (see `panic_consume_eof`)
+///    and does not change the stream.
+///  - `la(0)` returns `LA_UNDEFINED` sentinel.
+///  - `la(i)` pointing at or beyond the end of the stream returns `EOF`,
+///    as does `la(-i)` pointing before its start.
+///  - `seek(index)` clamps `index` to the `0..=EOF position` range,
+///    so seeking past the end positions stream at EOF.
+///  - once stream reaches EOF `index()` stays the same,
+///    for streams that keep all symbols it does not exceed `size()`.
+///
+/// ### Mark/release contract
+///  - `mark()` returns a marker that must be passed to `release` later,
+///    markers are released in reverse order of their creation.
+///  - While a marker is outstanding, `seek` to any index at or after `index()` at the time
+///    of the corresponding `mark()` call must succeed and restore the state of the stream.
+///  - `release` does not move the stream, `la(1)` and `la(-1)` stay the same.
+///  - Once all markers are released, streams that do not keep the whole input
+///    may discard symbols before `index()`, `seek` to them panics with internal error
+///    instead of silently reading wrong symbols, as does `release` of unknown marker.
+///  - Streams that keep the whole input may return any marker and ignore `release`,
+///    `seek` to any index always succeeds for them.
 pub trait IntStream {
     /// Consumes the current symbol in the stream.
     /// Advances this stream to the next element.
@@ -23,7 +51,7 @@ pub trait IntStream {
     /// Note that calling this method does not guarantee that `index()` is
     /// incremented by exactly 1.
     ///
-    /// Allowed to panic if trying to consume EOF
+    /// Panics with `ANTLRError::IllegalStateError` if trying to consume EOF
     fn consume(&mut self);
 
     /// Lookaheads (or loopbacks if `i` is negative)
@@ -33,13 +61,13 @@ pub trait IntStream {
     /// symbol in the stream (which is the next symbol to be consumed). When
     /// {@code i==-1}, this method returns the value of the previously read
     /// symbol in the stream. It is not valid to call this method with
-    /// {@code i==0}, but the specific behavior is unspecified because this
-    /// method is frequently called from performance-critical code.
+    /// {@code i==0}, it returns `LA_UNDEFINED` in that case.
     ///
     /// Note that default Lexer does not call this method with anything other than `-1`
     /// so it can be used for optimizations in downstream implementations.
     ///
-    /// Must return `EOF` if `i` points to position at or beyond the end of the stream
+    /// Must return `EOF` if `i` points to position at or beyond the end of the stream,
+    /// or before its start
     fn la(&mut self, i: isize) -> isize;
 
     /// After this call subsequent calls to seek must succeed if seek index is greater than mark index
@@ -47,7 +75,7 @@ pub trait IntStream {
     /// Returns marker that should be used later by `release` call to release this stream from
     fn mark(&mut self) -> isize;
 
-    /// Releases `marker`
+    /// Releases `marker`, which must be the last one returned by `mark` and not released yet
     fn release(&mut self, marker: isize);
 
     /// Returns current position of the input stream
@@ -57,16 +85,126 @@ pub trait IntStream {
     fn index(&self) -> isize;
     /// Put stream back in state it was when it was in `index` position
     ///
+    /// `index` past the end of the stream is clamped to the position of EOF,
+    /// negative `index` is clamped to 0.
+    ///
     /// Allowed to panic if `index` does not belong to marked region(via `mark`-`release` calls)
     fn seek(&mut self, index: isize);
 
     /// Returns the total number of symbols in the stream.
+    ///
+    /// Streams that do not keep the whole input return number of currently buffered symbols
     fn size(&self) -> isize;
 
     /// Returns name of the source this stream operates over if any
     fn get_source_name(&self) -> String;
 }
 
+/// Value of `IntStream::la(0)`.
+///
+/// For token streams it is `TOKEN_INVALID_TYPE`,
+/// for char streams it can't be distinguished from `'\0'` so `la(0)` should not be called at all.
+pub const LA_UNDEFINED: isize = 0;
+
+/// Panics with `ANTLRError::IllegalStateError`, should be called by `IntStream::consume`
+/// implementations when stream is at EOF
+#[cold]
+#[inline(never)]
+pub fn panic_consume_eof() -> ! { panic!("{}", consume_eof_error()) }
+
+fn consume_eof_error() -> ANTLRError {
+    ANTLRError::IllegalStateError("cannot consume EOF".to_owned())
+}
+
+/// Checks that `stream` follows boundary contract of `IntStream`,
+/// panics with the description of the first violation.
+///
+/// `stream` must be at its start and `symbols` must be all symbols it yields before EOF.
+/// Intended to be used in tests of custom stream implementations.
+pub fn check_stream_contract<S: IntStream + ?Sized>(stream: &mut S, symbols: &[isize]) {
+    let marker = stream.mark();
+    assert_eq!(stream.index(), 0, "stream must be at its start");
+    assert_eq!(stream.la(0), LA_UNDEFINED, "la(0) must return LA_UNDEFINED");
+    assert_eq!(stream.la(-1), EOF, "la(-1) at the start must return EOF");
+
+    let mut indexes = Vec::with_capacity(symbols.len());
+    for (i, &symbol) in symbols.iter().enumerate() {
+        assert_eq!(stream.la(1), symbol, "la(1) at symbol {}", i);
+        let index = stream.index();
+        if let Some(&prev) = indexes.last() {
+            assert!(index > prev, "index must grow after consume, symbol {}", i);
+        }
+        indexes.push(index);
+        stream.consume();
+        assert_eq!(stream.la(-1), symbol, "la(-1) after consuming symbol {}", i);
+    }
+
+    let eof_index = stream.index();
+    for &i in &[1, 2, 10] {
+        assert_eq!(stream.la(i), EOF, "la({}) at the end must return EOF", i);
+    }
+    assert!(eof_index <= stream.size(), "index() must not exceed size()");
+
+    let result = catch_unwind(AssertUnwindSafe(|| stream.consume()));
+    let message = match result {
+        Ok(()) => panic!("consume() at EOF must panic"),
+        Err(payload) => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|&it| it.to_owned()))
+            .unwrap_or_default(),
+    };
+    assert_eq!(
+        message,
+        consume_eof_error().to_string(),
+        "consume() at EOF must panic with IllegalStateError, see `panic_consume_eof`"
+    );
+    assert_eq!(
+        stream.index(),
+        eof_index,
+        "failed consume() must not move the stream"
+    );
+    assert_eq!(stream.la(1), EOF);
+
+    stream.seek(eof_index + 10);
+    assert_eq!(
+        stream.index(),
+        eof_index,
+        "seek past the end must be clamped to EOF"
+    );
+    assert_eq!(stream.la(1), EOF);
+
+    for (&index, &symbol) in indexes.iter().zip(symbols).rev() {
+        stream.seek(index);
+        assert_eq!(stream.index(), index);
+        assert_eq!(stream.la(1), symbol, "la(1) after seek({})", index);
+    }
+    stream.seek(eof_index);
+    stream.seek(-10);
+    assert_eq!(
+        stream.index(),
+        0,
+        "seek before the start must be clamped to 0"
+    );
+
+    let middle = indexes.get(indexes.len() / 2).copied().unwrap_or(eof_index);
+    stream.seek(middle);
+    let (current, previous) = (stream.la(1), stream.la(-1));
+    let inner = stream.mark();
+    stream.seek(eof_index);
+    stream.seek(middle);
+    assert_eq!(
+        stream.la(1),
+        current,
+        "la(1) after seek back to nested marker"
+    );
+    stream.release(inner);
+    stream.release(marker);
+    assert_eq!(stream.index(), middle, "release must not move the stream");
+    assert_eq!(stream.la(1), current, "la(1) after release");
+    assert_eq!(stream.la(-1), previous, "la(-1) after release");
+}
+
 /// Iterator over `IntStream`
 #[derive(Debug)]
 pub struct IterWrapper<'a, T: IntStream>(pub &'a mut T);
@@ -75,11 +213,12 @@ impl<'a, T: IntStream> Iterator for IterWrapper<'a, T> {
     type Item = isize;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let result = self.0.la(1);
-        self.0.consume();
-        match result {
+        match self.0.la(1) {
             EOF => None,
-            x => Some(x),
+            x => {
+                self.0.consume();
+                Some(x)
+            }
         }
     }
 }
@@ -1,8 +1,10 @@
-use std::borrow::Cow;
 use std::borrow::Cow::Borrowed;
+use std::borrow::{Borrow, Cow};
 use std::cmp::{max, min, Ordering};
+use std::fmt::{self, Display, Formatter};
 
-use crate::token::{TOKEN_EOF, TOKEN_EPSILON};
+use crate::token::{Token, TOKEN_EOF, TOKEN_EPSILON};
+use crate::token_stream::TokenStream;
 use crate::vocabulary::{Vocabulary, DUMMY_VOCAB};
 
 /// Represents interval equivalent to `a..=b`
@@ -14,8 +16,6 @@ pub struct Interval {
     pub b: isize,
 }
 
-pub(crate) const INVALID: Interval = Interval { a: -1, b: -2 };
-
 impl Interval {
     /* stop is not included! */
     fn new(a: isize, b: isize) -> Interval { Interval { a, b } }
@@ -77,17 +77,165 @@ impl Interval {
     //    }
 }
 
+macro_rules! typed_interval {
+    ($(#[$attr:meta])* $name:ident, $unit:literal) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        pub struct $name {
+            #[doc = concat!("First ", $unit, " index")]
+            pub a: isize,
+            #[doc = concat!("Last ", $unit, " index, inclusive")]
+            pub b: isize,
+        }
+
+        impl $name {
+            /// Creates interval equivalent to `a..=b`
+            pub fn new(a: isize, b: isize) -> $name { $name { a, b } }
+
+            /// Drops the coordinate kind, for code that works with any `Interval`
+            pub fn untyped(self) -> Interval { Interval { a: self.a, b: self.b } }
+
+            #[doc = concat!("Number of ", $unit, "s in this interval, 0 if it is empty")]
+            pub fn len(&self) -> usize { max(self.b - self.a + 1, 0) as usize }
+
+            /// Returns true if `b < a`, which is how empty input is represented
+            pub fn is_empty(&self) -> bool { self.b < self.a }
+
+            #[doc = concat!("Returns true if ", $unit, " `index` is inside this interval")]
+            pub fn contains(&self, index: isize) -> bool { self.a <= index && index <= self.b }
+
+            /// Smallest interval that covers both `self` and `other`
+            pub fn union(&self, other: &$name) -> $name {
+                $name::from(self.untyped().union(&other.untyped()))
+            }
+
+            /// Does self start completely before other? Disjoint
+            pub fn starts_before_disjoint(&self, other: &$name) -> bool {
+                self.untyped().starts_before_disjoint(&other.untyped())
+            }
+
+            /// Does self start at or before other? Nondisjoint
+            pub fn starts_before_non_disjoint(&self, other: &$name) -> bool {
+                self.untyped().starts_before_non_disjoint(&other.untyped())
+            }
+
+            /// Does self.a start after other.a? May or may not be disjoint
+            pub fn starts_after(&self, other: &$name) -> bool {
+                self.untyped().starts_after(&other.untyped())
+            }
+
+            /// Does self start completely after other? Disjoint
+            pub fn starts_after_disjoint(&self, other: &$name) -> bool {
+                self.untyped().starts_after_disjoint(&other.untyped())
+            }
+
+            /// Does self start after other? NonDisjoint
+            pub fn starts_after_non_disjoint(&self, other: &$name) -> bool {
+                self.untyped().starts_after_non_disjoint(&other.untyped())
+            }
+
+            /// Are both ranges disjoint? I.e., no overlap?
+            pub fn disjoint(&self, other: &$name) -> bool {
+                self.untyped().disjoint(&other.untyped())
+            }
+
+            /// Are two intervals adjacent such as 0..41 and 42..42?
+            pub fn adjacent(&self, other: &$name) -> bool {
+                self.untyped().adjacent(&other.untyped())
+            }
+        }
+
+        impl From<Interval> for $name {
+            fn from(it: Interval) -> Self { $name { a: it.a, b: it.b } }
+        }
+
+        impl From<$name> for Interval {
+            fn from(it: $name) -> Self { it.untyped() }
+        }
+    };
+}
+
+typed_interval!(
+    /// Interval of token indexes, e.g. part of the `TokenStream` covered by a parse tree node.
+    ///
+    /// Distinct type from `CharInterval` so the two can't be mixed up by accident:
+    /// ```compile_fail
+    /// use antlr_rust::interval_set::{CharInterval, TokenInterval};
+    /// use antlr_rust::InputStream;
+    /// use antlr_rust::char_stream::CharStream;
+    ///
+    /// let input = InputStream::new("a b");
+    /// let tokens = TokenInterval::new(0, 1);
+    /// let text: String = input.get_text(tokens);
+    /// ```
+    /// Use [`to_char_interval`](TokenInterval::to_char_interval) to convert it explicitly.
+    TokenInterval,
+    "token"
+);
+
+typed_interval!(
+    /// Interval of char indexes in the `CharStream`, e.g. source text of a token
+    CharInterval,
+    "char"
+);
+
+pub(crate) const INVALID: TokenInterval = TokenInterval { a: -1, b: -2 };
+
+impl TokenInterval {
+    /// Char interval covered by tokens of this interval in `tokens`,
+    /// from the start of the token `a` to the stop of the token `b`.
+    ///
+    /// Empty token interval maps to the empty char interval right before token `a`.
+    /// Returns `None` if tokens are not in `tokens` or don't have char positions,
+    /// e.g. were conjured up during error recovery.
+    pub fn to_char_interval<'input, S>(&self, tokens: &S) -> Option<CharInterval>
+    where
+        S: TokenStream<'input> + ?Sized,
+    {
+        let in_stream = |index: isize| index >= 0 && index < tokens.size();
+        if !in_stream(self.a) {
+            return None;
+        }
+        let start = tokens.get(self.a).borrow().get_start();
+        if start < 0 {
+            return None;
+        }
+        if self.is_empty() {
+            return Some(CharInterval::new(start, start - 1));
+        }
+        if !in_stream(self.b) {
+            return None;
+        }
+        let stop = tokens.get(self.b).borrow().get_stop();
+        if stop < start - 1 {
+            return None;
+        }
+        Some(CharInterval::new(start, stop))
+    }
+}
+
 /// Set of disjoint intervals
 ///
 /// Basically a set of integers but optimized for cases when it is sparse and created by adding
 /// intervals of integers.
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug)]
 pub struct IntervalSet {
     intervals: Vec<Interval>,
     #[allow(missing_docs)]
     pub read_only: bool,
 }
 
+/// Clone is always writable, even if the original set is read-only,
+/// so sets cached by ATN can be cloned and then extended.
+impl Clone for IntervalSet {
+    fn clone(&self) -> Self {
+        IntervalSet {
+            intervals: self.intervals.clone(),
+            read_only: false,
+        }
+    }
+}
+
 #[allow(missing_docs)]
 impl IntervalSet {
     pub fn new() -> IntervalSet {
@@ -97,13 +245,37 @@ impl IntervalSet {
         }
     }
 
+    /// Set of integers from `a` to `b` inclusive
+    pub fn of(a: isize, b: isize) -> IntervalSet {
+        let mut set = IntervalSet::new();
+        set.add_range(a, b);
+        set
+    }
+
     pub fn get_min(&self) -> Option<isize> { self.intervals.first().map(|x| x.a) }
 
+    /// Largest element of the set
+    pub fn get_max(&self) -> Option<isize> { self.intervals.last().map(|x| x.b) }
+
+    /// Whether set has no elements
+    pub fn is_empty(&self) -> bool { self.intervals.is_empty() }
+
+    /// Disjoint, non adjacent intervals of this set in ascending order
+    pub fn get_intervals(&self) -> &[Interval] { &self.intervals }
+
+    /// Elements of this set in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = isize> + '_ {
+        self.intervals.iter().flat_map(|it| it.a..=it.b)
+    }
+
     pub fn add_one(&mut self, _v: isize) { self.add_range(_v, _v) }
 
     pub fn add_range(&mut self, l: isize, h: isize) { self.add_interval(Interval { a: l, b: h }) }
 
     pub fn add_interval(&mut self, added: Interval) {
+        if self.read_only {
+            panic!("can't alter readonly IntervalSet")
+        }
         if added.length() < 0 {
             return;
         }
@@ -153,6 +325,33 @@ impl IntervalSet {
         }
     }
 
+    /// Union of this set and `other`
+    pub fn or(&self, other: &IntervalSet) -> IntervalSet {
+        let mut result = self.clone();
+        result.add_set(other);
+        result
+    }
+
+    /// Intersection of this set and `other`
+    pub fn and(&self, other: &IntervalSet) -> IntervalSet {
+        let mut result = IntervalSet::new();
+        let (mut i, mut j) = (0, 0);
+        while let (Some(left), Some(right)) = (self.intervals.get(i), other.intervals.get(j)) {
+            let a = max(left.a, right.a);
+            let b = min(left.b, right.b);
+            if a <= b {
+                result.intervals.push(Interval::new(a, b));
+            }
+            // interval that ends first can't intersect anything else
+            if left.b < right.b {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        result
+    }
+
     pub fn substract(&mut self, right: &IntervalSet) {
         let result = self;
         let mut result_i = 0usize;
@@ -332,6 +531,36 @@ impl IntervalSet {
     }
 }
 
+/// Formats set as `{1..3, 5}`, single element sets without braces
+impl Display for IntervalSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.intervals.is_empty() {
+            return f.write_str("{}");
+        }
+        let braces = self.length() > 1;
+        if braces {
+            f.write_str("{")?;
+        }
+        for (i, int) in self.intervals.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            if int.a == TOKEN_EOF {
+                f.write_str("<EOF>")?;
+            } else {
+                write!(f, "{}", int.a)?;
+            }
+            if int.b > int.a {
+                write!(f, "..{}", int.b)?;
+            }
+        }
+        if braces {
+            f.write_str("}")?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -371,6 +600,30 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_typed_intervals() {
+        let tokens = TokenInterval::new(2, 4);
+        assert_eq!(tokens.len(), 3);
+        assert!(!tokens.is_empty());
+        assert!(tokens.contains(4) && !tokens.contains(5));
+        assert!(tokens.adjacent(&TokenInterval::new(5, 6)));
+        assert!(tokens.disjoint(&TokenInterval::new(5, 6)));
+        assert_eq!(
+            tokens.union(&TokenInterval::new(7, 8)),
+            TokenInterval::new(2, 8)
+        );
+
+        let empty = CharInterval::new(3, 2);
+        assert!(empty.is_empty());
+        assert_eq!(empty.len(), 0);
+        assert!(INVALID.is_empty());
+
+        let untyped: Interval = tokens.into();
+        assert_eq!(untyped, Interval { a: 2, b: 4 });
+        assert_eq!(TokenInterval::from(untyped), tokens);
+        assert_eq!(CharInterval::from(untyped).untyped(), untyped);
+    }
+
     #[test]
     fn test_substract() {
         let mut set1 = IntervalSet::new();
@@ -384,4 +637,35 @@ mod test {
             &[Interval { a: 1, b: 1 }, Interval { a: 5, b: 5 }]
         );
     }
+
+    #[test]
+    fn test_set_algebra() {
+        let mut set1 = IntervalSet::of(1, 5);
+        set1.add_range(10, 20);
+        let mut set2 = IntervalSet::of(4, 12);
+        set2.add_one(30);
+
+        let union = set1.or(&set2);
+        assert_eq!(
+            union.get_intervals(),
+            &[Interval::new(1, 20), Interval::new(30, 30)]
+        );
+        let intersection = set1.and(&set2);
+        assert_eq!(
+            intersection.get_intervals(),
+            &[Interval::new(4, 5), Interval::new(10, 12)]
+        );
+        assert!(set1.and(&IntervalSet::of(6, 9)).is_empty());
+        assert_eq!(set2.complement(0, 31).to_string(), "{0..3, 13..29, 31}");
+
+        assert_eq!(intersection.iter().collect::<Vec<_>>(), [4, 5, 10, 11, 12]);
+        assert_eq!(intersection.length(), 5);
+        assert_eq!(union.get_min(), Some(1));
+        assert_eq!(union.get_max(), Some(30));
+        assert!(union.contains(20) && !union.contains(21));
+
+        assert_eq!(IntervalSet::new().to_string(), "{}");
+        assert_eq!(IntervalSet::of(-1, -1).to_string(), "<EOF>");
+        assert_eq!(IntervalSet::of(-1, 2).to_string(), "{<EOF>..2}");
+    }
 }
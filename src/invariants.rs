@@ -0,0 +1,191 @@
+//! Checks of internal runtime invariants.
+//!
+//! Runtime code uses `runtime_assert!`, `runtime_panic!`, `runtime_error!` and `runtime_bail!`
+//! instead of bare `assert!`/`panic!` for conditions that can only be violated by a bug in the
+//! runtime itself or by a corrupted serialized ATN. Each of them accepts optional context as
+//! `{ name: value, .. }` block before the message:
+//! ```text
+//! runtime_assert!(dfa.is_precedence_dfa, { decision: dfa.decision }, "not a precedence dfa");
+//! ```
+//! With `debug-diagnostics` feature enabled context values are formatted with `Debug` and
+//! appended to the message, e.g. current decision, ATN state, input index and rule stack.
+//! Otherwise context expressions are not evaluated at all and only a short message is produced,
+//! so it is fine to put expensive expressions there.
+//!
+//! Parts of ANTLR API that are not supported by this runtime use `unimplemented_feature!`
+//! which names missing capability instead of failing with generic `not implemented`.
+#[cfg(feature = "debug-diagnostics")]
+use std::fmt::{Debug, Write};
+
+/// Prefix of all messages about violated runtime invariants
+pub(crate) const INTERNAL_ERROR: &str = "antlr-rust internal error";
+
+/// Appends `context` to `message` as `message [name=value, ..]`
+#[cfg(feature = "debug-diagnostics")]
+pub(crate) fn with_context(mut message: String, context: &[(&str, &dyn Debug)]) -> String {
+    if context.is_empty() {
+        return message;
+    }
+    message.push_str(" [");
+    for (i, (name, value)) in context.iter().enumerate() {
+        if i > 0 {
+            message.push_str(", ");
+        }
+        write!(message, "{}={:?}", name, value).unwrap();
+    }
+    message.push(']');
+    message
+}
+
+/// Message for `unimplemented_feature!`
+pub(crate) fn unsupported(feature: &str) -> String {
+    format!(
+        "{} is not supported by antlr-rust runtime yet, \
+         if you need it please open an issue at {}/issues",
+        feature,
+        env!("CARGO_PKG_REPOSITORY")
+    )
+}
+
+/// Formats message of the violated invariant, with context if `debug-diagnostics` is enabled
+macro_rules! runtime_message {
+    ({ $($name:ident : $value:expr),* $(,)? }, $($msg:tt)+) => {{
+        let message = format!(
+            "{}: {}",
+            $crate::invariants::INTERNAL_ERROR,
+            format_args!($($msg)+)
+        );
+        #[cfg(feature = "debug-diagnostics")]
+        let message = $crate::invariants::with_context(
+            message,
+            &[$((stringify!($name), &$value as &dyn ::std::fmt::Debug)),*],
+        );
+        #[cfg(not(feature = "debug-diagnostics"))]
+        #[allow(unreachable_code)]
+        {
+            // keeps context expressions type checked without evaluating them
+            if false {
+                $(let _ = &$value;)*
+            }
+        }
+        message
+    }};
+}
+
+/// Panics with internal error message and optional `{ name: value, .. }` context
+macro_rules! runtime_panic {
+    ({ $($ctx:tt)* }, $($msg:tt)+) => {
+        panic!("{}", runtime_message!({ $($ctx)* }, $($msg)+))
+    };
+    ($($msg:tt)+) => {
+        runtime_panic!({}, $($msg)+)
+    };
+}
+
+/// Same as `assert!` but with optional `{ name: value, .. }` context before the message
+macro_rules! runtime_assert {
+    ($cond:expr, { $($ctx:tt)* }, $($msg:tt)+) => {
+        if !$cond {
+            runtime_panic!({ $($ctx)* }, $($msg)+)
+        }
+    };
+    ($cond:expr, $($msg:tt)+) => {
+        runtime_assert!($cond, {}, $($msg)+)
+    };
+}
+
+/// Creates `ANTLRError::IllegalStateError` with internal error message
+/// and optional `{ name: value, .. }` context
+macro_rules! runtime_error {
+    ({ $($ctx:tt)* }, $($msg:tt)+) => {
+        $crate::errors::ANTLRError::IllegalStateError(runtime_message!({ $($ctx)* }, $($msg)+))
+    };
+    ($($msg:tt)+) => {
+        runtime_error!({}, $($msg)+)
+    };
+}
+
+/// Returns `runtime_error!` from the current function
+macro_rules! runtime_bail {
+    ($($args:tt)+) => {
+        return Err(runtime_error!($($args)+))
+    };
+}
+
+/// Panics with message naming the `feature` this runtime does not support
+macro_rules! unimplemented_feature {
+    ($feature:expr) => {
+        panic!("{}", $crate::invariants::unsupported($feature))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{catch_unwind, UnwindSafe};
+
+    use crate::errors::ANTLRError;
+
+    fn panic_message(f: impl FnOnce() + UnwindSafe) -> String {
+        let payload = catch_unwind(f).unwrap_err();
+        payload.downcast_ref::<String>().cloned().unwrap()
+    }
+
+    fn bail(state: usize) -> Result<(), ANTLRError> {
+        runtime_bail!({ state: state, rule_stack: ["expr", "atom"] }, "bad state {}", 7);
+    }
+
+    fn error_message(state: usize) -> String {
+        match bail(state) {
+            Err(ANTLRError::IllegalStateError(message)) => message,
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    fn not_evaluated() -> isize { panic!("context must be evaluated only on failure") }
+
+    #[test]
+    fn test_messages() {
+        let broken = false;
+        let message = panic_message(|| runtime_assert!(broken, { decision: 3 }, "broken"));
+        assert!(
+            message.starts_with("antlr-rust internal error: broken"),
+            "{}",
+            message
+        );
+
+        let message = panic_message(|| runtime_panic!("no context {}", 1));
+        assert_eq!(message, "antlr-rust internal error: no context 1");
+
+        runtime_assert!(!broken, { decision: not_evaluated() }, "unreachable");
+
+        assert!(error_message(5).starts_with("antlr-rust internal error: bad state 7"));
+
+        let message = panic_message(|| unimplemented_feature!("Tree::get_payload"));
+        assert!(message.starts_with("Tree::get_payload is not supported"));
+        assert!(message.ends_with("/issues"));
+    }
+
+    #[test]
+    #[cfg(feature = "debug-diagnostics")]
+    fn test_context() {
+        let message = panic_message(
+            || runtime_assert!(false, { decision: 3, input_index: -1 }, "broken {}", "dfa"),
+        );
+        assert_eq!(
+            message,
+            "antlr-rust internal error: broken dfa [decision=3, input_index=-1]"
+        );
+        assert_eq!(
+            error_message(5),
+            "antlr-rust internal error: bad state 7 [state=5, rule_stack=[\"expr\", \"atom\"]]"
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "debug-diagnostics"))]
+    fn test_no_context() {
+        let message = panic_message(|| runtime_assert!(false, { decision: 3 }, "broken"));
+        assert_eq!(message, "antlr-rust internal error: broken");
+        assert_eq!(error_message(5), "antlr-rust internal error: bad state 7");
+    }
+}
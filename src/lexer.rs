@@ -2,21 +2,27 @@
 use std::borrow::Cow::Borrowed;
 use std::borrow::{Borrow, Cow};
 use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 
 use std::rc::Rc;
 
+use crate::atn::ATN;
 use crate::char_stream::{CharStream, InputData};
 use crate::error_listener::{ConsoleErrorListener, ErrorListener};
 use crate::errors::ANTLRError;
-use crate::int_stream::IntStream;
+use crate::int_stream::{IntStream, EOF};
+use crate::interval_set::CharInterval;
 use crate::lexer_atn_simulator::{ILexerATNSimulator, LexerATNSimulator};
+use crate::line_index::{NewlineMode, PositionEncoding};
 use crate::parser::ParserNodeType;
 
-use crate::recognizer::{Actions, Recognizer};
+use crate::recognizer::{Actions, Recognizer, RecognizerRef};
 use crate::rule_context::EmptyContextType;
-use crate::token::TOKEN_INVALID_TYPE;
+use crate::token::{Token, TOKEN_GARBAGE_CHANNEL, TOKEN_INVALID_TYPE};
 use crate::token_factory::{CommonTokenFactory, TokenAware, TokenFactory};
 use crate::token_source::TokenSource;
+use crate::utils::CrateOnly;
+use crate::vocabulary::Vocabulary;
 use std::ops::{Deref, DerefMut};
 
 ///  Lexer functionality required by `LexerATNSimulator` to work properly
@@ -56,7 +62,10 @@ pub trait Lexer<'input>:
     /// Tells lexer to completely ignore and not emit current token.
     fn skip(&mut self);
 
-    #[doc(hidden)]
+    /// Moves lexer back to the start of its input and resets lexing state, including modes.
+    ///
+    /// Input is rewound with `seek(0)`, so for streams that do not keep the whole input
+    /// it panics if the start of the input has already been released.
     fn reset(&mut self);
 
     #[doc(hidden)]
@@ -71,6 +80,81 @@ pub trait LexerRecog<'a, T: Recognizer<'a>>: Actions<'a, T> + Sized + 'static {
     fn before_emit(_lexer: &mut T) {}
 }
 
+/// Receives lexer mode changes, e.g. for tracing.
+/// Registered with `BaseLexer::add_mode_listener`
+pub trait ModeTransitionListener {
+    /// Called when lexer pushed mode `from` to the mode stack and switched to mode `to`
+    fn on_push(&self, _from: usize, _to: usize) {}
+    /// Called when lexer popped mode `to` from the mode stack leaving mode `from`
+    fn on_pop(&self, _from: usize, _to: usize) {}
+    /// Called when lexer switched from mode `from` to `to` without changing mode stack
+    fn on_set(&self, _from: usize, _to: usize) {}
+}
+
+/// Rewrites tokens matched by lexer rules right before they are created,
+/// e.g. to turn identifiers into contextual keywords or to move tokens of a particular dialect
+/// to another channel without regenerating the lexer.
+/// Set with `BaseLexer::set_token_transformer`, closures with the same signature as
+/// `transform` can be used directly.
+pub trait TokenTransformer<'input, TF: TokenFactory<'input>> {
+    /// Called with type, channel and text of the token that is about to be emitted,
+    /// after grammar actions and `LexerRecog::before_emit`.
+    /// `token_type` and `channel` can be changed in place,
+    /// returning `Some` replaces token text.
+    fn transform(
+        &mut self, token_type: &mut isize, channel: &mut isize, text: &TF::Data,
+    ) -> Option<<TF::Data as ToOwned>::Owned>;
+}
+
+impl<'input, TF, F> TokenTransformer<'input, TF> for F
+where
+    TF: TokenFactory<'input>,
+    F: FnMut(&mut isize, &mut isize, &TF::Data) -> Option<<TF::Data as ToOwned>::Owned>,
+{
+    fn transform(
+        &mut self, token_type: &mut isize, channel: &mut isize, text: &TF::Data,
+    ) -> Option<<TF::Data as ToOwned>::Owned> {
+        self(token_type, channel, text)
+    }
+}
+
+/// What lexer does with input that no lexer rule matches, see `LexerErrorStrategy`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LexerRecovery {
+    /// Drops input consumed while attempting to match the failed token
+    /// together with the char matching failed at, same as Java runtime. Default
+    Skip,
+    /// Drops only the first char of the failed token, lexing resumes right after it
+    SkipChar,
+    /// Emits the first char of the failed token as a token of `token_type` on `channel`,
+    /// lexing resumes right after it.
+    /// Token type should not clash with token types of the grammar,
+    /// so parser reports it as unexpected input.
+    ErrorToken {
+        /// Type of the emitted token
+        token_type: isize,
+        /// Channel of the emitted token
+        channel: isize,
+    },
+}
+
+/// Decides how lexer recovers when no lexer rule matches the input, so tokenization continues.
+/// Set with `BaseLexer::set_error_strategy`, `LexerRecovery` itself can be used as a strategy
+/// that always recovers the same way.
+///
+/// Error listeners are notified about the error before the strategy is called.
+pub trait LexerErrorStrategy {
+    /// Returns how to recover from `error`
+    fn recover(&mut self, error: &ANTLRError) -> LexerRecovery;
+}
+
+impl LexerErrorStrategy for LexerRecovery {
+    fn recover(&mut self, _error: &ANTLRError) -> LexerRecovery { *self }
+}
+
+type RuleAction<'input, L> = Rc<dyn Fn(&mut L, isize) + 'input>;
+type RulePredicate<'input, L> = Rc<dyn Fn(&mut L, isize) -> bool + 'input>;
+
 /// Default implementation of Lexer
 ///
 /// Public fields in this struct are intended to be used by embedded actions
@@ -89,7 +173,12 @@ pub struct BaseLexer<
 
     factory: &'input TF,
 
-    error_listeners: RefCell<Vec<Box<dyn ErrorListener<'input, Self>>>>,
+    error_listeners: RefCell<Vec<Rc<dyn ErrorListener + 'input>>>,
+    mode_listeners: Vec<Rc<dyn ModeTransitionListener + 'input>>,
+    rule_actions: HashMap<isize, RuleAction<'input, Self>>,
+    rule_predicates: HashMap<isize, RulePredicate<'input, Self>>,
+    token_transformer: Option<Box<dyn TokenTransformer<'input, TF> + 'input>>,
+    error_strategy: Box<dyn LexerErrorStrategy + 'input>,
 
     pub token_start_char_index: isize,
     pub token_start_line: isize,
@@ -105,14 +194,18 @@ pub struct BaseLexer<
     mode_stack: Vec<usize>,
     /// Mode lexer is currently in
     pub mode: usize,
+    token_start_mode: usize,
     /// Make it `Some` to override text for token that is currently being generated by lexer
     pub text: Option<<TF::Data as ToOwned>::Owned>,
+    scan_targets: Option<Vec<isize>>,
 }
 
 #[derive(Debug)]
 pub(crate) struct LexerPosition {
     pub(crate) line: Cell<isize>,
     pub(crate) char_position_in_line: Cell<isize>,
+    pub(crate) encoding: Cell<PositionEncoding>,
+    pub(crate) newline_mode: Cell<NewlineMode>,
 }
 
 impl<'input, T, Input, TF> Deref for BaseLexer<'input, T, Input, TF>
@@ -149,7 +242,12 @@ where
         rule_index: isize,
         action_index: isize,
     ) -> bool {
-        <T as Actions<'input, Self>>::sempred(_localctx, rule_index, action_index, self)
+        match self.rule_predicates.get(&rule_index).cloned() {
+            Some(predicate) => predicate(self, action_index),
+            None => {
+                <T as Actions<'input, Self>>::sempred(_localctx, rule_index, action_index, self)
+            }
+        }
     }
 
     fn action(
@@ -158,7 +256,47 @@ where
         rule_index: isize,
         action_index: isize,
     ) {
-        <T as Actions<'input, Self>>::action(_localctx, rule_index, action_index, self)
+        match self.rule_actions.get(&rule_index).cloned() {
+            Some(action) => action(self, action_index),
+            None => <T as Actions<'input, Self>>::action(_localctx, rule_index, action_index, self),
+        }
+    }
+
+    fn get_serialized_atn(&self) -> &str { self.recog.get_serialized_atn() }
+}
+
+impl<'input, T, Input, TF> RecognizerRef for BaseLexer<'input, T, Input, TF>
+where
+    T: LexerRecog<'input, Self> + 'static,
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    fn rule_names(&self) -> &[&str] { self.recog.get_rule_names() }
+
+    fn vocabulary(&self) -> Option<&dyn Vocabulary> { Some(self.recog.get_vocabulary()) }
+
+    fn grammar_file_name(&self) -> &str { self.recog.get_grammar_file_name() }
+
+    fn atn(&self) -> Option<&ATN> { None }
+
+    fn input_text(&self, start: isize, stop: isize) -> String {
+        self.input
+            .as_ref()
+            .map(|input| TF::get_data(input.get_text(CharInterval::new(start, stop))).to_display())
+            .unwrap_or_default()
+    }
+
+    fn report_diagnostic(&self, msg: String) {
+        for listener in self.error_listeners.borrow().iter() {
+            listener.syntax_error(
+                self,
+                None,
+                self.token_start_line,
+                self.token_start_column,
+                &msg,
+                None,
+            )
+        }
     }
 }
 
@@ -191,8 +329,19 @@ where
 
     fn emit(&mut self) {
         <T as LexerRecog<Self>>::before_emit(self);
+        if let Some(mut transformer) = self.token_transformer.take() {
+            let (mut token_type, mut channel) = (self.token_type, self.channel);
+            let text = transformer.transform(&mut token_type, &mut channel, &self.get_text());
+            self.token_type = token_type;
+            self.channel = channel;
+            if text.is_some() {
+                self.text = text;
+            }
+            self.token_transformer = Some(transformer);
+        }
         let stop = self.get_char_index() - 1;
-        let token = self.factory.create(
+        let (end_line, end_column) = (self.get_line(), self.get_char_position_in_line());
+        let token = self.factory.create_with_end(
             Some(self.input.as_mut().unwrap()),
             self.token_type,
             self.text.take(),
@@ -201,21 +350,30 @@ where
             stop,
             self.token_start_line,
             self.token_start_column,
+            end_line,
+            end_column,
         );
+        let inner: &TF::Inner = token.borrow();
+        inner.set_mode(self.token_start_mode, CrateOnly);
         self.emit_token(token);
     }
 
     fn emit_eof(&mut self) {
-        let token = self.factory.create(
+        let (line, column) = (self.get_line(), self.get_char_position_in_line());
+        let token = self.factory.create_with_end(
             None::<&mut Input>,
             super::int_stream::EOF,
             None,
             LEXER_DEFAULT_TOKEN_CHANNEL,
             self.get_char_index(),
             self.get_char_index() - 1,
-            self.get_line(),
-            self.get_char_position_in_line(),
+            line,
+            column,
+            line,
+            column,
         );
+        let inner: &TF::Inner = token.borrow();
+        inner.set_mode(self.mode, CrateOnly);
         self.emit_token(token)
     }
 
@@ -232,11 +390,10 @@ where
             .map(|it| Borrowed(it.borrow()))
             // .unwrap_or("")
             .unwrap_or_else(|| {
-                let text = self
-                    .input
-                    .as_ref()
-                    .unwrap()
-                    .get_text(self.token_start_char_index, self.get_char_index() - 1);
+                let text = self.input.as_ref().unwrap().get_text(CharInterval::new(
+                    self.token_start_char_index,
+                    self.get_char_index() - 1,
+                ));
                 TF::get_data(text)
             })
     }
@@ -244,18 +401,261 @@ where
     /// Used from lexer actions to override text of the token that will be emitted next
     pub fn set_text(&mut self, _text: <TF::Data as ToOwned>::Owned) { self.text = Some(_text); }
 
+    /// Makes lexer skip input until it can match a token of one of `start_token_types` or EOF.
+    ///
+    /// Skipped input is not reported as error but returned as a single token on
+    /// `TOKEN_GARBAGE_CHANNEL` before the found token, so nothing is lost. Useful to parse
+    /// islands of a known language embedded in arbitrary text,
+    /// see also `CommonTokenStream::scan_for`.
+    ///
+    /// Skipped input is lexed as usual, dropping tokens of other types and chars that
+    /// can't be matched, so a target token is only found where a regular token could start
+    /// and the time is proportional to the length of skipped input.
+    ///
+    /// After each attempted match lexer mode, mode stack, position and pending token state
+    /// are restored, and mode listeners are not notified about mode changes made during scan.
+    /// Custom actions and predicates embedded in grammar are still executed for attempted
+    /// tokens and their effects are not rolled back, so they should not have side effects
+    /// beyond the lexer state.
+    pub fn scan_for(&mut self, start_token_types: &[isize]) {
+        self.scan_targets = Some(start_token_types.to_vec());
+    }
+
+    /// Moves lexer right after `token` it has produced before, or to the start of input
+    /// if `token` is `None`, so tokens after it are going to be produced again.
+    ///
+    /// Lexer mode is not changed.
+    pub fn resume_after(&mut self, token: Option<&TF::Inner>) {
+        let (start, line, column) = match token {
+            Some(token) => (token.get_start(), token.get_line(), token.get_column()),
+            None => (0, 1, 0),
+        };
+        let input = self.input.as_mut().unwrap();
+        input.seek(start);
+        self.current_pos.line.set(line);
+        self.current_pos.char_position_in_line.set(column);
+        if let Some(token) = token {
+            let interpreter = self.interpreter.as_ref().unwrap();
+            while input.index() <= token.get_stop() && input.la(1) != EOF {
+                interpreter.consume(input);
+            }
+        }
+        self.hit_eof = false;
+        self.token = None;
+    }
+
+    // Skips input until a token of one of `targets` types or EOF can be matched there,
+    // returns garbage token with skipped input, if any.
+    fn scan(&mut self, targets: &[isize]) -> Option<TF::Tok> {
+        let start = self.get_char_index();
+        let (start_line, start_column) = (self.get_line(), self.get_char_position_in_line());
+        // mode changes of the attempted tokens are rolled back, so listeners must not see them,
+        // custom actions still run, see `scan_for`
+        let mode_listeners = std::mem::take(&mut self.mode_listeners);
+        let (mode, mode_stack) = (self.mode, self.mode_stack.clone());
+        while self.input().la(1) != EOF {
+            let index = self.get_char_index();
+            let (line, column) = (self.get_line(), self.get_char_position_in_line());
+            self.token = None;
+            self.text = None;
+            self.channel = LEXER_DEFAULT_TOKEN_CHANNEL;
+            self.token_type = TOKEN_INVALID_TYPE;
+            self.token_start_char_index = index;
+            self.token_start_line = line;
+            self.token_start_column = column;
+            self.token_start_mode = mode;
+
+            let mut interpreter = self.interpreter.take().unwrap();
+            let result = interpreter.match_token(mode, self);
+            self.interpreter = Some(interpreter);
+            let found = match result {
+                Ok(_) if self.token_type != TOKEN_INVALID_TYPE => {
+                    Some(targets.contains(&self.token_type))
+                }
+                Ok(ttype) => Some(targets.contains(&ttype)),
+                Err(_) => None,
+            };
+
+            self.mode = mode;
+            self.mode_stack.clone_from(&mode_stack);
+            // input matched by a token of other type is skipped as a whole,
+            // so each char is scanned once like in regular lexing
+            if found == Some(false) && self.get_char_index() > index {
+                continue;
+            }
+            self.input().seek(index);
+            self.current_pos.line.set(line);
+            self.current_pos.char_position_in_line.set(column);
+            if found == Some(true) {
+                break;
+            }
+            let interpreter = self.interpreter.as_ref().unwrap();
+            interpreter.consume(self.input.as_mut().unwrap());
+        }
+        self.mode_listeners = mode_listeners;
+        self.token_type = TOKEN_INVALID_TYPE;
+        self.token = None;
+        self.text = None;
+
+        let stop = self.get_char_index() - 1;
+        if stop < start {
+            return None;
+        }
+        let (end_line, end_column) = (self.get_line(), self.get_char_position_in_line());
+        let token = self.factory.create_with_end(
+            Some(self.input.as_mut().unwrap()),
+            TOKEN_INVALID_TYPE,
+            None,
+            TOKEN_GARBAGE_CHANNEL,
+            start,
+            stop,
+            start_line,
+            start_column,
+            end_line,
+            end_column,
+        );
+        let inner: &TF::Inner = token.borrow();
+        inner.set_mode(mode, CrateOnly);
+        Some(token)
+    }
+
+    // Recovers from `err` of the match attempt started at `attempt` index, line and column,
+    // returns token type to continue with.
+    #[cold]
+    #[inline(never)]
+    fn recover(&mut self, err: ANTLRError, attempt: (isize, isize, isize)) -> isize {
+        notify_listeners(&self.error_listeners.borrow(), &err, self);
+        let recovery = self.error_strategy.recover(&err);
+        let interpreter = self.interpreter.as_mut().unwrap();
+        let input = self.input.as_mut().unwrap();
+        if recovery == LexerRecovery::Skip {
+            interpreter.recover(err, input);
+            return LEXER_SKIP;
+        }
+        let (index, line, column) = attempt;
+        input.seek(index);
+        self.current_pos.line.set(line);
+        self.current_pos.char_position_in_line.set(column);
+        if input.la(1) != EOF {
+            interpreter.consume(input);
+        }
+        match recovery {
+            LexerRecovery::ErrorToken {
+                token_type,
+                channel,
+            } => {
+                // text accumulated by `more` is replaced with input text
+                self.text = None;
+                self.token_type = token_type;
+                self.channel = channel;
+                token_type
+            }
+            _ => LEXER_SKIP,
+        }
+    }
+
     // fn get_all_tokens(&mut self) -> Vec<TF::Tok> { unimplemented!() }
 
     // fn get_char_error_display(&self, _c: char) -> String { unimplemented!() }
 
-    /// Add error listener
-    pub fn add_error_listener(&mut self, listener: Box<dyn ErrorListener<'input, Self>>) {
+    /// Add error listener.
+    /// The same listener can be added to the parser as well
+    pub fn add_error_listener(&mut self, listener: Rc<dyn ErrorListener + 'input>) {
         self.error_listeners.borrow_mut().push(listener);
     }
 
     /// Remove and drop all error listeners
     pub fn remove_error_listeners(&mut self) { self.error_listeners.borrow_mut().clear(); }
 
+    /// Modes saved by `push_mode`, the last one will be restored by the next `pop_mode`.
+    /// Current mode is not included, it is available as `mode`
+    pub fn mode_stack(&self) -> &[usize] { &self.mode_stack }
+
+    /// Replaces saved modes, e.g. to restore lexer mode state saved from `mode_stack`
+    /// together with `mode` before lexing next chunk of input.
+    /// Mode listeners are not notified.
+    pub fn set_mode_stack(&mut self, mode_stack: &[usize]) {
+        self.mode_stack.clear();
+        self.mode_stack.extend_from_slice(mode_stack);
+    }
+
+    /// Name of the current mode, if recognizer provides mode names
+    pub fn current_mode_name(&self) -> Option<&str> {
+        self.recog.get_mode_names().get(self.mode).copied()
+    }
+
+    /// Adds listener that will be notified about all mode changes
+    pub fn add_mode_listener(&mut self, listener: Rc<dyn ModeTransitionListener + 'input>) {
+        self.mode_listeners.push(listener);
+    }
+
+    /// Remove and drop all mode listeners
+    pub fn remove_mode_listeners(&mut self) { self.mode_listeners.clear(); }
+
+    /// Registers `action` to be executed for actions of rule `rule_index`
+    /// instead of the ones generated from the grammar, if any.
+    ///
+    /// Closure receives index of the action within the rule, same as generated
+    /// `<rule>_action` methods, so hand-written lexers can attach behavior without codegen.
+    /// Only actions present in ATN are executed, so rule must have at least an empty action.
+    pub fn register_action(
+        &mut self, rule_index: isize, action: impl Fn(&mut Self, isize) + 'input,
+    ) {
+        self.rule_actions.insert(rule_index, Rc::new(action));
+    }
+
+    /// Registers `predicate` to be evaluated for semantic predicates of rule `rule_index`
+    /// instead of the ones generated from the grammar, if any.
+    ///
+    /// Closure receives index of the predicate within the rule.
+    pub fn register_sempred(
+        &mut self, rule_index: isize, predicate: impl Fn(&mut Self, isize) -> bool + 'input,
+    ) {
+        self.rule_predicates.insert(rule_index, Rc::new(predicate));
+    }
+
+    /// Sets `transformer` that can rewrite type, channel and text of each token
+    /// matched by lexer rules before it is emitted, replacing previous one, if any.
+    ///
+    /// EOF, tokens set directly via `token` field and skipped input of `scan_for`
+    /// are not passed to the transformer.
+    pub fn set_token_transformer(
+        &mut self, transformer: impl TokenTransformer<'input, TF> + 'input,
+    ) {
+        self.token_transformer = Some(Box::new(transformer));
+    }
+
+    /// Removes token transformer, so tokens are emitted as lexer rules matched them
+    pub fn remove_token_transformer(&mut self) { self.token_transformer = None; }
+
+    /// Sets how lexer recovers from input no lexer rule matches,
+    /// default is `LexerRecovery::Skip`
+    pub fn set_error_strategy(&mut self, strategy: impl LexerErrorStrategy + 'input) {
+        self.error_strategy = Box::new(strategy);
+    }
+
+    /// Sets units token columns and `get_char_position_in_line` are counted in,
+    /// e.g. `PositionEncoding::Utf16` for Language Server Protocol.
+    /// Default is `PositionEncoding::CodePoints`.
+    ///
+    /// Should be set before lexing, columns of already produced tokens are not changed.
+    pub fn set_position_encoding(&mut self, encoding: PositionEncoding) {
+        self.current_pos.encoding.set(encoding)
+    }
+
+    /// Units token columns are counted in
+    pub fn get_position_encoding(&self) -> PositionEncoding { self.current_pos.encoding.get() }
+
+    /// Sets which line terminators start a new line for token lines and columns.
+    /// Default is `NewlineMode::Lf`, with `NewlineMode::Any` standalone `\r` also ends a line
+    /// and `\r` of `\r\n` does not advance the column. Token indexes are not affected.
+    pub fn set_newline_mode(&mut self, mode: NewlineMode) {
+        self.current_pos.newline_mode.set(mode)
+    }
+
+    /// Line terminators that start a new line for token lines and columns
+    pub fn get_newline_mode(&self) -> NewlineMode { self.current_pos.newline_mode.get() }
+
     /// Creates new lexer instance
     pub fn new_base_lexer(
         input: Input,
@@ -268,13 +668,20 @@ where
             input: Some(input),
             recog,
             factory,
-            error_listeners: RefCell::new(vec![Box::new(ConsoleErrorListener {})]),
+            error_listeners: RefCell::new(vec![Rc::new(ConsoleErrorListener {})]),
+            mode_listeners: Vec::new(),
+            rule_actions: HashMap::new(),
+            rule_predicates: HashMap::new(),
+            token_transformer: None,
+            error_strategy: Box::new(LexerRecovery::Skip),
             token_start_char_index: 0,
             token_start_line: 0,
             token_start_column: 0,
             current_pos: Rc::new(LexerPosition {
                 line: Cell::new(1),
                 char_position_in_line: Cell::new(0),
+                encoding: Cell::new(PositionEncoding::CodePoints),
+                newline_mode: Cell::new(NewlineMode::Lf),
             }),
             token_type: super::token::TOKEN_INVALID_TYPE,
             text: None,
@@ -284,6 +691,8 @@ where
             //            token_factory_source_pair: None,
             mode_stack: Vec::new(),
             mode: self::LEXER_DEFAULT_MODE,
+            token_start_mode: self::LEXER_DEFAULT_MODE,
+            scan_targets: None,
         };
         let pos = lexer.current_pos.clone();
         lexer.interpreter.as_mut().unwrap().current_pos = pos;
@@ -318,6 +727,13 @@ where
                 self.emit_eof();
                 break;
             }
+            if let Some(targets) = self.scan_targets.take() {
+                if let Some(garbage) = self.scan(&targets) {
+                    self.hit_eof = self.input().la(1) == EOF;
+                    self.token = Some(garbage);
+                    break;
+                }
+            }
             self.token = None;
             self.channel = LEXER_DEFAULT_TOKEN_CHANNEL;
             self.token_start_column = self
@@ -329,24 +745,22 @@ where
             self.text = None;
             let index = self.input().index();
             self.token_start_char_index = index;
+            self.token_start_mode = self.mode;
 
             'inner: loop {
                 self.token_type = TOKEN_INVALID_TYPE;
+                let attempt = (
+                    self.input().index(),
+                    self.get_line(),
+                    self.get_char_position_in_line(),
+                );
                 // detach from self, to allow self to be passed deeper
                 let mut interpreter = self.interpreter.take().unwrap();
                 //                    let mut input = self.input.take().unwrap();
                 let result = interpreter.match_token(self.mode, self);
                 self.interpreter = Some(interpreter);
 
-                let ttype = result.unwrap_or_else(|err| {
-                    //                            println!("error, recovering");
-                    notify_listeners(&mut self.error_listeners.borrow_mut(), &err, self);
-                    self.interpreter
-                        .as_mut()
-                        .unwrap()
-                        .recover(err, self.input.as_mut().unwrap());
-                    LEXER_SKIP
-                });
+                let ttype = result.unwrap_or_else(|err| self.recover(err, attempt));
                 //                    self.input = Some(input)
 
                 if self.input().la(1) == super::int_stream::EOF {
@@ -366,6 +780,13 @@ where
                 }
             }
 
+            // text accumulated by `more` until the end of input is dropped,
+            // so EOF is always empty and located right after the last char
+            if self.token_type == EOF {
+                self.emit_eof();
+                break;
+            }
+
             if self.token.is_none() {
                 self.emit();
                 break;
@@ -403,24 +824,22 @@ where
 #[cold]
 #[inline(never)]
 fn notify_listeners<'input, T, Input, TF>(
-    liseners: &mut Vec<Box<dyn ErrorListener<'input, BaseLexer<'input, T, Input, TF>>>>,
-    e: &ANTLRError,
+    liseners: &[Rc<dyn ErrorListener + 'input>], e: &ANTLRError,
     lexer: &BaseLexer<'input, T, Input, TF>,
 ) where
     T: LexerRecog<'input, BaseLexer<'input, T, Input, TF>> + 'static,
     Input: CharStream<TF::From>,
     TF: TokenFactory<'input>,
 {
-    let inner = lexer
-        .input
-        .as_ref()
-        .unwrap()
-        .get_text(lexer.token_start_char_index, lexer.get_char_index());
+    let inner = lexer.input.as_ref().unwrap().get_text(CharInterval::new(
+        lexer.token_start_char_index,
+        lexer.get_char_index(),
+    ));
     let text = format!(
         "token recognition error at: '{}'",
         TF::get_data(inner).to_display()
     );
-    for listener in liseners.iter_mut() {
+    for listener in liseners.iter() {
         listener.syntax_error(
             lexer,
             None,
@@ -445,26 +864,59 @@ where
     fn set_channel(&mut self, v: isize) { self.channel = v; }
 
     fn push_mode(&mut self, m: usize) {
-        self.mode_stack.push(self.mode);
+        let from = self.mode;
+        self.mode_stack.push(from);
         self.mode = m;
+        self.mode_listeners
+            .iter()
+            .for_each(|it| it.on_push(from, m));
     }
 
     fn pop_mode(&mut self) -> Option<usize> {
+        let from = self.mode;
         self.mode_stack.pop().map(|mode| {
             self.mode = mode;
+            self.mode_listeners
+                .iter()
+                .for_each(|it| it.on_pop(from, mode));
             mode
         })
     }
 
     fn set_type(&mut self, t: isize) { self.token_type = t; }
 
-    fn set_mode(&mut self, m: usize) { self.mode = m; }
+    fn set_mode(&mut self, m: usize) {
+        let from = self.mode;
+        self.mode = m;
+        self.mode_listeners.iter().for_each(|it| it.on_set(from, m));
+    }
 
     fn more(&mut self) { self.set_type(LEXER_MORE) }
 
     fn skip(&mut self) { self.set_type(LEXER_SKIP) }
 
-    fn reset(&mut self) { unimplemented!() }
+    fn reset(&mut self) {
+        if let Some(input) = self.input.as_mut() {
+            input.seek(0);
+        }
+        self.token = None;
+        self.token_type = TOKEN_INVALID_TYPE;
+        self.channel = LEXER_DEFAULT_TOKEN_CHANNEL;
+        self.token_start_char_index = 0;
+        self.token_start_line = 0;
+        self.token_start_column = 0;
+        self.text = None;
+        self.hit_eof = false;
+        self.mode = self::LEXER_DEFAULT_MODE;
+        self.token_start_mode = self::LEXER_DEFAULT_MODE;
+        self.mode_stack.clear();
+        self.scan_targets = None;
+        self.current_pos.line.set(1);
+        self.current_pos.char_position_in_line.set(0);
+        if let Some(interpreter) = self.interpreter.as_mut() {
+            interpreter.reset();
+        }
+    }
 
     fn get_interpreter(&self) -> Option<&LexerATNSimulator> { self.interpreter.as_deref() }
 }
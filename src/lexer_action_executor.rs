@@ -44,6 +44,8 @@ impl LexerActionExecutor {
         new
     }
 
+    pub(crate) fn get_lexer_actions(&self) -> &[LexerAction] { &self.lexer_actions }
+
     pub fn fix_offset_before_match(mut self, offset: isize) -> LexerActionExecutor {
         for action in self.lexer_actions.iter_mut() {
             match action {
@@ -20,6 +20,7 @@ use crate::errors::ANTLRError::LexerNoAltError;
 use crate::int_stream::{IntStream, EOF};
 use crate::lexer::{Lexer, LexerPosition, LEXER_MAX_CHAR_VALUE, LEXER_MIN_CHAR_VALUE};
 use crate::lexer_action_executor::LexerActionExecutor;
+use crate::line_index::{NewlineMode, PositionEncoding};
 use crate::prediction_context::EMPTY_PREDICTION_CONTEXT;
 use crate::prediction_context::{
     PredictionContext, PredictionContextCache, PREDICTION_CONTEXT_EMPTY_RETURN_STATE,
@@ -85,9 +86,9 @@ impl ILexerATNSimulator for LexerATNSimulator {
             self.start_index = lexer.input().index();
             self.prev_accept.reset();
             let temp = self.base.decision_to_dfa.clone();
-            let dfa = temp
-                .get(mode)
-                .ok_or_else(|| ANTLRError::IllegalStateError("invalid mode".into()))?;
+            let dfa = temp.get(mode).ok_or_else(
+                || runtime_error!({ input_index: self.start_index }, "invalid lexer mode {}", mode),
+            )?;
             let dfa = dfa.upgradable_read();
 
             let s0 = dfa.s0;
@@ -109,13 +110,23 @@ impl ILexerATNSimulator for LexerATNSimulator {
 
     fn get_line(&self) -> isize { self.current_pos.line.get() }
 
-    fn set_line(&mut self, line: isize) { self.current_pos.char_position_in_line.set(line) }
+    fn set_line(&mut self, line: isize) { self.current_pos.line.set(line) }
 
     fn consume<T: IntStream + ?Sized>(&self, _input: &mut T) {
         let ch = _input.la(1);
-        self.current_pos.line.set( self.current_pos.line.get() + 1);
-        if ch == '\n' as isize {
-            self.current_pos.char_position_in_line.set(0);
+        let pos = &self.current_pos;
+        // with `NewlineMode::Any` `\r` ends a line unless it is a part of `\r\n`
+        let cr = ch == '\r' as isize && pos.newline_mode.get() == NewlineMode::Any;
+        if ch == '\n' as isize || (cr && _input.la(2) != '\n' as isize) {
+            pos.line.set(pos.line.get() + 1);
+            pos.char_position_in_line.set(0);
+        } else if !cr {
+            // symbols that are not chars, e.g. bytes of binary input, take one unit
+            let width = std::char::from_u32(ch as u32)
+                .filter(|_| ch >= 0)
+                .map_or(1, |c| pos.encoding.get().len_of(c));
+            pos.char_position_in_line
+                .set(pos.char_position_in_line.get() + width as isize);
         }
         _input.consume();
     }
@@ -157,6 +168,8 @@ impl LexerATNSimulator {
             current_pos: Rc::new(LexerPosition {
                 line: Cell::new(0),
                 char_position_in_line: Cell::new(0),
+                encoding: Cell::new(PositionEncoding::CodePoints),
+                newline_mode: Cell::new(NewlineMode::Lf),
             }),
             mode: 0,
             prev_accept: SimState::new(),
@@ -179,7 +192,7 @@ impl LexerATNSimulator {
         let start_state = *atn
             .mode_to_start_state
             .get(self.mode)
-            .ok_or_else(|| ANTLRError::IllegalStateError("invalid mode".into()))?;
+            .ok_or_else(|| runtime_error!("invalid lexer mode {}", self.mode))?;
 
         let _old_mode = self.mode;
         let mut s0_closure = self.compute_start_state(atn.states[start_state].as_ref(), lexer);
@@ -577,7 +590,10 @@ impl LexerATNSimulator {
             TransitionType::TRANSITION_WILDCARD => {}
             TransitionType::TRANSITION_NOTSET => {}
             TransitionType::TRANSITION_PRECEDENCE => {
-                panic!("precedence predicates are not supposed to be in lexer");
+                runtime_panic!(
+                    { state: _config.get_state() },
+                    "precedence predicates are not supposed to be in lexer"
+                );
             }
         }
 
@@ -648,7 +664,11 @@ impl LexerATNSimulator {
 // where
     //     V: DerefMut<Target = Vec<DFAState>>,
     {
-        assert!(!_configs.has_semantic_context());
+        runtime_assert!(
+            !_configs.has_semantic_context(),
+            { decision: dfa.decision },
+            "lexer DFA state can't have semantic context"
+        );
         let mut dfastate = DFAState::new_dfastate(usize::MAX, _configs);
         let rule_index = dfastate
             .configs //_configs
@@ -700,6 +720,17 @@ impl LexerATNSimulator {
     /// Returns current DFA for particular lexer mode
     pub fn get_dfa_for_mode(&self, mode: usize) -> &RwLock<DFA> { &self.decision_to_dfa()[mode] }
 
+    /// Drops all DFA states that were built for lexer modes.
+    ///
+    /// Note that DFA is usually shared between all lexer instances of the same grammar,
+    /// so this affects them too.
+    pub fn clear_dfa(&self) {
+        for (mode, dfa) in self.decision_to_dfa().iter().enumerate() {
+            let mut dfa = dfa.write();
+            *dfa = DFA::new(self.base.atn.clone(), dfa.atn_start_state, mode as isize);
+        }
+    }
+
     // fn get_token_name(&self, _tt: isize) -> String { unimplemented!() }
 
     // fn reset_sim_state(_sim: &mut SimState) { unimplemented!() }
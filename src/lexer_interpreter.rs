@@ -0,0 +1,252 @@
+//! Lexer that runs lexer grammar ATN without code generation
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use better_any::{Tid, TidAble};
+use parking_lot::RwLock;
+
+use crate::atn::ATN;
+use crate::atn_type::ATNType;
+use crate::char_stream::CharStream;
+use crate::dfa::DFA;
+use crate::int_stream::IntStream;
+use crate::lexer::{BaseLexer, LexerRecog};
+use crate::lexer_atn_simulator::LexerATNSimulator;
+use crate::prediction_context::PredictionContextCache;
+use crate::recognizer::Actions;
+use crate::token_factory::{CommonTokenFactory, TokenFactory};
+use crate::token_source::TokenSource;
+use crate::vocabulary::Vocabulary;
+
+/// Lexer grammar loaded at runtime, everything `LexerInterpreter` needs besides the input.
+///
+/// Clones share DFA cache, so one grammar instance should be cloned for all interpreters
+/// of the same grammar to not repeat ATN simulation for the same input.
+/// Names are `'static` same as in generated lexers,
+/// names loaded at runtime can be made static with `Box::leak`.
+#[derive(Clone)]
+pub struct LexerGrammar {
+    atn: Arc<ATN>,
+    decision_to_dfa: Arc<Vec<RwLock<DFA>>>,
+    shared_context_cache: Arc<PredictionContextCache>,
+    vocabulary: Arc<dyn Vocabulary>,
+    grammar_file_name: String,
+    rule_names: &'static [&'static str],
+    channel_names: &'static [&'static str],
+    mode_names: &'static [&'static str],
+}
+
+impl Debug for LexerGrammar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LexerGrammar")
+            .field("grammar_file_name", &self.grammar_file_name)
+            .field("vocabulary", &self.vocabulary)
+            .field("rule_names", &self.rule_names)
+            .field("channel_names", &self.channel_names)
+            .field("mode_names", &self.mode_names)
+            .finish()
+    }
+}
+
+impl LexerGrammar {
+    /// Creates grammar from deserialized lexer `atn` and its token `vocabulary`.
+    ///
+    /// Panics if `atn` is not a lexer ATN.
+    pub fn new(atn: Arc<ATN>, vocabulary: impl Vocabulary + 'static) -> Self {
+        runtime_assert!(
+            atn.grammar_type == ATNType::LEXER,
+            "the ATN must be a lexer ATN"
+        );
+        let decision_to_dfa = (0..atn.decision_to_state.len())
+            .map(|i| RwLock::new(DFA::new(atn.clone(), atn.get_decision_state(i), i as isize)))
+            .collect::<Vec<_>>();
+        Self {
+            atn,
+            decision_to_dfa: Arc::new(decision_to_dfa),
+            shared_context_cache: Arc::new(PredictionContextCache::new()),
+            vocabulary: Arc::new(vocabulary),
+            grammar_file_name: String::new(),
+            rule_names: &[],
+            channel_names: &[],
+            mode_names: &[],
+        }
+    }
+
+    /// Sets name of the file grammar was loaded from, used in error reporting
+    pub fn with_grammar_file_name(mut self, grammar_file_name: impl Into<String>) -> Self {
+        self.grammar_file_name = grammar_file_name.into();
+        self
+    }
+
+    /// Sets rule names, indexed by rule index
+    pub fn with_rule_names(mut self, rule_names: &'static [&'static str]) -> Self {
+        self.rule_names = rule_names;
+        self
+    }
+
+    /// Sets channel names, indexed by channel
+    pub fn with_channel_names(mut self, channel_names: &'static [&'static str]) -> Self {
+        self.channel_names = channel_names;
+        self
+    }
+
+    /// Sets mode names, indexed by mode
+    pub fn with_mode_names(mut self, mode_names: &'static [&'static str]) -> Self {
+        self.mode_names = mode_names;
+        self
+    }
+
+    /// ATN of this grammar
+    pub fn get_atn(&self) -> &ATN { &self.atn }
+
+    /// Token vocabulary of this grammar
+    pub fn get_vocabulary(&self) -> &dyn Vocabulary { &*self.vocabulary }
+
+    /// Name of the file grammar was loaded from
+    pub fn get_grammar_file_name(&self) -> &str { &self.grammar_file_name }
+
+    /// Rule names, indexed by rule index
+    pub fn get_rule_names(&self) -> &[&str] { self.rule_names }
+
+    /// Channel names, indexed by channel
+    pub fn get_channel_names(&self) -> &[&str] { self.channel_names }
+
+    /// Mode names, indexed by mode
+    pub fn get_mode_names(&self) -> &[&str] { self.mode_names }
+}
+
+impl<'input, Input, TF> Actions<'input, BaseLexer<'input, LexerGrammar, Input, TF>> for LexerGrammar
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    fn get_rule_names(&self) -> &[&str] { self.rule_names }
+
+    fn get_vocabulary(&self) -> &dyn Vocabulary { &*self.vocabulary }
+
+    fn get_grammar_file_name(&self) -> &str { &self.grammar_file_name }
+
+    fn get_atn(&self) -> &ATN { &self.atn }
+
+    fn get_mode_names(&self) -> &[&str] { self.mode_names }
+}
+
+impl<'input, Input, TF> LexerRecog<'input, BaseLexer<'input, LexerGrammar, Input, TF>>
+    for LexerGrammar
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+}
+
+/// Lexer that tokenizes input according to `LexerGrammar` loaded at runtime,
+/// e.g. for grammar development tools or tests that don't want to run ANTLR tool.
+///
+/// Behaves like generated lexer except that grammar actions are not executed
+/// and semantic predicates are considered true, since their code is not available.
+/// They can be replaced with closures via `BaseLexer::register_action`
+/// and `BaseLexer::register_sempred`.
+/// ```text
+/// let atn = Arc::new(ATNDeserializer::new(None).deserialize(serialized_atn.chars()));
+/// let grammar = LexerGrammar::new(atn, vocabulary).with_rule_names(&RULE_NAMES);
+/// let mut lexer = LexerInterpreter::new(grammar.clone(), InputStream::new(text));
+/// let token = lexer.next_token();
+/// ```
+#[derive(Tid)]
+pub struct LexerInterpreter<'input, Input, TF = CommonTokenFactory>
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    base: BaseLexer<'input, LexerGrammar, Input, TF>,
+}
+
+impl<'input, Input, TF> Debug for LexerInterpreter<'input, Input, TF>
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LexerInterpreter")
+            .field("grammar", self.get_grammar())
+            .field("mode", &self.base.mode)
+            .finish()
+    }
+}
+
+impl<'input, Input, TF> Deref for LexerInterpreter<'input, Input, TF>
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    type Target = BaseLexer<'input, LexerGrammar, Input, TF>;
+
+    fn deref(&self) -> &Self::Target { &self.base }
+}
+
+impl<'input, Input, TF> DerefMut for LexerInterpreter<'input, Input, TF>
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut self.base }
+}
+
+impl<'input, Input, TF> LexerInterpreter<'input, Input, TF>
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    /// Creates interpreter of `grammar` over `input` that creates tokens with `tf`
+    pub fn new_with_token_factory(grammar: LexerGrammar, input: Input, tf: &'input TF) -> Self {
+        let interpreter = LexerATNSimulator::new_lexer_atnsimulator(
+            grammar.atn.clone(),
+            grammar.decision_to_dfa.clone(),
+            grammar.shared_context_cache.clone(),
+        );
+        Self {
+            base: BaseLexer::new_base_lexer(input, interpreter, grammar, tf),
+        }
+    }
+
+    /// Grammar this lexer interprets
+    pub fn get_grammar(&self) -> &LexerGrammar { &self.base }
+}
+
+impl<'input, Input, TF> LexerInterpreter<'input, Input, TF>
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+    &'input TF: Default,
+{
+    /// Creates interpreter of `grammar` over `input` with default token factory
+    pub fn new(grammar: LexerGrammar, input: Input) -> Self {
+        Self::new_with_token_factory(grammar, input, <&TF as Default>::default())
+    }
+}
+
+impl<'input, Input, TF> TokenSource<'input> for LexerInterpreter<'input, Input, TF>
+where
+    Input: CharStream<TF::From>,
+    TF: TokenFactory<'input>,
+{
+    type TF = TF;
+
+    fn next_token(&mut self) -> <Self::TF as TokenFactory<'input>>::Tok { self.base.next_token() }
+
+    fn try_next_token(&mut self) -> io::Result<<Self::TF as TokenFactory<'input>>::Tok> {
+        self.base.try_next_token()
+    }
+
+    fn get_line(&self) -> isize { self.base.get_line() }
+
+    fn get_char_position_in_line(&self) -> isize { self.base.get_char_position_in_line() }
+
+    fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { self.base.get_input_stream() }
+
+    fn get_source_name(&self) -> String { self.base.get_source_name() }
+
+    fn get_token_factory(&self) -> &'input Self::TF { self.base.get_token_factory() }
+}
@@ -1,12 +1,12 @@
 #![crate_type = "lib"]
-//#![feature(try_blocks)]
+#![feature(try_blocks)]
 //#![feature(nll)]
 //#![feature(is_sorted)]
-//#![feature(cell_update)]
-//#![feature(get_mut_unchecked)]
-//#![feature(specialization)]
-//#![feature(coerce_unsized)]
-//#![feature(associated_type_defaults)]
+#![feature(cell_update)]
+#![feature(get_mut_unchecked)]
+#![feature(specialization)]
+#![feature(coerce_unsized)]
+#![feature(associated_type_defaults)]
 //#![feature(generic_associated_types)]
 //#![feature(crate_visibility_modifier)]
 // #![feature(generic_associated_types)]
@@ -60,6 +60,15 @@
 //! types that contains "owned" in their name or constructor function like `OwningTokenFactory`
 //! or `InputStream::new_owned()`.
 //!
+//! ### Allocation
+//!
+//! By default every token is allocated separately. For large inputs [`ArenaFactory`] can be
+//! used to allocate all tokens of the parse in a single arena, which improves locality
+//! and frees all of them at once when the factory is dropped.
+//! Parse tree nodes are reference counted, so for huge inputs that need the tree
+//! to be kept in memory, it can be converted to [`CompactTree`] which stores all nodes
+//! in a few contiguous arrays and shares token data with the token stream.
+//!
 //! ### Visitors and Listeners
 //!
 //! Parse listeners must outlive `'input` because they have to be stored inside of the parser.
@@ -79,6 +88,7 @@
 //! [`CharStream`]: crate::char_stream::CharStream
 //! [`TokenFactory`]: crate::token_factory::TokenFactory
 //! [`ArenaFactory`]: crate::token_factory::ArenaFactory
+//! [`CompactTree`]: crate::compact_tree::CompactTree
 //! [`Token`]: crate::token::Token
 //! [`TokenStream`]: crate::token_stream::TokenStream
 //! [`ParserRuleContext`]: crate::parser_rule_context::ParserRuleContext
@@ -98,6 +108,9 @@ pub use better_any::{impl_tid, type_id, Tid, TidAble, TidExt};
 #[doc(inline)]
 pub use error_strategy::{BailErrorStrategy, DefaultErrorStrategy, ErrorStrategy};
 
+#[doc(inline)]
+pub use char_streams::CharStreams;
+#[doc(inline)]
 pub use input_stream::InputStream;
 
 #[doc(inline)]
@@ -113,6 +126,9 @@ pub use prediction_context::PredictionContextCache;
 #[doc(inline)]
 pub use prediction_mode::PredictionMode;
 
+#[macro_use]
+mod invariants;
+
 #[doc(hidden)]
 pub mod atn_config;
 #[doc(hidden)]
@@ -128,7 +144,9 @@ pub mod token_factory;
 pub mod atn_deserialization_options;
 #[doc(hidden)]
 pub mod atn_state;
+pub mod case_changing_char_stream;
 pub mod char_stream;
+pub mod char_streams;
 #[doc(hidden)]
 pub mod dfa_state;
 #[doc(hidden)]
@@ -137,11 +155,12 @@ pub mod parser_rule_context;
 mod prediction_context;
 #[doc(hidden)]
 pub mod semantic_context;
-mod token_source;
+pub mod token_source;
 pub mod token_stream;
 //pub mod trace_listener;
 #[doc(hidden)]
 pub mod dfa;
+pub mod dfa_cache;
 #[doc(hidden)]
 pub mod transition;
 pub mod tree;
@@ -152,26 +171,48 @@ pub mod atn;
 pub mod atn_config_set;
 #[doc(hidden)]
 pub mod atn_deserializer;
+pub mod byte_char_stream;
+pub mod cancellation;
 pub mod common_token_stream;
+pub mod compact_tree;
 mod dfa_serializer;
 pub mod error_listener;
 pub mod error_strategy;
 pub mod errors;
+pub mod indentation_token_source;
 pub mod input_stream;
 pub mod lexer;
 #[doc(hidden)]
 pub mod lexer_action_executor;
 pub mod lexer_atn_simulator;
+pub mod lexer_interpreter;
+pub mod line_index;
+pub mod multi_channel_token_stream;
 pub mod parser;
 pub mod parser_atn_simulator;
+pub mod parser_interpreter;
 mod prediction_mode;
 pub mod token;
+pub mod token_buffer;
+pub mod tree_builder;
+pub mod tree_edits;
 pub mod trees;
+pub mod unbuffered_char_stream;
 mod utils;
 //pub mod tokenstream_rewriter_test;
-mod atn_type;
+#[doc(hidden)]
+pub mod atn_type;
 // mod context_factory;
 pub mod rule_context;
+pub mod rule_profiler;
+pub mod runtime_info;
 pub mod vocabulary;
+
+#[cfg(feature = "bench")]
+pub mod bench_support;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+#[cfg(feature = "interchange")]
+pub mod tree_interchange;
 //#[cfg(test)]
 // tests are either integration tests in "tests" foulder or unit tests in some modules
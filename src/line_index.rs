@@ -0,0 +1,356 @@
+//! Conversions between line/column positions and offsets in text
+use std::ops::Range;
+
+/// Units columns are counted in
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum PositionEncoding {
+    /// Unicode code points, i.e. Rust `char`s, default for lexers
+    CodePoints,
+    /// UTF-16 code units, used by Language Server Protocol and JavaScript
+    Utf16,
+    /// UTF-8 bytes
+    Utf8,
+}
+
+impl Default for PositionEncoding {
+    fn default() -> Self { PositionEncoding::CodePoints }
+}
+
+/// Which line terminators start a new line
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum NewlineMode {
+    /// Only `\n`, so `\r\n` is supported as well, but `\r` is an ordinary char
+    Lf,
+    /// `\n`, `\r\n` and standalone `\r`, `\r\n` is a single line terminator
+    /// and `\r` in it does not advance the column
+    Any,
+}
+
+impl Default for NewlineMode {
+    fn default() -> Self { NewlineMode::Lf }
+}
+
+impl PositionEncoding {
+    /// Number of units `c` takes in this encoding
+    pub fn len_of(self, c: char) -> usize {
+        match self {
+            PositionEncoding::CodePoints => 1,
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf8 => c.len_utf8(),
+        }
+    }
+}
+
+/// Index of line starts in a text that converts `(line, column)` positions,
+/// as reported by tokens and error listeners, into byte or char offsets and back.
+///
+/// Lines are 1-based and columns are 0-based and counted in chars, same as in tokens produced
+/// by lexer. By default only `\n` starts a new line, so both `\n` and `\r\n` terminated lines
+/// are supported, use `NewlineMode::Any` to support standalone `\r` as well.
+/// Column past the end of line is clamped to the position of its terminator,
+/// positions past the end of input are clamped to the last position.
+///
+/// Index does not keep the text, only line starts and positions of non-ASCII chars,
+/// so it can be built once and kept next to the parse result, e.g. by a language server.
+/// `InputStream<&str>` builds it on demand, see `InputStream::line_index`.
+/// ```
+/// use antlr_rust::line_index::LineIndex;
+/// let index = LineIndex::new("a\r\nпри x");
+/// // e.g. for `token.get_line()` and `token.get_column()`
+/// assert_eq!(index.byte_offset_of(2, 4), 10);
+/// assert_eq!(index.char_offset_of(2, 4), 7);
+/// assert_eq!(index.position_of_byte(10), (2, 4));
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LineIndex {
+    // byte offset of each line start
+    line_starts: Vec<usize>,
+    // byte offset of each line end, without line terminator
+    line_ends: Vec<usize>,
+    // number of chars before each line start
+    char_starts: Vec<usize>,
+    // non-ASCII chars of each line as their byte offset from line start and UTF-8 length
+    wide_chars: Vec<Vec<(usize, usize)>>,
+    len: usize,
+    char_len: usize,
+}
+
+impl LineIndex {
+    /// Builds index of `text` with `NewlineMode::Lf`
+    pub fn new(text: &str) -> Self { Self::with_newline_mode(text, NewlineMode::Lf) }
+
+    /// Builds index of `text` with lines terminated according to `mode`
+    pub fn with_newline_mode(text: &str, mode: NewlineMode) -> Self {
+        let mut index = LineIndex {
+            line_starts: vec![0],
+            line_ends: Vec::new(),
+            char_starts: vec![0],
+            wide_chars: vec![Vec::new()],
+            len: text.len(),
+            char_len: 0,
+        };
+        let mut line_start = 0;
+        for (offset, c) in text.char_indices() {
+            index.char_len += 1;
+            let standalone_cr = mode == NewlineMode::Any
+                && c == '\r'
+                && text.as_bytes().get(offset + 1) != Some(&b'\n');
+            if c == '\n' || standalone_cr {
+                let line = &text[line_start..offset];
+                let end = line_start + line.strip_suffix('\r').unwrap_or(line).len();
+                line_start = offset + 1;
+                index.line_ends.push(end);
+                index.line_starts.push(line_start);
+                index.char_starts.push(index.char_len);
+                index.wide_chars.push(Vec::new());
+            } else if !c.is_ascii() {
+                let wide_chars = index.wide_chars.last_mut().unwrap();
+                wide_chars.push((offset - line_start, c.len_utf8()));
+            }
+        }
+        index.line_ends.push(text.len());
+        index
+    }
+
+    /// Number of lines in the text.
+    /// Text ending with line terminator has additional empty last line.
+    pub fn line_count(&self) -> usize { self.line_starts.len() }
+
+    /// Length of the text in bytes
+    pub fn len(&self) -> usize { self.len }
+
+    /// Returns true if text is empty
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Length of the text in chars
+    pub fn char_len(&self) -> usize { self.char_len }
+
+    /// Byte range of the `line` without line terminator
+    pub fn line_range(&self, line: isize) -> Range<usize> {
+        let line = self.line(line);
+        self.line_starts[line]..self.line_ends[line]
+    }
+
+    /// Converts byte offset into `(line, column)`.
+    /// Offset in the middle of a char is considered to be the offset of that char.
+    pub fn position_of_byte(&self, offset: usize) -> (isize, isize) {
+        let offset = offset.min(self.len);
+        let line = line_of(&self.line_starts, offset);
+        let column = self.column_of(line, offset - self.line_starts[line]);
+        (line as isize + 1, column as isize)
+    }
+
+    /// Converts char offset into `(line, column)`
+    pub fn position_of_char(&self, offset: usize) -> (isize, isize) {
+        let offset = offset.min(self.char_len);
+        let line = line_of(&self.char_starts, offset);
+        (
+            line as isize + 1,
+            (offset - self.char_starts[line]) as isize,
+        )
+    }
+
+    /// Converts `(line, column)` into byte offset
+    pub fn byte_offset_of(&self, line: isize, column: isize) -> usize {
+        if line > self.line_count() as isize {
+            return self.len;
+        }
+        let line = self.line(line);
+        let column = column.max(0) as usize;
+        let offset = self.line_starts[line] + self.column_offset(line, column);
+        // position of `\n` itself is kept, others past the end are clamped to the terminator
+        let last = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.len, |&next| next - 1);
+        if offset <= last {
+            offset
+        } else {
+            self.line_ends[line]
+        }
+    }
+
+    /// Converts `(line, column)` into char offset
+    pub fn char_offset_of(&self, line: isize, column: isize) -> usize {
+        let offset = self.byte_offset_of(line, column);
+        self.char_offset_of_byte(offset)
+    }
+
+    /// Converts byte offset into char offset
+    pub fn char_offset_of_byte(&self, offset: usize) -> usize {
+        let (line, column) = self.position_of_byte(offset);
+        self.char_starts[line as usize - 1] + column as usize
+    }
+
+    /// Converts char offset into byte offset
+    pub fn byte_offset_of_char(&self, offset: usize) -> usize {
+        let (line, column) = self.position_of_char(offset);
+        let line = line as usize - 1;
+        self.line_starts[line] + self.column_offset(line, column as usize)
+    }
+
+    /// Converts char `column` in `line` into column in `encoding`
+    pub fn encode_column(&self, line: isize, column: isize, encoding: PositionEncoding) -> isize {
+        let line = self.line(line);
+        let column = column.max(0) as usize;
+        let offset = self.column_offset(line, column);
+        let encoded = match encoding {
+            PositionEncoding::CodePoints => column,
+            PositionEncoding::Utf8 => offset,
+            PositionEncoding::Utf16 => {
+                let surrogate_pairs = self.wide_chars[line]
+                    .iter()
+                    .take_while(|&&(start, _)| start < offset)
+                    .filter(|&&(_, len)| len == 4)
+                    .count();
+                column + surrogate_pairs
+            }
+        };
+        encoded as isize
+    }
+
+    /// Converts `column` in `encoding` in `line` into char column.
+    /// Column in the middle of a char is considered to be the column of that char.
+    pub fn decode_column(&self, line: isize, column: isize, encoding: PositionEncoding) -> isize {
+        let line = self.line(line);
+        let column = column.max(0) as usize;
+        let decoded = match encoding {
+            PositionEncoding::CodePoints => column,
+            PositionEncoding::Utf8 => self.column_of(line, column),
+            PositionEncoding::Utf16 => {
+                let mut surrogate_pairs = 0;
+                let mut extra_bytes = 0;
+                for &(start, len) in &self.wide_chars[line] {
+                    let char_column = start - extra_bytes;
+                    if char_column + surrogate_pairs >= column {
+                        break;
+                    }
+                    if len == 4 {
+                        if char_column + surrogate_pairs + 1 == column {
+                            // low surrogate
+                            return char_column as isize;
+                        }
+                        surrogate_pairs += 1;
+                    }
+                    extra_bytes += len - 1;
+                }
+                column - surrogate_pairs
+            }
+        };
+        decoded as isize
+    }
+
+    fn line(&self, line: isize) -> usize { (line.max(1) as usize).min(self.line_count()) - 1 }
+
+    // char column of the char at byte `offset` from the start of `line`
+    fn column_of(&self, line: usize, offset: usize) -> usize {
+        let mut column = offset;
+        for &(start, len) in &self.wide_chars[line] {
+            if start >= offset {
+                break;
+            }
+            column -= (len - 1).min(offset - start);
+        }
+        column
+    }
+
+    // byte offset from the start of `line` of the char at `column`, not clamped to the line
+    fn column_offset(&self, line: usize, column: usize) -> usize {
+        let mut offset = column;
+        for &(start, len) in &self.wide_chars[line] {
+            if start >= offset {
+                break;
+            }
+            offset += len - 1;
+        }
+        offset
+    }
+}
+
+// index of the last line that starts at or before `offset`
+fn line_of(starts: &[usize], offset: usize) -> usize {
+    match starts.binary_search(&offset) {
+        Ok(line) => line,
+        Err(next) => next - 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LineIndex, NewlineMode, PositionEncoding};
+
+    #[test]
+    fn test_conversions() {
+        let text = "ab\r\nпри\nx😀y";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 3);
+        assert_eq!(index.char_len(), text.chars().count());
+        assert_eq!(&text[index.line_range(1)], "ab");
+        assert_eq!(&text[index.line_range(2)], "при");
+        assert_eq!(&text[index.line_range(7)], "x😀y");
+
+        for (char_offset, (byte_offset, _)) in text.char_indices().enumerate() {
+            let position = index.position_of_byte(byte_offset);
+            assert_eq!(index.position_of_char(char_offset), position);
+            assert_eq!(index.byte_offset_of(position.0, position.1), byte_offset);
+            assert_eq!(index.char_offset_of(position.0, position.1), char_offset);
+            assert_eq!(index.char_offset_of_byte(byte_offset), char_offset);
+            assert_eq!(index.byte_offset_of_char(char_offset), byte_offset);
+        }
+        assert_eq!(index.position_of_byte(6), (2, 1));
+        // middle of 'р'
+        assert_eq!(index.position_of_byte(7), (2, 1));
+        assert_eq!(index.position_of_byte(100), (3, 3));
+        assert_eq!(index.position_of_char(100), (3, 3));
+        assert_eq!(index.byte_offset_of(1, 10), 2);
+        assert_eq!(index.byte_offset_of(0, 1), 1);
+        assert_eq!(index.byte_offset_of(4, 0), text.len());
+        assert_eq!(index.byte_offset_of_char(100), text.len());
+
+        let index = LineIndex::new("a\n");
+        assert_eq!(index.line_count(), 2);
+        assert_eq!(index.position_of_byte(2), (2, 0));
+        assert_eq!(LineIndex::new("").line_count(), 1);
+    }
+
+    #[test]
+    fn test_column_encodings() {
+        let text = "a\nж😀b😀";
+        let index = LineIndex::new(text);
+        let encoded = |column, encoding| index.encode_column(2, column, encoding);
+        let decoded = |column, encoding| index.decode_column(2, column, encoding);
+        for &(column, utf16, utf8) in &[(0, 0, 0), (1, 1, 2), (2, 3, 6), (3, 4, 7), (4, 6, 11)] {
+            assert_eq!(encoded(column, PositionEncoding::CodePoints), column);
+            assert_eq!(encoded(column, PositionEncoding::Utf16), utf16);
+            assert_eq!(encoded(column, PositionEncoding::Utf8), utf8);
+            assert_eq!(decoded(utf16, PositionEncoding::Utf16), column);
+            assert_eq!(decoded(utf8, PositionEncoding::Utf8), column);
+        }
+        // middle of '😀'
+        assert_eq!(decoded(2, PositionEncoding::Utf16), 1);
+        assert_eq!(decoded(4, PositionEncoding::Utf8), 1);
+        assert_eq!(PositionEncoding::Utf16.len_of('😀'), 2);
+    }
+
+    #[test]
+    fn test_newline_modes() {
+        let text = "a\rb\r\nc\n\r";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 3);
+        assert_eq!(&text[index.line_range(1)], "a\rb");
+        assert_eq!(index.position_of_byte(5), (2, 0));
+
+        let index = LineIndex::with_newline_mode(text, NewlineMode::Any);
+        assert_eq!(index.line_count(), 5);
+        let lines = (1..=5)
+            .map(|line| &text[index.line_range(line)])
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["a", "b", "c", "", ""]);
+        assert_eq!(index.position_of_byte(2), (2, 0));
+        assert_eq!(index.position_of_byte(4), (2, 2));
+        assert_eq!(index.position_of_byte(6), (3, 1));
+        assert_eq!(index.position_of_byte(7), (4, 0));
+        assert_eq!(index.byte_offset_of(1, 5), 1);
+        assert_eq!(index.byte_offset_of(5, 0), 8);
+    }
+}
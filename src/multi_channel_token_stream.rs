@@ -0,0 +1,213 @@
+//! `TokenStream` over a changeable set of channels
+use std::borrow::Borrow;
+
+use better_any::{Tid, TidAble};
+
+use crate::errors::ANTLRError;
+use crate::int_stream::{IntStream, IterWrapper, EOF};
+use crate::interval_set::TokenInterval;
+use crate::token::{Token, TOKEN_DEFAULT_CHANNEL, TOKEN_INVALID_TYPE};
+use crate::token_factory::TokenFactory;
+use crate::token_source::TokenSource;
+use crate::token_stream::{infallible, FetchedTokens, TokenStream, UnbufferedTokenStream};
+
+/// Token stream that produces tokens from several channels, skipping tokens on other ones.
+///
+/// Unlike `CommonTokenStream` set of enabled channels can be changed in the middle of parsing,
+/// e.g. from parser actions, for grammars that interleave languages on different channels.
+/// Change affects only tokens that are not consumed yet, so the next token becomes
+/// the first token after the last consumed one that is on one of the enabled channels.
+/// ```text
+/// parser.input.enable_channel(TEMPLATE_CHANNEL);
+/// let directive = parser.directive()?;
+/// parser.input.disable_channel(TEMPLATE_CHANNEL);
+/// ```
+#[derive(Tid, Debug)]
+pub struct MultiChannelTokenStream<'input, T: TokenSource<'input>> {
+    base: UnbufferedTokenStream<'input, T>,
+    channels: Vec<isize>,
+    // index of the first token after the last consumed one
+    unconsumed: isize,
+}
+
+impl<'input, T: TokenSource<'input>> IntStream for MultiChannelTokenStream<'input, T> {
+    #[inline]
+    fn consume(&mut self) {
+        infallible(self.sync_current(false));
+        self.base.consume();
+        self.unconsumed = self.base.index();
+        let next = infallible(self.next_enabled(self.unconsumed, 1, false));
+        self.base.seek(next);
+    }
+
+    #[inline]
+    fn la(&mut self, i: isize) -> isize {
+        if i == 0 {
+            return TOKEN_INVALID_TYPE;
+        }
+        self.lt(i)
+            .map(|t| t.borrow().get_token_type())
+            .unwrap_or(EOF)
+    }
+
+    #[inline(always)]
+    fn mark(&mut self) -> isize { 0 }
+
+    #[inline(always)]
+    fn release(&mut self, _marker: isize) {}
+
+    #[inline(always)]
+    fn index(&self) -> isize { self.base.index() }
+
+    /// Token at `index` that is not on enabled channel is skipped, same as by `consume`
+    #[inline]
+    fn seek(&mut self, index: isize) {
+        let index = index.max(0);
+        // seek back to the current token, e.g. after prediction, does not consume anything
+        if index < self.unconsumed || index > self.base.index() {
+            self.unconsumed = index;
+        }
+        let next = infallible(self.next_enabled(index, 1, false));
+        self.base.seek(next);
+    }
+
+    #[inline(always)]
+    fn size(&self) -> isize { self.base.size() }
+
+    fn get_source_name(&self) -> String { self.base.get_source_name() }
+}
+
+impl<'input, T: TokenSource<'input>> TokenStream<'input> for MultiChannelTokenStream<'input, T> {
+    type TF = T::TF;
+
+    fn lt(&mut self, k: isize) -> Option<&<Self::TF as TokenFactory<'input>>::Tok> {
+        infallible(self.lt_inner(k, false))
+    }
+
+    fn try_lt(
+        &mut self, k: isize,
+    ) -> Result<Option<&<Self::TF as TokenFactory<'input>>::Tok>, ANTLRError> {
+        self.lt_inner(k, true)
+    }
+
+    fn try_consume(&mut self) -> Result<(), ANTLRError> {
+        self.sync_current(true)?;
+        self.consume();
+        Ok(())
+    }
+
+    fn set_defer_errors(&mut self, defer: bool) { self.base.set_defer_errors(defer); }
+
+    fn defers_errors(&self) -> bool { self.base.defers_errors() }
+
+    #[inline]
+    fn get(&self, index: isize) -> &<Self::TF as TokenFactory<'input>>::Tok { self.base.get(index) }
+
+    fn get_token_source(&self) -> &dyn TokenSource<'input, TF = Self::TF> {
+        self.base.get_token_source()
+    }
+
+    fn get_text_from_interval(&self, interval: TokenInterval) -> String {
+        self.base.get_text_from_interval(interval)
+    }
+}
+
+impl<'input, T: TokenSource<'input>> MultiChannelTokenStream<'input, T> {
+    /// Creates stream that produces tokens from `TOKEN_DEFAULT_CHANNEL`
+    pub fn new(lexer: T) -> Self { Self::with_channels(lexer, &[TOKEN_DEFAULT_CHANNEL]) }
+
+    /// Creates stream that produces tokens from `channels`
+    pub fn with_channels(lexer: T, channels: &[isize]) -> Self {
+        let mut r = MultiChannelTokenStream {
+            base: UnbufferedTokenStream::new_buffered(lexer),
+            channels: channels.to_vec(),
+            unconsumed: 0,
+        };
+        // failure to fetch the first token is reported by the next call that reads it
+        r.base.defer_errors = true;
+        r.seek(0);
+        r.base.defer_errors = false;
+        r
+    }
+
+    /// Makes tokens on `channel` visible starting from the first unconsumed token
+    pub fn enable_channel(&mut self, channel: isize) {
+        if !self.channels.contains(&channel) {
+            self.channels.push(channel);
+            self.seek(self.unconsumed);
+        }
+    }
+
+    /// Makes tokens on `channel` skipped starting from the first unconsumed token
+    pub fn disable_channel(&mut self, channel: isize) {
+        self.channels.retain(|&it| it != channel);
+        self.seek(self.unconsumed);
+    }
+
+    /// Returns true if tokens on `channel` are produced by this stream
+    pub fn is_channel_enabled(&self, channel: isize) -> bool { self.channels.contains(&channel) }
+
+    /// Channels tokens are currently produced from
+    pub fn enabled_channels(&self) -> &[isize] { &self.channels }
+
+    /// Creates iterator over this token stream
+    pub fn iter(&mut self) -> IterWrapper<'_, Self> { IterWrapper(self) }
+
+    /// Tokens with indexes in `from..=to` on all channels, without moving the stream,
+    /// see `UnbufferedTokenStream::peek_range`
+    pub fn peek_range(
+        &mut self, from: isize, to: isize,
+    ) -> &[<T::TF as TokenFactory<'input>>::Tok] {
+        self.base.peek_range(from, to)
+    }
+
+    fn lt_inner(
+        &mut self, k: isize, fallible: bool,
+    ) -> Result<Option<&<T::TF as TokenFactory<'input>>::Tok>, ANTLRError> {
+        if k == 0 {
+            return Ok(None);
+        }
+        if k < 0 {
+            let mut i = self.base.p;
+            for _ in 0..-k {
+                if i <= 0 {
+                    return Ok(None);
+                }
+                i = self.next_enabled(i - 1, -1, fallible)?;
+            }
+            return Ok(self.base.tokens.get(i as usize));
+        }
+        self.sync_current(fallible)?;
+        let channels = &self.channels;
+        let visible = |token: &<T::TF as TokenFactory<'input>>::Inner| {
+            channels.contains(&token.get_channel())
+        };
+        let i = self.base.lookahead(self.base.p, k, fallible, visible)?;
+        Ok(match i {
+            Some(i) => self.base.tokens.get(i as usize),
+            None => None,
+        })
+    }
+
+    // Current token can be missing if token source failed when stream tried to fetch it
+    fn sync_current(&mut self, fallible: bool) -> Result<(), ANTLRError> {
+        if self.base.p < self.size() {
+            return Ok(());
+        }
+        let next = self.next_enabled(self.base.p, 1, fallible)?;
+        self.base.seek(next);
+        Ok(())
+    }
+
+    // Index of the first token on enabled channel starting from `i` in `direction`,
+    // EOF token is always enabled
+    fn next_enabled(
+        &mut self, i: isize, direction: isize, fallible: bool,
+    ) -> Result<isize, ANTLRError> {
+        let channels = &self.channels;
+        let visible = |token: &<T::TF as TokenFactory<'input>>::Inner| {
+            channels.contains(&token.get_channel())
+        };
+        self.base.next_visible(i, direction, fallible, visible)
+    }
+}
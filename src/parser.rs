@@ -1,31 +1,42 @@
 //! Base parser implementation
 use std::borrow::Borrow;
 use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Instant;
+
+use once_cell::unsync::OnceCell;
 
 use crate::atn::ATN;
 use crate::atn_simulator::IATNSimulator;
-use crate::error_listener::{ConsoleErrorListener, ErrorListener, ProxyErrorListener};
-use crate::error_strategy::ErrorStrategy;
-use crate::errors::ANTLRError;
-use crate::interval_set::IntervalSet;
+use crate::cancellation::{CancellationToken, ParseCancelled, DEADLINE_CHECK_INTERVAL};
+use crate::char_stream::InputData;
+use crate::error_listener::{
+    CollectingErrorListener, ConsoleErrorListener, Diagnostic, ErrorListener, ProxyErrorListener,
+};
+use crate::error_strategy::{ErrorStrategy, ParseCancelledError};
+use crate::errors::{ANTLRError, DepthExceededError, InputMisMatchError};
+use crate::interval_set::{IntervalSet, TokenInterval};
 use crate::parser_atn_simulator::ParserATNSimulator;
 use crate::parser_rule_context::ParserRuleContext;
-use crate::recognizer::{Actions, Recognizer};
+use crate::prediction_mode::PredictionMode;
+use crate::recognizer::{Actions, Recognizer, RecognizerRef};
 use crate::rule_context::{states_stack, CustomRuleContext, RuleContext};
-use crate::token::{Token, TOKEN_EOF};
+use crate::token::{Token, TOKEN_EOF, TOKEN_INVALID_TYPE};
 use crate::token_factory::{TokenAware, TokenFactory};
 use crate::token_stream::TokenStream;
 use crate::tree::{ErrorNode, Listenable, ParseTreeListener, TerminalNode};
-use crate::vocabulary::Vocabulary;
-use better_any::{Tid, TidAble};
+use crate::utils::escape_whitespaces;
+use crate::vocabulary::{TokenTypeMap, Vocabulary, VocabularyWarning};
+use better_any::{Tid, TidAble, TidExt};
 
 /// parser functionality required for `ParserATNSimulator` to work
 #[allow(missing_docs)] // todo rewrite it so downstream crates actually could meaningfully implement it
-pub trait Parser<'input>: Recognizer<'input> {
+pub trait Parser<'input>: Recognizer<'input> + RecognizerRef {
     fn get_interpreter(&self) -> &ParserATNSimulator;
 
     fn get_token_factory(&self) -> &'input Self::TF;
@@ -49,27 +60,38 @@ pub trait Parser<'input>: Recognizer<'input> {
     fn get_input_stream(&self) -> &dyn TokenStream<'input, TF = Self::TF>;
     fn get_current_token(&self) -> &<Self::TF as TokenFactory<'input>>::Tok;
     fn get_expected_tokens(&self) -> IntervalSet;
+    /// Tokens that can follow the current state without leaving the current rule,
+    /// contains `TOKEN_EPSILON` if the end of the rule can be reached
+    fn get_expected_tokens_within_current_rule(&self) -> IntervalSet;
 
-    fn add_error_listener(&mut self, listener: Box<dyn ErrorListener<'input, Self>>)
-    where
-        Self: Sized;
+    fn add_error_listener(&mut self, listener: Rc<dyn ErrorListener + 'input>);
     fn remove_error_listeners(&mut self);
+    /// Replaces all error listeners with `listeners`, returns previous ones
+    fn replace_error_listeners(
+        &mut self, listeners: Vec<Rc<dyn ErrorListener + 'input>>,
+    ) -> Vec<Rc<dyn ErrorListener + 'input>>;
     fn notify_error_listeners(
         &self,
         msg: String,
         offending_token: Option<isize>,
         err: Option<&ANTLRError>,
     );
-    fn get_error_lister_dispatch<'a>(&'a self) -> Box<dyn ErrorListener<'input, Self> + 'a>
-    where
-        Self: Sized;
+    fn get_error_lister_dispatch<'a>(&'a self) -> Box<dyn ErrorListener + 'a>;
 
     fn is_expected_token(&self, symbol: isize) -> bool;
     fn get_precedence(&self) -> isize;
 
     fn get_state(&self) -> isize;
     fn set_state(&mut self, v: isize);
+    /// Names of the rules from the current one up to the start rule,
+    /// empty if parser is not inside any rule
     fn get_rule_invocation_stack(&self) -> Vec<String>;
+    /// Returns `ANTLRError::DepthExceededError` if parser is inside more nested rules
+    /// than allowed by `BaseParser::set_max_rule_depth`
+    fn check_rule_depth(&mut self) -> Result<(), ANTLRError>;
+    /// Returns `ANTLRError::Cancelled` if parsing has been cancelled
+    /// via `BaseParser::set_cancellation_token` or its deadline has passed
+    fn check_cancelled(&self) -> Result<(), ANTLRError>;
 }
 
 // trait CsvContext<'input>: for<'x> Listenable<'input, dyn CsvParseTreeListener<'input,CsvTreeNodeType> + 'x> + ParserRuleContext<'input,TF=CommonTokenFactory,Ctx=CsvTreeNodeType>{}
@@ -140,11 +162,25 @@ pub struct BaseParser<
     state: isize,
     /// Token stream that is currently used by this parser
     pub input: I,
+    // error deferral setting of `input` before parser enabled it
+    input_defer_errors: bool,
     precedence_stack: Vec<isize>,
 
     parse_listeners: Vec<Box<T>>,
     _syntax_errors: Cell<isize>,
-    error_listeners: RefCell<Vec<Box<dyn ErrorListener<'input, Self>>>>,
+    error_listeners: RefCell<Vec<Rc<dyn ErrorListener + 'input>>>,
+    soft_keywords: Vec<(isize, String)>,
+    identifier_type: isize,
+    soft_keyword_match: Option<isize>,
+    token_type_map: OnceCell<TokenTypeMap>,
+    rule_memo: RuleMemo<'input, Ctx>,
+    event_sink: Option<EventSink<'input, <I::TF as TokenFactory<'input>>::Inner>>,
+    rule_depth: usize,
+    max_rule_depth: Option<usize>,
+    cancellation_token: Option<CancellationToken>,
+    deadline: Option<Instant>,
+    // number of `check_cancelled` calls since deadline was last checked
+    deadline_countdown: Cell<u32>,
 
     ext: Ext,
     pd: PhantomData<fn() -> &'input str>,
@@ -203,6 +239,32 @@ where
     fn get_grammar_file_name(&self) -> &str { self.ext.get_grammar_file_name() }
 
     fn get_atn(&self) -> &ATN { self.interp.atn() }
+
+    fn get_serialized_atn(&self) -> &str { self.ext.get_serialized_atn() }
+}
+
+impl<'input, Ext, I, Ctx, T> RecognizerRef for BaseParser<'input, Ext, I, Ctx, T>
+where
+    Ext: ParserRecog<'input, Self> + 'static,
+    I: TokenStream<'input>,
+    Ctx: ParserNodeType<'input, TF = I::TF>,
+    T: ParseTreeListener<'input, Ctx> + ?Sized,
+    Ctx::Type: Listenable<T>,
+{
+    fn rule_names(&self) -> &[&str] { self.ext.get_rule_names() }
+
+    fn vocabulary(&self) -> Option<&dyn Vocabulary> { Some(self.ext.get_vocabulary()) }
+
+    fn grammar_file_name(&self) -> &str { self.ext.get_grammar_file_name() }
+
+    fn atn(&self) -> Option<&ATN> { Some(self.interp.atn()) }
+
+    fn input_text(&self, start: isize, stop: isize) -> String {
+        self.input
+            .get_text_from_interval(TokenInterval::new(start, stop))
+    }
+
+    fn report_diagnostic(&self, msg: String) { self.notify_error_listeners(msg, None, None) }
 }
 
 impl<'input, Ext, I, Ctx, T> TokenAware<'input> for BaseParser<'input, Ext, I, Ctx, T>
@@ -238,6 +300,7 @@ where
 
     fn consume(&mut self, err_handler: &mut impl ErrorStrategy<'input, Self>) {
         let o = self.get_current_token().clone();
+        let matched_type = self.soft_keyword_match.take();
         if o.borrow().get_token_type() != TOKEN_EOF {
             self.input.consume();
         }
@@ -253,7 +316,12 @@ where
                     listener.visit_error_node(&*node)
                 }
             } else {
-                let node: Rc<TerminalNode<'_, Ctx>> = self.create_token_node(o.clone());
+                let node: Rc<TerminalNode<'_, Ctx>> = match matched_type {
+                    Some(matched_type) => {
+                        TerminalNode::with_matched_type(o.clone(), matched_type).into()
+                    }
+                    None => self.create_token_node(o.clone()),
+                };
                 self.ctx
                     .as_deref()
                     .unwrap()
@@ -263,6 +331,13 @@ where
                 }
             }
         }
+        if self.event_sink.is_some() && !err_handler.in_error_recovery_mode(self) {
+            let token: &<I::TF as TokenFactory<'input>>::Inner = o.borrow();
+            self.event_sink
+                .as_mut()
+                .unwrap()
+                .emit(ParseEvent::Token { token });
+        }
     }
 
     fn precpred(&self, _localctx: Option<&Ctx::Type>, precedence: isize) -> bool {
@@ -289,12 +364,24 @@ where
             .get_expected_tokens(self.state, states_stack)
     }
 
-    fn add_error_listener(&mut self, listener: Box<dyn ErrorListener<'input, Self>>) {
+    fn get_expected_tokens_within_current_rule(&self) -> IntervalSet {
+        let atn = self.interp.atn();
+        atn.next_tokens(atn.states[self.state as usize].as_ref())
+            .clone()
+    }
+
+    fn add_error_listener(&mut self, listener: Rc<dyn ErrorListener + 'input>) {
         self.error_listeners.borrow_mut().push(listener)
     }
 
     fn remove_error_listeners(&mut self) { self.error_listeners.borrow_mut().clear(); }
 
+    fn replace_error_listeners(
+        &mut self, listeners: Vec<Rc<dyn ErrorListener + 'input>>,
+    ) -> Vec<Rc<dyn ErrorListener + 'input>> {
+        self.error_listeners.replace(listeners)
+    }
+
     fn notify_error_listeners(
         &self,
         msg: String,
@@ -308,19 +395,26 @@ where
         };
         let line = offending_token.map(|x| x.get_line()).unwrap_or(-1);
         let column = offending_token.map(|x| x.get_column()).unwrap_or(-1);
+        let offending_token = offending_token.map(Token::to_owned);
 
         for listener in self.error_listeners.borrow().iter() {
-            listener.syntax_error(self, offending_token, line, column, &msg, err)
+            listener.syntax_error(self, offending_token.as_ref(), line, column, &msg, err)
         }
     }
 
-    fn get_error_lister_dispatch<'a>(&'a self) -> Box<dyn ErrorListener<'input, Self> + 'a> {
+    fn get_error_lister_dispatch<'a>(&'a self) -> Box<dyn ErrorListener + 'a> {
         Box::new(ProxyErrorListener {
             delegates: self.error_listeners.borrow(),
         })
     }
 
-    fn is_expected_token(&self, _symbol: isize) -> bool { unimplemented!() }
+    fn is_expected_token(&self, symbol: isize) -> bool {
+        let atn = self.interp.atn();
+        match self.ctx.as_ref() {
+            Some(ctx) => atn.is_expected_token(symbol, self.state, states_stack(ctx.clone())),
+            None => atn.is_expected_token(symbol, self.state, std::iter::empty()),
+        }
+    }
 
     fn get_precedence(&self) -> isize { *self.precedence_stack.last().unwrap_or(&-1) }
 
@@ -333,22 +427,43 @@ where
     fn get_rule_invocation_stack(&self) -> Vec<String> {
         let mut vec = Vec::new();
         let rule_names = self.get_rule_names();
-        let mut ctx = self.get_parser_rule_context().clone();
-        loop {
-            let rule_index = ctx.get_rule_index();
+        let mut ctx = self.ctx.clone();
+        while let Some(current) = ctx {
+            let rule_index = current.get_rule_index();
             vec.push(rule_names.get(rule_index).unwrap_or(&"n/a").to_string());
-            ctx = if let Some(parent) = ctx.get_parent_ctx() {
-                parent
-            } else {
-                break;
-            }
+            ctx = current.get_parent_ctx();
         }
         vec
     }
 
-    //    fn get_rule_invocation_stack(&self, c: _) -> Vec<String> {
-    //        unimplemented!()
-    //    }
+    fn check_rule_depth(&mut self) -> Result<(), ANTLRError> {
+        match self.max_rule_depth {
+            Some(max_depth) if self.rule_depth > max_depth => {
+                self.input.try_lt(1)?;
+                Err(ANTLRError::DepthExceededError(DepthExceededError::new(
+                    self, max_depth,
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), ANTLRError> {
+        let reason = if self
+            .cancellation_token
+            .as_ref()
+            .map_or(false, |it| it.is_cancelled())
+        {
+            ParseCancelled::Cancelled
+        } else if self.deadline.map_or(false, |it| {
+            self.deadline_check_due() && Instant::now() >= it
+        }) {
+            ParseCancelled::DeadlineExceeded
+        } else {
+            return Ok(());
+        };
+        Err(ANTLRError::Cancelled(reason))
+    }
 }
 
 #[allow(missing_docs)] // todo docs
@@ -362,7 +477,12 @@ where
     /*Rc<TerminalNode<'input, Ctx>>: CoerceUnsized<Rc<Ctx::Type>>,
     Rc<ErrorNode<'input, Ctx>>: CoerceUnsized<Rc<Ctx::Type>>,*/
 {
-    pub fn new_base_parser(input: I, interpreter: Arc<ParserATNSimulator>, ext: Ext) -> Self {
+    /// Token source failure is returned from the rule that reaches the failed token,
+    /// so error deferral is enabled on `input`, see `TokenStream::set_defer_errors`.
+    /// Previous setting is restored when stream is replaced with `set_input_stream`.
+    pub fn new_base_parser(mut input: I, interpreter: Arc<ParserATNSimulator>, ext: Ext) -> Self {
+        let input_defer_errors = input.defers_errors();
+        input.set_defer_errors(true);
         Self {
             interp: interpreter,
             ctx: None,
@@ -370,17 +490,115 @@ where
             matched_eof: false,
             state: -1,
             input,
+            input_defer_errors,
             precedence_stack: vec![0],
             parse_listeners: vec![],
             _syntax_errors: Cell::new(0),
-            error_listeners: RefCell::new(vec![Box::new(ConsoleErrorListener {})]),
+            error_listeners: RefCell::new(vec![Rc::new(ConsoleErrorListener {})]),
+            soft_keywords: Vec::new(),
+            identifier_type: TOKEN_INVALID_TYPE,
+            soft_keyword_match: None,
+            token_type_map: OnceCell::new(),
+            rule_memo: RuleMemo::new(),
+            event_sink: None,
+            rule_depth: 0,
+            max_rule_depth: None,
+            cancellation_token: None,
+            deadline: None,
+            deadline_countdown: Cell::new(0),
             ext,
             pd: PhantomData,
         }
     }
 
-    //
-    //    fn reset(&self) { unimplemented!() }
+    /// Rewinds input to the beginning and drops parser state, so input can be parsed again.
+    ///
+    /// Error strategy is not owned by `BaseParser`, so it should be reset separately
+    /// with `ErrorStrategy::reset`.
+    pub fn reset(&mut self) {
+        self.input.seek(0);
+        self.ctx = None;
+        self.state = -1;
+        self.matched_eof = false;
+        self.precedence_stack = vec![0];
+        self.rule_depth = 0;
+        self.rule_memo.clear();
+        self.interp.clear_full_context_memo();
+    }
+
+    /// Replaces token stream parser works on, returns the previous one.
+    ///
+    /// Rule memo and full context memo are cleared
+    /// because recorded results refer to token indexes of the old stream.
+    /// Error deferral is enabled for the new stream, while the returned one
+    /// gets back the setting it had before it was given to the parser.
+    pub fn set_input_stream(&mut self, mut input: I) -> I {
+        self.rule_memo.clear();
+        self.interp.clear_full_context_memo();
+        self.interp.reset_dfa_growth_reports();
+        self.matched_eof = false;
+        let input_defer_errors = input.defers_errors();
+        input.set_defer_errors(true);
+        let mut old = std::mem::replace(&mut self.input, input);
+        old.set_defer_errors(std::mem::replace(
+            &mut self.input_defer_errors,
+            input_defer_errors,
+        ));
+        old
+    }
+
+    /// Enables memoization of the results of `rules` for `ParseMemoized::parse_memoized`,
+    /// replacing previously memoized set. Empty slice disables memoization.
+    ///
+    /// Memoize only rules which result depends solely on the start position,
+    /// i.e. rules without arguments, semantic predicates or actions depending on parser state,
+    /// and not left recursive ones since their result also depends on the precedence.
+    ///
+    /// Clears recorded results.
+    pub fn set_memoized_rules(&mut self, rules: &[usize]) {
+        self.rule_memo.rules = rules.to_vec();
+        self.rule_memo.clear();
+    }
+
+    /// Memo of rule results, mostly useful to get cache statistics
+    pub fn get_rule_memo(&self) -> &RuleMemo<'input, Ctx> { &self.rule_memo }
+
+    /// Drops all recorded rule results.
+    /// Must be called if token stream has been modified or replaced directly via `input` field.
+    pub fn clear_rule_memo(&mut self) { self.rule_memo.clear() }
+
+    fn reuse_memoized<R>(&mut self, key: (usize, isize)) -> Option<Result<Rc<R>, ANTLRError>>
+    where
+        R: ParserRuleContext<'input> + 'input,
+    {
+        let (resume_index, node) = match self.rule_memo.table.get(&key)? {
+            MemoEntry::Failed(e) => {
+                self.rule_memo.hits += 1;
+                return Some(Err(e.clone()));
+            }
+            MemoEntry::Parsed {
+                resume_index,
+                tree,
+                with_children,
+            } => {
+                if *with_children != self.build_parse_trees {
+                    return None;
+                }
+                (*resume_index, tree.clone())
+            }
+        };
+        // entry could have been recorded by a call with another context type
+        let tree = node.clone().downcast_rc::<R>().ok()?;
+        self.rule_memo.hits += 1;
+        self.input.seek(resume_index);
+        if self.build_parse_trees {
+            if let Some(parent) = &self.ctx {
+                node.set_parent(&self.ctx);
+                parent.add_child(node);
+            }
+        }
+        Some(Ok(tree))
+    }
 
     #[inline]
     pub fn match_token(
@@ -388,8 +606,13 @@ where
         ttype: isize,
         err_handler: &mut impl ErrorStrategy<'input, Self>,
     ) -> Result<<I::TF as TokenFactory<'input>>::Tok, ANTLRError> {
+        // current token might not be fetched yet if token source has failed
+        self.input.try_lt(1)?;
+        self.check_cancelled()?;
+        self.check_rule_depth()?;
         let mut token = self.get_current_token().clone();
-        if token.borrow().get_token_type() == ttype {
+        let token_type = token.borrow().get_token_type();
+        if token_type == ttype || self.match_soft_keyword(ttype, token.borrow()) {
             if ttype == TOKEN_EOF {
                 self.matched_eof = true;
             }
@@ -413,6 +636,9 @@ where
         &mut self,
         err_handler: &mut impl ErrorStrategy<'input, Self>,
     ) -> Result<<I::TF as TokenFactory<'input>>::Tok, ANTLRError> {
+        self.input.try_lt(1)?;
+        self.check_cancelled()?;
+        self.check_rule_depth()?;
         let mut t = self.get_current_token().clone();
         if t.borrow().get_token_type() > 0 {
             err_handler.report_match(self);
@@ -429,6 +655,123 @@ where
         return Ok(t);
     }
 
+    /// Limits the number of nested rule invocations, `None`, which is the default,
+    /// removes the limit.
+    ///
+    /// Generated parsers invoke rules recursively, so deeply nested input can overflow
+    /// the stack of the thread parser runs in and abort the process.
+    /// Once the limit is exceeded, next token match or decision fails with
+    /// `ANTLRError::DepthExceededError`, which error strategy reports and recovers from
+    /// like from any other syntax error, so outer rules continue parsing.
+    /// Appropriate limit depends on the grammar and on the stack size,
+    /// which can be increased by running parser in a thread built with `stack_size`.
+    pub fn set_max_rule_depth(&mut self, max_depth: Option<usize>) {
+        self.max_rule_depth = max_depth;
+    }
+
+    /// Number of rules parser has entered but not yet exited
+    pub fn get_rule_depth(&self) -> usize { self.rule_depth }
+
+    /// Makes parser stop once `token` is cancelled, `None` removes previously set token.
+    ///
+    /// Token is checked when parser matches tokens, enters decisions, and on each step
+    /// of adaptive prediction, so even pathological inputs are stopped promptly.
+    /// Parsing is then aborted with `ANTLRError::Cancelled`,
+    /// which is returned from the rule that was called by user without error recovery.
+    /// Parse tree built so far is left incomplete.
+    pub fn set_cancellation_token(&mut self, token: Option<CancellationToken>) {
+        self.cancellation_token = token;
+    }
+
+    /// Makes parser stop same way as with `set_cancellation_token` once `deadline` has passed,
+    /// `None` removes previously set deadline.
+    ///
+    /// Bounds worst-case parse time of untrusted input, e.g.
+    /// `parser.set_deadline(Some(Instant::now() + Duration::from_secs(1)))`
+    ///
+    /// To keep the overhead low, clock is read only on every
+    /// [`DEADLINE_CHECK_INTERVAL`](crate::cancellation::DEADLINE_CHECK_INTERVAL)th check,
+    /// so parsing can run slightly past the deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.deadline_countdown.set(0);
+    }
+
+    // Whether enough cancellation checks have passed since the clock was last read
+    fn deadline_check_due(&self) -> bool {
+        let countdown = self.deadline_countdown.get();
+        if countdown == 0 {
+            self.deadline_countdown.set(DEADLINE_CHECK_INTERVAL - 1);
+            true
+        } else {
+            self.deadline_countdown.set(countdown - 1);
+            false
+        }
+    }
+
+    /// Registers contextual keywords, i.e. keywords that are lexed as identifiers of `identifier_type`
+    /// but still can be matched by parser at positions where keyword of type `token_type` is expected.
+    /// And vice versa, if lexer produces keyword token where identifier is expected,
+    /// it is matched as identifier if its text is equal to the registered keyword text.
+    ///
+    /// Buffered token is never changed, reinterpreted type is available via
+    /// `LeafNode::get_matched_type` of the created terminal node.
+    /// Note that only direct token matches are affected, prediction still uses original token types,
+    /// so at decision points keywords should still be handled by grammar.
+    pub fn set_soft_keywords(&mut self, identifier_type: isize, keywords: &[(isize, &str)]) {
+        self.identifier_type = identifier_type;
+        self.soft_keywords = keywords
+            .iter()
+            .map(|&(token_type, text)| (token_type, text.to_owned()))
+            .collect();
+    }
+
+    /// Mapping from token names of this parser vocabulary to token types, built on first use
+    pub fn get_token_type_map(&self) -> &TokenTypeMap {
+        self.token_type_map
+            .get_or_init(|| TokenTypeMap::new(self.ext.get_vocabulary()))
+    }
+
+    /// Returns type of the token with literal or symbolic name `name`,
+    /// or `TOKEN_INVALID_TYPE` if there is no such token
+    pub fn get_token_type(&self, name: &str) -> isize {
+        self.get_token_type_map().get_token_type(name)
+    }
+
+    /// Case insensitive version of `get_token_type`, exact match is still preferred
+    pub fn get_token_type_ignore_case(&self, name: &str) -> isize {
+        self.get_token_type_map().get_token_type_ignore_case(name)
+    }
+
+    /// Token name collisions in vocabulary of this parser, see `TokenTypeMap`
+    pub fn get_vocabulary_warnings(&self) -> &[VocabularyWarning] {
+        self.get_token_type_map().get_vocabulary_warnings()
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn match_soft_keyword(
+        &mut self, ttype: isize, token: &<I::TF as TokenFactory<'input>>::Inner,
+    ) -> bool {
+        if self.soft_keywords.is_empty() {
+            return false;
+        }
+        let token_type = token.get_token_type();
+        let text = token.get_text().to_display();
+        let matched = self
+            .soft_keywords
+            .iter()
+            .any(|(keyword_type, keyword_text)| {
+                *keyword_text == text
+                    && ((ttype == *keyword_type && token_type == self.identifier_type)
+                        || (ttype == self.identifier_type && token_type == *keyword_type))
+            });
+        if matched {
+            self.soft_keyword_match = Some(ttype);
+        }
+        matched
+    }
+
     /// Adds parse listener for this parser
     /// returns `listener_id` that can be used later to get listener back
     ///
@@ -468,6 +811,9 @@ where
             // listener.enter_every_rule(ctx);
             ctx.enter(listener);
         }
+        if let Some(sink) = &mut self.event_sink {
+            sink.enter_rule(ctx.get_rule_index());
+        }
     }
 
     pub fn trigger_exit_rule_event(&mut self) {
@@ -476,6 +822,9 @@ where
             ctx.exit(listener);
             // listener.exit_every_rule(ctx);
         }
+        if let Some(sink) = &mut self.event_sink {
+            sink.exit_rule(ctx.get_rule_index());
+        }
     }
     //
     //    fn set_token_factory(&self, factory: TokenFactory) { unimplemented!() }
@@ -497,8 +846,20 @@ where
         }
     }
 
+    // entering a rule without parent context starts a new parse
+    #[inline]
+    fn start_parse_if_top_level(&mut self, localctx: &Ctx::Type) {
+        if localctx.get_parent_ctx().is_none() {
+            self.interp.reset_dfa_growth_reports();
+            // previous parse might have been aborted without exiting its rules
+            self.rule_depth = 0;
+        }
+    }
+
     #[inline]
     pub fn enter_rule(&mut self, localctx: Rc<Ctx::Type>, state: isize, _rule_index: usize) {
+        self.start_parse_if_top_level(&localctx);
+        self.rule_depth += 1;
         self.set_state(state);
         localctx.set_start(self.input.lt(1).cloned());
         self.ctx = Some(localctx);
@@ -523,6 +884,8 @@ where
         }
         self.trigger_exit_rule_event();
         self.set_state(self.get_parser_rule_context().get_invoking_state());
+        self.rule_memo.completed(self.ctx.as_ref().unwrap());
+        self.rule_depth = self.rule_depth.saturating_sub(1);
         let parent = self.ctx.as_ref().unwrap().get_parent_ctx();
         // mem::replace(&mut self.ctx, parent);
         self.ctx = parent;
@@ -555,6 +918,8 @@ where
         _rule_index: usize,
         precedence: isize,
     ) {
+        self.start_parse_if_top_level(&localctx);
+        self.rule_depth += 1;
         self.set_state(state);
         self.precedence_stack.push(precedence);
         localctx.set_start(self.input.lt(1).cloned());
@@ -585,9 +950,11 @@ where
 
     pub fn unroll_recursion_context(&mut self, parent_ctx: Option<Rc<Ctx::Type>>) {
         self.precedence_stack.pop();
+        self.rule_depth = self.rule_depth.saturating_sub(1);
         let retctx = self.ctx.clone().unwrap();
         retctx.set_stop(self.input.lt(-1).cloned());
-        if !self.parse_listeners.is_empty() {
+        self.rule_memo.completed(&retctx);
+        if !self.parse_listeners.is_empty() || self.event_sink.is_some() {
             while self.ctx.as_ref().map(|x| Rc::as_ptr(x))
                 != parent_ctx.as_ref().map(|x| Rc::as_ptr(x))
             {
@@ -621,6 +988,15 @@ where
         ErrorNode::new(token).into()
     }
 
+    /// Text representation of DFA of each decision, indexed by decision number
+    pub fn get_dfa_strings(&self) -> Vec<String> {
+        self.interp
+            .decision_to_dfa()
+            .iter()
+            .map(|dfa| dfa.read().to_string(self.get_vocabulary()))
+            .collect()
+    }
+
     /// Text representation of generated DFA for debugging purposes
     pub fn dump_dfa(&self) {
         let mut seen_one = false;
@@ -631,7 +1007,12 @@ where
                 if seen_one {
                     println!()
                 }
-                println!("Decision {}:", dfa.decision);
+                let description = self.get_atn().describe_decision(
+                    dfa.decision(),
+                    self.get_rule_names(),
+                    self.get_grammar_file_name(),
+                );
+                println!("{}:", description);
                 print!("{}", dfa.to_string(self.get_vocabulary()));
                 seen_one = true;
             }
@@ -642,8 +1023,6 @@ where
     //
     //    fn in_context(&self, context: ParserRuleContext) -> bool { unimplemented!() }
     //
-    //    fn get_expected_tokens_within_current_rule(&self) -> * IntervalSet { unimplemented!() }
-    //
     //
     //    fn get_rule_index(&self, ruleName: String) -> int { unimplemented!() }
     //
@@ -675,3 +1054,473 @@ impl<T> ListenerId<T> {
         Box::from_raw(Box::into_raw(boxed) as *mut T)
     }
 }
+
+/// Result of `ParseRuleAt::parse_rule_at`
+#[derive(Debug)]
+pub struct RuleMatch<Ctx: ?Sized> {
+    /// Root context of the parsed rule
+    pub tree: Rc<Ctx>,
+    consumed: TokenInterval,
+    resume_index: isize,
+}
+
+impl<Ctx: ?Sized> RuleMatch<Ctx> {
+    /// Token indexes of the first and the last token of the parsed rule, inclusive.
+    /// If rule matched empty input `b` is less than `a`.
+    pub fn consumed_interval(&self) -> TokenInterval { self.consumed }
+
+    /// Index of the first token after the parsed rule,
+    /// can be used as a start index for the next `parse_rule_at` call
+    pub fn resume_index(&self) -> isize { self.resume_index }
+}
+
+/// Allows to parse a single rule starting from arbitrary position in the token stream,
+/// e.g. to parse embedded language construct out of a larger host file.
+///
+/// Implemented for all generated parsers.
+/// ```text
+/// let first = parser.parse_rule_at(0, false, |p| p.statement())?;
+/// let second = parser.parse_rule_at(first.resume_index(), false, |p| p.statement())?;
+/// ```
+pub trait ParseRuleAt<'input>: DerefMut + Sized
+where
+    Self::Target: Parser<'input> + Sized,
+{
+    /// Seeks token stream to `start_token_index` and invokes `entry` rule there.
+    ///
+    /// `start_token_index` should be an index of token on the channel parser works on.
+    /// Parsing stops as soon as the rule is completed, if `match_eof` is true then
+    /// any input left after it is reported as an error.
+    fn parse_rule_at<Ctx>(
+        &mut self, start_token_index: isize, match_eof: bool,
+        entry: impl FnOnce(&mut Self) -> Result<Rc<Ctx>, ANTLRError>,
+    ) -> Result<RuleMatch<Ctx>, ANTLRError>
+    where
+        Ctx: ParserRuleContext<'input> + ?Sized,
+    {
+        self.get_input_stream_mut().seek(start_token_index);
+        let tree = entry(self)?;
+        let consumed = TokenInterval::new(
+            tree.start().get_token_index(),
+            tree.stop().get_token_index(),
+        );
+        let parser = &mut **self;
+        if match_eof && parser.get_input_stream_mut().la(1) != TOKEN_EOF {
+            let token = parser.get_current_token().borrow().to_owned();
+            let stop_state = parser.get_atn().rule_to_stop_state[tree.get_rule_index()];
+            let msg = format!(
+                "extraneous input '{}' expecting <EOF>",
+                escape_whitespaces(token.get_text(), false)
+            );
+            parser.notify_error_listeners(msg, None, None);
+            return Err(ANTLRError::InputMismatchError(
+                InputMisMatchError::trailing_input(
+                    &*parser,
+                    token,
+                    stop_state as isize,
+                    tree.get_rule_index(),
+                ),
+            ));
+        }
+        Ok(RuleMatch {
+            tree,
+            consumed,
+            resume_index: parser.get_input_stream().index(),
+        })
+    }
+}
+
+impl<'input, P> ParseRuleAt<'input> for P
+where
+    P: DerefMut,
+    P::Target: Parser<'input> + Sized,
+{
+}
+
+/// Allows to get all syntax errors of the parse as a value instead of listener callbacks.
+///
+/// Implemented for all generated parsers.
+/// ```text
+/// let (tree, diagnostics) = parser.parse_collecting(|p| p.compilation_unit());
+/// ```
+pub trait ParseCollecting<'input>: DerefMut + Sized
+where
+    Self::Target: Parser<'input> + Sized,
+{
+    /// Invokes `entry` rule with all parser error listeners temporarily replaced
+    /// by `CollectingErrorListener`, and returns its result together with the collected errors
+    /// sorted by position.
+    ///
+    /// Previously registered listeners are restored afterwards.
+    /// Lexer has its own error listeners, so lexer errors are not collected.
+    fn parse_collecting<Ctx: ?Sized>(
+        &mut self, entry: impl FnOnce(&mut Self) -> Result<Rc<Ctx>, ANTLRError>,
+    ) -> (Result<Rc<Ctx>, ANTLRError>, Vec<Diagnostic>) {
+        let collector = Rc::new(CollectingErrorListener::new());
+        let previous =
+            self.replace_error_listeners(vec![collector.clone() as Rc<dyn ErrorListener + 'input>]);
+        let result = entry(self);
+        self.replace_error_listeners(previous);
+        (result, collector.take_diagnostics())
+    }
+}
+
+impl<'input, P> ParseCollecting<'input> for P
+where
+    P: DerefMut,
+    P::Target: Parser<'input> + Sized,
+{
+}
+
+/// Event of the streamed parse, see `ParseEvents::parse_events`
+#[derive(Debug)]
+pub enum ParseEvent<'a, T: ?Sized> {
+    /// Parser entered rule `rule`, `depth` is the number of rules entered but not yet exited
+    EnterRule { rule: usize, depth: usize },
+    /// Parser exited rule `rule`
+    ExitRule { rule: usize },
+    /// Parser matched `token`, tokens consumed during error recovery are not reported
+    Token { token: &'a T },
+    /// Syntax error, reported before the event during which it has been detected
+    Error { diagnostic: Diagnostic },
+}
+
+struct EventSink<'input, T: ?Sized> {
+    callback: Box<dyn FnMut(ParseEvent<'_, T>) + 'input>,
+    errors: Rc<CollectingErrorListener>,
+    depth: usize,
+}
+
+impl<'input, T: ?Sized> EventSink<'input, T> {
+    fn flush_errors(&mut self) {
+        for diagnostic in self.errors.take_diagnostics() {
+            (self.callback)(ParseEvent::Error { diagnostic })
+        }
+    }
+
+    fn emit(&mut self, event: ParseEvent<'_, T>) {
+        self.flush_errors();
+        (self.callback)(event)
+    }
+
+    fn enter_rule(&mut self, rule: usize) {
+        let depth = self.depth;
+        self.depth += 1;
+        self.emit(ParseEvent::EnterRule { rule, depth })
+    }
+
+    fn exit_rule(&mut self, rule: usize) {
+        self.depth -= 1;
+        self.emit(ParseEvent::ExitRule { rule })
+    }
+}
+
+/// Allows to process the input as a stream of events without building a parse tree,
+/// so memory usage does not grow with the size of the input.
+///
+/// Implemented for all generated parsers.
+/// ```text
+/// parser.parse_events(|p| p.compilation_unit(), |event| match event {
+///     ParseEvent::Token { token } => println!("{}", token.get_text()),
+///     _ => {}
+/// })?;
+/// ```
+pub trait ParseEvents<'input>: Sized {
+    /// Type of the tokens reported by `ParseEvent::Token`
+    type Token: ?Sized;
+
+    /// Invokes `entry` rule with parse tree building disabled and passes rule enter/exit events,
+    /// matched tokens and syntax errors to `callback` in input order.
+    ///
+    /// Rule contexts are released as soon as their rule exits, so returned context
+    /// has no children. Error listeners are temporarily replaced, errors are reported
+    /// only as `ParseEvent::Error`. Subtrees reused by `ParseMemoized::parse_memoized`
+    /// produce no events.
+    fn parse_events<R: ?Sized>(
+        &mut self, entry: impl FnOnce(&mut Self) -> Result<Rc<R>, ANTLRError>,
+        callback: impl FnMut(ParseEvent<'_, Self::Token>) + 'input,
+    ) -> Result<Rc<R>, ANTLRError>;
+}
+
+impl<'input, P, Ext, I, Ctx, T> ParseEvents<'input> for P
+where
+    P: DerefMut<Target = BaseParser<'input, Ext, I, Ctx, T>>,
+    Ext: ParserRecog<'input, BaseParser<'input, Ext, I, Ctx, T>> + 'static,
+    I: TokenStream<'input>,
+    Ctx: ParserNodeType<'input, TF = I::TF>,
+    T: ParseTreeListener<'input, Ctx> + ?Sized,
+    Ctx::Type: Listenable<T>,
+{
+    type Token = <I::TF as TokenFactory<'input>>::Inner;
+
+    fn parse_events<R: ?Sized>(
+        &mut self, entry: impl FnOnce(&mut Self) -> Result<Rc<R>, ANTLRError>,
+        callback: impl FnMut(ParseEvent<'_, Self::Token>) + 'input,
+    ) -> Result<Rc<R>, ANTLRError> {
+        let errors = Rc::new(CollectingErrorListener::new());
+        let previous =
+            self.replace_error_listeners(vec![errors.clone() as Rc<dyn ErrorListener + 'input>]);
+        let build_parse_trees = std::mem::replace(&mut self.build_parse_trees, false);
+        let previous_sink = self.event_sink.replace(EventSink {
+            callback: Box::new(callback),
+            errors,
+            depth: 0,
+        });
+
+        let result = entry(self);
+
+        let mut sink = std::mem::replace(&mut self.event_sink, previous_sink).unwrap();
+        self.build_parse_trees = build_parse_trees;
+        self.replace_error_listeners(previous);
+        sink.flush_errors();
+        result
+    }
+}
+
+enum MemoEntry<'input, Node: ParserNodeType<'input>> {
+    Parsed {
+        resume_index: isize,
+        tree: Rc<Node::Type>,
+        with_children: bool,
+    },
+    Failed(ANTLRError),
+}
+
+/// Packrat style memo of rule results, keyed by rule index and start token index.
+///
+/// Filled and consulted by `ParseMemoized::parse_memoized` for rules enabled
+/// with `BaseParser::set_memoized_rules`.
+pub struct RuleMemo<'input, Node: ParserNodeType<'input>> {
+    rules: Vec<usize>,
+    table: HashMap<(usize, isize), MemoEntry<'input, Node>>,
+    last_completed: Option<Rc<Node::Type>>,
+    hits: usize,
+    misses: usize,
+}
+
+impl<'input, Node: ParserNodeType<'input>> RuleMemo<'input, Node> {
+    fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            table: HashMap::new(),
+            last_completed: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns true if results of rule `rule_index` are memoized
+    pub fn is_memoized(&self, rule_index: usize) -> bool { self.rules.contains(&rule_index) }
+
+    /// Number of rule invocations served from the memo
+    pub fn hits(&self) -> usize { self.hits }
+
+    /// Number of memoized rule invocations that actually had to run the rule
+    pub fn misses(&self) -> usize { self.misses }
+
+    /// Number of recorded results
+    pub fn len(&self) -> usize { self.table.len() }
+
+    /// Returns true if there are no recorded results
+    pub fn is_empty(&self) -> bool { self.table.is_empty() }
+
+    fn clear(&mut self) {
+        self.table.clear();
+        self.last_completed = None;
+    }
+
+    fn completed(&mut self, ctx: &Rc<Node::Type>) {
+        if !self.rules.is_empty() && self.is_memoized(ctx.get_rule_index()) {
+            self.last_completed = Some(ctx.clone())
+        }
+    }
+}
+
+impl<'input, Node: ParserNodeType<'input>> Debug for RuleMemo<'input, Node> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuleMemo")
+            .field("rules", &self.rules)
+            .field("len", &self.table.len())
+            .field("hits", &self.hits)
+            .field("misses", &self.misses)
+            .finish()
+    }
+}
+
+/// Allows to reuse results of already parsed rules, mostly useful for heavily speculating parsers
+/// which repeatedly try the same rule at the same position.
+///
+/// Implemented for all generated parsers.
+/// ```text
+/// parser.set_memoized_rules(&[RULE_expression]);
+/// let expr = parser.parse_memoized(RULE_expression, |p| p.expression())?;
+/// ```
+pub trait ParseMemoized<'input>: Sized {
+    /// Invokes `entry`, that must be a call of the rule `rule_index`, at the current position,
+    /// or, if memoization is enabled for this rule and it already has been parsed there,
+    /// skips to the recorded stop position and returns the recorded result.
+    /// When parse trees are being built, reused subtree is attached to the current rule context.
+    ///
+    /// Results of invocations that reported syntax errors are not recorded,
+    /// so errors are reported again and error recovery runs as usual.
+    /// Parse listeners are not notified about the rules of the reused subtree.
+    fn parse_memoized<R>(
+        &mut self, rule_index: usize, entry: impl FnOnce(&mut Self) -> Result<Rc<R>, ANTLRError>,
+    ) -> Result<Rc<R>, ANTLRError>
+    where
+        R: ParserRuleContext<'input> + 'input;
+}
+
+impl<'input, P, Ext, I, Ctx, T> ParseMemoized<'input> for P
+where
+    P: DerefMut<Target = BaseParser<'input, Ext, I, Ctx, T>>,
+    Ext: ParserRecog<'input, BaseParser<'input, Ext, I, Ctx, T>> + 'static,
+    I: TokenStream<'input>,
+    Ctx: ParserNodeType<'input, TF = I::TF>,
+    T: ParseTreeListener<'input, Ctx> + ?Sized,
+    Ctx::Type: Listenable<T>,
+{
+    fn parse_memoized<R>(
+        &mut self, rule_index: usize, entry: impl FnOnce(&mut Self) -> Result<Rc<R>, ANTLRError>,
+    ) -> Result<Rc<R>, ANTLRError>
+    where
+        R: ParserRuleContext<'input> + 'input,
+    {
+        if !self.rule_memo.is_memoized(rule_index) {
+            return entry(self);
+        }
+        let key = (rule_index, self.input.index());
+        if let Some(result) = self.reuse_memoized(key) {
+            return result;
+        }
+
+        self.rule_memo.misses += 1;
+        let errors = self._syntax_errors.get();
+        let result = entry(self);
+        let completed = self.rule_memo.last_completed.take();
+        if self._syntax_errors.get() != errors {
+            return result;
+        }
+        let memo_entry = match &result {
+            Ok(tree) => match completed {
+                Some(node) if Rc::as_ptr(&node) as *const () == Rc::as_ptr(tree) as *const () => {
+                    MemoEntry::Parsed {
+                        resume_index: self.input.index(),
+                        tree: node,
+                        with_children: self.build_parse_trees,
+                    }
+                }
+                _ => return result,
+            },
+            Err(ANTLRError::FallThrough(_)) | Err(ANTLRError::Cancelled(_)) => return result,
+            Err(e) => MemoEntry::Failed(e.clone()),
+        };
+        self.rule_memo.table.insert(key, memo_entry);
+        result
+    }
+}
+
+/// Two-stage parsing, the fastest way to parse inputs that are mostly correct.
+///
+/// Implemented for all generated parsers.
+/// ```text
+/// let mut parser = MyParser::with_dyn_strategy(tokens);
+/// let tree = parser.parse_two_stage(
+///     |p, bail| {
+///         p.err_handler = if bail {
+///             Box::new(BailErrorStrategy::new())
+///         } else {
+///             Box::new(DefaultErrorStrategy::new())
+///         }
+///     },
+///     |p| p.compilation_unit(),
+/// )?;
+/// ```
+pub trait ParseTwoStage<'input>: Sized {
+    /// Invokes `entry` with `PredictionMode::SLL` and bailing error strategy,
+    /// and if it bails out, rewinds the input and invokes `entry` again with
+    /// `PredictionMode::LL` and regular error strategy.
+    ///
+    /// Error strategy is owned by the generated parser, so `set_bail` is called to install
+    /// `BailErrorStrategy` when its argument is true, and the regular strategy otherwise.
+    /// Syntax errors are reported to error listeners only by the second stage,
+    /// parse listeners are notified by both stages.
+    /// Input must be seekable back to the position parsing started at.
+    /// Prediction mode is restored afterwards.
+    fn parse_two_stage<R: ?Sized>(
+        &mut self, set_bail: impl FnMut(&mut Self, bool),
+        entry: impl FnMut(&mut Self) -> Result<Rc<R>, ANTLRError>,
+    ) -> Result<Rc<R>, ANTLRError>;
+}
+
+impl<'input, P, Ext, I, Ctx, T> ParseTwoStage<'input> for P
+where
+    P: DerefMut<Target = BaseParser<'input, Ext, I, Ctx, T>>,
+    Ext: ParserRecog<'input, BaseParser<'input, Ext, I, Ctx, T>> + 'static,
+    I: TokenStream<'input>,
+    Ctx: ParserNodeType<'input, TF = I::TF>,
+    T: ParseTreeListener<'input, Ctx> + ?Sized,
+    Ctx::Type: Listenable<T>,
+{
+    fn parse_two_stage<R: ?Sized>(
+        &mut self, mut set_bail: impl FnMut(&mut Self, bool),
+        mut entry: impl FnMut(&mut Self) -> Result<Rc<R>, ANTLRError>,
+    ) -> Result<Rc<R>, ANTLRError> {
+        let start = self.input.index();
+        let errors = self._syntax_errors.get();
+        let mode = self.interp.get_prediction_mode();
+
+        self.interp.set_prediction_mode(PredictionMode::SLL);
+        let listeners = self.replace_error_listeners(Vec::new());
+        set_bail(self, true);
+        let result = entry(self);
+        set_bail(self, false);
+        self.replace_error_listeners(listeners);
+
+        let result = match result {
+            Err(ANTLRError::FallThrough(e)) if e.is::<ParseCancelledError>() => {
+                (**self).reset();
+                self.input.seek(start);
+                self._syntax_errors.set(errors);
+                self.interp.set_prediction_mode(PredictionMode::LL);
+                entry(self)
+            }
+            result => result,
+        };
+        self.interp.set_prediction_mode(mode);
+        result
+    }
+}
+
+/// Populates DFA of a grammar by parsing `samples`, so that latency sensitive parsing
+/// that follows does not have to simulate ATN for typical inputs.
+///
+/// `parse` is called for each sample, it should create lexer and parser over it,
+/// invoke the entry rule and return the parser; parse errors don't matter here.
+/// DFA is shared by all instances of the grammar, so parsers created afterwards,
+/// including in other threads, benefit from it.
+/// To start from a cold cache, e.g. for profiling, use `ParserATNSimulator::clear_dfa`
+/// and `LexerATNSimulator::clear_dfa`.
+///
+/// Returns the number of parser DFA states after the warm-up.
+/// ```text
+/// let states = warm_up(&SAMPLES, |sample| {
+///     let lexer = MyLexer::new(InputStream::new(sample));
+///     let mut parser = MyParser::new(CommonTokenStream::new(lexer));
+///     let _ = parser.compilation_unit();
+///     parser
+/// });
+/// ```
+pub fn warm_up<'input, P>(samples: &[&'input str], mut parse: impl FnMut(&'input str) -> P) -> usize
+where
+    P: Deref,
+    P::Target: Parser<'input>,
+{
+    let mut states = 0;
+    for sample in samples {
+        let parser = parse(sample);
+        let dfa = parser.get_interpreter().decision_to_dfa();
+        states = dfa.iter().map(|dfa| dfa.read().states.len()).sum();
+    }
+    states
+}
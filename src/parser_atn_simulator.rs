@@ -1,7 +1,7 @@
 //! Base parser implementation
 use std::borrow::Borrow;
-use std::cell::Cell;
-use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
@@ -19,7 +19,7 @@ use crate::atn_state::ATNStateType::RuleStopState;
 use crate::atn_state::{ATNDecisionState, ATNState, ATNStateRef, ATNStateType, ATNSTATE_BLOCK_END};
 use crate::dfa::{ScopeExt, DFA};
 use crate::dfa_state::{DFAState, DFAStateRef, PredPrediction};
-use crate::errors::{ANTLRError, NoViableAltError};
+use crate::errors::{ANTLRError, NoViableAltError, StrictnessError, StrictnessViolation};
 use crate::int_stream::EOF;
 use crate::interval_set::IntervalSet;
 use crate::lexer_atn_simulator::ERROR_DFA_STATE_REF;
@@ -29,7 +29,8 @@ use crate::prediction_context::{
     MurmurHasherBuilder, PredictionContext, PredictionContextCache, EMPTY_PREDICTION_CONTEXT,
     PREDICTION_CONTEXT_EMPTY_RETURN_STATE,
 };
-use crate::prediction_mode::*;
+use crate::prediction_mode::PredictionMode;
+use crate::recognizer::RecognizerRef;
 use crate::semantic_context::SemanticContext;
 use crate::token::{Token, TOKEN_EOF, TOKEN_EPSILON};
 
@@ -83,10 +84,164 @@ use parking_lot::{RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard};
 pub struct ParserATNSimulator {
     base: BaseATNSimulator,
     prediction_mode: Cell<PredictionMode>,
+    decision_prediction_modes: RefCell<HashMap<isize, PredictionMode>>,
+    strictness_policy: Cell<StrictnessPolicy>,
     start_index: Cell<isize>,
+    full_context_memo: RefCell<FullContextMemo>,
+    dfa_growth: RefCell<DfaGrowthWatchdog>,
     // pd:PhantomData<P>
 }
 
+/// Prediction statistics of the parser simulator.
+///
+/// All methods have default implementations,
+/// so new statistics can be added without breaking existing implementations.
+pub trait ParseInfo {
+    /// Number of full context predictions that were served from the full context memo,
+    /// see `ParserATNSimulator::set_full_context_memo_capacity`
+    fn get_full_context_memo_hits(&self) -> usize { 0 }
+
+    /// Number of full context predictions that were not found in the full context memo
+    /// and had to be computed. Not counted while memo is disabled.
+    fn get_full_context_memo_misses(&self) -> usize { 0 }
+
+    /// Human readable summary of the statistics, decisions are described
+    /// as `decision 7 (rule expr) at Expr.g4:42:10` using rule names of `recog`
+    fn report(&self, _recog: &dyn RecognizerRef) -> String { full_context_memo_report(self) }
+}
+
+fn full_context_memo_report(info: &(impl ParseInfo + ?Sized)) -> String {
+    format!(
+        "full context memo: {} hits, {} misses",
+        info.get_full_context_memo_hits(),
+        info.get_full_context_memo_misses()
+    )
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+struct FullContextKey {
+    decision: isize,
+    start_index: isize,
+    precedence: isize,
+    context: Arc<PredictionContext>,
+}
+
+/// What has to be reported to error listeners about full context prediction
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum FullContextReport {
+    Nothing,
+    /// Full context has resolved SLL conflict
+    ContextSensitivity,
+    /// Minimal of the ambiguous alternatives was chosen
+    Ambiguity {
+        exact: bool,
+    },
+}
+
+/// Outcome of full context prediction together with what has to be reported about it
+#[derive(Debug)]
+struct FullContextPrediction {
+    alt: isize,
+    stop_index: isize,
+    report: FullContextReport,
+    configs: ATNConfigSet,
+}
+
+/// Results of full context predictions, evicted in insertion order
+#[derive(Debug, Default)]
+struct FullContextMemo {
+    capacity: usize,
+    entries: HashMap<FullContextKey, Arc<FullContextPrediction>>,
+    order: VecDeque<FullContextKey>,
+    hits: usize,
+    misses: usize,
+}
+
+impl FullContextMemo {
+    fn get(&mut self, key: &FullContextKey) -> Option<Arc<FullContextPrediction>> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let result = self.entries.get(key).cloned();
+        match result {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        result
+    }
+
+    fn insert(&mut self, key: FullContextKey, prediction: Arc<FullContextPrediction>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key.clone(), prediction).is_none() {
+            self.order.push_back(key);
+            self.evict();
+        }
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.order.len() > self.capacity {
+            let key = self.order.pop_front().unwrap();
+            self.entries.remove(&key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// Limits for `set_dfa_growth_warning` and `set_dfa_growth_limit`, 0 means disabled
+#[derive(Debug, Default)]
+struct DfaGrowthWatchdog {
+    warning: usize,
+    limit: usize,
+    // decisions already reported during current parse
+    reported: HashSet<isize>,
+}
+
+impl DfaGrowthWatchdog {
+    // whether growth of `decision` DFA to `state_count` states should be reported
+    fn should_report(&mut self, decision: isize, state_count: usize) -> bool {
+        self.warning != 0 && state_count > self.warning && self.reported.insert(decision)
+    }
+
+    // whether DFA with `state_count` states must not grow anymore
+    fn is_over_limit(&self, state_count: usize) -> bool {
+        self.limit != 0 && state_count >= self.limit
+    }
+}
+
+// number of DFA states without the placeholder for null state
+fn dfa_state_count(dfa: &DFA) -> usize { dfa.states.len() - 1 }
+
+/// Allows to turn prediction reports into errors.
+///
+/// Useful to verify grammar in CI, because ambiguities and full context fallbacks
+/// usually indicate problems in grammar. When a flag is set, corresponding report is still
+/// delivered to error listeners, then `ANTLRError::StrictnessError` is reported to them
+/// as a syntax error and parsing fails with `ANTLRError::FallThrough` wrapping `StrictnessError`.
+/// Error strategy is not involved, so parse fails with any strategy,
+/// including `DefaultErrorStrategy`.
+///
+/// By default everything is disabled.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct StrictnessPolicy {
+    /// Fail when prediction finds an ambiguity
+    pub fail_on_ambiguity: bool,
+    /// Fail when SLL prediction has a conflict and parser has to fallback to full LL prediction
+    pub fail_on_full_context_fallback: bool,
+    /// Fail when full context prediction resolves SLL conflict
+    pub fail_on_context_sensitivity: bool,
+}
+
 /// Just a local helper structure to spoil function parameters as little as possible
 struct Local<'a, 'input, T: Parser<'input>> {
     outer_context: Rc<<T::Node as ParserNodeType<'input>>::Type>,
@@ -94,6 +249,11 @@ struct Local<'a, 'input, T: Parser<'input>> {
     dfa_mut: Option<RwLockWriteGuard<'a, DFA>>,
     merge_cache: &'a mut MergeCache,
     precedence: isize,
+    prediction_mode: PredictionMode,
+    // SLL was forced by `set_decision_prediction_mode`
+    forced_sll: bool,
+    // result of prediction depends on the parser state, so it can't be memoized
+    evaluated_predicates: bool,
     parser: &'a mut T,
     pd: PhantomData<Box<dyn TokenStream<'input, TF = T::TF>>>,
 }
@@ -112,10 +272,24 @@ impl<'a, 'input, T: Parser<'input> + 'a> Local<'a, 'input, T> {
     fn input(&mut self) -> &mut dyn TokenStream<'input, TF = T::TF> {
         self.parser.get_input_stream_mut()
     }
+    // lookahead that fails if token source fails to produce next token
+    fn try_la(&mut self) -> Result<isize, ANTLRError> {
+        Ok(self
+            .input()
+            .try_lt(1)?
+            .map_or(TOKEN_EOF, |t| t.borrow().get_token_type()))
+    }
     // fn seek(&mut self, i: isize) { self.input().seek(i) }
     fn outer_context(&self) -> &<T::Node as ParserNodeType<'input>>::Type {
         self.outer_context.deref()
     }
+    // parser position for internal error messages
+    fn decision(&self) -> isize {
+        let dfa = self.dfa.as_deref().or_else(|| self.dfa_mut.as_deref());
+        dfa.map_or(-1, |it| it.decision)
+    }
+    fn input_index(&self) -> isize { self.parser.get_input_stream().index() }
+    fn rule_stack(&self) -> Vec<String> { self.parser.get_rule_invocation_stack() }
 }
 
 pub(crate) type MergeCache = HashMap<
@@ -138,16 +312,135 @@ impl ParserATNSimulator {
                 shared_context_cache,
             ),
             prediction_mode: Cell::new(PredictionMode::LL),
+            decision_prediction_modes: RefCell::new(HashMap::new()),
+            strictness_policy: Cell::new(StrictnessPolicy::default()),
             start_index: Cell::new(0),
+            full_context_memo: RefCell::new(FullContextMemo::default()),
+            dfa_growth: RefCell::new(DfaGrowthWatchdog::default()),
         }
     }
 
+    pub(crate) fn base(&self) -> &BaseATNSimulator { &self.base }
+
     /// Returns current prediction mode
     pub fn get_prediction_mode(&self) -> PredictionMode { self.prediction_mode.get() }
 
     /// Sets current prediction mode
     pub fn set_prediction_mode(&self, v: PredictionMode) { self.prediction_mode.set(v) }
 
+    /// Returns prediction mode used for `decision`
+    pub fn get_decision_prediction_mode(&self, decision: isize) -> PredictionMode {
+        self.decision_prediction_modes
+            .borrow()
+            .get(&decision)
+            .copied()
+            .unwrap_or_else(|| self.prediction_mode.get())
+    }
+
+    /// Overrides global prediction mode for `decision`.
+    ///
+    /// Usually used to pin known-safe hot decisions to `PredictionMode::SLL`
+    /// while keeping full LL for the rest of the grammar.
+    /// If SLL prediction has a conflict in such decision, minimal conflicting alternative
+    /// is chosen and reported via `ErrorListener::report_context_sensitivity`,
+    /// because full LL prediction might have chosen differently.
+    pub fn set_decision_prediction_mode(&self, decision: isize, mode: PredictionMode) {
+        self.decision_prediction_modes
+            .borrow_mut()
+            .insert(decision, mode);
+    }
+
+    /// Removes all overrides made by `set_decision_prediction_mode`
+    pub fn clear_decision_overrides(&self) { self.decision_prediction_modes.borrow_mut().clear() }
+
+    /// Returns current strictness policy
+    pub fn get_strictness_policy(&self) -> StrictnessPolicy { self.strictness_policy.get() }
+
+    /// Sets which prediction reports should fail the parse
+    pub fn set_strictness_policy(&self, v: StrictnessPolicy) { self.strictness_policy.set(v) }
+
+    /// Returns maximum number of entries in the full context memo, 0 means it is disabled
+    pub fn get_full_context_memo_capacity(&self) -> usize {
+        self.full_context_memo.borrow().capacity
+    }
+
+    /// Enables memoization of full context (LL) prediction results.
+    ///
+    /// Results are keyed by decision, input index where prediction started,
+    /// current precedence and the full invocation context,
+    /// so repeated full context predictions at the same position are not recomputed,
+    /// e.g. when the same input is parsed again after seeking back.
+    /// At most `capacity` results are kept, the oldest ones are evicted first.
+    ///
+    /// Memoized predictions report the same ambiguities and context sensitivities
+    /// as the computed ones, so error listeners and `StrictnessPolicy` see them every time.
+    /// Predictions that have evaluated semantic predicates are not memoized,
+    /// because their result depends on the parser state.
+    /// Memo is bound to the current input, parser clears it on `reset` and `set_input_stream`,
+    /// it can also be cleared with `clear_full_context_memo`.
+    ///
+    /// Disabled by default, 0 disables it and drops all memoized results.
+    pub fn set_full_context_memo_capacity(&self, capacity: usize) {
+        let mut memo = self.full_context_memo.borrow_mut();
+        memo.set_capacity(capacity);
+    }
+
+    /// Returns DFA size that triggers `ErrorListener::report_dfa_growth`, 0 means disabled
+    pub fn get_dfa_growth_warning(&self) -> usize { self.dfa_growth.borrow().warning }
+
+    /// Enables `ErrorListener::report_dfa_growth` notification when DFA of some decision
+    /// grows over `states_per_decision` states.
+    ///
+    /// Certain inputs can make DFA of a single decision grow very large,
+    /// this allows to detect such cases before they become a memory problem.
+    /// Each decision is reported at most once per parse.
+    ///
+    /// Disabled by default, 0 disables it.
+    pub fn set_dfa_growth_warning(&self, states_per_decision: usize) {
+        self.dfa_growth.borrow_mut().warning = states_per_decision;
+    }
+
+    /// Returns maximum DFA size per decision, 0 means unlimited
+    pub fn get_dfa_growth_limit(&self) -> usize { self.dfa_growth.borrow().limit }
+
+    /// Stops DFA growth of decisions that have reached `states_per_decision` states.
+    ///
+    /// Already built DFA states are still used, but when prediction needs a state
+    /// that is not in DFA yet, it is done with full context ATN simulation that is not cached
+    /// in DFA, only in full context memo if it is enabled
+    /// (see `set_full_context_memo_capacity`). So such predictions become considerably slower,
+    /// and for decisions that use SLL prediction mode they can choose a different alternative
+    /// in case of SLL conflict.
+    ///
+    /// Disabled by default, 0 disables it.
+    pub fn set_dfa_growth_limit(&self, states_per_decision: usize) {
+        self.dfa_growth.borrow_mut().limit = states_per_decision;
+    }
+
+    /// Allows `ErrorListener::report_dfa_growth` to report all decisions again.
+    ///
+    /// Called by parser when new parse is started.
+    pub fn reset_dfa_growth_reports(&self) { self.dfa_growth.borrow_mut().reported.clear() }
+
+    /// Drops all results memoized by full context memo, hit and miss counters are kept
+    pub fn clear_full_context_memo(&self) { self.full_context_memo.borrow_mut().clear() }
+
+    /// Drops all DFA states that were built during prediction and full context memo.
+    ///
+    /// Note that DFA is usually shared between all parser instances of the same grammar,
+    /// so this affects them too.
+    pub fn clear_dfa(&self) {
+        for (decision, dfa) in self.decision_to_dfa().iter().enumerate() {
+            let mut dfa = dfa.write();
+            *dfa = DFA::new(
+                self.base.atn.clone(),
+                dfa.atn_start_state,
+                decision as isize,
+            );
+        }
+        self.clear_full_context_memo();
+    }
+
     // fn reset(&self) { unimplemented!() }
 
     /// Called by generated parser to choose an alternative when LL(1) parsing is not enough
@@ -156,8 +449,16 @@ impl ParserATNSimulator {
         decision: isize,
         parser: &mut T,
     ) -> Result<isize, ANTLRError> {
+        parser.check_cancelled()?;
+        parser.check_rule_depth()?;
         self.start_index.set(parser.get_input_stream_mut().index());
         let mut merge_cache: MergeCache = HashMap::with_hasher(MurmurHasherBuilder {});
+        let override_mode = self
+            .decision_prediction_modes
+            .borrow()
+            .get(&decision)
+            .copied();
+        let prediction_mode = override_mode.unwrap_or_else(|| self.prediction_mode.get());
         let mut local = Local {
             outer_context: parser.get_parser_rule_context().clone(),
             dfa: self.decision_to_dfa()[decision as usize]
@@ -166,6 +467,9 @@ impl ParserATNSimulator {
             dfa_mut: None,
             merge_cache: &mut merge_cache,
             precedence: parser.get_precedence(),
+            prediction_mode,
+            forced_sll: override_mode == Some(PredictionMode::SLL),
+            evaluated_predicates: false,
             parser,
             pd: PhantomData,
         };
@@ -182,49 +486,57 @@ impl ParserATNSimulator {
                 local.dfa().s0
             };
 
-            let s0 = s0.unwrap_or_else(|| {
-                let s0_closure = self.compute_start_state(
-                    local.dfa().atn_start_state,
-                    // PredictionContext::from_rule_context::<'a,T::Node>(self.atn(), empty_ctx::<T::Node>().as_ref()),
-                    EMPTY_PREDICTION_CONTEXT.clone(),
-                    false,
-                    &mut local,
-                );
-                local.upgrade_lock();
-                let mut s0;
-                if local.dfa_mut().is_precedence_dfa() {
-                    s0 = local.dfa_mut().s0.unwrap();
-                    let s0_closure_updated = self.apply_precedence_filter(&s0_closure, &mut local);
-                    local.dfa_mut().states[s0].configs = Box::new(s0_closure);
-
-                    s0 = self.add_dfastate(
-                        local.dfa_mut(),
-                        DFAState::new_dfastate(0, Box::new(s0_closure_updated)),
+            // decision that has reached DFA size limit can't get even a start state
+            if s0.is_none() && self.is_dfa_over_limit(local.dfa()) {
+                self.predict_with_full_context(&mut local)
+            } else {
+                let s0 = s0.unwrap_or_else(|| {
+                    let s0_closure = self.compute_start_state(
+                        local.dfa().atn_start_state,
+                        // PredictionContext::from_rule_context::<'a,T::Node>(self.atn(), empty_ctx::<T::Node>().as_ref()),
+                        EMPTY_PREDICTION_CONTEXT.clone(),
+                        false,
+                        &mut local,
                     );
+                    local.upgrade_lock();
+                    let mut s0;
+                    if local.dfa_mut().is_precedence_dfa() {
+                        s0 = local.dfa_mut().s0.unwrap();
+                        let s0_closure_updated =
+                            self.apply_precedence_filter(&s0_closure, &mut local);
+                        local.dfa_mut().states[s0].configs = Box::new(s0_closure);
+
+                        s0 = self.add_dfastate(
+                            local.dfa_mut(),
+                            DFAState::new_dfastate(0, Box::new(s0_closure_updated)),
+                        );
 
-                    local
-                        .dfa_mut
-                        .as_mut()
-                        .unwrap()
-                        .set_precedence_start_state(local.precedence, s0);
-                } else {
-                    s0 = self.add_dfastate(
-                        local.dfa_mut(),
-                        DFAState::new_dfastate(0, Box::new(s0_closure)),
-                    );
-                    local.dfa_mut().s0.replace(s0);
-                }
-                local.downgrade_lock();
-                s0
-            });
+                        local
+                            .dfa_mut
+                            .as_mut()
+                            .unwrap()
+                            .set_precedence_start_state(local.precedence, s0);
+                    } else {
+                        s0 = self.add_dfastate(
+                            local.dfa_mut(),
+                            DFAState::new_dfastate(0, Box::new(s0_closure)),
+                        );
+                        local.dfa_mut().s0.replace(s0);
+                    }
+                    local.downgrade_lock();
+                    self.check_dfa_growth(&mut local);
+                    s0
+                });
 
-            self.exec_atn(&mut local, s0)?
+                self.exec_atn(&mut local, s0)
+            }
         };
 
+        // input is restored even if token source has failed during prediction
         local.input().seek(self.start_index.get());
         local.input().release(m);
-        //        println!("result = {}", result);
-        Ok(result)
+        //        println!("result = {:?}", result);
+        result
     }
 
     #[allow(non_snake_case)]
@@ -235,12 +547,18 @@ impl ParserATNSimulator {
     ) -> Result<isize, ANTLRError> {
         let mut previousD = s0;
 
-        let mut token = local.input().la(1);
+        let mut token = local.try_la()?;
 
         loop {
+            local.parser.check_cancelled()?;
             //            println!("exec atn loop previous D {}",previousD as isize -1);
-            let D = Self::get_existing_target_state(local.dfa(), previousD, token)
-                .unwrap_or_else(|| self.compute_target_state(previousD, token, local));
+            let D = match Self::get_existing_target_state(local.dfa(), previousD, token) {
+                Some(D) => D,
+                None if self.is_dfa_over_limit(local.dfa()) => {
+                    return self.predict_with_full_context(local);
+                }
+                None => self.compute_target_state(previousD, token, local),
+            };
             debug_assert!(D > 0);
 
             let dfa = local.dfa.take().unwrap();
@@ -264,7 +582,7 @@ impl ParserATNSimulator {
             }
 
             let Dstate = &states[D];
-            if Dstate.requires_full_context && self.prediction_mode.get() != PredictionMode::SLL {
+            if Dstate.requires_full_context && local.prediction_mode != PredictionMode::SLL {
                 let mut conflicting_alts = Dstate.configs.conflicting_alts.clone(); //todo get rid of clone?
                 if !Dstate.predicates.is_empty() {
                     let conflict_index = local.input().index();
@@ -290,20 +608,22 @@ impl ParserATNSimulator {
                     self.start_index.get(),
                     local.input().index(),
                     local.parser,
-                );
+                )?;
                 local.dfa = Some(dfa);
 
-                let s0_closure = self.compute_start_state(
-                    local.dfa().atn_start_state,
-                    PredictionContext::from_rule_context::<T::Node>(
-                        self.atn(),
-                        local.outer_context(),
-                    ),
-                    true,
-                    local,
-                );
+                return self.predict_with_full_context(local);
+            }
 
-                return self.exec_atn_with_full_context(local, s0_closure);
+            if Dstate.requires_full_context && local.forced_sll {
+                // full context prediction could have chosen another alternative
+                self.report_context_sensitivity(
+                    &dfa,
+                    Dstate.prediction,
+                    Dstate.configs.as_ref(),
+                    self.start_index.get(),
+                    local.input().index(),
+                    local.parser,
+                )?;
             }
 
             if Dstate.is_accept_state {
@@ -334,7 +654,7 @@ impl ParserATNSimulator {
                             &alts,
                             Dstate.configs.as_ref(),
                             local.parser,
-                        );
+                        )?;
                         return Ok(alts.iter().next().unwrap() as isize);
                     }
                 }
@@ -342,13 +662,101 @@ impl ParserATNSimulator {
             previousD = D;
 
             if token != EOF {
-                local.input().consume();
-                token = local.input().la(1);
+                local.input().try_consume()?;
+                token = local.try_la()?;
             }
             local.dfa = Some(dfa);
         }
     }
 
+    // full context prediction is not cached in DFA, only in full context memo
+    fn predict_with_full_context<'a, T: Parser<'a>>(
+        &self, local: &mut Local<'_, 'a, T>,
+    ) -> Result<isize, ANTLRError> {
+        let context =
+            PredictionContext::from_rule_context::<T::Node>(self.atn(), local.outer_context());
+        let key = FullContextKey {
+            decision: local.dfa().decision,
+            start_index: self.start_index.get(),
+            precedence: local.precedence,
+            context: context.clone(),
+        };
+        // memo is released before reporting, listeners can access the simulator
+        let memoized = self.full_context_memo.borrow_mut().get(&key);
+        if let Some(prediction) = memoized {
+            return self.report_full_context_prediction(local, &prediction);
+        }
+
+        local.evaluated_predicates = false;
+        let s0_closure =
+            self.compute_start_state(local.dfa().atn_start_state, context, true, local);
+
+        let prediction = Arc::new(self.exec_atn_with_full_context(local, s0_closure)?);
+        if !local.evaluated_predicates {
+            self.full_context_memo
+                .borrow_mut()
+                .insert(key, prediction.clone());
+        }
+        self.report_full_context_prediction(local, &prediction)
+    }
+
+    fn report_full_context_prediction<'a, T: Parser<'a>>(
+        &self, local: &mut Local<'_, 'a, T>, prediction: &FullContextPrediction,
+    ) -> Result<isize, ANTLRError> {
+        let dfa = local.dfa.take().unwrap();
+        let configs = &prediction.configs;
+        match prediction.report {
+            FullContextReport::Nothing => {}
+            FullContextReport::ContextSensitivity => self.report_context_sensitivity(
+                &dfa,
+                prediction.alt,
+                configs,
+                self.start_index.get(),
+                prediction.stop_index,
+                local.parser,
+            )?,
+            FullContextReport::Ambiguity { exact } => self.report_ambiguity(
+                &dfa,
+                self.start_index.get(),
+                prediction.stop_index,
+                exact,
+                &configs.get_alts(),
+                configs,
+                local.parser,
+            )?,
+        }
+        Ok(prediction.alt)
+    }
+
+    fn is_dfa_over_limit(&self, dfa: &DFA) -> bool {
+        self.dfa_growth.borrow().is_over_limit(dfa_state_count(dfa))
+    }
+
+    // notifies error listeners when DFA of the current decision has grown over the warning size
+    fn check_dfa_growth<'a, T: Parser<'a>>(&self, local: &mut Local<'_, 'a, T>) {
+        let decision = local.decision();
+        let state_count = dfa_state_count(local.dfa());
+        if !self
+            .dfa_growth
+            .borrow_mut()
+            .should_report(decision, state_count)
+        {
+            return;
+        }
+        let input_index = local.input_index();
+        let rule_index = self.atn().decision_to_rule(decision as usize);
+        let parser = &*local.parser;
+        let rule_name = parser.get_rule_names().get(rule_index).copied();
+        parser.get_error_lister_dispatch().report_dfa_growth(
+            parser,
+            decision,
+            rule_name.unwrap_or_default(),
+            state_count,
+            self.start_index.get(),
+            input_index,
+        );
+    }
+
     #[allow(non_snake_case)]
     fn get_existing_target_state(
         dfa: &DFA,
@@ -378,6 +786,7 @@ impl ParserATNSimulator {
             let closure = closure.states[previousD].configs.as_ref();
             self.compute_reach_set(closure, t, false, local)
         };
+        let prediction_mode = local.prediction_mode;
         local.upgrade_lock();
         let dfa = local.dfa_mut();
         let reach = match reach {
@@ -400,7 +809,7 @@ impl ParserATNSimulator {
             D.configs.set_unique_alt(predicted_alt);
             D.prediction = predicted_alt
         } else if self.all_configs_in_rule_stop_state(reach)
-            || has_sll_conflict_terminating_prediction(self.prediction_mode.get(), reach)
+            || prediction_mode.has_sll_conflict_terminating_prediction(reach)
         {
             let alts = self.get_conflicting_alts(reach);
             D.prediction = alts.iter().next().unwrap() as isize;
@@ -422,6 +831,7 @@ impl ParserATNSimulator {
         let D = self.add_dfastate(dfa, D);
         self.add_dfaedge(&mut dfa.states[previousD], t, D);
         local.downgrade_lock();
+        self.check_dfa_growth(local);
         D
     }
 
@@ -452,16 +862,17 @@ impl ParserATNSimulator {
         local: &mut Local<'_, 'a, T>,
         // _D: &DFAState,
         s0: ATNConfigSet,
-    ) -> Result<isize, ANTLRError> {
+    ) -> Result<FullContextPrediction, ANTLRError> {
         //println!("exec_atn_with_full_context");
         let full_ctx = true;
         let mut found_exact_ambig = false;
         let mut prev = s0;
         local.input().seek(self.start_index.get());
-        let mut t = local.input().la(1);
+        let mut t = local.try_la()?;
         let mut predicted_alt;
         // local.upgrade_lock();
         loop {
+            local.parser.check_cancelled()?;
             //            println!("full_ctx loop");
 
             let reach = self.compute_reach_set(&prev, t, full_ctx, local);
@@ -473,60 +884,57 @@ impl ParserATNSimulator {
                             &prev, local,
                         );
                     if alt != INVALID_ALT {
-                        return Ok(alt);
+                        return Ok(FullContextPrediction {
+                            alt,
+                            stop_index: self.start_index.get(),
+                            report: FullContextReport::Nothing,
+                            configs: prev,
+                        });
                     }
                     return Err(self.no_viable_alt(local, &prev, self.start_index.get()));
                 }
                 Some(x) => x,
             };
 
-            let alt_sub_sets = get_conflicting_alt_subsets(&prev);
+            let alt_sub_sets = PredictionMode::get_conflicting_alt_subsets(&prev);
             prev.set_unique_alt(self.get_unique_alt(&prev));
             if prev.get_unique_alt() != INVALID_ALT {
                 predicted_alt = prev.get_unique_alt();
                 break;
             }
-            if self.prediction_mode.get() != PredictionMode::LL_EXACT_AMBIG_DETECTION {
-                predicted_alt = resolves_to_just_one_viable_alt(&alt_sub_sets);
+            if local.prediction_mode != PredictionMode::LL_EXACT_AMBIG_DETECTION {
+                predicted_alt = PredictionMode::resolves_to_just_one_viable_alt(&alt_sub_sets);
                 if predicted_alt != INVALID_ALT {
                     break;
                 }
-            } else if all_subsets_conflict(&alt_sub_sets) && all_subsets_equal(&alt_sub_sets) {
+            } else if PredictionMode::all_subsets_conflict(&alt_sub_sets)
+                && PredictionMode::all_subsets_equal(&alt_sub_sets)
+            {
                 found_exact_ambig = true;
-                predicted_alt = get_single_viable_alt(&alt_sub_sets);
+                predicted_alt = PredictionMode::get_single_viable_alt(&alt_sub_sets);
                 break;
             }
 
             if t != TOKEN_EOF {
-                local.input().consume();
-                t = local.input().la(1);
+                local.input().try_consume()?;
+                t = local.try_la()?;
             }
         }
 
         // local.downgrade_lock();
-        let dfa = local.dfa.take().unwrap();
-        if prev.get_unique_alt() != INVALID_ALT {
-            self.report_context_sensitivity(
-                &dfa,
-                predicted_alt,
-                &prev,
-                self.start_index.get(),
-                local.input().index(),
-                local.parser,
-            );
-            return Ok(predicted_alt);
-        }
-        self.report_ambiguity(
-            &dfa,
-            self.start_index.get(),
-            local.input().index(),
-            found_exact_ambig,
-            &prev.get_alts(),
-            &prev,
-            local.parser,
-        );
-
-        Ok(predicted_alt)
+        let report = if prev.get_unique_alt() != INVALID_ALT {
+            FullContextReport::ContextSensitivity
+        } else {
+            FullContextReport::Ambiguity {
+                exact: found_exact_ambig,
+            }
+        };
+        Ok(FullContextPrediction {
+            alt: predicted_alt,
+            stop_index: local.input().index(),
+            report,
+            configs: prev,
+        })
     }
 
     // ATNConfigSet is pretty big so should be boxed to move it cheaper
@@ -545,7 +953,11 @@ impl ParserATNSimulator {
         for c in closure.get_items() {
             let state = self.atn().states[c.get_state()].as_ref();
             if let RuleStopState = state.get_state_type() {
-                assert!(c.get_context().unwrap().is_empty());
+                runtime_assert!(
+                    c.get_context().unwrap().is_empty(),
+                    { decision: local.decision(), state: c.get_state() },
+                    "rule stop state config has non empty context"
+                );
                 if full_ctx || t == TOKEN_EOF {
                     skipped_stop_states.push(c);
                 }
@@ -933,6 +1345,7 @@ impl ParserATNSimulator {
         _alt: isize,
         _full_ctx: bool,
     ) -> bool {
+        local.evaluated_predicates = true;
         pred.borrow().evaluate(local.parser, &*local.outer_context)
     }
 
@@ -960,7 +1373,15 @@ impl ParserATNSimulator {
             treat_eofas_epsilon,
             local,
         );
-        assert!(!full_ctx || !configs.get_dips_into_outer_context())
+        runtime_assert!(
+            !full_ctx || !configs.get_dips_into_outer_context(),
+            {
+                decision: local.decision(),
+                input_index: local.input_index(),
+                rule_stack: local.rule_stack(),
+            },
+            "full context closure dips into outer context"
+        )
     }
 
     fn closure_checking_stop_state<'a, T: Parser<'a>>(
@@ -1004,9 +1425,16 @@ impl ParserATNSimulator {
                 let context = config.take_context();
                 for i in 0..context.length() {
                     if context.get_return_state(i) == PREDICTION_CONTEXT_EMPTY_RETURN_STATE {
-                        if i != context.length() - 1 {
-                            panic!("EMPTY_RETURN_STATE is not last for some reason, please report error")
-                        }
+                        runtime_assert!(
+                            i == context.length() - 1,
+                            {
+                                decision: local.decision(),
+                                state: config.get_state(),
+                                input_index: local.input_index(),
+                                rule_stack: local.rule_stack(),
+                            },
+                            "EMPTY_RETURN_STATE is not last in prediction context"
+                        );
                         continue;
                     }
                     let return_state = context.get_return_state(i) as ATNStateRef;
@@ -1088,7 +1516,11 @@ impl ParserATNSimulator {
             if let Some(mut c) = c {
                 let mut new_depth = depth;
                 if let RuleStopState = self.atn().states[config.get_state()].get_state_type() {
-                    assert!(!full_ctx);
+                    runtime_assert!(
+                        !full_ctx,
+                        { decision: local.decision(), state: config.get_state() },
+                        "rule stop state reached during full context closure"
+                    );
 
                     if local.dfa().is_precedence_dfa() {
                         let outermost_precedence_return = tr
@@ -1177,7 +1609,10 @@ impl ParserATNSimulator {
         {
             *end_state
         } else {
-            unreachable!("cast error")
+            runtime_panic!(
+                { state: decision_start_state.get_state_number() },
+                "left recursive loop entry is not followed by block start"
+            )
         };
 
         for i in 0..ctx_len {
@@ -1345,7 +1780,11 @@ impl ParserATNSimulator {
     }
 
     fn rule_transition(&self, config: &ATNConfig, t: &RuleTransition) -> ATNConfig {
-        assert!(config.get_context().is_some());
+        runtime_assert!(
+            config.get_context().is_some(),
+            { state: config.get_state(), target: t.target },
+            "rule transition from config without context"
+        );
         let new_ctx = PredictionContext::new_singleton(
             config.get_context().cloned(),
             t.follow_state as isize,
@@ -1354,8 +1793,8 @@ impl ParserATNSimulator {
     }
 
     fn get_conflicting_alts(&self, configs: &ATNConfigSet) -> BitSet {
-        let altsets = get_conflicting_alt_subsets(configs);
-        get_alts(&altsets)
+        let altsets = PredictionMode::get_conflicting_alt_subsets(configs);
+        PredictionMode::get_alts(&altsets)
     }
 
     //todo can return Cow
@@ -1376,20 +1815,14 @@ impl ParserATNSimulator {
     //    fn dump_dead_end_configs(&self, nvae: * NoViableAltError) { unimplemented!() }
     //
     fn no_viable_alt<'a, T: Parser<'a>>(
-        &self,
-        local: &mut Local<'_, 'a, T>,
-        _configs: &ATNConfigSet,
-        start_index: isize,
+        &self, local: &mut Local<'_, 'a, T>, configs: &ATNConfigSet, start_index: isize,
     ) -> ANTLRError {
         let start_token = local.parser.get_input_stream().get(start_index).borrow();
         let start_token = Token::to_owned(start_token);
         let offending_token = local.input().lt(1).unwrap().borrow();
         let offending_token = Token::to_owned(offending_token);
-        ANTLRError::NoAltError(NoViableAltError::new_full(
-            local.parser,
-            start_token,
-            offending_token,
-        ))
+        let error = NoViableAltError::new_full(local.parser, start_token, offending_token);
+        ANTLRError::NoAltError(error.with_dead_end_configs(configs))
     }
 
     fn get_unique_alt(&self, configs: &ATNConfigSet) -> isize {
@@ -1459,7 +1892,7 @@ impl ParserATNSimulator {
         start_index: isize,
         stop_index: isize,
         parser: &mut T,
-    ) {
+    ) -> Result<(), ANTLRError> {
         //        let ambig_index = parser.get_current_token().get_token_index();
         parser
             .get_error_lister_dispatch()
@@ -1470,7 +1903,16 @@ impl ParserATNSimulator {
                 stop_index,
                 conflicting_alts,
                 configs,
-            )
+            );
+        self.check_strictness(
+            self.strictness_policy.get().fail_on_full_context_fallback,
+            StrictnessViolation::FullContextFallback,
+            dfa,
+            conflicting_alts.iter().map(|it| it as isize).collect(),
+            start_index,
+            stop_index,
+            parser,
+        )
     }
 
     fn report_context_sensitivity<'a, T: Parser<'a>>(
@@ -1481,10 +1923,19 @@ impl ParserATNSimulator {
         start_index: isize,
         stop_index: isize,
         parser: &mut T,
-    ) {
+    ) -> Result<(), ANTLRError> {
         parser
             .get_error_lister_dispatch()
-            .report_context_sensitivity(parser, dfa, start_index, stop_index, prediction, configs)
+            .report_context_sensitivity(parser, dfa, start_index, stop_index, prediction, configs);
+        self.check_strictness(
+            self.strictness_policy.get().fail_on_context_sensitivity,
+            StrictnessViolation::ContextSensitivity,
+            dfa,
+            vec![prediction],
+            start_index,
+            stop_index,
+            parser,
+        )
     }
 
     fn report_ambiguity<'a, T: Parser<'a>>(
@@ -1496,7 +1947,7 @@ impl ParserATNSimulator {
         ambig_alts: &BitSet,
         configs: &ATNConfigSet,
         parser: &mut T,
-    ) {
+    ) -> Result<(), ANTLRError> {
         parser.get_error_lister_dispatch().report_ambiguity(
             parser,
             dfa,
@@ -1505,8 +1956,42 @@ impl ParserATNSimulator {
             exact,
             ambig_alts,
             configs,
+        );
+        self.check_strictness(
+            self.strictness_policy.get().fail_on_ambiguity,
+            StrictnessViolation::Ambiguity,
+            dfa,
+            ambig_alts.iter().map(|it| it as isize).collect(),
+            start_index,
+            stop_index,
+            parser,
         )
     }
+
+    fn check_strictness<'a, T: Parser<'a>>(
+        &self, enabled: bool, violation: StrictnessViolation, dfa: &DFA, alts: Vec<isize>,
+        start_index: isize, stop_index: isize, parser: &mut T,
+    ) -> Result<(), ANTLRError> {
+        if !enabled {
+            return Ok(());
+        }
+        parser.get_input_stream_mut().seek(start_index);
+        let e = StrictnessError::new(
+            parser,
+            violation,
+            dfa.decision,
+            alts,
+            start_index,
+            stop_index,
+        );
+        // reported here and not by error strategy, because strategy would recover from it
+        parser.notify_error_listeners(
+            e.base.message.clone(),
+            Some(start_index),
+            Some(&ANTLRError::StrictnessError(e.clone())),
+        );
+        Err(ANTLRError::FallThrough(Rc::new(e)))
+    }
 }
 
 impl IATNSimulator for ParserATNSimulator {
@@ -1516,3 +2001,75 @@ impl IATNSimulator for ParserATNSimulator {
 
     fn decision_to_dfa(&self) -> &Vec<RwLock<DFA>> { self.base.decision_to_dfa() }
 }
+
+impl ParseInfo for ParserATNSimulator {
+    fn get_full_context_memo_hits(&self) -> usize { self.full_context_memo.borrow().hits }
+
+    fn get_full_context_memo_misses(&self) -> usize { self.full_context_memo.borrow().misses }
+
+    fn report(&self, recog: &dyn RecognizerRef) -> String {
+        let mut report = full_context_memo_report(self);
+        for dfa in self.decision_to_dfa() {
+            let dfa = dfa.read();
+            let state_count = dfa_state_count(&dfa);
+            if state_count > 0 {
+                report.push_str(&format!(
+                    "\n{}: {} DFA states",
+                    self.atn().describe_decision(
+                        dfa.decision(),
+                        recog.rule_names(),
+                        recog.grammar_file_name(),
+                    ),
+                    state_count
+                ));
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::prediction_context::EMPTY_PREDICTION_CONTEXT;
+
+    use super::*;
+
+    fn key(start_index: isize) -> FullContextKey {
+        FullContextKey {
+            decision: 3,
+            start_index,
+            precedence: 0,
+            context: EMPTY_PREDICTION_CONTEXT.clone(),
+        }
+    }
+
+    fn prediction(alt: isize) -> Arc<FullContextPrediction> {
+        Arc::new(FullContextPrediction {
+            alt,
+            stop_index: 0,
+            report: FullContextReport::Nothing,
+            configs: ATNConfigSet::new_base_atnconfig_set(true),
+        })
+    }
+
+    fn get(memo: &mut FullContextMemo, start_index: isize) -> Option<isize> {
+        memo.get(&key(start_index)).map(|it| it.alt)
+    }
+
+    #[test]
+    fn test_full_context_memo_eviction() {
+        let mut memo = FullContextMemo::default();
+        memo.set_capacity(2);
+        memo.insert(key(5), prediction(1));
+        memo.insert(key(6), prediction(2));
+        memo.insert(key(7), prediction(1));
+        // oldest entry is evicted first
+        assert_eq!(get(&mut memo, 5), None);
+        assert_eq!(get(&mut memo, 6), Some(2));
+        assert_eq!(get(&mut memo, 7), Some(1));
+
+        memo.set_capacity(1);
+        assert_eq!(get(&mut memo, 6), None);
+        assert_eq!(get(&mut memo, 7), Some(1));
+    }
+}
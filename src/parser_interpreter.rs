@@ -0,0 +1,595 @@
+//! Parser that runs parser grammar ATN without code generation
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use better_any::{Tid, TidAble};
+use parking_lot::RwLock;
+
+use crate::atn::ATN;
+use crate::atn_state::{ATNDecisionState, ATNState, ATNStateRef, ATNStateType};
+use crate::atn_type::ATNType;
+use crate::dfa::DFA;
+use crate::error_listener::AmbiguityInfo;
+use crate::error_strategy::{DefaultErrorStrategy, DynHandler, ErrorStrategy};
+use crate::errors::{ANTLRError, FailedPredicateError};
+use crate::parser::{BaseParser, Parser, ParserRecog};
+use crate::parser_atn_simulator::ParserATNSimulator;
+use crate::parser_rule_context::BaseParserRuleContext;
+use crate::prediction_context::PredictionContextCache;
+use crate::recognizer::{Actions, Recognizer};
+use crate::rule_context::{CustomRuleContext, EmptyContext, EmptyContextType};
+use crate::token::TOKEN_MIN_USER_TOKEN_TYPE;
+use crate::token_factory::TokenFactory;
+use crate::token_stream::TokenStream;
+use crate::transition::{
+    ActionTransition, AtomTransition, PrecedencePredicateTransition, PredicateTransition,
+    RuleTransition, TransitionType,
+};
+use crate::tree::ParseTreeListener;
+use crate::trees::{is_ancestor_of, root_of_subtree_enclosing_region};
+use crate::vocabulary::Vocabulary;
+
+/// Node type of the trees created by `ParserInterpreter`
+pub type InterpretedTree<'input, TF> = EmptyContext<'input, TF>;
+
+/// Node type descriptor of the trees created by `ParserInterpreter`,
+/// e.g. for implementing `ParseTreeListener` for them
+pub type InterpretedNodeType<'input, TF> = EmptyContextType<'input, TF>;
+
+type NodeType<'input, I> = InterpretedNodeType<'input, <I as TokenStream<'input>>::TF>;
+
+type BaseParserType<'input, I> = BaseParser<
+    'input,
+    ParserGrammar,
+    I,
+    NodeType<'input, I>,
+    dyn ParseTreeListener<'input, NodeType<'input, I>> + 'input,
+>;
+
+type Node<'input, I> = InterpretedTree<'input, <I as TokenStream<'input>>::TF>;
+
+/// Context extension of the rule nodes created by `ParserInterpreter`, stores only rule index
+#[derive(Tid, Debug)]
+pub struct InterpretedRuleContext<'input, TF: TokenFactory<'input> + 'input> {
+    rule_index: usize,
+    ph: PhantomData<&'input TF::Tok>,
+}
+
+impl<'input, TF: TokenFactory<'input> + 'input> CustomRuleContext<'input>
+    for InterpretedRuleContext<'input, TF>
+{
+    type TF = TF;
+    type Ctx = InterpretedNodeType<'input, TF>;
+
+    fn get_rule_index(&self) -> usize { self.rule_index }
+}
+
+/// Parser grammar loaded at runtime, everything `ParserInterpreter` needs besides the input.
+///
+/// Clones share DFA cache, so one grammar instance should be cloned for all interpreters
+/// of the same grammar to not repeat ATN simulation for the same input.
+/// Rule names are `'static` same as in generated parsers,
+/// names loaded at runtime can be made static with `Box::leak`.
+#[derive(Clone)]
+pub struct ParserGrammar {
+    atn: Arc<ATN>,
+    decision_to_dfa: Arc<Vec<RwLock<DFA>>>,
+    shared_context_cache: Arc<PredictionContextCache>,
+    vocabulary: Arc<dyn Vocabulary>,
+    grammar_file_name: String,
+    rule_names: &'static [&'static str],
+}
+
+impl Debug for ParserGrammar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParserGrammar")
+            .field("grammar_file_name", &self.grammar_file_name)
+            .field("vocabulary", &self.vocabulary)
+            .field("rule_names", &self.rule_names)
+            .finish()
+    }
+}
+
+impl ParserGrammar {
+    /// Creates grammar from deserialized parser `atn` and its token `vocabulary`.
+    ///
+    /// Panics if `atn` is not a parser ATN.
+    pub fn new(atn: Arc<ATN>, vocabulary: impl Vocabulary + 'static) -> Self {
+        runtime_assert!(
+            atn.grammar_type == ATNType::PARSER,
+            "the ATN must be a parser ATN"
+        );
+        let decision_to_dfa = (0..atn.decision_to_state.len())
+            .map(|i| RwLock::new(DFA::new(atn.clone(), atn.get_decision_state(i), i as isize)))
+            .collect::<Vec<_>>();
+        Self {
+            atn,
+            decision_to_dfa: Arc::new(decision_to_dfa),
+            shared_context_cache: Arc::new(PredictionContextCache::new()),
+            vocabulary: Arc::new(vocabulary),
+            grammar_file_name: String::new(),
+            rule_names: &[],
+        }
+    }
+
+    /// Grammar of the parser that uses `simulator`, e.g. the one returned by
+    /// `Parser::get_interpreter` of a generated parser, sharing its ATN and DFA cache.
+    ///
+    /// Allows to parse input of a generated parser with `ParserInterpreter`,
+    /// which keeps invoked rules on the heap instead of the native stack,
+    /// so input that is nested too deeply for the recursive generated parser
+    /// is limited only by available memory.
+    ///
+    /// Note that interpreter does not run the code generated for the grammar:
+    /// actions are skipped and semantic predicates are treated as true,
+    /// only precedence predicates of left recursive rules are evaluated.
+    /// Resulting tree is untyped, it consists of `InterpretedTree` nodes
+    /// instead of the rule contexts of the generated parser, so labels and return values
+    /// are not available. Grammars whose parse depends on predicates or actions
+    /// can produce a different tree than generated parser would.
+    /// ```text
+    /// let vocabulary = VocabularyImpl::new(_LITERAL_NAMES.iter(), _SYMBOLIC_NAMES.iter(), None);
+    /// let grammar = ParserGrammar::from_simulator(parser.get_interpreter(), vocabulary)
+    ///     .with_rule_names(&ruleNames);
+    /// ```
+    pub fn from_simulator(
+        simulator: &ParserATNSimulator, vocabulary: impl Vocabulary + 'static,
+    ) -> Self {
+        let base = simulator.base();
+        Self {
+            atn: base.atn.clone(),
+            decision_to_dfa: base.decision_to_dfa.clone(),
+            shared_context_cache: base.shared_context_cache.clone(),
+            vocabulary: Arc::new(vocabulary),
+            grammar_file_name: String::new(),
+            rule_names: &[],
+        }
+    }
+
+    /// Sets name of the file grammar was loaded from, used in error reporting
+    pub fn with_grammar_file_name(mut self, grammar_file_name: impl Into<String>) -> Self {
+        self.grammar_file_name = grammar_file_name.into();
+        self
+    }
+
+    /// Sets rule names, indexed by rule index
+    pub fn with_rule_names(mut self, rule_names: &'static [&'static str]) -> Self {
+        self.rule_names = rule_names;
+        self
+    }
+
+    /// ATN of this grammar
+    pub fn get_atn(&self) -> &ATN { &self.atn }
+
+    /// Token vocabulary of this grammar
+    pub fn get_vocabulary(&self) -> &dyn Vocabulary { &*self.vocabulary }
+
+    /// Name of the file grammar was loaded from
+    pub fn get_grammar_file_name(&self) -> &str { &self.grammar_file_name }
+
+    /// Rule names, indexed by rule index
+    pub fn get_rule_names(&self) -> &[&str] { self.rule_names }
+
+    fn is_left_recursive(&self, rule_index: usize) -> bool {
+        let start_state = self.atn.rule_to_start_state[rule_index];
+        match self.atn.states[start_state].get_state_type() {
+            ATNStateType::RuleStartState {
+                is_left_recursive, ..
+            } => *is_left_recursive,
+            _ => false,
+        }
+    }
+}
+
+impl<'input, I> Actions<'input, BaseParserType<'input, I>> for ParserGrammar
+where
+    I: TokenStream<'input> + TidAble<'input>,
+{
+    fn get_rule_names(&self) -> &[&str] { self.rule_names }
+
+    fn get_vocabulary(&self) -> &dyn Vocabulary { &*self.vocabulary }
+
+    fn get_grammar_file_name(&self) -> &str { &self.grammar_file_name }
+
+    fn get_atn(&self) -> &ATN { &self.atn }
+}
+
+impl<'input, I> ParserRecog<'input, BaseParserType<'input, I>> for ParserGrammar where
+    I: TokenStream<'input> + TidAble<'input>
+{
+}
+
+/// Parser that parses input according to `ParserGrammar` loaded at runtime,
+/// e.g. for grammar development tools or tests that don't want to run ANTLR tool.
+///
+/// Any rule can be used as a start rule, including left recursive ones.
+/// Result is a generic parse tree of `InterpretedTree` nodes that only know their rule index,
+/// but otherwise are the same as the ones built by generated parser,
+/// so they can be printed with `to_string_tree` or walked with listeners.
+/// Grammar actions are not executed and semantic predicates are considered true,
+/// since their code is not available.
+///
+/// Rule invocations are kept in heap allocated contexts rather than in native stack frames,
+/// so unlike generated parser it can parse arbitrarily deeply nested input.
+/// Parse tree must be built for that, see `BaseParser::build_parse_trees`.
+/// ```text
+/// let atn = Arc::new(ATNDeserializer::new(None).deserialize(serialized_atn.chars()));
+/// let grammar = ParserGrammar::new(atn, vocabulary).with_rule_names(&RULE_NAMES);
+/// let mut parser = ParserInterpreter::new(grammar.clone(), CommonTokenStream::new(lexer));
+/// let tree = parser.parse(start_rule_index)?;
+/// println!("{}", tree.to_string_tree(&*parser));
+/// ```
+pub struct ParserInterpreter<'input, I, H = DefaultErrorStrategy<'input, NodeType<'input, I>>>
+where
+    I: TokenStream<'input> + TidAble<'input>,
+    H: ErrorStrategy<'input, BaseParserType<'input, I>>,
+{
+    base: BaseParserType<'input, I>,
+    interpreter: Arc<ParserATNSimulator>,
+    /// Error strategy used to recover from syntax errors
+    pub err_handler: H,
+    // parent context and invoking state of the entered left recursive rules,
+    // since left recursive rule contexts are replaced while rule is being parsed
+    parent_context_stack: Vec<(Option<Rc<Node<'input, I>>>, isize)>,
+    // decision, token index and alternative set by `add_decision_override`
+    decision_override: Option<(isize, isize, isize)>,
+    override_decision_reached: bool,
+    override_decision_root: Option<Rc<Node<'input, I>>>,
+}
+
+impl<'input, I, H> Debug for ParserInterpreter<'input, I, H>
+where
+    I: TokenStream<'input> + TidAble<'input>,
+    H: ErrorStrategy<'input, BaseParserType<'input, I>>,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParserInterpreter")
+            .field("grammar", self.get_grammar())
+            .field("state", &self.base.get_state())
+            .finish()
+    }
+}
+
+impl<'input, I, H> Deref for ParserInterpreter<'input, I, H>
+where
+    I: TokenStream<'input> + TidAble<'input>,
+    H: ErrorStrategy<'input, BaseParserType<'input, I>>,
+{
+    type Target = BaseParserType<'input, I>;
+
+    fn deref(&self) -> &Self::Target { &self.base }
+}
+
+impl<'input, I, H> DerefMut for ParserInterpreter<'input, I, H>
+where
+    I: TokenStream<'input> + TidAble<'input>,
+    H: ErrorStrategy<'input, BaseParserType<'input, I>>,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut self.base }
+}
+
+impl<'input, I> ParserInterpreter<'input, I>
+where
+    I: TokenStream<'input> + TidAble<'input>,
+{
+    /// Creates interpreter of `grammar` over `input` with `DefaultErrorStrategy`
+    pub fn new(grammar: ParserGrammar, input: I) -> Self {
+        Self::with_strategy(grammar, input, DefaultErrorStrategy::new())
+    }
+}
+
+impl<'input, I> ParserInterpreter<'input, I, DynHandler<'input, BaseParserType<'input, I>>>
+where
+    I: TokenStream<'input> + TidAble<'input>,
+{
+    /// Creates interpreter of `grammar` over `input` with `DefaultErrorStrategy`
+    /// that can be replaced at runtime by any other strategy
+    pub fn with_dyn_strategy(grammar: ParserGrammar, input: I) -> Self {
+        Self::with_strategy(grammar, input, Box::new(DefaultErrorStrategy::new()))
+    }
+}
+
+impl<'input, I, H> ParserInterpreter<'input, I, H>
+where
+    I: TokenStream<'input> + TidAble<'input>,
+    H: ErrorStrategy<'input, BaseParserType<'input, I>>,
+{
+    /// Creates interpreter of `grammar` over `input` that recovers from errors with `strategy`
+    pub fn with_strategy(grammar: ParserGrammar, input: I, strategy: H) -> Self {
+        let interpreter = Arc::new(ParserATNSimulator::new(
+            grammar.atn.clone(),
+            grammar.decision_to_dfa.clone(),
+            grammar.shared_context_cache.clone(),
+        ));
+        Self {
+            base: BaseParser::new_base_parser(input, Arc::clone(&interpreter), grammar),
+            interpreter,
+            err_handler: strategy,
+            parent_context_stack: Vec::new(),
+            decision_override: None,
+            override_decision_reached: false,
+            override_decision_root: None,
+        }
+    }
+
+    /// Sets error strategy
+    pub fn set_error_strategy(&mut self, strategy: H) { self.err_handler = strategy }
+
+    /// Grammar this parser interprets
+    pub fn get_grammar(&self) -> &ParserGrammar { &self.base }
+
+    /// Rewinds input to the beginning and resets parser and error strategy state,
+    /// so input can be parsed again. Decision override is kept.
+    pub fn reset(&mut self) {
+        self.base.reset();
+        self.err_handler.reset(&mut self.base);
+        self.parent_context_stack.clear();
+        self.override_decision_reached = false;
+        self.override_decision_root = None;
+    }
+
+    /// Forces `decision` to predict alternative `alt` when it is made at token `token_index`,
+    /// only the first such prediction during parse is overridden.
+    /// Replaces previous override.
+    pub fn add_decision_override(&mut self, decision: isize, token_index: isize, alt: isize) {
+        self.decision_override = Some((decision, token_index, alt));
+        self.override_decision_reached = false;
+    }
+
+    /// Rule context where overridden decision was made during last parse,
+    /// or the new context if it was a left recursive rule loop
+    pub fn get_override_decision_root(&self) -> Option<&Rc<Node<'input, I>>> {
+        self.override_decision_root.as_ref()
+    }
+
+    /// Parses input once per alternative of `ambiguity` forcing its decision to predict
+    /// that alternative, and returns the subtree for each one,
+    /// so that grammar authors can see competing interpretations of the ambiguous input.
+    ///
+    /// Each returned subtree is the deepest one that contains ambiguous input,
+    /// or the context of the overridden decision if it is higher.
+    /// Input is parsed starting from `start_rule_index`, which should be the rule
+    /// the original parse that reported `ambiguity` started from.
+    pub fn get_all_possible_parse_trees(
+        &mut self, ambiguity: &AmbiguityInfo, start_rule_index: usize,
+    ) -> Result<Vec<Rc<Node<'input, I>>>, ANTLRError> {
+        let mut trees = Vec::new();
+        for alt in ambiguity.alts.iter() {
+            self.reset();
+            self.add_decision_override(ambiguity.decision, ambiguity.start_index, alt as isize);
+            let tree = self.parse(start_rule_index)?;
+            // ambiguity can be detected at EOF, which is not a part of any subtree
+            let stop_index = ambiguity.stop_index.min(self.base.input.size() - 2);
+            let mut subtree = root_of_subtree_enclosing_region::<NodeType<'input, I>>(
+                &tree,
+                ambiguity.start_index,
+                stop_index,
+            )
+            .unwrap_or(tree);
+            if let Some(root) = &self.override_decision_root {
+                if is_ancestor_of::<NodeType<'input, I>>(&**root, &*subtree) {
+                    subtree = root.clone();
+                }
+            }
+            trees.push(subtree);
+        }
+        Ok(trees)
+    }
+
+    /// Parses input starting from rule `start_rule_index` and returns the resulting tree.
+    ///
+    /// Syntax errors are reported and recovered from by error strategy same as in generated
+    /// parser, so tree is returned even if input is not valid.
+    pub fn parse(&mut self, start_rule_index: usize) -> Result<Rc<Node<'input, I>>, ANTLRError> {
+        let atn = self.get_grammar().atn.clone();
+        let start_state = atn.rule_to_start_state[start_rule_index];
+        let parent = self.base.ctx.take();
+        let root = new_context(parent, self.base.get_state(), start_rule_index);
+        let left_recursive = self.get_grammar().is_left_recursive(start_rule_index);
+        if left_recursive {
+            self.enter_recursion_rule(root.clone(), start_state, start_rule_index, 0);
+        } else {
+            self.base
+                .enter_rule(root.clone(), start_state as isize, start_rule_index);
+            self.base.enter_outer_alt(None, 1);
+        }
+
+        // number of currently entered rules besides the start rule
+        let mut depth = 0;
+        loop {
+            let state = atn.states[self.base.get_state() as usize].as_ref();
+            if let ATNStateType::RuleStopState = state.get_state_type() {
+                if depth > 0 {
+                    depth -= 1;
+                    self.visit_rule_stop_state(&atn, state);
+                    continue;
+                }
+                if left_recursive {
+                    let result = self.base.ctx.clone().unwrap();
+                    let (parent, _) = self.parent_context_stack.pop().unwrap();
+                    self.base.unroll_recursion_context(parent);
+                    return Ok(result);
+                }
+                self.base.exit_rule();
+                return Ok(root);
+            }
+
+            match self.visit_state(&atn, state, &mut depth) {
+                Ok(_) => {}
+                Err(e @ ANTLRError::FallThrough(_)) => return Err(e),
+                Err(ref re) => {
+                    let stop_state = atn.rule_to_stop_state[state.get_rule_index()];
+                    self.base.set_state(stop_state as isize);
+                    self.err_handler.report_error(&mut self.base, re);
+                    self.err_handler.recover(&mut self.base, re)?;
+                }
+            }
+        }
+    }
+
+    fn visit_state(
+        &mut self, atn: &ATN, p: &dyn ATNState, depth: &mut usize,
+    ) -> Result<(), ANTLRError> {
+        let mut predicted_alt = 1;
+        let mut overridden = false;
+        if let ATNStateType::DecisionState { decision, .. } = p.get_state_type() {
+            if p.get_transitions().len() > 1 {
+                self.err_handler.sync(&mut self.base)?;
+                match self.decision_override {
+                    Some((override_decision, token_index, alt))
+                        if override_decision == *decision
+                            && token_index == self.base.input.index()
+                            && !self.override_decision_reached =>
+                    {
+                        predicted_alt = alt;
+                        self.override_decision_reached = true;
+                        overridden = true;
+                    }
+                    _ => {
+                        predicted_alt = self
+                            .interpreter
+                            .adaptive_predict(*decision, &mut self.base)?;
+                    }
+                }
+            }
+        }
+
+        let transition = p.get_transitions()[predicted_alt as usize - 1].as_ref();
+        match transition.get_serialization_type() {
+            TransitionType::TRANSITION_EPSILON => {
+                let target = atn.states[transition.get_target()].get_state_type();
+                if is_precedence_decision(p) && !matches!(target, ATNStateType::LoopEndState(_)) {
+                    // next iteration of left recursive rule, current context becomes its child
+                    let (parent, invoking_state) = self.parent_context_stack.last().unwrap();
+                    let rule_index = p.get_rule_index();
+                    let ctx = new_context(parent.clone(), *invoking_state, rule_index);
+                    let start_state = atn.rule_to_start_state[rule_index];
+                    self.base.trigger_exit_rule_event();
+                    self.base
+                        .push_new_recursion_context(ctx, start_state as isize, rule_index);
+                }
+            }
+            TransitionType::TRANSITION_ATOM => {
+                let label = transition.cast::<AtomTransition>().label;
+                self.base.match_token(label, &mut self.err_handler)?;
+            }
+            TransitionType::TRANSITION_RANGE
+            | TransitionType::TRANSITION_SET
+            | TransitionType::TRANSITION_NOTSET => {
+                let la = self.base.input.la(1);
+                if transition.matches(la, TOKEN_MIN_USER_TOKEN_TYPE, 65535) {
+                    self.base.match_wildcard(&mut self.err_handler)?;
+                } else {
+                    self.err_handler.recover_inline(&mut self.base)?;
+                }
+            }
+            TransitionType::TRANSITION_WILDCARD => {
+                self.base.match_wildcard(&mut self.err_handler)?;
+            }
+            TransitionType::TRANSITION_RULE => {
+                let rule_transition = transition.cast::<RuleTransition>();
+                let rule_index = rule_transition.rule_index as usize;
+                let invoking_state = p.get_state_number() as isize;
+                let ctx = new_context(self.base.ctx.clone(), invoking_state, rule_index);
+                let start_state = rule_transition.target;
+                if self.get_grammar().is_left_recursive(rule_index) {
+                    let precedence = rule_transition.precedence;
+                    self.enter_recursion_rule(ctx, start_state, rule_index, precedence);
+                } else {
+                    self.base.enter_rule(ctx, start_state as isize, rule_index);
+                    self.base.enter_outer_alt(None, 1);
+                }
+                *depth += 1;
+            }
+            TransitionType::TRANSITION_PREDICATE => {
+                let predicate = transition.cast::<PredicateTransition>();
+                let ctx = self.base.ctx.clone();
+                if !self
+                    .base
+                    .sempred(ctx.as_deref(), predicate.rule_index, predicate.pred_index)
+                {
+                    return Err(FailedPredicateError::new(&mut self.base, None, None));
+                }
+            }
+            TransitionType::TRANSITION_ACTION => {
+                let action = transition.cast::<ActionTransition>();
+                let ctx = self.base.ctx.clone();
+                self.base
+                    .action(ctx.as_deref(), action.rule_index, action.action_index);
+            }
+            TransitionType::TRANSITION_PRECEDENCE => {
+                let precedence = transition
+                    .cast::<PrecedencePredicateTransition>()
+                    .precedence;
+                let ctx = self.base.ctx.clone();
+                if !self.base.precpred(ctx.as_deref(), precedence) {
+                    return Err(FailedPredicateError::new(
+                        &mut self.base,
+                        Some(format!("precpred(_ctx, {})", precedence)),
+                        None,
+                    ));
+                }
+            }
+        }
+
+        if overridden {
+            self.override_decision_root = self.base.ctx.clone();
+        }
+        self.base.set_state(transition.get_target() as isize);
+        Ok(())
+    }
+
+    fn visit_rule_stop_state(&mut self, atn: &ATN, p: &dyn ATNState) {
+        if self.get_grammar().is_left_recursive(p.get_rule_index()) {
+            let (parent, invoking_state) = self.parent_context_stack.pop().unwrap();
+            self.base.unroll_recursion_context(parent);
+            self.base.set_state(invoking_state);
+        } else {
+            self.base.exit_rule();
+        }
+
+        let invoking_state = atn.states[self.base.get_state() as usize].as_ref();
+        let rule_transition = invoking_state.get_transitions()[0]
+            .as_ref()
+            .cast::<RuleTransition>();
+        self.base.set_state(rule_transition.follow_state as isize);
+    }
+
+    fn enter_recursion_rule(
+        &mut self, ctx: Rc<Node<'input, I>>, state: ATNStateRef, rule_index: usize,
+        precedence: isize,
+    ) {
+        let invoking_state = ctx.get_invoking_state();
+        self.parent_context_stack
+            .push((self.base.ctx.clone(), invoking_state));
+        self.base
+            .enter_recursion_rule(ctx, state as isize, rule_index, precedence);
+        self.base.enter_outer_alt(None, 1);
+    }
+}
+
+fn new_context<'input, TF: TokenFactory<'input> + 'input>(
+    parent: Option<Rc<InterpretedTree<'input, TF>>>, invoking_state: isize, rule_index: usize,
+) -> Rc<InterpretedTree<'input, TF>> {
+    Rc::new(BaseParserRuleContext::new_parser_ctx(
+        parent,
+        invoking_state,
+        InterpretedRuleContext {
+            rule_index,
+            ph: PhantomData,
+        },
+    ))
+}
+
+fn is_precedence_decision(state: &dyn ATNState) -> bool {
+    match state.get_state_type() {
+        ATNStateType::DecisionState {
+            state: ATNDecisionState::StarLoopEntry { is_precedence, .. },
+            ..
+        } => *is_precedence,
+        _ => false,
+    }
+}
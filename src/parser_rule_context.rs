@@ -1,15 +1,16 @@
 //! Full parser node
 use std::any::{type_name, Any};
 use std::borrow::{Borrow, BorrowMut};
-use std::cell::{Ref, RefCell, RefMut};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::fmt::{Debug, Error, Formatter};
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 
 use better_any::{Tid, TidAble, TidExt};
 
+use crate::atn::INVALID_ALT;
 use crate::errors::ANTLRError;
-use crate::interval_set::Interval;
+use crate::interval_set::TokenInterval;
 use crate::parser::ParserNodeType;
 use crate::rule_context::{BaseRuleContext, CustomRuleContext, RuleContext};
 use crate::token::Token;
@@ -36,13 +37,13 @@ pub trait ParserRuleContext<'input>:
     where
         'input: 'a,
     {
-        unimplemented!()
+        unimplemented_feature!("start token of contexts that don't store it")
     }
     fn start_mut<'a>(&'a self) -> RefMut<'a, <Self::TF as TokenFactory<'input>>::Tok>
     where
         'input: 'a,
     {
-        unimplemented!()
+        unimplemented_feature!("start token of contexts that don't store it")
     }
 
     fn set_stop(&self, _t: Option<<Self::TF as TokenFactory<'input>>::Tok>) {}
@@ -55,13 +56,13 @@ pub trait ParserRuleContext<'input>:
     where
         'input: 'a,
     {
-        unimplemented!()
+        unimplemented_feature!("stop token of contexts that don't store it")
     }
     fn stop_mut<'a>(&'a self) -> RefMut<'a, <Self::TF as TokenFactory<'input>>::Tok>
     where
         'input: 'a,
     {
-        unimplemented!()
+        unimplemented_feature!("stop token of contexts that don't store it")
     }
 
     // fn add_token_node(&self, token: TerminalNode<'input, Self::TF>) { }
@@ -70,6 +71,25 @@ pub trait ParserRuleContext<'input>:
     fn add_child(&self, _child: Rc<<Self::Ctx as ParserNodeType<'input>>::Type>) {}
     fn remove_last_child(&self) {}
 
+    /// Returns true if this node or any of its descendants is an error node
+    /// (including tokens conjured up by parser for missing input)
+    /// or a rule context that has recorded an exception.
+    ///
+    /// Flag is propagated to ancestors when tree is built, so this check is O(1).
+    fn subtree_has_errors(&self) -> bool { false }
+
+    /// Marks this node and all its ancestors as containing errors
+    fn mark_subtree_has_errors(&self) {}
+
+    /// Storage for user data attached to this node, see `RuleContextExt::set_user_data`.
+    /// Returns `None` if this node type can't store user data.
+    fn user_data_slot<'a>(&'a self) -> Option<&'a RefCell<Option<UserData>>>
+    where
+        'input: 'a,
+    {
+        None
+    }
+
     // fn enter_rule(&self, listener: &mut dyn Any);
     // fn exit_rule(&self, listener: &mut dyn Any);
 
@@ -100,7 +120,7 @@ pub trait ParserRuleContext<'input>:
         self.get_children()
             // .filter(|it| it.deref().self_id() == TerminalNode::<'input, Self::Ctx>::id())
             .filter_map(|it| it.downcast_rc::<TerminalNode<'input, Self::Ctx>>().ok())
-            .filter(|it| it.symbol.borrow().get_token_type() == ttype)
+            .filter(|it| it.get_matched_type() == ttype)
             .nth(pos)
     }
 
@@ -110,7 +130,7 @@ pub trait ParserRuleContext<'input>:
             .filter_map(|it| it.downcast_rc::<TerminalNode<'input, Self::Ctx>>().ok())
             // .filter(|it| it.deref().self_id() == TerminalNode::<'input, Self::Ctx>::id())
             // .map(|it| cast_rc::<'input, TerminalNode<'input, Self::Ctx>>(it.clone()))
-            .filter(|it| it.symbol.borrow().get_token_type() == ttype)
+            .filter(|it| it.get_matched_type() == ttype)
             .collect()
     }
 
@@ -132,6 +152,20 @@ pub trait RuleContextExt<'input>: ParserRuleContext<'input> {
     where
         V: ParseTreeVisitor<'input, Self::Ctx> + ?Sized,
         <Self::Ctx as ParserNodeType<'input>>::Type: VisitableDyn<V>;
+
+    /// Attaches arbitrary value to this node, returning previously attached one.
+    ///
+    /// All nodes created by the runtime support user data, including terminal and error nodes.
+    /// Panics only for custom node types that don't provide `ParserRuleContext::user_data_slot`.
+    fn set_user_data<D: Any + Send>(&self, data: D) -> Option<UserData>;
+
+    /// Removes user data from this node
+    fn take_user_data(&self) -> Option<UserData>;
+
+    /// Returns reference to attached user data if it is present and has type `D`
+    fn user_data_ref<'a, D: Any + Send>(&'a self) -> Option<Ref<'a, D>>
+    where
+        'input: 'a;
 }
 
 impl<'input, T: ParserRuleContext<'input> + ?Sized + 'input> RuleContextExt<'input> for T {
@@ -142,8 +176,6 @@ impl<'input, T: ParserRuleContext<'input> + ?Sized + 'input> RuleContextExt<'inp
         /*Rc<T>: CoerceUnsized<Rc<Z>>,*/
     {
         let mut result = String::from("[");
-        self.
-        let x = self.clone_into()
         let mut next: Option<Rc<Z>> = Some(self.clone() as Rc<Z>);
         while let Some(ref p) = next {
             if stop.is_some() && (stop.is_none() || Rc::ptr_eq(p, stop.as_ref().unwrap())) {
@@ -182,8 +214,40 @@ impl<'input, T: ParserRuleContext<'input> + ?Sized + 'input> RuleContextExt<'inp
         self.get_children()
             .for_each(|child| child.accept_dyn(visitor))
     }
+
+    fn set_user_data<D: Any + Send>(&self, data: D) -> Option<UserData> {
+        self.user_data_slot()
+            .expect("this node type does not support user data")
+            .replace(Some(Box::new(data)))
+    }
+
+    fn take_user_data(&self) -> Option<UserData> {
+        self.user_data_slot().and_then(|slot| slot.borrow_mut().take())
+    }
+
+    fn user_data_ref<'a, D: Any + Send>(&'a self) -> Option<Ref<'a, D>>
+    where
+        'input: 'a,
+    {
+        let slot = self.user_data_slot()?.borrow();
+        if !slot.as_ref().map_or(false, |data| (**data).is::<D>()) {
+            return None;
+        }
+        Some(Ref::map(slot, |data| {
+            (**data.as_ref().unwrap()).downcast_ref::<D>().unwrap()
+        }))
+    }
 }
 
+/// Value that user can attach to parse tree node.
+///
+/// It is not a part of the tree structure, so it is ignored by everything that prints,
+/// compares or serializes trees, unless it is exported explicitly
+/// with `tree_interchange::export_v1_with_user_data`. Copying node content into another context (`BaseParserRuleContext::copy_from`,
+/// which is used by generated parser for labeled alternatives) does not copy user data,
+/// if value has to survive such copy it should be attached after the parsing is finished.
+pub type UserData = Box<dyn Any + Send>;
+
 #[inline]
 #[doc(hidden)]
 pub fn cast<'a, T, Result>(ctx: &T) -> &Result
@@ -237,9 +301,22 @@ pub struct BaseParserRuleContext<'input, Ctx: CustomRuleContext<'input>> {
     pub exception: Option<Box<ANTLRError>>,
     /// List of children of current node
     pub(crate) children: RefCell<Vec<Rc<<Ctx::Ctx as ParserNodeType<'input>>::Type>>>,
+    contains_errors: Cell<bool>,
+    user_data: RefCell<Option<UserData>>,
 }
 impl<'input, Ctx: CustomRuleContext<'input>> NodeText for BaseParserRuleContext<'input, Ctx> {
-    fn get_node_text(&self, _rule_names: &[&str]) -> String { "<unknown>".to_owned() }
+    fn get_node_text(&self, rule_names: &[&str]) -> String {
+        let rule_index = self.get_rule_index();
+        let rule_name = rule_names
+            .get(rule_index)
+            .map(|&it| it.to_owned())
+            .unwrap_or_else(|| rule_index.to_string());
+        let alt_number = self.get_alt_number();
+        if alt_number != INVALID_ALT {
+            return format!("{}:{}", rule_name, alt_number);
+        }
+        rule_name
+    }
 }
 
 impl<'input, Ctx: CustomRuleContext<'input>> Debug for BaseParserRuleContext<'input, Ctx> {
@@ -305,7 +382,9 @@ impl<'input, Ctx: CustomRuleContext<'input>> BorrowMut<Ctx> for BaseParserRuleCo
 impl<'input, Ctx: CustomRuleContext<'input> + TidAble<'input>> ParserRuleContext<'input>
     for BaseParserRuleContext<'input, Ctx>
 {
-    fn set_exception(&self, _e: ANTLRError) { /*self.exception = Some(Box::new(e));*/
+    fn set_exception(&self, _e: ANTLRError) {
+        /*self.exception = Some(Box::new(e));*/
+        self.mark_subtree_has_errors()
     }
 
     fn set_start(&self, t: Option<<Ctx::TF as TokenFactory<'input>>::Tok>) {
@@ -360,11 +439,33 @@ impl<'input, Ctx: CustomRuleContext<'input> + TidAble<'input>> ParserRuleContext
     //     }
 
     fn add_child(&self, child: Rc<<Ctx::Ctx as ParserNodeType<'input>>::Type>) {
+        let has_errors = child.subtree_has_errors();
         self.children.borrow_mut().push(child);
+        if has_errors {
+            self.mark_subtree_has_errors()
+        }
     }
 
     fn remove_last_child(&self) { self.children.borrow_mut().pop(); }
 
+    fn subtree_has_errors(&self) -> bool { self.contains_errors.get() }
+
+    fn mark_subtree_has_errors(&self) {
+        // if flag is already set, ancestors have it set as well
+        if !self.contains_errors.replace(true) {
+            if let Some(parent) = self.get_parent_ctx() {
+                parent.mark_subtree_has_errors()
+            }
+        }
+    }
+
+    fn user_data_slot<'a>(&'a self) -> Option<&'a RefCell<Option<UserData>>>
+    where
+        'input: 'a,
+    {
+        Some(&self.user_data)
+    }
+
     // fn enter_rule(&self, listener: &mut dyn Any) {
     //     Ctx::enter(self, listener)
     // }
@@ -378,6 +479,22 @@ impl<'input, Ctx: CustomRuleContext<'input> + TidAble<'input>> ParserRuleContext
     // }
 }
 
+// Subtrees are released iteratively, so that trees of deeply nested input,
+// e.g. the ones built by `ParserInterpreter`, do not overflow the stack when dropped
+impl<'input, Ctx: CustomRuleContext<'input>> Drop for BaseParserRuleContext<'input, Ctx> {
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(self.children.get_mut());
+        while let Some(node) = pending.pop() {
+            // nodes that are still referenced elsewhere will be dropped later
+            if Rc::strong_count(&node) == 1 {
+                // children are kept alive here until `node` is dropped at the end of iteration,
+                // so it only releases references to them whatever node type it is
+                pending.extend(node.get_children());
+            }
+        }
+    }
+}
+
 impl<'input, Ctx: CustomRuleContext<'input>> Tree<'input> for BaseParserRuleContext<'input, Ctx> {
     fn get_parent(&self) -> Option<Rc<<Ctx::Ctx as ParserNodeType<'input>>::Type>> {
         self.get_parent_ctx()
@@ -385,7 +502,7 @@ impl<'input, Ctx: CustomRuleContext<'input>> Tree<'input> for BaseParserRuleCont
 
     fn has_parent(&self) -> bool { self.base.parent_ctx.borrow().is_some() }
 
-    fn get_payload(&self) -> Box<dyn Any> { unimplemented!() }
+    fn get_payload(&self) -> Box<dyn Any> { unimplemented_feature!("RuleContext::get_payload") }
 
     fn get_child(&self, i: usize) -> Option<Rc<<Self::Ctx as ParserNodeType<'input>>::Type>> {
         self.children.borrow().get(i).cloned()
@@ -407,11 +524,11 @@ impl<'input, Ctx: CustomRuleContext<'input>> Tree<'input> for BaseParserRuleCont
 impl<'input, Ctx: CustomRuleContext<'input> + TidAble<'input>> ParseTree<'input>
     for BaseParserRuleContext<'input, Ctx>
 {
-    fn get_source_interval(&self) -> Interval {
-        Interval {
-            a: self.start().get_token_index(),
-            b: self.stop().get_token_index(),
-        }
+    fn get_source_interval(&self) -> TokenInterval {
+        TokenInterval::new(
+            self.start().get_token_index(),
+            self.stop().get_token_index(),
+        )
     }
 
     /*default*/ fn get_text(&self) -> String {
@@ -438,6 +555,8 @@ impl<'input, Ctx: CustomRuleContext<'input> + 'input> BaseParserRuleContext<'inp
             stop: RefCell::new(Ctx::TF::create_invalid()),
             exception: None,
             children: RefCell::new(vec![]),
+            contains_errors: Cell::new(false),
+            user_data: RefCell::new(None),
         }
     }
     pub fn copy_from<T: ParserRuleContext<'input, TF = Ctx::TF, Ctx = Ctx::Ctx> + ?Sized>(
@@ -454,6 +573,8 @@ impl<'input, Ctx: CustomRuleContext<'input> + 'input> BaseParserRuleContext<'inp
             stop: RefCell::new(ctx.stop_mut().clone()),
             exception: None,
             children: RefCell::new(ctx.get_children().collect()),
+            contains_errors: Cell::new(ctx.subtree_has_errors()),
+            user_data: RefCell::new(None),
         }
     }
 
@@ -520,6 +641,17 @@ where
 
     fn remove_last_child(&self) { self.deref().remove_last_child() }
 
+    fn subtree_has_errors(&self) -> bool { self.deref().subtree_has_errors() }
+
+    fn mark_subtree_has_errors(&self) { self.deref().mark_subtree_has_errors() }
+
+    fn user_data_slot<'a>(&'a self) -> Option<&'a RefCell<Option<UserData>>>
+    where
+        'input: 'a,
+    {
+        self.deref().user_data_slot()
+    }
+
     // fn enter_rule(&self, listener: &mut dyn Any) { self.deref().enter_rule(listener) }
     //
     // fn exit_rule(&self, listener: &mut dyn Any) { self.deref().exit_rule(listener) }
@@ -552,7 +684,7 @@ where
     T: DerefSeal<Target = I> + 'input + Debug + Tid<'input> + NodeText,
     I: ParserRuleContext<'input> + 'input + ?Sized,
 {
-    fn get_source_interval(&self) -> Interval { self.deref().get_source_interval() }
+    fn get_source_interval(&self) -> TokenInterval { self.deref().get_source_interval() }
 
     fn get_text(&self) -> String { self.deref().get_text() }
 }
@@ -661,3 +793,70 @@ where
 //    }
 //
 //    fn new_base_interpreter_rule_context(parent BaseInterpreterRuleContext, invokingStateNumber: isize, ruleIndex: isize) -> * BaseInterpreterRuleContext { unimplemented!() }
+
+#[cfg(test)]
+mod test {
+    use std::marker::PhantomData;
+    use std::rc::Rc;
+
+    use crate::parser_rule_context::{BaseParserRuleContext, RuleContextExt};
+    use crate::rule_context::{EmptyContext, EmptyCustomRuleContext};
+    use crate::token_factory::CommonTokenFactory;
+    use crate::tree_builder::TreeBuilder;
+
+    type Node = EmptyContext<'static, CommonTokenFactory>;
+
+    fn node(parent: Option<Rc<Node>>) -> Rc<Node> {
+        Rc::new(BaseParserRuleContext::new_parser_ctx(
+            parent,
+            0,
+            EmptyCustomRuleContext(PhantomData),
+        ))
+    }
+
+    fn tag(node: &Rc<Node>, depth: usize) {
+        node.set_user_data(depth);
+        node.get_children().for_each(|child| tag(&child, depth + 1));
+    }
+
+    #[test]
+    fn test_user_data() {
+        let root = node(None);
+        let child = node(Some(root.clone()));
+        root.add_child(child.clone());
+        let grandchild = TreeBuilder::new()
+            .rule(0)
+            .token(1, "x")
+            .error_token(2, ",")
+            .end()
+            .finish()
+            .unwrap();
+        child.add_child(grandchild.clone());
+
+        // terminal and error nodes have user data too
+        tag(&root, 0);
+        assert_eq!(*root.user_data_ref::<usize>().unwrap(), 0);
+        assert_eq!(*child.user_data_ref::<usize>().unwrap(), 1);
+        assert_eq!(*grandchild.user_data_ref::<usize>().unwrap(), 2);
+        for leaf in grandchild.get_children() {
+            assert_eq!(*leaf.user_data_ref::<usize>().unwrap(), 3);
+        }
+        assert!(root.user_data_ref::<String>().is_none());
+
+        let old = child.set_user_data("label".to_owned()).unwrap();
+        assert_eq!(*old.downcast::<usize>().unwrap(), 1);
+        assert_eq!(&*child.user_data_ref::<String>().unwrap(), "label");
+
+        // copied contexts do not inherit user data
+        let copy: Rc<Node> = Rc::new(BaseParserRuleContext::copy_from(
+            &*child,
+            EmptyCustomRuleContext(PhantomData),
+        ));
+        assert!(copy.user_data_ref::<String>().is_none());
+        assert_eq!(copy.get_child_count(), 1);
+
+        assert!(child.take_user_data().is_some());
+        assert!(child.take_user_data().is_none());
+    }
+}
+
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use std::fmt::{Display, Error, Formatter};
 use std::hash::{BuildHasher, Hash, Hasher};
 use std::ops::Deref;
-
+use std::rc::Rc;
 use std::sync::{Arc, RwLock};
 
 use murmur3::murmur3_32::MurmurHasher;
@@ -524,25 +524,31 @@ impl PredictionContext {
         atn: &ATN,
         outer_context: &Ctx::Type,
     ) -> Arc<PredictionContext> {
-        if outer_context.get_parent_ctx().is_none() || outer_context.is_empty()
-        /*ptr::eq(outer_context, empty_ctx().as_ref())*/
-        {
-            return EMPTY_PREDICTION_CONTEXT.clone();
+        // iterative, so that deeply nested rule contexts do not overflow the stack
+        let mut follow_states = Vec::new();
+        let mut current: Option<Rc<Ctx::Type>> = None;
+        loop {
+            let ctx = current.as_deref().unwrap_or(outer_context);
+            let parent = match ctx.get_parent_ctx() {
+                Some(parent) if !ctx.is_empty() => parent,
+                _ => break,
+            };
+            let transition = atn.states[ctx.get_invoking_state() as usize]
+                .get_transitions()
+                .first()
+                .unwrap()
+                .deref()
+                .cast::<RuleTransition>();
+            follow_states.push(transition.follow_state as isize);
+            current = Some(parent);
         }
 
-        let parent = PredictionContext::from_rule_context::<Ctx>(
-            atn,
-            outer_context.get_parent_ctx().unwrap().deref(),
-        );
-
-        let transition = atn.states[outer_context.get_invoking_state() as usize]
-            .get_transitions()
-            .first()
-            .unwrap()
-            .deref()
-            .cast::<RuleTransition>();
-
-        PredictionContext::new_singleton(Some(parent), transition.follow_state as isize).alloc()
+        follow_states.into_iter().rev().fold(
+            EMPTY_PREDICTION_CONTEXT.clone(),
+            |parent, follow_state| {
+                PredictionContext::new_singleton(Some(parent), follow_state).alloc()
+            },
+        )
     }
 
     fn combine_common_parents(array: &mut ArrayPredictionContext) {
@@ -637,7 +643,10 @@ impl PredictionContextCache {
             if let Array(array) = context.deref() {
                 PredictionContext::new_array(parents, array.return_states.clone())
             } else {
-                unreachable!()
+                runtime_panic!(
+                    { parents: parents.len() },
+                    "prediction context with several parents is not an array"
+                )
             }
         };
 
@@ -13,7 +13,9 @@ use crate::semantic_context::SemanticContext;
 /// utility methods for analyzing configuration sets for conflicts and/or
 /// ambiguities.
 ///
-/// It is set through `ParserATNSimulator::
+/// It is set through `ParserATNSimulator::set_prediction_mode`, e.g. for two-stage parsing
+/// that first tries fast `SLL` with `BailErrorStrategy` and reparses with `LL` only
+/// if that fails.
 #[allow(non_camel_case_types)]
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum PredictionMode {
@@ -78,64 +80,106 @@ pub enum PredictionMode {
 }
 
 impl PredictionMode {
-    //todo move everything here
-}
+    /// Whether SLL prediction can stop on a conflict in `configs`
+    /// because no further lookahead would resolve it.
+    ///
+    /// That is the case when `configs` contain a conflicting subset of alternatives
+    /// and no ATN state is associated with a single alternative.
+    /// In `SLL` mode semantic predicates are ignored, since they are not evaluated
+    /// until the whole prediction is done.
+    pub fn has_sll_conflict_terminating_prediction(self, configs: &ATNConfigSet) -> bool {
+        //    if all_configs_in_rule_stop_states(configs) {
+        //        return true          checked outside
+        //    }
+        let mut dup = ATNConfigSet::new_base_atnconfig_set(true);
+        let mut configs = &*configs;
+        if self == PredictionMode::SLL {
+            if configs.has_semantic_context() {
+                configs.get_items().for_each(|it| {
+                    let c = ATNConfig::new_with_semantic(
+                        it.get_state(),
+                        it.get_alt(),
+                        it.get_context().cloned(),
+                        Box::new(SemanticContext::NONE),
+                    );
+                    dup.add(Box::new(c));
+                });
+                configs = &dup;
+            }
+        }
+
+        let altsets = Self::get_conflicting_alt_subsets(&configs);
+        let heuristic =
+            has_conflicting_alt_set(&altsets) && !has_state_associated_with_one_alt(&configs);
+        return heuristic;
+    }
+
+    /// Returns the only alternative predicted by all `altsets`,
+    /// `INVALID_ALT` if they predict different alternatives
+    pub fn resolves_to_just_one_viable_alt(altsets: &[BitSet]) -> isize {
+        Self::get_single_viable_alt(altsets)
+    }
+
+    /// Whether every subset in `altsets` contains more than one alternative
+    pub fn all_subsets_conflict(altsets: &[BitSet]) -> bool {
+        !has_non_conflicting_alt_set(altsets)
+    }
 
-//
-//
-pub(crate) fn has_sll_conflict_terminating_prediction(
-    mode: PredictionMode,
-    configs: &ATNConfigSet,
-) -> bool {
-    //    if all_configs_in_rule_stop_states(configs) {
-    //        return true          checked outside
-    //    }
-    let mut dup = ATNConfigSet::new_base_atnconfig_set(true);
-    let mut configs = &*configs;
-    if mode == PredictionMode::SLL {
-        if configs.has_semantic_context() {
-            configs.get_items().for_each(|it| {
-                let c = ATNConfig::new_with_semantic(
-                    it.get_state(),
-                    it.get_alt(),
-                    it.get_context().cloned(),
-                    Box::new(SemanticContext::NONE),
-                );
-                dup.add(Box::new(c));
-            });
-            configs = &dup;
+    /// Whether all subsets in `altsets` are equal
+    pub fn all_subsets_equal(altsets: &[BitSet]) -> bool {
+        let mut iter = altsets.iter();
+        let first = iter.next();
+        iter.all(|it| it == first.unwrap())
+    }
+
+    /// Union of all `altsets`
+    pub fn get_alts(altsets: &[BitSet]) -> BitSet {
+        altsets.iter().fold(BitSet::new(), |mut acc, it| {
+            acc.extend(it);
+            acc
+        })
+    }
+
+    /// Groups alternatives of `configs` by their state and context,
+    /// alternatives of each group conflict with each other
+    pub fn get_conflicting_alt_subsets(configs: &ATNConfigSet) -> Vec<BitSet> {
+        let mut configs_to_alts: HashMap<(ATNStateRef, &PredictionContext), BitSet> =
+            HashMap::new();
+        for c in configs.get_items() {
+            let alts = configs_to_alts
+                .entry((c.get_state(), c.get_context().unwrap()))
+                .or_default();
+
+            alts.insert(c.get_alt() as usize);
         }
+        configs_to_alts.drain().map(|(_, x)| x).collect()
     }
 
-    let altsets = get_conflicting_alt_subsets(&configs);
-    let heuristic =
-        has_conflicting_alt_set(&altsets) && !has_state_associated_with_one_alt(&configs);
-    return heuristic;
+    /// Returns the minimal alternative if it is the same for all `altsets`,
+    /// `INVALID_ALT` otherwise
+    pub fn get_single_viable_alt(altsets: &[BitSet]) -> isize {
+        let mut viable_alts = BitSet::new();
+        let mut min_alt = INVALID_ALT as usize;
+        for alt in altsets {
+            min_alt = alt.iter().next().unwrap();
+            viable_alts.insert(min_alt);
+            if viable_alts.len() > 1 {
+                return INVALID_ALT;
+            }
+        }
+        min_alt as isize
+    }
 }
 
 //fn all_configs_in_rule_stop_states(configs: &ATNConfigSet) -> bool {
 //    for co
 //}
 
-pub(crate) fn resolves_to_just_one_viable_alt(altsets: &Vec<BitSet>) -> isize {
-    get_single_viable_alt(altsets)
-}
-
-pub(crate) fn all_subsets_conflict(altsets: &Vec<BitSet>) -> bool {
-    !has_non_conflicting_alt_set(altsets)
-}
-
-pub(crate) fn all_subsets_equal(altsets: &Vec<BitSet>) -> bool {
-    let mut iter = altsets.iter();
-    let first = iter.next();
-    iter.all(|it| it == first.unwrap())
-}
-
-fn has_non_conflicting_alt_set(altsets: &Vec<BitSet>) -> bool {
+fn has_non_conflicting_alt_set(altsets: &[BitSet]) -> bool {
     altsets.iter().any(|it| it.len() == 1)
 }
 
-fn has_conflicting_alt_set(altsets: &Vec<BitSet>) -> bool {
+fn has_conflicting_alt_set(altsets: &[BitSet]) -> bool {
     for alts in altsets {
         if alts.len() > 1 {
             return true;
@@ -145,26 +189,6 @@ fn has_conflicting_alt_set(altsets: &Vec<BitSet>) -> bool {
 }
 
 //fn get_unique_alt(altsets: &Vec<BitSet>) -> int { unimplemented!() }
-//
-pub(crate) fn get_alts(altsets: &Vec<BitSet>) -> BitSet {
-    altsets.iter().fold(BitSet::new(), |mut acc, it| {
-        acc.extend(it);
-        acc
-    })
-}
-
-//
-pub(crate) fn get_conflicting_alt_subsets(configs: &ATNConfigSet) -> Vec<BitSet> {
-    let mut configs_to_alts: HashMap<(ATNStateRef, &PredictionContext), BitSet> = HashMap::new();
-    for c in configs.get_items() {
-        let alts = configs_to_alts
-            .entry((c.get_state(), c.get_context().unwrap()))
-            .or_default();
-
-        alts.insert(c.get_alt() as usize);
-    }
-    configs_to_alts.drain().map(|(_, x)| x).collect()
-}
 
 fn get_state_to_alt_map(configs: &ATNConfigSet) -> HashMap<ATNStateRef, BitSet> {
     let mut m = HashMap::new();
@@ -185,15 +209,30 @@ fn has_state_associated_with_one_alt(configs: &ATNConfigSet) -> bool {
     false
 }
 
-pub(crate) fn get_single_viable_alt(altsets: &Vec<BitSet>) -> isize {
-    let mut viable_alts = BitSet::new();
-    let mut min_alt = INVALID_ALT as usize;
-    for alt in altsets {
-        min_alt = alt.iter().next().unwrap();
-        viable_alts.insert(min_alt);
-        if viable_alts.len() > 1 {
-            return INVALID_ALT;
-        }
+#[cfg(test)]
+mod test {
+    use bit_set::BitSet;
+
+    use crate::atn::INVALID_ALT;
+
+    use super::PredictionMode;
+
+    fn alts(alts: &[usize]) -> BitSet { alts.iter().copied().collect() }
+
+    #[test]
+    fn test_alt_subsets() {
+        let conflicting = [alts(&[1, 2]), alts(&[1, 2])];
+        assert!(PredictionMode::all_subsets_conflict(&conflicting));
+        assert!(PredictionMode::all_subsets_equal(&conflicting));
+        assert_eq!(PredictionMode::get_single_viable_alt(&conflicting), 1);
+
+        let different = [alts(&[1, 3]), alts(&[2])];
+        assert!(!PredictionMode::all_subsets_conflict(&different));
+        assert!(!PredictionMode::all_subsets_equal(&different));
+        assert_eq!(
+            PredictionMode::resolves_to_just_one_viable_alt(&different),
+            INVALID_ALT
+        );
+        assert_eq!(PredictionMode::get_alts(&different), alts(&[1, 2, 3]));
     }
-    min_alt as isize
 }
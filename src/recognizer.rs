@@ -1,5 +1,6 @@
 use crate::atn::ATN;
 use crate::parser::ParserNodeType;
+use crate::runtime_info::{check_version_with_policy, CompatibilityPolicy};
 
 use crate::token_factory::TokenAware;
 use crate::vocabulary::Vocabulary;
@@ -13,9 +14,10 @@ pub const VERSION_MINOR: &'static str = env!("CARGO_PKG_VERSION_MINOR");
 
 // todo move to compile time check when it will be possible to compare strings in constants
 /// Used by generated parser to verify that it is compatible with current version of runtime
+///
+/// Same as `check_version_with_policy` with `CompatibilityPolicy::Exact`
 pub fn check_version(major: &str, minor: &str) {
-    assert!(major == VERSION_MAJOR && minor == VERSION_MINOR,
-            "parser is not compatible with current runtime version, please generate parser with the latest version of ANTLR")
+    check_version_with_policy(major, minor, CompatibilityPolicy::Exact)
 }
 //todo just a reminder to update version to be inserted in generated parser,
 //const _:[();0-!(VERSION_MAJOR == "0" && VERSION_MINOR == "2") as usize] = [];
@@ -47,11 +49,47 @@ pub trait Recognizer<'input>: TokenAware<'input> {
     /// Returns array of rule names.
     /// Used for debugging and error reporting
     fn get_rule_names(&self) -> &[&str] { &[] }
-    fn get_vocabulary(&self) -> &dyn Vocabulary { unimplemented!() }
+    fn get_vocabulary(&self) -> &dyn Vocabulary {
+        unimplemented_feature!("vocabulary for recognizers not generated by ANTLR")
+    }
 
     /// Name of the file this recognizer was generated from
     fn get_grammar_file_name(&self) -> &str { "" }
-    fn get_atn(&self) -> &ATN { unimplemented!() }
+    fn get_atn(&self) -> &ATN {
+        unimplemented_feature!("ATN for recognizers not generated by ANTLR")
+    }
+
+    /// Serialized ATN this recognizer was created from.
+    /// Returns the same slice on every call, it is never rebuilt.
+    /// Empty if recognizer was not generated by ANTLR or its ATN is not stored as a string.
+    fn get_serialized_atn(&self) -> &str { "" }
+}
+
+/// Type erased view of a lexer or parser.
+///
+/// Passed to [`ErrorListener`](crate::error_listener::ErrorListener) instead of concrete
+/// recognizer type, so that the same listener instance can be registered on both lexer and parser.
+pub trait RecognizerRef {
+    /// Rule names of this recognizer
+    fn rule_names(&self) -> &[&str];
+
+    /// Token vocabulary, if recognizer provides one.
+    /// Generated lexers and parsers, as well as interpreters, always do.
+    fn vocabulary(&self) -> Option<&dyn Vocabulary>;
+
+    /// Name of the file this recognizer was generated from
+    fn grammar_file_name(&self) -> &str;
+
+    /// ATN this recognizer uses for prediction, available only for parsers
+    fn atn(&self) -> Option<&ATN>;
+
+    /// Text of the input between `start` and `stop`, inclusive.
+    /// Indexes are token indexes for parser and char indexes for lexer
+    fn input_text(&self, start: isize, stop: isize) -> String;
+
+    /// Reports `msg` to all error listeners of this recognizer as a syntax error
+    /// at current position without associated `ANTLRError`
+    fn report_diagnostic(&self, msg: String);
 }
 
 /// **! Usually generated by ANTLR !**
@@ -79,11 +117,24 @@ pub trait Actions<'a, P: Recognizer<'a>> {
     /// Returns array of rule names.
     /// Used for debugging and error reporting
     fn get_rule_names(&self) -> &[&str] { &[] }
-    fn get_vocabulary(&self) -> &dyn Vocabulary { unimplemented!() }
+    fn get_vocabulary(&self) -> &dyn Vocabulary {
+        unimplemented_feature!("vocabulary for recognizers not generated by ANTLR")
+    }
 
     /// Name of the file this recognizer was generated from
     fn get_grammar_file_name(&self) -> &str { "" }
-    fn get_atn(&self) -> &ATN { unimplemented!() }
+    fn get_atn(&self) -> &ATN {
+        unimplemented_feature!("ATN for recognizers not generated by ANTLR")
+    }
+
+    /// Serialized ATN this recognizer was created from.
+    /// Returns the same slice on every call, it is never rebuilt.
+    /// Empty if recognizer was not generated by ANTLR or its ATN is not stored as a string.
+    fn get_serialized_atn(&self) -> &str { "" }
+
+    /// Returns array of lexer mode names, empty for parsers.
+    /// Used for debugging
+    fn get_mode_names(&self) -> &[&str] { &[] }
 }
 
 //impl Recognizer for BaseRecognizer {
@@ -10,7 +10,7 @@ use crate::atn::INVALID_ALT;
 use crate::parser::ParserNodeType;
 use crate::parser_rule_context::ParserRuleContext;
 use crate::token_factory::TokenFactory;
-use crate::tree::{NodeText, ParseTree, Tree};
+use crate::tree::{Listenable, NodeText, ParseTree, ParseTreeListener, Tree};
 use better_any::{Tid, TidAble};
 use std::any::type_name;
 
@@ -98,6 +98,23 @@ impl<'a, TF: TokenFactory<'a>> ParserNodeType<'a> for EmptyContextType<'a, TF> {
     // type Visitor = dyn ParseTreeVisitor<'a, Self> + 'a;
 }
 
+impl<'input, 'a, TF: TokenFactory<'input> + 'input>
+    Listenable<dyn ParseTreeListener<'input, EmptyContextType<'input, TF>> + 'a>
+    for EmptyContext<'input, TF>
+{
+    fn enter(
+        &self, listener: &mut (dyn ParseTreeListener<'input, EmptyContextType<'input, TF>> + 'a),
+    ) {
+        listener.enter_every_rule(self)
+    }
+
+    fn exit(
+        &self, listener: &mut (dyn ParseTreeListener<'input, EmptyContextType<'input, TF>> + 'a),
+    ) {
+        listener.exit_every_rule(self)
+    }
+}
+
 /// Implemented by generated parser for context extension for particular rule
 #[allow(missing_docs)]
 pub trait CustomRuleContext<'input> {
@@ -0,0 +1,203 @@
+//! Per-rule timing statistics
+use std::time::{Duration, Instant};
+
+use crate::parser::ParserNodeType;
+use crate::recognizer::Recognizer;
+use crate::rule_context::CustomRuleContext;
+use crate::tree::ParseTreeListener;
+
+/// Parse listener that measures how much time parser spends in each rule.
+///
+/// Time is attributed as self time, i.e. time spent in nested rule invocations is subtracted
+/// from the time of invoking rule, so recursive rules are not counted several times.
+/// Each enter/exit event costs exactly one `Instant::now()` call,
+/// and nothing is measured unless profiler is added to the parser.
+///
+/// To use it with generated parser, implement generated listener trait for it
+/// (all methods have default implementations):
+/// ```text
+/// impl<'input> CSVListener<'input> for RuleProfiler {}
+/// let id = parser.add_parse_listener(Box::new(RuleProfiler::new()));
+/// parser.csvFile();
+/// let profiler = parser.remove_parse_listener(id);
+/// println!("{:?}", profiler.report(&*parser));
+/// ```
+#[derive(Debug, Default)]
+pub struct RuleProfiler {
+    stack: Vec<Frame>,
+    stats: Vec<RuleStats>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    rule_index: usize,
+    start: Instant,
+    children_time: Duration,
+}
+
+/// Statistics for single rule
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct RuleStats {
+    /// Number of times rule was entered
+    pub invocations: usize,
+    /// Time spent in this rule excluding time spent in rules invoked from it
+    pub self_time: Duration,
+    /// Longest single invocation, including nested rules
+    pub max_invocation: Duration,
+}
+
+/// Row of `RuleProfiler::report`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RuleReport {
+    /// Rule index
+    pub rule_index: usize,
+    /// Rule name, or its index if recognizer does not provide names
+    pub rule_name: String,
+    /// Collected statistics
+    pub stats: RuleStats,
+}
+
+impl RuleProfiler {
+    /// Creates new profiler
+    pub fn new() -> RuleProfiler { Self::default() }
+
+    /// Records entering into rule `rule_index`
+    pub fn enter_rule(&mut self, rule_index: usize) {
+        self.enter_rule_at(rule_index, Instant::now())
+    }
+
+    /// Records exit from the most recently entered rule
+    pub fn exit_rule(&mut self) { self.exit_rule_at(Instant::now()) }
+
+    pub(crate) fn enter_rule_at(&mut self, rule_index: usize, now: Instant) {
+        self.stack.push(Frame {
+            rule_index,
+            start: now,
+            children_time: Duration::default(),
+        })
+    }
+
+    pub(crate) fn exit_rule_at(&mut self, now: Instant) {
+        let frame = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = now.saturating_duration_since(frame.start);
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children_time += elapsed;
+        }
+        if self.stats.len() <= frame.rule_index {
+            self.stats
+                .resize(frame.rule_index + 1, RuleStats::default());
+        }
+        let stats = &mut self.stats[frame.rule_index];
+        stats.invocations += 1;
+        stats.self_time += elapsed.checked_sub(frame.children_time).unwrap_or_default();
+        stats.max_invocation = stats.max_invocation.max(elapsed);
+    }
+
+    /// Statistics for rule `rule_index`, if it was invoked at least once
+    pub fn get_stats(&self, rule_index: usize) -> Option<&RuleStats> {
+        self.stats
+            .get(rule_index)
+            .filter(|stats| stats.invocations > 0)
+    }
+
+    /// Returns statistics for all invoked rules, sorted by self time in descending order
+    pub fn report<'input, R: Recognizer<'input> + ?Sized>(&self, recog: &R) -> Vec<RuleReport> {
+        let rule_names = recog.get_rule_names();
+        let mut result = self
+            .stats
+            .iter()
+            .enumerate()
+            .filter(|(_, stats)| stats.invocations > 0)
+            .map(|(rule_index, stats)| RuleReport {
+                rule_index,
+                rule_name: rule_names
+                    .get(rule_index)
+                    .map(|&it| it.to_owned())
+                    .unwrap_or_else(|| rule_index.to_string()),
+                stats: stats.clone(),
+            })
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| b.stats.self_time.cmp(&a.stats.self_time));
+        result
+    }
+
+    /// Clears collected statistics
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.stats.clear();
+    }
+}
+
+impl<'input, Node: ParserNodeType<'input>> ParseTreeListener<'input, Node> for RuleProfiler {
+    fn enter_every_rule(&mut self, ctx: &Node::Type) { self.enter_rule(ctx.get_rule_index()) }
+
+    fn exit_every_rule(&mut self, _ctx: &Node::Type) { self.exit_rule() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::RuleProfiler;
+
+    fn ms(v: u64) -> Duration { Duration::from_millis(v) }
+
+    #[test]
+    fn test_self_time_attribution() {
+        let t = Instant::now();
+        let mut profiler = RuleProfiler::new();
+        profiler.enter_rule_at(0, t);
+        profiler.enter_rule_at(1, t + ms(10));
+        profiler.exit_rule_at(t + ms(40));
+        profiler.enter_rule_at(1, t + ms(45));
+        profiler.exit_rule_at(t + ms(50));
+        profiler.exit_rule_at(t + ms(60));
+
+        let root = profiler.get_stats(0).unwrap();
+        assert_eq!(root.invocations, 1);
+        assert_eq!(root.self_time, ms(25));
+        assert_eq!(root.max_invocation, ms(60));
+        let child = profiler.get_stats(1).unwrap();
+        assert_eq!(child.invocations, 2);
+        assert_eq!(child.self_time, ms(35));
+        assert_eq!(child.max_invocation, ms(30));
+    }
+
+    #[test]
+    fn test_recursion() {
+        let t = Instant::now();
+        let mut profiler = RuleProfiler::new();
+        // expr -> expr -> expr, each level spends 10ms by itself
+        profiler.enter_rule_at(2, t);
+        profiler.enter_rule_at(2, t + ms(10));
+        profiler.enter_rule_at(2, t + ms(20));
+        profiler.exit_rule_at(t + ms(30));
+        profiler.exit_rule_at(t + ms(40));
+        profiler.exit_rule_at(t + ms(50));
+
+        let stats = profiler.get_stats(2).unwrap();
+        assert_eq!(stats.invocations, 3);
+        assert_eq!(stats.self_time, ms(50));
+        assert_eq!(stats.max_invocation, ms(50));
+        assert!(profiler.get_stats(0).is_none());
+    }
+
+    #[test]
+    fn test_real_clock() {
+        let mut profiler = RuleProfiler::new();
+        profiler.enter_rule(0);
+        std::thread::sleep(ms(5));
+        profiler.enter_rule(1);
+        std::thread::sleep(ms(20));
+        profiler.exit_rule();
+        profiler.exit_rule();
+        let outer = profiler.get_stats(0).unwrap();
+        let inner = profiler.get_stats(1).unwrap();
+        assert!(inner.self_time >= ms(20));
+        assert!(outer.self_time >= ms(5));
+        assert!(outer.max_invocation >= inner.self_time + outer.self_time);
+    }
+}
@@ -0,0 +1,175 @@
+//! Information about this runtime build for tools that generate or load code for it.
+//!
+//! ```
+//! use antlr_rust::runtime_info::runtime_info;
+//! let info = runtime_info();
+//! assert!(info.supports_atn_format(3));
+//! assert_eq!(info.supports_feature("testkit"), cfg!(feature = "testkit"));
+//! ```
+use crate::atn_deserializer::{SERIALIZED_VERSION, SERIALIZED_VERSION_4};
+
+/// Optional cargo features this runtime has been compiled with
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "bench")]
+    "bench",
+    #[cfg(feature = "interchange")]
+    "interchange",
+    #[cfg(feature = "mmap")]
+    "mmap",
+    #[cfg(feature = "rope")]
+    "rope",
+    #[cfg(feature = "testkit")]
+    "testkit",
+    #[cfg(feature = "debug-diagnostics")]
+    "debug-diagnostics",
+];
+
+const ATN_FORMATS: &[u32] = &[SERIALIZED_VERSION as u32, SERIALIZED_VERSION_4 as u32];
+
+#[cfg(feature = "interchange")]
+const INTERCHANGE_FORMATS: &[u64] = &[crate::tree_interchange::FORMAT_VERSION];
+#[cfg(not(feature = "interchange"))]
+const INTERCHANGE_FORMATS: &[u64] = &[];
+
+/// What this runtime build supports, see `runtime_info`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RuntimeInfo {
+    /// `(major, minor, patch)` version of the runtime crate
+    pub version: (u32, u32, u32),
+    /// Versions of serialized ATN format that can be deserialized
+    pub supported_atn_formats: &'static [u32],
+    /// Versions of `tree_interchange` format, empty if `interchange` feature is disabled
+    pub supported_interchange_formats: &'static [u64],
+    /// Optional cargo features this runtime has been compiled with
+    pub features: &'static [&'static str],
+}
+
+/// Returns information about this runtime build
+pub fn runtime_info() -> RuntimeInfo {
+    RuntimeInfo {
+        version: parse_version(env!("CARGO_PKG_VERSION")),
+        supported_atn_formats: ATN_FORMATS,
+        supported_interchange_formats: INTERCHANGE_FORMATS,
+        features: FEATURES,
+    }
+}
+
+fn parse_version(version: &str) -> (u32, u32, u32) {
+    // pre-release and build metadata are ignored
+    let mut parts = version
+        .split(|c: char| c == '-' || c == '+')
+        .next()
+        .unwrap()
+        .split('.')
+        .map(|it| it.parse().unwrap_or(0));
+    let mut next = || parts.next().unwrap_or(0);
+    (next(), next(), next())
+}
+
+impl RuntimeInfo {
+    /// Returns true if ATN serialized with format `version` can be deserialized
+    pub fn supports_atn_format(&self, version: u32) -> bool {
+        self.supported_atn_formats.contains(&version)
+    }
+
+    /// Returns true if runtime has been compiled with cargo feature `name`
+    pub fn supports_feature(&self, name: &str) -> bool { self.features.contains(&name) }
+
+    /// Returns true if code generated for runtime `major.minor` version
+    /// can be used with this runtime according to `policy`
+    pub fn is_compatible(&self, major: &str, minor: &str, policy: CompatibilityPolicy) -> bool {
+        let (actual_major, actual_minor, _) = self.version;
+        let same_major = major.parse().ok() == Some(actual_major);
+        match policy {
+            CompatibilityPolicy::Exact => same_major && minor.parse().ok() == Some(actual_minor),
+            CompatibilityPolicy::SameMajor => same_major,
+            CompatibilityPolicy::Any => true,
+        }
+    }
+}
+
+/// Which runtime versions generated code is allowed to run with
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CompatibilityPolicy {
+    /// Major and minor versions must be equal, used by generated code
+    Exact,
+    /// Only major versions must be equal
+    SameMajor,
+    /// Any version, for embedders that knowingly mix versions
+    Any,
+}
+
+/// Panics if code generated for runtime `major.minor` version
+/// can't be used with this runtime according to `policy`
+pub fn check_version_with_policy(major: &str, minor: &str, policy: CompatibilityPolicy) {
+    let info = runtime_info();
+    if !info.is_compatible(major, minor, policy) {
+        let (actual_major, actual_minor, _) = info.version;
+        panic!(
+            "parser generated for runtime {}.{} is not compatible with current runtime \
+             version {}.{} ({:?} policy), please generate parser with the latest version of ANTLR",
+            major, minor, actual_major, actual_minor, policy
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::recognizer::{VERSION_MAJOR, VERSION_MINOR};
+
+    #[test]
+    fn test_runtime_info() {
+        let info = runtime_info();
+        let (major, minor, _) = info.version;
+        assert_eq!(major.to_string(), VERSION_MAJOR);
+        assert_eq!(minor.to_string(), VERSION_MINOR);
+        assert_eq!(parse_version("1.22.3-alpha.1+build"), (1, 22, 3));
+
+        assert!(info.supports_atn_format(3));
+        assert!(info.supports_atn_format(4));
+        assert!(!info.supports_atn_format(5));
+        assert_eq!(
+            info.supported_interchange_formats.is_empty(),
+            !cfg!(feature = "interchange")
+        );
+        for &(feature, enabled) in &[
+            ("bench", cfg!(feature = "bench")),
+            ("interchange", cfg!(feature = "interchange")),
+            ("mmap", cfg!(feature = "mmap")),
+            ("rope", cfg!(feature = "rope")),
+            ("testkit", cfg!(feature = "testkit")),
+            ("debug-diagnostics", cfg!(feature = "debug-diagnostics")),
+        ] {
+            assert_eq!(info.supports_feature(feature), enabled, "{}", feature);
+        }
+        assert!(!info.supports_feature("heavy_tests"));
+    }
+
+    #[test]
+    fn test_compatibility_policy() {
+        let info = RuntimeInfo {
+            version: (1, 2, 3),
+            ..runtime_info()
+        };
+        use CompatibilityPolicy::*;
+        let cases = [
+            (("1", "2"), [true, true, true]),
+            (("1", "3"), [false, true, true]),
+            (("2", "2"), [false, false, true]),
+            (("x", "2"), [false, false, true]),
+        ];
+        for &((major, minor), expected) in &cases {
+            let actual = [Exact, SameMajor, Any]
+                .iter()
+                .map(|&policy| info.is_compatible(major, minor, policy))
+                .collect::<Vec<_>>();
+            assert_eq!(actual, expected, "{}.{}", major, minor);
+        }
+
+        check_version_with_policy(VERSION_MAJOR, VERSION_MINOR, Exact);
+        check_version_with_policy("1000", "0", Any);
+        let result = std::panic::catch_unwind(|| check_version_with_policy("1000", "0", SameMajor));
+        assert!(result.is_err());
+    }
+}
@@ -235,7 +235,10 @@ impl SemanticContext {
 fn sort_prec_pred(a: &&SemanticContext, b: &&SemanticContext) -> Ordering {
     match (*a, *b) {
         (SemanticContext::Precedence(a), SemanticContext::Precedence(b)) => a.cmp(b),
-        _ => panic!("shoudl be sorting list of precedence predicates"),
+        (a, b) => runtime_panic!(
+            { a: a, b: b },
+            "should be sorting list of precedence predicates"
+        ),
     }
 }
 
@@ -0,0 +1,1017 @@
+//! Test kit for generated parsers and other code that drives the runtime.
+//!
+//! Code generator authors and downstream crates can use it to check that their code
+//! talks to the runtime correctly without writing mocks by hand:
+//!  - [`MockTokenSource`] and [`MockCharStream`] are scripted inputs with failure injection,
+//!  - [`EventRecorder`] is an error listener and parse listener that captures all callbacks
+//!    into a `Vec<Event>` which can be compared with the expected one,
+//!  - [`assert_tree_eq`], [`assert_diagnostics_eq`] and [`assert_events_eq`]
+//!    give readable messages on mismatch,
+//!  - [`LexerFixture`]s are small checked in lexer ATNs with documented expected behavior,
+//!    they can be run without any generated code.
+//!
+//! Only available with `testkit` feature enabled.
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use better_any::{Tid, TidAble};
+use bit_set::BitSet;
+
+use crate::atn::ATN;
+use crate::atn_config_set::ATNConfigSet;
+use crate::atn_deserializer::ATNDeserializer;
+use crate::char_stream::{CharStream, InputData};
+use crate::dfa::DFA;
+use crate::error_listener::{Diagnostic, ErrorListener};
+use crate::errors::ANTLRError;
+use crate::int_stream::{panic_consume_eof, IntStream, EOF, LA_UNDEFINED};
+use crate::interval_set::CharInterval;
+use crate::lexer::{BaseLexer, LexerRecog};
+use crate::lexer_atn_simulator::LexerATNSimulator;
+use crate::parser::ParserNodeType;
+use crate::recognizer::{Actions, RecognizerRef};
+use crate::rule_context::CustomRuleContext;
+use crate::token::{OwningToken, Token, TOKEN_DEFAULT_CHANNEL, TOKEN_EOF};
+use crate::token_factory::{CommonTokenFactory, TokenAware, TokenFactory};
+use crate::token_source::TokenSource;
+use crate::tree::{ErrorNode, ParseTree, ParseTreeListener, TerminalNode, Tree};
+use crate::trees;
+use crate::utils::escape_whitespaces;
+use crate::vocabulary::{Vocabulary, VocabularyImpl};
+use crate::{InputStream, PredictionContextCache, RwLock};
+
+#[derive(Debug)]
+enum ScriptStep {
+    Token {
+        token_type: isize,
+        text: String,
+        channel: isize,
+    },
+    Failure(String),
+}
+
+/// `TokenSource` that returns scripted tokens and then EOF forever.
+///
+/// Tokens get char positions, lines and columns as if their texts were concatenated
+/// without separators, so `CommonTokenStream::get_all_text` returns the scripted input.
+/// Fetch can be made to fail with `io::Error` to check handling of token source failures.
+/// ```
+/// use antlr_rust::testkit::MockTokenSource;
+/// use antlr_rust::token_factory::CommonTokenFactory;
+///
+/// let source = MockTokenSource::new(&CommonTokenFactory)
+///     .token(1, "a")
+///     .fail_next("connection reset")
+///     .token(2, "b");
+/// ```
+#[derive(Tid, Debug)]
+pub struct MockTokenSource<'input, TF: TokenFactory<'input>> {
+    factory: &'input TF,
+    script: VecDeque<ScriptStep>,
+    source_name: String,
+    char_index: isize,
+    line: isize,
+    column: isize,
+    fetched: usize,
+    failures: usize,
+}
+
+impl<'input, TF> MockTokenSource<'input, TF>
+where
+    TF: TokenFactory<'input, Data = str>,
+    TF::From: From<String>,
+{
+    /// Creates token source with empty script that creates tokens with `factory`
+    pub fn new(factory: &'input TF) -> Self {
+        MockTokenSource {
+            factory,
+            script: VecDeque::new(),
+            source_name: "<mock>".to_owned(),
+            char_index: 0,
+            line: 1,
+            column: 0,
+            fetched: 0,
+            failures: 0,
+        }
+    }
+
+    /// Appends token on the default channel to the script
+    pub fn token(self, token_type: isize, text: &str) -> Self {
+        self.token_on_channel(token_type, text, TOKEN_DEFAULT_CHANNEL)
+    }
+
+    /// Appends token on `channel` to the script
+    pub fn token_on_channel(mut self, token_type: isize, text: &str, channel: isize) -> Self {
+        self.script.push_back(ScriptStep::Token {
+            token_type,
+            text: text.to_owned(),
+            channel,
+        });
+        self
+    }
+
+    /// Appends tokens on the default channel to the script
+    pub fn tokens(self, tokens: &[(isize, &str)]) -> Self {
+        tokens.iter().fold(self, |source, &(token_type, text)| {
+            source.token(token_type, text)
+        })
+    }
+
+    /// Makes the next fetch after already scripted tokens fail with `io::ErrorKind::Other`
+    /// error with `message`. Fetch after the failure continues with the rest of the script,
+    /// so several calls in a row make the same token fail several times.
+    pub fn fail_next(mut self, message: &str) -> Self {
+        self.script
+            .push_back(ScriptStep::Failure(message.to_owned()));
+        self
+    }
+
+    /// Sets name returned by `get_source_name`, `<mock>` by default
+    pub fn with_source_name(mut self, name: &str) -> Self {
+        self.source_name = name.to_owned();
+        self
+    }
+
+    /// Number of tokens returned so far, including EOF tokens
+    pub fn fetched(&self) -> usize { self.fetched }
+
+    /// Number of injected failures returned so far
+    pub fn failures(&self) -> usize { self.failures }
+
+    fn create_token(&mut self, token_type: isize, text: String, channel: isize) -> TF::Tok {
+        let len = if token_type == TOKEN_EOF {
+            0
+        } else {
+            text.chars().count() as isize
+        };
+        let (line, column, start) = (self.line, self.column, self.char_index);
+        if token_type != TOKEN_EOF {
+            for ch in text.chars() {
+                if ch == '\n' {
+                    self.line += 1;
+                    self.column = 0;
+                } else {
+                    self.column += 1;
+                }
+            }
+            self.char_index += len;
+        }
+        let token = self.factory.create_with_end(
+            None::<&mut MockCharStream>,
+            token_type,
+            Some(text),
+            channel,
+            start,
+            start + len - 1,
+            line,
+            column,
+            self.line,
+            self.column,
+        );
+        self.fetched += 1;
+        token
+    }
+}
+
+impl<'input, TF> TokenSource<'input> for MockTokenSource<'input, TF>
+where
+    TF: TokenFactory<'input, Data = str>,
+    TF::From: From<String>,
+{
+    type TF = TF;
+
+    fn next_token(&mut self) -> TF::Tok {
+        self.try_next_token()
+            .unwrap_or_else(|e| panic!("injected token source failure: {}", e))
+    }
+
+    fn try_next_token(&mut self) -> io::Result<TF::Tok> {
+        match self.script.pop_front() {
+            Some(ScriptStep::Failure(message)) => {
+                self.failures += 1;
+                Err(io::Error::new(io::ErrorKind::Other, message))
+            }
+            Some(ScriptStep::Token {
+                token_type,
+                text,
+                channel,
+            }) => Ok(self.create_token(token_type, text, channel)),
+            None => Ok(self.create_token(TOKEN_EOF, "<EOF>".to_owned(), TOKEN_DEFAULT_CHANNEL)),
+        }
+    }
+
+    fn get_line(&self) -> isize { self.line }
+
+    fn get_char_position_in_line(&self) -> isize { self.column }
+
+    fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { None }
+
+    fn get_source_name(&self) -> String { self.source_name.clone() }
+
+    fn get_token_factory(&self) -> &'input TF { self.factory }
+}
+
+/// `CharStream` over a string that checks how it is driven by the lexer.
+///
+/// It counts seeks and tracks the largest lookahead, can simulate unbuffered input
+/// which forgets chars that are not protected by `mark`, and can fail at given char index.
+/// All violations and injected failures are panics, same as with real char streams.
+#[derive(Debug)]
+pub struct MockCharStream {
+    data: Vec<char>,
+    index: isize,
+    markers: Vec<(isize, isize)>,
+    next_marker: isize,
+    discarded: isize,
+    unbuffered: bool,
+    fail_at: Option<(isize, String)>,
+    max_lookahead: isize,
+    seeks: usize,
+    source_name: String,
+}
+
+impl MockCharStream {
+    /// Creates stream over `text`
+    pub fn new(text: &str) -> Self {
+        MockCharStream {
+            data: text.chars().collect(),
+            index: 0,
+            markers: Vec::new(),
+            next_marker: 0,
+            discarded: 0,
+            unbuffered: false,
+            fail_at: None,
+            max_lookahead: 0,
+            seeks: 0,
+            source_name: "<mock>".to_owned(),
+        }
+    }
+
+    /// Makes stream forget chars before the current position unless they are protected
+    /// by an active `mark`, like unbuffered streams do.
+    /// Accessing forgotten chars panics, except for `la(-1)` of the first available char.
+    pub fn unbuffered(mut self) -> Self {
+        self.unbuffered = true;
+        self
+    }
+
+    /// Makes stream panic with `message` when char at `index` or after it is requested,
+    /// simulating input that can't be read
+    pub fn fail_at(mut self, index: isize, message: &str) -> Self {
+        self.fail_at = Some((index, message.to_owned()));
+        self
+    }
+
+    /// Largest `i` `la(i)` was called with
+    pub fn max_lookahead(&self) -> isize { self.max_lookahead }
+
+    /// Number of `seek` calls
+    pub fn seeks(&self) -> usize { self.seeks }
+
+    /// Number of markers that were not released yet
+    pub fn active_markers(&self) -> usize { self.markers.len() }
+
+    fn check_available(&self, index: isize) {
+        if let Some((fail_at, message)) = &self.fail_at {
+            if index >= *fail_at && index < self.data.len() as isize {
+                panic!("{}", message)
+            }
+        }
+        if self.unbuffered && index < self.discarded {
+            panic!(
+                "char at {} is not available anymore, stream has been released up to {}",
+                index, self.discarded
+            )
+        }
+    }
+
+    fn forget(&mut self) {
+        if self.unbuffered {
+            let oldest = self
+                .markers
+                .iter()
+                .map(|it| it.1)
+                .min()
+                .unwrap_or(self.index);
+            self.discarded = self.discarded.max(oldest.min(self.index));
+        }
+    }
+}
+
+impl IntStream for MockCharStream {
+    fn consume(&mut self) {
+        if self.index >= self.data.len() as isize {
+            panic_consume_eof()
+        }
+        self.index += 1;
+        self.forget();
+    }
+
+    fn la(&mut self, i: isize) -> isize {
+        if i == 0 {
+            return LA_UNDEFINED;
+        }
+        let index = if i > 0 {
+            self.max_lookahead = self.max_lookahead.max(i);
+            self.index + i - 1
+        } else {
+            self.index + i
+        };
+        if index < 0 || index >= self.data.len() as isize {
+            return EOF;
+        }
+        // last consumed char stays available for `la(-1)`
+        self.check_available(if i == -1 { index + 1 } else { index });
+        self.data[index as usize] as isize
+    }
+
+    fn mark(&mut self) -> isize {
+        let marker = self.next_marker;
+        self.next_marker += 1;
+        self.markers.push((marker, self.index));
+        marker
+    }
+
+    fn release(&mut self, marker: isize) {
+        match self.markers.last() {
+            Some(&(last, _)) if last == marker => self.markers.pop(),
+            _ => panic!(
+                "marker {} released out of order, active markers: {:?}",
+                marker, self.markers
+            ),
+        };
+        self.forget();
+    }
+
+    fn index(&self) -> isize { self.index }
+
+    fn seek(&mut self, index: isize) {
+        let index = index.max(0).min(self.data.len() as isize);
+        self.check_available(index);
+        self.seeks += 1;
+        self.index = index;
+        self.forget();
+    }
+
+    fn size(&self) -> isize { self.data.len() as isize }
+
+    fn get_source_name(&self) -> String { self.source_name.clone() }
+}
+
+impl<D: From<String>> CharStream<D> for MockCharStream {
+    fn get_text(&self, interval: CharInterval) -> D {
+        let CharInterval { a, b } = interval;
+        if b < a {
+            return String::new().into();
+        }
+        self.check_available(a);
+        let b = b.min(self.data.len() as isize - 1);
+        self.data[a as usize..=b as usize]
+            .iter()
+            .collect::<String>()
+            .into()
+    }
+}
+
+/// Callback captured by `EventRecorder`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Event {
+    /// `ErrorListener::syntax_error`
+    SyntaxError {
+        /// Line of the error
+        line: isize,
+        /// Column of the error
+        column: isize,
+        /// Error message
+        message: String,
+        /// Token index of the offending token, `None` for lexer errors
+        /// and `Some(-1)` for tokens conjured up by error recovery
+        offending_token: Option<isize>,
+    },
+    /// `ErrorListener::report_ambiguity`
+    Ambiguity {
+        /// Decision number
+        decision: isize,
+        /// Token index where prediction started
+        start_index: isize,
+        /// Token index where ambiguity was detected
+        stop_index: isize,
+        /// Whether ambiguity is exact
+        exact: bool,
+        /// Ambiguous alternatives
+        alts: Vec<usize>,
+    },
+    /// `ErrorListener::report_attempting_full_context`
+    AttemptingFullContext {
+        /// Decision number
+        decision: isize,
+        /// Token index where prediction started
+        start_index: isize,
+        /// Token index where SLL conflict was detected
+        stop_index: isize,
+    },
+    /// `ErrorListener::report_context_sensitivity`
+    ContextSensitivity {
+        /// Decision number
+        decision: isize,
+        /// Token index where prediction started
+        start_index: isize,
+        /// Token index where full context prediction finished
+        stop_index: isize,
+        /// Predicted alternative
+        prediction: isize,
+    },
+    /// `ErrorListener::report_dfa_growth`
+    DfaGrowth {
+        /// Decision number
+        decision: isize,
+        /// Name of the rule containing the decision
+        rule_name: String,
+        /// Number of DFA states
+        state_count: usize,
+        /// Token index where prediction started
+        start_index: isize,
+        /// Token index where the latest DFA state was added
+        input_index: isize,
+    },
+    /// `ParseTreeListener::enter_every_rule` with rule index
+    EnterRule(usize),
+    /// `ParseTreeListener::exit_every_rule` with rule index
+    ExitRule(usize),
+    /// `ParseTreeListener::visit_terminal` with token text
+    Terminal(String),
+    /// `ParseTreeListener::visit_error_node` with token text
+    ErrorNode(String),
+}
+
+/// Error listener and parse listener that records all callbacks as `Event`s.
+///
+/// Clones share the same event log, so one clone can be added to parser as a parse listener,
+/// another one to the parser and lexer as an error listener, and events from all of them
+/// are recorded in the order they happened.
+/// To be added as a parse listener of a generated parser it needs an (empty) implementation
+/// of the generated listener trait, e.g. `impl<'input> MyListener<'input> for EventRecorder {}`.
+#[derive(Debug, Clone, Default)]
+pub struct EventRecorder {
+    events: Rc<RefCell<Vec<Event>>>,
+}
+
+impl EventRecorder {
+    /// Creates recorder with empty event log
+    pub fn new() -> Self { Self::default() }
+
+    /// Returns copy of recorded events
+    pub fn events(&self) -> Vec<Event> { self.events.borrow().clone() }
+
+    /// Returns recorded events and clears the log
+    pub fn take_events(&self) -> Vec<Event> { self.events.take() }
+
+    /// Recorded syntax errors formatted as `line:column message`
+    pub fn syntax_errors(&self) -> Vec<String> {
+        self.events
+            .borrow()
+            .iter()
+            .filter_map(|event| match event {
+                Event::SyntaxError {
+                    line,
+                    column,
+                    message,
+                    ..
+                } => Some(format!("{}:{} {}", line, column, message)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, event: Event) { self.events.borrow_mut().push(event) }
+}
+
+impl ErrorListener for EventRecorder {
+    fn syntax_error(
+        &self, _recognizer: &dyn RecognizerRef, offending_symbol: Option<&OwningToken>,
+        line: isize, column: isize, msg: &str, _error: Option<&ANTLRError>,
+    ) {
+        self.record(Event::SyntaxError {
+            line,
+            column,
+            message: msg.to_owned(),
+            offending_token: offending_symbol.map(|it| it.get_token_index()),
+        })
+    }
+
+    fn report_ambiguity(
+        &self, _recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        exact: bool, ambig_alts: &BitSet, _configs: &ATNConfigSet,
+    ) {
+        self.record(Event::Ambiguity {
+            decision: dfa.decision,
+            start_index,
+            stop_index,
+            exact,
+            alts: ambig_alts.iter().collect(),
+        })
+    }
+
+    fn report_attempting_full_context(
+        &self, _recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        _conflicting_alts: &BitSet, _configs: &ATNConfigSet,
+    ) {
+        self.record(Event::AttemptingFullContext {
+            decision: dfa.decision,
+            start_index,
+            stop_index,
+        })
+    }
+
+    fn report_context_sensitivity(
+        &self, _recognizer: &dyn RecognizerRef, dfa: &DFA, start_index: isize, stop_index: isize,
+        prediction: isize, _configs: &ATNConfigSet,
+    ) {
+        self.record(Event::ContextSensitivity {
+            decision: dfa.decision,
+            start_index,
+            stop_index,
+            prediction,
+        })
+    }
+
+    fn report_dfa_growth(
+        &self, _recognizer: &dyn RecognizerRef, decision: isize, rule_name: &str,
+        state_count: usize, start_index: isize, input_index: isize,
+    ) {
+        self.record(Event::DfaGrowth {
+            decision,
+            rule_name: rule_name.to_owned(),
+            state_count,
+            start_index,
+            input_index,
+        })
+    }
+}
+
+impl<'input, Node: ParserNodeType<'input>> ParseTreeListener<'input, Node> for EventRecorder {
+    fn visit_terminal(&mut self, node: &TerminalNode<'input, Node>) {
+        self.record(Event::Terminal(node.get_text()))
+    }
+
+    fn visit_error_node(&mut self, node: &ErrorNode<'input, Node>) {
+        self.record(Event::ErrorNode(node.get_text()))
+    }
+
+    fn enter_every_rule(&mut self, ctx: &Node::Type) {
+        self.record(Event::EnterRule(ctx.get_rule_index()))
+    }
+
+    fn exit_every_rule(&mut self, ctx: &Node::Type) {
+        self.record(Event::ExitRule(ctx.get_rule_index()))
+    }
+}
+
+/// Asserts that LISP style representation of `tree` (see `trees::string_tree`) is `expected`.
+/// On mismatch panics with both trees and the position where they start to differ.
+pub fn assert_tree_eq<'a, T: Tree<'a> + ?Sized>(tree: &T, rule_names: &[&str], expected: &str) {
+    let actual = trees::string_tree(tree, rule_names);
+    if actual != expected {
+        let position = actual
+            .chars()
+            .zip(expected.chars())
+            .take_while(|(actual, expected)| actual == expected)
+            .count();
+        panic!(
+            "parse trees differ at char {}\n  actual: {}\nexpected: {}",
+            position, actual, expected
+        )
+    }
+}
+
+/// Asserts that `diagnostics` formatted as `line:column message` are equal to `expected`.
+pub fn assert_diagnostics_eq(diagnostics: &[Diagnostic], expected: &[&str]) {
+    let actual = diagnostics
+        .iter()
+        .map(|it| format!("{}:{} {}", it.line, it.column, it.message))
+        .collect::<Vec<_>>();
+    if actual != expected {
+        panic!(
+            "diagnostics differ\n  actual: {:#?}\nexpected: {:#?}",
+            actual, expected
+        )
+    }
+}
+
+/// Asserts that `events` are equal to `expected`, on mismatch panics with the first
+/// differing event and both event lists.
+pub fn assert_events_eq(events: &[Event], expected: &[Event]) {
+    if events == expected {
+        return;
+    }
+    let position = events
+        .iter()
+        .zip(expected)
+        .take_while(|(actual, expected)| actual == expected)
+        .count();
+    panic!(
+        "events differ at {}: {:?} != {:?}\n  actual: {:#?}\nexpected: {:#?}",
+        position,
+        events.get(position),
+        expected.get(position),
+        events,
+        expected
+    )
+}
+
+/// Formats token similarly to ANTLR's `CommonToken::toString`, as `'text',<NAME>`,
+/// followed by `,channel=N` for tokens that are not on the default channel.
+///
+/// `NAME` is the display name of the token type from `vocabulary`.
+pub fn token_summary<T: Token + ?Sized>(token: &T, vocabulary: &dyn Vocabulary) -> String {
+    let mut summary = format!(
+        "'{}',<{}>",
+        escape_whitespaces(token.get_text().to_display(), false),
+        escape_whitespaces(vocabulary.get_display_name(token.get_token_type()), false)
+    );
+    if token.get_channel() != TOKEN_DEFAULT_CHANNEL {
+        summary += &format!(",channel={}", token.get_channel());
+    }
+    summary
+}
+
+/// Input of a `LexerFixture` together with its expected result
+#[derive(Debug)]
+pub struct LexerCase {
+    /// Lexer input
+    pub input: &'static str,
+    /// Expected tokens in the `token_summary` format, including EOF
+    pub tokens: &'static [&'static str],
+    /// Expected lexer errors formatted as `line:column message`
+    pub errors: &'static [&'static str],
+}
+
+/// Lexer ATN checked into the runtime together with its documented behavior.
+///
+/// Lexers created from fixtures have no embedded actions or predicates,
+/// so fixture grammars use only ATN level features, e.g. `skip` or `channel`.
+#[derive(Debug)]
+pub struct LexerFixture {
+    /// Name of the fixture, also used as grammar file name of its lexer
+    pub name: &'static str,
+    /// Grammar ATN has been generated from
+    pub grammar: &'static str,
+    /// Serialized ATN
+    pub serialized_atn: &'static str,
+    /// Lexer rule names
+    pub rule_names: &'static [&'static str],
+    /// Literal names of token types
+    pub literal_names: &'static [Option<&'static str>],
+    /// Symbolic names of token types
+    pub symbolic_names: &'static [Option<&'static str>],
+    /// Documented behavior
+    pub cases: &'static [LexerCase],
+}
+
+/// Lexer created by `LexerFixture::lexer`
+pub type FixtureLexer<'input, Input> =
+    BaseLexer<'input, FixtureLexerActions, Input, CommonTokenFactory>;
+
+/// Recognizer data of a `FixtureLexer`
+#[derive(Debug)]
+pub struct FixtureLexerActions {
+    name: &'static str,
+    serialized_atn: &'static str,
+    rule_names: &'static [&'static str],
+    vocabulary: VocabularyImpl,
+}
+
+impl<'input, Input: CharStream<Cow<'input, str>>> Actions<'input, FixtureLexer<'input, Input>>
+    for FixtureLexerActions
+{
+    fn get_rule_names(&self) -> &[&str] { self.rule_names }
+
+    fn get_vocabulary(&self) -> &dyn Vocabulary { &self.vocabulary }
+
+    fn get_grammar_file_name(&self) -> &str { self.name }
+
+    fn get_serialized_atn(&self) -> &str { self.serialized_atn }
+}
+
+impl<'input, Input: CharStream<Cow<'input, str>>> LexerRecog<'input, FixtureLexer<'input, Input>>
+    for FixtureLexerActions
+{
+}
+
+impl<'input> TokenAware<'input> for FixtureLexerActions {
+    type TF = CommonTokenFactory;
+}
+
+impl LexerFixture {
+    /// Deserializes fixture ATN
+    pub fn atn(&self) -> ATN { ATNDeserializer::new(None).deserialize(self.serialized_atn.chars()) }
+
+    /// Vocabulary of the fixture lexer
+    pub fn vocabulary(&self) -> VocabularyImpl {
+        VocabularyImpl::new(self.literal_names.iter(), self.symbolic_names.iter(), None)
+    }
+
+    /// Creates lexer running fixture ATN over `input`.
+    /// Each lexer gets its own ATN and DFA cache.
+    pub fn lexer<'input, Input: CharStream<Cow<'input, str>>>(
+        &self, input: Input,
+    ) -> FixtureLexer<'input, Input> {
+        let atn = Arc::new(self.atn());
+        let decision_to_dfa = (0..atn.decision_to_state.len())
+            .map(|i| RwLock::new(DFA::new(atn.clone(), atn.get_decision_state(i), i as isize)))
+            .collect();
+        let actions = FixtureLexerActions {
+            name: self.name,
+            serialized_atn: self.serialized_atn,
+            rule_names: self.rule_names,
+            vocabulary: self.vocabulary(),
+        };
+        let interpreter = LexerATNSimulator::new_lexer_atnsimulator(
+            atn,
+            Arc::new(decision_to_dfa),
+            Arc::new(PredictionContextCache::new()),
+        );
+        BaseLexer::new_base_lexer(input, interpreter, actions, &CommonTokenFactory)
+    }
+
+    /// Runs fixture lexer over `input` until EOF,
+    /// returns tokens in `token_summary` format and lexer errors recorded by `EventRecorder`
+    pub fn tokenize<'input, Input: CharStream<Cow<'input, str>>>(
+        &self, input: Input,
+    ) -> (Vec<String>, Vec<String>) {
+        let vocabulary = self.vocabulary();
+        let recorder = Rc::new(EventRecorder::new());
+        let mut lexer = self.lexer(input);
+        lexer.remove_error_listeners();
+        lexer.add_error_listener(recorder.clone());
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            tokens.push(token_summary(&*token, &vocabulary));
+            if token.get_token_type() == TOKEN_EOF {
+                break;
+            }
+        }
+        (tokens, recorder.syntax_errors())
+    }
+
+    /// Checks that fixture lexer behaves as documented in `cases`
+    pub fn check(&self) {
+        for case in self.cases {
+            let (tokens, errors) = self.tokenize(InputStream::new(case.input));
+            assert_eq!(
+                tokens, case.tokens,
+                "{}: tokens of {:?}",
+                self.name, case.input
+            );
+            assert_eq!(
+                errors, case.errors,
+                "{}: errors of {:?}",
+                self.name, case.input
+            );
+        }
+    }
+}
+
+/// Lexer of comma separated values with hidden whitespace and quoted strings:
+/// ```text
+/// lexer grammar CSV;
+/// T__0 : ',' ;
+/// T__1 : '\r' ;
+/// T__2 : '\n' ;
+/// WS     : [ ]+ -> channel(HIDDEN);
+/// TEXT   : ~[ ,\n\r"]+ ;
+/// STRING : '"' ('""'|~'"')* '"' ; // quote-quote is an escaped quote
+/// ```
+pub const CSV_LEXER: LexerFixture = LexerFixture {
+    name: "CSV",
+    grammar: "CSV.g4",
+    serialized_atn: "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x02\
+		\x08\x2c\x08\x01\x04\x02\x09\x02\x04\x03\x09\x03\x04\x04\x09\x04\x04\x05\
+		\x09\x05\x04\x06\x09\x06\x04\x07\x09\x07\x03\x02\x03\x02\x03\x03\x03\x03\
+		\x03\x04\x03\x04\x03\x05\x06\x05\x17\x0a\x05\x0d\x05\x0e\x05\x18\x03\x05\
+		\x03\x05\x03\x06\x06\x06\x1e\x0a\x06\x0d\x06\x0e\x06\x1f\x03\x07\x03\x07\
+		\x03\x07\x03\x07\x07\x07\x26\x0a\x07\x0c\x07\x0e\x07\x29\x0b\x07\x03\x07\
+		\x03\x07\x02\x02\x08\x03\x03\x05\x04\x07\x05\x09\x06\x0b\x07\x0d\x08\x03\
+		\x02\x05\x03\x02\x22\x22\x07\x02\x0c\x0c\x0f\x0f\x22\x22\x24\x24\x2e\x2e\
+		\x03\x02\x24\x24\x02\x2f\x02\x03\x03\x02\x02\x02\x02\x05\x03\x02\x02\x02\
+		\x02\x07\x03\x02\x02\x02\x02\x09\x03\x02\x02\x02\x02\x0b\x03\x02\x02\x02\
+		\x02\x0d\x03\x02\x02\x02\x03\x0f\x03\x02\x02\x02\x05\x11\x03\x02\x02\x02\
+		\x07\x13\x03\x02\x02\x02\x09\x16\x03\x02\x02\x02\x0b\x1d\x03\x02\x02\x02\
+		\x0d\x21\x03\x02\x02\x02\x0f\x10\x07\x2e\x02\x02\x10\x04\x03\x02\x02\x02\
+		\x11\x12\x07\x0f\x02\x02\x12\x06\x03\x02\x02\x02\x13\x14\x07\x0c\x02\x02\
+		\x14\x08\x03\x02\x02\x02\x15\x17\x09\x02\x02\x02\x16\x15\x03\x02\x02\x02\
+		\x17\x18\x03\x02\x02\x02\x18\x16\x03\x02\x02\x02\x18\x19\x03\x02\x02\x02\
+		\x19\x1a\x03\x02\x02\x02\x1a\x1b\x08\x05\x02\x02\x1b\x0a\x03\x02\x02\x02\
+		\x1c\x1e\x0a\x03\x02\x02\x1d\x1c\x03\x02\x02\x02\x1e\x1f\x03\x02\x02\x02\
+		\x1f\x1d\x03\x02\x02\x02\x1f\x20\x03\x02\x02\x02\x20\x0c\x03\x02\x02\x02\
+		\x21\x27\x07\x24\x02\x02\x22\x23\x07\x24\x02\x02\x23\x26\x07\x24\x02\x02\
+		\x24\x26\x0a\x04\x02\x02\x25\x22\x03\x02\x02\x02\x25\x24\x03\x02\x02\x02\
+		\x26\x29\x03\x02\x02\x02\x27\x25\x03\x02\x02\x02\x27\x28\x03\x02\x02\x02\
+		\x28\x2a\x03\x02\x02\x02\x29\x27\x03\x02\x02\x02\x2a\x2b\x07\x24\x02\x02\
+		\x2b\x0e\x03\x02\x02\x02\x07\x02\x18\x1f\x25\x27\x03\x02\x03\x02",
+    rule_names: &["T__0", "T__1", "T__2", "WS", "TEXT", "STRING"],
+    literal_names: &[None, Some("','"), Some("'\r'"), Some("'\n'")],
+    symbolic_names: &[
+        None,
+        None,
+        None,
+        None,
+        Some("WS"),
+        Some("TEXT"),
+        Some("STRING"),
+    ],
+    cases: &[
+        LexerCase {
+            input: "a, \"b\"\"c\"\r\n",
+            tokens: &[
+                "'a',<TEXT>",
+                "',',<','>",
+                "' ',<WS>,channel=1",
+                "'\"b\"\"c\"',<STRING>",
+                "'\\r',<'\\r'>",
+                "'\\n',<'\\n'>",
+                "'<EOF>',<EOF>",
+            ],
+            errors: &[],
+        },
+        // unterminated string can't be matched, lexer reports and drops the rest of the input
+        LexerCase {
+            input: "\"x",
+            tokens: &["'<EOF>',<EOF>"],
+            errors: &["1:0 token recognition error at: '\"x'"],
+        },
+    ],
+};
+
+/// Lexer of lowercase identifiers that skips whitespace:
+/// ```text
+/// lexer grammar SimpleLR;
+/// ID : 'a'..'z'+ ;
+/// WS : (' '|'\n') -> skip ;
+/// ```
+pub const SIMPLE_LR_LEXER: LexerFixture = LexerFixture {
+    name: "SimpleLR",
+    grammar: "SimpleLR.g4",
+    serialized_atn: "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x02\
+		\x04\x10\x08\x01\x04\x02\x09\x02\x04\x03\x09\x03\x03\x02\x06\x02\x09\x0a\
+		\x02\x0d\x02\x0e\x02\x0a\x03\x03\x03\x03\x03\x03\x03\x03\x02\x02\x04\x03\
+		\x03\x05\x04\x03\x02\x03\x04\x02\x0c\x0c\x22\x22\x02\x10\x02\x03\x03\x02\
+		\x02\x02\x02\x05\x03\x02\x02\x02\x03\x08\x03\x02\x02\x02\x05\x0c\x03\x02\
+		\x02\x02\x07\x09\x04\x63\x7c\x02\x08\x07\x03\x02\x02\x02\x09\x0a\x03\x02\
+		\x02\x02\x0a\x08\x03\x02\x02\x02\x0a\x0b\x03\x02\x02\x02\x0b\x04\x03\x02\
+		\x02\x02\x0c\x0d\x09\x02\x02\x02\x0d\x0e\x03\x02\x02\x02\x0e\x0f\x08\x03\
+		\x02\x02\x0f\x06\x03\x02\x02\x02\x04\x02\x0a\x03\x08\x02\x02",
+    rule_names: &["ID", "WS"],
+    literal_names: &[],
+    symbolic_names: &[None, Some("ID"), Some("WS")],
+    cases: &[
+        LexerCase {
+            input: "ab c\nd",
+            tokens: &["'ab',<ID>", "'c',<ID>", "'d',<ID>", "'<EOF>',<EOF>"],
+            errors: &[],
+        },
+        LexerCase {
+            input: "a1b",
+            tokens: &["'a',<ID>", "'b',<ID>", "'<EOF>',<EOF>"],
+            errors: &["1:1 token recognition error at: '1'"],
+        },
+    ],
+};
+
+#[cfg(test)]
+mod test {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use crate::common_token_stream::CommonTokenStream;
+    use crate::int_stream::check_stream_contract;
+    use crate::token_stream::TokenStream;
+    use crate::tree_builder::TreeBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_mock_token_source() {
+        let source = MockTokenSource::new(&CommonTokenFactory)
+            .tokens(&[(1, "a"), (2, "\n")])
+            .fail_next("connection reset")
+            .token_on_channel(3, "  ", 1)
+            .token(1, "b");
+        let mut stream = CommonTokenStream::new(source);
+        match stream.try_lt(3) {
+            Err(ANTLRError::Io(e)) => {
+                assert_eq!((e.token_index, e.line, e.column), (2, 2, 0));
+                assert_eq!(e.error.to_string(), "connection reset");
+            }
+            r => panic!("expected io error, got {:?}", r.map(|t| t.cloned())),
+        }
+        let b = stream.try_lt(3).unwrap().unwrap();
+        assert_eq!(
+            (b.get_line(), b.get_column(), b.get_start(), b.get_stop()),
+            (2, 2, 4, 4)
+        );
+        assert_eq!(stream.la(4), TOKEN_EOF);
+        assert_eq!(stream.get_all_text(), "a\n  b");
+        assert_eq!(stream.get_token_source().get_source_name(), "<mock>");
+    }
+
+    #[test]
+    fn test_mock_char_stream() {
+        let symbols = ['a' as isize, 'b' as isize, 'c' as isize];
+        check_stream_contract(&mut MockCharStream::new("abc"), &symbols);
+
+        let mut stream = MockCharStream::new("abc").unbuffered();
+        let marker = stream.mark();
+        stream.consume();
+        stream.consume();
+        stream.seek(1);
+        stream.release(marker);
+        assert_eq!(stream.la(-1), 'a' as isize);
+        assert_eq!(stream.la(2), 'c' as isize);
+        assert_eq!((stream.seeks(), stream.max_lookahead()), (1, 2));
+        assert_eq!(stream.active_markers(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "char at 0 is not available anymore")]
+    fn test_mock_char_stream_unbuffered() {
+        let mut stream = MockCharStream::new("abc").unbuffered();
+        stream.consume();
+        stream.consume();
+        stream.seek(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "disk is on fire")]
+    fn test_mock_char_stream_failure() {
+        CSV_LEXER.tokenize(MockCharStream::new("a,b").fail_at(2, "disk is on fire"));
+    }
+
+    #[test]
+    fn test_fixtures() {
+        CSV_LEXER.check();
+        SIMPLE_LR_LEXER.check();
+
+        // lexer releases all chars it has consumed
+        let (tokens, _) = SIMPLE_LR_LEXER.tokenize(MockCharStream::new("ab c\nd").unbuffered());
+        assert_eq!(tokens.len(), 4);
+
+        let atn = SIMPLE_LR_LEXER.atn();
+        assert_eq!(atn.modes_count(), 1);
+        assert_eq!(
+            atn.rule_to_start_state.len(),
+            SIMPLE_LR_LEXER.rule_names.len()
+        );
+    }
+
+    #[test]
+    fn test_event_recorder() {
+        let recorder = Rc::new(EventRecorder::new());
+        let mut lexer = SIMPLE_LR_LEXER.lexer(InputStream::new("1\n2"));
+        lexer.remove_error_listeners();
+        lexer.add_error_listener(recorder.clone());
+        while lexer.next_token().get_token_type() != TOKEN_EOF {}
+
+        assert_eq!(
+            recorder.syntax_errors(),
+            vec![
+                "1:0 token recognition error at: '1'",
+                "2:0 token recognition error at: '2'"
+            ]
+        );
+        assert_events_eq(
+            &recorder.take_events()[..1],
+            &[Event::SyntaxError {
+                line: 1,
+                column: 0,
+                message: "token recognition error at: '1'".to_owned(),
+                offending_token: None,
+            }],
+        );
+        assert!(recorder.events().is_empty());
+    }
+
+    #[test]
+    fn test_assert_tree_eq() {
+        let tree = TreeBuilder::new()
+            .rule(0)
+            .token(1, "a")
+            .end()
+            .finish()
+            .unwrap();
+        assert_tree_eq(&*tree, &["s"], "(s a)");
+        let message = catch_unwind(AssertUnwindSafe(|| assert_tree_eq(&*tree, &["s"], "(s b)")))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(
+            message.starts_with("parse trees differ at char 3"),
+            "{}",
+            message
+        );
+    }
+}
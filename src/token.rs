@@ -4,11 +4,12 @@ use std::borrow::{Borrow, Cow};
 use std::fmt::Formatter;
 use std::fmt::{Debug, Display};
 
-use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 
 use crate::char_stream::InputData;
 use crate::int_stream::EOF;
 use crate::token_factory::{INVALID_COMMON, INVALID_OWNING};
+use crate::utils::CrateOnly;
 
 use better_any::type_id;
 
@@ -27,6 +28,9 @@ pub const TOKEN_DEFAULT_CHANNEL: isize = 0;
 pub const TOKEN_HIDDEN_CHANNEL: isize = 1;
 /// Shorthand for TOKEN_HIDDEN_CHANNEL
 pub const HIDDEN: isize = TOKEN_HIDDEN_CHANNEL;
+/// Channel of the tokens with input skipped by `BaseLexer::scan_for`,
+/// not used by generated lexers
+pub const TOKEN_GARBAGE_CHANNEL: isize = isize::MAX;
 
 /// Implemented by tokens that are produced by a `TokenFactory`
 #[allow(missing_docs)]
@@ -40,13 +44,33 @@ pub trait Token: Debug + Display {
     fn get_stop(&self) -> isize { 0 }
     fn get_line(&self) -> isize { 0 }
     fn get_column(&self) -> isize { 0 }
-
+    /// Line of the position right after the last char of this token.
+    ///
+    /// Recorded by lexer from its input position, so diagnostics can highlight tokens
+    /// spanning several lines without rescanning their text.
+    /// Tokens that were not produced by lexer end where they start.
+    fn get_end_line(&self) -> isize { self.get_line() }
+    /// Column of the position right after the last char of this token on `get_end_line`,
+    /// counted in the same units as `get_column`
+    fn get_end_column(&self) -> isize { self.get_column() }
+
+    /// Text of the token.
+    ///
+    /// EOF token produced by lexer has empty char interval `start..=start-1` at the end
+    /// of input and its text is `<EOF>`, so it can be shown in error messages.
+    /// Tree and token stream text methods skip EOF token instead of using this text.
     fn get_text(&self) -> &Self::Data;
-    fn set_text(&mut self, _text: <Self::Data as ToOwned>::Owned) {}
 
     fn get_token_index(&self) -> isize { 0 }
     fn set_token_index(&self, _v: isize) {}
 
+    /// Lexer mode this token was started to be recognized in.
+    /// Tokens that were not produced by lexer are in default mode `0`
+    fn get_mode(&self) -> usize { 0 }
+    /// Called by lexer to record mode token was produced in
+    #[doc(hidden)]
+    fn set_mode(&self, _mode: usize, _: CrateOnly) {}
+
     // fn get_token_source(&self) -> &dyn TokenSource;
     // fn get_input_stream(&self) -> &dyn CharStream;
 
@@ -58,8 +82,11 @@ pub trait Token: Debug + Display {
             start: self.get_start(),
             stop: self.get_stop(),
             token_index: AtomicIsize::from(self.get_token_index()),
+            mode: AtomicUsize::new(self.get_mode()),
             line: self.get_line(),
             column: self.get_column(),
+            end_line: self.get_end_line(),
+            end_column: self.get_end_column(),
             text: self.get_text().to_display(),
             read_only: true,
         }
@@ -70,6 +97,11 @@ pub trait Token: Debug + Display {
 pub type OwningToken = GenericToken<String>;
 /// Most versatile Token that uses Cow to save data
 /// Can be used seamlessly switch from owned to zero-copy parsing
+///
+/// When created by `CommonTokenFactory` or `ArenaCommonFactory` from `InputStream<&str>`,
+/// text is borrowed from the input by token start/stop indexes without allocation.
+/// It is owned only if it has been overridden, e.g. by lexer action or
+/// `WritableToken::set_text`, or if input does not allow borrowing, like `InputStream<Box<str>>`.
 pub type CommonToken<'a> = GenericToken<Cow<'a, str>>;
 
 type_id!(OwningToken);
@@ -84,8 +116,14 @@ pub struct GenericToken<T> {
     pub start: isize,
     pub stop: isize,
     pub token_index: AtomicIsize,
+    /// Lexer mode, accessed with `Token::get_mode`/`Token::set_mode`
+    pub(crate) mode: AtomicUsize,
     pub line: isize,
     pub column: isize,
+    /// Position after the last char, see `Token::get_end_line`
+    pub end_line: isize,
+    /// Position after the last char, see `Token::get_end_column`
+    pub end_column: isize,
     pub text: T,
     pub read_only: bool,
 }
@@ -101,8 +139,11 @@ where
             start: self.start,
             stop: self.stop,
             token_index: AtomicIsize::new(self.get_token_index()),
+            mode: AtomicUsize::new(self.get_mode()),
             line: self.line,
             column: self.column,
+            end_line: self.get_end_line(),
+            end_column: self.get_end_column(),
             text: self.text.clone(),
             read_only: false,
         }
@@ -167,12 +208,18 @@ impl<T: Borrow<str> + Debug> Token for GenericToken<T> {
         }
     }
 
-    fn set_text(&mut self, _text: String) { unimplemented!() }
-
     fn get_token_index(&self) -> isize { self.token_index.load(Ordering::Relaxed) }
 
     fn set_token_index(&self, _v: isize) { self.token_index.store(_v, Ordering::Relaxed) }
 
+    fn get_mode(&self) -> usize { self.mode.load(Ordering::Relaxed) }
+
+    fn set_mode(&self, mode: usize, _: CrateOnly) { self.mode.store(mode, Ordering::Relaxed) }
+
+    fn get_end_line(&self) -> isize { self.end_line }
+
+    fn get_end_column(&self) -> isize { self.end_column }
+
     fn to_owned(&self) -> OwningToken {
         OwningToken {
             token_type: self.token_type,
@@ -180,14 +227,69 @@ impl<T: Borrow<str> + Debug> Token for GenericToken<T> {
             start: self.start,
             stop: self.stop,
             token_index: AtomicIsize::new(self.get_token_index()),
+            mode: AtomicUsize::new(self.get_mode()),
             line: self.line,
             column: self.column,
+            end_line: self.get_end_line(),
+            end_column: self.get_end_column(),
             text: self.text.borrow().to_owned(),
             read_only: self.read_only,
         }
     }
 }
 
+/// Token which properties can be changed after it has been created,
+/// e.g. by error strategies that conjure up missing tokens or by token stream rewriters.
+pub trait WritableToken: Token {
+    /// Overrides token text
+    fn set_text(&mut self, text: <Self::Data as ToOwned>::Owned);
+    /// Sets token type
+    fn set_token_type(&mut self, token_type: isize);
+    /// Moves token to `channel`
+    fn set_channel(&mut self, channel: isize);
+    /// Sets line token starts at
+    fn set_line(&mut self, line: isize);
+    /// Sets column token starts at
+    fn set_column(&mut self, column: isize);
+}
+
+impl<T> GenericToken<T> {
+    fn check_writable(&self) {
+        runtime_assert!(
+            !self.read_only,
+            { token_type: self.token_type },
+            "can't modify read-only token"
+        );
+    }
+}
+
+impl<T: Borrow<str> + From<String> + Debug> WritableToken for GenericToken<T> {
+    fn set_text(&mut self, text: String) {
+        self.check_writable();
+        self.text = text.into()
+    }
+
+    fn set_token_type(&mut self, token_type: isize) {
+        self.check_writable();
+        self.token_type = token_type
+    }
+
+    fn set_channel(&mut self, channel: isize) {
+        self.check_writable();
+        self.channel = channel
+    }
+
+    fn set_line(&mut self, line: isize) {
+        self.check_writable();
+        self.line = line
+    }
+
+    fn set_column(&mut self, column: isize) {
+        self.check_writable();
+        self.column = column
+    }
+}
+
 impl Default for &'_ OwningToken {
     fn default() -> Self { &**INVALID_OWNING }
 }
@@ -212,3 +314,55 @@ impl Default for &'_ CommonToken<'_> {
 //         unimplemented!()
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::char_stream::CharStream;
+    use crate::input_stream::InputStream;
+    use crate::token::{Token, WritableToken, TOKEN_HIDDEN_CHANNEL};
+    use crate::token_factory::{CommonTokenFactory, TokenFactory, INVALID_OWNING};
+
+    #[test]
+    fn test_writable_token() {
+        let factory = CommonTokenFactory;
+        let mut token = factory.create(
+            None::<&mut dyn CharStream<Cow<'_, str>>>,
+            1,
+            Some("a".to_owned()),
+            0,
+            0,
+            0,
+            1,
+            0,
+        );
+        token.set_text("b".to_owned());
+        token.set_token_type(2);
+        token.set_channel(TOKEN_HIDDEN_CHANNEL);
+        token.set_line(3);
+        token.set_column(4);
+        assert_eq!(token.to_string(), "[@-1,0:0='b',<2>1,3:4]");
+        assert!(matches!(token.text, Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_borrowed_text() {
+        let factory = CommonTokenFactory;
+        let text = "abc";
+        let mut input = InputStream::new(text);
+        let token = factory.create(Some(&mut input), 1, None, 0, 1, 2, 1, 1);
+        match &token.text {
+            Cow::Borrowed(borrowed) => assert_eq!(borrowed.as_ptr(), text[1..].as_ptr()),
+            Cow::Owned(_) => panic!("token text is copied"),
+        }
+        assert_eq!(token.get_text(), "bc");
+    }
+
+    #[test]
+    #[should_panic(expected = "can't modify read-only token")]
+    fn test_read_only_token() {
+        let mut token = Token::to_owned(&**INVALID_OWNING);
+        token.set_channel(1);
+    }
+}
@@ -0,0 +1,455 @@
+//! Token buffer shared between several token streams.
+//!
+//! `TokenBuffer` lexes input once and keeps all tokens, while any number of `TokenCursor`s
+//! read them as independent `TokenStream`s, each with its own position and channel filter.
+//! So a parser and, for example, a whitespace/comment analyzer can work over the same tokens
+//! without lexing input twice. Tokens fetched on behalf of one cursor are visible to all others.
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::rc::Rc;
+
+use better_any::{Tid, TidAble};
+use typed_arena::Arena;
+
+use crate::char_stream::InputData;
+use crate::errors::{ANTLRError, IoError};
+use crate::int_stream::{panic_consume_eof, IntStream, IterWrapper, EOF};
+use crate::interval_set::TokenInterval;
+use crate::token::{Token, TOKEN_DEFAULT_CHANNEL, TOKEN_EOF, TOKEN_INVALID_TYPE};
+use crate::token_factory::TokenFactory;
+use crate::token_source::TokenSource;
+use crate::token_stream::{infallible, FetchedTokens, TokenStream};
+
+/// Storage of the tokens fetched by `TokenBuffer`.
+///
+/// Tokens never move after they are allocated in the arena, so buffer can hand out
+/// references to them while it keeps fetching new ones. Like [`ArenaFactory`],
+/// arena is created by the caller and must outlive the buffers that use it.
+/// Single arena can be shared by several buffers.
+///
+/// [`ArenaFactory`]: crate::token_factory::ArenaFactory
+pub struct TokenArena<'input, TF: TokenFactory<'input>> {
+    arena: Arena<TF::Tok>,
+}
+
+impl<'input, TF: TokenFactory<'input>> Debug for TokenArena<'input, TF> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenArena")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<'input, TF: TokenFactory<'input>> Default for TokenArena<'input, TF> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<'input, TF: TokenFactory<'input>> TokenArena<'input, TF> {
+    /// Creates empty arena
+    pub fn new() -> Self {
+        TokenArena {
+            arena: Arena::new(),
+        }
+    }
+
+    /// Number of tokens allocated in this arena so far
+    pub fn len(&self) -> usize { self.arena.len() }
+
+    /// Whether this arena has not allocated any tokens yet
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+/// Append-only buffer of all tokens produced by the token source.
+///
+/// Buffer is read through `TokenCursor`s, which share it via `Rc`.
+/// It is not `Sync`, so it can't be shared between threads behind `Arc`,
+/// same as the token sources and parse trees it is used with.
+/// The only mutable state is the fill frontier: tokens are fetched lazily when some cursor
+/// looks past already fetched ones, and are never removed or replaced afterwards.
+pub struct TokenBuffer<'input, T: TokenSource<'input>> {
+    source: RefCell<T>,
+    arena: &'input TokenArena<'input, T::TF>,
+    tokens: RefCell<Vec<&'input <T::TF as TokenFactory<'input>>::Tok>>,
+    // token source error that happened in infallible method
+    error: RefCell<Option<ANTLRError>>,
+    // whether infallible methods keep token source error instead of panicking
+    defer_errors: Cell<bool>,
+}
+
+impl<'input, T: TokenSource<'input>> Debug for TokenBuffer<'input, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenBuffer")
+            .field("tokens", &self.tokens.borrow())
+            .field("error", &self.error.borrow())
+            .field("defer_errors", &self.defer_errors.get())
+            .finish()
+    }
+}
+
+impl<'input, T: TokenSource<'input>> TokenBuffer<'input, T> {
+    /// Creates empty buffer that fetches tokens from `source` on demand
+    /// and stores them in `arena`
+    pub fn new(source: T, arena: &'input TokenArena<'input, T::TF>) -> Self {
+        TokenBuffer {
+            source: RefCell::new(source),
+            arena,
+            tokens: RefCell::new(Vec::new()),
+            error: RefCell::new(None),
+            defer_errors: Cell::new(false),
+        }
+    }
+
+    /// Number of tokens fetched so far
+    pub fn len(&self) -> usize { self.tokens.borrow().len() }
+
+    /// Returns true if nothing has been fetched yet
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Returns token at `index` if it has already been fetched
+    pub fn get(&self, index: isize) -> Option<&'input <T::TF as TokenFactory<'input>>::Tok> {
+        if index < 0 {
+            return None;
+        }
+        self.tokens.borrow().get(index as usize).copied()
+    }
+
+    /// Returns true if EOF token was fetched
+    pub fn is_finished(&self) -> bool {
+        self.tokens
+            .borrow()
+            .last()
+            .map_or(false, |t| (**t).borrow().get_token_type() == TOKEN_EOF)
+    }
+
+    /// Fetches all remaining tokens up to and including EOF
+    pub fn fill(&self) -> Result<(), ANTLRError> {
+        while !self.is_finished() {
+            self.try_fetch(64)?;
+        }
+        Ok(())
+    }
+
+    /// Get combined text of tokens in `interval`, EOF token does not contribute to it
+    pub fn get_text_from_interval(&self, interval: TokenInterval) -> String {
+        let TokenInterval { a: start, b: stop } = interval;
+        if start < 0 || stop >= self.len() as isize {
+            panic!(
+                "interval {}..={} not in token buffer of {} tokens",
+                start,
+                stop,
+                self.len()
+            );
+        }
+
+        let mut buf = String::new();
+        for i in start..=stop {
+            let t = self.get(i).unwrap().borrow();
+            if t.get_token_type() == TOKEN_EOF {
+                break;
+            }
+            buf.extend(t.get_text().to_display().chars());
+        }
+        buf
+    }
+
+    // Same as `try_fetch` but panics on error, or, if `defer_errors` is set,
+    // keeps the error until the next `try_fetch` call,
+    // so until then buffer looks like it ends right before the failed token.
+    fn fetch(&self, need: isize) -> isize {
+        if let Some(e) = &*self.error.borrow() {
+            if !self.defer_errors.get() {
+                panic!("token source failed: {}", e)
+            }
+            return 0;
+        }
+        let len = self.len();
+        match self.try_fetch(need) {
+            Ok(fetched) => fetched,
+            Err(e) if self.defer_errors.get() => {
+                *self.error.borrow_mut() = Some(e);
+                (self.len() - len) as isize
+            }
+            Err(e) => panic!("token source failed: {}", e),
+        }
+    }
+
+    // Fetches `need` tokens, returns number of fetched tokens
+    // which is less than `need` only if EOF was reached.
+    fn try_fetch(&self, need: isize) -> Result<isize, ANTLRError> {
+        if let Some(e) = self.error.borrow_mut().take() {
+            return Err(e);
+        }
+        let mut source = self.source.borrow_mut();
+        for i in 0..need {
+            if self.is_finished() {
+                return Ok(i);
+            }
+            let token = match source.try_next_token() {
+                Ok(token) => token,
+                Err(e) => return Err(self.io_error(&*source, e)),
+            };
+            token.borrow().set_token_index(self.len() as isize);
+            self.tokens.borrow_mut().push(self.arena.arena.alloc(token));
+        }
+        Ok(need)
+    }
+
+    fn io_error(&self, source: &T, error: io::Error) -> ANTLRError {
+        ANTLRError::Io(IoError {
+            token_index: self.len() as isize,
+            line: source.get_line(),
+            column: source.get_char_position_in_line(),
+            error: Rc::new(error),
+        })
+    }
+}
+
+impl<'a, 'input, T: TokenSource<'input>> FetchedTokens<'input> for &'a TokenBuffer<'input, T> {
+    type TF = T::TF;
+
+    fn fetched(&self) -> isize { self.len() as isize }
+
+    fn is_finished(&self) -> bool { TokenBuffer::is_finished(self) }
+
+    fn token(&self, i: isize) -> &<T::TF as TokenFactory<'input>>::Inner {
+        TokenBuffer::get(self, i).unwrap().borrow()
+    }
+
+    fn fetch_to(&mut self, i: isize, fallible: bool) -> Result<bool, ANTLRError> {
+        let need = i - self.len() as isize + 1;
+        if need > 0 {
+            let fetched = if fallible {
+                self.try_fetch(need)?
+            } else {
+                self.fetch(need)
+            };
+            return Ok(fetched >= need);
+        }
+        Ok(true)
+    }
+}
+
+/// Exposes the underlying token source through `TokenStream::get_token_source` of cursors.
+///
+/// Tokens can't be pulled from the shared buffer directly, `next_token` panics,
+/// use `TokenCursor` instead. Underlying input stream is not available either.
+impl<'input, T: TokenSource<'input>> TokenSource<'input> for TokenBuffer<'input, T> {
+    type TF = T::TF;
+
+    fn next_token(&mut self) -> <Self::TF as TokenFactory<'input>>::Tok {
+        panic!("tokens of the shared `TokenBuffer` can be read only through `TokenCursor`")
+    }
+
+    fn get_line(&self) -> isize { self.source.borrow().get_line() }
+
+    fn get_char_position_in_line(&self) -> isize {
+        self.source.borrow().get_char_position_in_line()
+    }
+
+    fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { None }
+
+    fn get_source_name(&self) -> String { self.source.borrow().get_source_name() }
+
+    fn get_token_factory(&self) -> &'input Self::TF { self.source.borrow().get_token_factory() }
+}
+
+/// Lightweight `TokenStream` view of the shared `TokenBuffer`.
+///
+/// Each cursor has its own position and sees only tokens on its channel (and EOF),
+/// or all tokens if created with `all_channels`. Cloning cursor is cheap,
+/// clone starts at the same position but then moves independently.
+#[derive(Tid)]
+pub struct TokenCursor<'input, T: TokenSource<'input>> {
+    buffer: Rc<TokenBuffer<'input, T>>,
+    channel: Option<isize>,
+    p: isize,
+}
+
+impl<'input, T: TokenSource<'input>> Debug for TokenCursor<'input, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenCursor")
+            .field("channel", &self.channel)
+            .field("p", &self.p)
+            .finish()
+    }
+}
+
+impl<'input, T: TokenSource<'input>> Clone for TokenCursor<'input, T> {
+    fn clone(&self) -> Self {
+        TokenCursor {
+            buffer: self.buffer.clone(),
+            channel: self.channel,
+            p: self.p,
+        }
+    }
+}
+
+impl<'input, T: TokenSource<'input>> TokenCursor<'input, T> {
+    /// Creates cursor that produces tokens from `TOKEN_DEFAULT_CHANNEL`
+    pub fn new(buffer: Rc<TokenBuffer<'input, T>>) -> Self {
+        Self::with_channel(buffer, TOKEN_DEFAULT_CHANNEL)
+    }
+
+    /// Creates cursor that produces tokens from `channel`
+    pub fn with_channel(buffer: Rc<TokenBuffer<'input, T>>, channel: isize) -> Self {
+        Self::create(buffer, Some(channel))
+    }
+
+    /// Creates cursor that produces tokens from all channels
+    pub fn all_channels(buffer: Rc<TokenBuffer<'input, T>>) -> Self { Self::create(buffer, None) }
+
+    fn create(buffer: Rc<TokenBuffer<'input, T>>, channel: Option<isize>) -> Self {
+        let mut cursor = TokenCursor {
+            buffer,
+            channel,
+            p: 0,
+        };
+        // failure to fetch the first token is reported by the next call that reads it
+        let defer_errors = cursor.buffer.defer_errors.replace(true);
+        infallible(cursor.sync_current(false));
+        cursor.buffer.defer_errors.set(defer_errors);
+        cursor
+    }
+
+    /// Buffer this cursor reads tokens from
+    pub fn get_buffer(&self) -> &Rc<TokenBuffer<'input, T>> { &self.buffer }
+
+    /// Channel of the tokens this cursor produces, `None` if it produces all tokens
+    pub fn get_channel(&self) -> Option<isize> { self.channel }
+
+    /// Creates iterator over this cursor
+    pub fn iter(&mut self) -> IterWrapper<'_, Self> { IterWrapper(self) }
+
+    // Other cursors could have fetched the token current position was waiting for,
+    // so position is moved to the nearest visible token every time.
+    fn sync_current(&mut self, fallible: bool) -> Result<(), ANTLRError> {
+        self.p = self.next_token_on_channel(self.p, 1, fallible)?;
+        Ok(())
+    }
+
+    fn next_token_on_channel(
+        &self, i: isize, direction: isize, fallible: bool,
+    ) -> Result<isize, ANTLRError> {
+        let channel = self.channel;
+        let visible = |token: &<T::TF as TokenFactory<'input>>::Inner| {
+            channel.map_or(true, |channel| token.get_channel() == channel)
+        };
+        (&*self.buffer).next_visible(i, direction, fallible, visible)
+    }
+
+    fn lt_inner(
+        &mut self, k: isize, fallible: bool,
+    ) -> Result<Option<&<T::TF as TokenFactory<'input>>::Tok>, ANTLRError> {
+        self.sync_current(fallible)?;
+        let channel = self.channel;
+        let visible = |token: &<T::TF as TokenFactory<'input>>::Inner| {
+            channel.map_or(true, |channel| token.get_channel() == channel)
+        };
+        let i = (&*self.buffer).lookahead(self.p, k, fallible, visible)?;
+        Ok(match i {
+            Some(i) => self.buffer.get(i),
+            None => None,
+        })
+    }
+
+    fn lb(&self, k: isize) -> Option<&<T::TF as TokenFactory<'input>>::Tok> {
+        if self.p - k < 0 {
+            return None;
+        }
+
+        let mut i = self.p;
+        let mut n = 1;
+        while n <= k && i > 0 {
+            i = infallible(self.next_token_on_channel(i - 1, -1, false));
+            n += 1;
+        }
+        self.buffer.get(i)
+    }
+}
+
+impl<'input, T: TokenSource<'input>> IntStream for TokenCursor<'input, T> {
+    fn consume(&mut self) {
+        if self.la(1) == TOKEN_EOF {
+            panic_consume_eof();
+        }
+        self.p = infallible(self.next_token_on_channel(self.p + 1, 1, false));
+    }
+
+    fn la(&mut self, i: isize) -> isize {
+        if i == 0 {
+            return TOKEN_INVALID_TYPE;
+        }
+        self.lt(i)
+            .map(|t| t.borrow().get_token_type())
+            .unwrap_or(EOF)
+    }
+
+    #[inline(always)]
+    fn mark(&mut self) -> isize { 0 }
+
+    #[inline(always)]
+    fn release(&mut self, _marker: isize) {}
+
+    #[inline(always)]
+    fn index(&self) -> isize { self.p }
+
+    fn seek(&mut self, index: isize) {
+        self.p = infallible(self.next_token_on_channel(index.max(0), 1, false));
+    }
+
+    fn size(&self) -> isize { self.buffer.len() as isize }
+
+    fn get_source_name(&self) -> String { self.buffer.get_source_name() }
+}
+
+impl<'input, T: TokenSource<'input>> TokenStream<'input> for TokenCursor<'input, T> {
+    type TF = T::TF;
+
+    fn lt(&mut self, k: isize) -> Option<&<Self::TF as TokenFactory<'input>>::Tok> {
+        if k == 0 {
+            return None;
+        }
+        if k < 0 {
+            return self.lb(-k);
+        }
+        infallible(self.lt_inner(k, false))
+    }
+
+    fn try_lt(
+        &mut self, k: isize,
+    ) -> Result<Option<&<Self::TF as TokenFactory<'input>>::Tok>, ANTLRError> {
+        if k == 0 {
+            return Ok(None);
+        }
+        if k < 0 {
+            return Ok(self.lb(-k));
+        }
+        self.lt_inner(k, true)
+    }
+
+    fn try_consume(&mut self) -> Result<(), ANTLRError> {
+        self.sync_current(true)?;
+        self.consume();
+        Ok(())
+    }
+
+    /// Error deferral is a property of the shared buffer,
+    /// so it changes behavior of all cursors created from it.
+    fn set_defer_errors(&mut self, defer: bool) { self.buffer.defer_errors.set(defer); }
+
+    fn defers_errors(&self) -> bool { self.buffer.defer_errors.get() }
+
+    fn get(&self, index: isize) -> &<Self::TF as TokenFactory<'input>>::Tok {
+        self.buffer
+            .get(index)
+            .unwrap_or_else(|| panic!("token {} is not in the token buffer", index))
+    }
+
+    fn get_token_source(&self) -> &dyn TokenSource<'input, TF = Self::TF> { &*self.buffer }
+
+    fn get_text_from_interval(&self, interval: TokenInterval) -> String {
+        self.buffer.get_text_from_interval(interval)
+    }
+}
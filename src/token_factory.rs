@@ -5,11 +5,12 @@ use std::borrow::{Borrow, Cow};
 use std::fmt::{Debug, Formatter};
 use std::marker::PhantomData;
 
-use std::sync::atomic::AtomicIsize;
+use std::sync::atomic::{AtomicIsize, AtomicUsize};
 
 use typed_arena::Arena;
 
 use crate::char_stream::{CharStream, InputData};
+use crate::interval_set::CharInterval;
 use crate::token::Token;
 use crate::token::{CommonToken, OwningToken, TOKEN_INVALID_TYPE};
 use better_any::{Tid, TidAble};
@@ -24,8 +25,11 @@ lazy_static! {
         start: -1,
         stop: -1,
         token_index: AtomicIsize::new(-1),
+        mode: AtomicUsize::new(0),
         line: -1,
         column: -1,
+        end_line: -1,
+        end_column: -1,
         text: "<invalid>".to_owned(),
         read_only: true,
     });
@@ -35,14 +39,30 @@ lazy_static! {
         start: -1,
         stop: -1,
         token_index: AtomicIsize::new(-1),
+        mode: AtomicUsize::new(0),
         line: -1,
         column: -1,
+        end_line: -1,
+        end_column: -1,
         text: Borrowed("<invalid>"),
         read_only: true,
     });
 }
 
 /// Trait for creating tokens.
+///
+/// Lexer creates all tokens through its token factory, so implementing this trait
+/// is the way to customize tokens: their type, ownership of the text, or their contents.
+/// Generated lexer uses factory type specified in `@tokenfactory` action of the grammar
+/// as `LocalTokenFactory`, and custom factory instance can be passed to
+/// `new_with_token_factory` constructor of the generated lexer.
+/// ```text
+/// @tokenfactory{
+/// pub type LocalTokenFactory<'input> = antlr_rust::token_factory::CommonTokenFactoryWithOffset;
+/// }
+/// ```
+/// Custom factory usually delegates to one of the existing factories
+/// and only adjusts created tokens, like `CommonTokenFactoryWithOffset` does.
 pub trait TokenFactory<'a>: TidAble<'a> + Sized {
     /// Type of tokens emitted by this factory.
     type Inner: Token<Data = Self::Data> + ?Sized + 'a;
@@ -70,6 +90,30 @@ pub trait TokenFactory<'a>: TidAble<'a> + Sized {
     where
         T: CharStream<Self::From> + ?Sized;
 
+    /// Same as `create` but also records `end_line` and `end_column`,
+    /// the position right after the last char of the token, see `Token::get_end_line`.
+    ///
+    /// Lexer creates tokens with this method. Default implementation ignores end position,
+    /// so tokens of factories that don't override it report their start position as the end.
+    fn create_with_end<T>(
+        &'a self,
+        source: Option<&mut T>,
+        ttype: isize,
+        text: Option<<Self::Data as ToOwned>::Owned>,
+        channel: isize,
+        start: isize,
+        stop: isize,
+        line: isize,
+        column: isize,
+        _end_line: isize,
+        _end_column: isize,
+    ) -> Self::Tok
+    where
+        T: CharStream<Self::From> + ?Sized,
+    {
+        self.create(source, ttype, text, channel, start, stop, line, column)
+    }
+
     /// Creates invalid token
     /// Invalid tokens must have `TOKEN_INVALID_TYPE` token type.
     fn create_invalid() -> Self::Tok;
@@ -105,6 +149,26 @@ impl<'a> TokenFactory<'a> for CommonTokenFactory {
         line: isize,
         column: isize,
     ) -> Self::Tok
+    where
+        T: CharStream<Self::From> + ?Sized,
+    {
+        self.create_with_end(source, ttype, text, channel, start, stop, line, column, line, column)
+    }
+
+    #[inline]
+    fn create_with_end<T>(
+        &'a self,
+        source: Option<&mut T>,
+        ttype: isize,
+        text: Option<String>,
+        channel: isize,
+        start: isize,
+        stop: isize,
+        line: isize,
+        column: isize,
+        end_line: isize,
+        end_column: isize,
+    ) -> Self::Tok
     where
         T: CharStream<Self::From> + ?Sized,
     {
@@ -114,7 +178,7 @@ impl<'a> TokenFactory<'a> for CommonTokenFactory {
                 if stop >= x.size() || start >= x.size() {
                     Borrowed("<EOF>")
                 } else {
-                    x.get_text(start, stop).into()
+                    x.get_text(CharInterval::new(start, stop)).into()
                 }
             }
             _ => Borrowed(""),
@@ -125,8 +189,11 @@ impl<'a> TokenFactory<'a> for CommonTokenFactory {
             start,
             stop,
             token_index: AtomicIsize::new(-1),
+            mode: AtomicUsize::new(0),
             line,
             column,
+            end_line,
+            end_column,
             text,
             read_only: false,
         })
@@ -137,6 +204,82 @@ impl<'a> TokenFactory<'a> for CommonTokenFactory {
     fn get_data(from: Self::From) -> Cow<'a, Self::Data> { from }
 }
 
+/// Token factory that produces the same tokens as `CommonTokenFactory`,
+/// but with lines and columns shifted to the position of the input in outer document.
+///
+/// Useful when lexing snippets embedded in another file, e.g. SQL inside a host language,
+/// so positions in tokens and error messages point into the outer document.
+/// Lines after the first one are shifted by the origin line only,
+/// while columns on the first line are also shifted by the origin column.
+/// Tokens without position, e.g. with negative line, are left as is.
+#[derive(Tid, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommonTokenFactoryWithOffset {
+    line: isize,
+    column: isize,
+}
+
+impl CommonTokenFactoryWithOffset {
+    /// Creates factory for input that starts at `line` and `column` of the outer document,
+    /// lines are 1-based and columns are 0-based same as in tokens
+    pub fn new(line: isize, column: isize) -> Self { Self { line, column } }
+
+    /// Line of the outer document where input starts
+    pub fn get_origin_line(&self) -> isize { self.line }
+
+    /// Column of the outer document where input starts
+    pub fn get_origin_column(&self) -> isize { self.column }
+
+    /// Converts position relative to input into position in the outer document
+    pub fn adjust(&self, line: isize, column: isize) -> (isize, isize) {
+        match line {
+            line if line < 1 => (line, column),
+            1 => (self.line, self.column + column),
+            line => (self.line + line - 1, column),
+        }
+    }
+}
+
+impl Default for CommonTokenFactoryWithOffset {
+    fn default() -> Self { Self::new(1, 0) }
+}
+
+impl<'a> TokenFactory<'a> for CommonTokenFactoryWithOffset {
+    type Inner = CommonToken<'a>;
+    type Tok = Box<Self::Inner>;
+    type Data = str;
+    type From = Cow<'a, str>;
+
+    #[inline]
+    fn create<T>(
+        &'a self, source: Option<&mut T>, ttype: isize, text: Option<String>, channel: isize,
+        start: isize, stop: isize, line: isize, column: isize,
+    ) -> Self::Tok
+    where
+        T: CharStream<Self::From> + ?Sized,
+    {
+        self.create_with_end(source, ttype, text, channel, start, stop, line, column, line, column)
+    }
+
+    #[inline]
+    fn create_with_end<T>(
+        &'a self, source: Option<&mut T>, ttype: isize, text: Option<String>, channel: isize,
+        start: isize, stop: isize, line: isize, column: isize, end_line: isize, end_column: isize,
+    ) -> Self::Tok
+    where
+        T: CharStream<Self::From> + ?Sized,
+    {
+        let (line, column) = self.adjust(line, column);
+        let (end_line, end_column) = self.adjust(end_line, end_column);
+        COMMON_TOKEN_FACTORY_DEFAULT.create_with_end(
+            source, ttype, text, channel, start, stop, line, column, end_line, end_column,
+        )
+    }
+
+    fn create_invalid() -> Self::Tok { INVALID_COMMON.clone() }
+
+    fn get_data(from: Self::From) -> Cow<'a, Self::Data> { from }
+}
+
 /// Token factory that produces heap allocated
 /// `OwningToken`s
 #[derive(Default, Tid, Debug)]
@@ -160,6 +303,26 @@ impl<'a> TokenFactory<'a> for OwningTokenFactory {
         line: isize,
         column: isize,
     ) -> Self::Tok
+    where
+        T: CharStream<String> + ?Sized,
+    {
+        self.create_with_end(source, ttype, text, channel, start, stop, line, column, line, column)
+    }
+
+    #[inline]
+    fn create_with_end<T>(
+        &'a self,
+        source: Option<&mut T>,
+        ttype: isize,
+        text: Option<String>,
+        channel: isize,
+        start: isize,
+        stop: isize,
+        line: isize,
+        column: isize,
+        end_line: isize,
+        end_column: isize,
+    ) -> Self::Tok
     where
         T: CharStream<String> + ?Sized,
     {
@@ -169,7 +332,7 @@ impl<'a> TokenFactory<'a> for OwningTokenFactory {
                 if stop >= x.size() || start >= x.size() {
                     "<EOF>".to_owned()
                 } else {
-                    x.get_text(start, stop)
+                    x.get_text(CharInterval::new(start, stop))
                 }
             }
             _ => String::new(),
@@ -180,8 +343,11 @@ impl<'a> TokenFactory<'a> for OwningTokenFactory {
             start,
             stop,
             token_index: AtomicIsize::new(-1),
+            mode: AtomicUsize::new(0),
             line,
             column,
+            end_line,
+            end_column,
             text,
             read_only: false,
         })
@@ -207,6 +373,10 @@ pub type ArenaCommonFactory<'a> = ArenaFactory<'a, CommonTokenFactory, CommonTok
 /// This is a wrapper for Token factory that allows to allocate tokens in separate arena.
 /// It can allow to significantly improve performance by passing Tokens by references everywhere.
 ///
+/// All tokens are freed at once when factory is dropped, so it must outlive
+/// lexer, token stream and parse tree that reference its tokens.
+/// Single factory can be reused for several inputs that have the same lifetime.
+///
 /// Requires `&'a Tok: Default` bound to produce invalid tokens, which can be easily implemented
 /// like this:
 /// ```text
@@ -236,6 +406,29 @@ impl<'input, TF: Debug, T> Debug for ArenaFactory<'input, TF, T> {
     }
 }
 
+impl<'input, TF, T> ArenaFactory<'input, TF, T>
+where
+    TF: Default,
+{
+    /// Creates factory with arena preallocated for `capacity` tokens,
+    /// e.g. estimated from the input size, to avoid arena growth during lexing
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            arena: Arena::with_capacity(capacity),
+            factory: Default::default(),
+            pd: Default::default(),
+        }
+    }
+}
+
+impl<'input, TF, T> ArenaFactory<'input, TF, T> {
+    /// Number of tokens allocated by this factory so far
+    pub fn len(&self) -> usize { self.arena.len() }
+
+    /// Whether this factory has not allocated any tokens yet
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
 impl<'input, TF, T> Default for ArenaFactory<'input, TF, T>
 where
     TF: Default,
@@ -282,6 +475,29 @@ where
         self.arena.alloc(*token)
     }
 
+    #[inline]
+    fn create_with_end<T>(
+        &'input self,
+        source: Option<&mut T>,
+        ttype: isize,
+        text: Option<<Self::Data as ToOwned>::Owned>,
+        channel: isize,
+        start: isize,
+        stop: isize,
+        line: isize,
+        column: isize,
+        end_line: isize,
+        end_column: isize,
+    ) -> Self::Tok
+    where
+        T: CharStream<Self::From> + ?Sized,
+    {
+        let token = self.factory.create_with_end(
+            source, ttype, text, channel, start, stop, line, column, end_line, end_column,
+        );
+        self.arena.alloc(*token)
+    }
+
     fn create_invalid() -> &'input Tok { <&Tok as Default>::default() }
 
     fn get_data(from: Self::From) -> Cow<'input, Self::Data> { TF::get_data(from) }
@@ -291,3 +507,39 @@ where
 pub trait TokenAware<'input> {
     type TF: TokenFactory<'input> + 'input;
 }
+
+#[cfg(test)]
+mod test {
+    use crate::input_stream::InputStream;
+    use crate::token::Token;
+
+    use super::{ArenaCommonFactory, CommonTokenFactoryWithOffset, TokenFactory};
+
+    #[test]
+    fn test_factory_with_offset() {
+        let factory = CommonTokenFactoryWithOffset::new(10, 4);
+        let mut input = InputStream::new("a\nb");
+        let first = factory.create_with_end(Some(&mut input), 1, None, 0, 0, 0, 1, 0, 1, 1);
+        let second = factory.create_with_end(Some(&mut input), 1, None, 0, 2, 2, 2, 0, 2, 1);
+        assert_eq!((first.get_line(), first.get_column()), (10, 4));
+        assert_eq!((first.get_end_line(), first.get_end_column()), (10, 5));
+        assert_eq!((second.get_line(), second.get_column()), (11, 0));
+        assert_eq!((second.get_end_line(), second.get_end_column()), (11, 1));
+        assert_eq!(second.get_text(), "b");
+        assert_eq!(factory.adjust(-1, -1), (-1, -1));
+        assert_eq!(CommonTokenFactoryWithOffset::default().adjust(3, 5), (3, 5));
+    }
+
+    #[test]
+    fn test_arena_factory() {
+        let factory = ArenaCommonFactory::with_capacity(2);
+        assert!(factory.is_empty());
+        let mut input = InputStream::new("abc");
+        let tokens = (0..3)
+            .map(|i| factory.create(Some(&mut input), 1, None, 0, i, i, 1, i))
+            .collect::<Vec<_>>();
+        assert_eq!(factory.len(), 3);
+        let texts = tokens.iter().map(|it| it.get_text()).collect::<Vec<_>>();
+        assert_eq!(texts, ["a", "b", "c"]);
+    }
+}
@@ -1,8 +1,23 @@
+//! Source of tokens for `TokenStream`s
+use std::borrow::Borrow;
+use std::io;
+
+use better_any::{Tid, TidAble};
+
 use crate::int_stream::IntStream;
+use crate::token::{Token, TOKEN_EOF};
 use crate::token_factory::TokenFactory;
 
 /// Produces tokens to be used by parser.
 /// `TokenStream` implementations are responsible for buffering tokens for parser lookahead
+///
+/// Token sources can be chained, so decorators that filter, change or inject tokens
+/// can be put in between lexer and token stream, see `TokenSourceExt`
+/// and `vocabulary::RemappedTokenSource`.
+/// ```text
+/// let source = lexer.filter_tokens(|token| token.get_token_type() != COMMENT);
+/// let parser = MyParser::new(CommonTokenStream::new(source));
+/// ```
 pub trait TokenSource<'input> {
     /// TokenFactory this token source produce tokens with
     type TF: TokenFactory<'input> + 'input;
@@ -11,6 +26,14 @@ pub trait TokenSource<'input> {
     /// on the characters until you get a good one; errors are not passed through
     /// to the parser.
     fn next_token(&mut self) -> <Self::TF as TokenFactory<'input>>::Tok;
+    /// Fallible version of `next_token` for token sources that read their input lazily,
+    /// so producing a token can fail with IO or decoding error.
+    ///
+    /// Token streams propagate returned error as `ANTLRError::Io`
+    /// and call this method again when the same token is requested next time.
+    fn try_next_token(&mut self) -> io::Result<<Self::TF as TokenFactory<'input>>::Tok> {
+        Ok(self.next_token())
+    }
     /**
      * Get the line number for the current position in the input stream. The
      * first line in the input is line 1.
@@ -50,6 +73,41 @@ where
     #[inline(always)]
     fn next_token(&mut self) -> <Self::TF as TokenFactory<'input>>::Tok { (**self).next_token() }
 
+    #[inline(always)]
+    fn try_next_token(&mut self) -> io::Result<<Self::TF as TokenFactory<'input>>::Tok> {
+        (**self).try_next_token()
+    }
+
+    #[inline(always)]
+    fn get_line(&self) -> isize { (**self).get_line() }
+
+    #[inline(always)]
+    fn get_char_position_in_line(&self) -> isize { (**self).get_char_position_in_line() }
+
+    #[inline(always)]
+    fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { (**self).get_input_stream() }
+
+    #[inline(always)]
+    fn get_source_name(&self) -> String { (**self).get_source_name() }
+
+    #[inline(always)]
+    fn get_token_factory(&self) -> &'input Self::TF { (**self).get_token_factory() }
+}
+
+// allows to chain token source decorators dynamically
+impl<'input, T> TokenSource<'input> for Box<T>
+where
+    T: TokenSource<'input> + ?Sized,
+{
+    type TF = T::TF;
+    #[inline(always)]
+    fn next_token(&mut self) -> <Self::TF as TokenFactory<'input>>::Tok { (**self).next_token() }
+
+    #[inline(always)]
+    fn try_next_token(&mut self) -> io::Result<<Self::TF as TokenFactory<'input>>::Tok> {
+        (**self).try_next_token()
+    }
+
     #[inline(always)]
     fn get_line(&self) -> isize { (**self).get_line() }
 
@@ -66,6 +124,88 @@ where
     fn get_token_factory(&self) -> &'input Self::TF { (**self).get_token_factory() }
 }
 
+/// Decorators for `TokenSource`
+pub trait TokenSourceExt<'input>: TokenSource<'input> + Sized {
+    /// Creates token source that skips tokens of `self` for which `predicate` returns false.
+    /// EOF is never skipped.
+    ///
+    /// `predicate` must be `'static` for the result to be `TidAble`,
+    /// so it can be consumed by generated parsers.
+    fn filter_tokens<F>(self, predicate: F) -> FilteredTokenSource<Self, F>
+    where
+        F: FnMut(&<Self::TF as TokenFactory<'input>>::Inner) -> bool + 'static,
+    {
+        FilteredTokenSource {
+            source: self,
+            predicate,
+        }
+    }
+}
+
+impl<'input, T: TokenSource<'input>> TokenSourceExt<'input> for T {}
+
+/// Token source that skips some tokens of the underlying one, see `TokenSourceExt::filter_tokens`
+#[derive(Tid, Debug)]
+pub struct FilteredTokenSource<T, F: 'static> {
+    source: T,
+    predicate: F,
+}
+
+impl<T, F: 'static> FilteredTokenSource<T, F> {
+    /// Returns underlying token source
+    pub fn get_ref(&self) -> &T { &self.source }
+
+    /// Returns underlying token source
+    pub fn into_inner(self) -> T { self.source }
+}
+
+impl<'input, T, F> TokenSource<'input> for FilteredTokenSource<T, F>
+where
+    T: TokenSource<'input>,
+    F: FnMut(&<T::TF as TokenFactory<'input>>::Inner) -> bool + 'static,
+{
+    type TF = T::TF;
+
+    fn next_token(&mut self) -> <Self::TF as TokenFactory<'input>>::Tok {
+        loop {
+            let token = self.source.next_token();
+            if self.accepts(&token) {
+                return token;
+            }
+        }
+    }
+
+    fn try_next_token(&mut self) -> io::Result<<Self::TF as TokenFactory<'input>>::Tok> {
+        loop {
+            let token = self.source.try_next_token()?;
+            if self.accepts(&token) {
+                return Ok(token);
+            }
+        }
+    }
+
+    fn get_line(&self) -> isize { self.source.get_line() }
+
+    fn get_char_position_in_line(&self) -> isize { self.source.get_char_position_in_line() }
+
+    fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { self.source.get_input_stream() }
+
+    fn get_source_name(&self) -> String { self.source.get_source_name() }
+
+    fn get_token_factory(&self) -> &'input Self::TF { self.source.get_token_factory() }
+}
+
+impl<'input, T, F> FilteredTokenSource<T, F>
+where
+    T: TokenSource<'input>,
+    F: FnMut(&<T::TF as TokenFactory<'input>>::Inner) -> bool + 'static,
+{
+    fn accepts(&mut self, token: &<T::TF as TokenFactory<'input>>::Tok) -> bool {
+        let token: &<T::TF as TokenFactory<'input>>::Inner = token.borrow();
+        token.get_token_type() == TOKEN_EOF || (self.predicate)(token)
+    }
+}
+
 // / adaptor to feed parser with existing tokens
 // pub struct IterTokenSource<S, F> where S: Iterator, S::Item: Token, F: TokenFactory<Tok=S::Item> {
 //     iter: S,
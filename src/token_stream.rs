@@ -4,12 +4,18 @@ use std::cmp::min;
 use std::marker::PhantomData;
 
 use crate::char_stream::InputData;
-use crate::int_stream::{IntStream, IterWrapper};
+use crate::int_stream::{panic_consume_eof, IntStream, IterWrapper};
+use crate::interval_set::TokenInterval;
 use crate::token::{OwningToken, Token, TOKEN_EOF, TOKEN_INVALID_TYPE};
 use crate::token_factory::TokenFactory;
 use crate::token_source::TokenSource;
+use crate::tree::ParseTree;
 use better_any::{Tid, TidAble};
 use std::fmt::{Debug, Formatter};
+use std::io;
+use std::rc::Rc;
+
+use crate::errors::{ANTLRError, IoError};
 
 /// An `IntSteam` of `Token`s
 ///
@@ -20,8 +26,43 @@ pub trait TokenStream<'input>: IntStream {
     /// Token factory that created tokens in this stream
     type TF: TokenFactory<'input> + 'input;
 
-    /// Lookahead for tokens, same as `IntSteam::la` but return reference to full token
+    /// Lookahead for tokens, same as `IntSteam::la` but return reference to full token.
+    /// Lookahead past the end of input returns the same EOF token every time.
     fn lt(&mut self, k: isize) -> Option<&<Self::TF as TokenFactory<'input>>::Tok>;
+    /// Fallible version of `lt` for streams that fetch tokens lazily from the token source
+    /// which can fail, see `TokenSource::try_next_token`.
+    ///
+    /// Infallible methods panic on such failure unless error deferral is enabled
+    /// with `set_defer_errors`.
+    ///
+    /// Default implementation is for in-memory streams that can't fail.
+    fn try_lt(
+        &mut self, k: isize,
+    ) -> Result<Option<&<Self::TF as TokenFactory<'input>>::Tok>, ANTLRError> {
+        Ok(self.lt(k))
+    }
+    /// Fallible version of `IntStream::consume`, nothing is consumed if error is returned.
+    fn try_consume(&mut self) -> Result<(), ANTLRError> {
+        self.consume();
+        Ok(())
+    }
+    /// Makes infallible methods treat token source failure as the end of input
+    /// and keep the error until it is returned from the next fallible call, instead of panicking.
+    ///
+    /// Parser enables it: generated code and error strategies look ahead with infallible
+    /// methods between fallible calls that match tokens and predict alternatives,
+    /// so the error is returned from the rule instead of a panic.
+    /// Code that enables it and reads a stream with infallible methods should finish
+    /// with a fallible call, e.g. `try_lt(1)`, otherwise it can't tell failed input
+    /// from the complete one.
+    ///
+    /// `TokenCursor` stores this flag in the shared `TokenBuffer`,
+    /// so it applies to every cursor reading from the same buffer.
+    ///
+    /// Default implementation does nothing, for in-memory streams that can't fail.
+    fn set_defer_errors(&mut self, _defer: bool) {}
+    /// Returns true if error deferral is enabled, see `set_defer_errors`
+    fn defers_errors(&self) -> bool { false }
     /// Returns reference to token at `index`
     fn get(&self, index: isize) -> &<Self::TF as TokenFactory<'input>>::Tok;
 
@@ -29,16 +70,31 @@ pub trait TokenStream<'input>: IntStream {
     fn get_token_source(&self) -> &dyn TokenSource<'input, TF = Self::TF>;
     //    fn set_token_source(&self,source: Box<TokenSource>);
     /// Get combined text of all tokens in this stream
-    fn get_all_text(&self) -> String { self.get_text_from_interval(0, self.size() - 1) }
-    /// Get combined text of tokens in start..=stop interval
-    fn get_text_from_interval(&self, start: isize, stop: isize) -> String;
-    //    fn get_text_from_rule_context(&self,context: RuleContext) -> String;
+    fn get_all_text(&self) -> String {
+        self.get_text_from_interval(TokenInterval::new(0, self.size() - 1))
+    }
+    /// Get combined text of tokens in `interval`, EOF token does not contribute to it
+    fn get_text_from_interval(&self, interval: TokenInterval) -> String;
+    /// Get source text of the `context` subtree, i.e. combined text of tokens from its start
+    /// to its stop token, including off-channel tokens in between.
+    /// Unlike `ParseTree::get_text` it is not limited to tokens added to the tree,
+    /// so whitespace and comments are preserved.
+    fn get_text_from_context<C: ParseTree<'input> + ?Sized>(&self, context: &C) -> String
+    where
+        Self: Sized,
+    {
+        let interval = context.get_source_interval();
+        if interval.a < 0 || interval.is_empty() {
+            return String::new();
+        }
+        self.get_text_from_interval(interval)
+    }
     /// Get combined text of tokens in between `a` and `b`
     fn get_text_from_tokens<T: Token + ?Sized>(&self, a: &T, b: &T) -> String
     where
         Self: Sized,
     {
-        self.get_text_from_interval(a.get_token_index(), b.get_token_index())
+        self.get_text_from_interval(TokenInterval::new(a.get_token_index(), b.get_token_index()))
     }
 }
 
@@ -67,23 +123,32 @@ impl<'a, 'input: 'a, T: TokenStream<'input>> Iterator for TokenIter<'a, 'input,
     }
 }
 
-/// Token stream that keeps all data in internal Vec
+/// Token stream that keeps tokens in internal Vec,
+/// either all of them or only ones that can still be reached by `seek`
 #[derive(Tid)]
 pub struct UnbufferedTokenStream<'input, T: TokenSource<'input>> {
-    token_source: T,
+    pub(crate) token_source: T,
     pub(crate) tokens: Vec<<T::TF as TokenFactory<'input>>::Tok>,
-    //todo prev token for lt(-1)
+    // token right before `tokens`, for `lt(-1)` after they have been released
+    last_token: Option<<T::TF as TokenFactory<'input>>::Tok>,
     pub(crate) current_token_index: isize,
     markers_count: isize,
     pub(crate) p: isize,
+    // token source error that happened in infallible method
+    pub(crate) error: Option<ANTLRError>,
+    // whether infallible methods keep token source error instead of panicking
+    pub(crate) defer_errors: bool,
 }
 impl<'input, T: TokenSource<'input>> Debug for UnbufferedTokenStream<'input, T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("UnbufferedTokenStream")
             .field("tokens", &self.tokens)
+            .field("last_token", &self.last_token)
             .field("current_token_index", &self.current_token_index)
             .field("markers_count", &self.markers_count)
             .field("p(buffer index)", &self.p)
+            .field("error", &self.error)
+            .field("defer_errors", &self.defer_errors)
             .finish()
     }
 }
@@ -104,14 +169,20 @@ impl<'input, T: TokenSource<'input>> UnbufferedTokenStream<'input, T> {
         a
     }
 
-    /// Creates token stream that keeps only tokens required by `mark`
+    /// Creates token stream that keeps only tokens required by `mark`.
+    ///
+    /// Consumed tokens are released unless there is an outstanding mark, so memory usage
+    /// does not depend on the input size. Only the last consumed token is kept for `lt(-1)`.
     pub fn new_unbuffered(source: T) -> Self {
         UnbufferedTokenStream {
             token_source: source,
             tokens: vec![],
+            last_token: None,
             current_token_index: 0,
             markers_count: 0,
             p: 0,
+            error: None,
+            defer_errors: false,
         }
     }
 
@@ -122,23 +193,123 @@ impl<'input, T: TokenSource<'input>> UnbufferedTokenStream<'input, T> {
         }
     }
 
-    fn get_buffer_start_index(&self) -> isize { self.current_token_index - self.p }
+    fn try_sync(&mut self, want: isize) -> Result<(), ANTLRError> {
+        let need = (self.p + want - 1) - self.tokens.len() as isize + 1;
+        if need > 0 {
+            self.try_fill(need)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_buffer_start_index(&self) -> isize { self.current_token_index - self.p }
+
+    /// Tokens with indexes in `from..=to`, fetching them from token source if needed,
+    /// without moving the stream.
+    ///
+    /// Slice ends early if EOF is reached. Token source failure is handled as in `lt`,
+    /// see `TokenStream::set_defer_errors`.
+    /// Panics if `from` is before the tokens kept by this stream.
+    pub fn peek_range(
+        &mut self, from: isize, to: isize,
+    ) -> &[<T::TF as TokenFactory<'input>>::Tok] {
+        let start = self.get_buffer_start_index();
+        runtime_assert!(
+            from >= start,
+            "token {} is not in token buffer window starting at {}",
+            from,
+            start
+        );
+        let need = to - (start + self.tokens.len() as isize) + 1;
+        if need > 0 {
+            self.fill(need);
+        }
+        let end = (to - start + 1).min(self.tokens.len() as isize);
+        let begin = (from - start).min(end);
+        &self.tokens[begin as usize..end.max(begin) as usize]
+    }
 
+    /// Same as `try_fill` but panics on error, or, if `defer_errors` is set,
+    /// keeps the error until the next `try_fill` call,
+    /// so until then stream looks like it ends right before the failed token.
     pub(crate) fn fill(&mut self, need: isize) -> isize {
+        if let Some(e) = &self.error {
+            if !self.defer_errors {
+                panic!("token source failed: {}", e)
+            }
+            return 0;
+        }
+        let len = self.tokens.len();
+        match self.try_fill(need) {
+            Ok(fetched) => fetched,
+            Err(e) if self.defer_errors => {
+                self.error = Some(e);
+                (self.tokens.len() - len) as isize
+            }
+            Err(e) => panic!("token source failed: {}", e),
+        }
+    }
+
+    /// Fetches `need` tokens from token source, returns number of fetched tokens
+    /// which is less than `need` only if EOF was reached.
+    ///
+    /// Tokens fetched before the error stay in the buffer,
+    /// failed token is requested from token source again on the next call.
+    pub(crate) fn try_fill(&mut self, need: isize) -> Result<isize, ANTLRError> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
         for i in 0..need {
-            if self.tokens.len() > 0
-                && self.tokens.last().unwrap().borrow().get_token_type() == TOKEN_EOF
-            {
-                return i;
+            if self.is_finished() {
+                return Ok(i);
             }
-            let token = self.token_source.next_token();
+            let token = match self.token_source.try_next_token() {
+                Ok(token) => token,
+                Err(e) => return Err(self.io_error(e)),
+            };
             token
                 .borrow()
                 .set_token_index(self.get_buffer_start_index() + self.tokens.len() as isize);
             self.tokens.push(token);
         }
 
-        need
+        Ok(need)
+    }
+
+    /// Returns true if EOF token was fetched
+    pub(crate) fn is_finished(&self) -> bool {
+        self.tokens
+            .last()
+            .map_or(false, |t| t.borrow().get_token_type() == TOKEN_EOF)
+    }
+
+    fn io_error(&self, error: io::Error) -> ANTLRError {
+        ANTLRError::Io(IoError {
+            token_index: self.get_buffer_start_index() + self.tokens.len() as isize,
+            line: self.token_source.get_line(),
+            column: self.token_source.get_char_position_in_line(),
+            error: Rc::new(error),
+        })
+    }
+
+    fn buffered_lt(&self, i: isize) -> Option<&<T::TF as TokenFactory<'input>>::Tok> {
+        if i == 0 {
+            return None;
+        }
+        if i < 0 {
+            let index = self.p + i;
+            return match index {
+                -1 => self.last_token.as_ref(),
+                index if index < 0 => None,
+                index => self.tokens.get(index as usize),
+            };
+        }
+
+        // lookahead past the end of stream returns EOF token
+        self.tokens.get((self.p + i - 1) as usize).or_else(|| {
+            self.tokens
+                .last()
+                .filter(|&t| t.borrow().get_token_type() == TOKEN_EOF)
+        })
     }
 }
 
@@ -147,15 +318,31 @@ impl<'input, T: TokenSource<'input>> TokenStream<'input> for UnbufferedTokenStre
 
     #[inline]
     fn lt(&mut self, i: isize) -> Option<&<Self::TF as TokenFactory<'input>>::Tok> {
-        if i == -1 {
-            return self.tokens.get(self.p as usize - 1);
+        if i > 0 {
+            self.sync(i);
         }
+        self.buffered_lt(i)
+    }
 
-        self.sync(i);
+    fn try_lt(
+        &mut self, i: isize,
+    ) -> Result<Option<&<Self::TF as TokenFactory<'input>>::Tok>, ANTLRError> {
+        if i > 0 {
+            self.try_sync(i)?;
+        }
+        Ok(self.buffered_lt(i))
+    }
 
-        self.tokens.get((self.p + i - 1) as usize)
+    fn try_consume(&mut self) -> Result<(), ANTLRError> {
+        self.try_sync(1)?;
+        self.consume();
+        Ok(())
     }
 
+    fn set_defer_errors(&mut self, defer: bool) { self.defer_errors = defer; }
+
+    fn defers_errors(&self) -> bool { self.defer_errors }
+
     #[inline]
     fn get(&self, index: isize) -> &<Self::TF as TokenFactory<'input>>::Tok {
         &self.tokens[(index - self.get_buffer_start_index()) as usize]
@@ -163,7 +350,8 @@ impl<'input, T: TokenSource<'input>> TokenStream<'input> for UnbufferedTokenStre
 
     fn get_token_source(&self) -> &dyn TokenSource<'input, TF = Self::TF> { &self.token_source }
 
-    fn get_text_from_interval(&self, start: isize, stop: isize) -> String {
+    fn get_text_from_interval(&self, interval: TokenInterval) -> String {
+        let TokenInterval { a: start, b: stop } = interval;
         //        println!("get_text_from_interval {}..{}",start,stop);
         //        println!("all tokens {:?}",self.tokens.iter().map(|x|x.as_ref().to_owned()).collect::<Vec<OwningToken>>());
 
@@ -196,11 +384,13 @@ impl<'input, T: TokenSource<'input>> IntStream for UnbufferedTokenStream<'input,
     #[inline]
     fn consume(&mut self) {
         if self.la(1) == TOKEN_EOF {
-            panic!("cannot consume EOF");
+            panic_consume_eof();
         }
 
-        if self.p == self.tokens.len() as isize && self.markers_count == 0 {
-            self.tokens.clear();
+        if self.markers_count == 0 && (self.p + 1) * 2 >= self.tokens.len() as isize {
+            // consumed tokens can't be reached by `seek` anymore, they are released
+            // once they make up at least half of the buffer to not shift lookahead too often
+            self.last_token = self.tokens.drain(..=self.p as usize).last();
             self.p = -1;
         }
 
@@ -213,9 +403,12 @@ impl<'input, T: TokenSource<'input>> IntStream for UnbufferedTokenStream<'input,
 
     #[inline]
     fn la(&mut self, i: isize) -> isize {
+        if i == 0 {
+            return TOKEN_INVALID_TYPE;
+        }
         self.lt(i)
             .map(|t| t.borrow().get_token_type())
-            .unwrap_or(TOKEN_INVALID_TYPE)
+            .unwrap_or(TOKEN_EOF)
     }
 
     #[inline]
@@ -226,11 +419,17 @@ impl<'input, T: TokenSource<'input>> IntStream for UnbufferedTokenStream<'input,
 
     #[inline]
     fn release(&mut self, marker: isize) {
-        assert_eq!(marker, -self.markers_count);
+        runtime_assert!(
+            marker == -self.markers_count,
+            "release of marker {} while {} markers are outstanding",
+            marker,
+            self.markers_count
+        );
 
         self.markers_count -= 1;
         if self.markers_count == 0 {
             if self.p > 0 {
+                self.last_token = Some(self.tokens[self.p as usize - 1].clone());
                 self.tokens.drain(0..self.p as usize);
                 //todo drain assembly is almost 2x longer than
                 // unsafe manual copy but need to bench before using unsafe
@@ -258,18 +457,27 @@ impl<'input, T: TokenSource<'input>> IntStream for UnbufferedTokenStream<'input,
     fn index(&self) -> isize { self.current_token_index }
 
     #[inline]
-    fn seek(&mut self, mut index: isize) {
+    fn seek(&mut self, index: isize) {
+        let mut index = index.max(0);
         if self.current_token_index == index {
             return;
         }
         if index > self.current_token_index {
-            self.sync(index - self.current_token_index);
-            index = min(index, self.get_buffer_start_index() + self.size() + 1);
+            self.sync(index - self.current_token_index + 1);
+            // clamp to the position of EOF
+            index = min(index, self.get_buffer_start_index() + self.size() - 1);
         }
         let i = index - self.get_buffer_start_index();
-        if i < 0 || i >= self.tokens.len() as isize {
-            panic!()
-        }
+        runtime_assert!(
+            i >= 0 && i < self.tokens.len() as isize,
+            {
+                current_index: self.current_token_index,
+                buffer_start: self.get_buffer_start_index(),
+                markers: self.markers_count,
+            },
+            "can't seek to token {} outside of the token buffer",
+            index
+        );
 
         self.p = i;
         self.current_token_index = index;
@@ -280,3 +488,103 @@ impl<'input, T: TokenSource<'input>> IntStream for UnbufferedTokenStream<'input,
 
     fn get_source_name(&self) -> String { self.token_source.get_source_name() }
 }
+
+/// Lazily fetched tokens of a token source.
+///
+/// Shared part of the token streams that skip tokens on other channels,
+/// which differ only in how tokens are stored and which of them are visible.
+pub(crate) trait FetchedTokens<'input> {
+    type TF: TokenFactory<'input> + 'input;
+
+    /// Number of tokens fetched so far
+    fn fetched(&self) -> isize;
+
+    /// Returns true if EOF token was fetched
+    fn is_finished(&self) -> bool;
+
+    /// Token at index `i`, which must have been fetched already
+    fn token(&self, i: isize) -> &<Self::TF as TokenFactory<'input>>::Inner;
+
+    /// Fetches tokens up to `i`, returns false if there is no token at `i` yet.
+    /// If not `fallible`, token source error is kept to be returned later.
+    fn fetch_to(&mut self, i: isize, fallible: bool) -> Result<bool, ANTLRError>;
+
+    /// Index of the first token starting from `i` in `direction` that is `visible`,
+    /// EOF token is always visible. Returns `-1` if there is none looking backwards.
+    ///
+    /// If token source has failed, returns index of the failed token to fetch it later.
+    fn next_visible(
+        &mut self, mut i: isize, direction: isize, fallible: bool,
+        visible: impl Fn(&<Self::TF as TokenFactory<'input>>::Inner) -> bool,
+    ) -> Result<isize, ANTLRError> {
+        if !self.fetch_to(i, fallible)? {
+            return Ok(if self.is_finished() {
+                self.fetched() - 1
+            } else {
+                i
+            });
+        }
+        loop {
+            let token = self.token(i);
+            if token.get_token_type() == TOKEN_EOF || visible(token) {
+                return Ok(i);
+            }
+            i += direction;
+            if i < 0 || !self.fetch_to(i, fallible)? {
+                return Ok(i);
+            }
+        }
+    }
+
+    /// Index of the `k`-th visible token counting from the visible token at `current` as 1st,
+    /// or the EOF token if there are fewer of them.
+    /// Returns `None` if token source failed before that token.
+    fn lookahead(
+        &mut self, current: isize, k: isize, fallible: bool,
+        visible: impl Fn(&<Self::TF as TokenFactory<'input>>::Inner) -> bool,
+    ) -> Result<Option<isize>, ANTLRError> {
+        let mut i = current;
+        for _ in 1..k {
+            if !self.fetch_to(i + 1, fallible)? {
+                if !self.is_finished() {
+                    // token source has failed, error is kept to be returned later
+                    return Ok(None);
+                }
+                break;
+            }
+            i = self.next_visible(i + 1, 1, fallible, &visible)?;
+        }
+        Ok(Some(i))
+    }
+}
+
+impl<'input, T: TokenSource<'input>> FetchedTokens<'input> for UnbufferedTokenStream<'input, T> {
+    type TF = T::TF;
+
+    fn fetched(&self) -> isize { self.size() }
+
+    fn is_finished(&self) -> bool { UnbufferedTokenStream::is_finished(self) }
+
+    fn token(&self, i: isize) -> &<T::TF as TokenFactory<'input>>::Inner {
+        self.tokens[i as usize].borrow()
+    }
+
+    fn fetch_to(&mut self, i: isize, fallible: bool) -> Result<bool, ANTLRError> {
+        let need = i - self.size() + 1;
+        if need > 0 {
+            let fetched = if fallible {
+                self.try_fill(need)?
+            } else {
+                self.fill(need)
+            };
+            return Ok(fetched >= need);
+        }
+        Ok(true)
+    }
+}
+
+/// Unwraps result of the `FetchedTokens` method that was called with `fallible == false`
+#[inline(always)]
+pub(crate) fn infallible<T>(result: Result<T, ANTLRError>) -> T {
+    result.unwrap_or_else(|_| unreachable!("token source error must be kept by the stream or panic"))
+}
@@ -115,7 +115,7 @@ impl Transition for RuleTransition {
     fn get_serialization_type(&self) -> TransitionType { TransitionType::TRANSITION_RULE }
 
     fn matches(&self, _symbol: isize, _min_vocab_symbol: isize, _max_vocab_symbol: isize) -> bool {
-        unimplemented!()
+        runtime_panic!({ target: self.target }, "rule transition can't match symbols")
     }
 }
 
@@ -234,7 +234,7 @@ impl Transition for WildcardTransition {
     fn get_serialization_type(&self) -> TransitionType { TransitionType::TRANSITION_WILDCARD }
 
     fn matches(&self, _symbol: isize, _min_vocab_symbol: isize, _max_vocab_symbol: isize) -> bool {
-        _symbol < _max_vocab_symbol && _symbol > _min_vocab_symbol
+        _symbol >= _min_vocab_symbol && _symbol <= _max_vocab_symbol
     }
 }
 
@@ -290,3 +290,58 @@ impl Transition for PrecedencePredicateTransition {
         Some(SemanticContext::Precedence(self.precedence))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_char_transitions() {
+        let max = LEXER_MAX_CHAR_VALUE;
+        let wildcard = WildcardTransition { target: 0 };
+        assert!(wildcard.get_reachable_target(0).is_some());
+        assert!(wildcard.get_reachable_target(max).is_some());
+        assert!(wildcard.get_reachable_target(-1).is_none());
+
+        // [\p{Emoji_Presentation}a-c] like set
+        let mut set = IntervalSet::new();
+        set.add_range('a' as isize, 'c' as isize);
+        set.add_range(0x1F600, 0x1F64F);
+        set.add_range(0x1F680, 0x1F6FF);
+        let not_set = NotSetTransition {
+            target: 0,
+            set: set.clone(),
+        };
+        let set = SetTransition { target: 0, set };
+        let cases = [
+            ('b', true),
+            ('d', false),
+            ('\u{1F600}', true),
+            ('\u{1F650}', false),
+        ];
+        for &(ch, in_set) in &cases {
+            assert_eq!(
+                set.get_reachable_target(ch as isize).is_some(),
+                in_set,
+                "{}",
+                ch
+            );
+            assert_eq!(
+                not_set.get_reachable_target(ch as isize).is_none(),
+                in_set,
+                "{}",
+                ch
+            );
+        }
+        assert!(not_set.get_reachable_target(max).is_some());
+        assert!(not_set.get_reachable_target(-1).is_none());
+
+        let range = RangeTransition {
+            target: 0,
+            start: 0x10000,
+            stop: max,
+        };
+        assert!(range.get_reachable_target(0x10FFFF).is_some());
+        assert!(range.get_reachable_target(0xFFFF).is_none());
+    }
+}
@@ -1,7 +1,7 @@
 //! General AST
 use std::any::{Any, TypeId};
 use std::borrow::Borrow;
-
+use std::cell::RefCell;
 use std::fmt::{Debug, Formatter};
 use std::iter::from_fn;
 use std::marker::PhantomData;
@@ -11,26 +11,34 @@ use std::rc::Rc;
 use crate::atn::INVALID_ALT;
 use crate::char_stream::InputData;
 use crate::int_stream::EOF;
-use crate::interval_set::Interval;
+use crate::interval_set::TokenInterval;
 use crate::parser::ParserNodeType;
-use crate::parser_rule_context::{ParserRuleContext, RuleContextExt};
+use crate::parser_rule_context::{ParserRuleContext, RuleContextExt, UserData};
 use crate::recognizer::Recognizer;
 use crate::rule_context::{CustomRuleContext, RuleContext};
 use crate::token::Token;
 use crate::token_factory::TokenFactory;
 use crate::{interval_set, trees};
-use better_any::{Tid, TidAble};
+use better_any::{impl_tid, Tid, TidAble};
 
 //todo try to make in more generic
+/// Navigation over the parse tree.
+///
+/// Navigation methods return `Rc` handles to the nodes that are already in the tree,
+/// so getting a parent or a child only increments reference count,
+/// nodes and their tokens are never copied.
 #[allow(missing_docs)]
 pub trait Tree<'input>: NodeText + RuleContext<'input> {
+    /// Handle of the parent node, `None` for the root and for leaves, they don't link to parent
     fn get_parent(&self) -> Option<Rc<<Self::Ctx as ParserNodeType<'input>>::Type>> { None }
     fn has_parent(&self) -> bool { false }
-    fn get_payload(&self) -> Box<dyn Any> { unimplemented!() }
+    fn get_payload(&self) -> Box<dyn Any> { unimplemented_feature!("Tree::get_payload") }
+    /// Handle of the `i`-th child node
     fn get_child(&self, _i: usize) -> Option<Rc<<Self::Ctx as ParserNodeType<'input>>::Type>> {
         None
     }
     fn get_child_count(&self) -> usize { 0 }
+    /// Iterates over handles of the child nodes, same ones `get_child` returns
     fn get_children<'a>(
         &'a self,
     ) -> Box<dyn Iterator<Item = Rc<<Self::Ctx as ParserNodeType<'input>>::Type>> + 'a>
@@ -54,11 +62,11 @@ pub trait Tree<'input>: NodeText + RuleContext<'input> {
 
 /// Tree that knows about underlying text
 pub trait ParseTree<'input>: Tree<'input> {
-    /// Return an {@link Interval} indicating the index in the
+    /// Return an {@link TokenInterval} indicating the index in the
     /// {@link TokenStream} of the first and last token associated with this
     /// subtree. If this node is a leaf, then the interval represents a single
     /// token and has interval i..i for token index i.
-    fn get_source_interval(&self) -> Interval { interval_set::INVALID }
+    fn get_source_interval(&self) -> TokenInterval { interval_set::INVALID }
 
     /// Return combined text of this AST node.
     /// To create resulting string it does traverse whole subtree,
@@ -66,7 +74,8 @@ pub trait ParseTree<'input>: Tree<'input> {
     ///
     /// Since tokens on hidden channels (e.g. whitespace or comments) are not
     ///	added to the parse trees, they will not appear in the output of this
-    ///	method.
+    ///	method. EOF token does not contribute to the text either,
+    /// `<EOF>` is shown only by display oriented methods like `to_string_tree`.
     fn get_text(&self) -> String { String::new() }
 
     /// Print out a whole tree, not just a node, in LISP format
@@ -119,6 +128,8 @@ pub struct IsError;
 pub struct LeafNode<'input, Node: ParserNodeType<'input>, T: 'static> {
     /// Token, this leaf consist of
     pub symbol: <Node::TF as TokenFactory<'input>>::Tok,
+    matched_type: Option<isize>,
+    user_data: RefCell<Option<UserData>>,
     iserror: PhantomData<T>,
 }
 
@@ -137,6 +148,14 @@ impl<'input, Node: ParserNodeType<'input> + TidAble<'input>, T: 'static + TidAbl
 impl<'input, Node: ParserNodeType<'input> + TidAble<'input>, T: 'static + TidAble<'input>>
     ParserRuleContext<'input> for LeafNode<'input, Node, T>
 {
+    fn subtree_has_errors(&self) -> bool { TypeId::of::<T>() == TypeId::of::<IsError>() }
+
+    fn user_data_slot<'a>(&'a self) -> Option<&'a RefCell<Option<UserData>>>
+    where
+        'input: 'a,
+    {
+        Some(&self.user_data)
+    }
 }
 
 impl<'input, Node: ParserNodeType<'input>, T: 'static> Tree<'input> for LeafNode<'input, Node, T> {}
@@ -147,30 +166,41 @@ impl<'input, Node: ParserNodeType<'input>, T: 'static> RuleContext<'input>
 }
 
 impl<'input, Node: ParserNodeType<'input>, T: 'static> NodeText for LeafNode<'input, Node, T> {
-    fn get_node_text(&self, _rule_names: &[&str]) -> String {
-        self.symbol.borrow().get_text().to_display()
+    fn get_node_text(&self, _rule_names: &[&str]) -> String { leaf_node_text(self.symbol.borrow()) }
+}
+
+/// Display text of the leaf with `token`, `<EOF>` for EOF token
+pub(crate) fn leaf_node_text<T: Token + ?Sized>(token: &T) -> String {
+    if token.get_token_type() == EOF {
+        "<EOF>".to_owned()
+    } else {
+        token.get_text().to_display()
+    }
+}
+
+/// Text the leaf with `token` contributes to the tree text, EOF token contributes nothing
+pub(crate) fn leaf_text<T: Token + ?Sized>(token: &T) -> String {
+    if token.get_token_type() == EOF {
+        String::new()
+    } else {
+        token.get_text().to_display()
     }
 }
 
 impl<'input, Node: ParserNodeType<'input>, T: 'static> ParseTree<'input>
     for LeafNode<'input, Node, T>
 {
-    fn get_source_interval(&self) -> Interval {
+    fn get_source_interval(&self) -> TokenInterval {
         let i = self.symbol.borrow().get_token_index();
-        Interval { a: i, b: i }
+        TokenInterval::new(i, i)
     }
 
-    fn get_text(&self) -> String { self.symbol.borrow().get_text().to_display() }
+    fn get_text(&self) -> String { leaf_text(self.symbol.borrow()) }
 }
 
 impl<'input, Node: ParserNodeType<'input>, T: 'static> Debug for LeafNode<'input, Node, T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if self.symbol.borrow().get_token_type() == EOF {
-            f.write_str("<EOF>")
-        } else {
-            let a = self.symbol.borrow().get_text().to_display();
-            f.write_str(&a)
-        }
+        f.write_str(&leaf_node_text(self.symbol.borrow()))
     }
 }
 
@@ -179,9 +209,32 @@ impl<'input, Node: ParserNodeType<'input>, T: 'static> LeafNode<'input, Node, T>
     pub fn new(symbol: <Node::TF as TokenFactory<'input>>::Tok) -> Self {
         Self {
             symbol,
+            matched_type: None,
+            user_data: RefCell::new(None),
             iserror: Default::default(),
         }
     }
+
+    /// Creates leaf node for `symbol` that parser has matched as a token of `matched_type`
+    /// without changing the token itself, see `BaseParser::set_soft_keywords`
+    pub fn with_matched_type(
+        symbol: <Node::TF as TokenFactory<'input>>::Tok, matched_type: isize,
+    ) -> Self {
+        Self {
+            matched_type: Some(matched_type),
+            ..Self::new(symbol)
+        }
+    }
+
+    /// Token type this node was matched as by parser.
+    /// Differs from the type of `symbol` only if it was reinterpreted as a soft keyword or identifier
+    pub fn get_matched_type(&self) -> isize {
+        self.matched_type
+            .unwrap_or_else(|| self.symbol.borrow().get_token_type())
+    }
+
+    /// Returns true if parser has matched `symbol` as a token of another type
+    pub fn is_reinterpreted(&self) -> bool { self.matched_type.is_some() }
 }
 
 /// non-error AST leaf node
@@ -284,6 +337,14 @@ pub trait ParseTreeListener<'input, Node: ParserNodeType<'input>> {
     fn exit_every_rule(&mut self, _ctx: &Node::Type) {}
 }
 
+// so that error strategy trait object can be used by parsers
+// that are notified via this trait object directly, i.e. `ParserInterpreter`
+#[impl_tid]
+impl<'input, Node: ParserNodeType<'input>> TidAble<'input>
+    for dyn ParseTreeListener<'input, Node> + 'input
+{
+}
+
 /// Types that can accept particular listener
 /// ** Usually implemented only in generated parser **
 pub trait Listenable<T: ?Sized> {
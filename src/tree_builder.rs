@@ -0,0 +1,395 @@
+//! Construction of parse trees without running a parser
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicIsize, AtomicUsize};
+
+use better_any::{Tid, TidAble};
+
+use crate::int_stream::EOF;
+use crate::parser_rule_context::BaseParserRuleContext;
+use crate::rule_context::{CustomRuleContext, EmptyContext, EmptyContextType};
+use crate::token::{CommonToken, TOKEN_DEFAULT_CHANNEL};
+use crate::token_factory::CommonTokenFactory;
+use crate::tree::{ErrorNode, TerminalNode};
+
+/// Node type of the trees created by `TreeBuilder`
+pub type BuiltTree<'input> = EmptyContext<'input, CommonTokenFactory>;
+
+type BuiltNodeType<'input> = EmptyContextType<'input, CommonTokenFactory>;
+
+/// Context extension of the rule nodes created by `TreeBuilder`, stores only rule index
+#[derive(Tid, Debug)]
+pub struct BuiltRuleContext<'input> {
+    rule_index: usize,
+    ph: PhantomData<&'input str>,
+}
+
+impl<'input> CustomRuleContext<'input> for BuiltRuleContext<'input> {
+    type TF = CommonTokenFactory;
+    type Ctx = BuiltNodeType<'input>;
+
+    fn get_rule_index(&self) -> usize { self.rule_index }
+}
+
+struct OpenRule<'input> {
+    node: Rc<BuiltTree<'input>>,
+    first_token: Option<Box<CommonToken<'input>>>,
+}
+
+/// Builds parse tree by hand, e.g. for tests or code generators.
+///
+/// Tokens get sequential token indexes starting from 0 and char positions as if their texts
+/// were concatenated without separators, all on the first line.
+/// Rule nodes get start and stop tokens and parent links like the ones created by parser,
+/// rules without tokens have invalid start and stop tokens.
+///
+/// Mistakes in builder calls are reported by `finish`, so calls can be chained freely:
+/// ```
+/// use antlr_rust::tree::Tree;
+/// use antlr_rust::tree_builder::TreeBuilder;
+///
+/// let tree = TreeBuilder::new()
+///     .rule(0)
+///     .token(1, "x")
+///     .rule(1)
+///     .token(2, "y")
+///     .end()
+///     .end()
+///     .finish()
+///     .unwrap();
+/// assert_eq!(tree.get_child_count(), 2);
+/// ```
+pub struct TreeBuilder<'input> {
+    stack: Vec<OpenRule<'input>>,
+    root: Option<Rc<BuiltTree<'input>>>,
+    last_token: Option<Box<CommonToken<'input>>>,
+    next_token_index: isize,
+    next_char_index: isize,
+    error: Option<TreeBuilderError>,
+}
+
+/// Error returned by `TreeBuilder::finish`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TreeBuilderError {
+    /// Leaf was added or rule was closed while there was no open rule
+    NoOpenRule {
+        /// Name of the builder method that failed
+        operation: &'static str,
+    },
+    /// Rule was opened after the root rule was closed
+    MultipleRoots,
+    /// `finish` was called while some rules were not closed
+    UnclosedRules(usize),
+    /// `finish` was called without any rules
+    Empty,
+}
+
+impl Display for TreeBuilderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeBuilderError::NoOpenRule { operation } => {
+                write!(f, "`{}` called while there is no open rule", operation)
+            }
+            TreeBuilderError::MultipleRoots => f.write_str("tree can have only one root rule"),
+            TreeBuilderError::UnclosedRules(count) => {
+                write!(f, "{} rule(s) were not closed with `end`", count)
+            }
+            TreeBuilderError::Empty => f.write_str("tree has no rules"),
+        }
+    }
+}
+
+impl Error for TreeBuilderError {}
+
+impl<'input> Default for TreeBuilder<'input> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<'input> TreeBuilder<'input> {
+    /// Creates new builder for empty tree
+    pub fn new() -> Self {
+        TreeBuilder {
+            stack: vec![],
+            root: None,
+            last_token: None,
+            next_token_index: 0,
+            next_char_index: 0,
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, error: TreeBuilderError) {
+        if self.error.is_none() {
+            self.error = Some(error)
+        }
+    }
+
+    /// Opens new rule node with `rule_index` as a child of the current rule
+    pub fn rule(mut self, rule_index: usize) -> Self {
+        if self.stack.is_empty() && self.root.is_some() {
+            self.fail(TreeBuilderError::MultipleRoots);
+            return self;
+        }
+        let parent = self.stack.last().map(|it| it.node.clone());
+        let node: Rc<BuiltTree<'input>> = Rc::new(BaseParserRuleContext::new_parser_ctx(
+            parent.clone(),
+            -1,
+            BuiltRuleContext {
+                rule_index,
+                ph: PhantomData,
+            },
+        ));
+        match parent {
+            Some(parent) => parent.add_child(node.clone()),
+            None => self.root = Some(node.clone()),
+        }
+        self.stack.push(OpenRule {
+            node,
+            first_token: None,
+        });
+        self
+    }
+
+    /// Closes current rule
+    pub fn end(mut self) -> Self {
+        match self.stack.pop() {
+            Some(rule) => {
+                let stop = rule.first_token.as_ref().and(self.last_token.clone());
+                rule.node.set_start(rule.first_token);
+                rule.node.set_stop(stop);
+            }
+            None => self.fail(TreeBuilderError::NoOpenRule { operation: "end" }),
+        }
+        self
+    }
+
+    /// Adds terminal node with synthesized token of `token_type` to the current rule
+    pub fn token(mut self, token_type: isize, text: impl Into<Cow<'input, str>>) -> Self {
+        if let Some((parent, token)) = self.create_token("token", token_type, text.into()) {
+            parent.add_child(Rc::new(TerminalNode::<BuiltNodeType<'input>>::new(token)))
+        }
+        self
+    }
+
+    /// Adds error node with synthesized token of `token_type` to the current rule
+    pub fn error_token(mut self, token_type: isize, text: impl Into<Cow<'input, str>>) -> Self {
+        if let Some((parent, token)) = self.create_token("error_token", token_type, text.into()) {
+            parent.add_child(Rc::new(ErrorNode::<BuiltNodeType<'input>>::new(token)))
+        }
+        self
+    }
+
+    fn create_token(
+        &mut self, operation: &'static str, token_type: isize, text: Cow<'input, str>,
+    ) -> Option<(Rc<BuiltTree<'input>>, Box<CommonToken<'input>>)> {
+        let parent = match self.stack.last() {
+            Some(rule) => rule.node.clone(),
+            None => {
+                self.fail(TreeBuilderError::NoOpenRule { operation });
+                return None;
+            }
+        };
+        let start = self.next_char_index;
+        // like the one emitted by lexer, EOF token does not occupy any chars
+        let len = if token_type == EOF {
+            0
+        } else {
+            text.chars().count() as isize
+        };
+        let token = Box::new(CommonToken {
+            token_type,
+            channel: TOKEN_DEFAULT_CHANNEL,
+            start,
+            stop: start + len - 1,
+            token_index: AtomicIsize::new(self.next_token_index),
+            mode: AtomicUsize::new(0),
+            line: 1,
+            column: start,
+            end_line: 1,
+            end_column: start + len,
+            text,
+            read_only: false,
+        });
+        self.next_token_index += 1;
+        self.next_char_index += len;
+        for rule in self.stack.iter_mut().rev() {
+            if rule.first_token.is_some() {
+                break;
+            }
+            rule.first_token = Some(token.clone());
+        }
+        self.last_token = Some(token.clone());
+        Some((parent, token))
+    }
+
+    /// Returns root of the built tree
+    pub fn finish(self) -> Result<Rc<BuiltTree<'input>>, TreeBuilderError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if !self.stack.is_empty() {
+            return Err(TreeBuilderError::UnclosedRules(self.stack.len()));
+        }
+        self.root.ok_or(TreeBuilderError::Empty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use better_any::TidExt;
+
+    use crate::token::Token;
+    use crate::trees;
+
+    use super::*;
+
+    const RULE_NAMES: &[&str] = &["s", "e"];
+
+    // leaf nodes don't implement `ParserRuleContext::start`
+    fn leaf_token<'a, 'input>(node: &'a BuiltTree<'input>) -> &'a CommonToken<'input> {
+        &node
+            .downcast_ref::<TerminalNode<'input, BuiltNodeType<'input>>>()
+            .unwrap()
+            .symbol
+    }
+
+    #[test]
+    fn test_build_tree() {
+        let root = TreeBuilder::new()
+            .rule(0)
+            .rule(1)
+            .rule(1)
+            .token(1, "ab")
+            .end()
+            .token(2, "+")
+            .rule(1)
+            .token(1, "c")
+            .end()
+            .end()
+            .rule(1)
+            .end()
+            .error_token(3, ";")
+            .token(EOF, "<EOF>")
+            .end()
+            .finish()
+            .unwrap();
+
+        assert_eq!(
+            trees::string_tree(&*root, RULE_NAMES),
+            "(s (e (e ab) + (e c)) e ; <EOF>)"
+        );
+        let interval = root.get_source_interval();
+        assert_eq!((interval.a, interval.b), (0, 4));
+
+        let sum = root.get_child(0).unwrap();
+        let interval = sum.get_source_interval();
+        assert_eq!((interval.a, interval.b), (0, 2));
+        assert!(Rc::ptr_eq(&sum.get_parent().unwrap(), &root));
+        let right = sum.get_child(2).unwrap();
+        assert_eq!(right.get_text(), "c");
+        assert_eq!(
+            (right.start().get_start(), right.start().get_stop()),
+            (3, 3)
+        );
+        assert!(Rc::ptr_eq(&right.get_parent().unwrap(), &sum));
+        assert!(root.get_parent().is_none());
+
+        let empty = root.get_child(1).unwrap();
+        assert_eq!(empty.get_child_count(), 0);
+        assert_eq!(empty.start().get_token_index(), -1);
+
+        let eof = root.get_child(3).unwrap();
+        assert_eq!(eof.get_source_interval().a, 4);
+        let eof_token = leaf_token(&*eof);
+        assert_eq!((eof_token.get_start(), eof_token.get_stop()), (5, 4));
+        assert!(root.subtree_has_errors());
+        assert!(!sum.subtree_has_errors());
+    }
+
+    #[test]
+    fn test_node_text() {
+        let root = TreeBuilder::new()
+            .rule(0)
+            .rule(1)
+            .token(1, "x")
+            .end()
+            .end()
+            .finish()
+            .unwrap();
+        let child = root.get_child(0).unwrap();
+        assert_eq!(root.get_node_text(RULE_NAMES), "s");
+        assert_eq!(child.get_node_text(RULE_NAMES), "e");
+        // rule index is displayed if rule names are not known
+        assert_eq!(child.get_node_text(&RULE_NAMES[..1]), "1");
+        assert_eq!(trees::string_tree(&*root, &[]), "(0 (1 x))");
+    }
+
+    #[test]
+    fn test_navigation_shares_nodes() {
+        let mut builder = TreeBuilder::new().rule(0);
+        for _ in 0..2500 {
+            builder = builder
+                .rule(1)
+                .token(1, "a")
+                .token(2, "+")
+                .token(1, "b")
+                .end();
+        }
+        let root = builder.end().finish().unwrap();
+        let root_count = Rc::strong_count(&root);
+
+        let mut visited = 1;
+        for (i, child) in root.get_children().enumerate() {
+            assert!(Rc::ptr_eq(&child, &root.get_child(i).unwrap()));
+            assert!(Rc::ptr_eq(&child.get_parent().unwrap(), &root));
+            for (j, leaf) in child.get_children().enumerate() {
+                let same = child.get_child(j).unwrap();
+                assert!(Rc::ptr_eq(&leaf, &same));
+                assert!(std::ptr::eq(leaf_token(&*leaf), leaf_token(&*same)));
+                assert!(leaf.get_parent().is_none());
+                visited += 1;
+            }
+            visited += 1;
+        }
+        assert_eq!(visited, 10001);
+        assert_eq!(Rc::strong_count(&root), root_count);
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(
+            TreeBuilder::new().token(1, "x").finish().unwrap_err(),
+            TreeBuilderError::NoOpenRule { operation: "token" }
+        );
+        assert_eq!(
+            TreeBuilder::new().rule(0).end().end().finish().unwrap_err(),
+            TreeBuilderError::NoOpenRule { operation: "end" }
+        );
+        assert_eq!(
+            TreeBuilder::new()
+                .rule(0)
+                .end()
+                .rule(0)
+                .end()
+                .finish()
+                .unwrap_err(),
+            TreeBuilderError::MultipleRoots
+        );
+        assert_eq!(
+            TreeBuilder::new()
+                .rule(0)
+                .rule(1)
+                .end()
+                .finish()
+                .unwrap_err(),
+            TreeBuilderError::UnclosedRules(1)
+        );
+        assert_eq!(
+            TreeBuilder::new().finish().unwrap_err(),
+            TreeBuilderError::Empty
+        );
+    }
+}
@@ -0,0 +1,402 @@
+//! Text edits derived from parse tree nodes.
+//!
+//! Unlike token stream rewriting, edits here are expressed as char ranges of the original input
+//! with replacement text, so they can be applied to the source directly or sent to an editor,
+//! e.g. as LSP `TextEdit`s.
+//!
+//! ```
+//! use antlr_rust::tree::Tree;
+//! use antlr_rust::tree_builder::TreeBuilder;
+//! use antlr_rust::tree_edits::{self, EditSet};
+//!
+//! let call = TreeBuilder::new()
+//!     .rule(0)
+//!     .token(1, "f")
+//!     .token(2, "(")
+//!     .rule(1)
+//!     .token(3, "x")
+//!     .end()
+//!     .token(4, ")")
+//!     .end()
+//!     .finish()
+//!     .unwrap();
+//! let argument = call.get_child(2).unwrap();
+//!
+//! let mut edits = EditSet::new();
+//! edits.add(tree_edits::replace(&*argument, "g(x)").unwrap()).unwrap();
+//! edits.add(tree_edits::insert_after(&*argument, ", y").unwrap()).unwrap();
+//! assert_eq!(edits.apply("f(x)"), "f(g(x), y)");
+//! ```
+use std::borrow::Borrow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use better_any::TidExt;
+
+use crate::interval_set::{CharInterval, TokenInterval};
+use crate::parser_rule_context::ParserRuleContext;
+use crate::token::{Token, TOKEN_DEFAULT_CHANNEL, TOKEN_EOF};
+use crate::token_factory::TokenFactory;
+use crate::token_stream::TokenStream;
+use crate::tree::{ErrorNode, TerminalNode};
+
+/// Replacement of the chars in `range` of the source with `new_text`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TextEdit {
+    /// Char indexes of the replaced text, inclusive.
+    /// Empty range `a..a-1` means insertion right before char `a`
+    pub range: CharInterval,
+    /// Text that replaces `range`
+    pub new_text: String,
+}
+
+impl TextEdit {
+    /// Returns true if this edit does not remove anything from the source
+    pub fn is_insertion(&self) -> bool { self.range.b < self.range.a }
+
+    fn conflicts_with(&self, other: &TextEdit) -> bool {
+        match (self.is_insertion(), other.is_insertion()) {
+            (true, true) => false,
+            (true, false) => inserted_inside(self.range.a, &other.range),
+            (false, true) => inserted_inside(other.range.a, &self.range),
+            (false, false) => self.range.a <= other.range.b && other.range.a <= self.range.b,
+        }
+    }
+}
+
+// insertion right at the start or right after the end of the replaced range is fine
+fn inserted_inside(point: isize, range: &CharInterval) -> bool {
+    range.a < point && point <= range.b
+}
+
+/// Error returned when edit can't be created or added to `EditSet`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EditError {
+    /// Node does not correspond to any text of the source,
+    /// e.g. rule that matched nothing or token conjured up during error recovery
+    NoSourceRange,
+    /// Edit overlaps with the edit that is already in the set
+    Conflict {
+        /// Edit that was already added
+        existing: TextEdit,
+        /// Rejected edit
+        new: TextEdit,
+    },
+}
+
+impl Display for EditError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::NoSourceRange => f.write_str("node has no text in the source"),
+            EditError::Conflict { existing, new } => write!(
+                f,
+                "edit of chars {}..{} overlaps with edit of chars {}..{}",
+                new.range.a, new.range.b, existing.range.a, existing.range.b
+            ),
+        }
+    }
+}
+
+impl Error for EditError {}
+
+/// Non-overlapping set of text edits
+#[derive(Debug, Clone, Default)]
+pub struct EditSet {
+    edits: Vec<TextEdit>,
+}
+
+impl EditSet {
+    /// Creates empty set
+    pub fn new() -> Self { Self::default() }
+
+    /// Adds `edit` to this set, fails if it overlaps with any edit already in the set.
+    /// Adjacent edits and several insertions at the same position are allowed,
+    /// insertions at the same position are applied in the order they were added.
+    pub fn add(&mut self, edit: TextEdit) -> Result<(), EditError> {
+        if let Some(existing) = self.edits.iter().find(|it| it.conflicts_with(&edit)) {
+            return Err(EditError::Conflict {
+                existing: existing.clone(),
+                new: edit,
+            });
+        }
+        self.edits.push(edit);
+        Ok(())
+    }
+
+    /// Edits in the order they were added
+    pub fn edits(&self) -> &[TextEdit] { &self.edits }
+
+    /// Returns true if there are no edits
+    pub fn is_empty(&self) -> bool { self.edits.is_empty() }
+
+    /// Returns edits ordered by their position in the source
+    pub fn into_sorted_edits(mut self) -> Vec<TextEdit> {
+        self.sort();
+        self.edits
+    }
+
+    fn sort(&mut self) {
+        // stable, so insertions at the same position keep their order
+        // and go before the replacement that starts there
+        self.edits
+            .sort_by_key(|it| (it.range.a, !it.is_insertion()));
+    }
+
+    /// Applies edits to `source` which must be the text the edited tree was parsed from.
+    /// Ranges that go beyond the end of `source` are clamped to it
+    pub fn apply(&self, source: &str) -> String {
+        let mut edits = self.clone();
+        edits.sort();
+
+        let offsets = source
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(source.len()))
+            .collect::<Vec<_>>();
+        let offset = |char_index: isize| {
+            let char_index = (char_index.max(0) as usize).min(offsets.len() - 1);
+            offsets[char_index]
+        };
+
+        let mut result = String::with_capacity(source.len());
+        let mut copied = 0;
+        for edit in &edits.edits {
+            let start = offset(edit.range.a);
+            result.push_str(&source[copied..start.max(copied)]);
+            result.push_str(&edit.new_text);
+            copied = copied.max(offset(edit.range.b + 1));
+        }
+        result.push_str(&source[copied..]);
+        result
+    }
+}
+
+/// Token index and char intervals covered by `node`
+fn node_span<'input, T>(node: &T) -> Result<(TokenInterval, CharInterval), EditError>
+where
+    T: ParserRuleContext<'input> + ?Sized,
+{
+    let leaf = node
+        .downcast_ref::<TerminalNode<'input, T::Ctx>>()
+        .map(|it| &it.symbol)
+        .or_else(|| {
+            node.downcast_ref::<ErrorNode<'input, T::Ctx>>()
+                .map(|it| &it.symbol)
+        });
+    let span = match leaf {
+        Some(token) => token_span::<T::TF>(token.borrow(), token.borrow()),
+        None => token_span::<T::TF>(&*node.start(), &*node.stop()),
+    };
+    span.ok_or(EditError::NoSourceRange)
+}
+
+fn token_span<'input, TF: TokenFactory<'input>>(
+    start: &TF::Inner, stop: &TF::Inner,
+) -> Option<(TokenInterval, CharInterval)> {
+    let tokens = TokenInterval::new(start.get_token_index(), stop.get_token_index());
+    if tokens.a < 0 || tokens.b < tokens.a {
+        return None;
+    }
+    let chars = CharInterval::new(start.get_start(), stop.get_stop());
+    Some((tokens, chars))
+}
+
+/// Creates edit that replaces text of `node` with `new_text`
+pub fn replace<'input, T>(node: &T, new_text: impl Into<String>) -> Result<TextEdit, EditError>
+where
+    T: ParserRuleContext<'input> + ?Sized,
+{
+    let (_, range) = node_span(node)?;
+    Ok(TextEdit {
+        range,
+        new_text: new_text.into(),
+    })
+}
+
+/// Creates edit that inserts `text` right before `node`
+pub fn insert_before<'input, T>(node: &T, text: impl Into<String>) -> Result<TextEdit, EditError>
+where
+    T: ParserRuleContext<'input> + ?Sized,
+{
+    let (_, range) = node_span(node)?;
+    Ok(TextEdit {
+        range: CharInterval::new(range.a, range.a - 1),
+        new_text: text.into(),
+    })
+}
+
+/// Creates edit that inserts `text` right after `node`
+pub fn insert_after<'input, T>(node: &T, text: impl Into<String>) -> Result<TextEdit, EditError>
+where
+    T: ParserRuleContext<'input> + ?Sized,
+{
+    let (_, range) = node_span(node)?;
+    Ok(TextEdit {
+        range: CharInterval::new(range.b + 1, range.b),
+        new_text: text.into(),
+    })
+}
+
+/// Creates edit that removes text of `node`
+pub fn delete<'input, T>(node: &T) -> Result<TextEdit, EditError>
+where
+    T: ParserRuleContext<'input> + ?Sized,
+{
+    replace(node, "")
+}
+
+/// Same as `delete` but also removes hidden channel tokens, e.g. whitespace,
+/// right after `node`, or right before it if there are none after.
+///
+/// `tokens` must be the stream `node` was parsed from, or any `TokenCursor`
+/// of the `TokenBuffer` that stream has been reading.
+pub fn delete_with_trivia<'input, T, S>(node: &T, tokens: &S) -> Result<TextEdit, EditError>
+where
+    T: ParserRuleContext<'input> + ?Sized,
+    S: TokenStream<'input> + ?Sized,
+{
+    let (token_range, mut range) = node_span(node)?;
+    let is_trivia = |index: isize| {
+        let token = tokens.get(index).borrow();
+        token.get_channel() != TOKEN_DEFAULT_CHANNEL && token.get_token_type() != TOKEN_EOF
+    };
+
+    let mut last = token_range.b;
+    while last + 1 < tokens.size() && is_trivia(last + 1) {
+        last += 1;
+    }
+    if last != token_range.b {
+        range.b = tokens.get(last).borrow().get_stop();
+    } else {
+        let mut first = token_range.a;
+        while first > 0 && is_trivia(first - 1) {
+            first -= 1;
+        }
+        range.a = tokens.get(first).borrow().get_start();
+    }
+
+    Ok(TextEdit {
+        range,
+        new_text: String::new(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::tree_builder::{BuiltTree, TreeBuilder};
+
+    use super::*;
+
+    // f(x,yy)
+    fn call() -> Rc<BuiltTree<'static>> {
+        TreeBuilder::new()
+            .rule(0)
+            .token(1, "f")
+            .token(2, "(")
+            .rule(1)
+            .token(3, "x")
+            .end()
+            .token(4, ",")
+            .rule(1)
+            .token(3, "yy")
+            .end()
+            .rule(1)
+            .end()
+            .token(5, ")")
+            .end()
+            .finish()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_ranges() {
+        let root = call();
+        let x = root.get_child(2).unwrap();
+        let y = root.get_child(4).unwrap();
+        let close = root.get_child(6).unwrap();
+
+        assert_eq!(replace(&*y, "z").unwrap().range, CharInterval::new(4, 5));
+        assert_eq!(replace(&*root, "").unwrap().range, CharInterval::new(0, 6));
+        assert_eq!(
+            replace(&*close, "]").unwrap().range,
+            CharInterval::new(6, 6)
+        );
+        assert_eq!(
+            insert_before(&*x, "&").unwrap().range,
+            CharInterval::new(2, 1)
+        );
+        assert_eq!(
+            insert_after(&*y, "!").unwrap().range,
+            CharInterval::new(6, 5)
+        );
+        assert_eq!(
+            delete(&*root.get_child(5).unwrap()),
+            Err(EditError::NoSourceRange)
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let root = call();
+        let x = root.get_child(2).unwrap();
+        let comma = root.get_child(3).unwrap();
+        let y = root.get_child(4).unwrap();
+        let source = "f(x,yy)";
+
+        let mut edits = EditSet::new();
+        edits.add(insert_after(&*y, ")").unwrap()).unwrap();
+        edits.add(replace(&*y, "g(yy").unwrap()).unwrap();
+        edits.add(delete(&*comma).unwrap()).unwrap();
+        edits.add(insert_before(&*y, " + ").unwrap()).unwrap();
+        edits.add(replace(&*x, "ñ").unwrap()).unwrap();
+        assert_eq!(edits.apply(source), "f(ñ + g(yy))");
+
+        let sorted = edits.into_sorted_edits();
+        let starts = sorted.iter().map(|it| it.range.a).collect::<Vec<_>>();
+        assert_eq!(starts, vec![2, 3, 4, 4, 6]);
+        assert_eq!(sorted[2].new_text, " + ");
+
+        let mut edits = EditSet::new();
+        edits.add(replace(&*x, "é").unwrap()).unwrap();
+        edits.add(replace(&*y, "z").unwrap()).unwrap();
+        assert_eq!(edits.apply("f(x,yy)"), "f(é,z)");
+        // char based indexes
+        assert_eq!(EditSet::new().apply("ф(x)"), "ф(x)");
+        let mut edits = EditSet::new();
+        edits.add(replace(&*x, "y").unwrap()).unwrap();
+        assert_eq!(edits.apply("ф(x,yy)"), "ф(y,yy)");
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let root = call();
+        let x = root.get_child(2).unwrap();
+        let y = root.get_child(4).unwrap();
+
+        let mut edits = EditSet::new();
+        edits.add(replace(&*y, "z").unwrap()).unwrap();
+        let inner = TextEdit {
+            range: CharInterval::new(5, 4),
+            new_text: "-".to_owned(),
+        };
+        assert_eq!(
+            edits.add(inner.clone()),
+            Err(EditError::Conflict {
+                existing: replace(&*y, "z").unwrap(),
+                new: inner,
+            })
+        );
+        let err = edits.add(delete(&*root).unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "edit of chars 0..6 overlaps with edit of chars 4..5"
+        );
+        edits.add(replace(&*x, "a").unwrap()).unwrap();
+        edits.add(insert_before(&*y, "b").unwrap()).unwrap();
+        edits.add(insert_before(&*y, "c").unwrap()).unwrap();
+        assert_eq!(edits.edits().len(), 4);
+        assert_eq!(edits.apply("f(x,yy)"), "f(a,bcz)");
+    }
+}
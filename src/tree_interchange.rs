@@ -0,0 +1,703 @@
+//! Versioned JSON format for exchanging parse trees with other tools.
+//!
+//! ### Format version 1
+//! ```json
+//! {
+//!   "format_version": 1,
+//!   "tree": {
+//!     "kind": "rule",
+//!     "node_id": 0,
+//!     "rule_index": 2,
+//!     "rule_name": "row",
+//!     "children": [
+//!       { "kind": "token", "node_id": 1, "token_type": 5, "token_name": "TEXT",
+//!         "text": "a", "channel": 0, "start": 0, "stop": 0 },
+//!       { "kind": "error", "node_id": 2, "token_type": 3, "token_name": "'\\n'",
+//!         "text": "\n", "channel": 0, "start": 1, "stop": 1 }
+//!     ]
+//!   }
+//! }
+//! ```
+//! - `kind` is one of `"rule"`, `"token"` or `"error"`.
+//!   `"error"` nodes are tokens that were created or skipped by error recovery.
+//! - Rules are identified by both `rule_index` and `rule_name`, tokens by both `token_type`
+//!   and `token_name`, where token name is a vocabulary display name (e.g. `ID`, `','`, `EOF`).
+//!   On import names take precedence, index that does not match the name produces a warning.
+//! - `start` and `stop` are the inclusive char interval of the token in the lexer input.
+//!   Tokens that were not a part of the input (e.g. inserted by error recovery) have `-1` there.
+//! - `node_id` is the index of the node in preorder traversal, same as `trees::NodeIds` assigns.
+//!   It is optional on import, but if present must match node position,
+//!   otherwise import produces a warning. `OwnedTree::index` resolves ids back to nodes.
+//! - `user_data` is present only in trees exported with `export_v1_with_user_data`,
+//!   for nodes which user data could be converted to JSON. Importer ignores it.
+//!
+//! Fields that are not listed here are ignored by importer,
+//! so new optional fields can be added without changing the version.
+//!
+//! Available only with `interchange` feature.
+use std::any::Any;
+use std::borrow::Borrow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use better_any::TidExt;
+use serde_json::{json, Map, Value};
+
+use crate::char_stream::InputData;
+use crate::parser::ParserNodeType;
+use crate::parser_rule_context::ParserRuleContext;
+use crate::recognizer::Recognizer;
+use crate::token::{Token, TOKEN_EOF};
+use crate::token_factory::TokenFactory;
+use crate::tree::{ErrorNode, LeafNode, TerminalNode};
+use crate::trees::NodeId;
+use crate::vocabulary::Vocabulary;
+
+/// Version of the format produced by `export_v1`
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Parse tree detached from the parser and input
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum OwnedTree {
+    /// Rule node
+    Rule {
+        /// Index of the rule in the parser's rule names
+        rule_index: usize,
+        /// Rule name
+        rule_name: String,
+        /// Child nodes
+        children: Vec<OwnedTree>,
+    },
+    /// Token matched by parser
+    Token(OwnedTreeToken),
+    /// Token created or skipped by error recovery
+    Error(OwnedTreeToken),
+}
+
+/// Token data of the `OwnedTree` leaf
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OwnedTreeToken {
+    /// Token type
+    pub token_type: isize,
+    /// Display name of the token type in the vocabulary
+    pub token_name: String,
+    /// Token text
+    pub text: String,
+    /// Token channel
+    pub channel: isize,
+    /// Index of the first char of the token
+    pub start: isize,
+    /// Index of the last char of the token
+    pub stop: isize,
+}
+
+impl OwnedTreeToken {
+    fn new<T: Token + ?Sized>(token: &T, vocabulary: &dyn Vocabulary) -> Self {
+        OwnedTreeToken {
+            token_type: token.get_token_type(),
+            token_name: vocabulary
+                .get_display_name(token.get_token_type())
+                .into_owned(),
+            text: token.get_text().to_display(),
+            channel: token.get_channel(),
+            start: token.get_start(),
+            stop: token.get_stop(),
+        }
+    }
+
+    fn from_leaf<'input, Node: ParserNodeType<'input>, E: 'static>(
+        leaf: &LeafNode<'input, Node, E>, vocabulary: &dyn Vocabulary,
+    ) -> Self {
+        let token: &<Node::TF as TokenFactory<'input>>::Inner = leaf.symbol.borrow();
+        Self::new(token, vocabulary)
+    }
+
+    fn to_v1(&self, kind: &str, node_id: NodeId) -> Value {
+        json!({
+            "kind": kind,
+            "node_id": node_id,
+            "token_type": self.token_type,
+            "token_name": self.token_name,
+            "text": self.text,
+            "channel": self.channel,
+            "start": self.start,
+            "stop": self.stop,
+        })
+    }
+}
+
+impl OwnedTree {
+    /// Copies parse tree starting at `node`
+    pub fn from_parse_tree<'input, T>(
+        node: &T, rule_names: &[&str], vocabulary: &dyn Vocabulary,
+    ) -> OwnedTree
+    where
+        T: ParserRuleContext<'input> + ?Sized,
+    {
+        if let Some(leaf) = node.downcast_ref::<TerminalNode<'input, T::Ctx>>() {
+            return OwnedTree::Token(OwnedTreeToken::from_leaf(leaf, vocabulary));
+        }
+        if let Some(leaf) = node.downcast_ref::<ErrorNode<'input, T::Ctx>>() {
+            return OwnedTree::Error(OwnedTreeToken::from_leaf(leaf, vocabulary));
+        }
+        let rule_index = node.get_rule_index();
+        OwnedTree::Rule {
+            rule_index,
+            rule_name: rule_names
+                .get(rule_index)
+                .map(|&it| it.to_owned())
+                .unwrap_or_default(),
+            children: node
+                .get_children()
+                .map(|child| Self::from_parse_tree(&*child, rule_names, vocabulary))
+                .collect(),
+        }
+    }
+
+    /// Serializes this tree in format version 1
+    pub fn to_v1(&self) -> Value {
+        json!({
+            "format_version": FORMAT_VERSION,
+            "tree": self.node_to_v1(&mut 0),
+        })
+    }
+
+    fn node_to_v1(&self, next_id: &mut NodeId) -> Value {
+        let node_id = *next_id;
+        *next_id += 1;
+        match self {
+            OwnedTree::Rule {
+                rule_index,
+                rule_name,
+                children,
+            } => json!({
+                "kind": "rule",
+                "node_id": node_id,
+                "rule_index": rule_index,
+                "rule_name": rule_name,
+                "children": children
+                    .iter()
+                    .map(|it| it.node_to_v1(next_id))
+                    .collect::<Vec<_>>(),
+            }),
+            OwnedTree::Token(token) => token.to_v1("token", node_id),
+            OwnedTree::Error(token) => token.to_v1("error", node_id),
+        }
+    }
+
+    /// Text of all tokens in this subtree, same as `ParseTree::get_text` of the original node
+    pub fn get_text(&self) -> String {
+        match self {
+            OwnedTree::Rule { children, .. } => children.iter().map(|it| it.get_text()).collect(),
+            OwnedTree::Token(token) | OwnedTree::Error(token) if token.token_type == TOKEN_EOF => {
+                String::new()
+            }
+            OwnedTree::Token(token) | OwnedTree::Error(token) => token.text.clone(),
+        }
+    }
+
+    /// Assigns ids to nodes of this tree, the same way `trees::NodeIds` does for parse trees.
+    ///
+    /// Index borrows the tree, so it can't become stale,
+    /// after the tree is modified it has to be created again.
+    pub fn index(&self) -> TreeIndex<'_> {
+        let mut nodes = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            if let OwnedTree::Rule { children, .. } = node {
+                stack.extend(children.iter().rev());
+            }
+        }
+        TreeIndex { nodes }
+    }
+}
+
+/// Lookup of `OwnedTree` nodes by their ids, see `OwnedTree::index`
+#[derive(Debug, Clone)]
+pub struct TreeIndex<'a> {
+    nodes: Vec<&'a OwnedTree>,
+}
+
+impl<'a> TreeIndex<'a> {
+    /// Returns node with `id`
+    pub fn node_by_id(&self, id: NodeId) -> Option<&'a OwnedTree> { self.nodes.get(id).copied() }
+
+    /// Returns id of `node` or `None` if it is not a node of the indexed tree
+    pub fn node_id(&self, node: &OwnedTree) -> Option<NodeId> {
+        self.nodes.iter().position(|&it| std::ptr::eq(it, node))
+    }
+}
+
+/// Exports parse tree starting at `node` in format version 1
+pub fn export_v1<'input, T, R>(node: &T, recognizer: &R) -> Value
+where
+    T: ParserRuleContext<'input> + ?Sized,
+    R: Recognizer<'input> + ?Sized,
+{
+    OwnedTree::from_parse_tree(
+        node,
+        recognizer.get_rule_names(),
+        recognizer.get_vocabulary(),
+    )
+    .to_v1()
+}
+
+/// Same as `export_v1` but also writes user data attached to nodes
+/// (see `RuleContextExt::set_user_data`) into their `user_data` field.
+///
+/// User data is not serializable in general, so `serialize` converts it to JSON,
+/// or returns `None` to leave the field out, e.g. for values of unknown type:
+/// ```text
+/// export_v1_with_user_data(&*tree, &*parser, |data| {
+///     serde_json::to_value(data.downcast_ref::<Tag>()?).ok()
+/// })
+/// ```
+pub fn export_v1_with_user_data<'input, T, R, F>(node: &T, recognizer: &R, serialize: F) -> Value
+where
+    T: ParserRuleContext<'input> + ?Sized,
+    R: Recognizer<'input> + ?Sized,
+    F: Fn(&(dyn Any + Send)) -> Option<Value>,
+{
+    let mut exported = export_v1(node, recognizer);
+    add_user_data(node, &mut exported["tree"], &serialize);
+    exported
+}
+
+// `value` is the exported `node`, so their children are in the same order
+fn add_user_data<'input, T, F>(node: &T, value: &mut Value, serialize: &F)
+where
+    T: ParserRuleContext<'input> + ?Sized,
+    F: Fn(&(dyn Any + Send)) -> Option<Value>,
+{
+    let data = node
+        .user_data_slot()
+        .and_then(|slot| slot.borrow().as_ref().and_then(|data| serialize(&**data)));
+    if let Some(data) = data {
+        value["user_data"] = data;
+    }
+    if let Some(children) = value.get_mut("children").and_then(Value::as_array_mut) {
+        for (child, child_value) in node.get_children().zip(children) {
+            add_user_data(&*child, child_value, serialize);
+        }
+    }
+}
+
+/// Result of a successful `import_v1`
+#[derive(Debug, Clone)]
+pub struct ImportedTree {
+    /// Imported tree
+    pub tree: OwnedTree,
+    /// Non fatal inconsistencies found in the input, e.g. rule index that does not match rule name
+    pub warnings: Vec<String>,
+}
+
+/// Input can't be imported
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InterchangeError {
+    /// Location of the invalid value, e.g. `tree.children[1].kind`
+    pub path: String,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl Display for InterchangeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Error for InterchangeError {}
+
+fn error<T>(path: &str, message: String) -> Result<T, InterchangeError> {
+    Err(InterchangeError {
+        path: path.to_owned(),
+        message,
+    })
+}
+
+/// Imports tree in format version 1.
+///
+/// `rule_names` and `vocabulary` should be the ones of the parser that will work with the tree,
+/// rules and tokens are resolved against them by name.
+pub fn import_v1(
+    value: &Value, rule_names: &[&str], vocabulary: &dyn Vocabulary,
+) -> Result<ImportedTree, InterchangeError> {
+    let root = as_object(value, "")?;
+    match root.get("format_version") {
+        Some(version) if version.as_u64() == Some(FORMAT_VERSION) => {}
+        Some(version) => {
+            return error(
+                "format_version",
+                format!(
+                    "unsupported version {}, expected {}",
+                    version, FORMAT_VERSION
+                ),
+            )
+        }
+        None => return error("format_version", "missing field".to_owned()),
+    }
+    let mut importer = Importer {
+        rule_names,
+        vocabulary,
+        warnings: Vec::new(),
+        next_id: 0,
+    };
+    let tree = match root.get("tree") {
+        Some(tree) => importer.node(tree, "tree")?,
+        None => return error("tree", "missing field".to_owned()),
+    };
+    Ok(ImportedTree {
+        tree,
+        warnings: importer.warnings,
+    })
+}
+
+struct Importer<'a> {
+    rule_names: &'a [&'a str],
+    vocabulary: &'a dyn Vocabulary,
+    warnings: Vec<String>,
+    next_id: NodeId,
+}
+
+fn as_object<'a>(value: &'a Value, path: &str) -> Result<&'a Map<String, Value>, InterchangeError> {
+    match value.as_object() {
+        Some(object) => Ok(object),
+        None => error(path, format!("expected object, found {}", value)),
+    }
+}
+
+fn get_field<'a, T>(
+    object: &'a Map<String, Value>, path: &str, field: &str,
+    convert: impl FnOnce(&'a Value) -> Option<T>,
+) -> Result<Option<T>, InterchangeError> {
+    match object.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => match convert(value) {
+            Some(value) => Ok(Some(value)),
+            None => error(
+                &format!("{}.{}", path, field),
+                format!("unexpected value {}", value),
+            ),
+        },
+    }
+}
+
+impl Importer<'_> {
+    fn node(&mut self, value: &Value, path: &str) -> Result<OwnedTree, InterchangeError> {
+        let object = as_object(value, path)?;
+        let node_id = self.next_id;
+        self.next_id += 1;
+        if let Some(id) = get_field(object, path, "node_id", Value::as_u64)? {
+            if id != node_id as u64 {
+                self.warnings.push(format!(
+                    "{}: node id {} does not match node position, node id is {}",
+                    path, id, node_id
+                ));
+            }
+        }
+        let kind = get_field(object, path, "kind", Value::as_str)?;
+        match kind {
+            Some("rule") => self.rule(object, path),
+            Some("token") => Ok(OwnedTree::Token(self.token(object, path)?)),
+            Some("error") => Ok(OwnedTree::Error(self.token(object, path)?)),
+            Some(kind) => error(
+                &format!("{}.kind", path),
+                format!(
+                    "unknown node kind \"{}\", expected \"rule\", \"token\" or \"error\"",
+                    kind
+                ),
+            ),
+            None => error(&format!("{}.kind", path), "missing field".to_owned()),
+        }
+    }
+
+    fn rule(
+        &mut self, object: &Map<String, Value>, path: &str,
+    ) -> Result<OwnedTree, InterchangeError> {
+        let index = get_field(object, path, "rule_index", Value::as_u64)?;
+        let name = get_field(object, path, "rule_name", Value::as_str)?;
+        let rule_index = match (index, name) {
+            (index, Some(name)) => {
+                let resolved = match self.rule_names.iter().position(|&it| it == name) {
+                    Some(resolved) => resolved,
+                    None => {
+                        return error(
+                            &format!("{}.rule_name", path),
+                            format!("unknown rule \"{}\"", name),
+                        )
+                    }
+                };
+                if let Some(index) = index.filter(|&it| it != resolved as u64) {
+                    self.warnings.push(format!(
+                        "{}: rule index {} does not match rule \"{}\", using index {}",
+                        path, index, name, resolved
+                    ));
+                }
+                resolved
+            }
+            (Some(index), None) if (index as usize) < self.rule_names.len() => index as usize,
+            (Some(index), None) => {
+                return error(
+                    &format!("{}.rule_index", path),
+                    format!("rule index {} is out of range", index),
+                )
+            }
+            (None, None) => {
+                return error(
+                    path,
+                    "either rule_index or rule_name is required".to_owned(),
+                )
+            }
+        };
+        let children = match get_field(object, path, "children", Value::as_array)? {
+            Some(children) => children
+                .iter()
+                .enumerate()
+                .map(|(i, child)| self.node(child, &format!("{}.children[{}]", path, i)))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(OwnedTree::Rule {
+            rule_index,
+            rule_name: self.rule_names[rule_index].to_owned(),
+            children,
+        })
+    }
+
+    fn token(
+        &mut self, object: &Map<String, Value>, path: &str,
+    ) -> Result<OwnedTreeToken, InterchangeError> {
+        let token_type = get_field(object, path, "token_type", Value::as_i64)?;
+        let name = get_field(object, path, "token_name", Value::as_str)?;
+        let vocabulary = self.vocabulary;
+        let max_token_type = vocabulary.get_max_token_type();
+        let token_type = match (token_type, name) {
+            (token_type, Some(name)) => {
+                let resolved = match (TOKEN_EOF..=max_token_type)
+                    .find(|&it| vocabulary.get_display_name(it) == name)
+                {
+                    Some(resolved) => resolved,
+                    None => {
+                        return error(
+                            &format!("{}.token_name", path),
+                            format!("unknown token \"{}\"", name),
+                        )
+                    }
+                };
+                if let Some(token_type) = token_type.filter(|&it| it != resolved as i64) {
+                    self.warnings.push(format!(
+                        "{}: token type {} does not match token {}, using type {}",
+                        path, token_type, name, resolved
+                    ));
+                }
+                resolved
+            }
+            (Some(token_type), None)
+                if token_type == TOKEN_EOF as i64
+                    || (0..=max_token_type as i64).contains(&token_type) =>
+            {
+                token_type as isize
+            }
+            (Some(token_type), None) => {
+                return error(
+                    &format!("{}.token_type", path),
+                    format!("token type {} is out of range", token_type),
+                )
+            }
+            (None, None) => {
+                return error(
+                    path,
+                    "either token_type or token_name is required".to_owned(),
+                )
+            }
+        };
+        let text = match get_field(object, path, "text", Value::as_str)? {
+            Some(text) => text.to_owned(),
+            None => return error(&format!("{}.text", path), "missing field".to_owned()),
+        };
+        let int_field = |field| {
+            get_field(object, path, field, Value::as_i64).map(|it| it.map(|it| it as isize))
+        };
+        Ok(OwnedTreeToken {
+            token_type,
+            token_name: vocabulary.get_display_name(token_type).into_owned(),
+            text,
+            channel: int_field("channel")?.unwrap_or(0),
+            start: int_field("start")?.unwrap_or(-1),
+            stop: int_field("stop")?.unwrap_or(-1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::vocabulary::VocabularyImpl;
+
+    use super::*;
+
+    const RULE_NAMES: &[&str] = &["file", "item"];
+
+    fn vocabulary() -> VocabularyImpl {
+        VocabularyImpl::new(
+            [None, Some("','")].iter(),
+            [None, None, Some("ID")].iter(),
+            None,
+        )
+    }
+
+    fn id(text: &str, start: isize) -> OwnedTreeToken {
+        OwnedTreeToken {
+            token_type: 2,
+            token_name: "ID".to_owned(),
+            text: text.to_owned(),
+            channel: 0,
+            start,
+            stop: start + text.len() as isize - 1,
+        }
+    }
+
+    fn sample() -> OwnedTree {
+        OwnedTree::Rule {
+            rule_index: 0,
+            rule_name: "file".to_owned(),
+            children: vec![
+                OwnedTree::Rule {
+                    rule_index: 1,
+                    rule_name: "item".to_owned(),
+                    children: vec![OwnedTree::Token(id("ab", 0))],
+                },
+                OwnedTree::Error(OwnedTreeToken {
+                    token_type: 1,
+                    token_name: "','".to_owned(),
+                    text: ",".to_owned(),
+                    channel: 0,
+                    start: 2,
+                    stop: 2,
+                }),
+                OwnedTree::Token(OwnedTreeToken {
+                    token_type: TOKEN_EOF,
+                    token_name: "EOF".to_owned(),
+                    text: "<EOF>".to_owned(),
+                    channel: 0,
+                    start: 3,
+                    stop: 2,
+                }),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = sample().to_v1();
+        assert_eq!(value["format_version"], 1);
+        assert_eq!(value["tree"]["children"][1]["kind"], "error");
+        let imported = import_v1(&value, RULE_NAMES, &vocabulary()).unwrap();
+        assert!(imported.warnings.is_empty());
+        assert_eq!(imported.tree, sample());
+        assert_eq!(imported.tree.to_v1(), value);
+    }
+
+    #[test]
+    fn test_node_ids() {
+        let tree = sample();
+        let value = tree.to_v1();
+        assert_eq!(value["tree"]["node_id"], 0);
+        assert_eq!(value["tree"]["children"][0]["children"][0]["node_id"], 2);
+        assert_eq!(value["tree"]["children"][2]["node_id"], 4);
+
+        let index = tree.index();
+        assert_eq!(index.node_by_id(2), Some(&OwnedTree::Token(id("ab", 0))));
+        assert_eq!(index.node_by_id(3).unwrap().get_text(), ",");
+        assert_eq!(index.node_by_id(5), None);
+        assert_eq!(index.node_id(index.node_by_id(4).unwrap()), Some(4));
+        assert_eq!(index.node_id(&OwnedTree::Token(id("ab", 0))), None);
+        assert_eq!(tree.get_text(), "ab,");
+
+        let mut value = value;
+        value["tree"]["children"][1]["node_id"] = json!(1);
+        let imported = import_v1(&value, RULE_NAMES, &vocabulary()).unwrap();
+        assert_eq!(
+            imported.warnings,
+            vec!["tree.children[1]: node id 1 does not match node position, node id is 3"]
+        );
+        assert_eq!(imported.tree, tree);
+    }
+
+    #[test]
+    fn test_name_is_preferred() {
+        let value = json!({
+            "format_version": 1,
+            "tree": {
+                "kind": "rule", "rule_index": 0, "rule_name": "item",
+                "children": [
+                    { "kind": "token", "token_type": 1, "token_name": "ID", "text": "x" },
+                    { "kind": "token", "token_type": 2, "text": "y" }
+                ]
+            }
+        });
+        let imported = import_v1(&value, RULE_NAMES, &vocabulary()).unwrap();
+        assert_eq!(imported.warnings.len(), 2, "{:?}", imported.warnings);
+        match &imported.tree {
+            OwnedTree::Rule {
+                rule_index,
+                children,
+                ..
+            } => {
+                assert_eq!(*rule_index, 1);
+                let detached = |text: &str| OwnedTreeToken {
+                    start: -1,
+                    stop: -1,
+                    ..id(text, 0)
+                };
+                assert_eq!(children[0], OwnedTree::Token(detached("x")));
+                assert_eq!(children[1], OwnedTree::Token(detached("y")));
+            }
+            _ => panic!("expected rule"),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let import = |value: Value| import_v1(&value, RULE_NAMES, &vocabulary()).unwrap_err();
+
+        let err = import(json!({ "format_version": 2, "tree": {} }));
+        assert_eq!(err.path, "format_version");
+        assert!(err.message.contains("unsupported version 2"), "{}", err);
+
+        let err = import(json!({ "tree": {} }));
+        assert_eq!(err.path, "format_version");
+
+        let err = import(json!({
+            "format_version": 1,
+            "tree": { "kind": "rule", "rule_name": "file", "children": [{ "kind": "comment" }] }
+        }));
+        assert_eq!(err.path, "tree.children[0].kind");
+        assert!(
+            err.message.contains("unknown node kind \"comment\""),
+            "{}",
+            err
+        );
+
+        let err = import(json!({
+            "format_version": 1,
+            "tree": { "kind": "rule", "rule_index": 1, "rule_name": "expr" }
+        }));
+        assert_eq!(err.path, "tree.rule_name");
+        assert_eq!(err.to_string(), "tree.rule_name: unknown rule \"expr\"");
+
+        let err = import(json!({
+            "format_version": 1,
+            "tree": { "kind": "token", "token_type": 7, "text": "?" }
+        }));
+        assert_eq!(err.path, "tree.token_type");
+
+        let err = import(json!({
+            "format_version": 1,
+            "tree": { "kind": "token", "token_name": "ID" }
+        }));
+        assert_eq!(err.path, "tree.text");
+    }
+}
@@ -2,9 +2,21 @@
 A set of utility routines useful for all kinds of ANTLR trees.
 */
 
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::Write;
 use std::ops::Deref;
+use std::rc::Rc;
 
-use crate::tree::Tree;
+use better_any::TidExt;
+
+use crate::atn::INVALID_ALT;
+use crate::parser::ParserNodeType;
+use crate::parser_rule_context::ParserRuleContext;
+use crate::recognizer::RecognizerRef;
+use crate::rule_context::RuleContext;
+use crate::token::Token;
+use crate::tree::{ErrorNode, LeafNode, NodeText, ParseTree, TerminalNode, Tree};
 use crate::utils;
 
 /// Print out a whole tree, not just a node, in LISP format
@@ -35,6 +47,260 @@ pub fn get_node_text<'a>(t: &(impl Tree<'a> + ?Sized), rule_names: &[&str]) -> S
     t.get_node_text(rule_names)
 }
 
+/// Controls what `to_string_tree_annotated` adds to the tree dump
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct AnnotationOptions {
+    /// Appends matched token type to terminal nodes, like `+<PLUS=12>`,
+    /// or just `<12>` if token has no symbolic name
+    pub token_types: bool,
+    /// Appends char interval to all nodes, like `@15..17`
+    pub char_intervals: bool,
+    /// Appends alt number to rule names, like `expr:2`, same as `string_tree` does.
+    /// Available only if rule contexts store alt numbers
+    pub alt_numbers: bool,
+    /// Appends `<error>` to error nodes
+    pub error_nodes: bool,
+    /// Truncates longer token texts to `max_text_len` chars followed by `…`
+    pub max_text_len: Option<usize>,
+}
+
+impl AnnotationOptions {
+    /// Enables all annotations without truncating token text
+    pub fn all() -> Self {
+        AnnotationOptions {
+            token_types: true,
+            char_intervals: true,
+            alt_numbers: true,
+            error_nodes: true,
+            max_text_len: None,
+        }
+    }
+}
+
+/// Same as `string_tree` but with additional information for debugging dumps,
+/// e.g. `(s (e 1<INT=5>@0..0) +<3>@1..1 ;<7>@2..2<error>)`.
+///
+/// Annotations are appended right after node text and never contain spaces or parentheses,
+/// so output stays single line with the same structure as the plain one.
+/// All of them match the following regex, removing its matches gives `string_tree` output
+/// as long as token text is not truncated and token texts do not look like annotations:
+/// ```text
+/// <(?:\w+=)?-?\d+>|<error>|@-?\d+\.\.-?\d+
+/// ```
+pub fn to_string_tree_annotated<'input, T: ParserRuleContext<'input> + ?Sized>(
+    node: &T, recognizer: &dyn RecognizerRef, options: &AnnotationOptions,
+) -> String {
+    let mut result = String::new();
+    write_annotated(node, recognizer, options, &mut result);
+    result
+}
+
+fn write_annotated<'input, T: ParserRuleContext<'input> + ?Sized>(
+    node: &T, recognizer: &dyn RecognizerRef, options: &AnnotationOptions, result: &mut String,
+) {
+    if let Some(leaf) = node.downcast_ref::<TerminalNode<'input, T::Ctx>>() {
+        write_leaf(leaf, false, recognizer, options, result);
+        return;
+    }
+    if let Some(leaf) = node.downcast_ref::<ErrorNode<'input, T::Ctx>>() {
+        write_leaf(leaf, true, recognizer, options, result);
+        return;
+    }
+
+    let has_children = node.get_child_count() > 0;
+    if has_children {
+        result.push('(');
+    }
+    let rule_index = node.get_rule_index();
+    match recognizer.rule_names().get(rule_index) {
+        Some(name) => result.push_str(name),
+        None => write!(result, "{}", rule_index).unwrap(),
+    }
+    if options.alt_numbers && node.get_alt_number() != INVALID_ALT {
+        write!(result, ":{}", node.get_alt_number()).unwrap();
+    }
+    if options.char_intervals && node.start().get_token_index() >= 0 {
+        let (start, stop) = (node.start().get_start(), node.stop().get_stop());
+        write!(result, "@{}..{}", start, stop).unwrap();
+    }
+    for child in node.get_children() {
+        result.push(' ');
+        write_annotated(&*child, recognizer, options, result);
+    }
+    if has_children {
+        result.push(')');
+    }
+}
+
+fn write_leaf<'input, Node: ParserNodeType<'input>, T: 'static>(
+    leaf: &LeafNode<'input, Node, T>, is_error: bool, recognizer: &dyn RecognizerRef,
+    options: &AnnotationOptions, result: &mut String,
+) {
+    let text = leaf.get_node_text(&[]);
+    match options.max_text_len {
+        Some(max) if text.chars().count() > max => {
+            let truncated = text.chars().take(max).collect::<String>();
+            result.push_str(&utils::escape_whitespaces(truncated, false));
+            result.push('…');
+        }
+        _ => result.push_str(&utils::escape_whitespaces(text, false)),
+    }
+
+    let token = leaf.symbol.borrow();
+    if options.token_types {
+        let token_type = leaf.get_matched_type();
+        match recognizer
+            .vocabulary()
+            .and_then(|it| it.get_symbolic_name(token_type))
+        {
+            Some(name) => write!(result, "<{}={}>", name, token_type).unwrap(),
+            None => write!(result, "<{}>", token_type).unwrap(),
+        }
+    }
+    if options.char_intervals {
+        write!(result, "@{}..{}", token.get_start(), token.get_stop()).unwrap();
+    }
+    if options.error_nodes && is_error {
+        result.push_str("<error>");
+    }
+}
+
+/// Returns topmost nodes where errors were recorded during parsing,
+/// i.e. rule nodes that have error node children or recorded an exception themselves.
+///
+/// Together they partition the tree: subtrees that are not returned, not inside returned
+/// nodes, and not their ancestors are guaranteed to be error free.
+/// Returns empty vector if `root` has no errors.
+pub fn error_subtrees<'input, Node: ParserNodeType<'input>>(
+    root: &Rc<Node::Type>,
+) -> Vec<Rc<Node::Type>> {
+    let mut result = Vec::new();
+    if root.subtree_has_errors() {
+        collect_error_subtrees::<Node>(root, &mut result);
+    }
+    result
+}
+
+fn collect_error_subtrees<'input, Node: ParserNodeType<'input>>(
+    node: &Rc<Node::Type>, result: &mut Vec<Rc<Node::Type>>,
+) {
+    let erroneous = node
+        .get_children()
+        .filter(|child| child.subtree_has_errors())
+        .collect::<Vec<_>>();
+    let has_error_leaf = erroneous.iter().any(|child| {
+        (**child)
+            .downcast_ref::<ErrorNode<'input, Node>>()
+            .is_some()
+    });
+    if erroneous.is_empty() || has_error_leaf {
+        result.push(node.clone());
+    } else {
+        erroneous
+            .iter()
+            .for_each(|child| collect_error_subtrees::<Node>(child, result));
+    }
+}
+
+/// Returns the deepest rule node in the tree starting at `root`
+/// that contains tokens from `start_token_index` to `stop_token_index` inclusive,
+/// or `None` if even `root` does not contain them.
+pub fn root_of_subtree_enclosing_region<'input, Node: ParserNodeType<'input>>(
+    root: &Rc<Node::Type>, start_token_index: isize, stop_token_index: isize,
+) -> Option<Rc<Node::Type>> {
+    if (**root)
+        .downcast_ref::<TerminalNode<'input, Node>>()
+        .is_some()
+        || (**root).downcast_ref::<ErrorNode<'input, Node>>().is_some()
+    {
+        return None;
+    }
+    let enclosing_child = root.get_children().find_map(|child| {
+        root_of_subtree_enclosing_region::<Node>(&child, start_token_index, stop_token_index)
+    });
+    if enclosing_child.is_some() {
+        return enclosing_child;
+    }
+    let interval = root.get_source_interval();
+    // rule without stop token is still being parsed
+    if start_token_index >= interval.a && (interval.b < 0 || stop_token_index <= interval.b) {
+        Some(root.clone())
+    } else {
+        None
+    }
+}
+
+/// Returns true if `ancestor` is a parent of `node` or a node on the path from it to the root
+pub fn is_ancestor_of<'input, Node: ParserNodeType<'input>>(
+    ancestor: &Node::Type, node: &Node::Type,
+) -> bool {
+    let ancestor = ancestor as *const Node::Type as *const ();
+    let mut parent = node.get_parent_ctx();
+    while let Some(current) = parent {
+        if Rc::as_ptr(&current) as *const () == ancestor {
+            return true;
+        }
+        parent = current.get_parent_ctx();
+    }
+    false
+}
+
+/// Id of a parse tree node, see `NodeIds`
+pub type NodeId = usize;
+
+/// Deterministic ids of parse tree nodes.
+///
+/// Id of a node is its index in preorder traversal of the tree, so root has id 0.
+/// Ids depend only on the tree shape, so the same input parsed by the same grammar with the same
+/// runtime version always gets the same ids, and they stay the same when tree is exported with
+/// `tree_interchange` and imported in another process.
+///
+/// Ids are computed once, on creation. If tree is modified afterwards,
+/// they are stale until `reindex` is called.
+pub struct NodeIds<'input, Node: ParserNodeType<'input>> {
+    root: Rc<Node::Type>,
+    nodes: Vec<Rc<Node::Type>>,
+    ids: HashMap<*const (), NodeId>,
+}
+
+impl<'input, Node: ParserNodeType<'input>> NodeIds<'input, Node> {
+    /// Assigns ids to all nodes of the tree starting at `root`
+    pub fn new(root: Rc<Node::Type>) -> Self {
+        let mut result = NodeIds {
+            root,
+            nodes: Vec::new(),
+            ids: HashMap::new(),
+        };
+        result.reindex();
+        result
+    }
+
+    /// Recomputes ids after the tree was modified
+    pub fn reindex(&mut self) {
+        self.nodes.clear();
+        self.ids.clear();
+        let mut stack = vec![self.root.clone()];
+        while let Some(node) = stack.pop() {
+            self.ids
+                .insert(Rc::as_ptr(&node) as *const (), self.nodes.len());
+            let mut children = node.get_children().collect::<Vec<_>>();
+            children.reverse();
+            stack.extend(children);
+            self.nodes.push(node);
+        }
+    }
+
+    /// Returns node with `id`
+    pub fn node_by_id(&self, id: NodeId) -> Option<&Rc<Node::Type>> { self.nodes.get(id) }
+
+    /// Returns id of `node` or `None` if it is not in the tree
+    pub fn node_id(&self, node: &Node::Type) -> Option<NodeId> {
+        self.ids
+            .get(&(node as *const Node::Type as *const ()))
+            .copied()
+    }
+}
+
 //pub fn get_children(t: impl Tree) -> Vec<Rc<dyn Tree>> { unimplemented!() }
 //
 //pub fn get_ancestors(t: impl Tree) -> Vec<Rc<dyn Tree>> { unimplemented!() }
@@ -0,0 +1,310 @@
+//! Lexer input that is read lazily from `std::io::Read`
+use std::io::{self, Read};
+
+use better_any::{impl_tid, TidAble};
+
+use crate::char_stream::CharStream;
+use crate::int_stream::{panic_consume_eof, IntStream, EOF, LA_UNDEFINED};
+use crate::interval_set::CharInterval;
+
+const CHUNK_SIZE: usize = 4096;
+
+/// `CharStream` that decodes UTF-8 input from a reader on demand, e.g. from a socket or a pipe,
+/// and keeps only chars that can still be reached by `seek`, i.e. the ones after the earliest
+/// outstanding `mark` (lexer keeps the current token marked).
+///
+/// Unlike `InputStream<&str>` indexes are code point indexes. `size` is the number of chars
+/// read so far. If reading fails or input is not valid UTF-8, stream ends at that position
+/// and the error is available via `error`.
+#[derive(Debug)]
+pub struct UnbufferedCharStream<R> {
+    name: String,
+    reader: R,
+    data: Vec<char>,
+    // bytes of the incomplete UTF-8 sequence at the end of the last read
+    pending: Vec<u8>,
+    p: isize,
+    current_index: isize,
+    markers_count: isize,
+    // char right before `data`, for `la(-1)` after `data` has been released
+    last_char: isize,
+    finished: bool,
+    error: Option<io::Error>,
+}
+
+#[impl_tid]
+impl<'a, R: 'static> TidAble<'a> for UnbufferedCharStream<R> {}
+
+impl<R: Read> UnbufferedCharStream<R> {
+    /// Creates stream that reads UTF-8 input from `reader`
+    pub fn new(reader: R) -> Self {
+        Self {
+            name: "<empty>".to_string(),
+            reader,
+            data: Vec::new(),
+            pending: Vec::new(),
+            p: 0,
+            current_index: 0,
+            markers_count: 0,
+            last_char: EOF,
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Sets name returned by `get_source_name`
+    pub fn with_source_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Error that ended this stream prematurely, if any
+    pub fn error(&self) -> Option<&io::Error> { self.error.as_ref() }
+
+    fn buffer_start_index(&self) -> isize { self.current_index - self.p }
+
+    // Reads input until there are `want` chars starting from the current one or input ends
+    fn sync(&mut self, want: isize) {
+        while !self.finished && self.data.len() < (self.p + want) as usize {
+            self.read_chunk();
+        }
+    }
+
+    fn read_chunk(&mut self) {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        let read = match self.reader.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return,
+            Err(e) => return self.fail(e),
+        };
+        if read == 0 {
+            if !self.pending.is_empty() {
+                self.fail(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "input ends in the middle of UTF-8 sequence",
+                ));
+            }
+            self.finished = true;
+            return;
+        }
+        self.pending.extend_from_slice(&chunk[..read]);
+        let valid_up_to = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_some() => {
+                let valid = e.valid_up_to();
+                self.data
+                    .extend(std::str::from_utf8(&self.pending[..valid]).unwrap().chars());
+                return self.fail(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            // incomplete sequence at the end is decoded after the next read
+            Err(e) => e.valid_up_to(),
+        };
+        self.data.extend(
+            std::str::from_utf8(&self.pending[..valid_up_to])
+                .unwrap()
+                .chars(),
+        );
+        self.pending.drain(..valid_up_to);
+    }
+
+    fn fail(&mut self, error: io::Error) {
+        self.error = Some(error);
+        self.pending.clear();
+        self.finished = true;
+    }
+}
+
+impl<R: Read> IntStream for UnbufferedCharStream<R> {
+    fn consume(&mut self) {
+        if self.la(1) == EOF {
+            panic_consume_eof();
+        }
+        if self.markers_count == 0 && (self.p + 1) * 2 >= self.data.len() as isize {
+            // consumed chars can't be reached by `seek` anymore, they are released
+            // once they make up at least half of the buffer to not shift lookahead too often
+            self.last_char = self.data[self.p as usize] as isize;
+            self.data.drain(..=self.p as usize);
+            self.p = -1;
+        }
+        self.p += 1;
+        self.current_index += 1;
+        self.sync(1);
+    }
+
+    fn la(&mut self, i: isize) -> isize {
+        if i == 0 {
+            return LA_UNDEFINED;
+        }
+        let index = if i < 0 { self.p + i } else { self.p + i - 1 };
+        if i > 0 {
+            self.sync(i);
+        }
+        match index {
+            -1 => self.last_char,
+            index if index < 0 => EOF,
+            index => self.data.get(index as usize).map_or(EOF, |&c| c as isize),
+        }
+    }
+
+    fn mark(&mut self) -> isize {
+        self.markers_count += 1;
+        -self.markers_count
+    }
+
+    fn release(&mut self, marker: isize) {
+        runtime_assert!(
+            marker == -self.markers_count,
+            "release of marker {} while {} markers are outstanding",
+            marker,
+            self.markers_count
+        );
+        self.markers_count -= 1;
+        if self.markers_count == 0 && self.p > 0 {
+            self.last_char = self.data[self.p as usize - 1] as isize;
+            self.data.drain(..self.p as usize);
+            self.p = 0;
+        }
+    }
+
+    fn index(&self) -> isize { self.current_index }
+
+    fn seek(&mut self, index: isize) {
+        let index = index.max(0);
+        if index > self.current_index {
+            self.sync(index - self.current_index + 1);
+        }
+        let index = index.min(self.size());
+        let i = index - self.buffer_start_index();
+        runtime_assert!(
+            i >= 0 && i <= self.data.len() as isize,
+            {
+                current_index: self.current_index,
+                buffer_start: self.buffer_start_index(),
+                markers: self.markers_count,
+            },
+            "can't seek to char {} outside of the char buffer",
+            index
+        );
+        self.p = i;
+        self.current_index = index;
+    }
+
+    fn size(&self) -> isize { self.buffer_start_index() + self.data.len() as isize }
+
+    fn get_source_name(&self) -> String { self.name.clone() }
+}
+
+impl<R: Read, T: From<String>> CharStream<T> for UnbufferedCharStream<R> {
+    fn get_text(&self, interval: CharInterval) -> T {
+        let start = interval.a - self.buffer_start_index();
+        let stop = (interval.b - self.buffer_start_index()).min(self.data.len() as isize - 1);
+        runtime_assert!(
+            start >= 0,
+            { buffer_start: self.buffer_start_index() },
+            "chars {}..={} are not in the char buffer anymore",
+            interval.a,
+            interval.b
+        );
+        if stop < start {
+            return String::new().into();
+        }
+        self.data[start as usize..=stop as usize]
+            .iter()
+            .collect::<String>()
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read};
+
+    use crate::char_stream::CharStream;
+    use crate::int_stream::{check_stream_contract, IntStream, EOF};
+    use crate::interval_set::CharInterval;
+
+    use super::UnbufferedCharStream;
+
+    // returns at most one byte per `read` call
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_contract() {
+        let text = "aä€😀\n";
+        let symbols = text.chars().map(|c| c as isize).collect::<Vec<_>>();
+        check_stream_contract(&mut UnbufferedCharStream::new(text.as_bytes()), &symbols);
+        check_stream_contract(
+            &mut UnbufferedCharStream::new(Trickle(text.as_bytes())),
+            &symbols,
+        );
+    }
+
+    #[test]
+    fn test_buffer_window() {
+        let text = "abcä€😀".repeat(10_000);
+        let mut stream = UnbufferedCharStream::new(Trickle(text.as_bytes()));
+        for (i, c) in text.chars().enumerate() {
+            assert_eq!(stream.la(1), c as isize, "{}", i);
+            stream.consume();
+            assert!(stream.data.len() <= 2);
+        }
+        assert_eq!(stream.la(1), EOF);
+        assert_eq!(stream.la(-1), '😀' as isize);
+        assert_eq!(stream.index(), 60_000);
+
+        let mut stream = UnbufferedCharStream::new(text.as_bytes());
+        stream.seek(3);
+        let marker = stream.mark();
+        stream.seek(7);
+        let token: String = stream.get_text(CharInterval::new(3, 6));
+        assert_eq!(token, "ä€😀a");
+        stream.release(marker);
+        assert_eq!(stream.la(-1), 'a' as isize);
+        assert_eq!(stream.buffer_start_index(), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't seek to char 0 outside of the char buffer")]
+    fn test_seek_before_release() {
+        let mut stream = UnbufferedCharStream::new(&b"abc"[..]);
+        let marker = stream.mark();
+        stream.consume();
+        stream.consume();
+        stream.release(marker);
+        stream.seek(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "release of marker -1 while 2 markers are outstanding")]
+    fn test_release_order() {
+        let mut stream = UnbufferedCharStream::new(&b"abc"[..]);
+        let first = stream.mark();
+        stream.mark();
+        stream.release(first);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut stream = UnbufferedCharStream::new(&b"ab\xffcd"[..]);
+        assert_eq!(stream.la(2), 'b' as isize);
+        assert_eq!(stream.la(3), EOF);
+        assert_eq!(stream.error().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        // last byte of 'ä' is missing
+        let mut stream = UnbufferedCharStream::new(Trickle(&"aä".as_bytes()[..2]));
+        assert_eq!(stream.la(1), 'a' as isize);
+        assert_eq!(stream.la(2), EOF);
+        assert_eq!(stream.error().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
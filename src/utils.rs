@@ -16,6 +16,10 @@ pub fn escape_whitespaces(data: impl Borrow<str>, escape_spaces: bool) -> String
 
 pub trait Sealed {}
 
+/// Can't be named outside of this crate, so public trait methods that take it
+/// can be called only by the runtime itself
+pub struct CrateOnly;
+
 // pub enum Cow2<'a,Ref,T:Borrow<Ref> = Ref>{
 //     Borrowed2(&'a Ref),
 //     Owned2(T)
@@ -4,10 +4,22 @@ use std::borrow::Borrow;
 //use std::borrow::Cow;
 use std::borrow::Cow::{self, Borrowed, Owned};
 use std::cmp::max;
-use std::fmt::Debug;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
 
+use better_any::{Tid, TidAble};
+use once_cell::unsync::OnceCell;
+
+use crate::char_stream::CharStream;
 use crate::dfa::ScopeExt;
-use crate::token::TOKEN_EOF;
+use crate::int_stream::IntStream;
+use crate::token::{Token, TOKEN_EOF, TOKEN_INVALID_TYPE, TOKEN_MIN_USER_TOKEN_TYPE};
+use crate::token_factory::TokenFactory;
+use crate::token_source::TokenSource;
+use crate::utils::CrateOnly;
 
 pub trait Vocabulary: Sync + Debug {
     fn get_max_token_type(&self) -> isize;
@@ -84,6 +96,298 @@ impl VocabularyImpl {
             Some(token_names.iter()),
         )
     }
+
+    /// Merges `other` into the token type space of this vocabulary,
+    /// e.g. to combine vocabularies of separately generated lexer and parser.
+    ///
+    /// Tokens are matched by symbolic name, or by literal name if token has no symbolic one.
+    /// Matched tokens keep their token type from `self`, names missing on one side are taken
+    /// from the other, and tokens that exist only in `other` get new token types after
+    /// `self.get_max_token_type()`. Returned `TokenTypeRemap` converts token types of `other`
+    /// to the merged vocabulary.
+    ///
+    /// Fails if the same token is defined differently in `self` and `other`.
+    pub fn merge(
+        &self, other: &VocabularyImpl,
+    ) -> Result<(VocabularyImpl, TokenTypeRemap), MergeConflict> {
+        let mut merged = VocabularyImpl {
+            literal_names: self.literal_names.clone(),
+            symbolic_names: self.symbolic_names.clone(),
+            display_names: self.display_names.clone(),
+            max_token_type: self.max_token_type,
+        };
+        let mut by_literal = index_names(&self.literal_names);
+        let mut by_symbolic = index_names(&self.symbolic_names);
+        let mut types = vec![TOKEN_INVALID_TYPE; max(other.max_token_type + 1, 1) as usize];
+
+        for token_type in TOKEN_MIN_USER_TOKEN_TYPE..=other.max_token_type {
+            let literal = other.get_literal_name(token_type);
+            let symbolic = other.get_symbolic_name(token_type);
+            let literal_match = literal.and_then(|it| by_literal.get(it)).copied();
+            let existing = symbolic
+                .and_then(|it| by_symbolic.get(it))
+                .copied()
+                .or(literal_match);
+
+            let merged_type = match existing {
+                Some(existing) => {
+                    fn conflict<'a>(
+                        name: Option<&'a str>, other_name: Option<&'a str>,
+                    ) -> Option<(&'a str, &'a str)> {
+                        name.zip(other_name).filter(|(a, b)| a != b)
+                    }
+                    if let Some((a, b)) = conflict(merged.get_literal_name(existing), literal) {
+                        return Err(MergeConflict::LiteralMismatch {
+                            name: symbolic.unwrap_or_default().to_owned(),
+                            token_type: existing,
+                            other_token_type: token_type,
+                            literal: a.to_owned(),
+                            other_literal: b.to_owned(),
+                        });
+                    }
+                    let existing_symbolic = merged.get_symbolic_name(existing);
+                    let other_literal_type = literal_match.filter(|&it| it != existing);
+                    if let Some(literal_type) = other_literal_type {
+                        // literal belongs to the different token in `self`
+                        return Err(MergeConflict::NameMismatch {
+                            literal: literal.unwrap_or_default().to_owned(),
+                            token_type: literal_type,
+                            other_token_type: token_type,
+                            name: merged.get_symbolic_name(literal_type).map(str::to_owned),
+                            other_name: symbolic.map(str::to_owned),
+                        });
+                    }
+                    if let Some((a, b)) = conflict(existing_symbolic, symbolic) {
+                        return Err(MergeConflict::NameMismatch {
+                            literal: literal.unwrap_or_default().to_owned(),
+                            token_type: existing,
+                            other_token_type: token_type,
+                            name: Some(a.to_owned()),
+                            other_name: Some(b.to_owned()),
+                        });
+                    }
+                    existing
+                }
+                None => {
+                    merged.max_token_type += 1;
+                    merged.max_token_type
+                }
+            };
+
+            if merged.get_literal_name(merged_type).is_none() {
+                set_name(&mut merged.literal_names, merged_type, literal);
+            }
+            if merged.get_symbolic_name(merged_type).is_none() {
+                set_name(&mut merged.symbolic_names, merged_type, symbolic);
+            }
+            if existing.is_none() {
+                let display = other.display_names.get(token_type as usize);
+                set_name(
+                    &mut merged.display_names,
+                    merged_type,
+                    display.and_then(|it| it.as_deref()),
+                );
+            }
+            if let Some(literal) = literal {
+                by_literal.entry(literal).or_insert(merged_type);
+            }
+            if let Some(symbolic) = symbolic {
+                by_symbolic.entry(symbolic).or_insert(merged_type);
+            }
+            types[token_type as usize] = merged_type;
+        }
+
+        Ok((merged, TokenTypeRemap { types }))
+    }
+}
+
+// first token type for every name
+fn index_names(names: &[Option<String>]) -> HashMap<&str, isize> {
+    let mut result = HashMap::new();
+    for (token_type, name) in names.iter().enumerate() {
+        if let Some(name) = name {
+            result.entry(name.as_str()).or_insert(token_type as isize);
+        }
+    }
+    result
+}
+
+fn set_name(names: &mut Vec<Option<String>>, token_type: isize, name: Option<&str>) {
+    let name = match name {
+        Some(name) => name,
+        None => return,
+    };
+    let index = token_type as usize;
+    if names.len() <= index {
+        names.resize(index + 1, None);
+    }
+    names[index] = Some(name.to_owned());
+}
+
+/// Error returned by `VocabularyImpl::merge` when vocabularies define the same token differently
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MergeConflict {
+    /// Token with symbolic name `name` has different literal names
+    LiteralMismatch {
+        /// Symbolic name of the token
+        name: String,
+        /// Token type of `name` in the vocabulary `merge` was called on
+        token_type: isize,
+        /// Token type of `name` in the merged in vocabulary
+        other_token_type: isize,
+        /// Literal name of `token_type`
+        literal: String,
+        /// Literal name of `other_token_type`
+        other_literal: String,
+    },
+    /// Literal `literal` belongs to tokens with different symbolic names
+    NameMismatch {
+        /// Literal name of the token
+        literal: String,
+        /// Token type of `literal` in the vocabulary `merge` was called on
+        token_type: isize,
+        /// Token type of `literal` in the merged in vocabulary
+        other_token_type: isize,
+        /// Symbolic name of `token_type`
+        name: Option<String>,
+        /// Symbolic name of `other_token_type`
+        other_name: Option<String>,
+    },
+}
+
+impl Display for MergeConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeConflict::LiteralMismatch {
+                name,
+                token_type,
+                other_token_type,
+                literal,
+                other_literal,
+            } => write!(
+                f,
+                "token {} is {} (type {}) but other vocabulary defines it as {} (type {})",
+                name, literal, token_type, other_literal, other_token_type
+            ),
+            MergeConflict::NameMismatch {
+                literal,
+                token_type,
+                other_token_type,
+                name,
+                other_name,
+            } => write!(
+                f,
+                "literal {} is token {} (type {}) but other vocabulary defines it as {} (type {})",
+                literal,
+                name.as_deref().unwrap_or("<unnamed>"),
+                token_type,
+                other_name.as_deref().unwrap_or("<unnamed>"),
+                other_token_type
+            ),
+        }
+    }
+}
+
+impl Error for MergeConflict {}
+
+/// Converts token types of the vocabulary merged by `VocabularyImpl::merge`
+/// to token types of the resulting vocabulary
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TokenTypeRemap {
+    // indexed by the original token type
+    types: Vec<isize>,
+}
+
+impl TokenTypeRemap {
+    /// Returns merged token type for `token_type`.
+    /// Special token types like `TOKEN_EOF` and unknown token types are returned as is.
+    pub fn map(&self, token_type: isize) -> isize {
+        if token_type < TOKEN_MIN_USER_TOKEN_TYPE {
+            return token_type;
+        }
+        self.types
+            .get(token_type as usize)
+            .copied()
+            .unwrap_or(token_type)
+    }
+
+    /// Returns true if all token types are mapped to themselves
+    pub fn is_identity(&self) -> bool {
+        (TOKEN_MIN_USER_TOKEN_TYPE..self.types.len() as isize).all(|it| self.map(it) == it)
+    }
+}
+
+/// Token source adapter that converts token types of tokens produced by `source`
+/// with `TokenTypeRemap`, so they can be consumed by a parser that uses merged vocabulary.
+///
+/// Tokens with changed type are recreated with token factory of `source`,
+/// all other token properties are preserved.
+#[derive(Tid, Debug)]
+pub struct RemappedTokenSource<T> {
+    source: T,
+    remap: TokenTypeRemap,
+}
+
+impl<T> RemappedTokenSource<T> {
+    /// Creates adapter that applies `remap` to tokens of `source`
+    pub fn new(source: T, remap: TokenTypeRemap) -> Self { Self { source, remap } }
+
+    /// Remapping applied to the tokens
+    pub fn get_remap(&self) -> &TokenTypeRemap { &self.remap }
+
+    /// Returns underlying token source
+    pub fn into_inner(self) -> T { self.source }
+}
+
+impl<'input, T: TokenSource<'input>> RemappedTokenSource<T> {
+    fn remap_token(
+        &self, token: <T::TF as TokenFactory<'input>>::Tok,
+    ) -> <T::TF as TokenFactory<'input>>::Tok {
+        let original = token.borrow();
+        let token_type = self.remap.map(original.get_token_type());
+        if token_type == original.get_token_type() {
+            return token;
+        }
+        let remapped = self.source.get_token_factory().create_with_end(
+            None::<&mut dyn CharStream<<T::TF as TokenFactory<'input>>::From>>,
+            token_type,
+            Some(original.get_text().to_owned()),
+            original.get_channel(),
+            original.get_start(),
+            original.get_stop(),
+            original.get_line(),
+            original.get_column(),
+            original.get_end_line(),
+            original.get_end_column(),
+        );
+        let inner: &<T::TF as TokenFactory<'input>>::Inner = remapped.borrow();
+        inner.set_mode(original.get_mode(), CrateOnly);
+        remapped
+    }
+}
+
+impl<'input, T: TokenSource<'input>> TokenSource<'input> for RemappedTokenSource<T> {
+    type TF = T::TF;
+
+    fn next_token(&mut self) -> <Self::TF as TokenFactory<'input>>::Tok {
+        let token = self.source.next_token();
+        self.remap_token(token)
+    }
+
+    fn try_next_token(&mut self) -> io::Result<<Self::TF as TokenFactory<'input>>::Tok> {
+        let token = self.source.try_next_token()?;
+        Ok(self.remap_token(token))
+    }
+
+    fn get_line(&self) -> isize { self.source.get_line() }
+
+    fn get_char_position_in_line(&self) -> isize { self.source.get_char_position_in_line() }
+
+    fn get_input_stream(&mut self) -> Option<&mut dyn IntStream> { self.source.get_input_stream() }
+
+    fn get_source_name(&self) -> String { self.source.get_source_name() }
+
+    fn get_token_factory(&self) -> &'input Self::TF { self.source.get_token_factory() }
 }
 
 impl Vocabulary for VocabularyImpl {
@@ -121,11 +425,267 @@ pub(crate) static DUMMY_VOCAB: DummyVocab = DummyVocab;
 pub(crate) struct DummyVocab;
 
 impl Vocabulary for DummyVocab {
-    fn get_max_token_type(&self) -> isize { unimplemented!() }
+    fn get_max_token_type(&self) -> isize { runtime_panic!("dummy vocabulary has no token types") }
 
-    fn get_literal_name(&self, _token_type: isize) -> Option<&str> { unimplemented!() }
+    fn get_literal_name(&self, token_type: isize) -> Option<&str> {
+        runtime_panic!("dummy vocabulary has no name for token type {}", token_type)
+    }
 
-    fn get_symbolic_name(&self, _token_type: isize) -> Option<&str> { unimplemented!() }
+    fn get_symbolic_name(&self, token_type: isize) -> Option<&str> {
+        runtime_panic!("dummy vocabulary has no name for token type {}", token_type)
+    }
 
     fn get_display_name(&self, token_type: isize) -> Cow<'_, str> { token_type.to_string().into() }
 }
+
+/// Problem in vocabulary found while building `TokenTypeMap`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VocabularyWarning {
+    /// Token name that is used by several token types
+    pub name: String,
+    /// Token type `name` is mapped to
+    pub token_type: isize,
+    /// Token type that also has `name` but was not mapped to it
+    pub shadowed_type: isize,
+}
+
+impl Display for VocabularyWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "token name {} is used by token types {} and {}, {} is used",
+            self.name, self.token_type, self.shadowed_type, self.token_type
+        )
+    }
+}
+
+/// Mapping from token names, both literal and symbolic, to token types.
+///
+/// If several token types have the same name, symbolic name wins over literal one,
+/// like in Java runtime, and otherwise lower token type wins.
+/// Every such collision is recorded as a `VocabularyWarning`.
+/// `"EOF"` is always mapped to `TOKEN_EOF`.
+#[derive(Debug)]
+pub struct TokenTypeMap {
+    map: HashMap<String, isize>,
+    lowercase: OnceCell<HashMap<String, isize>>,
+    warnings: Vec<VocabularyWarning>,
+}
+
+impl TokenTypeMap {
+    /// Builds map from all token names of `vocabulary`
+    pub fn new(vocabulary: &dyn Vocabulary) -> TokenTypeMap {
+        // (token type, is symbolic)
+        let mut map = HashMap::<String, (isize, bool)>::new();
+        let mut warnings = Vec::new();
+        map.insert("EOF".to_owned(), (TOKEN_EOF, true));
+        for token_type in 0..=vocabulary.get_max_token_type() {
+            let names = [
+                (vocabulary.get_literal_name(token_type), false),
+                (vocabulary.get_symbolic_name(token_type), true),
+            ];
+            for (name, symbolic) in names.iter().copied() {
+                let name = match name {
+                    Some(name) => name,
+                    None => continue,
+                };
+                match map.entry(name.to_owned()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert((token_type, symbolic));
+                    }
+                    Entry::Occupied(mut entry) => {
+                        let (old_type, old_symbolic) = *entry.get();
+                        if old_type == token_type {
+                            continue;
+                        }
+                        let mut warning = VocabularyWarning {
+                            name: name.to_owned(),
+                            token_type: old_type,
+                            shadowed_type: token_type,
+                        };
+                        // EOF can't be redefined
+                        if symbolic && !old_symbolic && old_type != TOKEN_EOF {
+                            entry.insert((token_type, symbolic));
+                            warning.token_type = token_type;
+                            warning.shadowed_type = old_type;
+                        }
+                        warnings.push(warning);
+                    }
+                }
+            }
+        }
+
+        TokenTypeMap {
+            map: map.into_iter().map(|(k, (v, _))| (k, v)).collect(),
+            lowercase: OnceCell::new(),
+            warnings,
+        }
+    }
+
+    /// Returns token type with name `name` or `TOKEN_INVALID_TYPE` if there is no such token
+    pub fn get_token_type(&self, name: &str) -> isize {
+        self.map.get(name).copied().unwrap_or(TOKEN_INVALID_TYPE)
+    }
+
+    /// Same as `get_token_type` but if there is no exact match, falls back to case insensitive
+    /// lookup. If several names differ only in case, the one with lower token type is used.
+    ///
+    /// Case insensitive map is built on the first call.
+    pub fn get_token_type_ignore_case(&self, name: &str) -> isize {
+        if let Some(&token_type) = self.map.get(name) {
+            return token_type;
+        }
+        let lowercase = self.lowercase.get_or_init(|| {
+            let mut lowercase = HashMap::<String, isize>::new();
+            for (name, &token_type) in &self.map {
+                let entry = lowercase.entry(name.to_lowercase()).or_insert(token_type);
+                *entry = (*entry).min(token_type);
+            }
+            lowercase
+        });
+        lowercase
+            .get(&name.to_lowercase())
+            .copied()
+            .unwrap_or(TOKEN_INVALID_TYPE)
+    }
+
+    /// Name collisions found while building this map
+    pub fn get_vocabulary_warnings(&self) -> &[VocabularyWarning] { &self.warnings }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_type_map() {
+        let vocabulary = VocabularyImpl::new(
+            [None, Some("'+'"), Some("PLUS"), None, Some("'if'")].iter(),
+            [None, Some("PLUS"), Some("Id"), Some("ID"), Some("EOF")].iter(),
+            None,
+        );
+        let map = TokenTypeMap::new(&vocabulary);
+
+        assert_eq!(map.get_token_type("'+'"), 1);
+        assert_eq!(map.get_token_type("PLUS"), 1);
+        assert_eq!(map.get_token_type("EOF"), TOKEN_EOF);
+        assert_eq!(map.get_token_type("'if'"), 4);
+        assert_eq!(map.get_token_type("plus"), TOKEN_INVALID_TYPE);
+        assert_eq!(
+            map.get_vocabulary_warnings(),
+            &[
+                VocabularyWarning {
+                    name: "PLUS".to_owned(),
+                    token_type: 1,
+                    shadowed_type: 2,
+                },
+                VocabularyWarning {
+                    name: "EOF".to_owned(),
+                    token_type: TOKEN_EOF,
+                    shadowed_type: 4,
+                },
+            ]
+        );
+
+        assert_eq!(map.get_token_type_ignore_case("plus"), 1);
+        assert_eq!(map.get_token_type_ignore_case("eof"), TOKEN_EOF);
+        assert_eq!(map.get_token_type_ignore_case("Id"), 2);
+        assert_eq!(map.get_token_type_ignore_case("ID"), 3);
+        assert_eq!(map.get_token_type_ignore_case("iD"), 2);
+        assert_eq!(map.get_token_type_ignore_case("'IF'"), 4);
+        assert_eq!(map.get_token_type_ignore_case("x"), TOKEN_INVALID_TYPE);
+    }
+
+    #[test]
+    fn test_symbolic_name_wins() {
+        let vocabulary = VocabularyImpl::new(
+            [None, Some("A"), None].iter(),
+            [None, None, Some("A")].iter(),
+            None,
+        );
+        let map = TokenTypeMap::new(&vocabulary);
+        assert_eq!(map.get_token_type("A"), 2);
+        assert_eq!(
+            map.get_vocabulary_warnings()[0].to_string(),
+            "token name A is used by token types 2 and 1, 2 is used"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let lexer = VocabularyImpl::new(
+            [None, Some("'+'"), None, None].iter(),
+            [None, Some("PLUS"), Some("ID"), Some("WS")].iter(),
+            None,
+        );
+        let parser = VocabularyImpl::new(
+            [None, None, Some("'if'"), Some("'+'"), None].iter(),
+            [None, Some("ID"), None, None, Some("NUM")].iter(),
+            None,
+        );
+        let (merged, remap) = lexer.merge(&parser).unwrap();
+
+        assert_eq!(merged.get_max_token_type(), 5);
+        assert_eq!(remap.map(1), 2);
+        assert_eq!(remap.map(2), 4);
+        assert_eq!(remap.map(3), 1);
+        assert_eq!(remap.map(4), 5);
+        assert_eq!(remap.map(TOKEN_EOF), TOKEN_EOF);
+        assert_eq!(remap.map(10), 10);
+        assert!(!remap.is_identity());
+        assert_eq!(merged.get_literal_name(4), Some("'if'"));
+        assert_eq!(merged.get_symbolic_name(4), None);
+        assert_eq!(merged.get_symbolic_name(5), Some("NUM"));
+        assert_eq!(merged.get_display_name(1), "'+'");
+
+        let (same, remap) = merged.merge(&lexer).unwrap();
+        assert_eq!(same.get_max_token_type(), 5);
+        assert!(remap.is_identity());
+    }
+
+    #[test]
+    fn test_merge_conflicts() {
+        let lexer = VocabularyImpl::new(
+            [None, Some("'+'"), Some("'-'")].iter(),
+            [None, Some("PLUS"), Some("MINUS")].iter(),
+            None,
+        );
+        let other = VocabularyImpl::new(
+            [None, Some("'plus'")].iter(),
+            [None, Some("PLUS")].iter(),
+            None,
+        );
+        let conflict = lexer.merge(&other).unwrap_err();
+        assert_eq!(
+            conflict,
+            MergeConflict::LiteralMismatch {
+                name: "PLUS".to_owned(),
+                token_type: 1,
+                other_token_type: 1,
+                literal: "'+'".to_owned(),
+                other_literal: "'plus'".to_owned(),
+            }
+        );
+        assert_eq!(
+            conflict.to_string(),
+            "token PLUS is '+' (type 1) but other vocabulary defines it as 'plus' (type 1)"
+        );
+
+        let other = VocabularyImpl::new(
+            [None, None, Some("'-'")].iter(),
+            [None, None, Some("DASH")].iter(),
+            None,
+        );
+        assert_eq!(
+            lexer.merge(&other).unwrap_err().to_string(),
+            "literal '-' is token MINUS (type 2) but other vocabulary defines it as DASH (type 2)"
+        );
+
+        let other = VocabularyImpl::new(
+            [None, Some("'-'")].iter(),
+            [None, Some("PLUS")].iter(),
+            None,
+        );
+        assert!(lexer.merge(&other).is_err());
+    }
+}
@@ -8,7 +8,7 @@
 use super::csvlistener::*;
 use super::csvvisitor::*;
 use antlr_rust::atn::{ATN, INVALID_ALT};
-use antlr_rust::atn_deserializer::ATNDeserializer;
+use antlr_rust::atn_deserializer::{ATNDeserializer, SerializedATN};
 use antlr_rust::dfa::DFA;
 use antlr_rust::error_strategy::{DefaultErrorStrategy, ErrorStrategy};
 use antlr_rust::errors::*;
@@ -98,7 +98,7 @@ where
     I: TokenStream<'input, TF = LocalTokenFactory<'input>> + TidAble<'input>,
     H: ErrorStrategy<'input, BaseParserType<'input, I>>,
 {
-    pub fn get_serialized_atn() -> &'static str { _serializedATN }
+    pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
 
     pub fn set_error_strategy(&mut self, strategy: H) { self.err_handler = strategy }
 
@@ -209,6 +209,8 @@ impl<'input, I: TokenStream<'input, TF = LocalTokenFactory<'input>> + TidAble<'i
     fn get_rule_names(&self) -> &[&str] { &ruleNames }
 
     fn get_vocabulary(&self) -> &dyn Vocabulary { &**VOCABULARY }
+
+    fn get_serialized_atn(&self) -> &str { _serializedATN.as_str().unwrap_or("") }
 }
 //------------------- csvFile ----------------
 pub type CsvFileContextAll<'input> = CsvFileContext<'input>;
@@ -705,7 +707,7 @@ where
 
 lazy_static! {
     static ref _ATN: Arc<ATN> =
-        Arc::new(ATNDeserializer::new(None).deserialize(_serializedATN.chars()));
+        Arc::new(ATNDeserializer::new(None).deserialize_atn(&_serializedATN));
     static ref _decision_to_DFA: Arc<Vec<antlr_rust::RwLock<DFA>>> = {
         let mut dfa = Vec::new();
         let size = _ATN.decision_to_state.len();
@@ -716,7 +718,11 @@ lazy_static! {
     };
 }
 
-const _serializedATN: &'static str =
+/// Serialized ATN of the grammar, unlike associated `get_serialized_atn` of the recognizer
+/// it can be called without specifying type parameters of the recognizer type
+pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
+
+static _serializedATN: SerializedATN = SerializedATN::from_static(
     "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x03\
 	\x08\x25\x04\x02\x09\x02\x04\x03\x09\x03\x04\x04\x09\x04\x04\x05\x09\x05\
 	\x03\x02\x03\x02\x06\x02\x0d\x0a\x02\x0d\x02\x0e\x02\x0e\x03\x03\x03\x03\
@@ -734,4 +740,4 @@ const _serializedATN: &'static str =
 	\x1c\x03\x02\x02\x02\x1c\x1d\x03\x02\x02\x02\x1d\x1e\x07\x05\x02\x02\x1e\
 	\x07\x03\x02\x02\x02\x1f\x23\x07\x07\x02\x02\x20\x23\x07\x08\x02\x02\x21\
 	\x23\x03\x02\x02\x02\x22\x1f\x03\x02\x02\x02\x22\x20\x03\x02\x02\x02\x22\
-	\x21\x03\x02\x02\x02\x23\x09\x03\x02\x02\x02\x06\x0e\x17\x1b\x22";
+	\x21\x03\x02\x02\x02\x23\x09\x03\x02\x02\x02\x06\x0e\x17\x1b\x22");
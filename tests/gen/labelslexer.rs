@@ -4,7 +4,7 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 use antlr_rust::atn::ATN;
-use antlr_rust::atn_deserializer::ATNDeserializer;
+use antlr_rust::atn_deserializer::{ATNDeserializer, SerializedATN};
 use antlr_rust::char_stream::CharStream;
 use antlr_rust::dfa::DFA;
 use antlr_rust::error_listener::ErrorListener;
@@ -105,6 +105,8 @@ impl<'input, Input: CharStream<From<'input>>> LabelsLexer<'input, Input> {
 
     fn get_grammar_file_name(&self) -> &'static str { "LabelsLexer.g4" }
 
+    pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
+
     pub fn new_with_token_factory(input: Input, tf: &'input LocalTokenFactory<'input>) -> Self {
         antlr_rust::recognizer::check_version("0", "2");
         Self {
@@ -142,6 +144,15 @@ impl<'input, Input: CharStream<From<'input>>>
     Actions<'input, BaseLexer<'input, LabelsLexerActions, Input, LocalTokenFactory<'input>>>
     for LabelsLexerActions
 {
+    fn get_mode_names(&self) -> &[&str] { &modeNames }
+
+    fn get_rule_names(&self) -> &[&str] { &ruleNames }
+
+    fn get_vocabulary(&self) -> &dyn Vocabulary { &**VOCABULARY }
+
+    fn get_grammar_file_name(&self) -> &str { "Labels.g4" }
+
+    fn get_serialized_atn(&self) -> &str { _serializedATN.as_str().unwrap_or("") }
 }
 
 impl<'input, Input: CharStream<From<'input>>> LabelsLexer<'input, Input> {}
@@ -173,7 +184,7 @@ impl<'input, Input: CharStream<From<'input>>> TokenSource<'input> for LabelsLexe
 
 lazy_static! {
     static ref _ATN: Arc<ATN> =
-        Arc::new(ATNDeserializer::new(None).deserialize(_serializedATN.chars()));
+        Arc::new(ATNDeserializer::new(None).deserialize_atn(&_serializedATN));
     static ref _decision_to_DFA: Arc<Vec<antlr_rust::RwLock<DFA>>> = {
         let mut dfa = Vec::new();
         let size = _ATN.decision_to_state.len();
@@ -184,7 +195,11 @@ lazy_static! {
     };
 }
 
-const _serializedATN: &'static str =
+/// Serialized ATN of the grammar, unlike associated `get_serialized_atn` of the recognizer
+/// it can be called without specifying type parameters of the recognizer type
+pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
+
+static _serializedATN: SerializedATN = SerializedATN::from_static(
     "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x02\
 		\x0b\x31\x08\x01\x04\x02\x09\x02\x04\x03\x09\x03\x04\x04\x09\x04\x04\x05\
 		\x09\x05\x04\x06\x09\x06\x04\x07\x09\x07\x04\x08\x09\x08\x04\x09\x09\x09\
@@ -209,4 +224,4 @@ const _serializedATN: &'static str =
 		\x28\x2a\x04\x32\x3b\x02\x29\x28\x03\x02\x02\x02\x2a\x2b\x03\x02\x02\x02\
 		\x2b\x29\x03\x02\x02\x02\x2b\x2c\x03\x02\x02\x02\x2c\x12\x03\x02\x02\x02\
 		\x2d\x2e\x09\x02\x02\x02\x2e\x2f\x03\x02\x02\x02\x2f\x30\x08\x0a\x02\x02\
-		\x30\x14\x03\x02\x02\x02\x05\x02\x26\x2b\x03\x08\x02\x02";
+		\x30\x14\x03\x02\x02\x02\x05\x02\x26\x2b\x03\x08\x02\x02");
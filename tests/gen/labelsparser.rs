@@ -7,7 +7,7 @@
 #![allow(unused_mut)]
 use super::labelslistener::*;
 use antlr_rust::atn::{ATN, INVALID_ALT};
-use antlr_rust::atn_deserializer::ATNDeserializer;
+use antlr_rust::atn_deserializer::{ATNDeserializer, SerializedATN};
 use antlr_rust::dfa::DFA;
 use antlr_rust::error_strategy::{DefaultErrorStrategy, ErrorStrategy};
 use antlr_rust::errors::*;
@@ -111,7 +111,7 @@ where
     I: TokenStream<'input, TF = LocalTokenFactory<'input>> + TidAble<'input>,
     H: ErrorStrategy<'input, BaseParserType<'input, I>>,
 {
-    pub fn get_serialized_atn() -> &'static str { _serializedATN }
+    pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
 
     pub fn set_error_strategy(&mut self, strategy: H) { self.err_handler = strategy }
 
@@ -212,6 +212,8 @@ impl<'input, I: TokenStream<'input, TF = LocalTokenFactory<'input>> + TidAble<'i
     fn get_rule_names(&self) -> &[&str] { &ruleNames }
 
     fn get_vocabulary(&self) -> &dyn Vocabulary { &**VOCABULARY }
+
+    fn get_serialized_atn(&self) -> &str { _serializedATN.as_str().unwrap_or("") }
     fn sempred(
         _localctx: Option<&(dyn LabelsParserContext<'input> + 'input)>,
         rule_index: isize,
@@ -1343,7 +1345,7 @@ where
 
 lazy_static! {
     static ref _ATN: Arc<ATN> =
-        Arc::new(ATNDeserializer::new(None).deserialize(_serializedATN.chars()));
+        Arc::new(ATNDeserializer::new(None).deserialize_atn(&_serializedATN));
     static ref _decision_to_DFA: Arc<Vec<antlr_rust::RwLock<DFA>>> = {
         let mut dfa = Vec::new();
         let size = _ATN.decision_to_state.len();
@@ -1354,7 +1356,11 @@ lazy_static! {
     };
 }
 
-const _serializedATN: &'static str =
+/// Serialized ATN of the grammar, unlike associated `get_serialized_atn` of the recognizer
+/// it can be called without specifying type parameters of the recognizer type
+pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
+
+static _serializedATN: SerializedATN = SerializedATN::from_static(
     "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x03\
 	\x0b\x2a\x04\x02\x09\x02\x04\x03\x09\x03\x03\x02\x03\x02\x03\x03\x03\x03\
 	\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x03\x05\x03\
@@ -1375,4 +1381,4 @@ const _serializedATN: &'static str =
 	\x23\x07\x08\x02\x02\x23\x25\x08\x03\x01\x02\x24\x14\x03\x02\x02\x02\x24\
 	\x19\x03\x02\x02\x02\x24\x1e\x03\x02\x02\x02\x24\x21\x03\x02\x02\x02\x25\
 	\x28\x03\x02\x02\x02\x26\x24\x03\x02\x02\x02\x26\x27\x03\x02\x02\x02\x27\
-	\x05\x03\x02\x02\x02\x28\x26\x03\x02\x02\x02\x05\x12\x24\x26";
+	\x05\x03\x02\x02\x02\x28\x26\x03\x02\x02\x02\x05\x12\x24\x26");
@@ -4,7 +4,7 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 use antlr_rust::atn::ATN;
-use antlr_rust::atn_deserializer::ATNDeserializer;
+use antlr_rust::atn_deserializer::{ATNDeserializer, SerializedATN};
 use antlr_rust::char_stream::CharStream;
 use antlr_rust::dfa::DFA;
 use antlr_rust::error_listener::ErrorListener;
@@ -79,6 +79,8 @@ impl<'input, Input: CharStream<From<'input>>> ReferenceToATNLexer<'input, Input>
 
     fn get_grammar_file_name(&self) -> &'static str { "ReferenceToATNLexer.g4" }
 
+    pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
+
     pub fn new_with_token_factory(input: Input, tf: &'input LocalTokenFactory<'input>) -> Self {
         antlr_rust::recognizer::check_version("0", "2");
         Self {
@@ -116,6 +118,15 @@ impl<'input, Input: CharStream<From<'input>>>
     Actions<'input, BaseLexer<'input, ReferenceToATNLexerActions, Input, LocalTokenFactory<'input>>>
     for ReferenceToATNLexerActions
 {
+    fn get_mode_names(&self) -> &[&str] { &modeNames }
+
+    fn get_rule_names(&self) -> &[&str] { &ruleNames }
+
+    fn get_vocabulary(&self) -> &dyn Vocabulary { &**VOCABULARY }
+
+    fn get_grammar_file_name(&self) -> &str { "ReferenceToATN.g4" }
+
+    fn get_serialized_atn(&self) -> &str { _serializedATN.as_str().unwrap_or("") }
 }
 
 impl<'input, Input: CharStream<From<'input>>> ReferenceToATNLexer<'input, Input> {}
@@ -151,7 +162,7 @@ impl<'input, Input: CharStream<From<'input>>> TokenSource<'input>
 
 lazy_static! {
     static ref _ATN: Arc<ATN> =
-        Arc::new(ATNDeserializer::new(None).deserialize(_serializedATN.chars()));
+        Arc::new(ATNDeserializer::new(None).deserialize_atn(&_serializedATN));
     static ref _decision_to_DFA: Arc<Vec<antlr_rust::RwLock<DFA>>> = {
         let mut dfa = Vec::new();
         let size = _ATN.decision_to_state.len();
@@ -162,7 +173,11 @@ lazy_static! {
     };
 }
 
-const _serializedATN: &'static str =
+/// Serialized ATN of the grammar, unlike associated `get_serialized_atn` of the recognizer
+/// it can be called without specifying type parameters of the recognizer type
+pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
+
+static _serializedATN: SerializedATN = SerializedATN::from_static(
     "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x02\
 		\x05\x17\x08\x01\x04\x02\x09\x02\x04\x03\x09\x03\x04\x04\x09\x04\x03\x02\
 		\x06\x02\x0b\x0a\x02\x0d\x02\x0e\x02\x0c\x03\x03\x06\x03\x10\x0a\x03\x0d\
@@ -175,4 +190,4 @@ const _serializedATN: &'static str =
 		\x3b\x02\x0f\x0e\x03\x02\x02\x02\x10\x11\x03\x02\x02\x02\x11\x0f\x03\x02\
 		\x02\x02\x11\x12\x03\x02\x02\x02\x12\x06\x03\x02\x02\x02\x13\x14\x09\x02\
 		\x02\x02\x14\x15\x03\x02\x02\x02\x15\x16\x08\x04\x02\x02\x16\x08\x03\x02\
-		\x02\x02\x05\x02\x0c\x11\x03\x08\x02\x02";
+		\x02\x02\x05\x02\x0c\x11\x03\x08\x02\x02");
@@ -4,7 +4,7 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 use antlr_rust::atn::ATN;
-use antlr_rust::atn_deserializer::ATNDeserializer;
+use antlr_rust::atn_deserializer::{ATNDeserializer, SerializedATN};
 use antlr_rust::char_stream::CharStream;
 use antlr_rust::dfa::DFA;
 use antlr_rust::error_listener::ErrorListener;
@@ -156,6 +156,8 @@ impl<'input, Input: CharStream<From<'input>>> XMLLexer<'input, Input> {
 
     fn get_grammar_file_name(&self) -> &'static str { "XMLLexer.g4" }
 
+    pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
+
     pub fn new_with_token_factory(input: Input, tf: &'input LocalTokenFactory<'input>) -> Self {
         antlr_rust::recognizer::check_version("0", "2");
         Self {
@@ -190,6 +192,16 @@ impl<'input, Input: CharStream<From<'input>>>
     Actions<'input, BaseLexer<'input, XMLLexerActions, Input, LocalTokenFactory<'input>>>
     for XMLLexerActions
 {
+    fn get_mode_names(&self) -> &[&str] { &modeNames }
+
+    fn get_rule_names(&self) -> &[&str] { &ruleNames }
+
+    fn get_vocabulary(&self) -> &dyn Vocabulary { &**VOCABULARY }
+
+    fn get_grammar_file_name(&self) -> &str { "XMLLexer.g4" }
+
+    fn get_serialized_atn(&self) -> &str { _serializedATN.as_str().unwrap_or("") }
+
     fn action(
         _localctx: Option<&EmptyContext<'input, LocalTokenFactory<'input>>>,
         rule_index: isize,
@@ -267,7 +279,7 @@ impl<'input, Input: CharStream<From<'input>>> TokenSource<'input> for XMLLexer<'
 
 lazy_static! {
     static ref _ATN: Arc<ATN> =
-        Arc::new(ATNDeserializer::new(None).deserialize(_serializedATN.chars()));
+        Arc::new(ATNDeserializer::new(None).deserialize_atn(&_serializedATN));
     static ref _decision_to_DFA: Arc<Vec<antlr_rust::RwLock<DFA>>> = {
         let mut dfa = Vec::new();
         let size = _ATN.decision_to_state.len();
@@ -278,7 +290,11 @@ lazy_static! {
     };
 }
 
-const _serializedATN: &'static str =
+/// Serialized ATN of the grammar, unlike associated `get_serialized_atn` of the recognizer
+/// it can be called without specifying type parameters of the recognizer type
+pub fn get_serialized_atn() -> &'static SerializedATN { &_serializedATN }
+
+static _serializedATN: SerializedATN = SerializedATN::from_static(
     "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x02\
 		\x14\u{e8}\x08\x01\x08\x01\x08\x01\x04\x02\x09\x02\x04\x03\x09\x03\x04\
 		\x04\x09\x04\x04\x05\x09\x05\x04\x06\x09\x06\x04\x07\x09\x07\x04\x08\x09\
@@ -404,4 +420,4 @@ const _serializedATN: &'static str =
 		\x02\x02\x02\u{e5}\u{e6}\x03\x02\x02\x02\u{e6}\u{e7}\x08\x19\x04\x02\u{e7}\
 		\x34\x03\x02\x02\x02\x14\x02\x03\x04\x3d\x53\x60\x71\x7c\u{80}\u{84}\u{87}\
 		\u{a2}\u{b9}\u{c1}\u{c5}\u{cb}\u{da}\u{dd}\x08\x08\x02\x02\x07\x03\x02\
-		\x05\x02\x02\x07\x04\x02\x03\x0c\x02\x06\x02\x02";
+		\x05\x02\x02\x07\x04\x02\x03\x0c\x02\x06\x02\x02");
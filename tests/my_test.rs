@@ -193,6 +193,2587 @@ if (x < x && a > 0) then duh
         );
     }
 
+    #[test]
+    fn test_full_context_memo() {
+        use antlr_rust::error_listener::AmbiguityCollector;
+        use antlr_rust::error_strategy::BailErrorStrategy;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_atn_simulator::{ParseInfo, ParserATNSimulator, StrictnessPolicy};
+
+        // decision 0 is the `(ID|ATN)*` loop, which needs full context at trailing ATN token
+        let lexer = ReferenceToATNLexer::new_with_token_factory(
+            InputStream::new_owned("a 34".to_owned().into_boxed_str()),
+            &FACTORY,
+        );
+        let mut parser = ReferenceToATNParser::with_strategy(
+            CommonTokenStream::new(lexer),
+            BailErrorStrategy::new(),
+        );
+        let collector = Rc::new(AmbiguityCollector::new());
+        parser.add_error_listener(collector.clone());
+        // parses the same input again, `reset` would clear the memo
+        let mut parse = |setup: fn(&ParserATNSimulator)| {
+            parser.get_input_stream_mut().seek(0);
+            setup(parser.get_interpreter());
+            let text = parser.a().map(|it| it.get_text()).map_err(|_| "failed");
+            let interpreter = parser.get_interpreter();
+            let counters = (
+                interpreter.get_full_context_memo_hits(),
+                interpreter.get_full_context_memo_misses(),
+            );
+            assert_eq!(collector.take_ambiguities().len(), 1);
+            (text, counters)
+        };
+        let parsed = |hits, misses| (Ok("a34".to_owned()), (hits, misses));
+
+        // memo is disabled by default
+        assert_eq!(parse(|_| {}), parsed(0, 0));
+
+        let enable = |it: &ParserATNSimulator| it.set_full_context_memo_capacity(16);
+        assert_eq!(parse(enable), parsed(0, 1));
+        // second identical prediction is served from the memo and reports the same ambiguity
+        assert_eq!(parse(|_| {}), parsed(1, 1));
+        assert_eq!(parse(|_| {}), parsed(2, 1));
+        assert_eq!(parse(|it| it.clear_full_context_memo()), parsed(2, 2));
+        assert_eq!(parse(|it| it.clear_dfa()), parsed(2, 3));
+        let strict = |it: &ParserATNSimulator| {
+            it.set_strictness_policy(StrictnessPolicy {
+                fail_on_ambiguity: true,
+                ..StrictnessPolicy::default()
+            })
+        };
+        assert_eq!(parse(strict), (Err("failed"), (3, 3)));
+
+        // memo is bound to the input, so new parse computes prediction again
+        parser.reset();
+        parser
+            .get_interpreter()
+            .set_strictness_policy(StrictnessPolicy::default());
+        parser.a().unwrap();
+        let lexer = ReferenceToATNLexer::new_with_token_factory(
+            InputStream::new_owned("a 34".to_owned().into_boxed_str()),
+            &FACTORY,
+        );
+        parser.set_input_stream(CommonTokenStream::new(lexer));
+        parser.a().unwrap();
+        let interpreter = parser.get_interpreter();
+        let counters = (
+            interpreter.get_full_context_memo_hits(),
+            interpreter.get_full_context_memo_misses(),
+        );
+        assert_eq!(counters, (3, 5));
+    }
+
+    #[test]
+    fn test_decision_to_rule() {
+        use antlr_rust::atn_simulator::IATNSimulator;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_atn_simulator::ParseInfo;
+        use antlr_rust::recognizer::Recognizer;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let atn = parser.get_atn();
+        let rule_names = parser.get_rule_names();
+        // `row+` loop in `csvFile`, `(',' field)*` loop and `'\r'?` in `row`,
+        // and alternatives of `field`
+        let rules = (0..atn.decision_to_state.len())
+            .map(|decision| rule_names[atn.decision_to_rule(decision)])
+            .collect::<Vec<_>>();
+        assert_eq!(rules, ["csvFile", "row", "row", "field"]);
+        assert_eq!(
+            atn.describe_decision(3, rule_names, "CSV.g4"),
+            "decision 3 (rule field)"
+        );
+        assert_eq!(
+            atn.describe_decision(0, &[], "CSV.g4"),
+            "decision 0 (rule 0)"
+        );
+        assert_eq!(atn.describe_decision(9, rule_names, "CSV.g4"), "decision 9");
+
+        parser.csvFile().unwrap();
+        let atn = parser.get_atn();
+        for (decision, dfa) in parser
+            .get_interpreter()
+            .decision_to_dfa()
+            .iter()
+            .enumerate()
+        {
+            let dfa = dfa.read();
+            assert_eq!(dfa.decision(), decision as isize);
+            assert_eq!(dfa.atn_start_state(), atn.get_decision_state(decision));
+        }
+
+        let lexer = ReferenceToATNLexer::new_with_token_factory(
+            InputStream::new_owned("a 34".to_owned().into_boxed_str()),
+            &FACTORY,
+        );
+        let mut parser = ReferenceToATNParser::new(CommonTokenStream::new(lexer));
+        parser.a().unwrap();
+        let report = parser.get_interpreter().report(&*parser);
+        assert!(report.contains("\ndecision 0 (rule a): "), "{}", report);
+    }
+
+    #[test]
+    fn test_grammar_source_positions() {
+        use antlr_rust::atn::ATN;
+        use antlr_rust::atn_config_set::ATNConfigSet;
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::dfa::DFA;
+        use antlr_rust::dfa_state::DFAState;
+        use antlr_rust::error_listener::{DiagnosticErrorListener, ErrorListener};
+        use antlr_rust::parser_atn_simulator::{ParseInfo, ParserATNSimulator};
+        use antlr_rust::recognizer::RecognizerRef;
+        use antlr_rust::vocabulary::Vocabulary;
+        use antlr_rust::{PredictionContextCache, RwLock};
+        use std::cell::RefCell;
+        use std::sync::Arc;
+
+        struct Recog {
+            atn: Arc<ATN>,
+            diagnostics: RefCell<Vec<String>>,
+        }
+
+        impl RecognizerRef for Recog {
+            fn rule_names(&self) -> &[&str] { &csvparser::ruleNames }
+
+            fn vocabulary(&self) -> Option<&dyn Vocabulary> { None }
+
+            fn grammar_file_name(&self) -> &str { "CSV.g4" }
+
+            fn atn(&self) -> Option<&ATN> { Some(&self.atn) }
+
+            fn input_text(&self, _start: isize, _stop: isize) -> String { "a".to_owned() }
+
+            fn report_diagnostic(&self, msg: String) { self.diagnostics.borrow_mut().push(msg) }
+        }
+
+        let mut atn = ATNDeserializer::new(None).deserialize_atn(csvparser::get_serialized_atn());
+        assert_eq!(atn.decision_location(3, "CSV.g4"), None);
+        // rules are csvFile, hdr, row and field, only decision 3 has its own position
+        atn.set_rule_source_positions(vec![None, None, Some((7, 0)), Some((11, 0))]);
+        atn.set_decision_source_positions(vec![None, None, None, Some((12, 6))]);
+        assert_eq!(atn.rule_source_position(2), Some((7, 0)));
+        assert_eq!(atn.decision_source_position(1), None);
+        assert_eq!(atn.decision_location(1, "CSV.g4").unwrap(), "CSV.g4:7:0");
+        assert_eq!(atn.decision_location(3, "CSV.g4").unwrap(), "CSV.g4:12:6");
+        assert_eq!(atn.decision_location(0, "CSV.g4"), None);
+
+        let atn = Arc::new(atn);
+        let recog = Recog {
+            atn: atn.clone(),
+            diagnostics: RefCell::default(),
+        };
+        let mut dfas = (0..atn.decision_to_state.len())
+            .map(|i| DFA::new(atn.clone(), atn.get_decision_state(i), i as isize))
+            .collect::<Vec<_>>();
+        let configs = ATNConfigSet::new_base_atnconfig_set(true);
+        let listener = DiagnosticErrorListener::new(false);
+        listener.report_context_sensitivity(&recog, &dfas[3], 0, 0, 1, &configs);
+        listener.report_context_sensitivity(&recog, &dfas[0], 0, 0, 1, &configs);
+        assert_eq!(
+            *recog.diagnostics.borrow(),
+            vec![
+                "reportContextSensitivity decision 3 (rule field) at CSV.g4:12:6, input='a'",
+                "reportContextSensitivity decision 0 (rule csvFile), input='a'",
+            ]
+        );
+
+        dfas[1]
+            .states
+            .push(DFAState::new_dfastate(1, Box::new(configs)));
+        let simulator = ParserATNSimulator::new(
+            atn.clone(),
+            Arc::new(dfas.into_iter().map(RwLock::new).collect()),
+            Arc::new(PredictionContextCache::new()),
+        );
+        assert_eq!(
+            simulator.report(&recog),
+            "full context memo: 0 hits, 0 misses\n\
+             decision 1 (rule row) at CSV.g4:7:0: 1 DFA states"
+        );
+    }
+
+    #[test]
+    fn test_token_stream_contract() {
+        use antlr_rust::int_stream::check_stream_contract;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = || CSVLexer::new_with_token_factory(InputStream::new("a,b\n".into()), &tf);
+        let symbols = [TEXT, T__0, TEXT, T__2];
+        check_stream_contract(&mut CommonTokenStream::new(lexer()), &symbols);
+        check_stream_contract(&mut UnbufferedTokenStream::new_buffered(lexer()), &symbols);
+        check_stream_contract(
+            &mut UnbufferedTokenStream::new_unbuffered(lexer()),
+            &symbols,
+        );
+    }
+
+    #[test]
+    fn test_common_token_stream_fill() {
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a, b\n".into()), &tf);
+        let mut stream = CommonTokenStream::new(lexer);
+        stream.fill().unwrap();
+        // hidden whitespace is buffered too, but skipped by lookahead
+        assert_eq!(stream.size(), 6);
+        assert_eq!(stream.get(2).get_token_type(), WS);
+        assert_eq!(stream.get(5).get_token_type(), TOKEN_EOF);
+        assert_eq!(stream.index(), 0);
+        assert_eq!(stream.la(3), TEXT);
+
+        stream.seek(4);
+        assert_eq!(stream.la(1), T__2);
+        assert_eq!(stream.lt(-1).unwrap().get_text(), "b");
+        stream.fill().unwrap();
+        assert_eq!(stream.size(), 6);
+    }
+
+    #[test]
+    fn test_unbuffered_token_stream_releases_tokens() {
+        use antlr_rust::parser_rule_context::ParserRuleContext;
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a,b\n".repeat(10_000);
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.as_str()), &tf);
+        let mut tokens = UnbufferedTokenStream::new_unbuffered(lexer);
+        let mut consumed = 0;
+        while tokens.la(1) != TOKEN_EOF {
+            let next = tokens.la(1);
+            if consumed % 3 == 0 {
+                tokens.lt(2);
+            }
+            tokens.consume();
+            consumed += 1;
+            assert_eq!(tokens.la(-1), next);
+            assert!(tokens.size() <= 3, "{} tokens buffered", tokens.size());
+        }
+        assert_eq!(tokens.index(), 40_000);
+
+        // tokens after the outstanding mark are kept until it is released
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.as_str()), &tf);
+        let mut tokens = UnbufferedTokenStream::new_unbuffered(lexer);
+        tokens.consume();
+        let marker = tokens.mark();
+        for _ in 0..100 {
+            tokens.consume();
+        }
+        tokens.seek(1);
+        assert_eq!(tokens.la(1), T__0);
+        tokens.seek(101);
+        tokens.release(marker);
+        assert_eq!(tokens.la(1), T__0);
+        assert_eq!(tokens.la(-1), TEXT);
+        tokens.consume();
+        assert!(tokens.size() <= 2, "{} tokens buffered", tokens.size());
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.as_str()), &tf);
+        let mut parser = CSVParser::new(UnbufferedTokenStream::new_unbuffered(lexer));
+        parser.build_parse_trees = false;
+        let root = parser.csvFile().unwrap();
+        assert_eq!(root.stop().get_text(), "\n");
+        assert!(
+            parser.input.size() <= 2,
+            "{} tokens buffered",
+            parser.input.size()
+        );
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_char_stream() {
+        use antlr_rust::char_stream::MmapFile;
+        use std::path::PathBuf;
+
+        // removes test files even if the test fails, after the mappings are dropped
+        struct TempFiles(Vec<PathBuf>);
+
+        impl Drop for TempFiles {
+            fn drop(&mut self) {
+                for path in &self.0 {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("antlr_mmap_{}.csv", std::process::id()));
+        let invalid = path.with_extension("bin");
+        let _files = TempFiles(vec![path.clone(), invalid.clone()]);
+        std::fs::write(&path, "h1,h2\nä,\"b c\"\n").unwrap();
+        let file = unsafe { MmapFile::open(&path) }.unwrap();
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(file.char_stream(), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let root = parser.csvFile().unwrap();
+        assert_eq!(
+            root.to_string_tree(&*parser),
+            "(csvFile (hdr (row (field h1) , (field h2) \\n)) \
+             (row (field ä) , (field \"b c\") \\n))"
+        );
+        // token text points into the mapping
+        let text = parser.input.get(6).get_text();
+        let mapped = file.as_str().as_ptr() as usize;
+        assert!((mapped..mapped + file.as_str().len()).contains(&(text.as_ptr() as usize)));
+        assert_eq!(
+            parser.input.get_token_source().get_source_name(),
+            path.display().to_string()
+        );
+
+        std::fs::write(&invalid, b"\xff").unwrap();
+        let error = unsafe { MmapFile::open(&invalid) }.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "rope")]
+    #[test]
+    fn test_rope_char_stream() {
+        use antlr_rust::char_stream::RopeCharStream;
+        use antlr_rust::int_stream::check_stream_contract;
+        use antlr_rust::interval_set::CharInterval;
+        use ropey::Rope;
+
+        let mut rope = Rope::from_str("h1,h2\n");
+        rope.insert(6, &"😀,ä\n".repeat(500));
+        let symbols = rope.chars().map(|it| it as isize).collect::<Vec<_>>();
+        check_stream_contract(&mut RopeCharStream::new(&rope), &symbols);
+
+        let tf = ArenaCommonFactory::default();
+        let snapshot = rope.clone();
+        let input = RopeCharStream::new(&snapshot).with_source_name("doc");
+        let mut tokens = CommonTokenStream::new(CSVLexer::new_with_token_factory(input, &tf));
+        tokens.fill().unwrap();
+        let last = tokens.get(tokens.size() - 3);
+        assert_eq!(last.get_text(), "ä");
+        assert_eq!(last.get_start() as usize, rope.len_chars() - 2);
+        assert_eq!(tokens.get(4).get_text(), "😀");
+
+        let input = RopeCharStream::new(&rope);
+        let slice = input.get_slice(CharInterval::new(3, 7));
+        assert_eq!(slice, "h2\n😀,");
+        assert_eq!(input.get_slice(CharInterval::new(-5, 0)), "h");
+    }
+
+    #[test]
+    fn test_unbuffered_char_stream() {
+        use antlr_rust::unbuffered_char_stream::UnbufferedCharStream;
+        use std::io::Cursor;
+
+        let tf = ArenaCommonFactory::default();
+        let input = "h1,\"h 2\"\n".to_owned() + &"ä,b\n".repeat(1000);
+        let chars = UnbufferedCharStream::new(Cursor::new(input.clone().into_bytes()));
+        let lexer = CSVLexer::new_with_token_factory(chars, &tf);
+        let mut tokens = CommonTokenStream::new(lexer);
+        tokens.fill().unwrap();
+        assert_eq!(tokens.size(), 4 + 4000 + 1);
+        assert_eq!(tokens.get(2).get_text(), "\"h 2\"");
+        assert_eq!(tokens.get(4000).get_text(), "ä");
+        assert_eq!(tokens.get(4002).get_text(), "b");
+        assert_eq!(tokens.get_all_text(), input);
+    }
+
+    #[test]
+    fn test_code_point_char_stream() {
+        let tf = ArenaCommonFactory::default();
+        let input = InputStream::<Box<[u32]>>::from_code_points("ä,😀x\n");
+        let lexer = CSVLexer::new_with_token_factory(input, &tf);
+        let mut tokens = CommonTokenStream::new(lexer);
+        tokens.fill().unwrap();
+        let actual = (0..tokens.size())
+            .map(|i| {
+                let token = tokens.get(i);
+                (
+                    token.get_text().to_owned(),
+                    token.get_start(),
+                    token.get_stop(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let expected = [
+            ("ä", 0, 0),
+            (",", 1, 1),
+            ("😀x", 2, 3),
+            ("\n", 4, 4),
+            ("<EOF>", 5, 4),
+        ];
+        let expected = expected
+            .iter()
+            .map(|&(text, start, stop)| (text.to_owned(), start, stop))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_byte_char_stream() {
+        use antlr_rust::byte_char_stream::{ByteCharStream, ByteTextFormat};
+        use antlr_rust::input_stream::ByteStream;
+        use antlr_rust::TokenSource;
+
+        let tf = ArenaCommonFactory::default();
+        let data = b"a,\xff\xfe\n";
+        let input = ByteCharStream::new(ByteStream::new(data));
+        let lexer = CSVLexer::new_with_token_factory(input, &tf);
+        let mut tokens = CommonTokenStream::new(lexer);
+        tokens.fill().unwrap();
+        assert_eq!(tokens.size(), 5);
+        let binary = tokens.get(2);
+        assert_eq!((binary.get_start(), binary.get_stop()), (2, 3));
+        assert_eq!(binary.get_text(), "\u{FFFD}\u{FFFD}");
+
+        let input = InputStream::new_owned(Box::from(&data[..]));
+        let input = ByteCharStream::new(input).with_text_format(ByteTextFormat::Hex);
+        let mut lexer = CSVLexer::new_with_token_factory(input, &tf);
+        lexer.next_token();
+        lexer.next_token();
+        assert_eq!(lexer.next_token().get_text(), "ff fe");
+    }
+
+    #[test]
+    fn test_case_changing_char_stream() {
+        use antlr_rust::case_changing_char_stream::CaseChangingCharStream;
+
+        let tf = ArenaCommonFactory::default();
+        let input = CaseChangingCharStream::upper(InputStream::new("MiXed,\"Case\"\n"));
+        let mut tokens = CommonTokenStream::new(CSVLexer::new_with_token_factory(input, &tf));
+        tokens.fill().unwrap();
+        assert_eq!(tokens.get(0).get_text(), "MiXed");
+        assert_eq!(tokens.get(2).get_text(), "\"Case\"");
+        assert_eq!(tokens.get(2).get_token_type(), STRING);
+    }
+
+    #[test]
+    fn test_parse_rule_at() {
+        use antlr_rust::errors::ANTLRError;
+        use antlr_rust::parser::{ParseRuleAt, Parser};
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a, b\nc,d\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+
+        let first = parser.parse_rule_at(0, false, |p| p.row()).unwrap();
+        assert_eq!(
+            first.tree.to_string_tree(&*parser),
+            "(row (field a) , (field b) \\n)"
+        );
+        let consumed = first.consumed_interval();
+        assert_eq!((consumed.a, consumed.b), (0, 4));
+        // hidden whitespace at index 2 is the part of the first row
+        assert_eq!(first.resume_index(), 5);
+
+        let second = parser
+            .parse_rule_at(first.resume_index(), true, |p| p.row())
+            .unwrap();
+        assert_eq!(
+            second.tree.to_string_tree(&*parser),
+            "(row (field c) , (field d) \\n)"
+        );
+        let consumed = second.consumed_interval();
+        assert_eq!((consumed.a, consumed.b), (5, 8));
+        assert_eq!(
+            parser
+                .get_input_stream()
+                .get(second.resume_index())
+                .get_token_type(),
+            TOKEN_EOF
+        );
+
+        parser.remove_error_listeners();
+        match parser.parse_rule_at(0, true, |p| p.row()) {
+            Err(ANTLRError::InputMismatchError(e)) => {
+                assert_eq!(e.base.offending_token.get_token_index(), 5);
+                assert!(e.base.get_expected_tokens(&*parser).contains(TOKEN_EOF));
+                assert!(e.expected.contains(TOKEN_EOF));
+                let message = e.to_string();
+                let expected_message = "2:0 mismatched input 'c' expecting <EOF>";
+                assert!(message.ends_with(expected_message), "{}", message);
+            }
+            _ => panic!("trailing input must be an error"),
+        };
+    }
+
+    #[test]
+    fn test_parse_memoized() {
+        use antlr_rust::parser::{ParseMemoized, ParseRuleAt, Parser};
+        use std::rc::Rc;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\nc".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        parser.remove_error_listeners();
+        parser.set_memoized_rules(&[csvparser::RULE_row, csvparser::RULE_field]);
+
+        let first = parser
+            .parse_rule_at(0, false, |p| {
+                p.parse_memoized(csvparser::RULE_row, |p| p.row())
+            })
+            .unwrap();
+        assert_eq!(parser.get_rule_memo().misses(), 1);
+        assert_eq!(parser.get_rule_memo().hits(), 0);
+
+        // speculating over the same span again is served from the memo
+        let second = parser
+            .parse_rule_at(0, false, |p| {
+                p.parse_memoized(csvparser::RULE_row, |p| p.row())
+            })
+            .unwrap();
+        assert_eq!(parser.get_rule_memo().hits(), 1);
+        assert!(Rc::ptr_eq(&first.tree, &second.tree));
+        assert_eq!(second.resume_index(), first.resume_index());
+        assert_eq!(
+            second.tree.to_string_tree(&*parser),
+            "(row (field a) , (field b) \\n)"
+        );
+
+        // results of error recovery (missing '\n') are not recorded
+        for _ in 0..2 {
+            parser.get_input_stream_mut().seek(4);
+            parser.parse_memoized(csvparser::RULE_row, |p| p.row()).unwrap();
+        }
+        assert_eq!(parser.get_rule_memo().hits(), 1);
+        assert_eq!(parser.get_rule_memo().misses(), 3);
+        assert_eq!(parser.get_rule_memo().len(), 1);
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("c\n".into()), &tf);
+        parser.set_input_stream(CommonTokenStream::new(lexer));
+        assert!(parser.get_rule_memo().is_empty());
+        let row = parser.parse_memoized(csvparser::RULE_row, |p| p.row()).unwrap();
+        assert_eq!(row.to_string_tree(&*parser), "(row (field c) \\n)");
+    }
+
+    #[test]
+    fn test_deserialize_generated_atns() {
+        use antlr_rust::atn::ATN;
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::atn_simulator::IATNSimulator;
+        use antlr_rust::atn_state::{ATNDecisionState, ATNStateType};
+        use antlr_rust::atn_type::ATNType;
+
+        // structure every ATN serialized by the ANTLR tool must have
+        fn check(atn: &ATN, grammar_type: ATNType, rule_names: &[&str]) {
+            assert_eq!(atn.grammar_type, grammar_type);
+            assert_eq!(atn.rule_to_start_state.len(), rule_names.len());
+            assert_eq!(atn.rule_to_stop_state.len(), rule_names.len());
+            for (rule, &start) in atn.rule_to_start_state.iter().enumerate() {
+                assert_eq!(atn.states[start].get_rule_index(), rule);
+                match atn.states[start].get_state_type() {
+                    ATNStateType::RuleStartState { stop_state, .. } => {
+                        assert_eq!(*stop_state, atn.rule_to_stop_state[rule])
+                    }
+                    other => panic!("unexpected rule start state {:?}", other),
+                }
+            }
+            for (number, state) in atn.states.iter().enumerate() {
+                assert_eq!(state.get_state_number(), number);
+                for tr in state.get_transitions() {
+                    assert!(tr.get_target() < atn.states.len());
+                }
+            }
+            for decision in 0..atn.decision_to_state.len() {
+                let state = atn.states[atn.get_decision_state(decision)].get_state_type();
+                match state {
+                    ATNStateType::DecisionState { decision: d, .. } => {
+                        assert_eq!(*d, decision as isize)
+                    }
+                    other => panic!("unexpected decision state {:?}", other),
+                }
+            }
+        }
+
+        let parsers = [
+            (csvparser::get_serialized_atn(), &csvparser::ruleNames[..]),
+            (
+                labelsparser::get_serialized_atn(),
+                &labelsparser::ruleNames[..],
+            ),
+            (
+                referencetoatnparser::get_serialized_atn(),
+                &referencetoatnparser::ruleNames[..],
+            ),
+            (
+                simplelrparser::get_serialized_atn(),
+                &simplelrparser::ruleNames[..],
+            ),
+        ];
+        for &(serialized, rule_names) in &parsers {
+            let atn = ATNDeserializer::new(None).deserialize_atn(serialized);
+            check(&atn, ATNType::PARSER, rule_names);
+        }
+
+        // `a : a ID | ID` is left recursive, so its loop decides by precedence
+        let atn = ATNDeserializer::new(None).deserialize_atn(simplelrparser::get_serialized_atn());
+        let left_recursive =
+            |rule: usize| match atn.states[atn.rule_to_start_state[rule]].get_state_type() {
+                ATNStateType::RuleStartState {
+                    is_left_recursive, ..
+                } => *is_left_recursive,
+                _ => unreachable!(),
+            };
+        assert!(!left_recursive(simplelrparser::RULE_s));
+        assert!(left_recursive(simplelrparser::RULE_a));
+        assert!(atn.states.iter().any(|state| match state.get_state_type() {
+            ATNStateType::DecisionState {
+                state: ATNDecisionState::StarLoopEntry { is_precedence, .. },
+                ..
+            } => *is_precedence && state.get_rule_index() == simplelrparser::RULE_a,
+            _ => false,
+        }));
+
+        let lexer = XMLLexer::new(InputStream::new(""));
+        let atn = lexer.get_interpreter().unwrap().atn();
+        check(atn, ATNType::LEXER, &xmllexer::ruleNames);
+        assert_eq!(atn.mode_to_start_state.len(), xmllexer::modeNames.len());
+        assert_eq!(atn.rule_to_token_type.len(), xmllexer::ruleNames.len());
+        assert_eq!(atn.max_token_type, xmllexer::PI);
+    }
+
+    #[test]
+    fn test_recognizer_serialized_atn() {
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::recognizer::Recognizer;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\n".into()), &tf);
+        let serialized = Recognizer::get_serialized_atn(&*lexer);
+        assert!(!serialized.is_empty());
+        let atn = ATNDeserializer::new(None).deserialize(serialized.chars());
+        assert_eq!(atn.rule_to_start_state.len(), csvlexer::ruleNames.len());
+
+        let parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let serialized = Recognizer::get_serialized_atn(&*parser);
+        assert!(!serialized.is_empty());
+        let atn = ATNDeserializer::new(None).deserialize(serialized.chars());
+        assert_eq!(atn.rule_to_start_state.len(), csvparser::ruleNames.len());
+    }
+
+    #[test]
+    fn test_expected_tokens() {
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::token::TOKEN_EPSILON;
+        use antlr_rust::transition::{RuleTransition, TransitionType};
+        use csvparser::{RULE_field, RULE_row};
+
+        let atn = ATNDeserializer::new(None).deserialize_atn(csvparser::get_serialized_atn());
+        let field = atn.rule_to_start_state[RULE_field];
+        // first `field` invocation in `row : field (',' field)* '\r'? '\n'`
+        let invoking_state = atn
+            .states
+            .iter()
+            .find(|state| {
+                state.get_transitions().first().map_or(false, |tr| {
+                    tr.get_serialization_type() == TransitionType::TRANSITION_RULE
+                        && tr.cast::<RuleTransition>().target == field
+                        && state.get_rule_index() == RULE_row
+                })
+            })
+            .unwrap()
+            .get_state_number() as isize;
+
+        let within_rule = atn.next_tokens(atn.states[field].as_ref());
+        for &symbol in &[TEXT, STRING, TOKEN_EPSILON] {
+            assert!(within_rule.contains(symbol));
+        }
+        assert!(!within_rule.contains(T__0));
+        let field = field as isize;
+        assert!(atn.is_expected_token(TEXT, field, std::iter::empty()));
+        assert!(!atn.is_expected_token(T__0, field, std::iter::empty()));
+        assert!(atn.is_expected_token(TOKEN_EOF, field, std::iter::empty()));
+        for &symbol in &[T__0, T__1, T__2] {
+            assert!(atn.is_expected_token(symbol, field, std::iter::once(invoking_state)));
+        }
+        assert!(!atn.is_expected_token(TOKEN_EOF, field, std::iter::once(invoking_state)));
+        assert!(!atn.is_expected_token(STRING + 1, field, std::iter::once(invoking_state)));
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        parser.set_state(field);
+        assert!(parser.is_expected_token(STRING));
+        assert!(!parser.is_expected_token(T__0));
+        assert!(parser
+            .get_expected_tokens_within_current_rule()
+            .contains(TOKEN_EPSILON));
+    }
+
+    #[test]
+    fn test_parse_two_stage() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        use antlr_rust::error_listener::CollectingErrorListener;
+        use antlr_rust::error_strategy::{BailErrorStrategy, DefaultErrorStrategy};
+        use antlr_rust::parser::{ParseTwoStage, Parser};
+        use antlr_rust::PredictionMode;
+
+        let tf = ArenaCommonFactory::default();
+        // missing `\n` makes the first stage bail out
+        for &(input, stages, errors) in &[("a,b\nc\n", 1, 0), ("a,b\nc", 2, 1)] {
+            let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+            let mut parser = CSVParser::with_dyn_strategy(CommonTokenStream::new(lexer));
+            let listener = Rc::new(CollectingErrorListener::new());
+            parser.remove_error_listeners();
+            parser.add_error_listener(listener.clone());
+            let modes = RefCell::new(Vec::new());
+            parser
+                .parse_two_stage(
+                    |p, bail| {
+                        p.err_handler = if bail {
+                            Box::new(BailErrorStrategy::new())
+                        } else {
+                            Box::new(DefaultErrorStrategy::new())
+                        }
+                    },
+                    |p| {
+                        modes
+                            .borrow_mut()
+                            .push(p.get_interpreter().get_prediction_mode());
+                        p.csvFile()
+                    },
+                )
+                .unwrap();
+            let expected_modes = [PredictionMode::SLL, PredictionMode::LL];
+            assert_eq!(&modes.borrow()[..], &expected_modes[..stages]);
+            assert_eq!(listener.take_diagnostics().len(), errors);
+            assert_eq!(
+                parser.get_interpreter().get_prediction_mode(),
+                PredictionMode::LL
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_grammar_name() {
+        use antlr_rust::parser::{ParseRuleAt, Parser};
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\n".into()), &tf);
+        let mut csv = CSVParser::new(CommonTokenStream::new(lexer));
+        csv.remove_error_listeners();
+        let csv_error = csv.parse_rule_at(0, true, |p| p.field()).unwrap_err();
+
+        let lexer = LabelsLexer::new(InputStream::new("1 2".into()));
+        let mut labels = LabelsParser::new(CommonTokenStream::new(lexer));
+        labels.remove_error_listeners();
+        let labels_error = labels.parse_rule_at(0, true, |p| p.s()).unwrap_err();
+
+        let csv_error = csv_error.get_recognition_error().unwrap();
+        assert_eq!(csv_error.get_grammar_file_name(), "CSV.g4");
+        assert_eq!(csv_error.get_rule_index(), Some(csvparser::RULE_field));
+        assert_eq!(csv_error.get_rule_name(), "field");
+        let labels_error = labels_error.get_recognition_error().unwrap();
+        assert_eq!(labels_error.get_grammar_file_name(), "Labels.g4");
+        assert_eq!(labels_error.get_rule_name(), "s");
+
+        // headers include grammar names, so errors can be told apart
+        assert_eq!(csv_error.get_error_header(), "CSV.g4: line 1:1");
+        assert_eq!(labels_error.get_error_header(), "Labels.g4: line 1:2");
+    }
+
+    #[test]
+    fn test_compact_tree() {
+        use antlr_rust::compact_tree::{CompactNodeKind, CompactTree};
+        use antlr_rust::parser::Parser;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\n\"x\"y\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        parser.remove_error_listeners();
+        let root = parser.csvFile().unwrap();
+
+        let compact = CompactTree::from_parse_tree(&*root);
+        assert_eq!(
+            compact.string_tree(&csvparser::ruleNames, parser.get_input_stream()),
+            root.to_string_tree(&*parser)
+        );
+        let row = compact.root().get_child(1).unwrap();
+        assert_eq!(row.rule_index(), Some(csvparser::RULE_row));
+        assert_eq!(row.get_text(parser.get_input_stream()), "\"x\"y\n");
+        assert!(row.children().any(|it| it.kind() == CompactNodeKind::Error));
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn test_single_token_deletion() {
+        use antlr_rust::parser::Parser;
+        use antlr_rust::testkit::EventRecorder;
+        use std::rc::Rc;
+
+        let parse = |input: &str| {
+            let tf = ArenaCommonFactory::default();
+            let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+            let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+            let recorder = Rc::new(EventRecorder::new());
+            parser.remove_error_listeners();
+            parser.add_error_listener(recorder.clone());
+            let tree = parser.csvFile().unwrap().to_string_tree(&*parser);
+            (tree, recorder.syntax_errors())
+        };
+
+        // token after `y` is expected, so `y` is deleted and row continues normally
+        let (tree, messages) = parse("a\n\"x\"y\n");
+        assert_eq!(
+            messages,
+            vec!["2:3 extraneous input 'y' expecting {',', '\\r', '\\n'}"]
+        );
+        assert_eq!(
+            tree,
+            "(csvFile (hdr (row (field a) \\n)) (row (field \"x\") y \\n))"
+        );
+
+        // token after `y` is not expected, deleting `y` would not help
+        let (_, messages) = parse("a\n\"x\"y z\n");
+        assert!(
+            messages[0].starts_with("2:3 mismatched input 'y'"),
+            "{:?}",
+            messages
+        );
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn test_single_token_insertion() {
+        use antlr_rust::parser::Parser;
+        use antlr_rust::testkit::EventRecorder;
+        use std::rc::Rc;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a\rc,d\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let recorder = Rc::new(EventRecorder::new());
+        parser.remove_error_listeners();
+        parser.add_error_listener(recorder.clone());
+        let tree = parser.csvFile().unwrap().to_string_tree(&*parser);
+
+        // `c` can follow '\n', so the missing '\n' is conjured up as an error node
+        assert_eq!(recorder.syntax_errors(), vec!["1:2 missing '\\n' at 'c'"]);
+        assert_eq!(
+            tree,
+            "(csvFile (hdr (row (field a) \\r <missing '\\n'>)) (row (field c) , (field d) \\n))"
+        );
+    }
+
+    #[test]
+    fn test_loop_recovery() {
+        use antlr_rust::error_listener::CollectingErrorListener;
+        use antlr_rust::parser::Parser;
+        use std::rc::Rc;
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a\nb,c\"x\"\n";
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let listener = Rc::new(CollectingErrorListener::new());
+        parser.remove_error_listeners();
+        parser.add_error_listener(listener.clone());
+
+        // extraneous token after loop iteration is consumed by resynchronization
+        let tree = parser.csvFile().unwrap().to_string_tree(&*parser);
+        let errors = listener
+            .take_diagnostics()
+            .iter()
+            .map(|it| format!("{}:{} {}", it.line, it.column, it.message))
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0].starts_with("2:3 extraneous input '\"x\"'"),
+            "{}",
+            errors[0]
+        );
+        assert_eq!(
+            tree,
+            "(csvFile (hdr (row (field a) \\n)) (row (field b) , (field c) \"x\" \\n))"
+        );
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn test_parse_events() {
+        use antlr_rust::parser::Parser;
+        use antlr_rust::testkit::Event::*;
+        use antlr_rust::testkit::{
+            assert_events_eq, assert_tree_eq, EventRecorder, MockTokenSource,
+        };
+        use std::rc::Rc;
+
+        impl<'input> CSVListener<'input> for EventRecorder {}
+
+        let tf = ArenaCommonFactory::default();
+        let tokens = [
+            (TEXT, "a"),
+            (T__2, "\n"),
+            (TEXT, "b"),
+            (TEXT, "c"),
+            (T__2, "\n"),
+        ];
+        let source = MockTokenSource::new(&tf).tokens(&tokens);
+        let mut parser = CSVParser::new(CommonTokenStream::new(source));
+        let recorder = EventRecorder::new();
+        parser.remove_error_listeners();
+        parser.add_error_listener(Rc::new(recorder.clone()));
+        parser.add_parse_listener(Box::new(recorder.clone()));
+        let tree = parser.csvFile().unwrap();
+
+        assert_tree_eq(
+            &*tree,
+            &csvparser::ruleNames,
+            "(csvFile (hdr (row (field a) \\n)) (row (field b) c \\n))",
+        );
+        let (csv_file, hdr, row, field) = (0, 1, 2, 3);
+        assert_events_eq(
+            &recorder.events(),
+            &[
+                EnterRule(csv_file),
+                EnterRule(hdr),
+                EnterRule(row),
+                EnterRule(field),
+                Terminal("a".to_owned()),
+                ExitRule(field),
+                Terminal("\n".to_owned()),
+                ExitRule(row),
+                ExitRule(hdr),
+                EnterRule(row),
+                EnterRule(field),
+                Terminal("b".to_owned()),
+                ExitRule(field),
+                SyntaxError {
+                    line: 2,
+                    column: 1,
+                    message: "extraneous input 'c' expecting {',', '\\r', '\\n'}".to_owned(),
+                    offending_token: Some(3),
+                },
+                ErrorNode("c".to_owned()),
+                Terminal("\n".to_owned()),
+                ExitRule(row),
+                ExitRule(csv_file),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_parse_collecting() {
+        use antlr_rust::error_listener::CollectingErrorListener;
+        use antlr_rust::parser::{ParseCollecting, Parser};
+        use antlr_rust::trees::error_subtrees;
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a,b\n\"x\"y\nc\n\"z\"w\n";
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let listener = Rc::new(CollectingErrorListener::new());
+        parser.remove_error_listeners();
+        parser.add_error_listener(listener.clone());
+
+        let (root, diagnostics) = parser.parse_collecting(|p| p.csvFile());
+        let root: Rc<dyn CSVParserContext> = root.unwrap();
+        let errors = diagnostics
+            .iter()
+            .map(|it| format!("{}:{} {}", it.line, it.column, it.message))
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(
+            errors[0].starts_with("2:3 extraneous input 'y'"),
+            "{}",
+            errors[0]
+        );
+        assert!(
+            errors[1].starts_with("4:3 extraneous input 'w'"),
+            "{}",
+            errors[1]
+        );
+        assert_eq!(
+            root.to_string_tree(&*parser),
+            "(csvFile (hdr (row (field a) , (field b) \\n)) (row (field \"x\") y \\n) \
+             (row (field c) \\n) (row (field \"z\") w \\n))"
+        );
+        assert_eq!(error_subtrees::<CSVParserContextType>(&root).len(), 2);
+
+        // previously registered listener is restored and was not called during collecting parse
+        assert!(listener.take_diagnostics().is_empty());
+        parser.get_input_stream_mut().seek(0);
+        let _ = parser.csvFile();
+        assert_eq!(listener.take_diagnostics().len(), 2);
+    }
+
+    #[test]
+    fn test_streamed_parse_events() {
+        use antlr_rust::parser::{ParseEvent, ParseEvents};
+        use csvparser::{RULE_csvFile, RULE_field, RULE_hdr, RULE_row};
+        use std::cell::RefCell;
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a,b\nc,\"d\"\n\"x\"y\ne,f\n";
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+
+        let texts = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let (texts_sink, log_sink) = (texts.clone(), log.clone());
+        let root = parser
+            .parse_events(
+                |p| p.csvFile(),
+                move |event| {
+                    let entry = match event {
+                        ParseEvent::EnterRule { rule, depth } => {
+                            format!("enter {}@{}", rule, depth)
+                        }
+                        ParseEvent::ExitRule { rule } => format!("exit {}", rule),
+                        ParseEvent::Token { token } => {
+                            if token.get_token_type() == TEXT {
+                                texts_sink.borrow_mut().push(token.get_text().to_owned());
+                            }
+                            format!("token {}", token.get_text().escape_default())
+                        }
+                        ParseEvent::Error { diagnostic } => {
+                            format!("error {}:{}", diagnostic.line, diagnostic.column)
+                        }
+                    };
+                    log_sink.borrow_mut().push(entry);
+                },
+            )
+            .unwrap();
+
+        assert_eq!(&*texts.borrow(), &["a", "b", "c", "e", "f"]);
+        let log = log.borrow();
+        let expected_start = [
+            format!("enter {}@0", RULE_csvFile),
+            format!("enter {}@1", RULE_hdr),
+            format!("enter {}@2", RULE_row),
+            format!("enter {}@3", RULE_field),
+            "token a".to_owned(),
+            format!("exit {}", RULE_field),
+        ];
+        assert_eq!(&log[..expected_start.len()], &expected_start);
+        assert_eq!(log.last().unwrap(), &format!("exit {}", RULE_csvFile));
+        let enters = log.iter().filter(|it| it.starts_with("enter")).count();
+        assert_eq!(
+            enters,
+            log.iter().filter(|it| it.starts_with("exit")).count()
+        );
+
+        // token consumed by error recovery is not reported, error precedes the next token
+        let error = log.iter().position(|it| it.starts_with("error")).unwrap();
+        assert_eq!(log[error], "error 3:3");
+        let next_token = log[error + 1..].iter().find(|it| it.starts_with("token"));
+        assert_eq!(next_token.unwrap(), "token \\n");
+        assert_eq!(log.iter().filter(|it| it.starts_with("error")).count(), 1);
+
+        // no tree has been built, error strategy can still refer to the root context
+        // it last synchronized in, so only children are checked
+        assert_eq!(root.get_child_count(), 0);
+        assert!(parser.ctx.is_none());
+        assert!(parser.build_parse_trees);
+    }
+
+    #[test]
+    fn test_island_scanning() {
+        use antlr_rust::token::TOKEN_GARBAGE_CHANNEL;
+        use labelslexer::T__2 as LPAREN;
+
+        let input = "Total: (1+2) and (3*x)!";
+        let mut lexer = LabelsLexer::new(InputStream::new(input.into()));
+        lexer.remove_error_listeners();
+        let mut parser = LabelsParser::new(CommonTokenStream::new(lexer));
+
+        let mut islands = Vec::new();
+        loop {
+            parser.input.scan_for(&[LPAREN]);
+            if parser.input.la(1) == TOKEN_EOF {
+                break;
+            }
+            islands.push(parser.e().unwrap().get_text());
+        }
+        assert_eq!(islands, ["(1+2)", "(3*x)"]);
+
+        let garbage = (0..parser.input.size())
+            .map(|i| parser.input.get(i))
+            .filter(|it| it.get_channel() == TOKEN_GARBAGE_CHANNEL)
+            .map(|it| (it.get_text().to_owned(), it.get_start(), it.get_stop()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            garbage,
+            [
+                ("Total: ".to_owned(), 0, 6),
+                (" and ".to_owned(), 12, 16),
+                ("!".to_owned(), 22, 22)
+            ]
+        );
+        assert_eq!(
+            parser.input.get(parser.input.size() - 1).get_token_type(),
+            TOKEN_EOF
+        );
+    }
+
+    #[test]
+    fn test_string_tree_annotated() {
+        use antlr_rust::parser::Parser;
+        use antlr_rust::trees::{to_string_tree_annotated, AnnotationOptions};
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a,\"a rather long string\"\n\"x\"y\n";
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        parser.remove_error_listeners();
+        let root = parser.csvFile().unwrap();
+
+        let plain = to_string_tree_annotated(&*root, &*parser, &AnnotationOptions::default());
+        assert_eq!(plain, root.to_string_tree(&*parser));
+        assert_eq!(
+            plain,
+            "(csvFile (hdr (row (field a) , (field \"a rather long string\") \\n)) \
+             (row (field \"x\") y \\n))"
+        );
+
+        let options = AnnotationOptions {
+            max_text_len: Some(8),
+            ..AnnotationOptions::all()
+        };
+        assert_eq!(
+            to_string_tree_annotated(&*root, &*parser, &options),
+            "(csvFile@0..29 (hdr@0..24 (row@0..24 (field@0..0 a<TEXT=5>@0..0) ,<1>@1..1 \
+             (field@2..23 \"a rathe…<STRING=6>@2..23) \\n<3>@24..24)) \
+             (row@25..29 (field@25..27 \"x\"<STRING=6>@25..27) y<TEXT=5>@28..28<error> \
+             \\n<3>@29..29))"
+        );
+    }
+
+    #[test]
+    fn test_lexer_modes() {
+        use std::cell::RefCell;
+
+        use antlr_rust::lexer::ModeTransitionListener;
+        use antlr_rust::TokenSource;
+        use xmllexer::*;
+
+        #[derive(Default)]
+        struct Recorder(RefCell<Vec<String>>);
+
+        impl ModeTransitionListener for Recorder {
+            fn on_push(&self, from: usize, to: usize) {
+                self.0.borrow_mut().push(format!("push {}->{}", from, to))
+            }
+            fn on_pop(&self, from: usize, to: usize) {
+                self.0.borrow_mut().push(format!("pop {}->{}", from, to))
+            }
+            fn on_set(&self, from: usize, to: usize) {
+                self.0.borrow_mut().push(format!("set {}->{}", from, to))
+            }
+        }
+
+        let recorder = Rc::new(Recorder::default());
+        let mut lexer = XMLLexer::new(InputStream::new("<a x=\"1\"><?pi data?>t</a>"));
+        lexer.add_mode_listener(recorder.clone());
+        assert_eq!(lexer.current_mode_name(), Some("DEFAULT_MODE"));
+
+        let open = lexer.next_token();
+        assert_eq!((open.get_token_type(), open.get_mode()), (OPEN, 0));
+        assert_eq!(lexer.mode_stack(), &[0]);
+        assert_eq!(lexer.current_mode_name(), Some("INSIDE"));
+        lexer.push_mode(PROC_INSTR);
+        assert_eq!(lexer.mode_stack(), &[0, INSIDE]);
+        assert_eq!(lexer.current_mode_name(), Some("PROC_INSTR"));
+        assert_eq!(lexer.pop_mode(), Some(INSIDE));
+
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            tokens.push((token.get_token_type(), token.get_mode()));
+            if token.get_token_type() == TOKEN_EOF {
+                break;
+            }
+        }
+        // processing instruction is started in default mode and finished in PROC_INSTR
+        assert_eq!(
+            tokens,
+            vec![
+                (Name, INSIDE),
+                (Name, INSIDE),
+                (EQUALS, INSIDE),
+                (STRING, INSIDE),
+                (CLOSE, INSIDE),
+                (PI, 0),
+                (TEXT, 0),
+                (OPEN, 0),
+                (SLASH, INSIDE),
+                (Name, INSIDE),
+                (CLOSE, INSIDE),
+                (TOKEN_EOF, 0),
+            ]
+        );
+        assert!(lexer.mode_stack().is_empty());
+        assert_eq!(lexer.pop_mode(), None);
+        lexer.set_mode(INSIDE);
+        assert_eq!(lexer.current_mode_name(), Some("INSIDE"));
+
+        assert_eq!(
+            *recorder.0.borrow(),
+            vec![
+                "push 0->1",
+                "push 1->2",
+                "pop 2->1",
+                "pop 1->0",
+                "push 0->2",
+                "pop 2->0",
+                "push 0->1",
+                "pop 1->0",
+                "set 0->1",
+            ]
+        );
+
+        let saved = (lexer.mode, lexer.mode_stack().to_vec());
+        lexer.set_mode_stack(&[0, PROC_INSTR]);
+        assert_eq!(lexer.pop_mode(), Some(PROC_INSTR));
+        lexer.mode = saved.0;
+        lexer.set_mode_stack(&saved.1);
+        assert_eq!((lexer.mode, lexer.mode_stack()), (INSIDE, &[][..]));
+    }
+
+    #[test]
+    fn test_lexer_more_and_eof() {
+        use antlr_rust::TokenSource;
+        use xmllexer::*;
+
+        fn tokens(input: &str) -> Vec<(isize, String, isize, isize, usize)> {
+            let mut tokens = CommonTokenStream::new(XMLLexer::new(InputStream::new(input)));
+            tokens.fill().unwrap();
+            (0..tokens.size())
+                .map(|i| {
+                    let token = tokens.get(i);
+                    (
+                        token.get_token_type(),
+                        token.get_text().to_owned(),
+                        token.get_start(),
+                        token.get_stop(),
+                        token.get_mode(),
+                    )
+                })
+                .collect()
+        }
+
+        // `<?` and `.` are accumulated with `more` in PROC_INSTR mode until `?>`
+        let eof = |index: isize, mode| (TOKEN_EOF, "<EOF>".to_owned(), index, index - 1, mode);
+        assert_eq!(
+            tokens("<?pi d?>t"),
+            [
+                (PI, "<?pi d?>".to_owned(), 0, 7, 0),
+                (TEXT, "t".to_owned(), 8, 8, 0),
+                eof(9, 0),
+            ]
+        );
+        // unterminated instruction does not produce a token, EOF is still the only one
+        assert_eq!(
+            tokens("t<?pi d"),
+            [(TEXT, "t".to_owned(), 0, 0, 0), eof(7, PROC_INSTR)]
+        );
+        assert_eq!(tokens("<?p"), [eof(3, PROC_INSTR)]);
+        assert_eq!(tokens(""), [eof(0, 0)]);
+
+        let mut lexer = XMLLexer::new(InputStream::new("<?p"));
+        for _ in 0..2 {
+            let token = lexer.next_token();
+            assert_eq!((token.get_token_type(), token.get_start()), (TOKEN_EOF, 3));
+            assert_eq!((token.get_line(), token.get_column()), (1, 3));
+        }
+    }
+
+    #[test]
+    fn test_token_end_position() {
+        use antlr_rust::TokenSource;
+        use xmllexer::*;
+
+        let mut lexer = XMLLexer::new(InputStream::new("<!--a\nbc-->x\n"));
+        let comment = lexer.next_token();
+        assert_eq!(comment.get_token_type(), COMMENT);
+        assert_eq!((comment.get_line(), comment.get_column()), (1, 0));
+        assert_eq!((comment.get_end_line(), comment.get_end_column()), (2, 5));
+        let owned = comment.to_owned();
+        assert_eq!((owned.get_end_line(), owned.get_end_column()), (2, 5));
+
+        // text ending with line terminator ends at the start of the next line
+        let text = lexer.next_token();
+        assert_eq!((text.get_token_type(), text.get_text()), (TEXT, "x\n"));
+        assert_eq!((text.get_line(), text.get_column()), (2, 5));
+        assert_eq!((text.get_end_line(), text.get_end_column()), (3, 0));
+        let eof = lexer.next_token();
+        assert_eq!((eof.get_line(), eof.get_column()), (3, 0));
+        assert_eq!((eof.get_end_line(), eof.get_end_column()), (3, 0));
+    }
+
+    #[test]
+    fn test_registered_lexer_actions() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        use antlr_rust::TokenSource;
+        use xmllexer::*;
+
+        // indexes of COMMENT and CLOSE in `ruleNames`
+        const RULE_COMMENT: isize = 0;
+        const RULE_CLOSE: isize = 10;
+
+        fn types<'a>(lexer: &mut XMLLexer<'a, InputStream<&'a str>>) -> Vec<isize> {
+            let mut types = Vec::new();
+            loop {
+                let token_type = lexer.next_token().get_token_type();
+                types.push(token_type);
+                if token_type == TOKEN_EOF {
+                    return types;
+                }
+            }
+        }
+
+        let actions = Rc::new(Cell::new(0));
+        let mut lexer = XMLLexer::new(InputStream::new("<!--c--><a>t"));
+        let calls = actions.clone();
+        lexer.register_action(RULE_CLOSE, move |lexer, action_index| {
+            assert_eq!(action_index, 0);
+            calls.set(calls.get() + 1);
+            lexer.pop_mode();
+        });
+        let predicates = Rc::new(Cell::new(0));
+        let calls = predicates.clone();
+        lexer.register_sempred(RULE_COMMENT, move |_, _| {
+            calls.set(calls.get() + 1);
+            true
+        });
+        assert_eq!(
+            types(&mut lexer),
+            [COMMENT, OPEN, Name, CLOSE, TEXT, TOKEN_EOF]
+        );
+        assert_eq!(actions.get(), 1);
+        assert!(predicates.get() > 0);
+
+        let mut lexer = XMLLexer::new(InputStream::new("<!--c-->"));
+        lexer.remove_error_listeners();
+        lexer.register_sempred(RULE_COMMENT, |_, _| false);
+        assert_ne!(lexer.next_token().get_token_type(), COMMENT);
+    }
+
+    #[test]
+    fn test_token_transformer() {
+        use antlr_rust::token::TOKEN_HIDDEN_CHANNEL;
+        use antlr_rust::TokenSource;
+
+        const KEYWORD: isize = 100;
+
+        let tf = ArenaCommonFactory::default();
+        let mut lexer = CSVLexer::new_with_token_factory(InputStream::new("select,#x,b\n"), &tf);
+        lexer.set_token_transformer(
+            |token_type: &mut isize, channel: &mut isize, text: &str| -> Option<String> {
+                if *token_type != TEXT {
+                    None
+                } else if text == "select" {
+                    *token_type = KEYWORD;
+                    None
+                } else if text.starts_with('#') {
+                    *channel = TOKEN_HIDDEN_CHANNEL;
+                    Some(text[1..].to_owned())
+                } else {
+                    None
+                }
+            },
+        );
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            tokens.push((
+                token.get_token_type(),
+                token.get_channel(),
+                token.get_text().to_owned(),
+            ));
+            if token.get_token_type() == TOKEN_EOF {
+                break;
+            }
+        }
+        let expected = [
+            (KEYWORD, 0, "select"),
+            (T__0, 0, ","),
+            (TEXT, TOKEN_HIDDEN_CHANNEL, "x"),
+            (T__0, 0, ","),
+            (TEXT, 0, "b"),
+            (T__2, 0, "\n"),
+            (TOKEN_EOF, 0, "<EOF>"),
+        ];
+        let expected = expected
+            .iter()
+            .map(|&(token_type, channel, text)| (token_type, channel, text.to_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, expected);
+
+        lexer.remove_token_transformer();
+        lexer.reset();
+        assert_eq!(lexer.next_token().get_token_type(), TEXT);
+    }
+
+    #[test]
+    fn test_lexer_error_strategy() {
+        use std::rc::Rc;
+
+        use antlr_rust::error_listener::CollectingErrorListener;
+        use antlr_rust::lexer::LexerRecovery;
+        use antlr_rust::token::TOKEN_HIDDEN_CHANNEL;
+        use antlr_rust::TokenSource;
+
+        const ERROR: isize = 100;
+
+        // unterminated string fails at EOF after consuming `"b\n`
+        let tokens = |recovery: LexerRecovery| {
+            let tf = ArenaCommonFactory::default();
+            let mut lexer = CSVLexer::new_with_token_factory(InputStream::new("a,\"b\n"), &tf);
+            let listener = Rc::new(CollectingErrorListener::new());
+            lexer.remove_error_listeners();
+            lexer.add_error_listener(listener.clone());
+            lexer.set_error_strategy(recovery);
+            let mut tokens = Vec::new();
+            loop {
+                let token = lexer.next_token();
+                let token_type = token.get_token_type();
+                tokens.push((
+                    token_type,
+                    token.get_channel(),
+                    token.get_start(),
+                    token.get_stop(),
+                ));
+                if token_type == TOKEN_EOF {
+                    break;
+                }
+            }
+            let errors = listener.take_diagnostics();
+            assert_eq!(errors.len(), 1);
+            assert_eq!((errors[0].line, errors[0].column), (1, 2));
+            tokens
+        };
+
+        assert_eq!(
+            tokens(LexerRecovery::Skip),
+            [(TEXT, 0, 0, 0), (T__0, 0, 1, 1), (TOKEN_EOF, 0, 5, 4)]
+        );
+        assert_eq!(
+            tokens(LexerRecovery::SkipChar),
+            [
+                (TEXT, 0, 0, 0),
+                (T__0, 0, 1, 1),
+                (TEXT, 0, 3, 3),
+                (T__2, 0, 4, 4),
+                (TOKEN_EOF, 0, 5, 4)
+            ]
+        );
+        let recovery = LexerRecovery::ErrorToken {
+            token_type: ERROR,
+            channel: TOKEN_HIDDEN_CHANNEL,
+        };
+        assert_eq!(
+            tokens(recovery),
+            [
+                (TEXT, 0, 0, 0),
+                (T__0, 0, 1, 1),
+                (ERROR, TOKEN_HIDDEN_CHANNEL, 2, 2),
+                (TEXT, 0, 3, 3),
+                (T__2, 0, 4, 4),
+                (TOKEN_EOF, 0, 5, 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lexer_interpreter() {
+        use std::sync::Arc;
+
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::lexer_interpreter::{LexerGrammar, LexerInterpreter};
+        use antlr_rust::vocabulary::VocabularyImpl;
+        use antlr_rust::TokenSource;
+
+        // serialized ATN of the CSV lexer, same as in generated `csvlexer`
+        const CSV_LEXER_ATN: &str =
+            "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x02\
+             \x08\x2c\x08\x01\x04\x02\x09\x02\x04\x03\x09\x03\x04\x04\x09\x04\x04\x05\
+             \x09\x05\x04\x06\x09\x06\x04\x07\x09\x07\x03\x02\x03\x02\x03\x03\x03\x03\
+             \x03\x04\x03\x04\x03\x05\x06\x05\x17\x0a\x05\x0d\x05\x0e\x05\x18\x03\x05\
+             \x03\x05\x03\x06\x06\x06\x1e\x0a\x06\x0d\x06\x0e\x06\x1f\x03\x07\x03\x07\
+             \x03\x07\x03\x07\x07\x07\x26\x0a\x07\x0c\x07\x0e\x07\x29\x0b\x07\x03\x07\
+             \x03\x07\x02\x02\x08\x03\x03\x05\x04\x07\x05\x09\x06\x0b\x07\x0d\x08\x03\
+             \x02\x05\x03\x02\x22\x22\x07\x02\x0c\x0c\x0f\x0f\x22\x22\x24\x24\x2e\x2e\
+             \x03\x02\x24\x24\x02\x2f\x02\x03\x03\x02\x02\x02\x02\x05\x03\x02\x02\x02\
+             \x02\x07\x03\x02\x02\x02\x02\x09\x03\x02\x02\x02\x02\x0b\x03\x02\x02\x02\
+             \x02\x0d\x03\x02\x02\x02\x03\x0f\x03\x02\x02\x02\x05\x11\x03\x02\x02\x02\
+             \x07\x13\x03\x02\x02\x02\x09\x16\x03\x02\x02\x02\x0b\x1d\x03\x02\x02\x02\
+             \x0d\x21\x03\x02\x02\x02\x0f\x10\x07\x2e\x02\x02\x10\x04\x03\x02\x02\x02\
+             \x11\x12\x07\x0f\x02\x02\x12\x06\x03\x02\x02\x02\x13\x14\x07\x0c\x02\x02\
+             \x14\x08\x03\x02\x02\x02\x15\x17\x09\x02\x02\x02\x16\x15\x03\x02\x02\x02\
+             \x17\x18\x03\x02\x02\x02\x18\x16\x03\x02\x02\x02\x18\x19\x03\x02\x02\x02\
+             \x19\x1a\x03\x02\x02\x02\x1a\x1b\x08\x05\x02\x02\x1b\x0a\x03\x02\x02\x02\
+             \x1c\x1e\x0a\x03\x02\x02\x1d\x1c\x03\x02\x02\x02\x1e\x1f\x03\x02\x02\x02\
+             \x1f\x1d\x03\x02\x02\x02\x1f\x20\x03\x02\x02\x02\x20\x0c\x03\x02\x02\x02\
+             \x21\x27\x07\x24\x02\x02\x22\x23\x07\x24\x02\x02\x23\x26\x07\x24\x02\x02\
+             \x24\x26\x0a\x04\x02\x02\x25\x22\x03\x02\x02\x02\x25\x24\x03\x02\x02\x02\
+             \x26\x29\x03\x02\x02\x02\x27\x25\x03\x02\x02\x02\x27\x28\x03\x02\x02\x02\
+             \x28\x2a\x03\x02\x02\x02\x29\x27\x03\x02\x02\x02\x2a\x2b\x07\x24\x02\x02\
+             \x2b\x0e\x03\x02\x02\x02\x07\x02\x18\x1f\x25\x27\x03\x02\x03\x02";
+
+        let atn = Arc::new(ATNDeserializer::new(None).deserialize(CSV_LEXER_ATN.chars()));
+        let vocabulary = VocabularyImpl::new(
+            csvlexer::_LITERAL_NAMES.iter(),
+            csvlexer::_SYMBOLIC_NAMES.iter(),
+            None,
+        );
+        let grammar = LexerGrammar::new(atn, vocabulary)
+            .with_grammar_file_name("CSV.g4")
+            .with_rule_names(&csvlexer::ruleNames)
+            .with_channel_names(&csvlexer::channelNames)
+            .with_mode_names(&csvlexer::modeNames);
+
+        let input = "a, \"b\"\r\nc,d\n";
+        let mut expected = Vec::new();
+        let tf = ArenaCommonFactory::default();
+        let mut lexer = CSVLexer::new_with_token_factory(InputStream::new(input), &tf);
+        loop {
+            let token = lexer.next_token();
+            let text = token.get_text().to_owned();
+            expected.push((token.get_token_type(), token.get_channel(), text));
+            if token.get_token_type() == TOKEN_EOF {
+                break;
+            }
+        }
+
+        let mut interpreter: LexerInterpreter<'_, _> =
+            LexerInterpreter::new(grammar.clone(), InputStream::new(input));
+        let mut tokens = Vec::new();
+        loop {
+            let token = interpreter.next_token();
+            let text = token.get_text().to_owned();
+            tokens.push((token.get_token_type(), token.get_channel(), text));
+            if token.get_token_type() == TOKEN_EOF {
+                break;
+            }
+        }
+        assert_eq!(tokens, expected);
+        assert_eq!(interpreter.current_mode_name(), Some("DEFAULT_MODE"));
+        let vocabulary = interpreter.get_grammar().get_vocabulary();
+        assert_eq!(vocabulary.get_symbolic_name(STRING), Some("STRING"));
+
+        let mut interpreter: LexerInterpreter<'_, _> =
+            LexerInterpreter::new(grammar.clone(), InputStream::new("x"));
+        assert_eq!(interpreter.next_token().get_token_type(), TEXT);
+
+        // interpreted tokens are accepted by generated parser
+        let tf = ArenaCommonFactory::default();
+        let lexer = LexerInterpreter::new_with_token_factory(grammar, InputStream::new(input), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let interpreted = parser.csvFile().unwrap().to_string_tree(&*parser);
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        assert_eq!(
+            interpreted,
+            parser.csvFile().unwrap().to_string_tree(&*parser)
+        );
+    }
+
+    #[test]
+    fn test_antlr_4_10_serialized_atn() {
+        use std::borrow::Borrow;
+        use std::sync::Arc;
+
+        use antlr_rust::atn_deserializer::{ATNDeserializer, SerializedATN};
+        use antlr_rust::lexer_interpreter::{LexerGrammar, LexerInterpreter};
+        use antlr_rust::token_factory::TokenFactory;
+        use antlr_rust::vocabulary::VocabularyImpl;
+        use antlr_rust::TokenSource;
+
+        // serialized ATN of the CSV lexer in the format of ANTLR 4.10+: no UUID, values are
+        // stored as is and all sets are in a single list, -1 is the rule of token start states
+        #[rustfmt::skip]
+        const CSV_LEXER_ATN: &[i32] = &[
+            4, 0, 6, 42, 6, -1, 2, 0, 7, 0, 2, 1, 7, 1, 2, 2, 7, 2, 2, 3, 7, 3, 2, 4, 7, 4, 2, 5,
+            7, 5, 1, 0, 1, 0, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 4, 3, 21, 8, 3, 11, 3, 12, 3, 22, 1, 3,
+            1, 3, 1, 4, 4, 4, 28, 8, 4, 11, 4, 12, 4, 29, 1, 5, 1, 5, 1, 5, 1, 5, 5, 5, 36, 8, 5,
+            10, 5, 12, 5, 39, 9, 5, 1, 5, 1, 5, 0, 0, 6, 1, 1, 3, 2, 5, 3, 7, 4, 9, 5, 11, 6, 1, 0,
+            3, 1, 0, 32, 32, 5, 0, 10, 10, 13, 13, 32, 32, 34, 34, 44, 44, 1, 0, 34, 34, 45, 0, 1,
+            1, 0, 0, 0, 0, 3, 1, 0, 0, 0, 0, 5, 1, 0, 0, 0, 0, 7, 1, 0, 0, 0, 0, 9, 1, 0, 0, 0, 0,
+            11, 1, 0, 0, 0, 1, 13, 1, 0, 0, 0, 3, 15, 1, 0, 0, 0, 5, 17, 1, 0, 0, 0, 7, 20, 1, 0,
+            0, 0, 9, 27, 1, 0, 0, 0, 11, 31, 1, 0, 0, 0, 13, 14, 5, 44, 0, 0, 14, 2, 1, 0, 0, 0,
+            15, 16, 5, 13, 0, 0, 16, 4, 1, 0, 0, 0, 17, 18, 5, 10, 0, 0, 18, 6, 1, 0, 0, 0, 19, 21,
+            7, 0, 0, 0, 20, 19, 1, 0, 0, 0, 21, 22, 1, 0, 0, 0, 22, 20, 1, 0, 0, 0, 22, 23, 1, 0,
+            0, 0, 23, 24, 1, 0, 0, 0, 24, 25, 6, 3, 0, 0, 25, 8, 1, 0, 0, 0, 26, 28, 8, 1, 0, 0,
+            27, 26, 1, 0, 0, 0, 28, 29, 1, 0, 0, 0, 29, 27, 1, 0, 0, 0, 29, 30, 1, 0, 0, 0, 30, 10,
+            1, 0, 0, 0, 31, 37, 5, 34, 0, 0, 32, 33, 5, 34, 0, 0, 33, 36, 5, 34, 0, 0, 34, 36, 8,
+            2, 0, 0, 35, 32, 1, 0, 0, 0, 35, 34, 1, 0, 0, 0, 36, 39, 1, 0, 0, 0, 37, 35, 1, 0, 0,
+            0, 37, 38, 1, 0, 0, 0, 38, 40, 1, 0, 0, 0, 39, 37, 1, 0, 0, 0, 40, 41, 5, 34, 0, 0, 41,
+            12, 1, 0, 0, 0, 5, 0, 22, 29, 35, 37, 1, 0, 1, 0,
+        ];
+        // string and UTF-16 targets store values as 16-bit words, -1 takes two of them
+        let words: &'static [u16] = Box::leak(
+            CSV_LEXER_ATN
+                .iter()
+                .flat_map(|&it| {
+                    if it == -1 {
+                        vec![0xFFFF, 0xFFFF]
+                    } else {
+                        vec![it as u16]
+                    }
+                })
+                .collect(),
+        );
+
+        let input = "a, \"b\"\r\nc,d\n";
+        fn lex_all<'a, T>(lexer: &mut T) -> Vec<(isize, isize, String)>
+        where
+            T: TokenSource<'a>,
+            T::TF: TokenFactory<'a, Data = str>,
+        {
+            let mut tokens = Vec::new();
+            loop {
+                let token = lexer.next_token();
+                let token: &<T::TF as TokenFactory<'a>>::Inner = token.borrow();
+                let ttype = token.get_token_type();
+                tokens.push((ttype, token.get_channel(), token.get_text().to_string()));
+                if ttype == TOKEN_EOF {
+                    break tokens;
+                }
+            }
+        }
+        let tf = ArenaCommonFactory::default();
+        let expected = lex_all(&mut CSVLexer::new_with_token_factory(
+            InputStream::new(input),
+            &tf,
+        ));
+        for data in &[
+            SerializedATN::from(CSV_LEXER_ATN),
+            SerializedATN::from(words),
+        ] {
+            let atn = ATNDeserializer::new(None).deserialize_atn(data);
+            let vocabulary = VocabularyImpl::new(
+                csvlexer::_LITERAL_NAMES.iter(),
+                csvlexer::_SYMBOLIC_NAMES.iter(),
+                None,
+            );
+            let grammar = LexerGrammar::new(Arc::new(atn), vocabulary)
+                .with_rule_names(&csvlexer::ruleNames)
+                .with_mode_names(&csvlexer::modeNames);
+            let mut interpreter: LexerInterpreter<'_, _> =
+                LexerInterpreter::new(grammar, InputStream::new(input));
+            assert_eq!(lex_all(&mut interpreter), expected);
+        }
+    }
+
+    #[test]
+    fn test_stream_reset() {
+        use antlr_rust::TokenSource;
+
+        fn lex_all<'a>(lexer: &mut XMLLexer<'a, InputStream<&'a str>>) -> Vec<[isize; 4]> {
+            let mut tokens = Vec::new();
+            loop {
+                let token = lexer.next_token();
+                let ttype = token.get_token_type();
+                tokens.push([
+                    ttype,
+                    token.get_start(),
+                    token.get_line(),
+                    token.get_column(),
+                ]);
+                if ttype == TOKEN_EOF {
+                    break tokens;
+                }
+            }
+        }
+        let input = "<a x=\"1\">\n<?pi data?>t</a>";
+        let expected = lex_all(&mut XMLLexer::new(InputStream::new(input)));
+
+        // reset in the middle of the input inside the tag, in non default mode
+        let mut lexer = XMLLexer::new(InputStream::new(input));
+        for _ in 0..3 {
+            lexer.next_token();
+        }
+        assert!(!lexer.mode_stack().is_empty());
+        lexer.reset();
+        assert!(lexer.mode_stack().is_empty());
+        assert_eq!(lex_all(&mut lexer), expected);
+        lexer.reset();
+        assert_eq!(lex_all(&mut lexer), expected);
+        // pending `scan_for` is dropped too
+        lexer.scan_for(&[xmllexer::COMMENT]);
+        lexer.reset();
+        assert_eq!(lex_all(&mut lexer), expected);
+
+        // stream is restarted at the first token on its channel
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(" a,b\n"), &tf);
+        let mut tokens = CommonTokenStream::new(lexer);
+        assert_eq!(tokens.index(), 1);
+        tokens.consume();
+        tokens.consume();
+        tokens.reset();
+        assert_eq!((tokens.index(), tokens.la(1)), (1, TEXT));
+        tokens.seek(0);
+        assert_eq!((tokens.index(), tokens.la(1)), (1, TEXT));
+    }
+
+    #[test]
+    fn test_hidden_tokens() {
+        use antlr_rust::token::{TOKEN_DEFAULT_CHANNEL, TOKEN_HIDDEN_CHANNEL};
+
+        let tf = ArenaCommonFactory::default();
+        // tokens are `a` `,` ` ` `b` `  ` `,` `c` `\n` EOF
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a, b  ,c\n"), &tf);
+        let mut tokens = CommonTokenStream::new(lexer);
+        let mut hidden = |index: isize, right: bool, channel: Option<isize>| {
+            let found = if right {
+                tokens.get_hidden_tokens_to_right(index, channel)
+            } else {
+                tokens.get_hidden_tokens_to_left(index, channel)
+            };
+            found
+                .iter()
+                .map(|it| (it.get_token_index(), it.get_text().to_owned()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hidden(3, false, None), vec![(2, " ".to_owned())]);
+        assert_eq!(hidden(3, true, None), vec![(4, "  ".to_owned())]);
+        assert_eq!(hidden(5, false, None), vec![(4, "  ".to_owned())]);
+        assert_eq!(
+            hidden(1, true, Some(TOKEN_HIDDEN_CHANNEL)),
+            vec![(2, " ".to_owned())]
+        );
+        assert_eq!(hidden(1, true, Some(5)), vec![]);
+        // the next token on the stream channel is not hidden
+        assert_eq!(hidden(1, true, Some(TOKEN_DEFAULT_CHANNEL)), vec![]);
+        assert_eq!(hidden(0, false, None), vec![]);
+        assert_eq!(hidden(0, true, None), vec![]);
+        assert_eq!(hidden(8, true, None), vec![]);
+        assert_eq!(tokens.la(1), TEXT);
+    }
+
+    #[test]
+    fn test_text_from_context() {
+        use antlr_rust::parser_rule_context::ParserRuleContext;
+        use csvparser::CsvFileContextAttrs;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("h1 ,h2\na,  b\n"), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let root = parser.csvFile().unwrap();
+        let row = root.row(0).unwrap();
+        assert_eq!(row.get_text(), "a,b\n");
+        assert_eq!(parser.input.get_text_from_context(&*row), "a,  b\n");
+        assert_eq!(
+            parser.input.get_text_from_context(&*root.hdr().unwrap()),
+            "h1 ,h2\n"
+        );
+        assert_eq!(
+            parser.input.get_text_from_context(&*root),
+            "h1 ,h2\na,  b\n"
+        );
+        let (start, stop) = (row.start(), row.stop());
+        assert_eq!(
+            parser.input.get_text_from_tokens(&*start, &*stop),
+            "a,  b\n"
+        );
+    }
+
+    #[test]
+    fn test_multi_channel_token_stream() {
+        use antlr_rust::int_stream::check_stream_contract;
+        use antlr_rust::multi_channel_token_stream::MultiChannelTokenStream;
+        use antlr_rust::token::{TOKEN_DEFAULT_CHANNEL, TOKEN_HIDDEN_CHANNEL};
+
+        let tf = ArenaCommonFactory::default();
+        // tokens are `a` `,` ` ` `b` ` ` `,` `c` `\n` EOF
+        let lexer = || CSVLexer::new_with_token_factory(InputStream::new("a, b ,c\n"), &tf);
+        let all = [TOKEN_DEFAULT_CHANNEL, TOKEN_HIDDEN_CHANNEL];
+        let symbols = [TEXT, T__0, WS, TEXT, WS, T__0, TEXT, T__2];
+        check_stream_contract(
+            &mut MultiChannelTokenStream::with_channels(lexer(), &all),
+            &symbols,
+        );
+
+        let mut tokens = MultiChannelTokenStream::new(lexer());
+        tokens.consume();
+        tokens.consume();
+        assert_eq!((tokens.index(), tokens.la(1)), (3, TEXT));
+        tokens.seek(3);
+        // whitespace skipped after the last consumed token becomes visible
+        tokens.enable_channel(TOKEN_HIDDEN_CHANNEL);
+        assert!(tokens.is_channel_enabled(TOKEN_HIDDEN_CHANNEL));
+        assert_eq!((tokens.index(), tokens.la(1), tokens.la(2)), (2, WS, TEXT));
+        tokens.consume();
+        tokens.consume();
+        assert_eq!((tokens.index(), tokens.la(1)), (4, WS));
+        tokens.disable_channel(TOKEN_HIDDEN_CHANNEL);
+        assert_eq!(tokens.enabled_channels(), &[TOKEN_DEFAULT_CHANNEL]);
+        assert_eq!(
+            (tokens.index(), tokens.la(1), tokens.la(-1)),
+            (5, T__0, TEXT)
+        );
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a, b\n c\n"), &tf);
+        let mut parser = CSVParser::new(MultiChannelTokenStream::new(lexer));
+        let root = parser.csvFile().unwrap();
+        assert_eq!(root.get_text(), "a,b\nc\n");
+    }
+
+    #[test]
+    fn test_peek_range() {
+        use antlr_rust::token::CommonToken;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a, b\n"), &tf);
+        let mut tokens = CommonTokenStream::new(lexer);
+        tokens.consume();
+        let types = |range: &[&CommonToken<'_>]| {
+            range
+                .iter()
+                .map(|it| it.get_token_type())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(types(tokens.peek_range(1, 4)), vec![T__0, WS, TEXT, T__2]);
+        assert_eq!(tokens.peek_range(4, 10)[1].get_token_type(), TOKEN_EOF);
+        assert_eq!(tokens.peek_range(4, 10).len(), 2);
+        assert!(tokens.peek_range(3, 2).is_empty());
+        assert_eq!(tokens.peek_range(0, 0)[0].get_text(), "a");
+        assert_eq!((tokens.index(), tokens.la(1)), (1, T__0));
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\n"), &tf);
+        let mut tokens = UnbufferedTokenStream::new_unbuffered(lexer);
+        tokens.consume();
+        assert_eq!(types(tokens.peek_range(1, 2)), vec![T__0, TEXT]);
+        tokens.consume();
+        assert_eq!(types(tokens.peek_range(2, 5)), vec![TEXT, T__2, TOKEN_EOF]);
+    }
+
+    #[test]
+    fn test_token_buffer_access() {
+        use antlr_rust::token::CommonToken;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a, b\n"), &tf);
+        let mut tokens = CommonTokenStream::new(lexer);
+        tokens.consume();
+        assert_eq!(tokens.get_tokens().len(), 2);
+        tokens.fill().unwrap();
+        let all = tokens
+            .get_tokens()
+            .iter()
+            .map(|it| (it.get_token_index(), it.get_text()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            all,
+            vec![
+                (0, "a"),
+                (1, ","),
+                (2, " "),
+                (3, "b"),
+                (4, "\n"),
+                (5, "<EOF>")
+            ]
+        );
+        let texts =
+            |range: &[&CommonToken<'_>]| range.iter().map(|it| it.get_text()).collect::<String>();
+        assert_eq!(texts(tokens.peek_range(1, 3)), ", b");
+        assert_eq!(texts(tokens.peek_range(4, 10)), "\n<EOF>");
+        assert!(tokens.peek_range(3, 2).is_empty());
+        assert_eq!(tokens.get(3).get_text(), "b");
+        assert_eq!((tokens.index(), tokens.la(1)), (1, T__0));
+    }
+
+    #[test]
+    fn test_token_source_decorators() {
+        use antlr_rust::token_source::TokenSourceExt;
+        use antlr_rust::TokenSource;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b,\n"), &tf);
+        let source = lexer.filter_tokens(|token| token.get_token_type() != T__0);
+        let mut tokens = CommonTokenStream::new(source);
+        let types = tokens.iter().collect::<Vec<_>>();
+        assert_eq!(types, vec![TEXT, TEXT, T__2]);
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a, b\n"), &tf);
+        let mut source: Box<dyn TokenSource<'_, TF = LocalTokenFactory<'_>> + '_> = Box::new(lexer);
+        // only visible tokens are left
+        source = Box::new(source.filter_tokens(|token| token.get_channel() == 0));
+        let mut tokens = CommonTokenStream::new(source);
+        assert_eq!(
+            tokens.iter().collect::<Vec<_>>(),
+            vec![TEXT, T__0, TEXT, T__2]
+        );
+        assert_eq!(tokens.get(3).get_token_index(), 3);
+
+        // filtered source can be parsed
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a, b\nc\n"), &tf);
+        let source = lexer.filter_tokens(|token| token.get_channel() == 0);
+        let mut parser = CSVParser::new(CommonTokenStream::new(source));
+        let tree = parser.csvFile().unwrap().to_string_tree(&*parser);
+        assert_eq!(
+            tree,
+            "(csvFile (hdr (row (field a) , (field b) \\n)) (row (field c) \\n))"
+        );
+        assert_eq!(parser.input.get(2).get_text(), "b");
+    }
+
+    #[test]
+    fn test_indentation_token_source() {
+        use antlr_rust::indentation_token_source::IndentationAwareTokenSource;
+        use antlr_rust::token::TOKEN_HIDDEN_CHANNEL;
+        use antlr_rust::TokenSource;
+        use csvparser::CsvFileContextAttrs;
+
+        const INDENT: isize = 100;
+        const DEDENT: isize = 101;
+        let tf = ArenaCommonFactory::default();
+        let tokens = |text| {
+            let lexer = CSVLexer::new_with_token_factory(InputStream::new(text), &tf);
+            let mut source = IndentationAwareTokenSource::new(lexer, T__2, INDENT, DEDENT);
+            let mut tokens = Vec::new();
+            loop {
+                let token = source.next_token();
+                if token.get_channel() == 0 {
+                    tokens.push(token.get_token_type());
+                } else if token.get_token_type() == T__2 {
+                    // blank lines are hidden
+                    tokens.push(-TOKEN_HIDDEN_CHANNEL);
+                }
+                if token.get_token_type() == TOKEN_EOF {
+                    return tokens;
+                }
+            }
+        };
+        assert_eq!(
+            tokens("\na\n  b\n\n c\nd"),
+            vec![
+                -1, TEXT, T__2, INDENT, TEXT, T__2, -1, DEDENT, INDENT, TEXT, T__2, DEDENT, TEXT,
+                T__2, TOKEN_EOF
+            ]
+        );
+        assert_eq!(
+            tokens("a\n  b\n   c\n"),
+            vec![TEXT, T__2, INDENT, TEXT, T__2, INDENT, TEXT, T__2, DEDENT, DEDENT, TOKEN_EOF]
+        );
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a\n b\n"), &tf);
+        let source = IndentationAwareTokenSource::new(lexer, T__2, INDENT, DEDENT);
+        let mut tokens = CommonTokenStream::new(source);
+        tokens.seek(3);
+        let indent = tokens.lt(1).unwrap();
+        assert_eq!((indent.get_token_type(), indent.get_text()), (INDENT, ""));
+        assert_eq!((indent.get_line(), indent.get_column()), (2, 1));
+
+        // newline is added at the end of the last line, so it is a complete row
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a\nb"), &tf);
+        let source = IndentationAwareTokenSource::new(lexer, T__2, INDENT, DEDENT);
+        let mut parser = CSVParser::new(CommonTokenStream::new(source));
+        let root = parser.csvFile().unwrap();
+        assert_eq!(root.row_all().len(), 1);
+        assert_eq!(root.row(0).unwrap().get_text(), "b");
+    }
+
+    #[test]
+    fn test_position_encoding() {
+        use antlr_rust::line_index::PositionEncoding;
+        use antlr_rust::TokenSource;
+
+        let tf = ArenaCommonFactory::default();
+        let columns = |encoding| {
+            let mut lexer = CSVLexer::new_with_token_factory(InputStream::new("é😀,x\n,y"), &tf);
+            lexer.set_position_encoding(encoding);
+            let mut columns = Vec::new();
+            loop {
+                let token = lexer.next_token();
+                columns.push((token.get_line(), token.get_column()));
+                if token.get_token_type() == TOKEN_EOF {
+                    return columns;
+                }
+            }
+        };
+        assert_eq!(
+            columns(PositionEncoding::CodePoints),
+            vec![(1, 0), (1, 2), (1, 3), (1, 4), (2, 0), (2, 1), (2, 2)]
+        );
+        assert_eq!(
+            columns(PositionEncoding::Utf16),
+            vec![(1, 0), (1, 3), (1, 4), (1, 5), (2, 0), (2, 1), (2, 2)]
+        );
+        assert_eq!(
+            columns(PositionEncoding::Utf8),
+            vec![(1, 0), (1, 6), (1, 7), (1, 8), (2, 0), (2, 1), (2, 2)]
+        );
+    }
+
+    #[test]
+    fn test_newline_mode() {
+        use antlr_rust::line_index::NewlineMode;
+        use antlr_rust::TokenSource;
+
+        let tf = ArenaCommonFactory::default();
+        let positions = |mode| {
+            let mut lexer = CSVLexer::new_with_token_factory(InputStream::new("a\r\nb"), &tf);
+            lexer.set_newline_mode(mode);
+            let mut positions = Vec::new();
+            loop {
+                let token = lexer.next_token();
+                positions.push((token.get_start(), token.get_line(), token.get_column()));
+                if token.get_token_type() == TOKEN_EOF {
+                    return positions;
+                }
+            }
+        };
+        assert_eq!(
+            positions(NewlineMode::Lf),
+            vec![(0, 1, 0), (1, 1, 1), (2, 1, 2), (3, 2, 0), (4, 2, 1)]
+        );
+        assert_eq!(
+            positions(NewlineMode::Any),
+            vec![(0, 1, 0), (1, 1, 1), (2, 1, 1), (3, 2, 0), (4, 2, 1)]
+        );
+    }
+
+    #[test]
+    fn test_lexer_set_line() {
+        use antlr_rust::lexer_atn_simulator::ILexerATNSimulator;
+        use antlr_rust::TokenSource;
+
+        let tf = ArenaCommonFactory::default();
+        let mut lexer = CSVLexer::new_with_token_factory(InputStream::new("ab,c"), &tf);
+        assert_eq!(lexer.next_token().get_text(), "ab");
+        let interpreter = lexer.interpreter.as_mut().unwrap();
+        assert_eq!(
+            (
+                interpreter.get_line(),
+                interpreter.get_char_position_in_line()
+            ),
+            (1, 2)
+        );
+        // only the line changes, column stays the same
+        interpreter.set_line(5);
+        assert_eq!(
+            (
+                interpreter.get_line(),
+                interpreter.get_char_position_in_line()
+            ),
+            (5, 2)
+        );
+        let token = lexer.next_token();
+        assert_eq!((token.get_line(), token.get_column()), (5, 2));
+    }
+
+    #[test]
+    fn test_token_type_map() {
+        use antlr_rust::token::TOKEN_INVALID_TYPE;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("".into()), &tf);
+        let parser = CSVParser::new(CommonTokenStream::new(lexer));
+
+        assert_eq!(parser.get_token_type("TEXT"), 5);
+        assert_eq!(parser.get_token_type("','"), 1);
+        assert_eq!(parser.get_token_type("EOF"), TOKEN_EOF);
+        assert_eq!(parser.get_token_type("text"), TOKEN_INVALID_TYPE);
+        assert_eq!(parser.get_token_type_ignore_case("text"), 5);
+        assert_eq!(parser.get_token_type_ignore_case("eof"), TOKEN_EOF);
+        assert!(parser.get_vocabulary_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_token_interval_to_chars() {
+        use antlr_rust::interval_set::{CharInterval, TokenInterval};
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("ab,\"x y\"\n".into()), &tf);
+        let mut tokens = CommonTokenStream::new(lexer);
+        while tokens.la(1) != TOKEN_EOF {
+            tokens.consume();
+        }
+        assert_eq!(tokens.size(), 5);
+
+        let chars = |a, b| TokenInterval::new(a, b).to_char_interval(&tokens);
+        assert_eq!(chars(0, 2), Some(CharInterval::new(0, 7)));
+        assert_eq!(chars(2, 2), Some(CharInterval::new(3, 7)));
+        // empty interval stays empty and points right before the token
+        assert_eq!(chars(2, 1), Some(CharInterval::new(3, 2)));
+        assert_eq!(chars(4, 4), Some(CharInterval::new(9, 8)));
+        assert_eq!(chars(3, 5), None);
+        assert_eq!(chars(-1, -2), None);
+
+        let text = tokens.get_text_from_interval(TokenInterval::new(0, 2));
+        assert_eq!(text, "ab,\"x y\"");
+    }
+
+    #[test]
+    fn test_internal_error_messages() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        fn panic_message(f: impl FnOnce()) -> String {
+            let payload = catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+            payload.downcast_ref::<String>().cloned().unwrap()
+        }
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b,c\n".into()), &tf);
+        let mut tokens = UnbufferedTokenStream::new_unbuffered(lexer);
+        let marker = tokens.mark();
+        for _ in 0..3 {
+            tokens.consume();
+        }
+        tokens.release(marker);
+        let message = panic_message(|| tokens.seek(0));
+        assert!(
+            message.starts_with("antlr-rust internal error: can't seek to token 0 outside"),
+            "{}",
+            message
+        );
+        #[cfg(feature = "debug-diagnostics")]
+        assert!(
+            message.ends_with("[current_index=3, buffer_start=3, markers=0]"),
+            "{}",
+            message
+        );
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let row = parser.row().unwrap();
+        let newline = row.get_child(1).unwrap();
+        let message = panic_message(|| {
+            newline.start();
+        });
+        assert!(
+            message.starts_with("start token of contexts that don't store it is not supported"),
+            "{}",
+            message
+        );
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn test_merged_vocabulary() {
+        use antlr_rust::testkit::MockTokenSource;
+        use antlr_rust::vocabulary::{RemappedTokenSource, Vocabulary, VocabularyImpl};
+
+        // vocabulary of a separately generated lexer for the same language
+        let other = VocabularyImpl::new(
+            [None, None, Some("','"), Some("'\n'"), None, None].iter(),
+            [
+                None,
+                Some("TEXT"),
+                None,
+                None,
+                Some("STRING"),
+                Some("COMMENT"),
+            ]
+            .iter(),
+            None,
+        );
+        let csv = VocabularyImpl::new(
+            csvparser::_LITERAL_NAMES.iter(),
+            csvparser::_SYMBOLIC_NAMES.iter(),
+            None,
+        );
+        let (merged, remap) = csv.merge(&other).unwrap();
+        assert_eq!(
+            (1..=5).map(|it| remap.map(it)).collect::<Vec<_>>(),
+            vec![TEXT, T__0, T__2, STRING, 7]
+        );
+        assert_eq!(merged.get_max_token_type(), 7);
+        assert_eq!(merged.get_symbolic_name(7), Some("COMMENT"));
+
+        let tf = ArenaCommonFactory::default();
+        let tokens = [(1, "a"), (2, ","), (4, "\"b\""), (3, "\n")];
+        let source = RemappedTokenSource::new(MockTokenSource::new(&tf).tokens(&tokens), remap);
+        let mut parser = CSVParser::new(CommonTokenStream::new(source));
+        let remapped = parser.csvFile().unwrap().to_string_tree(&*parser);
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,\"b\"\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let lexed = parser.csvFile().unwrap().to_string_tree(&*parser);
+        assert_eq!(remapped, lexed);
+    }
+
+    #[test]
+    fn test_remapped_lexer() {
+        use antlr_rust::vocabulary::{RemappedTokenSource, VocabularyImpl};
+
+        let csv = VocabularyImpl::new(
+            csvparser::_LITERAL_NAMES.iter(),
+            csvparser::_SYMBOLIC_NAMES.iter(),
+            None,
+        );
+        let (_, remap) = csv.merge(&csv).unwrap();
+        assert!(remap.is_identity());
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a,\"b\"\n";
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+        let source = RemappedTokenSource::new(lexer, remap);
+        let mut parser = CSVParser::new(CommonTokenStream::new(source));
+        let remapped = parser.csvFile().unwrap().to_string_tree(&*parser);
+
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let lexed = parser.csvFile().unwrap().to_string_tree(&*parser);
+        assert_eq!(remapped, lexed);
+    }
+
+    #[test]
+    fn test_tree_edits_trivia() {
+        use antlr_rust::interval_set::CharInterval;
+        use antlr_rust::tree_edits::{self, EditSet};
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a ,b\nc, d\n";
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let root = parser.csvFile().unwrap();
+
+        let first = root.get_child(0).unwrap().get_child(0).unwrap();
+        let a = first.get_child(0).unwrap();
+        let last = root.get_child(1).unwrap();
+        let d = last.get_child(2).unwrap();
+
+        // whitespace after the node is swallowed first
+        let edit = tree_edits::delete_with_trivia(&*a, &parser.input).unwrap();
+        assert_eq!(edit.range, CharInterval::new(0, 1));
+        assert_eq!(
+            tree_edits::delete(&*a).unwrap().range,
+            CharInterval::new(0, 0)
+        );
+        // otherwise whitespace before it
+        let edit = tree_edits::delete_with_trivia(&*d, &parser.input).unwrap();
+        assert_eq!(edit.range, CharInterval::new(7, 8));
+
+        let mut edits = EditSet::new();
+        edits
+            .add(tree_edits::delete_with_trivia(&*a, &parser.input).unwrap())
+            .unwrap();
+        edits
+            .add(tree_edits::delete_with_trivia(&*d, &parser.input).unwrap())
+            .unwrap();
+        edits
+            .add(tree_edits::insert_after(&*last, "e\n").unwrap())
+            .unwrap();
+        assert_eq!(edits.apply(input), ",b\nc,\ne\n");
+    }
+
+    #[test]
+    fn test_shared_token_buffer() {
+        use antlr_rust::int_stream::check_stream_contract;
+        use antlr_rust::interval_set::CharInterval;
+        use antlr_rust::token::TOKEN_HIDDEN_CHANNEL;
+        use antlr_rust::token_buffer::{TokenArena, TokenBuffer, TokenCursor};
+        use antlr_rust::tree_edits::{self, EditSet};
+        use std::rc::Rc;
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a, b\n";
+        let lexer = || CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+        let arena = TokenArena::default();
+        let mut cursor = TokenCursor::new(Rc::new(TokenBuffer::new(lexer(), &arena)));
+        check_stream_contract(&mut cursor, &[TEXT, T__0, TEXT, T__2]);
+
+        // tokens are `a` `,` ` ` `b` `\n` EOF
+        let buffer = Rc::new(TokenBuffer::new(lexer(), &arena));
+        let mut tokens = TokenCursor::new(buffer.clone());
+        assert_eq!(tokens.la(3), TEXT);
+        assert_eq!(buffer.len(), 4);
+
+        // whitespace was already fetched by the other cursor
+        let mut trivia = TokenCursor::with_channel(buffer.clone(), TOKEN_HIDDEN_CHANNEL);
+        assert_eq!((trivia.index(), trivia.la(1)), (2, WS));
+        assert_eq!(buffer.len(), 4);
+        assert!(std::ptr::eq(trivia.lt(1).unwrap(), buffer.get(2).unwrap()));
+
+        trivia.consume();
+        assert_eq!(
+            (trivia.index(), trivia.la(1), trivia.la(-1)),
+            (5, TOKEN_EOF, WS)
+        );
+        assert_eq!(buffer.len(), 6);
+        assert_eq!((tokens.index(), tokens.la(1)), (0, TEXT));
+        tokens.consume();
+        tokens.consume();
+        assert_eq!(
+            (tokens.index(), tokens.la(1), tokens.la(-1)),
+            (3, TEXT, T__0)
+        );
+        assert_eq!(tokens.get_all_text(), input);
+
+        let mut parser = CSVParser::new(TokenCursor::new(buffer.clone()));
+        let root = parser.csvFile().unwrap();
+        assert_eq!(
+            root.to_string_tree(&*parser),
+            "(csvFile (hdr (row (field a) , (field b) \\n)))"
+        );
+        assert_eq!(buffer.len(), 6);
+
+        let b = root.get_child(0).unwrap().get_child(0).unwrap().get_child(2).unwrap();
+        let edit = tree_edits::delete_with_trivia(&*b, &trivia).unwrap();
+        assert_eq!(edit.range, CharInterval::new(2, 3));
+        let mut edits = EditSet::new();
+        edits.add(edit).unwrap();
+        assert_eq!(edits.apply(input), "a,\n");
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn test_token_source_io_error() {
+        use antlr_rust::errors::ANTLRError;
+        use antlr_rust::parser::{ParseCollecting, Parser};
+        use antlr_rust::testkit::MockTokenSource;
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a,b\nc,d\n";
+        // fails `failures` times when asked for the token with index 5
+        let source = |failures| {
+            let first_tokens = [
+                (TEXT, "a"),
+                (T__0, ","),
+                (TEXT, "b"),
+                (T__2, "\n"),
+                (TEXT, "c"),
+            ];
+            let mut source = MockTokenSource::new(&tf).tokens(&first_tokens);
+            for _ in 0..failures {
+                source = source.fail_next("connection reset");
+            }
+            source.tokens(&[(T__0, ","), (TEXT, "d"), (T__2, "\n")])
+        };
+
+        // with deferral stream keeps error of infallible call until it is returned by the fallible one
+        let mut stream = UnbufferedTokenStream::new_buffered(source(2));
+        stream.set_defer_errors(true);
+        match stream.try_lt(6) {
+            Err(ANTLRError::Io(e)) => {
+                assert_eq!((e.token_index, e.line, e.column), (5, 2, 1));
+                assert_eq!(e.to_string(), "line 2:1 failed to read token 5");
+                let source = std::error::Error::source(&e).unwrap();
+                assert_eq!(source.to_string(), "connection reset");
+            }
+            r => panic!("expected io error, got {:?}", r.map(|t| t.cloned())),
+        }
+        assert_eq!(stream.la(5), TEXT);
+        assert_eq!(stream.la(6), TOKEN_EOF);
+        assert!(stream.try_lt(6).is_err());
+        assert_eq!(stream.try_lt(6).unwrap().unwrap().get_text(), ",");
+        assert_eq!(stream.try_lt(6).unwrap().unwrap().get_token_index(), 5);
+        assert_eq!(stream.index(), 0);
+
+        // without deferral infallible methods panic instead of truncating input
+        let mut stream = CommonTokenStream::new(source(1));
+        let mut types = Vec::new();
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            while stream.la(1) != TOKEN_EOF {
+                types.push(stream.la(1));
+                stream.consume();
+            }
+        }))
+        .unwrap_err();
+        assert_eq!(types, [TEXT, T__0, TEXT, T__2, TEXT]);
+        assert_eq!(
+            payload.downcast_ref::<String>().unwrap(),
+            "token source failed: line 2:1 failed to read token 5"
+        );
+        assert_eq!(stream.la(1), T__0);
+
+        // parser returns error from the rule without reporting syntax errors
+        let mut parser = CSVParser::new(CommonTokenStream::new(source(1)));
+        let (result, diagnostics) = parser.parse_collecting(|p| p.csvFile());
+        match result {
+            Err(ANTLRError::Io(e)) => assert_eq!(e.token_index, 5),
+            r => panic!("expected io error, got {:?}", r.map(|it| it.get_text())),
+        }
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        // retry after the failure parses the same way as if there was no error
+        parser.get_input_stream_mut().seek(0);
+        let (retried, diagnostics) = parser.parse_collecting(|p| p.csvFile());
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let retried = retried.unwrap().to_string_tree(&*parser);
+
+        let mut clean_parser = CSVParser::new(CommonTokenStream::new(source(0)));
+        let clean = clean_parser
+            .csvFile()
+            .unwrap()
+            .to_string_tree(&*clean_parser);
+        assert_eq!(retried, clean);
+        let stream = parser.get_input_stream();
+        assert_eq!(stream.size(), 9);
+        for i in 0..stream.size() {
+            assert_eq!(stream.get(i).get_token_index(), i);
+        }
+        assert_eq!(stream.get_all_text(), input);
+
+        // replaced stream gets back error deferral setting it had before parser
+        assert!(parser.input.defers_errors());
+        let mut deferring = CommonTokenStream::new(source(0));
+        deferring.set_defer_errors(true);
+        let original = parser.set_input_stream(deferring);
+        assert!(!original.defers_errors());
+        let deferring = parser.set_input_stream(CommonTokenStream::new(source(0)));
+        assert!(deferring.defers_errors());
+    }
+
+    #[test]
+    fn test_subtree_has_errors() {
+        use antlr_rust::parser::Parser;
+        use antlr_rust::trees::error_subtrees;
+        use std::rc::Rc;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(
+            InputStream::new("a,b\nc,d\n\"x\"y\nf,g\n".into()),
+            &tf,
+        );
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        parser.remove_error_listeners();
+        let root: Rc<dyn CSVParserContext> = parser.csvFile().unwrap();
+        assert_eq!(
+            root.to_string_tree(&*parser),
+            "(csvFile (hdr (row (field a) , (field b) \\n)) (row (field c) , (field d) \\n) \
+             (row (field \"x\") y \\n) (row (field f) , (field g) \\n))"
+        );
+
+        let children = root.get_children().collect::<Vec<_>>();
+        let broken_row = &children[2];
+        assert!(root.subtree_has_errors());
+        assert!(broken_row.subtree_has_errors());
+        assert!(broken_row.get_child(1).unwrap().subtree_has_errors());
+        assert!(!broken_row.get_child(0).unwrap().subtree_has_errors());
+        assert!(!children[0].subtree_has_errors());
+        assert!(!children[1].subtree_has_errors());
+        assert!(!children[3].subtree_has_errors());
+
+        let subtrees = error_subtrees::<CSVParserContextType>(&root);
+        assert_eq!(subtrees.len(), 1);
+        assert!(Rc::ptr_eq(&subtrees[0], broken_row));
+        assert!(error_subtrees::<CSVParserContextType>(&children[1]).is_empty());
+    }
+
+    #[cfg(feature = "interchange")]
+    #[test]
+    fn test_tree_interchange_v1() {
+        use antlr_rust::recognizer::Recognizer;
+        use antlr_rust::tree_interchange::{export_v1, import_v1};
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\nc,d\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let tree = parser.csvFile().unwrap();
+
+        let exported = export_v1(&*tree, &*parser);
+        let imported = import_v1(&exported, parser.get_rule_names(), parser.get_vocabulary())
+            .unwrap();
+        assert!(imported.warnings.is_empty());
+        assert_eq!(imported.tree.to_v1(), exported);
+
+        // files written by previous releases must stay readable
+        let fixture: serde_json::Value =
+            serde_json::from_str(include_str!("interchange_v1.json")).unwrap();
+        let from_fixture =
+            import_v1(&fixture, parser.get_rule_names(), parser.get_vocabulary()).unwrap();
+        assert!(from_fixture.warnings.is_empty());
+        assert_eq!(from_fixture.tree, imported.tree);
+    }
+
+    #[cfg(feature = "interchange")]
+    #[test]
+    fn test_tree_interchange_user_data() {
+        use antlr_rust::parser_rule_context::RuleContextExt;
+        use antlr_rust::recognizer::Recognizer;
+        use antlr_rust::tree_interchange::{export_v1, export_v1_with_user_data, import_v1};
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\nc,d\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let tree = parser.csvFile().unwrap();
+        let hdr = tree.get_child(0).unwrap();
+        let field = hdr.get_child(0).unwrap().get_child(0).unwrap();
+        tree.set_user_data("file".to_owned());
+        field.get_child(0).unwrap().set_user_data(1usize);
+        // not serialized by the callback below
+        hdr.set_user_data(0.5f64);
+
+        let plain = export_v1(&*tree, &*parser);
+        let exported = export_v1_with_user_data(&*tree, &*parser, |data| {
+            if let Some(text) = data.downcast_ref::<String>() {
+                return serde_json::to_value(text).ok();
+            }
+            data.downcast_ref::<usize>()
+                .and_then(|it| serde_json::to_value(it).ok())
+        });
+        assert!(plain["tree"].get("user_data").is_none());
+        assert_eq!(exported["tree"]["user_data"], "file");
+        let hdr_value = &exported["tree"]["children"][0];
+        assert!(hdr_value.get("user_data").is_none());
+        let field_value = &hdr_value["children"][0]["children"][0];
+        assert_eq!(field_value["children"][0]["user_data"], 1);
+
+        // user data is ignored on import
+        let imported = import_v1(&exported, parser.get_rule_names(), parser.get_vocabulary())
+            .unwrap();
+        assert!(imported.warnings.is_empty());
+        assert_eq!(imported.tree.to_v1(), plain);
+    }
+
+    #[test]
+    fn test_node_ids() {
+        use antlr_rust::trees::NodeIds;
+
+        let tf = ArenaCommonFactory::default();
+        let input = "a,b\nc,\"d\"\n";
+        let parse = || {
+            let lexer = CSVLexer::new_with_token_factory(InputStream::new(input.into()), &tf);
+            let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+            parser.csvFile().unwrap()
+        };
+        let tree = parse();
+        let mut ids = NodeIds::<CSVParserContextType>::new(tree.clone());
+        let other = NodeIds::<CSVParserContextType>::new(parse());
+        for id in 0..20 {
+            let text =
+                |ids: &NodeIds<CSVParserContextType>| ids.node_by_id(id).map(|it| it.get_text());
+            assert_eq!(text(&ids), text(&other));
+        }
+
+        let last_row = tree.get_child(1).unwrap();
+        let field = last_row.get_child(2).unwrap();
+        assert_eq!(ids.node_id(&*tree), Some(0));
+        assert_eq!(ids.node_id(&*field), Some(13));
+        assert_eq!(ids.node_by_id(14).unwrap().get_text(), "\"d\"");
+        assert!(ids.node_by_id(16).is_none());
+        assert!(other.node_id(&*field).is_none());
+
+        // ids are not updated until reindex
+        let first_field = tree.get_child(0).unwrap().get_child(0).unwrap().get_child(0).unwrap();
+        last_row.add_child(first_field.clone());
+        assert!(ids.node_by_id(16).is_none());
+        ids.reindex();
+        assert_eq!(ids.node_by_id(16).unwrap().get_text(), "a");
+        assert_eq!(ids.node_id(&*field), Some(13));
+    }
+
+    #[cfg(feature = "interchange")]
+    #[test]
+    fn test_node_ids_interchange() {
+        use antlr_rust::recognizer::Recognizer;
+        use antlr_rust::tree_interchange::{export_v1, import_v1};
+        use antlr_rust::trees::NodeIds;
+
+        let tf = ArenaCommonFactory::default();
+        let lexer = CSVLexer::new_with_token_factory(InputStream::new("a,b\nc,d\n".into()), &tf);
+        let mut parser = CSVParser::new(CommonTokenStream::new(lexer));
+        let tree = parser.csvFile().unwrap();
+        let ids = NodeIds::<CSVParserContextType>::new(tree.clone());
+
+        let serialized = export_v1(&*tree, &*parser).to_string();
+        let value: serde_json::Value = serde_json::from_str(&serialized).unwrap();
+        let imported = import_v1(&value, parser.get_rule_names(), parser.get_vocabulary())
+            .unwrap();
+        assert!(imported.warnings.is_empty());
+        let index = imported.tree.index();
+        for &id in &[0, 2, 4, 7, 12, 15] {
+            let imported_node = index.node_by_id(id).unwrap();
+            let node = ids.node_by_id(id).unwrap();
+            assert_eq!(imported_node.get_text(), node.get_text(), "node {}", id);
+        }
+        assert!(index.node_by_id(16).is_none());
+    }
+
     struct Listener2 {}
 
     impl<'input> ParseTreeListener<'input, ReferenceToATNParserContextType> for Listener2 {
@@ -226,6 +2807,216 @@ if (x < x && a > 0) then duh
         test_static(result);
     }
 
+    #[test]
+    fn test_strictness_policy() {
+        use antlr_rust::error_listener::CollectingErrorListener;
+        use antlr_rust::error_strategy::BailErrorStrategy;
+        use antlr_rust::errors::{ANTLRError, StrictnessError, StrictnessViolation};
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_atn_simulator::StrictnessPolicy;
+        use std::rc::Rc;
+
+        // trailing ATN token can be matched either by the loop or by the optional `ATN?`
+        let parse = |policy: StrictnessPolicy| {
+            let lexer = ReferenceToATNLexer::new_with_token_factory(
+                InputStream::new_owned("a 34".to_owned().into_boxed_str()),
+                &FACTORY,
+            );
+            let mut parser = ReferenceToATNParser::with_strategy(
+                CommonTokenStream::new(lexer),
+                BailErrorStrategy::new(),
+            );
+            parser.get_interpreter().set_strictness_policy(policy);
+            parser.a().map(|_| ())
+        };
+
+        assert!(parse(StrictnessPolicy::default()).is_ok());
+        let result = parse(StrictnessPolicy {
+            fail_on_ambiguity: true,
+            ..StrictnessPolicy::default()
+        });
+        match result {
+            Err(ANTLRError::FallThrough(e)) => {
+                let msg = e.to_string();
+                assert!(
+                    msg.contains(&format!("{:?}", StrictnessViolation::Ambiguity)),
+                    "{}",
+                    msg
+                );
+                assert!(msg.contains("in decision 0 (rule a)"), "{}", msg);
+            }
+            _ => panic!("expected strictness error, got {:?}", result),
+        }
+
+        // default strategy gets no chance to recover, so parse fails with it too
+        let lexer = ReferenceToATNLexer::new_with_token_factory(
+            InputStream::new_owned("a 34".to_owned().into_boxed_str()),
+            &FACTORY,
+        );
+        let mut parser = ReferenceToATNParser::new(CommonTokenStream::new(lexer));
+        let listener = Rc::new(CollectingErrorListener::new());
+        parser.remove_error_listeners();
+        parser.add_error_listener(listener.clone());
+        parser
+            .get_interpreter()
+            .set_strictness_policy(StrictnessPolicy {
+                fail_on_ambiguity: true,
+                ..StrictnessPolicy::default()
+            });
+        let result = parser.a().map(|_| ());
+        assert!(
+            matches!(result, Err(ANTLRError::FallThrough(ref e)) if e.is::<StrictnessError>()),
+            "{:?}",
+            result
+        );
+        let diagnostics = listener.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        match &diagnostics[0].error {
+            Some(ANTLRError::StrictnessError(e)) => {
+                assert_eq!(e.violation, StrictnessViolation::Ambiguity)
+            }
+            error => panic!("expected strictness error, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_decision_prediction_mode() {
+        use antlr_rust::atn_config_set::ATNConfigSet;
+        use antlr_rust::dfa::DFA;
+        use antlr_rust::error_listener::ErrorListener;
+        use antlr_rust::error_strategy::BailErrorStrategy;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_atn_simulator::StrictnessPolicy;
+        use antlr_rust::recognizer::RecognizerRef;
+        use antlr_rust::PredictionMode;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(Default)]
+        struct Collector(RefCell<Vec<(isize, isize)>>);
+
+        impl ErrorListener for Collector {
+            fn report_context_sensitivity(
+                &self, _recognizer: &dyn RecognizerRef, dfa: &DFA, _start_index: isize,
+                _stop_index: isize, prediction: isize, _configs: &ATNConfigSet,
+            ) {
+                self.0.borrow_mut().push((dfa.decision, prediction));
+            }
+        }
+
+        // decision 0 is the `(ID|ATN)*` loop, which is ambiguous for trailing ATN token
+        let parse = |sll_loop: bool| {
+            let lexer = ReferenceToATNLexer::new_with_token_factory(
+                InputStream::new_owned("a 34".to_owned().into_boxed_str()),
+                &FACTORY,
+            );
+            let mut parser = ReferenceToATNParser::with_strategy(
+                CommonTokenStream::new(lexer),
+                BailErrorStrategy::new(),
+            );
+            let collector = Rc::new(Collector::default());
+            parser.add_error_listener(collector.clone());
+            parser
+                .get_interpreter()
+                .set_strictness_policy(StrictnessPolicy {
+                    fail_on_ambiguity: true,
+                    ..StrictnessPolicy::default()
+                });
+            if sll_loop {
+                parser
+                    .get_interpreter()
+                    .set_decision_prediction_mode(0, PredictionMode::SLL);
+            }
+            assert_eq!(
+                parser.get_interpreter().get_decision_prediction_mode(0),
+                if sll_loop {
+                    PredictionMode::SLL
+                } else {
+                    PredictionMode::LL
+                }
+            );
+            let result = parser.a().map(|ctx| ctx.get_text());
+            let warnings = collector.0.borrow().clone();
+            (result, warnings)
+        };
+
+        // full LL detects the ambiguity
+        let (result, warnings) = parse(false);
+        assert!(result.is_err(), "{:?}", result);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        // SLL resolves the conflict to the minimal alternative and reports it instead
+        let (result, warnings) = parse(true);
+        assert_eq!(result.unwrap(), "a34");
+        assert!(!warnings.is_empty());
+        assert!(
+            warnings.iter().all(|&(decision, _)| decision == 0),
+            "{:?}",
+            warnings
+        );
+        assert_eq!(warnings[0].1, 1);
+
+        let lexer = ReferenceToATNLexer::new_with_token_factory(
+            InputStream::new_owned("a".to_owned().into_boxed_str()),
+            &FACTORY,
+        );
+        let parser = ReferenceToATNParser::new(CommonTokenStream::new(lexer));
+        let interpreter = parser.get_interpreter();
+        interpreter.set_decision_prediction_mode(0, PredictionMode::SLL);
+        interpreter.clear_decision_overrides();
+        assert_eq!(
+            interpreter.get_decision_prediction_mode(0),
+            PredictionMode::LL
+        );
+    }
+
+    #[test]
+    fn test_shared_error_listener() {
+        use antlr_rust::error_listener::ErrorListener;
+        use antlr_rust::errors::ANTLRError;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::recognizer::RecognizerRef;
+        use antlr_rust::token::OwningToken;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(Default)]
+        struct Collector(RefCell<Vec<String>>);
+
+        impl ErrorListener for Collector {
+            fn syntax_error(
+                &self, recognizer: &dyn RecognizerRef, _offending_symbol: Option<&OwningToken>,
+                line: isize, column: isize, msg: &str, _error: Option<&ANTLRError>,
+            ) {
+                // token names can be rendered for lexer errors too
+                let id = recognizer.vocabulary().map(|it| it.get_display_name(1).into_owned());
+                self.0.borrow_mut().push(format!(
+                    "{} {}:{} {} ({})",
+                    recognizer.grammar_file_name(),
+                    line,
+                    column,
+                    msg,
+                    id.unwrap_or_default()
+                ));
+            }
+        }
+
+        let collector = Rc::new(Collector::default());
+        let mut lexer = SimpleLRLexer::new(InputStream::new("9".into()));
+        lexer.remove_error_listeners();
+        lexer.add_error_listener(collector.clone());
+        let mut parser = SimpleLRParser::new(CommonTokenStream::new(lexer));
+        parser.remove_error_listeners();
+        parser.add_error_listener(collector.clone());
+        let _ = parser.s();
+
+        let errors = collector.0.borrow();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(errors[0], "SimpleLR.g4 1:0 token recognition error at: '9' (ID)");
+        assert!(errors[1].starts_with("SimpleLR.g4 1:1 "), "{}", errors[1]);
+        assert!(errors[1].ends_with(" (ID)"), "{}", errors[1]);
+    }
+
     struct Listener3;
 
     impl<'input> ParseTreeListener<'input, SimpleLRParserContextType> for Listener3 {
@@ -276,6 +3067,225 @@ if (x < x && a > 0) then duh
         assert_eq!(result.to_string_tree(&*parser), "(a (a (a x) y) z)");
     }
 
+    #[test]
+    fn test_parser_interpreter() {
+        use std::cell::RefCell;
+        use std::sync::Arc;
+
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::error_listener::CollectingErrorListener;
+        use antlr_rust::error_strategy::{BailErrorStrategy, DefaultErrorStrategy};
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_interpreter::{
+            InterpretedNodeType, InterpretedTree, ParserGrammar, ParserInterpreter,
+        };
+        use antlr_rust::token_factory::CommonTokenFactory;
+        use antlr_rust::vocabulary::VocabularyImpl;
+
+        // serialized ATN of the SimpleLR parser, same as in generated `simplelrparser`
+        const SIMPLE_LR_PARSER_ATN: &str =
+            "\x03\u{608b}\u{a72a}\u{8133}\u{b9ed}\u{417c}\u{3be7}\u{7786}\u{5964}\x03\
+             \x04\x13\x04\x02\x09\x02\x04\x03\x09\x03\x03\x02\x03\x02\x03\x03\x03\x03\
+             \x03\x03\x03\x03\x03\x03\x07\x03\x0e\x0a\x03\x0c\x03\x0e\x03\x11\x0b\x03\
+             \x03\x03\x02\x03\x04\x04\x02\x04\x02\x02\x02\x11\x02\x06\x03\x02\x02\x02\
+             \x04\x08\x03\x02\x02\x02\x06\x07\x05\x04\x03\x02\x07\x03\x03\x02\x02\x02\
+             \x08\x09\x08\x03\x01\x02\x09\x0a\x07\x03\x02\x02\x0a\x0f\x03\x02\x02\x02\
+             \x0b\x0c\x0c\x04\x02\x02\x0c\x0e\x07\x03\x02\x02\x0d\x0b\x03\x02\x02\x02\
+             \x0e\x11\x03\x02\x02\x02\x0f\x0d\x03\x02\x02\x02\x0f\x10\x03\x02\x02\x02\
+             \x10\x05\x03\x02\x02\x02\x11\x0f\x03\x02\x02\x02\x03\x0f";
+
+        #[derive(Default)]
+        struct RuleEvents(Rc<RefCell<Vec<String>>>);
+
+        impl<'input> ParseTreeListener<'input, InterpretedNodeType<'input, CommonTokenFactory>>
+            for RuleEvents
+        {
+            fn enter_every_rule(&mut self, ctx: &InterpretedTree<'input, CommonTokenFactory>) {
+                let event = format!("enter {}", ctx.get_rule_index());
+                self.0.borrow_mut().push(event)
+            }
+
+            fn exit_every_rule(&mut self, ctx: &InterpretedTree<'input, CommonTokenFactory>) {
+                let event = format!("exit {}", ctx.get_rule_index());
+                self.0.borrow_mut().push(event)
+            }
+        }
+
+        let atn = Arc::new(ATNDeserializer::new(None).deserialize(SIMPLE_LR_PARSER_ATN.chars()));
+        let vocabulary = VocabularyImpl::new(
+            simplelrparser::_LITERAL_NAMES.iter(),
+            simplelrparser::_SYMBOLIC_NAMES.iter(),
+            None,
+        );
+        let grammar = ParserGrammar::new(atn, vocabulary)
+            .with_grammar_file_name("SimpleLR.g4")
+            .with_rule_names(&simplelrparser::ruleNames);
+
+        let lexer = SimpleLRLexer::new(InputStream::new("x y z".into()));
+        let mut parser = ParserInterpreter::new(grammar.clone(), CommonTokenStream::new(lexer));
+        let events = RuleEvents::default();
+        let log = events.0.clone();
+        parser.add_parse_listener(Box::new(events));
+        let tree = parser.parse(simplelrparser::RULE_s).unwrap();
+        assert_eq!(tree.to_string_tree(&*parser), "(s (a (a (a x) y) z))");
+        assert_eq!(
+            *log.borrow(),
+            ["enter 0", "enter 1", "exit 1", "enter 1", "exit 1", "enter 1", "exit 1", "exit 0"]
+        );
+
+        let lexer = SimpleLRLexer::new(InputStream::new("x y".into()));
+        let mut parser = ParserInterpreter::new(grammar.clone(), CommonTokenStream::new(lexer));
+        let tree = parser.parse(simplelrparser::RULE_a).unwrap();
+        assert_eq!(tree.to_string_tree(&*parser), "(a (a x) y)");
+
+        // errors are reported and recovered from same as in generated parser
+        let errors = |parser: &mut dyn FnMut(Rc<CollectingErrorListener>) -> String| {
+            let listener = Rc::new(CollectingErrorListener::new());
+            let tree = parser(listener.clone());
+            let diagnostics = listener.take_diagnostics();
+            let messages = diagnostics
+                .into_iter()
+                .map(|it| it.message)
+                .collect::<Vec<_>>();
+            (tree, messages)
+        };
+        let expected = errors(&mut |listener| {
+            let lexer = SimpleLRLexer::new(InputStream::new("".into()));
+            let mut parser = SimpleLRParser::new(CommonTokenStream::new(lexer));
+            parser.remove_error_listeners();
+            parser.add_error_listener(listener);
+            parser.s().unwrap().to_string_tree(&*parser)
+        });
+        let actual = errors(&mut |listener| {
+            let lexer = SimpleLRLexer::new(InputStream::new("".into()));
+            let mut parser = ParserInterpreter::new(grammar.clone(), CommonTokenStream::new(lexer));
+            parser.remove_error_listeners();
+            parser.add_error_listener(listener);
+            let tree = parser.parse(simplelrparser::RULE_s).unwrap();
+            tree.to_string_tree(&*parser)
+        });
+        assert_eq!(expected.1.len(), 1);
+        assert_eq!(actual, expected);
+
+        // error strategy can be replaced at runtime
+        let lexer = SimpleLRLexer::new(InputStream::new("".into()));
+        let tokens = CommonTokenStream::new(lexer);
+        let mut parser = ParserInterpreter::with_dyn_strategy(grammar.clone(), tokens);
+        parser.remove_error_listeners();
+        parser.set_error_strategy(Box::new(BailErrorStrategy::new()));
+        assert!(parser.parse(simplelrparser::RULE_s).is_err());
+        parser.reset();
+        parser.set_error_strategy(Box::new(DefaultErrorStrategy::new()));
+        assert!(parser.parse(simplelrparser::RULE_s).is_ok());
+    }
+
+    #[test]
+    fn test_all_possible_parse_trees() {
+        use std::sync::Arc;
+
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::error_listener::AmbiguityCollector;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_interpreter::{ParserGrammar, ParserInterpreter};
+        use antlr_rust::vocabulary::VocabularyImpl;
+
+        let tokens = || {
+            let lexer = ReferenceToATNLexer::new_with_token_factory(
+                InputStream::new_owned("a 34".to_owned().into_boxed_str()),
+                &FACTORY,
+            );
+            CommonTokenStream::new(lexer)
+        };
+
+        // trailing ATN token can be matched either by the loop or by the optional `ATN?`
+        let mut parser = ReferenceToATNParser::new(tokens());
+        let collector = Rc::new(AmbiguityCollector::new());
+        parser.add_error_listener(collector.clone());
+        parser.a().unwrap();
+        let ambiguities = collector.take_ambiguities();
+        assert_eq!(ambiguities.len(), 1, "{:?}", ambiguities);
+        let ambiguity = &ambiguities[0];
+        assert_eq!(ambiguity.decision, 0);
+        assert_eq!(ambiguity.start_index, 1);
+        assert_eq!(ambiguity.alts.iter().collect::<Vec<_>>(), [1, 2]);
+
+        let serialized_atn = referencetoatnparser::get_serialized_atn();
+        let atn = Arc::new(ATNDeserializer::new(None).deserialize_atn(serialized_atn));
+        let vocabulary = VocabularyImpl::new(
+            referencetoatnparser::_LITERAL_NAMES.iter(),
+            referencetoatnparser::_SYMBOLIC_NAMES.iter(),
+            None,
+        );
+        let grammar =
+            ParserGrammar::new(atn, vocabulary).with_rule_names(&referencetoatnparser::ruleNames);
+        let mut interpreter = ParserInterpreter::new(grammar, tokens());
+        let trees = interpreter
+            .get_all_possible_parse_trees(ambiguity, referencetoatnparser::RULE_a)
+            .unwrap();
+        assert_eq!(trees.len(), 2);
+        for tree in &trees {
+            assert_eq!(tree.to_string_tree(&*interpreter), "(a a 34)");
+        }
+        assert!(!Rc::ptr_eq(&trees[0], &trees[1]));
+        let root = interpreter.get_override_decision_root().unwrap();
+        assert!(Rc::ptr_eq(root, &trees[1]));
+    }
+
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn test_soft_keywords() {
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_rule_context::ParserRuleContext;
+        use antlr_rust::testkit::{EventRecorder, MockTokenSource};
+        use antlr_rust::token_factory::CommonTokenFactory;
+        use antlr_rust::tree::ParseTree;
+        use std::rc::Rc;
+
+        const MATCH_KEYWORD: isize = 10;
+
+        // `match` keyword at identifier position
+        let tokens = MockTokenSource::new(&CommonTokenFactory)
+            .tokens(&[(MATCH_KEYWORD, "match"), (simplelrparser::ID, "x")]);
+        let errors = Rc::new(EventRecorder::new());
+        let mut parser = SimpleLRParser::new(CommonTokenStream::new(tokens));
+        parser.add_error_listener(errors.clone());
+        parser.set_soft_keywords(simplelrparser::ID, &[(MATCH_KEYWORD, "match")]);
+        let result = parser.a().unwrap();
+        assert_eq!(result.to_string_tree(&*parser), "(a (a match) x)");
+        assert_eq!(errors.syntax_errors().len(), 0);
+
+        // `match` identifier at keyword position, registered as a soft version of ')'
+        let lexer = LabelsLexer::new(InputStream::new("(4 match".into()));
+        let mut parser = LabelsParser::new(CommonTokenStream::new(lexer));
+        parser.add_error_listener(errors.clone());
+        parser.set_soft_keywords(labelsparser::ID, &[(labelsparser::T__3, "match")]);
+        let result = parser.s().unwrap();
+        assert_eq!(result.to_string_tree(&*parser), "(s (e ( (e 4) match))");
+        assert_eq!(errors.syntax_errors().len(), 0);
+        // buffered token is not changed
+        assert_eq!(
+            parser.get_input_stream().get(2).get_token_type(),
+            labelsparser::ID
+        );
+        match result.q.as_deref().unwrap() {
+            EContextAll::ParensContext(parens) => {
+                let terminal = parens.get_token(labelsparser::T__3, 0).unwrap();
+                assert!(terminal.is_reinterpreted());
+                assert_eq!(terminal.get_matched_type(), labelsparser::T__3);
+                assert_eq!(terminal.get_text(), "match");
+            }
+            _ => panic!("expected parens"),
+        }
+
+        // without soft keywords the same input is an error
+        let lexer = LabelsLexer::new(InputStream::new("(4 match".into()));
+        let mut parser = LabelsParser::new(CommonTokenStream::new(lexer));
+        parser.remove_error_listeners();
+        parser.add_error_listener(errors.clone());
+        let _ = parser.s();
+        assert_eq!(errors.syntax_errors().len(), 1);
+    }
+
     struct Listener4 {
         data: String,
     }
@@ -349,6 +3359,549 @@ if (x < x && a > 0) then duh
         }
     }
 
+    #[cfg(feature = "testkit")]
+    #[test]
+    fn test_dfa_growth_limits() {
+        use std::collections::HashSet;
+        use std::sync::Arc;
+
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::atn_simulator::IATNSimulator;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_interpreter::{ParserGrammar, ParserInterpreter};
+        use antlr_rust::testkit::{Event, EventRecorder};
+        use antlr_rust::vocabulary::VocabularyImpl;
+
+        let input = "(a+4)*2 + b*c-- + (d++ + 5)*(e*f) + g--*h++";
+        let parse = |warning, limit| {
+            let lexer = LabelsLexer::new(InputStream::new(input.into()));
+            let mut parser = LabelsParser::new(CommonTokenStream::new(lexer));
+            parser.get_interpreter().set_dfa_growth_warning(warning);
+            parser.get_interpreter().set_dfa_growth_limit(limit);
+            let recorder = EventRecorder::new();
+            parser.add_error_listener(Rc::new(recorder.clone()));
+            let result = parser.s().expect("parser error");
+            let v = result.q.as_ref().unwrap().get_v().clone();
+            (v, recorder.take_events())
+        };
+
+        let is_growth = |event: &Event| matches!(event, Event::DfaGrowth { .. });
+        let (expected, events) = parse(0, 0);
+        assert!(!events.iter().any(is_growth), "{:?}", events);
+
+        // DFA is shared with other tests, so it is not known which states this parse adds
+        let (v, events) = parse(1, 0);
+        assert_eq!(v, expected);
+        let mut decisions = HashSet::new();
+        for event in events.into_iter().filter(is_growth) {
+            match event {
+                Event::DfaGrowth {
+                    decision,
+                    rule_name,
+                    state_count,
+                    start_index,
+                    input_index,
+                } => {
+                    assert!(
+                        decisions.insert(decision),
+                        "decision {} reported twice",
+                        decision
+                    );
+                    assert_eq!(rule_name, "e");
+                    assert!(state_count > 1);
+                    assert!(start_index <= input_index);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // predictions that need new DFA states bypass DFA
+        let (v, _) = parse(0, 1);
+        assert_eq!(v, expected);
+
+        // interpreter has its own DFA, so other tests don't affect its size
+        let serialized = labelsparser::get_serialized_atn();
+        let atn = Arc::new(ATNDeserializer::new(None).deserialize_atn(serialized));
+        let vocabulary = VocabularyImpl::new(
+            labelsparser::_LITERAL_NAMES.iter(),
+            labelsparser::_SYMBOLIC_NAMES.iter(),
+            None,
+        );
+        let lexer = LabelsLexer::new(InputStream::new(input.into()));
+        let mut parser = ParserInterpreter::new(
+            ParserGrammar::new(atn, vocabulary),
+            CommonTokenStream::new(lexer),
+        );
+        parser.get_interpreter().set_dfa_growth_limit(1);
+        parser.parse(labelsparser::RULE_s).expect("parser error");
+        let sizes = parser
+            .get_interpreter()
+            .decision_to_dfa()
+            .iter()
+            .map(|it| it.read().states.len() - 1)
+            .collect::<Vec<_>>();
+        assert!(sizes.iter().all(|&it| it <= 1), "{:?}", sizes);
+        assert!(sizes.contains(&1), "{:?}", sizes);
+    }
+
+    #[test]
+    fn test_warm_up() {
+        use std::sync::Arc;
+
+        use antlr_rust::atn_deserializer::ATNDeserializer;
+        use antlr_rust::parser::{warm_up, Parser};
+        use antlr_rust::parser_interpreter::{ParserGrammar, ParserInterpreter};
+        use antlr_rust::vocabulary::VocabularyImpl;
+
+        // interpreter has its own DFA, so other tests don't affect it
+        let serialized = simplelrparser::get_serialized_atn();
+        let atn = Arc::new(ATNDeserializer::new(None).deserialize_atn(serialized));
+        let vocabulary = VocabularyImpl::new(
+            simplelrparser::_LITERAL_NAMES.iter(),
+            simplelrparser::_SYMBOLIC_NAMES.iter(),
+            None,
+        );
+        let grammar = ParserGrammar::new(atn, vocabulary);
+        let new_parser = |sample: &'static str| {
+            let lexer = SimpleLRLexer::new(InputStream::new(sample.into()));
+            ParserInterpreter::new(grammar.clone(), CommonTokenStream::new(lexer))
+        };
+        let parse = |sample| {
+            let mut parser = new_parser(sample);
+            parser.parse(simplelrparser::RULE_s).unwrap();
+            parser
+        };
+
+        assert_eq!(warm_up(&[], &parse), 0);
+        let cold = warm_up(&["x"], &new_parser);
+        let states = warm_up(&["x", "x y z"], &parse);
+        assert!(states > cold);
+        assert_eq!(warm_up(&["x y z"], &parse), states);
+
+        new_parser("x").get_interpreter().clear_dfa();
+        assert_eq!(warm_up(&["x"], &new_parser), cold);
+    }
+
+    #[test]
+    fn test_dfa_cache() {
+        use std::io;
+        use std::sync::Arc;
+
+        use antlr_rust::atn_deserializer::{ATNDeserializer, SerializedATN};
+        use antlr_rust::atn_simulator::IATNSimulator;
+        use antlr_rust::dfa_cache::DfaCache;
+        use antlr_rust::lexer_interpreter::{LexerGrammar, LexerInterpreter};
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_interpreter::{ParserGrammar, ParserInterpreter};
+        use antlr_rust::vocabulary::VocabularyImpl;
+
+        // interpreters have their own DFA, so other tests don't affect it
+        type Grammars = (LexerGrammar, ParserGrammar);
+        let grammars = || -> Grammars {
+            let deserialize =
+                |data: &SerializedATN| Arc::new(ATNDeserializer::new(None).deserialize_atn(data));
+            let vocabulary = || {
+                VocabularyImpl::new(
+                    simplelrparser::_LITERAL_NAMES.iter(),
+                    simplelrparser::_SYMBOLIC_NAMES.iter(),
+                    None,
+                )
+            };
+            (
+                LexerGrammar::new(
+                    deserialize(simplelrlexer::get_serialized_atn()),
+                    vocabulary(),
+                ),
+                ParserGrammar::new(
+                    deserialize(simplelrparser::get_serialized_atn()),
+                    vocabulary(),
+                ),
+            )
+        };
+        let new_lexer = |grammars: &Grammars, input: &'static str| {
+            let lexer: LexerInterpreter<'_, _> =
+                LexerInterpreter::new(grammars.0.clone(), InputStream::new(input));
+            lexer
+        };
+        let new_parser = |grammars: &Grammars, input: &'static str| {
+            let tokens = CommonTokenStream::new(new_lexer(grammars, input));
+            ParserInterpreter::new(grammars.1.clone(), tokens)
+        };
+        let dump = |grammars: &Grammars| {
+            let mut dump = Vec::new();
+            for dfa in new_lexer(grammars, "")
+                .get_interpreter()
+                .unwrap()
+                .decision_to_dfa()
+            {
+                dump.push(dfa.read().to_lexer_string());
+            }
+            let vocabulary = grammars.1.get_vocabulary();
+            for dfa in new_parser(grammars, "").get_interpreter().decision_to_dfa() {
+                dump.push(dfa.read().to_string(vocabulary));
+            }
+            dump
+        };
+
+        let warm = grammars();
+        new_parser(&warm, "x y\nz")
+            .parse(simplelrparser::RULE_s)
+            .unwrap();
+        let expected = dump(&warm);
+        assert!(expected.iter().any(|it| !it.is_empty()), "{:?}", expected);
+        let lexer_cache = DfaCache::capture(new_lexer(&warm, "").get_interpreter().unwrap());
+        let parser_cache = DfaCache::capture(new_parser(&warm, "").get_interpreter());
+
+        let path = std::env::temp_dir().join(format!("antlr_dfa_cache_{}", std::process::id()));
+        parser_cache.save(&path).unwrap();
+        let loaded = DfaCache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.as_bytes(), parser_cache.as_bytes());
+
+        let cold = grammars();
+        lexer_cache
+            .restore(new_lexer(&cold, "").get_interpreter().unwrap())
+            .unwrap();
+        loaded
+            .restore(new_parser(&cold, "").get_interpreter())
+            .unwrap();
+        assert_eq!(dump(&cold), expected);
+        // restored DFA already has everything the same input needs
+        new_parser(&cold, "x y\nz")
+            .parse(simplelrparser::RULE_s)
+            .unwrap();
+        assert_eq!(dump(&cold), expected);
+
+        let other = grammars();
+        let error = lexer_cache
+            .restore(new_parser(&other, "").get_interpreter())
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = DfaCache::from_bytes(b"ANTLRDFA".to_vec()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(dump(&other), dump(&grammars()));
+    }
+
+    #[test]
+    fn test_dfa_strings() {
+        use antlr_rust::parser::Parser;
+        use antlr_rust::recognizer::Recognizer;
+
+        let input = "(a+4)*2 + b*c--";
+        let lexer = LabelsLexer::new(InputStream::new(input.into()));
+        let mut parser = LabelsParser::new(CommonTokenStream::new(lexer));
+        assert!(parser.get_rule_invocation_stack().is_empty());
+        parser.s().expect("parser error");
+        assert!(parser.get_rule_invocation_stack().is_empty());
+
+        let dfa = parser.get_dfa_strings();
+        assert_eq!(dfa.len(), parser.get_atn().decision_to_state.len());
+        // DFA is shared with other tests, but at least this parse has filled some of it
+        assert!(dfa.iter().any(|it| it.contains("->")), "{:?}", dfa);
+    }
+
+    #[test]
+    fn test_max_rule_depth() {
+        use antlr_rust::errors::ANTLRError;
+        use antlr_rust::parser::ParseCollecting;
+
+        let parse = |depth: usize| {
+            let input = format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+            let lexer = LabelsLexer::new(InputStream::new(input.as_str().into()));
+            let mut parser = LabelsParser::new(CommonTokenStream::new(lexer));
+            parser.set_max_rule_depth(Some(20));
+            let (result, diagnostics) = parser.parse_collecting(|p| p.s());
+            assert!(result.is_ok());
+            assert_eq!(parser.get_rule_depth(), 0);
+            diagnostics
+        };
+
+        let diagnostics = parse(10);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+        // too deep input is reported as an error, outer rules still match closing parentheses
+        let diagnostics = parse(100_000);
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        match &diagnostics[0].error {
+            Some(ANTLRError::DepthExceededError(e)) => assert_eq!(e.max_depth, 20),
+            e => panic!("expected depth error, got {:?}", e),
+        }
+        assert_eq!(
+            diagnostics[0].message,
+            "rule invocation depth exceeds limit of 20"
+        );
+    }
+
+    #[test]
+    fn test_parse_cancellation() {
+        use antlr_rust::cancellation::{CancellationToken, ParseCancelled};
+        use antlr_rust::errors::ANTLRError;
+        use std::rc::Rc;
+        use std::time::{Duration, Instant};
+
+        use crate::gen::labelsparser::SContextAll;
+
+        let input = "(a+4)*2 + b*c--";
+        let lexer = LabelsLexer::new(InputStream::new(input.into()));
+        let mut parser = LabelsParser::new(CommonTokenStream::new(lexer));
+        let reason = |result: Result<Rc<SContextAll>, ANTLRError>| match result {
+            Err(ANTLRError::Cancelled(reason)) => reason,
+            r => panic!("expected cancellation, got {:?}", r.map(|it| it.get_text())),
+        };
+
+        let token = CancellationToken::new();
+        parser.set_cancellation_token(Some(token.clone()));
+        token.cancel();
+        assert_eq!(reason(parser.s()), ParseCancelled::Cancelled);
+
+        // cancellation is checked before the rule depth limit
+        parser.set_max_rule_depth(Some(0));
+        parser.reset();
+        assert_eq!(reason(parser.s()), ParseCancelled::Cancelled);
+        parser.set_max_rule_depth(None);
+
+        parser.set_cancellation_token(None);
+        parser.set_deadline(Some(Instant::now()));
+        parser.reset();
+        assert_eq!(reason(parser.s()), ParseCancelled::DeadlineExceeded);
+
+        parser.set_deadline(Some(Instant::now() + Duration::from_secs(3600)));
+        parser.reset();
+        let tree = parser.s().expect("parser error");
+        assert_eq!(tree.get_text(), "(a+4)*2+b*c--");
+    }
+
+    #[test]
+    fn test_interpreter_deep_nesting() {
+        use antlr_rust::parser::{ParseCollecting, Parser};
+        use antlr_rust::parser_interpreter::{ParserGrammar, ParserInterpreter};
+        use antlr_rust::vocabulary::VocabularyImpl;
+
+        use crate::gen::labelsparser::{ruleNames, RULE_s, _LITERAL_NAMES, _SYMBOLIC_NAMES};
+
+        let grammar = {
+            let lexer = LabelsLexer::new(InputStream::new("".into()));
+            let parser = LabelsParser::new(CommonTokenStream::new(lexer));
+            let vocabulary =
+                VocabularyImpl::new(_LITERAL_NAMES.iter(), _SYMBOLIC_NAMES.iter(), None);
+            ParserGrammar::from_simulator(parser.get_interpreter(), vocabulary)
+                .with_rule_names(&ruleNames)
+        };
+
+        // far deeper than generated parser can handle on the test thread stack
+        let depth = 100_000;
+        let input = format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        let lexer = LabelsLexer::new(InputStream::new(input.as_str().into()));
+        let mut parser = ParserInterpreter::new(grammar, CommonTokenStream::new(lexer));
+        let (tree, diagnostics) = parser.parse_collecting(|p| p.parse(RULE_s));
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let tree = tree.unwrap();
+        assert_eq!(tree.get_source_interval().b, 2 * depth as isize);
+        // deep tree is dropped without recursion as well
+        drop(tree);
+    }
+
+    #[test]
+    fn test_recognition_error_payloads() {
+        use std::rc::Rc;
+
+        use antlr_rust::error_listener::CollectingErrorListener;
+        use antlr_rust::error_strategy::BailErrorStrategy;
+        use antlr_rust::errors::ANTLRError;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::parser_interpreter::{ParserGrammar, ParserInterpreter};
+        use antlr_rust::vocabulary::VocabularyImpl;
+
+        use crate::gen::labelsparser::{ruleNames, RULE_s, _LITERAL_NAMES, _SYMBOLIC_NAMES};
+
+        let grammar = {
+            let lexer = LabelsLexer::new(InputStream::new("".into()));
+            let parser = LabelsParser::new(CommonTokenStream::new(lexer));
+            let vocabulary =
+                VocabularyImpl::new(_LITERAL_NAMES.iter(), _SYMBOLIC_NAMES.iter(), None);
+            ParserGrammar::from_simulator(parser.get_interpreter(), vocabulary)
+                .with_rule_names(&ruleNames)
+        };
+
+        // bailing strategy does not sync, so it is prediction that fails at `)`
+        let lexer = LabelsLexer::new(InputStream::new(")".into()));
+        let tokens = CommonTokenStream::new(lexer);
+        let strategy = BailErrorStrategy::new();
+        let mut parser = ParserInterpreter::with_strategy(grammar, tokens, strategy);
+        let listener = Rc::new(CollectingErrorListener::new());
+        parser.remove_error_listeners();
+        parser.add_error_listener(listener.clone());
+        assert!(parser.parse(RULE_s).is_err());
+
+        let diagnostics = listener.take_diagnostics();
+        match &diagnostics[0].error {
+            Some(ANTLRError::NoAltError(e)) => {
+                // INT, '(' e ')' and ID alternatives of the primary expression
+                assert_eq!(e.get_dead_end_alts(), vec![1, 2, 3]);
+                let message = e.to_string();
+                assert!(
+                    message.ends_with("1:0 no viable alternative at input ')'"),
+                    "{}",
+                    message
+                );
+            }
+            e => panic!("expected no viable alternative error, got {:?}", e),
+        }
+        let error = diagnostics[0].error.as_ref().unwrap();
+        let message = error.to_string();
+        assert!(
+            message.ends_with("1:0 no viable alternative at input ')'"),
+            "{}",
+            message
+        );
+        // error chain does not repeat the message of the payload
+        assert!(std::error::Error::source(error).is_none());
+    }
+
+    #[test]
+    fn test_dfa_shared_between_threads() {
+        use antlr_rust::atn_simulator::IATNSimulator;
+        use antlr_rust::dfa::DFA;
+        use antlr_rust::parser::Parser;
+        use antlr_rust::RwLock;
+
+        fn state_count(dfa: &Vec<RwLock<DFA>>) -> usize {
+            dfa.iter().map(|it| it.read().states.len()).sum()
+        }
+
+        let input = "(a+4)*2 + b*c-- + (d++ + 5)*(e*f) + g--*h++";
+        let new_parser = move || {
+            let lexer = LabelsLexer::new(InputStream::new(input.into()));
+            LabelsParser::new(CommonTokenStream::new(lexer))
+        };
+
+        let (other_thread_dfa, warmed_up) = std::thread::spawn(move || {
+            let mut parser = new_parser();
+            parser.s().expect("parser error");
+            let dfa = parser.get_interpreter().decision_to_dfa();
+            (dfa as *const _ as usize, state_count(dfa))
+        })
+        .join()
+        .unwrap();
+        assert!(warmed_up > 0);
+
+        let mut parser = new_parser();
+        let dfa = parser.get_interpreter().decision_to_dfa();
+        assert_eq!(dfa as *const _ as usize, other_thread_dfa);
+        let states = state_count(dfa);
+        parser.s().expect("parser error");
+        // DFA warmed up by the other thread already has every state this input needs
+        assert_eq!(state_count(parser.get_interpreter().decision_to_dfa()), states);
+    }
+
+    /// EOF semantics that all streams and trees have to agree on
+    mod eof {
+        use antlr_rust::interval_set::{IntervalSet, TokenInterval};
+        use antlr_rust::token::CommonToken;
+        use antlr_rust::tree::NodeText;
+        use antlr_rust::tree_builder::TreeBuilder;
+        use antlr_rust::trees;
+        use antlr_rust::vocabulary::VocabularyImpl;
+
+        use super::*;
+
+        const INPUT: &str = "a,b\nc,d\n";
+
+        fn consume_all<'input>(tokens: &mut impl TokenStream<'input>) {
+            while tokens.la(1) != TOKEN_EOF {
+                tokens.consume();
+            }
+        }
+
+        #[test]
+        fn test_eof_token() {
+            let tf = ArenaCommonFactory::default();
+            let lexer = CSVLexer::new_with_token_factory(InputStream::new(INPUT.into()), &tf);
+            let mut tokens = CommonTokenStream::new(lexer);
+            consume_all(&mut tokens);
+            let eof = *tokens.lt(1).unwrap();
+            assert_eq!(eof.get_token_index(), 8);
+            // empty interval at the end of input
+            assert_eq!((eof.get_start(), eof.get_stop()), (8, 7));
+            assert_eq!((eof.get_line(), eof.get_column()), (3, 0));
+            assert_eq!(eof.get_text(), "<EOF>");
+            assert_eq!(eof.to_string(), "[@8,8:7='<EOF>',<-1>,3:0]");
+
+            let chars = TokenInterval::new(8, 8).to_char_interval(&tokens).unwrap();
+            assert_eq!((chars.a, chars.b), (8, 7));
+            assert!(chars.is_empty());
+        }
+
+        #[test]
+        fn test_eof_lookahead() {
+            let tf = ArenaCommonFactory::default();
+            let lexer = CSVLexer::new_with_token_factory(InputStream::new(INPUT.into()), &tf);
+            let mut tokens = CommonTokenStream::new(lexer);
+            consume_all(&mut tokens);
+            let eof: *const CommonToken = *tokens.lt(1).unwrap();
+            for k in &[1, 1, 2, 10] {
+                assert!(std::ptr::eq(*tokens.lt(*k).unwrap(), eof));
+                assert_eq!(tokens.la(*k), TOKEN_EOF);
+            }
+            assert_eq!(tokens.index(), 8);
+            assert_eq!(tokens.size(), 9);
+
+            let lexer = CSVLexer::new_with_token_factory(InputStream::new(INPUT.into()), &tf);
+            let mut tokens = UnbufferedTokenStream::new_unbuffered(lexer);
+            consume_all(&mut tokens);
+            let eof: *const CommonToken = *tokens.lt(1).unwrap();
+            for k in &[1, 3, 1] {
+                assert!(std::ptr::eq(*tokens.lt(*k).unwrap(), eof));
+            }
+            assert_eq!(tokens.index(), 8);
+        }
+
+        #[test]
+        fn test_eof_stream_text() {
+            let tf = ArenaCommonFactory::default();
+            let lexer = CSVLexer::new_with_token_factory(InputStream::new(INPUT.into()), &tf);
+            let mut tokens = CommonTokenStream::new(lexer);
+            consume_all(&mut tokens);
+            assert_eq!(tokens.get_all_text(), INPUT);
+            assert_eq!(
+                tokens.get_text_from_interval(TokenInterval::new(6, 8)),
+                "d\n"
+            );
+            assert_eq!(tokens.get_text_from_interval(TokenInterval::new(8, 8)), "");
+        }
+
+        #[test]
+        fn test_eof_tree_text() {
+            let tree = TreeBuilder::new()
+                .rule(0)
+                .token(TEXT, "a")
+                .token(T__2, "\n")
+                .token(TOKEN_EOF, "<EOF>")
+                .end()
+                .finish()
+                .unwrap();
+            // EOF leaf is shown only in display oriented output
+            assert_eq!(tree.get_text(), "a\n");
+            assert_eq!(trees::string_tree(&*tree, &["s"]), "(s a \\n <EOF>)");
+            let eof = tree.get_child(2).unwrap();
+            assert_eq!(eof.get_text(), "");
+            assert_eq!(eof.get_node_text(&[]), "<EOF>");
+        }
+
+        #[test]
+        fn test_eof_in_interval_set() {
+            let vocabulary = VocabularyImpl::new(
+                csvparser::_LITERAL_NAMES.iter(),
+                csvparser::_SYMBOLIC_NAMES.iter(),
+                None,
+            );
+            let mut set = IntervalSet::new();
+            set.add_one(TOKEN_EOF);
+            set.add_one(T__0);
+            assert_eq!(set.to_token_string(&vocabulary), "{<EOF>, ','}");
+        }
+    }
+
     struct MyCSVVisitor<'i, T>(Vec<&'i str>, T);
 
     impl<'i, T> ParseTreeVisitor<'i, CSVParserContextType> for MyCSVVisitor<'i, T> {